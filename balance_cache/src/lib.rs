@@ -0,0 +1,555 @@
+//! Cache of the data credit escrow balances of payers.
+//!
+//! Balances are loaded from chain the first time a payer is seen and debits
+//! are tracked locally until they are burned. Each payer has its own lock, so
+//! verifiers debiting different payers never wait on each other, and the
+//! cache can be cloned and shared between any number of consumers.
+//!
+//! A chain balance above the previously fetched one is a top up of the
+//! escrow, sent to the subscribers of top ups. A settled burn may or may not
+//! be part of a balance fetched around it, so the balance fetched after a
+//! settled burn is taken as it is, missing a top up landing in between.
+
+use helium_crypto::PublicKeyBinary;
+use solana::SolanaNetwork;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::{broadcast, Mutex, RwLock};
+
+const CHANGES_CAPACITY: usize = 1024;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Balance {
+    /// Escrow balance on chain as last fetched
+    pub balance: u64,
+    /// Debits not yet burned on chain
+    pub burned: u64,
+}
+
+impl Balance {
+    pub fn new(balance: u64) -> Self {
+        Self { balance, burned: 0 }
+    }
+
+    /// Balance left after the pending burns
+    pub fn available(&self) -> u64 {
+        self.balance.saturating_sub(self.burned)
+    }
+}
+
+/// Sent to subscribers whenever a different chain balance of a payer is seen
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BalanceChange {
+    pub payer: PublicKeyBinary,
+    pub balance: u64,
+}
+
+/// Sent to subscribers whenever a higher chain balance of a payer is fetched
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BalanceTopUp {
+    pub payer: PublicKeyBinary,
+    /// Data credits added to the escrow
+    pub amount: u64,
+    /// Chain balance after the top up
+    pub balance: u64,
+}
+
+#[derive(Debug, Default)]
+struct PayerEntry {
+    balance: Balance,
+    /// Chain balance as last fetched, none until it is fetched after a
+    /// settled burn
+    last_fetched: Option<u64>,
+}
+
+impl PayerEntry {
+    fn fetched(balance: Balance) -> Self {
+        Self {
+            balance,
+            last_fetched: Some(balance.balance),
+        }
+    }
+}
+
+type PayerBalance = Arc<Mutex<PayerEntry>>;
+
+pub struct BalanceCache<S> {
+    inner: Arc<Inner<S>>,
+}
+
+struct Inner<S> {
+    solana: S,
+    payers: RwLock<HashMap<PublicKeyBinary, PayerBalance>>,
+    changes: broadcast::Sender<BalanceChange>,
+    top_ups: broadcast::Sender<BalanceTopUp>,
+}
+
+impl<S> Clone for BalanceCache<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S> BalanceCache<S> {
+    pub fn new(solana: S) -> Self {
+        let (changes, _) = broadcast::channel(CHANGES_CAPACITY);
+        let (top_ups, _) = broadcast::channel(CHANGES_CAPACITY);
+        Self {
+            inner: Arc::new(Inner {
+                solana,
+                payers: RwLock::new(HashMap::new()),
+                changes,
+                top_ups,
+            }),
+        }
+    }
+
+    pub fn solana(&self) -> &S {
+        &self.inner.solana
+    }
+
+    /// Receive the chain balance changes seen by any consumer of the cache
+    pub fn subscribe(&self) -> broadcast::Receiver<BalanceChange> {
+        self.inner.changes.subscribe()
+    }
+
+    /// Receive the top ups of the escrow of any payer seen by any consumer of
+    /// the cache
+    pub fn subscribe_top_ups(&self) -> broadcast::Receiver<BalanceTopUp> {
+        self.inner.top_ups.subscribe()
+    }
+
+    /// Current balance of the payer, none when it was never loaded
+    pub async fn balance(&self, payer: &PublicKeyBinary) -> Option<Balance> {
+        let balance = self.inner.payers.read().await.get(payer).cloned()?;
+        let balance = balance.lock().await.balance;
+        Some(balance)
+    }
+
+    /// Current balances of every loaded payer
+    pub async fn balances(&self) -> Vec<(PublicKeyBinary, Balance)> {
+        let payers: Vec<_> = self
+            .inner
+            .payers
+            .read()
+            .await
+            .iter()
+            .map(|(payer, entry)| (payer.clone(), entry.clone()))
+            .collect();
+        let mut balances = Vec::with_capacity(payers.len());
+        for (payer, entry) in payers {
+            balances.push((payer, entry.lock().await.balance));
+        }
+        balances
+    }
+
+    /// Replace the chain balance of the payer, keeping its pending burns
+    pub async fn set_balance(&self, payer: &PublicKeyBinary, balance: u64) {
+        let entry = self.entry_or_insert(payer, PayerEntry::default()).await;
+        let mut entry = entry.lock().await;
+        self.update_balance(payer, &mut entry, balance);
+    }
+
+    /// Debit the payer without checking the balance, for usage that has to
+    /// be paid for whether or not the payer can afford it
+    pub async fn add_burned(&self, payer: &PublicKeyBinary, amount: u64) {
+        let entry = self.entry_or_insert(payer, PayerEntry::default()).await;
+        entry.lock().await.balance.burned += amount;
+    }
+
+    /// Record that `amount` of the pending burns of the payer was burned on
+    /// chain. The chain balance is zeroed so it is fetched again on the next
+    /// debit.
+    pub async fn settle_burn(&self, payer: &PublicKeyBinary, amount: u64) {
+        let entry = self.entry_or_insert(payer, PayerEntry::default()).await;
+        let mut entry = entry.lock().await;
+        entry.balance.burned = entry.balance.burned.saturating_sub(amount);
+        entry.balance.balance = 0;
+        entry.last_fetched = None;
+    }
+
+    /// Raise the cached debits of the payers to their pending burns. Debits
+    /// that were rolled back, or burns that failed after the cache was
+    /// settled, can leave the cache believing more is available than the
+    /// escrow minus the pending burns. Returns the number of corrected payers.
+    pub async fn cap_by_pending_burns<I>(&self, pending_burns: I) -> usize
+    where
+        I: IntoIterator<Item = (PublicKeyBinary, u64)>,
+    {
+        let mut corrected = 0;
+        for (payer, pending) in pending_burns {
+            if pending == 0 {
+                continue;
+            }
+            let entry = self.entry_or_insert(&payer, PayerEntry::default()).await;
+            let mut entry = entry.lock().await;
+            if entry.balance.burned < pending {
+                tracing::warn!(
+                    %payer,
+                    cached = entry.balance.burned,
+                    pending,
+                    "cached debits below pending burns"
+                );
+                entry.balance.burned = pending;
+                corrected += 1;
+            }
+        }
+        corrected
+    }
+
+    async fn entry_or_insert(&self, payer: &PublicKeyBinary, entry: PayerEntry) -> PayerBalance {
+        if let Some(entry) = self.inner.payers.read().await.get(payer) {
+            return entry.clone();
+        }
+        self.inner
+            .payers
+            .write()
+            .await
+            .entry(payer.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(entry)))
+            .clone()
+    }
+
+    fn update_balance(&self, payer: &PublicKeyBinary, entry: &mut PayerEntry, balance: u64) {
+        if let Some(fetched) = entry.last_fetched.filter(|fetched| balance > *fetched) {
+            let _ = self.inner.top_ups.send(BalanceTopUp {
+                payer: payer.clone(),
+                amount: balance - fetched,
+                balance,
+            });
+        }
+        entry.last_fetched = Some(balance);
+        if entry.balance.balance != balance {
+            entry.balance.balance = balance;
+            // Nobody listening is not an error
+            let _ = self.inner.changes.send(BalanceChange {
+                payer: payer.clone(),
+                balance,
+            });
+        }
+    }
+}
+
+impl<S> BalanceCache<S>
+where
+    S: SolanaNetwork,
+{
+    /// Create a cache with the payers that have pending burns, fetching their
+    /// current balances so the cache is accurate from the start
+    pub async fn with_pending_burns<I>(solana: S, pending_burns: I) -> Result<Self, S::Error>
+    where
+        I: IntoIterator<Item = (PublicKeyBinary, u64)>,
+    {
+        let cache = Self::new(solana);
+        {
+            let mut payers = cache.inner.payers.write().await;
+            for (payer, burned) in pending_burns {
+                let balance = cache.inner.solana.payer_balance(&payer).await?;
+                payers.insert(
+                    payer,
+                    Arc::new(Mutex::new(PayerEntry::fetched(Balance { balance, burned }))),
+                );
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Debits the balance of the payer, returning the remaining balance if
+    /// there was enough and none otherwise. The chain balance is fetched again
+    /// before refusing the debit in case the escrow has been topped up.
+    pub async fn debit_if_sufficient(
+        &self,
+        payer: &PublicKeyBinary,
+        amount: u64,
+    ) -> Result<Option<u64>, S::Error> {
+        // The read lock must be released before a new payer is inserted
+        let cached = self.inner.payers.read().await.get(payer).cloned();
+        let entry = match cached {
+            Some(entry) => entry,
+            None => {
+                let balance = self.inner.solana.payer_balance(payer).await?;
+                self.entry_or_insert(payer, PayerEntry::fetched(Balance::new(balance)))
+                    .await
+            }
+        };
+        let mut entry = entry.lock().await;
+
+        if entry.balance.balance < amount + entry.balance.burned {
+            let balance = self.inner.solana.payer_balance(payer).await?;
+            self.update_balance(payer, &mut entry, balance);
+        }
+
+        Ok(if entry.balance.balance >= amount + entry.balance.burned {
+            entry.balance.burned += amount;
+            Some(entry.balance.available())
+        } else {
+            None
+        })
+    }
+
+    /// Fetch the chain balance of every cached payer. Failures are logged and
+    /// leave the cached balance of the payer untouched.
+    pub async fn refresh(&self) {
+        self.refresh_matching(|_| true).await
+    }
+
+    /// Fetch the chain balance of the cached payers with less than
+    /// `threshold` available, whose orgs are or are about to be disabled
+    pub async fn refresh_below(&self, threshold: u64) {
+        self.refresh_matching(|balance| balance.available() < threshold)
+            .await
+    }
+
+    async fn refresh_matching(&self, matches: impl Fn(&Balance) -> bool) {
+        let payers: Vec<_> = self
+            .inner
+            .payers
+            .read()
+            .await
+            .iter()
+            .map(|(payer, entry)| (payer.clone(), entry.clone()))
+            .collect();
+
+        for (payer, entry) in payers {
+            // The payer stays locked over the fetch, so a burn settled in the
+            // meantime is never overwritten by the balance before the burn
+            let mut entry = entry.lock().await;
+            if !matches(&entry.balance) {
+                continue;
+            }
+            match self.inner.solana.payer_balance(&payer).await {
+                Ok(balance) => self.update_balance(&payer, &mut entry, balance),
+                Err(err) => tracing::warn!(%payer, "failed to refresh payer balance: {err}"),
+            }
+        }
+    }
+
+    /// Refresh the cached balances every `period` until shutdown so changes
+    /// to the escrow accounts made elsewhere are picked up
+    pub async fn watch(self, period: Duration, shutdown: triggered::Listener) {
+        let mut trigger = tokio::time::interval(period);
+        trigger.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                _ = trigger.tick() => self.refresh().await,
+            }
+        }
+    }
+
+    /// Refresh the cached balances of the payers with less than `threshold`
+    /// available every `period` until shutdown, so top ups of the payers
+    /// that can no longer be debited are picked up quickly
+    pub async fn watch_below(
+        self,
+        threshold: u64,
+        period: Duration,
+        shutdown: triggered::Listener,
+    ) {
+        let mut trigger = tokio::time::interval(period);
+        trigger.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                _ = trigger.tick() => self.refresh_below(threshold).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use solana::mock::{MockSolanaError, MockSolanaNetwork};
+    use tokio::sync::Notify;
+
+    fn payer(byte: u8) -> PublicKeyBinary {
+        PublicKeyBinary::from(vec![byte])
+    }
+
+    #[tokio::test]
+    async fn debits_until_balance_is_used_up() {
+        let solana = MockSolanaNetwork::new().with_balance(payer(1), 3).await;
+        let cache = BalanceCache::new(solana.clone());
+
+        assert_eq!(
+            cache.debit_if_sufficient(&payer(1), 2).await.unwrap(),
+            Some(1)
+        );
+        assert_eq!(cache.debit_if_sufficient(&payer(1), 2).await.unwrap(), None);
+        assert_eq!(
+            cache.debit_if_sufficient(&payer(1), 1).await.unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            cache.balance(&payer(1)).await,
+            Some(Balance {
+                balance: 3,
+                burned: 3
+            })
+        );
+        assert_eq!(cache.debit_if_sufficient(&payer(2), 1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn top_ups_are_seen_by_subscribers() {
+        let solana = MockSolanaNetwork::new().with_balance(payer(1), 1).await;
+        let cache = BalanceCache::with_pending_burns(solana.clone(), vec![(payer(1), 1)])
+            .await
+            .unwrap();
+        let mut changes = cache.subscribe();
+
+        assert_eq!(cache.debit_if_sufficient(&payer(1), 1).await.unwrap(), None);
+
+        solana.clone().with_balance(payer(1), 5).await;
+        cache.refresh().await;
+        assert_eq!(
+            changes.recv().await.unwrap(),
+            BalanceChange {
+                payer: payer(1),
+                balance: 5
+            }
+        );
+        assert_eq!(
+            cache.debit_if_sufficient(&payer(1), 1).await.unwrap(),
+            Some(3)
+        );
+
+        // Nothing changed on chain:
+        cache.refresh().await;
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn pending_burns_cap_the_available_balance() {
+        let solana = MockSolanaNetwork::new().with_balance(payer(1), 10).await;
+        let cache = BalanceCache::new(solana.clone());
+        cache.set_balance(&payer(1), 10).await;
+        cache.add_burned(&payer(1), 8).await;
+
+        // Lower pending burns never free up balance
+        assert_eq!(
+            cache
+                .cap_by_pending_burns(vec![(payer(1), 2), (payer(2), 0)])
+                .await,
+            0
+        );
+        assert_eq!(cache.balance(&payer(1)).await.unwrap().available(), 2);
+
+        assert_eq!(cache.cap_by_pending_burns(vec![(payer(1), 10)]).await, 1);
+        assert_eq!(cache.balance(&payer(1)).await.unwrap().available(), 0);
+        assert_eq!(cache.debit_if_sufficient(&payer(1), 1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn settled_burns_force_a_new_balance_fetch() {
+        let solana = MockSolanaNetwork::new().with_balance(payer(1), 10).await;
+        let cache = BalanceCache::new(solana.clone());
+
+        cache.add_burned(&payer(1), 4).await;
+        cache.set_balance(&payer(1), 10).await;
+        solana.burn_data_credits(&payer(1), 4).await.unwrap();
+        cache.settle_burn(&payer(1), 4).await;
+        assert_eq!(cache.balance(&payer(1)).await, Some(Balance::default()));
+
+        assert_eq!(
+            cache.debit_if_sufficient(&payer(1), 6).await.unwrap(),
+            Some(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn higher_fetched_balances_are_top_ups() {
+        let solana = MockSolanaNetwork::new().with_balance(payer(1), 10).await;
+        let cache = BalanceCache::new(solana.clone());
+        let mut top_ups = cache.subscribe_top_ups();
+
+        assert_eq!(
+            cache.debit_if_sufficient(&payer(1), 4).await.unwrap(),
+            Some(6)
+        );
+        solana.burn_data_credits(&payer(1), 4).await.unwrap();
+        cache.settle_burn(&payer(1), 4).await;
+        // The balance fetched after a settled burn is no top up
+        cache.refresh().await;
+        cache.refresh_below(7).await;
+        assert!(top_ups.try_recv().is_err());
+
+        solana.clone().with_balance(payer(1), 15).await;
+        // Only payers below the threshold are refreshed
+        cache.refresh_below(6).await;
+        assert!(top_ups.try_recv().is_err());
+        cache.refresh_below(7).await;
+        assert_eq!(
+            top_ups.try_recv().unwrap(),
+            BalanceTopUp {
+                payer: payer(1),
+                amount: 9,
+                balance: 15
+            }
+        );
+    }
+
+    /// Network whose balance fetches wait for `release` after reading the
+    /// balance
+    #[derive(Clone)]
+    struct PausedNetwork {
+        solana: MockSolanaNetwork,
+        fetched: Arc<Notify>,
+        release: Arc<Notify>,
+    }
+
+    #[async_trait]
+    impl SolanaNetwork for PausedNetwork {
+        type Error = MockSolanaError;
+
+        async fn payer_balance(&self, payer: &PublicKeyBinary) -> Result<u64, Self::Error> {
+            let balance = self.solana.payer_balance(payer).await;
+            self.fetched.notify_one();
+            self.release.notified().await;
+            balance
+        }
+
+        async fn burn_data_credits(
+            &self,
+            payer: &PublicKeyBinary,
+            amount: u64,
+        ) -> Result<Option<String>, Self::Error> {
+            self.solana.burn_data_credits(payer, amount).await
+        }
+    }
+
+    #[tokio::test]
+    async fn burns_settled_during_a_refresh_are_kept() {
+        let solana = MockSolanaNetwork::new().with_balance(payer(1), 10).await;
+        let network = PausedNetwork {
+            solana: solana.clone(),
+            fetched: Arc::new(Notify::new()),
+            release: Arc::new(Notify::new()),
+        };
+        let cache = BalanceCache::new(network.clone());
+        cache.set_balance(&payer(1), 10).await;
+        cache.add_burned(&payer(1), 4).await;
+
+        // The refresh fetches the balance before the burn lands on chain
+        let refresh = tokio::spawn({
+            let cache = cache.clone();
+            async move { cache.refresh().await }
+        });
+        network.fetched.notified().await;
+        solana.burn_data_credits(&payer(1), 4).await.unwrap();
+        let settle = tokio::spawn({
+            let cache = cache.clone();
+            async move { cache.settle_burn(&payer(1), 4).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        network.release.notify_one();
+        refresh.await.unwrap();
+        settle.await.unwrap();
+
+        // The stale balance is not kept next to the settled burn
+        assert_eq!(cache.balance(&payer(1)).await, Some(Balance::default()));
+    }
+}
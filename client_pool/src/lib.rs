@@ -0,0 +1,117 @@
+//! Load balanced grpc channels over the replicas of an oracle service.
+//!
+//! A single lazily connected channel stalls every caller when its endpoint
+//! degrades. The channels created here spread requests over all configured
+//! endpoints and periodically check that each of them accepts connections.
+//! Endpoints failing the check are taken out of rotation until they connect
+//! again, connections to the remaining endpoints are re-established
+//! transparently by the channel.
+
+use futures::future::join_all;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tonic::transport::{Channel, Endpoint};
+use tower::discover::Change;
+
+const HEALTHY_ENDPOINTS_METRIC: &str = "client_pool_healthy_endpoints";
+
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Create a channel balancing requests over `endpoints`, checking their
+/// health every `health_check_interval`. A single endpoint gets a plain lazy
+/// channel. Must be called from within a tokio runtime.
+pub fn balanced_channel(
+    name: &'static str,
+    endpoints: Vec<Endpoint>,
+    health_check_interval: Duration,
+) -> Channel {
+    if let [endpoint] = endpoints.as_slice() {
+        return endpoint.connect_lazy();
+    }
+    let (channel, changes) = Channel::balance_channel(endpoints.len().max(1));
+    tokio::spawn(health_check(
+        name,
+        endpoints,
+        changes,
+        health_check_interval,
+    ));
+    channel
+}
+
+async fn health_check(
+    name: &'static str,
+    endpoints: Vec<Endpoint>,
+    changes: mpsc::Sender<Change<usize, Endpoint>>,
+    interval: Duration,
+) {
+    // Every endpoint starts in rotation, the first check takes out the ones
+    // that are down
+    let mut in_rotation = vec![false; endpoints.len()];
+    let mut trigger = tokio::time::interval(interval);
+    trigger.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut healthy = vec![true; endpoints.len()];
+
+    loop {
+        let wanted = rotation(&healthy);
+        for (key, endpoint) in endpoints.iter().enumerate() {
+            if wanted[key] == in_rotation[key] {
+                continue;
+            }
+            let change = if wanted[key] {
+                tracing::info!(name, uri = %endpoint.uri(), "endpoint added to rotation");
+                Change::Insert(key, endpoint.clone())
+            } else {
+                tracing::warn!(name, uri = %endpoint.uri(), "endpoint removed from rotation");
+                Change::Remove(key)
+            };
+            // The channel and all its clones are gone
+            if changes.send(change).await.is_err() {
+                return;
+            }
+            in_rotation[key] = wanted[key];
+        }
+        metrics::gauge!(
+            HEALTHY_ENDPOINTS_METRIC,
+            healthy.iter().filter(|healthy| **healthy).count() as f64,
+            "name" => name
+        );
+
+        trigger.tick().await;
+        if changes.is_closed() {
+            return;
+        }
+        // Connecting is bounded by the connect timeout of the endpoint
+        healthy = join_all(endpoints.iter().map(|endpoint| async move {
+            match endpoint.connect().await {
+                Ok(_) => true,
+                Err(err) => {
+                    tracing::debug!(name, uri = %endpoint.uri(), "health check failed: {err}");
+                    false
+                }
+            }
+        }))
+        .await;
+    }
+}
+
+/// The endpoints to keep in rotation. When none is healthy all of them stay
+/// in rotation, so requests are still attempted instead of failing outright.
+fn rotation(healthy: &[bool]) -> Vec<bool> {
+    if healthy.iter().any(|healthy| *healthy) {
+        healthy.to_vec()
+    } else {
+        vec![true; healthy.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unhealthy_endpoints_leave_rotation_unless_all_are_down() {
+        assert_eq!(rotation(&[true, false, true]), vec![true, false, true]);
+        assert_eq!(rotation(&[false, false]), vec![true, true]);
+        assert_eq!(rotation(&[]), Vec::<bool>::new());
+    }
+}
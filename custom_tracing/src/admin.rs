@@ -0,0 +1,246 @@
+//! Signed grpc endpoint changing the log filter of a running service, for
+//! example to `info,iot_packet_verifier=debug` while debugging a production
+//! issue. Like the other query endpoints the service is hand written on top
+//! of the tonic primitives with its messages defined here.
+//!
+//! Requests are signed by one of the admin keys of the service. An override
+//! with a duration is restored to the filter of the settings once the
+//! duration passed, unless it was replaced by a later request in the
+//! meantime. A settings reload also restores the filter of the settings.
+
+use crate::{current_filter, override_filter, restore_filter};
+use chrono::{TimeZone, Utc};
+use helium_crypto::{PublicKey, Verify};
+use prost::Message;
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const SERVICE_NAME: &str = "helium.oracle.admin";
+const SET_LOG_FILTER_PATH: &str = "/helium.oracle.admin/set_log_filter";
+
+/// Requests signed longer ago, or further in the future, are rejected
+pub const MAX_SIGNATURE_AGE_SECS: i64 = 5 * 60;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SetLogFilterReqV1 {
+    /// Filter directives replacing the log filter, restoring the filter of
+    /// the settings when empty
+    #[prost(string, tag = "1")]
+    pub filter: String,
+    /// Seconds until the filter of the settings is restored, never when 0
+    #[prost(uint64, tag = "2")]
+    pub duration_secs: u64,
+    /// Milliseconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "3")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "4")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LogFilterResV1 {
+    /// The log filter in effect after the request
+    #[prost(string, tag = "1")]
+    pub filter: String,
+}
+
+impl SetLogFilterReqV1 {
+    /// The signer of the request if the signature is valid
+    fn verify(&self) -> Result<PublicKey, Status> {
+        let signer = PublicKey::try_from(self.signer.as_slice())
+            .map_err(|_| Status::invalid_argument("invalid signer"))?;
+        let unsigned = Self {
+            signature: vec![],
+            ..self.clone()
+        };
+        signer
+            .verify(&unsigned.encode_to_vec(), &self.signature)
+            .map_err(|_| Status::unauthenticated("invalid signature"))?;
+        Ok(signer)
+    }
+}
+
+/// The keys allowed to change the log filter of a service
+pub trait AdminKeys: Clone + Send + Sync + 'static {
+    fn is_admin(&self, key: &PublicKey) -> bool;
+}
+
+impl AdminKeys for Arc<HashSet<PublicKey>> {
+    fn is_admin(&self, key: &PublicKey) -> bool {
+        self.contains(key)
+    }
+}
+
+#[derive(Clone)]
+pub struct LogFilterService<K> {
+    admin_keys: K,
+    /// Incremented by every request, so an expiring override only restores
+    /// the filter when it was not replaced
+    generation: Arc<AtomicU64>,
+}
+
+impl<K: AdminKeys> LogFilterService<K> {
+    pub fn new(admin_keys: K) -> Self {
+        Self {
+            admin_keys,
+            generation: Arc::default(),
+        }
+    }
+
+    fn authorize(&self, request: &SetLogFilterReqV1) -> Result<PublicKey, Status> {
+        let signer = request.verify()?;
+        let signed_at = Utc
+            .timestamp_millis_opt(request.timestamp as i64)
+            .single()
+            .ok_or_else(|| Status::invalid_argument("invalid timestamp"))?;
+        if (Utc::now() - signed_at).num_seconds().abs() > MAX_SIGNATURE_AGE_SECS {
+            return Err(Status::unauthenticated("request expired"));
+        }
+        if !self.admin_keys.is_admin(&signer) {
+            return Err(Status::permission_denied("signer is not an admin key"));
+        }
+        Ok(signer)
+    }
+
+    pub async fn set_log_filter(
+        &self,
+        request: SetLogFilterReqV1,
+    ) -> Result<LogFilterResV1, Status> {
+        let signer = self.authorize(&request)?;
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let result = if request.filter.is_empty() {
+            restore_filter()
+        } else {
+            override_filter(&request.filter)
+        };
+        result.map_err(|err| Status::invalid_argument(err.to_string()))?;
+        tracing::warn!(
+            %signer,
+            filter = %request.filter,
+            duration_secs = request.duration_secs,
+            "log filter changed"
+        );
+
+        if !request.filter.is_empty() && request.duration_secs > 0 {
+            let current = self.generation.clone();
+            let duration = Duration::from_secs(request.duration_secs);
+            tokio::spawn(async move {
+                tokio::time::sleep(duration).await;
+                if current.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+                match restore_filter() {
+                    Ok(()) => tracing::warn!("log filter override expired, restored settings"),
+                    Err(err) => tracing::error!("failed to restore log filter: {err}"),
+                }
+            });
+        }
+
+        let filter = current_filter().map_err(|err| Status::internal(err.to_string()))?;
+        Ok(LogFilterResV1 { filter })
+    }
+}
+
+impl<K> NamedService for LogFilterService<K> {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<K, B> Service<http::Request<B>> for LogFilterService<K>
+where
+    K: AdminKeys,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            SET_LOG_FILTER_PATH => grpc_service::unary(req, move |request| async move {
+                svc.set_log_filter(request).await
+            }),
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_crypto::{KeyTag, KeyType, Keypair, Network, Sign};
+    use rand::rngs::OsRng;
+
+    fn keypair() -> Keypair {
+        Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::Ed25519,
+            },
+            &mut OsRng,
+        )
+    }
+
+    fn signed_request(keypair: &Keypair, timestamp: i64) -> SetLogFilterReqV1 {
+        let mut request = SetLogFilterReqV1 {
+            filter: "info,iot_packet_verifier=debug".to_string(),
+            duration_secs: 600,
+            timestamp: timestamp as u64,
+            signer: keypair.public_key().to_vec(),
+            signature: vec![],
+        };
+        request.signature = keypair.sign(&request.encode_to_vec()).unwrap();
+        request
+    }
+
+    #[test]
+    fn only_recent_requests_of_admin_keys_are_authorized() {
+        let admin = keypair();
+        let service = LogFilterService::new(Arc::new(HashSet::from([admin.public_key().clone()])));
+        let now = Utc::now().timestamp_millis();
+
+        let request = signed_request(&admin, now);
+        assert_eq!(&service.authorize(&request).unwrap(), admin.public_key());
+
+        let tampered = SetLogFilterReqV1 {
+            filter: "trace".to_string(),
+            ..request
+        };
+        assert_eq!(
+            service.authorize(&tampered).unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+
+        let expired = signed_request(&admin, now - 1000 * (MAX_SIGNATURE_AGE_SECS + 1));
+        assert_eq!(
+            service.authorize(&expired).unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+
+        let other = signed_request(&keypair(), now);
+        assert_eq!(
+            service.authorize(&other).unwrap_err().code(),
+            tonic::Code::PermissionDenied
+        );
+    }
+}
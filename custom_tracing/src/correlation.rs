@@ -0,0 +1,41 @@
+//! Correlation ids tie the work done for an input file to everything it
+//! produces. A verifier handles each ingest file within [`scope`], using the
+//! file name as id. Log events carry the id as a span field and file sinks
+//! record the ids of the inputs that went into each output file, so a report
+//! can be followed from its ingest file through every service that handles
+//! it.
+
+use std::{future::Future, sync::Arc};
+use tracing::Instrument;
+
+tokio::task_local! {
+    static CORRELATION_ID: Arc<str>;
+}
+
+/// Run `future` with `id` as the current correlation id
+pub async fn scope<F>(id: impl Into<Arc<str>>, future: F) -> F::Output
+where
+    F: Future,
+{
+    let id = id.into();
+    let span = tracing::info_span!("correlation", correlation_id = %id);
+    CORRELATION_ID.scope(id, future.instrument(span)).await
+}
+
+/// The correlation id of the current task, if it runs within a [`scope`]
+pub fn current() -> Option<Arc<str>> {
+    CORRELATION_ID.try_with(Arc::clone).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn id_is_only_set_within_scope() {
+        assert_eq!(current(), None);
+        let id = scope("iot_packet_report.1690000000000.gz", async { current() }).await;
+        assert_eq!(id.as_deref(), Some("iot_packet_report.1690000000000.gz"));
+        assert_eq!(current(), None);
+    }
+}
@@ -0,0 +1,234 @@
+//! Runtime diagnostics for finding the task a stalled service is stuck in.
+//!
+//! Compiled in with the `diagnostics` feature of a service, which requires
+//! building with `RUSTFLAGS="--cfg tokio_unstable"`. The `[tracing.diagnostics]`
+//! settings then serve the tokio-console instrumentation and periodically
+//! export runtime metrics: task counts, busy ratios and mean poll durations of
+//! the workers and the blocking thread pool. A worker that was busy for a
+//! whole collection period without completing a poll is reported as stalled,
+//! it is blocked by a task that does not yield.
+
+use serde::Deserialize;
+use std::net::SocketAddr;
+
+#[cfg(all(feature = "diagnostics", not(tokio_unstable)))]
+compile_error!("the diagnostics feature requires RUSTFLAGS=\"--cfg tokio_unstable\"");
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Settings {
+    /// Address serving the tokio-console instrumentation, for example
+    /// "127.0.0.1:6669". Disabled when not set
+    pub console: Option<SocketAddr>,
+    /// Seconds between collections of the runtime metrics. Disabled when not
+    /// set
+    pub runtime_metrics_secs: Option<u64>,
+}
+
+#[cfg(feature = "diagnostics")]
+pub(crate) fn console_layer(settings: &Settings) -> Option<console_subscriber::ConsoleLayer> {
+    settings.console.map(|addr| {
+        console_subscriber::ConsoleLayer::builder()
+            .server_addr(addr)
+            .spawn()
+    })
+}
+
+#[cfg(not(feature = "diagnostics"))]
+pub(crate) fn console_layer(_settings: &Settings) -> Option<tracing_subscriber::layer::Identity> {
+    None
+}
+
+/// Spawns the runtime metrics collector on the current tokio runtime. Does
+/// nothing when called outside of a runtime.
+#[cfg(feature = "diagnostics")]
+pub(crate) fn start(settings: &Settings) {
+    if let Some(addr) = settings.console {
+        tracing::info!(%addr, "serving tokio console");
+    }
+    let Some(secs) = settings.runtime_metrics_secs.filter(|secs| *secs > 0) else {
+        return;
+    };
+    let handle = match tokio::runtime::Handle::try_current() {
+        Ok(handle) => handle,
+        Err(_) => {
+            tracing::warn!("no tokio runtime; runtime metrics disabled");
+            return;
+        }
+    };
+    let mut collector = runtime::Collector::new(handle.metrics());
+    let period = std::time::Duration::from_secs(secs);
+    handle.spawn(async move {
+        let mut collect_timer = tokio::time::interval(period);
+        loop {
+            collect_timer.tick().await;
+            collector.collect();
+        }
+    });
+}
+
+#[cfg(not(feature = "diagnostics"))]
+pub(crate) fn start(settings: &Settings) {
+    if settings.console.is_some() || settings.runtime_metrics_secs.is_some() {
+        tracing::warn!("diagnostics configured but not built with the diagnostics feature");
+    }
+}
+
+#[cfg(feature = "diagnostics")]
+mod runtime {
+    use std::time::{Duration, Instant};
+    use tokio::runtime::RuntimeMetrics;
+
+    const WORKERS_METRIC: &str = "tokio_workers";
+    const ACTIVE_TASKS_METRIC: &str = "tokio_active_tasks";
+    const INJECTION_QUEUE_METRIC: &str = "tokio_injection_queue_depth";
+    const BLOCKING_THREADS_METRIC: &str = "tokio_blocking_threads";
+    const IDLE_BLOCKING_THREADS_METRIC: &str = "tokio_idle_blocking_threads";
+    const BLOCKING_QUEUE_METRIC: &str = "tokio_blocking_queue_depth";
+    const WORKER_BUSY_RATIO_METRIC: &str = "tokio_worker_busy_ratio";
+    const WORKER_MEAN_POLL_METRIC: &str = "tokio_worker_mean_poll_seconds";
+    const STALLED_WORKERS_METRIC: &str = "tokio_stalled_workers";
+
+    /// Share of a collection period a worker has to be busy without
+    /// completing a poll to count as stalled
+    const STALLED_BUSY_RATIO: f64 = 0.9;
+
+    /// Counters of a worker at a collection
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub(super) struct WorkerSample {
+        pub polls: u64,
+        pub busy: Duration,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(super) struct WorkerActivity {
+        pub busy_ratio: f64,
+        pub mean_poll: Option<Duration>,
+        pub stalled: bool,
+    }
+
+    impl WorkerSample {
+        pub fn activity_since(&self, previous: &Self, elapsed: Duration) -> WorkerActivity {
+            let polls = self.polls.saturating_sub(previous.polls);
+            let busy = self.busy.saturating_sub(previous.busy);
+            let busy_ratio = if elapsed.is_zero() {
+                0.0
+            } else {
+                (busy.as_secs_f64() / elapsed.as_secs_f64()).min(1.0)
+            };
+            WorkerActivity {
+                busy_ratio,
+                mean_poll: u32::try_from(polls)
+                    .ok()
+                    .filter(|polls| *polls > 0)
+                    .map(|polls| busy / polls),
+                stalled: polls == 0 && busy_ratio >= STALLED_BUSY_RATIO,
+            }
+        }
+    }
+
+    pub(super) struct Collector {
+        metrics: RuntimeMetrics,
+        workers: Vec<WorkerSample>,
+        collected_at: Instant,
+    }
+
+    impl Collector {
+        pub fn new(metrics: RuntimeMetrics) -> Self {
+            let workers = (0..metrics.num_workers())
+                .map(|worker| sample(&metrics, worker))
+                .collect();
+            Self {
+                metrics,
+                workers,
+                collected_at: Instant::now(),
+            }
+        }
+
+        pub fn collect(&mut self) {
+            let runtime = &self.metrics;
+            let now = Instant::now();
+            let elapsed = now - self.collected_at;
+            self.collected_at = now;
+
+            metrics::gauge!(WORKERS_METRIC, runtime.num_workers() as f64);
+            metrics::gauge!(ACTIVE_TASKS_METRIC, runtime.active_tasks_count() as f64);
+            metrics::gauge!(
+                INJECTION_QUEUE_METRIC,
+                runtime.injection_queue_depth() as f64
+            );
+            metrics::gauge!(
+                BLOCKING_THREADS_METRIC,
+                runtime.num_blocking_threads() as f64
+            );
+            metrics::gauge!(
+                IDLE_BLOCKING_THREADS_METRIC,
+                runtime.num_idle_blocking_threads() as f64
+            );
+            metrics::gauge!(BLOCKING_QUEUE_METRIC, runtime.blocking_queue_depth() as f64);
+
+            let mut stalled = 0;
+            for (worker, previous) in self.workers.iter_mut().enumerate() {
+                let current = sample(runtime, worker);
+                let activity = current.activity_since(previous, elapsed);
+                *previous = current;
+
+                let label = worker.to_string();
+                metrics::gauge!(WORKER_BUSY_RATIO_METRIC, activity.busy_ratio, "worker" => label.clone());
+                if let Some(mean_poll) = activity.mean_poll {
+                    metrics::gauge!(WORKER_MEAN_POLL_METRIC, mean_poll.as_secs_f64(), "worker" => label);
+                }
+                if activity.stalled {
+                    stalled += 1;
+                    tracing::warn!(
+                        worker,
+                        ?elapsed,
+                        "runtime worker stalled, a task did not yield for the whole period"
+                    );
+                }
+            }
+            metrics::gauge!(STALLED_WORKERS_METRIC, stalled as f64);
+        }
+    }
+
+    fn sample(metrics: &RuntimeMetrics, worker: usize) -> WorkerSample {
+        WorkerSample {
+            polls: metrics.worker_poll_count(worker),
+            busy: metrics.worker_total_busy_duration(worker),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "diagnostics"))]
+mod tests {
+    use super::runtime::*;
+    use std::time::Duration;
+
+    #[test]
+    fn workers_busy_without_polls_are_stalled() {
+        let previous = WorkerSample {
+            polls: 100,
+            busy: Duration::from_secs(10),
+        };
+        let period = Duration::from_secs(15);
+
+        let polling = WorkerSample {
+            polls: 150,
+            busy: Duration::from_secs(15),
+        }
+        .activity_since(&previous, period);
+        assert!(!polling.stalled);
+        assert_eq!(polling.mean_poll, Some(Duration::from_millis(100)));
+
+        let blocked = WorkerSample {
+            polls: 100,
+            busy: Duration::from_secs(24),
+        }
+        .activity_since(&previous, period);
+        assert!(blocked.stalled);
+        assert_eq!(blocked.mean_poll, None);
+
+        let idle = previous.activity_since(&previous, period);
+        assert_eq!(idle.busy_ratio, 0.0);
+        assert!(!idle.stalled);
+    }
+}
@@ -0,0 +1,156 @@
+//! Tracing setup shared by the oracle services.
+//!
+//! Services keep their `log` filter setting and add a `[tracing]` section to
+//! select the output format and override levels for individual modules. The
+//! filter can be overridden at runtime through the [`admin`] endpoint and
+//! the [`diagnostics`] of the tokio runtime are enabled in
+//! `[tracing.diagnostics]`.
+
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{collections::BTreeMap, sync::Mutex};
+use tracing_subscriber::{
+    filter::ParseError, layer::SubscriberExt, reload, util::SubscriberInitExt, util::TryInitError,
+    EnvFilter, Layer, Registry,
+};
+
+pub mod admin;
+pub mod correlation;
+pub mod diagnostics;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid log filter: {0}")]
+    InvalidFilter(#[from] ParseError),
+    #[error("tracing already initialized: {0}")]
+    Init(#[from] TryInitError),
+    #[error("tracing not initialized")]
+    NotInitialized,
+    #[error("failed to replace log filter: {0}")]
+    Reload(#[from] reload::Error),
+}
+
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+/// The filter of the service settings, restored after a runtime override
+static CONFIGURED: Mutex<String> = Mutex::new(String::new());
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable output
+    #[default]
+    Pretty,
+    /// One json object per event, including the fields of enclosing spans
+    Json,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Settings {
+    /// Output format of log events, "pretty" or "json". Default "pretty"
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Log levels for individual modules, overriding the service log filter.
+    /// For example `iot_packet_verifier::verifier = "debug"`
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+    /// Tokio console and runtime metrics of services built with the
+    /// diagnostics feature
+    #[serde(default)]
+    pub diagnostics: diagnostics::Settings,
+}
+
+impl Settings {
+    /// The service log filter extended with the module level overrides
+    pub fn filter(&self, log: &str) -> String {
+        std::iter::once(log.to_string())
+            .chain(
+                self.modules
+                    .iter()
+                    .map(|(module, level)| format!("{module}={level}")),
+            )
+            .filter(|directive| !directive.is_empty())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Install the global tracing subscriber for a service
+pub fn init(log: &str, settings: &Settings) -> Result<(), Error> {
+    let configured = settings.filter(log);
+    let (filter, handle) = reload::Layer::new(EnvFilter::try_new(&configured)?);
+    let format = match settings.format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .boxed(),
+    };
+    // The filter only applies to the log output, the console layer sees the
+    // runtime instrumentation regardless of the log filter
+    tracing_subscriber::registry()
+        .with(format.with_filter(filter))
+        .with(diagnostics::console_layer(&settings.diagnostics))
+        .try_init()?;
+    let _ = FILTER.set(handle);
+    *CONFIGURED.lock().expect("configured filter") = configured;
+    diagnostics::start(&settings.diagnostics);
+    Ok(())
+}
+
+/// Replace the log filter of the running service
+pub fn set_filter(log: &str, settings: &Settings) -> Result<(), Error> {
+    let configured = settings.filter(log);
+    reload_filter(&configured)?;
+    *CONFIGURED.lock().expect("configured filter") = configured;
+    Ok(())
+}
+
+/// Replace the log filter of the running service until it is restored or
+/// the settings are reloaded
+pub fn override_filter(directives: &str) -> Result<(), Error> {
+    reload_filter(directives)
+}
+
+/// Restore the log filter of the service settings
+pub fn restore_filter() -> Result<(), Error> {
+    let configured = CONFIGURED.lock().expect("configured filter").clone();
+    reload_filter(&configured)
+}
+
+/// The log filter in effect
+pub fn current_filter() -> Result<String, Error> {
+    Ok(FILTER
+        .get()
+        .ok_or(Error::NotInitialized)?
+        .with_current(|filter| filter.to_string())?)
+}
+
+fn reload_filter(directives: &str) -> Result<(), Error> {
+    let filter = EnvFilter::try_new(directives)?;
+    FILTER.get().ok_or(Error::NotInitialized)?.reload(filter)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_levels_extend_log_filter() {
+        let settings = Settings {
+            modules: BTreeMap::from([
+                ("file_store".to_string(), "warn".to_string()),
+                (
+                    "iot_packet_verifier::verifier".to_string(),
+                    "debug".to_string(),
+                ),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.filter("info"),
+            "info,file_store=warn,iot_packet_verifier::verifier=debug"
+        );
+        assert_eq!(Settings::default().filter("info"), "info");
+    }
+}
@@ -1,3 +1,4 @@
+use poc_metrics::error_code::{self, ErrorCategory, ErrorCode, ErrorLabels};
 use thiserror::Error;
 
 pub type Result<T = ()> = std::result::Result<T, Error>;
@@ -6,8 +7,14 @@ pub type Result<T = ()> = std::result::Result<T, Error>;
 pub enum Error {
     #[error("Sql error")]
     SqlError(#[from] sqlx::Error),
+    #[error("Migration error")]
+    MigrateError(#[from] sqlx::migrate::MigrateError),
     #[error("Failed to decode value")]
     DecodeError,
+    #[error("Failed to encode value")]
+    EncodeError,
+    #[error("meta key {0} was changed concurrently")]
+    Conflict(String),
     #[error("meta key not found {0}")]
     NotFound(String),
     #[error("invalid configuration: {0}")]
@@ -20,6 +27,8 @@ pub enum Error {
     SigningError(#[from] aws_sig_auth::signer::SigningError),
     #[error("tokio join error")]
     JoinError(#[from] tokio::task::JoinError),
+    #[error("leadership lost for {0}")]
+    LeadershipLost(String),
     #[error("invalid auth token, does not start with http")]
     InvalidAuthToken(),
 }
@@ -27,3 +36,62 @@ pub enum Error {
 pub fn invalid_configuration(str: impl Into<String>) -> Error {
     Error::InvalidConfiguration(str.into())
 }
+
+impl ErrorCode for Error {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::SqlError(err) => sqlx_error_labels(err).category,
+            Self::InvalidConfiguration(_) | Self::InvalidAuthToken() => ErrorCategory::Config,
+            Self::AwsStsError(_) | Self::InvalidAssumedCredentials(_) | Self::SigningError(_) => {
+                ErrorCategory::Network
+            }
+            Self::JoinError(_) | Self::LeadershipLost(_) => ErrorCategory::Internal,
+            _ => ErrorCategory::Database,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::SqlError(err) => sqlx_error_labels(err).code,
+            Self::MigrateError(_) => "migration",
+            Self::DecodeError => "decode_value",
+            Self::EncodeError => "encode_value",
+            Self::Conflict(_) => "meta_conflict",
+            Self::NotFound(_) => "meta_not_found",
+            Self::InvalidConfiguration(_) => "invalid_database_settings",
+            Self::AwsStsError(_) => "assume_role",
+            Self::InvalidAssumedCredentials(_) => "assumed_credentials",
+            Self::SigningError(_) => "iam_auth_signing",
+            Self::JoinError(_) => "join",
+            Self::LeadershipLost(_) => "leadership_lost",
+            Self::InvalidAuthToken() => "invalid_auth_token",
+        }
+    }
+}
+
+/// Labels of the errors of sqlx, shared by every crate running queries
+pub fn sqlx_error_labels(err: &sqlx::Error) -> ErrorLabels {
+    let code = match err {
+        sqlx::Error::Configuration(_) => {
+            return ErrorLabels::new(ErrorCategory::Config, "database_url")
+        }
+        sqlx::Error::Database(_) => "query_rejected",
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) => "connection",
+        sqlx::Error::PoolTimedOut => "pool_timeout",
+        sqlx::Error::PoolClosed => "pool_closed",
+        sqlx::Error::RowNotFound => "row_not_found",
+        sqlx::Error::ColumnDecode { .. } | sqlx::Error::Decode(_) => "decode_row",
+        sqlx::Error::Migrate(_) => "migration",
+        _ => "sql",
+    };
+    ErrorLabels::new(ErrorCategory::Database, code)
+}
+
+/// Label the errors of this crate and of sqlx in metrics and logs
+pub fn register_error_codes() {
+    error_code::register::<Error>();
+    error_code::register_with(sqlx_error_labels);
+    error_code::register_with(|_: &sqlx::migrate::MigrateError| {
+        ErrorLabels::new(ErrorCategory::Database, "migration")
+    });
+}
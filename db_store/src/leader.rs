@@ -0,0 +1,176 @@
+use crate::{Error, Result};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LeaderElectionSettings {
+    /// Seconds after which the lease of an unresponsive leader expires and a
+    /// standby instance takes over. Default 30
+    #[serde(default = "default_lease_timeout")]
+    pub lease_timeout: u64,
+}
+
+fn default_lease_timeout() -> u64 {
+    30
+}
+
+impl LeaderElectionSettings {
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.lease_timeout == 0 {
+            problems.push("leader_election.lease_timeout must be at least 1".to_string());
+        }
+        problems
+    }
+}
+
+// Distinguishes the elections created by a single process
+static ELECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Lease based leader election so only one of several replicas of a service
+/// sharing a database is active. The leader renews its lease well within the
+/// lease timeout; a standby acquires the lease once it has expired. Failing
+/// database calls are retried, the leader only gives up once its lease could
+/// have expired without being renewed.
+///
+/// Services using it need a `leader_leases` table:
+///
+/// ```sql
+/// CREATE TABLE leader_leases (
+///     name TEXT PRIMARY KEY,
+///     holder TEXT NOT NULL,
+///     expires_at TIMESTAMPTZ NOT NULL
+/// );
+/// ```
+pub struct LeaderElection {
+    pool: Pool<Postgres>,
+    name: String,
+    holder: String,
+    lease_timeout: Duration,
+}
+
+impl LeaderElection {
+    pub fn new(pool: Pool<Postgres>, name: &str, settings: &LeaderElectionSettings) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis())
+            .unwrap_or_default();
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string());
+        Self {
+            pool,
+            name: name.to_string(),
+            holder: format!(
+                "{host}-{}-{started}-{}",
+                std::process::id(),
+                ELECTIONS.fetch_add(1, Ordering::Relaxed)
+            ),
+            lease_timeout: Duration::from_secs(settings.lease_timeout),
+        }
+    }
+
+    fn renew_interval(&self) -> Duration {
+        self.lease_timeout / 3
+    }
+
+    /// Wait until this instance holds the lease. Returns false if shutdown is
+    /// triggered first.
+    pub async fn wait_for_leadership(&self, shutdown: &triggered::Listener) -> Result<bool> {
+        let mut standby_logged = false;
+        loop {
+            match self.try_acquire().await {
+                Ok(true) => {
+                    tracing::info!(name = %self.name, holder = %self.holder, "acquired leadership");
+                    return Ok(true);
+                }
+                Ok(false) if !standby_logged => {
+                    tracing::info!(name = %self.name, holder = %self.holder, "waiting as standby");
+                    standby_logged = true;
+                }
+                Ok(false) => (),
+                Err(err) => {
+                    tracing::warn!(name = %self.name, ?err, "failed to acquire lease, retrying");
+                }
+            }
+            tokio::select! {
+                _ = shutdown.clone() => return Ok(false),
+                _ = tokio::time::sleep(self.renew_interval()) => (),
+            }
+        }
+    }
+
+    /// Keep renewing the lease until shutdown, then release it so a standby
+    /// can take over right away. Fails if the lease was lost to another
+    /// instance, or could not be renewed before it expired, in which case
+    /// this instance must stop processing.
+    pub async fn maintain(self, shutdown: triggered::Listener) -> Result {
+        let mut renew_timer = tokio::time::interval(self.renew_interval());
+        renew_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // the lease was acquired or renewed right before maintaining it
+        let mut renewed_at = Instant::now();
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                _ = renew_timer.tick() => self.renew(&mut renewed_at).await?,
+            }
+        }
+        self.release().await
+    }
+
+    /// Renew the lease, tolerating failing database calls until the lease
+    /// could expire before the next renewal
+    async fn renew(&self, renewed_at: &mut Instant) -> Result {
+        match self.try_acquire().await {
+            Ok(true) => {
+                *renewed_at = Instant::now();
+                Ok(())
+            }
+            Ok(false) => {
+                tracing::error!(name = %self.name, holder = %self.holder, "lost leadership");
+                Err(Error::LeadershipLost(self.name.clone()))
+            }
+            Err(err) if renewed_at.elapsed() + self.renew_interval() < self.lease_timeout => {
+                tracing::warn!(name = %self.name, ?err, "failed to renew lease, retrying");
+                Ok(())
+            }
+            Err(err) => {
+                tracing::error!(name = %self.name, ?err, "lease expired before it was renewed");
+                Err(Error::LeadershipLost(self.name.clone()))
+            }
+        }
+    }
+
+    async fn try_acquire(&self) -> Result<bool> {
+        let acquired = sqlx::query(
+            r#"
+            insert into leader_leases (name, holder, expires_at)
+            values ($1, $2, now() + make_interval(secs => $3))
+            on conflict (name) do update set
+                holder = excluded.holder,
+                expires_at = excluded.expires_at
+            where leader_leases.holder = excluded.holder
+                or leader_leases.expires_at < now()
+            returning holder
+            "#,
+        )
+        .bind(&self.name)
+        .bind(&self.holder)
+        .bind(self.lease_timeout.as_secs_f64())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(acquired.is_some())
+    }
+
+    async fn release(&self) -> Result {
+        sqlx::query("delete from leader_leases where name = $1 and holder = $2")
+            .bind(&self.name)
+            .bind(&self.holder)
+            .execute(&self.pool)
+            .await?;
+        tracing::info!(name = %self.name, holder = %self.holder, "released leadership");
+        Ok(())
+    }
+}
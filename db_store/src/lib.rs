@@ -4,10 +4,17 @@ mod iam_auth_pool;
 mod metric_tracker;
 mod settings;
 
-pub use error::{Error, Result};
+pub use error::{register_error_codes, sqlx_error_labels, Error, Result};
+pub use leader::{LeaderElection, LeaderElectionSettings};
+pub use maintenance::{Maintenance, MaintenanceSettings};
+pub use partitions::{PartitionSettings, Partitioner};
 pub use settings::Settings;
 
+pub mod leader;
+pub mod maintenance;
 pub mod meta;
+pub mod migrate;
+pub mod partitions;
 
 /// A key-value pair that is stored in the metadata table.
 pub struct MetaValue<T> {
@@ -83,4 +90,37 @@ where
         meta::store(exec, &self.key, new_val.to_string()).await?;
         Ok(std::mem::replace(&mut self.value, new_val))
     }
+
+    /// Update the stored value only if it still holds the value last seen by
+    /// this instance, failing with [`Error::Conflict`] when another writer
+    /// changed it in the meantime. The transaction is committed with the
+    /// update, and the value of this instance only changes once the commit
+    /// succeeded; on any failure the transaction is rolled back. Use
+    /// [`MetaValue::refresh`] to pick up the current value after a conflict.
+    pub async fn compare_and_update(
+        &mut self,
+        mut transaction: sqlx::Transaction<'_, sqlx::Postgres>,
+        new_val: T,
+    ) -> Result<T> {
+        let expected = Some(self.value.to_string());
+        if !meta::compare_and_store(&mut transaction, &self.key, expected, new_val.to_string())
+            .await?
+        {
+            return Err(Error::Conflict(self.key.clone()));
+        }
+        transaction.commit().await?;
+        Ok(std::mem::replace(&mut self.value, new_val))
+    }
+
+    /// Reload the value from the meta table, returning the previous value
+    pub async fn refresh<'c, E>(&mut self, exec: E) -> Result<T>
+    where
+        E: sqlx::PgExecutor<'c>,
+    {
+        let value = meta::fetch::<String>(exec, &self.key)
+            .await?
+            .parse()
+            .map_err(|_| Error::DecodeError)?;
+        Ok(std::mem::replace(&mut self.value, value))
+    }
 }
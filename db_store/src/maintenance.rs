@@ -0,0 +1,238 @@
+//! Scheduled purging of old rows from tables that otherwise grow without
+//! bound.
+//!
+//! Every service declares the tables that may be purged together with the
+//! column holding the age of a row. Operators pick the retention of each
+//! table in the settings; tables without a retention are left alone. Rows
+//! are deleted in batches, optionally moving them to a `<table>_archive`
+//! table first. Archive tables are created by the migrations of the service,
+//! which declares the tables that have one.
+
+use crate::{error::invalid_configuration, Result};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use std::{collections::HashMap, time::Duration};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MaintenanceSettings {
+    /// Minutes between maintenance runs. Default 60
+    #[serde(default = "default_period")]
+    pub period: u64,
+    /// Rows deleted per statement, so a large backlog does not hold locks for
+    /// long. Default 10_000
+    #[serde(default = "default_batch_size")]
+    pub batch_size: u64,
+    /// Retention keyed by table name. Tables without one are never purged
+    #[serde(default)]
+    pub retention: HashMap<String, RetentionSettings>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RetentionSettings {
+    /// Rows older than this many days are purged
+    pub days: u64,
+    /// Move the purged rows to `<table>_archive` instead of dropping them.
+    /// Default false
+    #[serde(default)]
+    pub archive: bool,
+}
+
+fn default_period() -> u64 {
+    60
+}
+
+fn default_batch_size() -> u64 {
+    10_000
+}
+
+impl Default for MaintenanceSettings {
+    fn default() -> Self {
+        Self {
+            period: default_period(),
+            batch_size: default_batch_size(),
+            retention: HashMap::new(),
+        }
+    }
+}
+
+/// A table that can be purged by the maintenance
+#[derive(Clone, Copy, Debug)]
+pub struct Table {
+    pub name: &'static str,
+    /// Column compared against the retention
+    pub timestamp_column: &'static str,
+    /// Additional condition a row must meet to be purged
+    pub condition: Option<&'static str>,
+    /// Whether the migrations create a `<name>_archive` table purged rows
+    /// can be moved to
+    pub archived: bool,
+}
+
+impl Table {
+    pub const fn new(name: &'static str, timestamp_column: &'static str) -> Self {
+        Self {
+            name,
+            timestamp_column,
+            condition: None,
+            archived: false,
+        }
+    }
+
+    /// Only purge the rows matching the given condition
+    pub const fn condition(self, condition: &'static str) -> Self {
+        Self {
+            condition: Some(condition),
+            ..self
+        }
+    }
+
+    /// Rows can be moved to the `<name>_archive` table of the migrations
+    pub const fn archived(self) -> Self {
+        Self {
+            archived: true,
+            ..self
+        }
+    }
+
+    fn archive_name(&self) -> String {
+        format!("{}_archive", self.name)
+    }
+
+    fn purge_query(&self, archive: bool) -> String {
+        let condition = self
+            .condition
+            .map(|condition| format!(" AND ({condition})"))
+            .unwrap_or_default();
+        // Row ids are only unique within a partition of a partitioned table
+        let delete = format!(
+            r#"
+            DELETE FROM {table} WHERE (tableoid, ctid) IN (
+                SELECT tableoid, ctid FROM {table}
+                WHERE {column} < now() - make_interval(days => $1){condition}
+                LIMIT $2
+            )
+            "#,
+            table = self.name,
+            column = self.timestamp_column,
+        );
+        if archive {
+            format!(
+                "WITH purged AS ({delete} RETURNING *) INSERT INTO {} SELECT * FROM purged",
+                self.archive_name()
+            )
+        } else {
+            delete
+        }
+    }
+}
+
+struct Purge {
+    table: Table,
+    days: i32,
+    archive: bool,
+}
+
+pub struct Maintenance {
+    pool: Pool<Postgres>,
+    rows_purged_name: String,
+    table_bytes_name: String,
+    period: Duration,
+    batch_size: i64,
+    purges: Vec<Purge>,
+}
+
+impl Maintenance {
+    /// Fails when the settings retain a table that is not one of `tables`
+    pub fn new(
+        pool: Pool<Postgres>,
+        app_name: &str,
+        settings: &MaintenanceSettings,
+        tables: &[Table],
+    ) -> Result<Self> {
+        let mut purges = Vec::new();
+        for (name, retention) in &settings.retention {
+            let table = tables
+                .iter()
+                .find(|table| table.name == name)
+                .ok_or_else(|| invalid_configuration(format!("table {name} can not be purged")))?;
+            if retention.days == 0 {
+                return Err(invalid_configuration(format!(
+                    "retention of {name} must be at least 1 day"
+                )));
+            }
+            if retention.archive && !table.archived {
+                return Err(invalid_configuration(format!(
+                    "table {name} has no archive table"
+                )));
+            }
+            purges.push(Purge {
+                table: *table,
+                days: retention.days.min(i32::MAX as u64) as i32,
+                archive: retention.archive,
+            });
+        }
+        Ok(Self {
+            pool,
+            rows_purged_name: format!("{app_name}_db_rows_purged"),
+            table_bytes_name: format!("{app_name}_db_table_bytes"),
+            period: Duration::from_secs(60 * settings.period.max(1)),
+            batch_size: settings.batch_size.clamp(1, i64::MAX as u64) as i64,
+            purges,
+        })
+    }
+
+    /// Purge the tables every period until shutdown. Failures are logged and
+    /// retried on the next run.
+    pub async fn run(self, shutdown: triggered::Listener) -> Result {
+        if self.purges.is_empty() {
+            return Ok(());
+        }
+        let mut trigger = tokio::time::interval(self.period);
+        trigger.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                _ = trigger.tick() => self.purge_all().await,
+            }
+        }
+        Ok(())
+    }
+
+    /// Purge every table with a retention once, logging the failures
+    pub async fn purge_all(&self) {
+        for purge in &self.purges {
+            if let Err(err) = self.purge(purge).await {
+                tracing::error!(table = purge.table.name, "failed to purge table: {err}");
+            }
+        }
+    }
+
+    async fn purge(&self, purge: &Purge) -> Result {
+        let table = purge.table;
+        let query = table.purge_query(purge.archive);
+        let mut purged = 0;
+        loop {
+            let rows = sqlx::query(&query)
+                .bind(purge.days)
+                .bind(self.batch_size)
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+            purged += rows;
+            if rows < self.batch_size as u64 {
+                break;
+            }
+        }
+        if purged > 0 {
+            tracing::info!(table = table.name, purged, "purged old rows");
+        }
+        metrics::counter!(self.rows_purged_name.clone(), purged, "table" => table.name);
+
+        let bytes: i64 = sqlx::query_scalar("SELECT pg_total_relation_size($1::regclass)")
+            .bind(table.name)
+            .fetch_one(&self.pool)
+            .await?;
+        metrics::gauge!(self.table_bytes_name.clone(), bytes as f64, "table" => table.name);
+        Ok(())
+    }
+}
@@ -1,7 +1,15 @@
 use std::str::FromStr;
 
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::postgres::{PgListener, PgPool};
+
 use crate::{Error, Result};
 
+/// Notification channel on which the key of every changed meta value is
+/// published once the change is committed
+pub const META_CHANGED_CHANNEL: &str = "db_store_meta_changed";
+
 macro_rules! query_exec_timed {
     ( $name:literal, $query:expr, $meth:ident, $exec:expr ) => {{
         match poc_metrics::record_duration!(concat!($name, "_duration"), $query.$meth($exec).await) {
@@ -23,14 +31,19 @@ where
 {
     let query = sqlx::query(
         r#"
-            insert into meta(key, value)
-            values ($1, $2)
-            on conflict (key) do update set
-            value = EXCLUDED.value
+            with stored as (
+                insert into meta(key, value)
+                values ($1, $2)
+                on conflict (key) do update set
+                value = EXCLUDED.value
+                returning key
+            )
+            select pg_notify($3, key) from stored
             "#,
     )
     .bind(key)
-    .bind(value.to_string());
+    .bind(value.to_string())
+    .bind(META_CHANGED_CHANNEL);
     query_exec_timed!("db_store_meta_store", query, execute, exec).map(|_| ())
 }
 
@@ -48,3 +61,163 @@ where
         .ok_or_else(|| Error::NotFound(key.to_string()))
         .and_then(|value| value.parse().map_err(|_| Error::DecodeError))
 }
+
+/// A value that can be stored in the meta table
+pub trait MetaType: Sized {
+    fn encode(&self) -> Result<String>;
+    fn decode(value: &str) -> Result<Self>;
+}
+
+macro_rules! impl_meta_type_from_str {
+    ( $( $t:ty ),* ) => {
+        $(
+            impl MetaType for $t {
+                fn encode(&self) -> Result<String> {
+                    Ok(self.to_string())
+                }
+
+                fn decode(value: &str) -> Result<Self> {
+                    value.parse().map_err(|_| Error::DecodeError)
+                }
+            }
+        )*
+    };
+}
+
+impl_meta_type_from_str!(i64, u64, bool, String);
+
+/// Timestamps are stored as seconds since the unix epoch, matching the
+/// existing `store(exec, key, timestamp.timestamp())` convention
+impl MetaType for DateTime<Utc> {
+    fn encode(&self) -> Result<String> {
+        Ok(self.timestamp().to_string())
+    }
+
+    fn decode(value: &str) -> Result<Self> {
+        let secs = i64::decode(value)?;
+        Utc.timestamp_opt(secs, 0)
+            .single()
+            .ok_or(Error::DecodeError)
+    }
+}
+
+/// Wrapper to store any serde type as a json blob
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T> MetaType for Json<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn encode(&self) -> Result<String> {
+        serde_json::to_string(&self.0).map_err(|_| Error::EncodeError)
+    }
+
+    fn decode(value: &str) -> Result<Self> {
+        serde_json::from_str(value)
+            .map(Json)
+            .map_err(|_| Error::DecodeError)
+    }
+}
+
+/// Fetch a typed meta value, returning `None` if the key is not set
+pub async fn get<T>(exec: impl sqlx::PgExecutor<'_>, key: &str) -> Result<Option<T>>
+where
+    T: MetaType,
+{
+    match fetch::<String>(exec, key).await {
+        Ok(value) => T::decode(&value).map(Some),
+        Err(Error::NotFound(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Store a typed meta value, replacing any existing value
+pub async fn set<T>(exec: impl sqlx::PgExecutor<'_>, key: &str, value: &T) -> Result
+where
+    T: MetaType,
+{
+    store(exec, key, value.encode()?).await
+}
+
+/// Atomically replace the value of `key` with `new` only if it currently
+/// holds `expected`, where an `expected` of `None` requires the key to be
+/// unset. Returns whether the value was replaced. The change only takes
+/// effect, and is only notified, when the caller commits the transaction.
+pub async fn compare_and_set<T>(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    key: &str,
+    expected: Option<&T>,
+    new: &T,
+) -> Result<bool>
+where
+    T: MetaType,
+{
+    let expected = expected.map(T::encode).transpose()?;
+    compare_and_store(transaction, key, expected, new.encode()?).await
+}
+
+pub(crate) async fn compare_and_store(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    key: &str,
+    expected: Option<String>,
+    new: String,
+) -> Result<bool> {
+    let query = match expected {
+        None => sqlx::query(
+            r#"
+                with stored as (
+                    insert into meta(key, value)
+                    values ($1, $2)
+                    on conflict (key) do nothing
+                    returning key
+                )
+                select pg_notify($3, key) from stored
+                "#,
+        )
+        .bind(key)
+        .bind(new)
+        .bind(META_CHANGED_CHANNEL),
+        Some(expected) => sqlx::query(
+            r#"
+                with stored as (
+                    update meta set value = $2
+                    where key = $1 and value = $4
+                    returning key
+                )
+                select pg_notify($3, key) from stored
+                "#,
+        )
+        .bind(key)
+        .bind(new)
+        .bind(META_CHANGED_CHANNEL)
+        .bind(expected),
+    };
+    let rows = query_exec_timed!(
+        "db_store_meta_compare_and_store",
+        query,
+        fetch_all,
+        &mut *transaction
+    )?;
+    Ok(!rows.is_empty())
+}
+
+/// Listener for committed changes to meta values, so a service can react to
+/// checkpoints moved by another instance
+pub struct MetaListener {
+    listener: PgListener,
+}
+
+impl MetaListener {
+    pub async fn connect(pool: &PgPool) -> Result<Self> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(META_CHANGED_CHANNEL).await?;
+        Ok(Self { listener })
+    }
+
+    /// Wait for the next changed meta key
+    pub async fn recv(&mut self) -> Result<String> {
+        let notification = self.listener.recv().await?;
+        Ok(notification.payload().to_string())
+    }
+}
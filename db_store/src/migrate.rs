@@ -0,0 +1,78 @@
+use crate::Result;
+use sqlx::{migrate::Migrator, PgConnection, PgPool};
+
+/// Run the embedded migrations of a service, typically `sqlx::migrate!()`,
+/// and record the applied versions in the `migration_meta` table.
+///
+/// Migrations run while holding a Postgres advisory lock keyed on the app
+/// name so replicas of a service starting at the same time apply them once,
+/// one after the other. Returns the latest applied version, if any.
+pub async fn run(pool: &PgPool, app_name: &str, migrator: &Migrator) -> Result<Option<i64>> {
+    let mut conn = pool.acquire().await?;
+
+    tracing::info!(app_name, "acquiring migration lock");
+    sqlx::query("select pg_advisory_lock(hashtext($1))")
+        .bind(app_name)
+        .execute(&mut *conn)
+        .await?;
+
+    let result = migrate(&mut conn, app_name, migrator).await;
+
+    let unlocked = sqlx::query("select pg_advisory_unlock(hashtext($1))")
+        .bind(app_name)
+        .execute(&mut *conn)
+        .await;
+    if let Err(err) = unlocked {
+        // Closing the connection releases the lock, never return it to the
+        // pool while still locked
+        tracing::warn!(app_name, "failed to release migration lock: {err:?}");
+        conn.detach();
+    }
+
+    result
+}
+
+async fn migrate(
+    conn: &mut PgConnection,
+    app_name: &str,
+    migrator: &Migrator,
+) -> Result<Option<i64>> {
+    migrator.run(&mut *conn).await?;
+
+    sqlx::query(
+        r#"
+        create table if not exists migration_meta (
+            app_name text not null,
+            version bigint not null,
+            description text not null,
+            applied_at timestamptz not null default now(),
+            primary key (app_name, version)
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let mut latest = None;
+    for migration in migrator
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+    {
+        sqlx::query(
+            r#"
+            insert into migration_meta (app_name, version, description)
+            values ($1, $2, $3)
+            on conflict (app_name, version) do nothing
+            "#,
+        )
+        .bind(app_name)
+        .bind(migration.version)
+        .bind(migration.description.as_ref())
+        .execute(&mut *conn)
+        .await?;
+        latest = latest.max(Some(migration.version));
+    }
+
+    tracing::info!(app_name, version = ?latest, "database migrations applied");
+    Ok(latest)
+}
@@ -0,0 +1,262 @@
+//! Tables partitioned by day.
+//!
+//! A partitioned table is split by range of a timestamp column into one
+//! partition per utc day, named `<table>_pYYYYMMDD`. The partitioner creates
+//! the partitions of the coming days ahead of time, and of any range a
+//! service is about to write, and drops the partitions that ended longer ago
+//! than the retention. Rows of the day of a new partition that were written
+//! to the default partition before it existed are moved into it. The
+//! partitioned table itself, its default partition and the partitions of rows
+//! that existed before the table was partitioned are created by the
+//! migrations of the service.
+
+use crate::{error::invalid_configuration, Result};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use std::{
+    collections::HashSet,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PartitionSettings {
+    /// Minutes between partition maintenance runs. Default 60
+    #[serde(default = "default_period")]
+    pub period: u64,
+    /// Days of partitions created ahead. Default 2
+    #[serde(default = "default_premake")]
+    pub premake: u64,
+    /// Partitions ending more than this many days ago are dropped. Partitions
+    /// are never dropped when not set
+    pub retention_days: Option<u64>,
+}
+
+fn default_period() -> u64 {
+    60
+}
+
+fn default_premake() -> u64 {
+    2
+}
+
+impl Default for PartitionSettings {
+    fn default() -> Self {
+        Self {
+            period: default_period(),
+            premake: default_premake(),
+            retention_days: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Partitioner {
+    pool: Pool<Postgres>,
+    table: &'static str,
+    /// Timestamp column the table is partitioned by
+    column: &'static str,
+    period: std::time::Duration,
+    premake: i64,
+    retention: Option<Duration>,
+    /// Days known to have a partition
+    created: Arc<Mutex<HashSet<NaiveDate>>>,
+}
+
+impl Partitioner {
+    pub fn new(
+        pool: Pool<Postgres>,
+        table: &'static str,
+        column: &'static str,
+        settings: &PartitionSettings,
+    ) -> Result<Self> {
+        if settings.retention_days == Some(0) {
+            return Err(invalid_configuration(format!(
+                "partition retention of {table} must be at least 1 day"
+            )));
+        }
+        Ok(Self {
+            pool,
+            table,
+            column,
+            period: std::time::Duration::from_secs(60 * settings.period.max(1)),
+            premake: settings.premake.min(366) as i64,
+            retention: settings
+                .retention_days
+                .map(|days| Duration::days(days.min(i32::MAX as u64) as i64)),
+            created: Arc::default(),
+        })
+    }
+
+    fn partition_name(&self, day: NaiveDate) -> String {
+        format!("{}_p{}", self.table, day.format("%Y%m%d"))
+    }
+
+    fn partition_day(&self, name: &str) -> Option<NaiveDate> {
+        let suffix = name.strip_prefix(self.table)?.strip_prefix("_p")?;
+        NaiveDate::parse_from_str(suffix, "%Y%m%d").ok()
+    }
+
+    /// Create the partitions of every day overlapping the range
+    pub async fn ensure(&self, range: &Range<DateTime<Utc>>) -> Result {
+        if range.is_empty() {
+            return Ok(());
+        }
+        let last = (range.end - Duration::nanoseconds(1)).naive_utc().date();
+        let mut day = range.start.naive_utc().date();
+        while day <= last {
+            self.create(day).await?;
+            day = match day.succ_opt() {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        Ok(())
+    }
+
+    async fn create(&self, day: NaiveDate) -> Result {
+        if self.created.lock().expect("created days").contains(&day) {
+            return Ok(());
+        }
+        let start = Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0).expect("midnight"));
+        let end = start + Duration::days(1);
+        let name = self.partition_name(day);
+        let mut transaction = self.pool.begin().await?;
+        // Serializes the creation of the partitions of the table across
+        // replicas
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(self.table)
+            .execute(&mut transaction)
+            .await?;
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(&name)
+            .fetch_one(&mut transaction)
+            .await?;
+        if !exists {
+            // Attaching a partition fails while the default partition holds
+            // rows of its range, so the partition is created detached and
+            // those rows moved into it first
+            sqlx::query(&format!(
+                "CREATE TABLE {name} (LIKE {table} INCLUDING DEFAULTS INCLUDING CONSTRAINTS)",
+                table = self.table,
+            ))
+            .execute(&mut transaction)
+            .await?;
+            let default: Option<String> = sqlx::query_scalar(
+                r#"
+                SELECT default_partition.relname::text
+                FROM pg_partitioned_table
+                JOIN pg_class default_partition ON pg_partitioned_table.partdefid = default_partition.oid
+                WHERE pg_partitioned_table.partrelid = $1::regclass
+                "#,
+            )
+            .bind(self.table)
+            .fetch_optional(&mut transaction)
+            .await?;
+            if let Some(default) = default {
+                let moved = sqlx::query(&format!(
+                    r#"
+                    WITH moved AS (
+                        DELETE FROM {default} WHERE {column} >= $1 AND {column} < $2 RETURNING *
+                    )
+                    INSERT INTO {name} SELECT * FROM moved
+                    "#,
+                    column = self.column,
+                ))
+                .bind(start)
+                .bind(end)
+                .execute(&mut transaction)
+                .await?
+                .rows_affected();
+                if moved > 0 {
+                    tracing::warn!(
+                        table = self.table,
+                        partition = %name,
+                        moved,
+                        "moved rows of the default partition"
+                    );
+                }
+            }
+            sqlx::query(&format!(
+                "ALTER TABLE {table} ATTACH PARTITION {name} FOR VALUES FROM ('{start}') TO ('{end}')",
+                table = self.table,
+                start = start.to_rfc3339(),
+                end = end.to_rfc3339(),
+            ))
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        self.created.lock().expect("created days").insert(day);
+        Ok(())
+    }
+
+    /// Drop the partitions ending before the retention, returning their names
+    pub async fn drop_expired(&self, now: DateTime<Utc>) -> Result<Vec<String>> {
+        let Some(retention) = self.retention else {
+            return Ok(vec![]);
+        };
+        let expired_before = (now - retention).naive_utc().date();
+        let partitions: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT child.relname::text
+            FROM pg_inherits
+            JOIN pg_class parent ON pg_inherits.inhparent = parent.oid
+            JOIN pg_class child ON pg_inherits.inhrelid = child.oid
+            WHERE parent.relname = $1
+            "#,
+        )
+        .bind(self.table)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut dropped = vec![];
+        for name in partitions {
+            // The default partition and partitions named otherwise are kept
+            let Some(day) = self.partition_day(&name) else {
+                continue;
+            };
+            // A partition ends at the start of the following day
+            if day >= expired_before {
+                continue;
+            }
+            sqlx::query(&format!("DROP TABLE IF EXISTS {name}"))
+                .execute(&self.pool)
+                .await?;
+            self.created.lock().expect("created days").remove(&day);
+            dropped.push(name);
+        }
+        Ok(dropped)
+    }
+
+    /// Create the partitions from yesterday to the days ahead and drop the
+    /// expired ones every period until shutdown. Failures are logged and
+    /// retried on the next run.
+    pub async fn run(self, shutdown: triggered::Listener) -> Result {
+        let mut trigger = tokio::time::interval(self.period);
+        trigger.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                _ = trigger.tick() => {
+                    let now = Utc::now();
+                    let ahead = now - Duration::days(1)..now + Duration::days(self.premake + 1);
+                    if let Err(err) = self.ensure(&ahead).await {
+                        tracing::error!(table = self.table, "failed to create partitions: {err}");
+                    }
+                    match self.drop_expired(now).await {
+                        Ok(dropped) if !dropped.is_empty() => {
+                            tracing::info!(table = self.table, ?dropped, "dropped expired partitions");
+                        }
+                        Ok(_) => (),
+                        Err(err) => {
+                            tracing::error!(table = self.table, "failed to drop partitions: {err}");
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
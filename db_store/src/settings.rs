@@ -1,4 +1,5 @@
 use crate::{iam_auth_pool, metric_tracker, Error, Result};
+use oracle_settings::Secret;
 use serde::Deserialize;
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
 
@@ -14,8 +15,9 @@ pub struct Settings {
     pub max_connections: u32,
 
     /// URL to access the postgres database, only used when
-    /// the auth_type is Postgres
-    pub url: Option<String>,
+    /// the auth_type is Postgres. May be given as a `file:` or `env:` secret
+    /// reference to keep the password out of the settings file
+    pub url: Option<Secret>,
 
     #[serde(default = "default_auth_type")]
     auth_type: AuthType,
@@ -77,6 +79,8 @@ impl Settings {
             .url
             .as_ref()
             .ok_or_else(|| Error::InvalidConfiguration("url is required".to_string()))?
+            .resolve()
+            .map_err(|err| Error::InvalidConfiguration(err.to_string()))?
             .parse()?;
 
         let pool = self.pool_options().connect_with(connect_options).await?;
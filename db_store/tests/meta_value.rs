@@ -0,0 +1,77 @@
+use db_store::{meta, Error, MetaValue};
+use sqlx::PgPool;
+
+const KEY: &str = "last_processed";
+
+async fn setup(pool: &PgPool) -> MetaValue<u64> {
+    sqlx::query("create table meta (key text primary key not null, value text)")
+        .execute(pool)
+        .await
+        .unwrap();
+    MetaValue::fetch_or_insert_with(pool, KEY, || 1)
+        .await
+        .unwrap()
+}
+
+async fn stored(pool: &PgPool) -> String {
+    meta::fetch::<String>(pool, KEY).await.unwrap()
+}
+
+#[sqlx::test(migrations = false)]
+async fn updates_are_cached_once_committed(pool: PgPool) {
+    let mut value = setup(&pool).await;
+
+    let previous = value
+        .compare_and_update(pool.begin().await.unwrap(), 2)
+        .await
+        .unwrap();
+    assert_eq!(previous, 1);
+    assert_eq!(*value.value(), 2);
+    assert_eq!(stored(&pool).await, "2");
+}
+
+#[sqlx::test(migrations = false)]
+async fn conflicting_updates_keep_the_cached_value(pool: PgPool) {
+    let mut value = setup(&pool).await;
+    let mut other = MetaValue::<u64>::fetch_or_insert_with(&pool, KEY, || 0)
+        .await
+        .unwrap();
+    other
+        .compare_and_update(pool.begin().await.unwrap(), 2)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        value
+            .compare_and_update(pool.begin().await.unwrap(), 3)
+            .await,
+        Err(Error::Conflict(_))
+    ));
+    assert_eq!(*value.value(), 1);
+    assert_eq!(stored(&pool).await, "2");
+
+    value.refresh(&pool).await.unwrap();
+    assert_eq!(*value.value(), 2);
+}
+
+#[sqlx::test(migrations = false)]
+async fn rolled_back_updates_keep_the_cached_value(pool: PgPool) {
+    let mut value = setup(&pool).await;
+    // A deferred constraint the transaction violates, failing its commit
+    sqlx::query("create table commit_guard (id int primary key deferrable initially deferred)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let mut transaction = pool.begin().await.unwrap();
+    sqlx::query("insert into commit_guard values (1), (1)")
+        .execute(&mut transaction)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        value.compare_and_update(transaction, 2).await,
+        Err(Error::SqlError(_))
+    ));
+    assert_eq!(*value.value(), 1);
+    assert_eq!(stored(&pool).await, "1");
+}
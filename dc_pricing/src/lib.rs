@@ -0,0 +1,222 @@
+//! Pricing of data usage in data credits.
+//!
+//! Data is paid for in whole data credits of a fixed number of bytes each,
+//! where the number of bytes differs per network. Usage that does not fill
+//! its last data credit is rounded according to the [`Rounding`] rule, and
+//! every usage costs at least the minimum number of data credits, including
+//! usage of zero bytes. Data credits are priced in USD, by default at
+//! [`USD_PER_DC`].
+
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use rust_decimal_macros::dec;
+use serde::Deserialize;
+
+/// Default price of a single data credit in USD
+pub const USD_PER_DC: Decimal = dec!(0.00001);
+
+/// Bytes of packet payload paid for by a data credit on the iot network
+pub const IOT_BYTES_PER_DC: u64 = 24;
+
+/// Bytes of data transfer paid for by a data credit on the mobile network
+pub const MOBILE_BYTES_PER_DC: u64 = 20_000;
+
+/// How usage not filling its last data credit is charged
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// Any remainder costs a whole data credit
+    #[default]
+    Up,
+    /// Remainders are free
+    Down,
+    /// Remainders of at least half a data credit cost a whole one
+    Nearest,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub struct DcPricing {
+    /// Bytes paid for by a single data credit
+    pub bytes_per_dc: u64,
+    /// Data credits charged for any usage, however small. (Default is 1)
+    #[serde(default = "default_minimum_dc")]
+    pub minimum_dc: u64,
+    /// Rounding of remainders. (Default is up)
+    #[serde(default)]
+    pub rounding: Rounding,
+    /// Price of a single data credit in USD. (Default is 0.00001)
+    #[serde(default = "default_usd_per_dc")]
+    pub usd_per_dc: Decimal,
+}
+
+fn default_minimum_dc() -> u64 {
+    1
+}
+
+fn default_usd_per_dc() -> Decimal {
+    USD_PER_DC
+}
+
+impl DcPricing {
+    pub const fn new(bytes_per_dc: u64) -> Self {
+        Self {
+            bytes_per_dc,
+            minimum_dc: 1,
+            rounding: Rounding::Up,
+            usd_per_dc: USD_PER_DC,
+        }
+    }
+
+    /// Pricing of iot packet payloads
+    pub const fn iot() -> Self {
+        Self::new(IOT_BYTES_PER_DC)
+    }
+
+    /// Pricing of mobile data transfer
+    pub const fn mobile() -> Self {
+        Self::new(MOBILE_BYTES_PER_DC)
+    }
+
+    pub fn minimum_dc(self, minimum_dc: u64) -> Self {
+        Self { minimum_dc, ..self }
+    }
+
+    pub fn rounding(self, rounding: Rounding) -> Self {
+        Self { rounding, ..self }
+    }
+
+    pub fn usd_per_dc(self, usd_per_dc: Decimal) -> Self {
+        Self { usd_per_dc, ..self }
+    }
+
+    /// Problems making the pricing unusable, prefixed with the name of the
+    /// settings section
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.bytes_per_dc == 0 {
+            problems.push("dc_pricing.bytes_per_dc must be at least 1".to_string());
+        }
+        if self.usd_per_dc <= Decimal::ZERO {
+            problems.push(format!(
+                "dc_pricing.usd_per_dc must be positive, got {}",
+                self.usd_per_dc
+            ));
+        }
+        problems
+    }
+
+    // Validated pricing never has zero bytes per data credit, this only
+    // keeps unvalidated pricing from dividing by zero
+    fn bytes_per_dc(&self) -> u64 {
+        self.bytes_per_dc.max(1)
+    }
+
+    /// Data credits charged for the bytes
+    pub fn bytes_to_dc(&self, bytes: u64) -> u64 {
+        let bytes_per_dc = self.bytes_per_dc();
+        let whole = bytes / bytes_per_dc;
+        let remainder = bytes % bytes_per_dc;
+        let rounded_up = match self.rounding {
+            Rounding::Up => remainder > 0,
+            Rounding::Down => false,
+            Rounding::Nearest => remainder >= bytes_per_dc - bytes_per_dc / 2,
+        };
+        (whole + rounded_up as u64).max(self.minimum_dc)
+    }
+
+    /// Bytes paid for by the data credits
+    pub fn dc_to_bytes(&self, dc: u64) -> u64 {
+        dc.saturating_mul(self.bytes_per_dc())
+    }
+
+    /// Bytes in excess of the last whole data credit
+    pub fn remainder(&self, bytes: u64) -> u64 {
+        bytes % self.bytes_per_dc()
+    }
+
+    /// Price of the data credits in USD
+    pub fn dc_to_usd(&self, dc: Decimal) -> Decimal {
+        dc * self.usd_per_dc
+    }
+
+    /// Whole data credits bought by the USD amount, zero for negative amounts
+    pub fn usd_to_dc(&self, usd: Decimal) -> u64 {
+        if self.usd_per_dc <= Decimal::ZERO {
+            return 0;
+        }
+        (usd / self.usd_per_dc)
+            .max(Decimal::ZERO)
+            .trunc()
+            .to_u64()
+            .unwrap_or(u64::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remainders_are_rounded_by_the_rule() {
+        let up = DcPricing::new(24);
+        assert_eq!(up.bytes_to_dc(24), 1);
+        assert_eq!(up.bytes_to_dc(25), 2);
+        assert_eq!(up.bytes_to_dc(48), 2);
+
+        let down = up.rounding(Rounding::Down);
+        assert_eq!(down.bytes_to_dc(47), 1);
+        assert_eq!(down.bytes_to_dc(48), 2);
+
+        let nearest = up.rounding(Rounding::Nearest);
+        assert_eq!(nearest.bytes_to_dc(35), 1);
+        assert_eq!(nearest.bytes_to_dc(36), 2);
+        // Exactly half can not happen with an odd number of bytes
+        let odd = DcPricing::new(5).rounding(Rounding::Nearest);
+        assert_eq!(odd.bytes_to_dc(7), 1);
+        assert_eq!(odd.bytes_to_dc(8), 2);
+    }
+
+    #[test]
+    fn small_usage_costs_the_minimum() {
+        let pricing = DcPricing::mobile();
+        assert_eq!(pricing.bytes_to_dc(0), 1);
+        assert_eq!(pricing.bytes_to_dc(1), 1);
+        assert_eq!(
+            pricing
+                .rounding(Rounding::Down)
+                .minimum_dc(0)
+                .bytes_to_dc(1),
+            0
+        );
+        assert_eq!(DcPricing::iot().minimum_dc(3).bytes_to_dc(48), 3);
+    }
+
+    #[test]
+    fn bytes_and_remainders_of_data_credits() {
+        let pricing = DcPricing::iot();
+        assert_eq!(pricing.dc_to_bytes(2), 48);
+        assert_eq!(pricing.dc_to_bytes(u64::MAX), u64::MAX);
+        assert_eq!(pricing.remainder(50), 2);
+        assert_eq!(pricing.bytes_to_dc(u64::MAX), u64::MAX / 24 + 1);
+    }
+
+    #[test]
+    fn data_credits_are_priced_in_usd() {
+        let pricing = DcPricing::iot();
+        assert_eq!(pricing.dc_to_usd(dec!(100_000)), dec!(1));
+        assert_eq!(pricing.usd_to_dc(dec!(1)), 100_000);
+        assert_eq!(pricing.usd_to_dc(dec!(0.000019)), 1);
+        assert_eq!(pricing.usd_to_dc(dec!(-1)), 0);
+
+        let doubled = pricing.usd_per_dc(dec!(0.00002));
+        assert_eq!(doubled.dc_to_usd(dec!(100_000)), dec!(2));
+        assert_eq!(doubled.usd_to_dc(dec!(1)), 50_000);
+    }
+
+    #[test]
+    fn unusable_pricing_is_rejected() {
+        assert!(DcPricing::iot().problems().is_empty());
+        assert_eq!(DcPricing::new(0).problems().len(), 1);
+        assert_eq!(DcPricing::mobile().usd_per_dc(dec!(0)).problems().len(), 1);
+        assert_eq!(DcPricing::new(0).usd_per_dc(dec!(-0.1)).problems().len(), 2);
+    }
+}
@@ -100,6 +100,12 @@ impl DenyList {
 
 /// deconstruct bytes into the filter component parts
 pub fn filter_from_bin(bin: &Vec<u8>) -> Result<Xor32> {
+    serial_and_filter_from_bin(bin).map(|(_serial, filter)| filter)
+}
+
+/// deconstruct bytes into the serial number of the filter and the filter,
+/// verifying the filter signature
+pub fn serial_and_filter_from_bin(bin: &[u8]) -> Result<(u32, Xor32)> {
     if bin.is_empty() {
         return Err(Error::InvalidBinary("invalid filter bin".to_string()));
     }
@@ -112,9 +118,9 @@ pub fn filter_from_bin(bin: &Vec<u8>) -> Result<Xor32> {
     match pubkey.verify(buf, &signature) {
         Ok(_) => {
             tracing::info!("updating filter to latest");
-            let _serial = buf.get_u32_le();
+            let serial = buf.get_u32_le();
             let xor = bincode::deserialize::<Xor32>(buf)?;
-            Ok(xor)
+            Ok((serial, xor))
         }
         Err(_) => {
             tracing::warn!("filter signature verification failed");
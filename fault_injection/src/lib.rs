@@ -0,0 +1,242 @@
+//! Programmable faults for tests of the retry and rollback paths.
+//!
+//! A [`Faulty`] wraps an implementation of one of the service traits, like a
+//! debiter, a config server, the pending burns or a packet writer, and
+//! consults its [`Faults`] before every call. A call can be delayed, fail
+//! with an [`InjectedFault`] without reaching the wrapped implementation, or
+//! reach it and fail anyway, like a write whose acknowledgement is lost.
+//! Faults are decided by counting calls so tests are deterministic. Clones
+//! share their state, so a test can change the faults of a wrapper it has
+//! already handed to the code under test.
+//!
+//! The trait implementations for [`Faulty`] live next to the traits.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("injected fault on call {call}")]
+pub struct InjectedFault {
+    /// Number of the failed call, starting at 1
+    pub call: u64,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FaultyError<E> {
+    #[error(transparent)]
+    Injected(#[from] InjectedFault),
+    #[error("{0}")]
+    Inner(E),
+}
+
+impl<E> FaultyError<E> {
+    pub fn is_injected(&self) -> bool {
+        matches!(self, Self::Injected(_))
+    }
+}
+
+/// How a call is disturbed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The call fails without reaching the wrapped implementation
+    Fail(InjectedFault),
+    /// The call reaches the wrapped implementation but fails anyway
+    Partial(InjectedFault),
+}
+
+#[derive(Default)]
+struct State {
+    calls: AtomicU64,
+    injected: AtomicU64,
+    fail_next: AtomicU64,
+    fail_every: AtomicU64,
+    partial_every: AtomicU64,
+    latency_ms: AtomicU64,
+}
+
+/// The faults of one or more wrappers. No call is disturbed by default
+#[derive(Clone, Default)]
+pub struct Faults {
+    state: Arc<State>,
+}
+
+impl Faults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail every nth call, zero never fails
+    pub fn fail_every(self, n: u64) -> Self {
+        self.state.fail_every.store(n, Ordering::SeqCst);
+        self
+    }
+
+    /// Let every nth call through to the wrapped implementation and fail it
+    /// anyway, zero never does
+    pub fn partial_every(self, n: u64) -> Self {
+        self.state.partial_every.store(n, Ordering::SeqCst);
+        self
+    }
+
+    /// Delay every call
+    pub fn latency(self, latency: Duration) -> Self {
+        self.state
+            .latency_ms
+            .store(latency.as_millis() as u64, Ordering::SeqCst);
+        self
+    }
+
+    /// Fail the next `n` calls, before any other fault applies
+    pub fn fail_next(&self, n: u64) {
+        self.state.fail_next.store(n, Ordering::SeqCst);
+    }
+
+    /// Stop disturbing calls. The counters are kept
+    pub fn clear(&self) {
+        self.state.fail_next.store(0, Ordering::SeqCst);
+        self.state.fail_every.store(0, Ordering::SeqCst);
+        self.state.partial_every.store(0, Ordering::SeqCst);
+        self.state.latency_ms.store(0, Ordering::SeqCst);
+    }
+
+    /// Calls made so far, including the disturbed ones
+    pub fn calls(&self) -> u64 {
+        self.state.calls.load(Ordering::SeqCst)
+    }
+
+    /// Calls that failed because of a fault
+    pub fn injected(&self) -> u64 {
+        self.state.injected.load(Ordering::SeqCst)
+    }
+
+    /// Count a call, waiting out the latency, and decide its fault
+    pub async fn next_call(&self) -> Option<Fault> {
+        let latency = self.state.latency_ms.load(Ordering::SeqCst);
+        if latency > 0 {
+            tokio::time::sleep(Duration::from_millis(latency)).await;
+        }
+
+        let call = self.state.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        let every = |n: &AtomicU64| {
+            let n = n.load(Ordering::SeqCst);
+            n > 0 && call % n == 0
+        };
+        let failed_next = self
+            .state
+            .fail_next
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        let fault = if failed_next || every(&self.state.fail_every) {
+            Fault::Fail(InjectedFault { call })
+        } else if every(&self.state.partial_every) {
+            Fault::Partial(InjectedFault { call })
+        } else {
+            return None;
+        };
+        self.state.injected.fetch_add(1, Ordering::SeqCst);
+        Some(fault)
+    }
+
+    /// Run a call of a wrapped implementation. The call is only started when
+    /// it is not failed outright
+    pub async fn apply<T, E>(
+        &self,
+        call: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, FaultyError<E>> {
+        match self.next_call().await {
+            Some(Fault::Fail(fault)) => Err(fault.into()),
+            Some(Fault::Partial(fault)) => {
+                call.await.map_err(FaultyError::Inner)?;
+                Err(fault.into())
+            }
+            None => call.await.map_err(FaultyError::Inner),
+        }
+    }
+}
+
+/// An implementation disturbed by faults
+#[derive(Clone)]
+pub struct Faulty<T> {
+    pub inner: T,
+    pub faults: Faults,
+}
+
+impl<T> Faulty<T> {
+    pub fn new(inner: T, faults: Faults) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    async fn ok() -> Result<(), ()> {
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn every_nth_call_fails() {
+        let faults = Faults::new().fail_every(3);
+        let mut failed = vec![];
+        for _ in 0..7 {
+            if let Err(err) = faults.apply(ok()).await {
+                let FaultyError::Injected(fault) = err else {
+                    panic!("unexpected error");
+                };
+                failed.push(fault.call);
+            }
+        }
+        assert_eq!(failed, vec![3, 6]);
+        assert_eq!(faults.calls(), 7);
+        assert_eq!(faults.injected(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_calls_do_not_reach_the_implementation() {
+        let reached = AtomicBool::new(false);
+        let call = async {
+            reached.store(true, Ordering::SeqCst);
+            Ok::<_, ()>(())
+        };
+        let faults = Faults::new();
+        faults.fail_next(1);
+        assert!(faults.apply(call).await.unwrap_err().is_injected());
+        assert!(!reached.load(Ordering::SeqCst));
+        assert!(faults.apply(ok()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn partial_calls_reach_the_implementation() {
+        let reached = AtomicBool::new(false);
+        let call = async {
+            reached.store(true, Ordering::SeqCst);
+            Ok::<_, ()>(())
+        };
+        let faults = Faults::new().partial_every(1);
+        assert!(faults.apply(call).await.unwrap_err().is_injected());
+        assert!(reached.load(Ordering::SeqCst));
+
+        faults.clear();
+        assert!(faults.apply(ok()).await.is_ok());
+        assert_eq!(faults.injected(), 1);
+    }
+
+    #[tokio::test]
+    async fn clones_share_the_faults() {
+        let faults = Faults::new();
+        let faulty = Faulty::new((), faults.clone());
+        faults.fail_next(2);
+        assert!(faulty.faults.apply(ok()).await.is_err());
+        assert!(faulty.faults.apply(ok()).await.is_err());
+        assert!(faulty.faults.apply(ok()).await.is_ok());
+        assert_eq!(faults.calls(), 3);
+    }
+}
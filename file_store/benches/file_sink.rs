@@ -0,0 +1,91 @@
+//! Throughput of a file sink written to by many concurrent clients, with and
+//! without batching queued writes and with larger write buffers.
+//!
+//! Run with `cargo bench -p file-store --bench file_sink`
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use file_store::{
+    file_sink::{FileSinkBuilder, DEFAULT_BUFFER_SIZE, DEFAULT_WRITE_BATCH_SIZE},
+    FileType,
+};
+use futures::future::try_join_all;
+use tempfile::TempDir;
+
+const MESSAGES: usize = 20_000;
+const WRITERS: usize = 50;
+const MESSAGE_SIZE: usize = 200;
+
+async fn write_messages(write_batch_size: usize, buffer_size: usize) {
+    let tmp_dir = TempDir::new().expect("temp dir");
+    let (trigger, shutdown) = triggered::trigger();
+    let (client, mut server) = FileSinkBuilder::new(
+        FileType::CellHeartbeatIngestReport,
+        tmp_dir.path(),
+        "bench_file_sink",
+        shutdown,
+    )
+    .write_batch_size(write_batch_size)
+    .buffer_size(buffer_size)
+    .create()
+    .await
+    .expect("file sink");
+    let server = tokio::spawn(async move { server.run().await });
+
+    try_join_all((0..WRITERS).map(|writer| {
+        let client = client.clone();
+        tokio::spawn(async move {
+            let message: Vec<u8> = (0..MESSAGE_SIZE).map(|i| (i + writer) as u8).collect();
+            for _ in 0..MESSAGES / WRITERS {
+                client
+                    .write(message.clone(), &[])
+                    .await
+                    .expect("queued")
+                    .await
+                    .expect("written")
+                    .expect("stored");
+            }
+        })
+    }))
+    .await
+    .expect("writers");
+
+    client
+        .commit()
+        .await
+        .expect("commit")
+        .await
+        .expect("committed")
+        .expect("manifest");
+    trigger.trigger();
+    server.await.expect("sink").expect("sink run");
+}
+
+fn file_sink_throughput(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("runtime");
+    let mut group = c.benchmark_group("file_sink");
+    group
+        .throughput(Throughput::Elements(MESSAGES as u64))
+        .sample_size(10);
+
+    for (name, write_batch_size, buffer_size) in [
+        ("unbatched", 1, DEFAULT_BUFFER_SIZE),
+        ("batched", DEFAULT_WRITE_BATCH_SIZE, DEFAULT_BUFFER_SIZE),
+        ("batched_256k_buffer", DEFAULT_WRITE_BATCH_SIZE, 256 * 1024),
+    ] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            &(write_batch_size, buffer_size),
+            |b, &(write_batch_size, buffer_size)| {
+                b.to_async(&runtime)
+                    .iter(|| write_messages(write_batch_size, buffer_size))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, file_sink_throughput);
+criterion_main!(benches);
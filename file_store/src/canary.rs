@@ -0,0 +1,407 @@
+//! Canary verification of uploaded files.
+//!
+//! A [`Canary`] is registered with the [`FileUpload`](crate::file_upload::FileUpload)
+//! as the upload hook of a file type. Every uploaded file of that type is
+//! downloaded again and decoded, totalling its messages and their amounts.
+//! The service writing the files tells the canary which files it committed
+//! together and the totals it computed for them in memory. Once every file of
+//! such a group was uploaded the totals are compared, a mismatch is counted,
+//! logged and posted to the optional webhook.
+//!
+//! Uploads finishing before their group is expected are held briefly, and
+//! groups whose files are not all uploaded within a couple of days are
+//! reported incomplete, so neither outlives a failed pass. With file names
+//! reproducible across passes, a file held from a failed pass would
+//! otherwise count towards the group of its rerun.
+
+use crate::{file_upload::UploadHook, FileInfo, FileStore, FileType, Result};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    ops::AddAssign,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+const CANARY_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "_canary");
+
+/// How long an upload is held for its group to be expected. Files are
+/// committed right before their group is expected, so only uploads racing
+/// the expectation are held
+const UPLOADED_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How long a group waits for its files, long enough for uploads held back
+/// by the upload windows
+const GROUP_TTL: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+/// Most uploads and groups held, the oldest are dropped beyond
+const MAX_HELD: usize = 1000;
+
+/// Amount of a single encoded message, for example the bones of a reward
+/// share
+pub type Amount = fn(&[u8]) -> std::result::Result<u64, helium_proto::DecodeError>;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Settings {
+    /// Url receiving a json POST for every mismatch. Mismatches are only
+    /// counted and logged when not set
+    pub webhook: Option<String>,
+    /// Seconds before a webhook request times out. Default is 10
+    #[serde(default = "default_webhook_timeout")]
+    pub webhook_timeout: u64,
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Totals {
+    pub messages: u64,
+    pub amount: u64,
+}
+
+impl AddAssign for Totals {
+    fn add_assign(&mut self, other: Self) {
+        self.messages += other.messages;
+        self.amount += other.amount;
+    }
+}
+
+/// Files committed together with the totals computed for them
+struct Group {
+    expected_at: Instant,
+    label: String,
+    remaining: HashSet<String>,
+    files: Vec<String>,
+    expected: Totals,
+    actual: Totals,
+}
+
+#[derive(Default)]
+struct State {
+    groups: Vec<Group>,
+    /// Totals of files uploaded before their group was expected, with the
+    /// time of the upload
+    uploaded: HashMap<String, (Instant, Totals)>,
+}
+
+impl State {
+    /// Drop the uploads and groups held for too long, returning the groups
+    /// that will never complete
+    fn expire(&mut self, now: Instant) -> Vec<Group> {
+        self.uploaded
+            .retain(|_, (uploaded_at, _)| now.duration_since(*uploaded_at) < UPLOADED_TTL);
+        let (expired, groups) = mem::take(&mut self.groups)
+            .into_iter()
+            .partition(|group| now.duration_since(group.expected_at) >= GROUP_TTL);
+        self.groups = groups;
+        expired
+    }
+
+    fn hold_upload(&mut self, key: String, now: Instant, totals: Totals) {
+        self.uploaded.insert(key, (now, totals));
+        if self.uploaded.len() > MAX_HELD {
+            let oldest = self
+                .uploaded
+                .iter()
+                .min_by_key(|(_, (uploaded_at, _))| *uploaded_at)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = oldest {
+                self.uploaded.remove(&key);
+            }
+        }
+    }
+
+    /// Wait for the remaining files of the group, returning the oldest
+    /// group when too many are waiting
+    fn hold_group(&mut self, group: Group) -> Option<Group> {
+        // groups are held in the order they are expected
+        self.groups.push(group);
+        (self.groups.len() > MAX_HELD).then(|| self.groups.remove(0))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Mismatch<'a> {
+    file_type: &'a str,
+    label: &'a str,
+    files: &'a [String],
+    expected: Totals,
+    actual: Totals,
+}
+
+#[derive(Clone)]
+pub struct Canary {
+    file_type: FileType,
+    amount: Amount,
+    webhook: Option<(reqwest::Client, String)>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Canary {
+    pub fn new(settings: &Settings, file_type: FileType, amount: Amount) -> Result<Self> {
+        let webhook = match &settings.webhook {
+            Some(url) => {
+                let client = reqwest::Client::builder()
+                    .timeout(Duration::from_secs(settings.webhook_timeout))
+                    .build()?;
+                Some((client, url.clone()))
+            }
+            None => None,
+        };
+        Ok(Self {
+            file_type,
+            amount,
+            webhook,
+            state: Arc::default(),
+        })
+    }
+
+    /// Expect the files committed together to add up to the totals, labeled
+    /// by for example the reward period
+    pub async fn expect(&self, label: impl Into<String>, files: &[String], expected: Totals) {
+        self.expect_at(Instant::now(), label.into(), files, expected)
+            .await
+    }
+
+    async fn expect_at(&self, now: Instant, label: String, files: &[String], expected: Totals) {
+        let mut group = Group {
+            expected_at: now,
+            label,
+            remaining: files.iter().cloned().collect(),
+            files: files.to_vec(),
+            expected,
+            actual: Totals::default(),
+        };
+        let (complete, expired) = {
+            let mut state = self.state.lock().await;
+            let mut expired = state.expire(now);
+            for file in files {
+                if let Some((_, totals)) = state.uploaded.remove(file) {
+                    group.remaining.remove(file);
+                    group.actual += totals;
+                }
+            }
+            if group.remaining.is_empty() {
+                (Some(group), expired)
+            } else {
+                expired.extend(state.hold_group(group));
+                (None, expired)
+            }
+        };
+        self.report(complete, expired).await;
+    }
+
+    async fn verified(&self, now: Instant, key: String, totals: Totals) {
+        let (complete, expired) = {
+            let mut state = self.state.lock().await;
+            let expired = state.expire(now);
+            let complete = match state
+                .groups
+                .iter()
+                .position(|group| group.remaining.contains(&key))
+            {
+                Some(index) => {
+                    let group = &mut state.groups[index];
+                    group.remaining.remove(&key);
+                    group.actual += totals;
+                    if group.remaining.is_empty() {
+                        Some(state.groups.remove(index))
+                    } else {
+                        None
+                    }
+                }
+                None => {
+                    state.hold_upload(key, now, totals);
+                    None
+                }
+            };
+            (complete, expired)
+        };
+        self.report(complete, expired).await;
+    }
+
+    async fn report(&self, complete: Option<Group>, expired: Vec<Group>) {
+        let file_type = self.file_type.to_str();
+        for group in expired {
+            metrics::increment_counter!(CANARY_METRIC, "file_type" => file_type, "result" => "incomplete");
+            tracing::warn!(
+                file_type,
+                label = %group.label,
+                remaining = ?group.remaining,
+                "canary gave up waiting for uploaded files"
+            );
+        }
+        if let Some(group) = complete {
+            self.compare(group).await;
+        }
+    }
+
+    async fn compare(&self, group: Group) {
+        let file_type = self.file_type.to_str();
+        if group.expected == group.actual {
+            metrics::increment_counter!(CANARY_METRIC, "file_type" => file_type, "result" => "verified");
+            tracing::info!(
+                file_type,
+                label = %group.label,
+                files = group.files.len(),
+                "canary verified uploaded files"
+            );
+            return;
+        }
+        metrics::increment_counter!(CANARY_METRIC, "file_type" => file_type, "result" => "mismatch");
+        tracing::error!(
+            file_type,
+            label = %group.label,
+            files = ?group.files,
+            expected = ?group.expected,
+            actual = ?group.actual,
+            "canary mismatch of uploaded files"
+        );
+        if let Some((client, url)) = &self.webhook {
+            let mismatch = Mismatch {
+                file_type,
+                label: &group.label,
+                files: &group.files,
+                expected: group.expected,
+                actual: group.actual,
+            };
+            let response = client
+                .post(url)
+                .json(&mismatch)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(err) = response {
+                tracing::error!("failed to post canary mismatch: {err:?}");
+            }
+        }
+    }
+
+    async fn totals(&self, store: &FileStore, key: &str) -> Result<Totals> {
+        let amount = self.amount;
+        store
+            .get(key)
+            .await?
+            .try_fold(Totals::default(), |mut totals, buf| async move {
+                totals += Totals {
+                    messages: 1,
+                    amount: amount(&buf).map_err(crate::error::DecodeError::from)?,
+                };
+                Ok(totals)
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl UploadHook for Canary {
+    async fn uploaded(&self, store: &FileStore, file_info: FileInfo) {
+        match self.totals(store, &file_info.key).await {
+            Ok(totals) => self.verified(Instant::now(), file_info.key, totals).await,
+            Err(err) => {
+                metrics::increment_counter!(CANARY_METRIC, "file_type" => self.file_type.to_str(), "result" => "error");
+                tracing::error!("canary failed to read back {}: {err:?}", file_info.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(buf: &[u8]) -> std::result::Result<u64, helium_proto::DecodeError> {
+        Ok(buf.len() as u64)
+    }
+
+    fn canary() -> Canary {
+        Canary::new(&Settings::default(), FileType::MobileRewardShare, amount).unwrap()
+    }
+
+    fn totals(messages: u64, amount: u64) -> Totals {
+        Totals { messages, amount }
+    }
+
+    #[tokio::test]
+    async fn groups_complete_once_every_file_was_uploaded() {
+        let canary = canary();
+        let now = Instant::now();
+        let files = vec!["a".to_string(), "b".to_string()];
+
+        // Uploaded before the group was expected
+        canary.verified(now, "a".to_string(), totals(1, 10)).await;
+        canary
+            .expect_at(now, "epoch".to_string(), &files, totals(3, 30))
+            .await;
+        assert_eq!(canary.state.lock().await.groups.len(), 1);
+        assert!(canary.state.lock().await.uploaded.is_empty());
+
+        canary.verified(now, "b".to_string(), totals(2, 20)).await;
+        assert!(canary.state.lock().await.groups.is_empty());
+    }
+
+    #[tokio::test]
+    async fn groups_without_files_complete_right_away() {
+        let canary = canary();
+        canary.expect("epoch", &[], Totals::default()).await;
+        assert!(canary.state.lock().await.groups.is_empty());
+    }
+
+    #[tokio::test]
+    async fn uploads_of_a_failed_pass_do_not_count_towards_its_rerun() {
+        let canary = canary();
+        let now = Instant::now();
+        let files = vec!["a".to_string()];
+
+        // The pass failed after its files were uploaded, the rerun writes a
+        // file of the same name
+        canary.verified(now, "a".to_string(), totals(1, 99)).await;
+        let rerun = now + UPLOADED_TTL;
+        canary
+            .expect_at(rerun, "epoch".to_string(), &files, totals(1, 10))
+            .await;
+        assert!(canary.state.lock().await.uploaded.is_empty());
+        assert_eq!(canary.state.lock().await.groups.len(), 1);
+
+        canary.verified(rerun, "a".to_string(), totals(1, 10)).await;
+        assert!(canary.state.lock().await.groups.is_empty());
+    }
+
+    #[tokio::test]
+    async fn groups_missing_files_are_given_up() {
+        let canary = canary();
+        let now = Instant::now();
+        canary
+            .expect_at(now, "epoch".to_string(), &["a".to_string()], totals(1, 1))
+            .await;
+        canary
+            .verified(now + GROUP_TTL, "b".to_string(), totals(1, 1))
+            .await;
+
+        let state = canary.state.lock().await;
+        assert!(state.groups.is_empty());
+        assert!(state.uploaded.contains_key("b"));
+    }
+
+    #[tokio::test]
+    async fn held_uploads_are_bounded() {
+        let canary = canary();
+        let now = Instant::now();
+        for index in 0..=MAX_HELD {
+            let uploaded_at = now + Duration::from_millis(index as u64);
+            canary
+                .verified(uploaded_at, index.to_string(), totals(1, 1))
+                .await;
+        }
+
+        let state = canary.state.lock().await;
+        assert_eq!(state.uploaded.len(), MAX_HELD);
+        assert!(!state.uploaded.contains_key("0"));
+    }
+}
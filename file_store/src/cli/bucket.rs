@@ -7,11 +7,17 @@ use chrono::{NaiveDateTime, TimeZone, Utc};
 use futures::{stream::TryStreamExt, StreamExt, TryFutureExt};
 use helium_crypto::PublicKey;
 use serde::{ser::SerializeSeq, Serializer};
+use sha2::{Digest, Sha256};
 use std::{
+    collections::HashSet,
     io,
     path::{Path, PathBuf},
 };
-use tokio::fs;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::Mutex,
+};
 
 /// Commands on remote buckets
 #[derive(Debug, clap::Args)]
@@ -27,6 +33,7 @@ pub enum BucketCmd {
     Put(Put),
     Get(Get),
     Locate(Locate),
+    Cp(Cp),
 }
 
 impl Cmd {
@@ -43,6 +50,7 @@ impl BucketCmd {
             Self::Put(cmd) => cmd.run(settings).await,
             Self::Get(cmd) => cmd.run(settings).await,
             Self::Locate(cmd) => cmd.run(settings).await,
+            Self::Cp(cmd) => cmd.run(settings).await,
         }
     }
 }
@@ -167,6 +175,150 @@ impl Get {
     }
 }
 
+/// Copy files from the configured bucket to another bucket, keeping their
+/// names. Useful to replay production data in a staging environment.
+#[derive(Debug, clap::Args)]
+pub struct Cp {
+    /// The bucket to copy the files to
+    #[clap(long)]
+    dest_bucket: String,
+    /// Optional endpoint of the destination bucket. Defaults to the endpoint
+    /// of the source bucket.
+    #[clap(long)]
+    dest_endpoint: Option<String>,
+    /// Optional region of the destination bucket. Defaults to the region of
+    /// the source bucket.
+    #[clap(long)]
+    dest_region: Option<String>,
+    /// Re-compress the copied files with the given gzip level (0-9)
+    #[clap(long)]
+    recompress: Option<u32>,
+    /// Number of files copied concurrently
+    #[clap(long, default_value = "5")]
+    concurrency: usize,
+    /// File recording the copied keys. Keys already in the manifest are
+    /// skipped, so an interrupted copy can be resumed.
+    #[clap(long)]
+    manifest: Option<PathBuf>,
+    #[clap(flatten)]
+    filter: FileFilter,
+}
+
+impl Cp {
+    pub async fn run(&self, settings: &Settings) -> Result {
+        let source = FileStore::from_settings(settings).await?;
+        let dest = FileStore::from_settings(&Settings {
+            bucket: self.dest_bucket.clone(),
+            endpoint: self
+                .dest_endpoint
+                .clone()
+                .or_else(|| settings.endpoint.clone()),
+            region: self
+                .dest_region
+                .clone()
+                .unwrap_or_else(|| settings.region.clone()),
+            secondary: None,
+            ..settings.clone()
+        })
+        .await?;
+
+        let copied = match &self.manifest {
+            Some(path) => read_manifest(path).await?,
+            None => HashSet::new(),
+        };
+        let manifest = match &self.manifest {
+            Some(path) => Some(Mutex::new(
+                fs::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(path)
+                    .await?,
+            )),
+            None => None,
+        };
+        let (copied, manifest) = (&copied, &manifest);
+
+        self.filter
+            .list(&source)
+            .try_filter(|info| futures::future::ready(!copied.contains(&info.key)))
+            .try_for_each_concurrent(self.concurrency.max(1), |info| {
+                let (source, dest) = (&source, &dest);
+                async move {
+                    let digest = self.copy(source, dest, &info.key).await?;
+                    if let Some(manifest) = manifest {
+                        manifest
+                            .lock()
+                            .await
+                            .write_all(format!("{}\t{digest}\n", info.key).as_bytes())
+                            .await?;
+                    }
+                    tracing::info!(key = %info.key, "copied");
+                    Ok(())
+                }
+            })
+            .await?;
+
+        if let Some(manifest) = manifest {
+            manifest.lock().await.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Copy one file, returning the hex encoded sha256 of the stored data
+    /// once it has been read back from the destination and found identical
+    async fn copy(&self, source: &FileStore, dest: &FileStore, key: &str) -> Result<String> {
+        let mut data = source.get_bytes(key).await?;
+        if let Some(level) = self.recompress {
+            data = recompress(&data, level).await?;
+        }
+        let digest = format!("{:x}", Sha256::digest(&data));
+        dest.put_bytes(key, data).await?;
+
+        let stored = format!("{:x}", Sha256::digest(dest.get_bytes(key).await?));
+        if stored != digest {
+            return Err(Error::ChecksumMismatch(format!(
+                "{key} in {}: {stored} != {digest}",
+                self.dest_bucket
+            )));
+        }
+        Ok(digest)
+    }
+}
+
+/// Decompress a gzipped file and compress it again at the given level
+async fn recompress(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    use async_compression::{
+        tokio::{bufread::GzipDecoder, write::GzipEncoder},
+        Level,
+    };
+    let mut decoded = Vec::new();
+    GzipDecoder::new(data).read_to_end(&mut decoded).await?;
+    let mut encoder = GzipEncoder::with_quality(Vec::new(), Level::Precise(level));
+    encoder.write_all(&decoded).await?;
+    encoder.shutdown().await?;
+    Ok(encoder.into_inner())
+}
+
+/// The keys in a copy manifest. Each line holds a key and the checksum of its
+/// copy separated by a tab.
+async fn read_manifest(path: &Path) -> Result<HashSet<String>> {
+    match fs::read_to_string(path).await {
+        Ok(contents) => Ok(parse_manifest(&contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn parse_manifest(contents: &str) -> HashSet<String> {
+    contents
+        .lines()
+        .filter_map(|line| line.split('\t').next())
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
 /// Locate specific records in a time range
 #[derive(Debug, clap::Args)]
 pub struct Locate {
@@ -283,3 +435,36 @@ impl Gateway for IotPoc {
         self.beacon_report.report.pub_key.as_ref() == pub_key
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::write::GzipEncoder;
+
+    #[test]
+    fn manifest_keys_are_parsed() {
+        let keys = parse_manifest("a.123.gz\tabc\n\nb.456.gz\tdef\nc.789.gz\n");
+        assert_eq!(
+            keys,
+            HashSet::from(["a.123.gz", "b.456.gz", "c.789.gz"].map(String::from))
+        );
+    }
+
+    #[tokio::test]
+    async fn recompressed_files_keep_their_contents() {
+        let contents = b"some report bytes, some report bytes".repeat(10);
+        let mut encoder = GzipEncoder::new(Vec::new());
+        encoder.write_all(&contents).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        let original = encoder.into_inner();
+
+        let recompressed = recompress(&original, 9).await.unwrap();
+
+        let mut decoded = Vec::new();
+        async_compression::tokio::bufread::GzipDecoder::new(recompressed.as_slice())
+            .read_to_end(&mut decoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded, contents);
+    }
+}
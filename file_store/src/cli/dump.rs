@@ -1,15 +1,18 @@
 use crate::{
-    cli::print_json,
+    cli::{info::message_timestamp, print_json},
     file_source,
     heartbeat::{CellHeartbeat, CellHeartbeatIngestReport},
     iot_packet::IotValidPacket,
     mobile_session::{DataTransferSessionIngestReport, InvalidDataTransferIngestReport},
     mobile_subscriber::{SubscriberLocationIngestReport, VerifiedSubscriberLocationIngestReport},
+    mobile_transfer::InvalidDataTransferSessionV1,
     speedtest::{CellSpeedtest, CellSpeedtestIngestReport},
     traits::MsgDecode,
-    FileType, Result, Settings,
+    BytesMutStream, Error, FileStore, FileType, Result, Settings,
 };
 use base64::Engine;
+use bytes::BytesMut;
+use chrono::{DateTime, Utc};
 use csv::Writer;
 use futures::stream::StreamExt;
 use helium_crypto::PublicKey;
@@ -26,253 +29,445 @@ use helium_proto::{
     },
     BlockchainTxn, Message, PriceReportV1, RewardManifest, SubnetworkRewards,
 };
-use serde_json::json;
+use oracle_settings::parse_key_value;
+use serde::Serialize;
+use serde_json::{json, Value};
 use std::io;
-use std::path::PathBuf;
 
-/// Print information about a given store file.
+/// Print the messages of a given store file, or statistics about them
 #[derive(Debug, clap::Args)]
 pub struct Cmd {
     /// Type of file to be dump
     file_type: FileType,
-    /// Path to file
-    in_path: PathBuf,
+    /// Path to a local file or s3://<bucket>/<key> of a file in a bucket
+    /// reached with the credentials of the settings
+    in_path: String,
+    /// Only include messages at or after this time, for example
+    /// 2023-05-01T00:00:00Z
+    #[clap(long)]
+    after: Option<DateTime<Utc>>,
+    /// Only include messages before this time
+    #[clap(long)]
+    before: Option<DateTime<Utc>>,
+    /// Only include messages with a field of the given value, for example
+    /// `--match report.pub_key=<key>`. Nested fields are separated by dots,
+    /// all matchers have to match
+    #[clap(long = "match", value_name = "FIELD=VALUE", value_parser = parse_key_value)]
+    matchers: Vec<(String, String)>,
+    /// Print the number of matching messages and their time range instead of
+    /// the messages
+    #[clap(long)]
+    count: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Stats {
+    messages: u64,
+    matched: u64,
+    first_timestamp: Option<DateTime<Utc>>,
+    last_timestamp: Option<DateTime<Utc>>,
+}
+
+impl Stats {
+    fn record(&mut self, timestamp: Option<DateTime<Utc>>) {
+        self.matched += 1;
+        if let Some(timestamp) = timestamp {
+            self.first_timestamp =
+                Some(self.first_timestamp.map_or(timestamp, |t| t.min(timestamp)));
+            self.last_timestamp = Some(self.last_timestamp.map_or(timestamp, |t| t.max(timestamp)));
+        }
+    }
 }
 
 impl Cmd {
-    pub async fn run(&self, _settings: &Settings) -> Result {
-        let mut file_stream = file_source::source([&self.in_path]);
+    pub async fn run(&self, settings: &Settings) -> Result {
+        let mut file_stream = self.source(settings).await?;
+        let filter_time = self.after.is_some() || self.before.is_some();
 
         let mut wtr = Writer::from_writer(io::stdout());
+        let mut stats = Stats::default();
         while let Some(result) = file_stream.next().await {
             let msg = result?;
-            match self.file_type {
-                FileType::CellHeartbeat => {
-                    let dec_msg = CellHeartbeatReqV1::decode(msg)?;
-                    wtr.serialize(CellHeartbeat::try_from(dec_msg)?)?;
-                }
-                FileType::CellSpeedtest => {
-                    let dec_msg = SpeedtestReqV1::decode(msg)?;
-                    wtr.serialize(CellSpeedtest::try_from(dec_msg)?)?;
-                }
-                FileType::CellHeartbeatIngestReport => {
-                    let dec_msg = CellHeartbeatIngestReportV1::decode(msg)?;
-                    let ingest_report = CellHeartbeatIngestReport::try_from(dec_msg)?;
-                    print_json(&ingest_report)?;
-                }
-                FileType::CellSpeedtestIngestReport => {
-                    let dec_msg = SpeedtestIngestReportV1::decode(msg)?;
-                    let ingest_report = CellSpeedtestIngestReport::try_from(dec_msg)?;
-                    print_json(&ingest_report)?;
-                }
-                FileType::DataTransferSessionIngestReport => {
-                    let dtr = DataTransferSessionIngestReport::decode(msg)?;
-                    print_json(&json!({
-                        "received_timestamp": dtr.received_timestamp,
-                        "reward_cancelled": dtr.report.reward_cancelled,
-                        "pub_key": dtr.report.data_transfer_usage.pub_key,
-                        "upload_bytes": dtr.report.data_transfer_usage.upload_bytes,
-                        "download_bytes": dtr.report.data_transfer_usage.download_bytes,
-                        "radio_access_technology": dtr.report.data_transfer_usage.radio_access_technology,
-                        "event_id": dtr.report.data_transfer_usage.event_id,
-                        "payer": dtr.report.data_transfer_usage.payer,
-                        "timestamp": dtr.report.data_transfer_usage.timestamp,
-                    }))?;
-                }
-                FileType::InvalidDataTransferSessionIngestReport => {
-                    let msg: InvalidDataTransferIngestReport =
-                        InvalidDataTransferIngestReportV1::decode(msg)?.try_into()?;
-                    print_json(&json!({
-                        "invalid_reason": msg.reason,
-                        "invalid_timestamp": msg.timestamp,
-                        "received_timestamp": msg.report.received_timestamp,
-                        "reward_cancelled": msg.report.report.reward_cancelled,
-                        "hotspot_key": PublicKey::try_from(msg.report.report.data_transfer_usage.pub_key)?,
-                        "upload_bytes": msg.report.report.data_transfer_usage.upload_bytes,
-                        "download_bytes": msg.report.report.data_transfer_usage.download_bytes,
-                        "radio_access_technology": msg.report.report.data_transfer_usage.radio_access_technology,
-                        "event_id": msg.report.report.data_transfer_usage.event_id,
-                        "payer":  PublicKey::try_from(msg.report.report.data_transfer_usage.payer)?,
-                        "event_timestamp": msg.report.report.data_transfer_usage.timestamp,
-                    }))?;
-                }
-                FileType::ValidDataTransferSession => {
-                    let msg = ValidDataTransferSessionProto::decode(msg)?;
-                    print_json(&json!({
-                        "pub_key": PublicKey::try_from(msg.pub_key)?,
-                        "upload_bytes": msg.upload_bytes,
-                        "download_bytes": msg.download_bytes,
-                        "num_dcs": msg.num_dcs,
-                        "upload_bytes": msg.upload_bytes,
-                        "payer": PublicKey::try_from(msg.payer)?,
-                        "first_timestamp": msg.first_timestamp,
-                        "last_timestamp": msg.last_timestamp,
-                    }))?;
-                }
-                FileType::IotBeaconIngestReport => {
-                    let dec_msg = LoraBeaconIngestReportV1::decode(msg)?;
-                    let json = json!({
-                        "received_timestamp": dec_msg.received_timestamp,
-                        "report":  dec_msg.report,
-                    });
-                    // TODO: tmp dump out as json
-                    // printing to json here as csv serializing failing due on header generation from struct
-                    print_json(&json)?;
-                    // wtr.serialize(IotBeaconIngestReport::try_from(dec_msg)?)?;
-                }
-                FileType::IotWitnessIngestReport => {
-                    let dec_msg = LoraWitnessIngestReportV1::decode(msg)?;
-                    let json = json!({
-                        "received_timestamp": dec_msg.received_timestamp,
-                        "report":  dec_msg.report,
-                    });
-                    // TODO: tmp dump out as json
-                    // printing to json here as csv serializing failing due on header generation from struct
-                    print_json(&json)?;
-                    // wtr.serialize(IotWitnessIngestReport::try_from(dec_msg)?)?;
-                }
-                FileType::IotPoc => {
-                    let dec_msg = LoraPocV1::decode(msg)?;
-                    let json = json!({
-                        "poc_id": dec_msg.poc_id,
-                        "beacon_report":  dec_msg.beacon_report,
-                        "selected_witnesses": dec_msg.selected_witnesses,
-                        "unselected_witnesses": dec_msg.unselected_witnesses,
-                    });
-                    // TODO: tmp dump out as json
-                    // printing to json here as csv serializing failing due on header generation from struct
-                    print_json(&json)?;
-                    // wtr.serialize(IotValidPoc::try_from(dec_msg)?)?;
-                }
-                FileType::SubnetworkRewards => {
-                    let proto_rewards = SubnetworkRewards::decode(msg)?.rewards;
-                    let total_rewards = proto_rewards
-                        .iter()
-                        .fold(0, |acc, reward| acc + reward.amount);
+            stats.messages += 1;
 
-                    let rewards: Vec<(PublicKey, u64)> = proto_rewards
-                        .iter()
-                        .map(|r| {
-                            (
-                                PublicKey::try_from(r.account.as_slice())
-                                    .expect("unable to get public key"),
-                                r.amount,
-                            )
-                        })
-                        .collect();
-                    print_json(&json!({ "rewards": rewards, "total_rewards": total_rewards }))?;
-                }
-                FileType::SpeedtestAvg => {
-                    let speedtest_avg = SpeedtestAvg::decode(msg)?;
-                    print_json(&json!({
-                        "pub_key": PublicKey::try_from(speedtest_avg.pub_key)?,
-                        "upload_speed_avg_bps": speedtest_avg.upload_speed_avg_bps,
-                        "download_speed_avg_bps": speedtest_avg.download_speed_avg_bps,
-                        "latency_avg_ms": speedtest_avg.latency_avg_ms,
-                        "validity": speedtest_avg.validity,
-                        "number_of_speedtests": speedtest_avg.speedtests.len(),
-                        "reward_multiplier": speedtest_avg.reward_multiplier,
-                    }))?;
-                }
-                FileType::ValidatedHeartbeat => {
-                    let heartbeat = Heartbeat::decode(msg)?;
-                    print_json(&json!({
-                        "cbsd_id": heartbeat.cbsd_id,
-                        "pub_key": PublicKey::try_from(heartbeat.pub_key)?,
-                        "reward_multiplier": heartbeat.reward_multiplier,
-                        "timestamp": heartbeat.timestamp,
-                        "cell_type": heartbeat.cell_type,
-                        "validity": heartbeat.validity,
-                    }))?;
-                }
-                FileType::MobileRewardShare => {
-                    let reward = MobileRewardShare::decode(msg)?;
-                    match reward.reward {
-                        Some(Reward::GatewayReward(reward)) => print_json(&json!({
-                            "hotspot_key": PublicKey::try_from(reward.hotspot_key)?,
-                            "dc_transfer_reward": reward.dc_transfer_reward,
-                        }))?,
-                        Some(Reward::RadioReward(reward)) => print_json(&json!({
-                            "cbsd_id": reward.cbsd_id,
-                            "poc_reward": reward.poc_reward,
-                        }))?,
-                        Some(Reward::SubscriberReward(reward)) => print_json(&json!({
-                            "subscriber_id": reward.subscriber_id,
-                            "discovery_location_amount": reward.discovery_location_amount,
-                        }))?,
-                        _ => (),
-                    }
-                }
-                FileType::RadioRewardShare => {
-                    let reward = RadioRewardShare::decode(msg)?;
-                    print_json(&json!({
-                        "owner_key": PublicKey::try_from(reward.owner_key)?,
-                        "hotpost_key": PublicKey::try_from(reward.hotspot_key)?,
-                        "cbsd_id": reward.cbsd_id,
-                        "amount": reward.amount,
-                        "start_epoch": reward.start_epoch,
-                        "end_epoch": reward.end_epoch,
-                    }))?;
+            let timestamp = if filter_time || self.count {
+                message_timestamp(&self.file_type, &msg)?
+            } else {
+                None
+            };
+            if filter_time {
+                let timestamp = timestamp.ok_or_else(|| {
+                    Error::not_found(format!("no message timestamps in {}", self.file_type))
+                })?;
+                if self.after.is_some_and(|after| timestamp < after)
+                    || self.before.is_some_and(|before| timestamp >= before)
+                {
+                    continue;
                 }
-                FileType::RewardManifest => {
-                    let manifest = RewardManifest::decode(msg)?;
-                    print_json(&json!({
-                        "written_files": manifest.written_files,
-                        "start_timestamp": manifest.start_timestamp,
-                        "end_timestamp": manifest.end_timestamp,
-                    }))?;
-                }
-                FileType::SignedPocReceiptTxn => {
-                    // This just outputs a binary of the txns instead of the typical decode.
-                    // This is to make ingesting the output of these transactions simpler on chain.
-                    let wrapped_txn = BlockchainTxn::decode(msg)?;
-                    println!("{:?}", wrapped_txn.encode_to_vec());
-                }
-                FileType::IotPacketReport => {
-                    let packet_report = PacketRouterPacketReportV1::decode(msg)?;
-                    print_json(&json!({
-                        "oui": packet_report.oui,
-                        "timestamp": packet_report.gateway_tmst}))?;
-                }
-                FileType::PriceReport => {
-                    let manifest = PriceReportV1::decode(msg)?;
-                    print_json(&json!({
-                        "price": manifest.price,
-                        "timestamp": manifest.timestamp,
-                        "token_type": manifest.token_type(),
-                    }))?;
-                }
-                FileType::IotValidPacket => {
-                    let manifest = IotValidPacket::decode(msg)?;
-                    print_json(&json!({
-                        "payload_size": manifest.payload_size,
-                        "gateway": PublicKey::try_from(manifest.gateway)?,
-                        "payload_hash": base64::engine::general_purpose::STANDARD.encode(manifest.payload_hash),
-                        "num_dcs": manifest.num_dcs,
-                        "packet_timestamp": manifest.packet_timestamp,
-                    }))?;
-                }
-                FileType::SubscriberLocationIngestReport => {
-                    let report = SubscriberLocationIngestReport::decode(msg)?;
-                    print_json(&json!({
-                        "subscriber_id": report.report.subscriber_id,
-                        "carrier_pub_key": report.report.carrier_pub_key,
-                        "recv_timestamp": report.received_timestamp}))?;
-                }
-                FileType::VerifiedSubscriberLocationIngestReport => {
-                    let report = VerifiedSubscriberLocationIngestReport::decode(msg)?;
-                    print_json(&json!({
-                        "subscriber_id": report.report.report.subscriber_id,
-                        "carrier_pub_key": report.report.report.carrier_pub_key,
-                        "status": report.status,
-                        "recv_timestamp": report.report.received_timestamp}))?;
+            }
+
+            let Some(record) = decode(&self.file_type, msg)? else {
+                continue;
+            };
+            if !self.matchers.is_empty() {
+                let value = record.to_value()?;
+                if !self
+                    .matchers
+                    .iter()
+                    .all(|(field, expected)| field_matches(&value, field, expected))
+                {
+                    continue;
                 }
-                _ => (),
+            }
+            stats.record(timestamp);
+            if self.count {
+                continue;
+            }
+            match record {
+                Decoded::CellHeartbeat(heartbeat) => wtr.serialize(heartbeat)?,
+                Decoded::CellSpeedtest(speedtest) => wtr.serialize(speedtest)?,
+                Decoded::Json(value) => print_json(&value)?,
+                Decoded::Raw(bytes) => println!("{bytes:?}"),
             }
         }
 
         wtr.flush()?;
+        if self.count {
+            print_json(&stats)?;
+        }
 
         Ok(())
     }
+
+    async fn source(&self, settings: &Settings) -> Result<BytesMutStream> {
+        match self.in_path.strip_prefix("s3://") {
+            Some(location) => {
+                let (bucket, key) = location
+                    .split_once('/')
+                    .ok_or_else(|| Error::not_found(format!("no key in {}", self.in_path)))?;
+                let store = FileStore::from_settings(&Settings {
+                    bucket: bucket.to_string(),
+                    ..settings.clone()
+                })
+                .await?;
+                store.get(key).await
+            }
+            None => Ok(file_source::source_with_limits(
+                [&self.in_path],
+                settings.limits,
+            )),
+        }
+    }
+}
+
+enum Decoded {
+    CellHeartbeat(CellHeartbeat),
+    CellSpeedtest(CellSpeedtest),
+    Json(Value),
+    Raw(Vec<u8>),
+}
+
+impl Decoded {
+    fn to_value(&self) -> Result<Value> {
+        Ok(match self {
+            Self::CellHeartbeat(heartbeat) => serde_json::to_value(heartbeat)?,
+            Self::CellSpeedtest(speedtest) => serde_json::to_value(speedtest)?,
+            Self::Json(value) => value.clone(),
+            Self::Raw(_) => Value::Null,
+        })
+    }
+}
+
+/// Decode a message into its json representation, none for file types that
+/// have no decoded form
+pub(crate) fn decode_json(file_type: &FileType, msg: BytesMut) -> Result<Option<Value>> {
+    match decode(file_type, msg)? {
+        None | Some(Decoded::Raw(_)) => Ok(None),
+        Some(record) => record.to_value().map(Some),
+    }
+}
+
+fn json_record<T: ?Sized + Serialize>(value: &T) -> Result<Option<Decoded>> {
+    Ok(Some(Decoded::Json(serde_json::to_value(value)?)))
+}
+
+fn decode(file_type: &FileType, msg: BytesMut) -> Result<Option<Decoded>> {
+    let record = match file_type {
+        FileType::CellHeartbeat => {
+            let dec_msg = CellHeartbeatReqV1::decode(msg)?;
+            Some(Decoded::CellHeartbeat(CellHeartbeat::try_from(dec_msg)?))
+        }
+        FileType::CellSpeedtest => {
+            let dec_msg = SpeedtestReqV1::decode(msg)?;
+            Some(Decoded::CellSpeedtest(CellSpeedtest::try_from(dec_msg)?))
+        }
+        FileType::CellHeartbeatIngestReport => {
+            let dec_msg = CellHeartbeatIngestReportV1::decode(msg)?;
+            let ingest_report = CellHeartbeatIngestReport::try_from(dec_msg)?;
+            json_record(&ingest_report)?
+        }
+        FileType::CellSpeedtestIngestReport => {
+            let dec_msg = SpeedtestIngestReportV1::decode(msg)?;
+            let ingest_report = CellSpeedtestIngestReport::try_from(dec_msg)?;
+            json_record(&ingest_report)?
+        }
+        FileType::DataTransferSessionIngestReport => {
+            let dtr = DataTransferSessionIngestReport::decode(msg)?;
+            json_record(&json!({
+                "received_timestamp": dtr.received_timestamp,
+                "reward_cancelled": dtr.report.reward_cancelled,
+                "pub_key": dtr.report.data_transfer_usage.pub_key,
+                "upload_bytes": dtr.report.data_transfer_usage.upload_bytes,
+                "download_bytes": dtr.report.data_transfer_usage.download_bytes,
+                "radio_access_technology": dtr.report.data_transfer_usage.radio_access_technology,
+                "event_id": dtr.report.data_transfer_usage.event_id,
+                "payer": dtr.report.data_transfer_usage.payer,
+                "timestamp": dtr.report.data_transfer_usage.timestamp,
+            }))?
+        }
+        FileType::InvalidDataTransferSessionIngestReport => {
+            let msg: InvalidDataTransferIngestReport =
+                InvalidDataTransferIngestReportV1::decode(msg)?.try_into()?;
+            json_record(&json!({
+                "invalid_reason": msg.reason,
+                "invalid_timestamp": msg.timestamp,
+                "received_timestamp": msg.report.received_timestamp,
+                "reward_cancelled": msg.report.report.reward_cancelled,
+                "hotspot_key": PublicKey::try_from(msg.report.report.data_transfer_usage.pub_key)?,
+                "upload_bytes": msg.report.report.data_transfer_usage.upload_bytes,
+                "download_bytes": msg.report.report.data_transfer_usage.download_bytes,
+                "radio_access_technology": msg.report.report.data_transfer_usage.radio_access_technology,
+                "event_id": msg.report.report.data_transfer_usage.event_id,
+                "payer":  PublicKey::try_from(msg.report.report.data_transfer_usage.payer)?,
+                "event_timestamp": msg.report.report.data_transfer_usage.timestamp,
+            }))?
+        }
+        FileType::InvalidVerifiedDataTransferSession => {
+            let msg = InvalidDataTransferSessionV1::decode(msg)?;
+            let session = msg.session.unwrap_or_default();
+            json_record(&json!({
+                "pub_key": PublicKey::try_from(session.pub_key)?,
+                "upload_bytes": session.upload_bytes,
+                "download_bytes": session.download_bytes,
+                "num_dcs": session.num_dcs,
+                "payer": PublicKey::try_from(session.payer)?,
+                "first_timestamp": session.first_timestamp,
+                "last_timestamp": session.last_timestamp,
+                "reason": msg.reason,
+                "timestamp": msg.timestamp,
+            }))?
+        }
+        FileType::ValidDataTransferSession | FileType::VerifiedDataTransferSession => {
+            let msg = ValidDataTransferSessionProto::decode(msg)?;
+            json_record(&json!({
+                "pub_key": PublicKey::try_from(msg.pub_key)?,
+                "upload_bytes": msg.upload_bytes,
+                "download_bytes": msg.download_bytes,
+                "num_dcs": msg.num_dcs,
+                "upload_bytes": msg.upload_bytes,
+                "payer": PublicKey::try_from(msg.payer)?,
+                "first_timestamp": msg.first_timestamp,
+                "last_timestamp": msg.last_timestamp,
+            }))?
+        }
+        FileType::IotBeaconIngestReport => {
+            let dec_msg = LoraBeaconIngestReportV1::decode(msg)?;
+            let json = json!({
+                "received_timestamp": dec_msg.received_timestamp,
+                "report":  dec_msg.report,
+            });
+            // TODO: tmp dump out as json
+            // printing to json here as csv serializing failing due on header generation from struct
+            json_record(&json)?
+            // wtr.serialize(IotBeaconIngestReport::try_from(dec_msg)?)?;
+        }
+        FileType::IotWitnessIngestReport => {
+            let dec_msg = LoraWitnessIngestReportV1::decode(msg)?;
+            let json = json!({
+                "received_timestamp": dec_msg.received_timestamp,
+                "report":  dec_msg.report,
+            });
+            // TODO: tmp dump out as json
+            // printing to json here as csv serializing failing due on header generation from struct
+            json_record(&json)?
+            // wtr.serialize(IotWitnessIngestReport::try_from(dec_msg)?)?;
+        }
+        FileType::IotPoc => {
+            let dec_msg = LoraPocV1::decode(msg)?;
+            let json = json!({
+                "poc_id": dec_msg.poc_id,
+                "beacon_report":  dec_msg.beacon_report,
+                "selected_witnesses": dec_msg.selected_witnesses,
+                "unselected_witnesses": dec_msg.unselected_witnesses,
+            });
+            // TODO: tmp dump out as json
+            // printing to json here as csv serializing failing due on header generation from struct
+            json_record(&json)?
+            // wtr.serialize(IotValidPoc::try_from(dec_msg)?)?;
+        }
+        FileType::SubnetworkRewards => {
+            let proto_rewards = SubnetworkRewards::decode(msg)?.rewards;
+            let total_rewards = proto_rewards
+                .iter()
+                .fold(0, |acc, reward| acc + reward.amount);
+
+            let rewards: Vec<(PublicKey, u64)> = proto_rewards
+                .iter()
+                .map(|r| {
+                    (
+                        PublicKey::try_from(r.account.as_slice())
+                            .expect("unable to get public key"),
+                        r.amount,
+                    )
+                })
+                .collect();
+            json_record(&json!({ "rewards": rewards, "total_rewards": total_rewards }))?
+        }
+        FileType::SpeedtestAvg => {
+            let speedtest_avg = SpeedtestAvg::decode(msg)?;
+            json_record(&json!({
+                "pub_key": PublicKey::try_from(speedtest_avg.pub_key)?,
+                "upload_speed_avg_bps": speedtest_avg.upload_speed_avg_bps,
+                "download_speed_avg_bps": speedtest_avg.download_speed_avg_bps,
+                "latency_avg_ms": speedtest_avg.latency_avg_ms,
+                "validity": speedtest_avg.validity,
+                "number_of_speedtests": speedtest_avg.speedtests.len(),
+                "reward_multiplier": speedtest_avg.reward_multiplier,
+            }))?
+        }
+        FileType::ValidatedHeartbeat => {
+            let heartbeat = Heartbeat::decode(msg)?;
+            json_record(&json!({
+                "cbsd_id": heartbeat.cbsd_id,
+                "pub_key": PublicKey::try_from(heartbeat.pub_key)?,
+                "reward_multiplier": heartbeat.reward_multiplier,
+                "timestamp": heartbeat.timestamp,
+                "cell_type": heartbeat.cell_type,
+                "validity": heartbeat.validity,
+            }))?
+        }
+        FileType::MobileRewardShare => {
+            let reward = MobileRewardShare::decode(msg)?;
+            match reward.reward {
+                Some(Reward::GatewayReward(reward)) => json_record(&json!({
+                    "hotspot_key": PublicKey::try_from(reward.hotspot_key)?,
+                    "dc_transfer_reward": reward.dc_transfer_reward,
+                }))?,
+                Some(Reward::RadioReward(reward)) => json_record(&json!({
+                    "cbsd_id": reward.cbsd_id,
+                    "poc_reward": reward.poc_reward,
+                }))?,
+                Some(Reward::SubscriberReward(reward)) => json_record(&json!({
+                    "subscriber_id": reward.subscriber_id,
+                    "discovery_location_amount": reward.discovery_location_amount,
+                }))?,
+                _ => None,
+            }
+        }
+        FileType::RadioRewardShare => {
+            let reward = RadioRewardShare::decode(msg)?;
+            json_record(&json!({
+                "owner_key": PublicKey::try_from(reward.owner_key)?,
+                "hotpost_key": PublicKey::try_from(reward.hotspot_key)?,
+                "cbsd_id": reward.cbsd_id,
+                "amount": reward.amount,
+                "start_epoch": reward.start_epoch,
+                "end_epoch": reward.end_epoch,
+            }))?
+        }
+        FileType::RewardManifest => {
+            let manifest = RewardManifest::decode(msg)?;
+            json_record(&json!({
+                "written_files": manifest.written_files,
+                "start_timestamp": manifest.start_timestamp,
+                "end_timestamp": manifest.end_timestamp,
+            }))?
+        }
+        FileType::SignedPocReceiptTxn => {
+            // This just outputs a binary of the txns instead of the typical decode.
+            // This is to make ingesting the output of these transactions simpler on chain.
+            let wrapped_txn = BlockchainTxn::decode(msg)?;
+            Some(Decoded::Raw(wrapped_txn.encode_to_vec()))
+        }
+        FileType::IotPacketReport => {
+            let packet_report = PacketRouterPacketReportV1::decode(msg)?;
+            json_record(&json!({
+                "oui": packet_report.oui,
+                "timestamp": packet_report.gateway_tmst}))?
+        }
+        FileType::PriceReport => {
+            let manifest = PriceReportV1::decode(msg)?;
+            json_record(&json!({
+                "price": manifest.price,
+                "timestamp": manifest.timestamp,
+                "token_type": manifest.token_type(),
+            }))?
+        }
+        FileType::IotValidPacket => {
+            let manifest = IotValidPacket::decode(msg)?;
+            json_record(&json!({
+                "payload_size": manifest.payload_size,
+                "gateway": PublicKey::try_from(manifest.gateway)?,
+                "payload_hash": base64::engine::general_purpose::STANDARD.encode(manifest.payload_hash),
+                "num_dcs": manifest.num_dcs,
+                "packet_timestamp": manifest.packet_timestamp,
+            }))?
+        }
+        FileType::SubscriberLocationIngestReport => {
+            let report = SubscriberLocationIngestReport::decode(msg)?;
+            json_record(&json!({
+                "subscriber_id": report.report.subscriber_id,
+                "carrier_pub_key": report.report.carrier_pub_key,
+                "recv_timestamp": report.received_timestamp}))?
+        }
+        FileType::VerifiedSubscriberLocationIngestReport => {
+            let report = VerifiedSubscriberLocationIngestReport::decode(msg)?;
+            json_record(&json!({
+                "subscriber_id": report.report.report.subscriber_id,
+                "carrier_pub_key": report.report.report.carrier_pub_key,
+                "status": report.status,
+                "recv_timestamp": report.report.received_timestamp}))?
+        }
+        _ => None,
+    };
+    Ok(record)
+}
+
+/// Whether the dotted `field` of `record` has the `expected` value. Strings
+/// are compared as is, any other value by its json representation.
+pub(crate) fn field_matches(record: &Value, field: &str, expected: &str) -> bool {
+    let value = field.split('.').try_fold(record, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    });
+    match value {
+        Some(Value::String(value)) => value == expected,
+        Some(value) => value.to_string() == expected,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_nested_fields() {
+        let record = json!({
+            "received_timestamp": 1_683_000_000_000_u64,
+            "report": {"pub_key": "112abc", "data": [1, 2]},
+            "valid": true,
+        });
+        assert!(field_matches(&record, "report.pub_key", "112abc"));
+        assert!(field_matches(
+            &record,
+            "received_timestamp",
+            "1683000000000"
+        ));
+        assert!(field_matches(&record, "valid", "true"));
+        assert!(field_matches(&record, "report.data.1", "2"));
+        assert!(!field_matches(&record, "report.pub_key", "112abd"));
+        assert!(!field_matches(&record, "report.missing", ""));
+    }
 }
@@ -0,0 +1,69 @@
+use crate::{parquet_export::Exporter, FileStore, FileType, Result, Settings};
+use std::time::Duration;
+
+/// Export files to Parquet in an analytics bucket, continuing from the
+/// latest file exported before
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// The analytics bucket to write the Parquet files to
+    #[clap(long)]
+    dest_bucket: String,
+    /// Optional endpoint of the analytics bucket. Defaults to the endpoint of
+    /// the source bucket.
+    #[clap(long)]
+    dest_endpoint: Option<String>,
+    /// Optional region of the analytics bucket. Defaults to the region of
+    /// the source bucket.
+    #[clap(long)]
+    dest_region: Option<String>,
+    /// The file types to export, types without a Parquet schema are
+    /// rejected
+    #[clap(long = "file-type", required = true)]
+    file_types: Vec<FileType>,
+    /// Keep exporting new files, checking for them every given number of
+    /// seconds, until interrupted
+    #[clap(long)]
+    follow: Option<u64>,
+}
+
+impl Cmd {
+    pub async fn run(&self, settings: &Settings) -> Result {
+        let source = FileStore::from_settings(settings).await?;
+        let dest = FileStore::from_settings(&Settings {
+            bucket: self.dest_bucket.clone(),
+            endpoint: self
+                .dest_endpoint
+                .clone()
+                .or_else(|| settings.endpoint.clone()),
+            region: self
+                .dest_region
+                .clone()
+                .unwrap_or_else(|| settings.region.clone()),
+            secondary: None,
+            ..settings.clone()
+        })
+        .await?;
+        let exporter = Exporter::new(source, dest, self.file_types.clone())?;
+
+        match self.follow {
+            Some(period) => {
+                let (trigger, shutdown) = triggered::trigger();
+                tokio::spawn(async move {
+                    let _ = tokio::signal::ctrl_c().await;
+                    trigger.trigger()
+                });
+                exporter
+                    .poll_duration(Duration::from_secs(period))
+                    .run(shutdown)
+                    .await
+            }
+            None => {
+                for file_type in &self.file_types {
+                    let exported = exporter.export_new(*file_type).await?;
+                    println!("{file_type}: exported {exported} files");
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -27,9 +27,9 @@ pub struct Cmd {
 }
 
 impl Cmd {
-    pub async fn run(&self, _settings: &Settings) -> Result {
+    pub async fn run(&self, settings: &Settings) -> Result {
         let file_info = FileInfo::try_from(self.path.as_path())?;
-        let mut file_stream = file_source::source([&self.path]);
+        let mut file_stream = file_source::source_with_limits([&self.path], settings.limits);
 
         let mut count = 1;
         let buf = match file_stream.next().await {
@@ -73,6 +73,12 @@ impl MsgTimestamp<Result<DateTime<Utc>>> for PriceReportV1 {
 }
 
 fn get_timestamp(file_type: &FileType, buf: &[u8]) -> Result<DateTime<Utc>> {
+    Ok(message_timestamp(file_type, buf)?.unwrap_or_else(Utc::now))
+}
+
+/// Timestamp of an encoded message of the given file type, none for file
+/// types without message timestamps
+pub(crate) fn message_timestamp(file_type: &FileType, buf: &[u8]) -> Result<Option<DateTime<Utc>>> {
     let result = match file_type {
         FileType::CellHeartbeat => CellHeartbeatReqV1::decode(buf)
             .map_err(Error::from)
@@ -119,7 +125,7 @@ fn get_timestamp(file_type: &FileType, buf: &[u8]) -> Result<DateTime<Utc>> {
             .map_err(Error::from)
             .and_then(|entry| entry.timestamp())?,
 
-        _ => Utc::now(),
+        _ => return Ok(None),
     };
-    Ok(result)
+    Ok(Some(result))
 }
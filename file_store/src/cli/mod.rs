@@ -1,6 +1,9 @@
 pub mod bucket;
 pub mod dump;
+#[cfg(feature = "parquet")]
+pub mod export;
 pub mod info;
+pub mod tail;
 
 use crate::Result;
 
@@ -0,0 +1,132 @@
+use crate::{
+    cli::dump::{decode_json, field_matches},
+    Error, FileStore, FileType, Result, Settings,
+};
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::TryStreamExt;
+use oracle_settings::parse_key_value;
+use serde_json::Value;
+
+/// Minutes of files printed when no start time is given
+const DEFAULT_LOOKBACK_MINS: i64 = 60;
+
+/// Print the messages of the files of a type in the bucket as json lines,
+/// oldest file first. With --follow new files are printed as they appear.
+///
+/// Files are found by listing the keys after the last printed file, so a
+/// file stored late with an older timestamp is not printed.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// Type of the files to print
+    file_type: FileType,
+    /// Print the files with a timestamp at or after this time, for example
+    /// 2023-05-01T00:00:00Z. Defaults to an hour ago
+    #[clap(long)]
+    since: Option<DateTime<Utc>>,
+    /// Keep polling for new files instead of exiting after the present ones
+    #[clap(short, long)]
+    follow: bool,
+    /// Seconds between polls for new files
+    #[clap(long, default_value = "10")]
+    interval: u64,
+    /// Only print messages with a field of the given value, with the matching
+    /// of the dump command
+    #[clap(long = "match", value_name = "FIELD=VALUE", value_parser = parse_key_value)]
+    matchers: Vec<(String, String)>,
+}
+
+impl Cmd {
+    pub async fn run(&self, settings: &Settings) -> Result {
+        let store = FileStore::from_settings(settings).await?;
+        // Listing is exclusive of the given time
+        let mut after = self.start(Utc::now()) - Duration::milliseconds(1);
+        let interval = std::time::Duration::from_secs(self.interval.max(1));
+        loop {
+            let mut infos = store.list(self.file_type, after, None);
+            while let Some(info) = infos.try_next().await? {
+                let mut messages = store.stream_file(info.clone()).await?;
+                while let Some(msg) = messages.try_next().await? {
+                    let Some(record) = decode_json(&self.file_type, msg)? else {
+                        return Err(Error::not_found(format!(
+                            "no json form for {}",
+                            self.file_type
+                        )));
+                    };
+                    if self.matches(&record) {
+                        println!("{}", serde_json::to_string(&record)?);
+                    }
+                }
+                after = info.timestamp;
+            }
+            if !self.follow {
+                return Ok(());
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Timestamp of the first file to print
+    fn start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        self.since
+            .unwrap_or(now - Duration::minutes(DEFAULT_LOOKBACK_MINS))
+    }
+
+    fn matches(&self, record: &Value) -> bool {
+        self.matchers
+            .iter()
+            .all(|(field, expected)| field_matches(record, field, expected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use clap::Parser;
+    use serde_json::json;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        cmd: Cmd,
+    }
+
+    fn parse(args: &[&str]) -> Cmd {
+        Cli::try_parse_from(std::iter::once("tail").chain(args.iter().copied()))
+            .unwrap()
+            .cmd
+    }
+
+    #[test]
+    fn files_of_the_last_hour_are_printed_by_default() {
+        let now = Utc.with_ymd_and_hms(2023, 5, 1, 12, 0, 0).unwrap();
+        assert_eq!(
+            parse(&["heartbeat_report"]).start(now),
+            Utc.with_ymd_and_hms(2023, 5, 1, 11, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse(&["heartbeat_report", "--since", "2023-04-30T00:00:00Z"]).start(now),
+            Utc.with_ymd_and_hms(2023, 4, 30, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn records_must_match_every_matcher() {
+        let cmd = parse(&[
+            "heartbeat_report",
+            "--match",
+            "report.cbsd_id=P27-SCE4255W",
+            "--match",
+            "report.operation_mode=true",
+        ]);
+        let record = |cbsd_id: &str| {
+            json!({
+                "report": {"cbsd_id": cbsd_id, "operation_mode": true}
+            })
+        };
+        assert!(cmd.matches(&record("P27-SCE4255W")));
+        assert!(!cmd.matches(&record("P27-SCE4255X")));
+        assert!(parse(&["heartbeat_report"]).matches(&record("any")));
+        assert!(Cli::try_parse_from(["tail", "heartbeat_report", "--match", "x"]).is_err());
+    }
+}
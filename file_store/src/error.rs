@@ -1,3 +1,4 @@
+use poc_metrics::error_code::{self, ErrorCategory, ErrorCode};
 use thiserror::Error;
 
 pub type Result<T = ()> = std::result::Result<T, Error>;
@@ -30,8 +31,22 @@ pub enum Error {
     JoinError(#[from] tokio::task::JoinError),
     #[error("send timeout")]
     SendTimeout,
+    #[error("sink queue full")]
+    SinkFull,
     #[error("shutting down")]
     Shutdown,
+    #[error("checksum mismatch for {0}")]
+    ChecksumMismatch(String),
+    #[error("http error")]
+    Http(#[from] reqwest::Error),
+    #[error("message bus error")]
+    MessageBus(Box<dyn std::error::Error + Send + Sync>),
+    #[cfg(feature = "parquet")]
+    #[error("arrow error")]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[cfg(feature = "parquet")]
+    #[error("parquet error")]
+    Parquet(#[from] parquet::errors::ParquetError),
 }
 
 #[derive(Error, Debug)]
@@ -56,6 +71,12 @@ pub enum DecodeError {
     UnsupportedStatusReason(String, i32),
     #[error("invalid unix timestamp {0}")]
     InvalidTimestamp(u64),
+    #[error("invalid csv field {0}: {1}")]
+    CsvField(String, String),
+    #[error("frame exceeds {0} bytes")]
+    FrameTooLarge(usize),
+    #[error("file exceeds {0} decompressed bytes")]
+    FileTooLarge(u64),
 }
 
 #[derive(Error, Debug)]
@@ -91,6 +112,13 @@ impl Error {
         Error::Channel
     }
 
+    pub fn message_bus<E>(err: E) -> Self
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        Self::MessageBus(Box::new(err))
+    }
+
     pub fn s3_error<T>(err: T) -> Self
     where
         T: Into<aws_sdk_s3::Error>,
@@ -104,6 +132,10 @@ impl DecodeError {
         Error::Decode(Self::FileInfo(msg.to_string()))
     }
 
+    pub fn csv_field<E: ToString>(field: &str, msg: E) -> Error {
+        Error::Decode(Self::CsvField(field.to_string(), msg.to_string()))
+    }
+
     pub fn unsupported_region<E: ToString>(msg1: E, msg2: i32) -> Error {
         Error::Decode(Self::UnsupportedRegion(msg1.to_string(), msg2))
     }
@@ -134,3 +166,98 @@ impl From<helium_crypto::Error> for Error {
         Self::Crypto(Box::new(err))
     }
 }
+
+impl ErrorCode for Error {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Io(_)
+            | Self::NotFound(_)
+            | Self::Aws(_)
+            | Self::NoManifest
+            | Self::ChecksumMismatch(_) => ErrorCategory::Storage,
+            Self::Encode(_) | Self::Decode(_) | Self::Csv(_) => ErrorCategory::Decode,
+            Self::Crypto(_) => ErrorCategory::Crypto,
+            Self::Config(_) => ErrorCategory::Config,
+            Self::DbError(_) => ErrorCategory::Database,
+            Self::Http(_) | Self::MessageBus(_) => ErrorCategory::Network,
+            Self::Channel
+            | Self::JoinError(_)
+            | Self::SendTimeout
+            | Self::SinkFull
+            | Self::Shutdown => ErrorCategory::Internal,
+            #[cfg(feature = "parquet")]
+            Self::Arrow(_) | Self::Parquet(_) => ErrorCategory::Decode,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "io",
+            Self::Encode(err) => err.code(),
+            Self::Decode(err) => err.code(),
+            Self::NotFound(_) => "not_found",
+            Self::Crypto(_) => "crypto",
+            Self::Csv(_) => "csv",
+            Self::Aws(_) => "s3",
+            Self::Config(_) => "invalid_settings",
+            Self::Channel => "channel_closed",
+            Self::NoManifest => "no_manifest",
+            Self::DbError(_) => "sql",
+            Self::JoinError(_) => "join",
+            Self::SendTimeout => "send_timeout",
+            Self::SinkFull => "sink_full",
+            Self::Shutdown => "shutdown",
+            Self::ChecksumMismatch(_) => "checksum_mismatch",
+            Self::Http(_) => "http",
+            Self::MessageBus(_) => "message_bus",
+            #[cfg(feature = "parquet")]
+            Self::Arrow(_) => "arrow",
+            #[cfg(feature = "parquet")]
+            Self::Parquet(_) => "parquet",
+        }
+    }
+}
+
+impl ErrorCode for DecodeError {
+    fn category(&self) -> ErrorCategory {
+        ErrorCategory::Decode
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Prost(_) => "prost_decode",
+            Self::FileInfo(_) => "file_info",
+            Self::Uri(_) => "uri",
+            Self::FromInt(_) => "int_conversion",
+            Self::UnsupportedRegion(_, _) => "unsupported_region",
+            Self::UnsupportedDataRate(_, _) => "unsupported_datarate",
+            Self::UnsupportedInvalidReason(_, _) => "unsupported_invalid_reason",
+            Self::UnsupportedParticipantSide(_, _) => "unsupported_participant_side",
+            Self::UnsupportedStatusReason(_, _) => "unsupported_status_reason",
+            Self::InvalidTimestamp(_) => "invalid_timestamp",
+            Self::CsvField(_, _) => "csv_field",
+            Self::FrameTooLarge(_) => "frame_too_large",
+            Self::FileTooLarge(_) => "file_too_large",
+        }
+    }
+}
+
+impl ErrorCode for EncodeError {
+    fn category(&self) -> ErrorCategory {
+        ErrorCategory::Decode
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Prost(_) => "prost_encode",
+            Self::Json(_) => "json_encode",
+        }
+    }
+}
+
+/// Label the errors of this crate in metrics and logs
+pub fn register_error_codes() {
+    error_code::register::<Error>();
+    error_code::register::<DecodeError>();
+    error_code::register::<EncodeError>();
+}
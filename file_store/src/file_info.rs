@@ -128,8 +128,19 @@ pub const PRICE_REPORT: &str = "price_report";
 pub const MOBILE_REWARD_SHARE: &str = "mobile_reward_share";
 pub const MAPPER_MSG: &str = "mapper_msg";
 pub const COVERAGE_OBJECT_INGEST_REPORT: &str = "coverage_object_ingest_report";
+pub const VERIFIED_DATA_TRANSFER_SESSION: &str = "verified_data_transfer_session";
+pub const INVALID_VERIFIED_DATA_TRANSFER_SESSION: &str = "invalid_verified_data_transfer_session";
+pub const IOT_CONFIG_AUDIT: &str = "iot_config_audit";
+pub const IOT_CONFIG_DENYLIST: &str = "iot_config_denylist";
+pub const IOT_CONFIG_ROUTE_SNAPSHOT: &str = "iot_config_route_snapshot";
+pub const IOT_BALANCE_TOP_UP: &str = "iot_balance_top_up";
+pub const IOT_ORG_STATE_CHANGE: &str = "iot_org_state_change";
+pub const INGEST_RECEIPT: &str = "ingest_receipt";
+pub const INVALID_RADIO_REWARD_SHARE: &str = "invalid_radio_reward_share";
+pub const MOBILE_EPOCH_SUMMARY: &str = "mobile_epoch_summary";
+pub const MOBILE_REWARD_DIFF: &str = "mobile_reward_diff";
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Copy, strum::EnumCount)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Copy, strum::EnumCount)]
 #[serde(rename_all = "snake_case")]
 pub enum FileType {
     CellHeartbeat = 0,
@@ -164,6 +175,17 @@ pub enum FileType {
     VerifiedSubscriberLocationIngestReport,
     MapperMsg,
     CoverageObjectIngestReport,
+    VerifiedDataTransferSession,
+    InvalidVerifiedDataTransferSession,
+    IotConfigAudit,
+    IotConfigDenylist,
+    IotConfigRouteSnapshot,
+    IotBalanceTopUp,
+    IotOrgStateChange,
+    IngestReceipt,
+    InvalidRadioRewardShare,
+    MobileEpochSummary,
+    MobileRewardDiff,
 }
 
 impl fmt::Display for FileType {
@@ -205,6 +227,17 @@ impl fmt::Display for FileType {
             Self::MobileRewardShare => MOBILE_REWARD_SHARE,
             Self::MapperMsg => MAPPER_MSG,
             Self::CoverageObjectIngestReport => COVERAGE_OBJECT_INGEST_REPORT,
+            Self::VerifiedDataTransferSession => VERIFIED_DATA_TRANSFER_SESSION,
+            Self::InvalidVerifiedDataTransferSession => INVALID_VERIFIED_DATA_TRANSFER_SESSION,
+            Self::IotConfigAudit => IOT_CONFIG_AUDIT,
+            Self::IotConfigDenylist => IOT_CONFIG_DENYLIST,
+            Self::IotConfigRouteSnapshot => IOT_CONFIG_ROUTE_SNAPSHOT,
+            Self::IotBalanceTopUp => IOT_BALANCE_TOP_UP,
+            Self::IotOrgStateChange => IOT_ORG_STATE_CHANGE,
+            Self::IngestReceipt => INGEST_RECEIPT,
+            Self::InvalidRadioRewardShare => INVALID_RADIO_REWARD_SHARE,
+            Self::MobileEpochSummary => MOBILE_EPOCH_SUMMARY,
+            Self::MobileRewardDiff => MOBILE_REWARD_DIFF,
         };
         f.write_str(s)
     }
@@ -249,6 +282,17 @@ impl FileType {
             Self::MobileRewardShare => MOBILE_REWARD_SHARE,
             Self::MapperMsg => MAPPER_MSG,
             Self::CoverageObjectIngestReport => COVERAGE_OBJECT_INGEST_REPORT,
+            Self::VerifiedDataTransferSession => VERIFIED_DATA_TRANSFER_SESSION,
+            Self::InvalidVerifiedDataTransferSession => INVALID_VERIFIED_DATA_TRANSFER_SESSION,
+            Self::IotConfigAudit => IOT_CONFIG_AUDIT,
+            Self::IotConfigDenylist => IOT_CONFIG_DENYLIST,
+            Self::IotConfigRouteSnapshot => IOT_CONFIG_ROUTE_SNAPSHOT,
+            Self::IotBalanceTopUp => IOT_BALANCE_TOP_UP,
+            Self::IotOrgStateChange => IOT_ORG_STATE_CHANGE,
+            Self::IngestReceipt => INGEST_RECEIPT,
+            Self::InvalidRadioRewardShare => INVALID_RADIO_REWARD_SHARE,
+            Self::MobileEpochSummary => MOBILE_EPOCH_SUMMARY,
+            Self::MobileRewardDiff => MOBILE_REWARD_DIFF,
         }
     }
 }
@@ -293,6 +337,17 @@ impl FromStr for FileType {
             MOBILE_REWARD_SHARE => Self::MobileRewardShare,
             MAPPER_MSG => Self::MapperMsg,
             COVERAGE_OBJECT_INGEST_REPORT => Self::CoverageObjectIngestReport,
+            VERIFIED_DATA_TRANSFER_SESSION => Self::VerifiedDataTransferSession,
+            INVALID_VERIFIED_DATA_TRANSFER_SESSION => Self::InvalidVerifiedDataTransferSession,
+            IOT_CONFIG_AUDIT => Self::IotConfigAudit,
+            IOT_CONFIG_DENYLIST => Self::IotConfigDenylist,
+            IOT_CONFIG_ROUTE_SNAPSHOT => Self::IotConfigRouteSnapshot,
+            IOT_BALANCE_TOP_UP => Self::IotBalanceTopUp,
+            IOT_ORG_STATE_CHANGE => Self::IotOrgStateChange,
+            INGEST_RECEIPT => Self::IngestReceipt,
+            INVALID_RADIO_REWARD_SHARE => Self::InvalidRadioRewardShare,
+            MOBILE_EPOCH_SUMMARY => Self::MobileEpochSummary,
+            MOBILE_REWARD_DIFF => Self::MobileRewardDiff,
             _ => return Err(Error::from(io::Error::from(io::ErrorKind::InvalidInput))),
         };
         Ok(result)
@@ -1,10 +1,19 @@
-use crate::{traits::MsgDecode, Error, FileInfo, FileStore, FileType, Result};
+use crate::{
+    error::DecodeError,
+    file_source::{AnyLegacyDecoder, FromCsv, LegacyDecoder},
+    traits::MsgDecode,
+    Error, FileInfo, FileStore, FileType, Result,
+};
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use derive_builder::Builder;
 use futures::{stream::BoxStream, StreamExt};
 use retainer::Cache;
-use std::marker::PhantomData;
-use tokio::sync::mpsc::{error::TrySendError, Receiver, Sender};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender, UnboundedSender};
 
 const DEFAULT_POLL_DURATION_SECS: i64 = 30;
 const DEFAULT_POLL_DURATION: std::time::Duration =
@@ -12,23 +21,178 @@ const DEFAULT_POLL_DURATION: std::time::Duration =
 const CLEAN_DURATION: std::time::Duration = std::time::Duration::from_secs(12 * 60 * 60);
 const CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(3 * 60 * 60);
 
+const OLDEST_UNPROCESSED_METRIC: &str = "file_info_poller_oldest_unprocessed_age_seconds";
+const PROCESSING_LAG_METRIC: &str = "file_info_poller_processing_lag_seconds";
+const QUARANTINED_METRIC: &str = "file_info_poller_quarantined_files";
+
 type MemoryFileCache = Cache<String, bool>;
 
 pub struct FileInfoStream<T> {
     pub file_info: FileInfo,
     stream: BoxStream<'static, T>,
+    completion: Completion,
 }
 
 impl<T> FileInfoStream<T>
 where
     T: Send,
 {
+    /// Record the file as processed in the given transaction and return its
+    /// messages. The returned completion must be committed with the
+    /// transaction. Until then the poller holds the file back, and if the
+    /// completion is dropped uncommitted the file is offered again.
     pub async fn into_stream(
         self,
         transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    ) -> Result<BoxStream<'static, T>> {
+    ) -> Result<(BoxStream<'static, T>, Completion)> {
         db::insert(transaction, self.file_info).await?;
-        Ok(self.stream)
+        Ok((self.stream, self.completion))
+    }
+}
+
+#[derive(Debug)]
+enum Processed {
+    Committed(FileInfo),
+    Abandoned(FileInfo),
+    /// Exceeded the decode limits while streamed and was never committed
+    Quarantined(FileInfo),
+}
+
+/// Handle to the processing of a file handed out by a [`FileInfoPoller`]
+#[must_use = "the file is offered again unless the completion is committed"]
+#[derive(Debug)]
+pub struct Completion {
+    file_info: Option<FileInfo>,
+    processed: UnboundedSender<Processed>,
+    /// Why the file was quarantined while streamed, its stream ended early
+    quarantined: Arc<Mutex<Option<DecodeError>>>,
+}
+
+impl Completion {
+    /// Commit the transaction the file was recorded in and mark the file as
+    /// processed.
+    ///
+    /// A file quarantined while streamed was only read in part. The
+    /// transaction is rolled back instead and the decode error returned, the
+    /// file is not offered again.
+    pub async fn commit(mut self, transaction: sqlx::Transaction<'_, sqlx::Postgres>) -> Result {
+        if let Some(err) = self.quarantined() {
+            transaction.rollback().await?;
+            return Err(err.into());
+        }
+        transaction.commit().await?;
+        self.committed();
+        Ok(())
+    }
+
+    /// Commit a transaction shared by the files of all the given completions.
+    /// If one of the files was quarantined the transaction is rolled back
+    /// like in [`Completion::commit`], and the other files are offered again
+    pub async fn commit_all<I>(
+        transaction: sqlx::Transaction<'_, sqlx::Postgres>,
+        completions: I,
+    ) -> Result
+    where
+        I: IntoIterator<Item = Completion>,
+    {
+        let mut completions: Vec<Completion> = completions.into_iter().collect();
+        let quarantined: Vec<DecodeError> = completions
+            .iter_mut()
+            .filter_map(Completion::quarantined)
+            .collect();
+        if let Some(err) = quarantined.into_iter().next() {
+            transaction.rollback().await?;
+            return Err(err.into());
+        }
+        transaction.commit().await?;
+        completions.into_iter().for_each(Completion::committed);
+        Ok(())
+    }
+
+    /// The decode error the file was quarantined for, the poller is told the
+    /// file will not be committed
+    fn quarantined(&mut self) -> Option<DecodeError> {
+        let err = self
+            .quarantined
+            .lock()
+            .expect("quarantine lock poisoned")
+            .take()?;
+        if let Some(file_info) = self.file_info.take() {
+            let _ = self.processed.send(Processed::Quarantined(file_info));
+        }
+        Some(err)
+    }
+
+    fn committed(mut self) {
+        if let Some(file_info) = self.file_info.take() {
+            // The poller has shut down, the database is the record of the
+            // processed files from here on
+            let _ = self.processed.send(Processed::Committed(file_info));
+        }
+    }
+
+    /// Drop the completion without notifying the poller
+    fn forget(mut self) {
+        self.file_info = None;
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        if let Some(file_info) = self.file_info.take() {
+            let _ = self.processed.send(Processed::Abandoned(file_info));
+        }
+    }
+}
+
+/// Files handed to the consumer whose processing is not committed yet, and
+/// abandoned files waiting to be offered again.
+///
+/// The latest committed timestamp only moves forward, so once a newer file
+/// is committed the listing would start past an older file still in flight
+/// or abandoned. The listing therefore never starts after the oldest pending
+/// file.
+#[derive(Debug, Default)]
+struct Pending {
+    in_flight: HashMap<String, DateTime<Utc>>,
+    abandoned: HashMap<String, DateTime<Utc>>,
+}
+
+impl Pending {
+    fn sent(&mut self, file: &FileInfo) {
+        self.abandoned.remove(&file.key);
+        self.in_flight.insert(file.key.clone(), file.timestamp);
+    }
+
+    fn is_in_flight(&self, file: &FileInfo) -> bool {
+        self.in_flight.contains_key(&file.key)
+    }
+
+    fn committed(&mut self, file: &FileInfo) {
+        self.in_flight.remove(&file.key);
+        self.abandoned.remove(&file.key);
+    }
+
+    fn abandoned(&mut self, file: FileInfo) {
+        if self.in_flight.remove(&file.key).is_some() {
+            self.abandoned.insert(file.key, file.timestamp);
+        }
+    }
+
+    fn oldest(&self) -> Option<DateTime<Utc>> {
+        self.in_flight
+            .values()
+            .chain(self.abandoned.values())
+            .min()
+            .copied()
+    }
+
+    /// Start of the listing, moved back to include the oldest pending file
+    fn list_after(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        match self.oldest() {
+            Some(oldest) => after.min(oldest - Duration::milliseconds(1)),
+            None => after,
+        }
     }
 }
 
@@ -50,10 +214,26 @@ pub struct FileInfoPoller<T> {
     offset: Duration,
     #[builder(default = "20")]
     queue_size: usize,
+    /// Decoder of the legacy csv exports among the files
+    #[builder(default, setter(custom))]
+    legacy: Option<AnyLegacyDecoder>,
     #[builder(setter(skip))]
     p: PhantomData<T>,
 }
 
+impl<T> FileInfoPollerBuilder<T>
+where
+    T: MsgDecode,
+    T::Msg: FromCsv + 'static,
+{
+    /// Also read the legacy gzip csv exports among the files with the
+    /// decoder. Only framed files are read without one
+    pub fn legacy(&mut self, decoder: Option<LegacyDecoder<T::Msg>>) -> &mut Self {
+        self.legacy = Some(decoder.map(AnyLegacyDecoder::from));
+        self
+    }
+}
+
 impl<T> FileInfoPoller<T>
 where
     T: MsgDecode + TryFrom<T::Msg, Error = Error> + Send + Sync + 'static,
@@ -83,9 +263,11 @@ where
         let mut cleanup_trigger = tokio::time::interval(CLEAN_DURATION);
 
         let mut latest_ts = db::latest_ts(&self.db, self.file_type).await?;
+        let mut pending = Pending::default();
+        let (processed_tx, mut processed_rx) = mpsc::unbounded_channel();
 
         loop {
-            let after = self.after(latest_ts);
+            let after = pending.list_after(self.after(latest_ts));
             let before = Utc::now();
 
             tokio::select! {
@@ -94,19 +276,55 @@ where
                     break;
                 }
                 _ = cleanup_trigger.tick() => self.clean(&cache).await?,
+                Some(processed) = processed_rx.recv() => match processed {
+                    Processed::Committed(file) => {
+                        pending.committed(&file);
+                        latest_ts = latest_ts.max(Some(file.timestamp));
+                        self.record_processing_lag(&file);
+                        cache_file(&cache, &file).await;
+                    }
+                    Processed::Abandoned(file) => {
+                        tracing::warn!(
+                            file = %file.key,
+                            "FileInfoPoller: file not committed, retrying"
+                        );
+                        pending.abandoned(file);
+                    }
+                    Processed::Quarantined(file) => {
+                        pending.committed(&file);
+                        cache_file(&cache, &file).await;
+                    }
+                },
                 _ = poll_trigger.tick() => {
                     let files = self.store.list_all(self.file_type, after, before).await?;
+                    // Files are listed oldest first, the first one that could
+                    // not be queued is the oldest left waiting
+                    let mut oldest_waiting = None;
                     for file in files {
+                        if pending.is_in_flight(&file) {
+                            continue;
+                        }
                         if !is_already_processed(&self.db, &cache, &file).await? {
-                            if send_stream(&sender, &self.store, file.clone()).await? {
-                                latest_ts = Some(file.timestamp);
-                                cache_file(&cache, &file).await;
+                            let sent = send_stream(
+                                &sender,
+                                &self.db,
+                                &self.store,
+                                self.legacy.as_ref(),
+                                file.clone(),
+                                &processed_tx,
+                            )
+                            .await?;
+                            if sent {
+                                pending.sent(&file);
                             } else {
                                 tracing::info!("FileInfoPoller: channel full");
+                                oldest_waiting = Some(file.timestamp);
                                 break;
                             }
                         }
                     }
+                    let oldest = pending.oldest().into_iter().chain(oldest_waiting).min();
+                    self.record_oldest_unprocessed(oldest);
                 }
             }
         }
@@ -130,6 +348,25 @@ where
         Ok(())
     }
 
+    /// Age of the oldest file not processed yet, zero when caught up
+    fn record_oldest_unprocessed(&self, oldest: Option<DateTime<Utc>>) {
+        let age = oldest.map_or(0.0, seconds_since);
+        metrics::gauge!(
+            OLDEST_UNPROCESSED_METRIC,
+            age,
+            "file_type" => self.file_type.to_str()
+        );
+    }
+
+    /// Time between the creation of the file and the commit of its processing
+    fn record_processing_lag(&self, file: &FileInfo) {
+        metrics::gauge!(
+            PROCESSING_LAG_METRIC,
+            seconds_since(file.timestamp),
+            "file_type" => self.file_type.to_str()
+        );
+    }
+
     fn poll_duration(&self) -> std::time::Duration {
         self.poll_duration.to_std().unwrap_or(DEFAULT_POLL_DURATION)
     }
@@ -137,15 +374,50 @@ where
 
 async fn send_stream<T>(
     sender: &Sender<FileInfoStream<T>>,
+    db: &sqlx::Pool<sqlx::Postgres>,
     store: &FileStore,
+    legacy: Option<&AnyLegacyDecoder>,
     file: FileInfo,
+    processed: &UnboundedSender<Processed>,
 ) -> Result<bool>
 where
     T: MsgDecode + TryFrom<T::Msg, Error = Error> + Send + Sync + 'static,
 {
-    let stream = store
-        .stream_file(file.clone())
-        .await?
+    let (db, quarantine_store, quarantine_file) = (db.clone(), store.clone(), file.clone());
+    let quarantined = Arc::new(Mutex::new(None));
+    let quarantined_by_stream = quarantined.clone();
+    let stream = match legacy {
+        Some(legacy) => store.stream_legacy_file(file.clone(), legacy).await?,
+        None => store.stream_file(file.clone()).await?,
+    };
+    let stream = stream
+        // A file exceeding the decode limits is not read any further, and
+        // its completion fails to commit
+        .take_while(move |msg| {
+            let oversize = match msg {
+                Err(Error::Decode(DecodeError::FrameTooLarge(max))) => {
+                    Some(DecodeError::FrameTooLarge(*max))
+                }
+                Err(Error::Decode(DecodeError::FileTooLarge(max))) => {
+                    Some(DecodeError::FileTooLarge(*max))
+                }
+                _ => None,
+            };
+            let (db, store, file, quarantined) = (
+                db.clone(),
+                quarantine_store.clone(),
+                quarantine_file.clone(),
+                quarantined_by_stream.clone(),
+            );
+            async move {
+                let Some(err) = oversize else {
+                    return true;
+                };
+                quarantine(&db, &store, &file, &err.to_string()).await;
+                *quarantined.lock().expect("quarantine lock poisoned") = Some(err);
+                false
+            }
+        })
         .filter_map(|msg| async {
             msg.map_err(|err| {
                 tracing::error!(
@@ -170,17 +442,52 @@ where
         .boxed();
 
     let incoming_data_stream = FileInfoStream {
+        completion: Completion {
+            file_info: Some(file.clone()),
+            processed: processed.clone(),
+            quarantined,
+        },
         file_info: file,
         stream,
     };
 
     match sender.try_send(incoming_data_stream) {
         Ok(_) => Ok(true),
-        Err(TrySendError::Full(_)) => Ok(false),
-        Err(TrySendError::Closed(_)) => Err(Error::channel()),
+        // The file was never handed out and is offered again on the next poll
+        Err(TrySendError::Full(rejected)) => {
+            rejected.completion.forget();
+            Ok(false)
+        }
+        Err(TrySendError::Closed(rejected)) => {
+            rejected.completion.forget();
+            Err(Error::channel())
+        }
+    }
+}
+
+/// Record a file exceeding the decode limits in the quarantine table and
+/// copy it under the quarantine prefix of the store, so it is skipped from
+/// here on and kept for inspection
+async fn quarantine(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    store: &FileStore,
+    file: &FileInfo,
+    reason: &str,
+) {
+    tracing::error!(file = %file.key, "quarantining file: {reason}");
+    metrics::increment_counter!(QUARANTINED_METRIC, "file_type" => file.file_type.to_str());
+    if let Err(err) = db::quarantine(db, file, reason).await {
+        tracing::error!(file = %file.key, "failed to record quarantined file: {err:?}");
+    }
+    if let Err(err) = store.quarantine(&file.key).await {
+        tracing::error!(file = %file.key, "failed to copy quarantined file: {err:?}");
     }
 }
 
+fn seconds_since(timestamp: DateTime<Utc>) -> f64 {
+    (Utc::now() - timestamp).num_milliseconds().max(0) as f64 / 1000.0
+}
+
 fn create_cache() -> MemoryFileCache {
     Cache::new()
 }
@@ -231,6 +538,7 @@ mod db {
         Ok(sqlx::query_scalar::<_, bool>(
             r#"
         SELECT EXISTS(SELECT 1 from files_processed where file_name = $1)
+            OR EXISTS(SELECT 1 from quarantine where file_name = $1)
         "#,
         )
         .bind(file_info.key.clone())
@@ -255,6 +563,29 @@ mod db {
         Ok(())
     }
 
+    pub async fn quarantine(
+        db: impl sqlx::PgExecutor<'_>,
+        file_info: &FileInfo,
+        reason: &str,
+    ) -> Result {
+        sqlx::query(
+            r#"
+        INSERT INTO quarantine(file_name, file_type, file_timestamp, reason, quarantined_at)
+        VALUES($1, $2, $3, $4, $5)
+        ON CONFLICT (file_name) DO NOTHING
+        "#,
+        )
+        .bind(&file_info.key)
+        .bind(file_info.file_type.to_str())
+        .bind(file_info.timestamp)
+        .bind(reason)
+        .bind(Utc::now())
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn clean(db: impl sqlx::PgExecutor<'_>, file_type: &FileType) -> Result {
         sqlx::query(
             r#"
@@ -274,3 +605,86 @@ mod db {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn completion(processed: &UnboundedSender<Processed>) -> Completion {
+        Completion {
+            file_info: Some(FileInfo::from_str("cell_heartbeat.1658832527866.gz").unwrap()),
+            processed: processed.clone(),
+            quarantined: Default::default(),
+        }
+    }
+
+    #[test]
+    fn uncommitted_files_are_reported_abandoned() {
+        let (processed, mut received) = mpsc::unbounded_channel();
+
+        drop(completion(&processed));
+        assert!(matches!(
+            received.try_recv(),
+            Ok(Processed::Abandoned(file)) if file.key == "cell_heartbeat.1658832527866.gz"
+        ));
+
+        completion(&processed).committed();
+        assert!(matches!(received.try_recv(), Ok(Processed::Committed(_))));
+        // Nothing is sent on drop once committed
+        assert!(received.try_recv().is_err());
+
+        completion(&processed).forget();
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn quarantined_files_are_not_committed() {
+        let (processed, mut received) = mpsc::unbounded_channel();
+
+        let mut unquarantined = completion(&processed);
+        assert!(unquarantined.quarantined().is_none());
+        unquarantined.forget();
+
+        let mut quarantined = completion(&processed);
+        *quarantined.quarantined.lock().unwrap() = Some(DecodeError::FileTooLarge(10));
+        assert!(matches!(
+            quarantined.quarantined(),
+            Some(DecodeError::FileTooLarge(10))
+        ));
+        assert!(matches!(
+            received.try_recv(),
+            Ok(Processed::Quarantined(file)) if file.key == "cell_heartbeat.1658832527866.gz"
+        ));
+        // Nothing is sent on drop once quarantined
+        drop(quarantined);
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn files_committed_out_of_order_are_not_skipped() {
+        let older = FileInfo::from_str("cell_heartbeat.1658832527866.gz").unwrap();
+        let newer = FileInfo::from_str("cell_heartbeat.1658832587866.gz").unwrap();
+        let mut pending = Pending::default();
+        pending.sent(&older);
+        pending.sent(&newer);
+
+        // the newer file commits first, moving the latest timestamp past the
+        // older file still in flight
+        pending.committed(&newer);
+        let latest = newer.timestamp;
+        assert!(pending.list_after(latest) < older.timestamp);
+
+        // the older file is abandoned and listed again until it is re-sent
+        pending.abandoned(older.clone());
+        assert!(!pending.is_in_flight(&older));
+        assert!(pending.list_after(latest) < older.timestamp);
+        pending.sent(&older);
+        assert!(pending.list_after(latest) < older.timestamp);
+
+        // once committed the listing continues from the latest file
+        pending.committed(&older);
+        assert_eq!(pending.list_after(latest), latest);
+        assert_eq!(pending.oldest(), None);
+    }
+}
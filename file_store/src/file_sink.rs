@@ -1,18 +1,26 @@
-use crate::{file_upload, Error, Result};
-use async_compression::tokio::write::GzipEncoder;
+use crate::{
+    file_upload, message_bus, sink_overflow::Overflow, AckMode, Error, OverflowPolicy, Result,
+    SinkSettings, SinksSettings,
+};
+use async_compression::{tokio::write::GzipEncoder, Level};
 use bytes::Bytes;
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use futures::SinkExt;
 use metrics::Label;
 use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
     io, mem,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 use tokio::{
     fs::{self, File, OpenOptions},
     io::{AsyncWriteExt, BufWriter},
     sync::{
-        mpsc::{self, error::SendTimeoutError},
+        mpsc::{
+            self,
+            error::{SendTimeoutError, TrySendError},
+        },
         oneshot,
     },
     time,
@@ -27,6 +35,11 @@ pub const SINK_CHECK_MILLIS: i64 = 60_000;
 pub const SINK_CHECK_MILLIS: i64 = 50;
 
 pub const MAX_FRAME_LENGTH: usize = 15_000_000;
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+/// Most queued messages written before the sink file is flushed
+pub const DEFAULT_WRITE_BATCH_SIZE: usize = 100;
+/// Messages queued for a sink before its clients overflow
+pub const QUEUE_SIZE: usize = 50;
 
 type Sink = GzipEncoder<BufWriter<File>>;
 type Transport = FramedWrite<Sink, LengthDelimitedCodec>;
@@ -44,11 +57,24 @@ fn transport_sink(transport: &mut Transport) -> &mut Sink {
 
 #[derive(Debug)]
 pub enum Message {
-    Data(oneshot::Sender<Result>, Vec<u8>),
+    /// Bytes to write, with the correlation id of the writing task if any
+    Data(oneshot::Sender<Result>, Vec<u8>, Option<Arc<str>>),
     Commit(oneshot::Sender<Result<FileManifest>>),
     Rollback(oneshot::Sender<Result<FileManifest>>),
+    /// Close the file being written without depositing it
+    Close(oneshot::Sender<Result>),
+    /// Name the files opened until the next commit or rollback by the time
+    FileTime(DateTime<Utc>),
 }
 
+const DUPLICATES_METRIC: &str = "file_sink_duplicates";
+const QUEUE_DEPTH_METRIC: &str = "file_sink_queue_depth";
+/// Most message hashes remembered by a deduplicating sink
+const DEDUPE_CAPACITY: usize = 100_000;
+
+/// A queued write with the sender for its result
+pub(crate) type QueuedWrite = (oneshot::Sender<Result>, Vec<u8>, Option<Arc<str>>);
+
 pub type MessageSender = mpsc::Sender<Message>;
 pub type MessageReceiver = mpsc::Receiver<Message>;
 
@@ -63,9 +89,19 @@ pub struct FileSinkBuilder {
     max_size: usize,
     roll_time: Duration,
     deposits: Option<file_upload::MessageSender>,
-    auto_commit: bool,
+    tee: Option<message_bus::Tee>,
+    /// Whether files are committed as they close, when set by the code
+    /// creating the sink rather than by its settings
+    auto_commit: Option<bool>,
+    compression_level: Option<u32>,
+    buffer_size: usize,
+    write_batch_size: usize,
+    dedupe_window: Option<std::time::Duration>,
+    overflow: OverflowPolicy,
     metric: &'static str,
     shutdown_listener: triggered::Listener,
+    overrides: SinkSettings,
+    aligned_from: Option<DateTime<Utc>>,
 }
 
 impl FileSinkBuilder {
@@ -82,9 +118,35 @@ impl FileSinkBuilder {
             max_size: 50_000_000,
             roll_time: Duration::minutes(DEFAULT_SINK_ROLL_MINS),
             deposits: None,
-            auto_commit: true,
+            tee: None,
+            auto_commit: None,
+            compression_level: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+            dedupe_window: None,
+            overflow: OverflowPolicy::Block,
             metric,
             shutdown_listener,
+            overrides: SinkSettings::default(),
+            aligned_from: None,
+        }
+    }
+
+    /// Create a builder whose policies are overridden by the settings for its
+    /// prefix, if any. The settings take precedence over the policies set on
+    /// the builder.
+    pub fn from_settings(
+        prefix: impl ToString,
+        target_path: &Path,
+        metric: &'static str,
+        shutdown_listener: triggered::Listener,
+        sinks: &SinksSettings,
+    ) -> Self {
+        let prefix = prefix.to_string();
+        let overrides = sinks.get(&prefix).cloned().unwrap_or_default();
+        Self {
+            overrides,
+            ..Self::new(prefix, target_path, metric, shutdown_listener)
         }
     }
 
@@ -110,9 +172,18 @@ impl FileSinkBuilder {
         Self { deposits, ..self }
     }
 
+    /// Also publish every written record to a message bus once the file it
+    /// is written to is committed
+    pub fn tee(self, tee: Option<message_bus::Tee>) -> Self {
+        Self { tee, ..self }
+    }
+
+    /// Whether closed files are committed right away, true by default. Set
+    /// here it takes precedence over the ack mode of the sink settings, as
+    /// the code creating the sink relies on committing files itself or not
     pub fn auto_commit(self, auto_commit: bool) -> Self {
         Self {
-            auto_commit,
+            auto_commit: Some(auto_commit),
             ..self
         }
     }
@@ -124,29 +195,140 @@ impl FileSinkBuilder {
         }
     }
 
+    /// Gzip compression level from 0 to 9, the encoder default when not set
+    pub fn compression_level(self, compression_level: u32) -> Self {
+        Self {
+            compression_level: Some(compression_level),
+            ..self
+        }
+    }
+
+    /// Size of the write buffer of the sink files
+    pub fn buffer_size(self, buffer_size: usize) -> Self {
+        Self {
+            buffer_size,
+            ..self
+        }
+    }
+
+    /// Most messages queued by clients that are written to the sink file
+    /// with a single flush. One flushes every message.
+    pub fn write_batch_size(self, write_batch_size: usize) -> Self {
+        Self {
+            write_batch_size: write_batch_size.max(1),
+            ..self
+        }
+    }
+
+    /// Drop messages identical to one written within the given window
+    /// instead of writing them again
+    pub fn dedupe(self, dedupe_window: Option<std::time::Duration>) -> Self {
+        Self {
+            dedupe_window,
+            ..self
+        }
+    }
+
+    /// What clients do with a write when the queue of the sink is full
+    pub fn overflow(self, overflow: OverflowPolicy) -> Self {
+        Self { overflow, ..self }
+    }
+
+    pub(crate) fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub(crate) fn target_dir(&self) -> &Path {
+        &self.target_path
+    }
+
+    /// Name the files by the windows of the roll time starting at the epoch,
+    /// with the first file named no earlier than `from`. Used by sink groups
+    /// whose sinks have to roll together
+    pub(crate) fn aligned(self, from: DateTime<Utc>, roll_time: Duration) -> Self {
+        Self {
+            aligned_from: Some(from),
+            roll_time,
+            ..self
+        }
+    }
+
+    fn apply_overrides(self) -> Self {
+        let overrides = self.overrides.clone();
+        let settings_auto_commit = overrides.ack_mode.map(|mode| mode == AckMode::Auto);
+        if let (Some(auto_commit), Some(ack_mode)) = (self.auto_commit, overrides.ack_mode) {
+            if Some(auto_commit) != settings_auto_commit {
+                tracing::warn!(
+                    prefix = self.prefix,
+                    ?ack_mode,
+                    auto_commit,
+                    "ignoring the ack mode setting of a sink whose service sets its commits"
+                );
+            }
+        }
+        Self {
+            // The roll time of aligned sinks is that of their group
+            roll_time: match self.aligned_from {
+                Some(_) => self.roll_time,
+                None => overrides
+                    .roll_minutes
+                    .map_or(self.roll_time, Duration::minutes),
+            },
+            max_size: overrides.max_size.unwrap_or(self.max_size),
+            compression_level: overrides.compression_level.or(self.compression_level),
+            buffer_size: overrides.buffer_size.unwrap_or(self.buffer_size),
+            dedupe_window: overrides
+                .dedupe_seconds
+                .map(std::time::Duration::from_secs)
+                .or(self.dedupe_window),
+            auto_commit: self.auto_commit.or(settings_auto_commit),
+            overflow: overrides.overflow.unwrap_or(self.overflow),
+            ..self
+        }
+    }
+
     pub async fn create(self) -> Result<(FileSinkClient, FileSink)> {
-        let (tx, rx) = message_channel(50);
+        let builder = self.apply_overrides();
+        let (tx, rx) = message_channel(QUEUE_SIZE);
+        let overflow = Overflow::new(
+            builder.overflow,
+            &builder.prefix,
+            &builder.target_path.join("overflow"),
+        )
+        .await?
+        .map(Arc::new);
 
         let client = FileSinkClient {
             sender: tx,
-            metric: self.metric,
-            shutdown_listener: self.shutdown_listener.clone(),
+            prefix: builder.prefix.clone(),
+            overflow: overflow.clone(),
+            metric: builder.metric,
+            shutdown_listener: builder.shutdown_listener.clone(),
         };
 
         metrics::register_counter!(client.metric, vec![OK_LABEL]);
 
         let mut sink = FileSink {
-            target_path: self.target_path,
-            tmp_path: self.tmp_path,
-            prefix: self.prefix,
-            max_size: self.max_size,
-            deposits: self.deposits,
-            roll_time: self.roll_time,
+            target_path: builder.target_path,
+            tmp_path: builder.tmp_path,
+            prefix: builder.prefix,
+            max_size: builder.max_size,
+            deposits: builder.deposits,
+            tee: builder.tee,
+            tee_held: Vec::new(),
+            roll_time: builder.roll_time,
             messages: rx,
             staged_files: Vec::new(),
-            auto_commit: self.auto_commit,
+            auto_commit: builder.auto_commit.unwrap_or(true),
+            compression_level: builder.compression_level,
+            buffer_size: builder.buffer_size,
+            write_batch_size: builder.write_batch_size,
+            dedupe: builder.dedupe_window.map(Dedupe::new),
+            overflow,
             active_sink: None,
-            shutdown_listener: self.shutdown_listener,
+            aligned_from: builder.aligned_from,
+            file_time: None,
+            shutdown_listener: builder.shutdown_listener,
         };
         sink.init().await?;
         Ok((client, sink))
@@ -156,6 +338,8 @@ impl FileSinkBuilder {
 #[derive(Debug, Clone)]
 pub struct FileSinkClient {
     sender: MessageSender,
+    prefix: String,
+    overflow: Option<Arc<Overflow>>,
     metric: &'static str,
     shutdown_listener: triggered::Listener,
 }
@@ -165,46 +349,116 @@ const ERROR_LABEL: Label = Label::from_static_parts("status", "error");
 const SEND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
 impl FileSinkClient {
+    /// Queue the item, applying the overflow policy of the sink when its
+    /// queue is full
     pub async fn write<T: prost::Message>(
         &self,
         item: T,
         labels: impl IntoIterator<Item = &(&'static str, &'static str)>,
     ) -> Result<oneshot::Receiver<Result>> {
         let (on_write_tx, on_write_rx) = oneshot::channel();
-        let bytes = item.encode_to_vec();
-        let labels = labels.into_iter().map(Label::from);
+        let write = (
+            on_write_tx,
+            item.encode_to_vec(),
+            custom_tracing::correlation::current(),
+        );
 
-        tokio::select! {
-            _ = self.shutdown_listener.clone() => {
-                Err(Error::Shutdown)
-            }
-            result = self.sender.send_timeout(Message::Data(on_write_tx, bytes), SEND_TIMEOUT) => match result {
-                Ok(_) => {
-                    metrics::increment_counter!(
-                        self.metric,
-                        labels
-                            .chain(std::iter::once(OK_LABEL))
-                            .collect::<Vec<Label>>()
-                    );
-                    tracing::debug!("file_sink write succeeded for {:?}", self.metric);
-                    Ok(on_write_rx)
-                }
-                Err(SendTimeoutError::Closed(_)) => {
-                    metrics::increment_counter!(
-                        self.metric,
-                        labels
-                            .chain(std::iter::once(ERROR_LABEL))
-                            .collect::<Vec<Label>>()
-                    );
-                    tracing::error!("file_sink write failed for {:?} channel closed", self.metric);
-                    Err(Error::channel())
-                }
-                Err(SendTimeoutError::Timeout(_)) => {
-                    tracing::error!("file_sink write failed due to send timeout");
-                    Err(Error::SendTimeout)
+        let result = match &self.overflow {
+            Some(overflow) => overflow.send(&self.sender, write).await,
+            None => {
+                let (on_write_tx, bytes, correlation_id) = write;
+                let message = Message::Data(on_write_tx, bytes, correlation_id);
+                tokio::select! {
+                    _ = self.shutdown_listener.clone() => Err(Error::Shutdown),
+                    result = self.sender.send_timeout(message, SEND_TIMEOUT) => {
+                        result.map_err(|err| match err {
+                            SendTimeoutError::Closed(_) => Error::channel(),
+                            SendTimeoutError::Timeout(_) => Error::SendTimeout,
+                        })
+                    }
                 }
-            },
+            }
+        };
+        self.record_write(labels, result).map(|_| on_write_rx)
+    }
+
+    /// Queue the item without waiting, failing with [`Error::SinkFull`]
+    /// when the queue of the sink is full or its overflow holds writes,
+    /// whatever the overflow policy of the sink. Lets producers shed or defer
+    /// work themselves
+    pub fn try_write<T: prost::Message>(
+        &self,
+        item: T,
+        labels: impl IntoIterator<Item = &(&'static str, &'static str)>,
+    ) -> Result<oneshot::Receiver<Result>> {
+        let (on_write_tx, on_write_rx) = oneshot::channel();
+        let result = if self
+            .overflow
+            .as_ref()
+            .is_some_and(|overflow| !overflow.is_empty())
+        {
+            Err(Error::SinkFull)
+        } else {
+            let message = Message::Data(
+                on_write_tx,
+                item.encode_to_vec(),
+                custom_tracing::correlation::current(),
+            );
+            self.sender.try_send(message).map_err(|err| match err {
+                TrySendError::Full(_) => Error::SinkFull,
+                TrySendError::Closed(_) => Error::channel(),
+            })
+        };
+        self.record_write(labels, result).map(|_| on_write_rx)
+    }
+
+    /// Messages waiting in the queue of the sink and writes in its overflow
+    pub fn queue_depth(&self) -> usize {
+        let queued = QUEUE_SIZE.saturating_sub(self.sender.capacity());
+        queued
+            + self
+                .overflow
+                .as_ref()
+                .map_or(0, |overflow| overflow.depth())
+    }
+
+    fn record_write(
+        &self,
+        labels: impl IntoIterator<Item = &(&'static str, &'static str)>,
+        result: Result,
+    ) -> Result {
+        let queued = QUEUE_SIZE.saturating_sub(self.sender.capacity());
+        metrics::gauge!(QUEUE_DEPTH_METRIC, queued as f64, "prefix" => self.prefix.clone());
+        if matches!(result, Err(Error::Shutdown)) {
+            return result;
+        }
+        let status = if result.is_ok() {
+            OK_LABEL
+        } else {
+            ERROR_LABEL
+        };
+        metrics::increment_counter!(
+            self.metric,
+            labels
+                .into_iter()
+                .map(Label::from)
+                .chain(std::iter::once(status))
+                .collect::<Vec<Label>>()
+        );
+        match &result {
+            Ok(()) => tracing::debug!("file_sink write succeeded for {:?}", self.metric),
+            Err(Error::Channel) => {
+                tracing::error!(
+                    "file_sink write failed for {:?} channel closed",
+                    self.metric
+                )
+            }
+            Err(Error::SendTimeout) => {
+                tracing::error!("file_sink write failed due to send timeout")
+            }
+            Err(err) => tracing::warn!("file_sink write failed for {:?}: {err}", self.metric),
         }
+        result
     }
 
     pub async fn commit(&self) -> Result<oneshot::Receiver<Result<FileManifest>>> {
@@ -230,6 +484,35 @@ impl FileSinkClient {
             })
             .map(|_| on_rollback_rx)
     }
+
+    /// Close the file being written so a following commit has nothing left
+    /// to write out
+    pub async fn close(&self) -> Result<oneshot::Receiver<Result>> {
+        let (on_close_tx, on_close_rx) = oneshot::channel();
+        self.sender
+            .send(Message::Close(on_close_tx))
+            .await
+            .map_err(|e| {
+                tracing::error!("file_sink failed to close with {e:?}");
+                Error::channel()
+            })
+            .map(|_| on_close_rx)
+    }
+
+    /// Name the files opened until the next commit or rollback by the given
+    /// time rather than the time they are opened, every file rolled within
+    /// them a millisecond after the previous one. Output that has to be
+    /// reproducible, such as the rewards of an epoch, is named by the epoch
+    /// so that every run writes the same files
+    pub async fn file_time(&self, time: DateTime<Utc>) -> Result {
+        self.sender
+            .send(Message::FileTime(time))
+            .await
+            .map_err(|e| {
+                tracing::error!("file_sink failed to set the file time with {e:?}");
+                Error::channel()
+            })
+    }
 }
 
 #[derive(Debug)]
@@ -242,8 +525,21 @@ pub struct FileSink {
 
     messages: MessageReceiver,
     deposits: Option<file_upload::MessageSender>,
+    tee: Option<message_bus::Tee>,
+    /// Records written to the staged files, published once committed
+    tee_held: Vec<Bytes>,
     staged_files: Vec<PathBuf>,
     auto_commit: bool,
+    compression_level: Option<u32>,
+    buffer_size: usize,
+    write_batch_size: usize,
+    dedupe: Option<Dedupe>,
+    /// Writes overflowed by clients while the queue was full
+    overflow: Option<Arc<Overflow>>,
+    /// Earliest time of the next file of an aligned sink
+    aligned_from: Option<DateTime<Utc>>,
+    /// Time the next file is named by until the next commit or rollback
+    file_time: Option<DateTime<Utc>>,
 
     active_sink: Option<ActiveSink>,
     shutdown_listener: triggered::Listener,
@@ -251,14 +547,28 @@ pub struct FileSink {
 
 #[derive(Debug)]
 struct ActiveSink {
+    path: PathBuf,
     size: usize,
+    /// Time the file is named by
     time: DateTime<Utc>,
+    opened: DateTime<Utc>,
     transport: Transport,
+    correlation_ids: BTreeSet<Arc<str>>,
 }
 
 impl ActiveSink {
     async fn shutdown(&mut self) -> Result {
+        // Write out any frames fed without a flush
+        self.transport.flush().await?;
         transport_sink(&mut self.transport).shutdown().await?;
+        let correlation_ids = mem::take(&mut self.correlation_ids);
+        if !correlation_ids.is_empty() {
+            tracing::info!(
+                file = %self.path.display(),
+                inputs = ?correlation_ids,
+                "closed sink file"
+            );
+        }
         Ok(())
     }
 }
@@ -327,25 +637,9 @@ impl FileSink {
             tokio::select! {
                 _ = self.shutdown_listener.clone() => break,
                 _ = rollover_timer.tick() => self.maybe_roll().await?,
+                _ = overflowed(&self.overflow) => self.drain_overflow().await,
                 msg = self.messages.recv() => match msg {
-                    Some(Message::Data(on_write_tx, bytes)) => {
-                        let res = match self.write(Bytes::from(bytes)).await {
-                            Ok(_) => Ok(()),
-                            Err(err) => {
-                                tracing::error!("failed to store {}: {err:?}", &self.prefix);
-                                Err(err)
-                            }
-                        };
-                        let _ = on_write_tx.send(res);
-                    }
-                    Some(Message::Commit(on_commit_tx)) => {
-                        let res = self.commit().await;
-                        let _ = on_commit_tx.send(res);
-                    }
-                    Some(Message::Rollback(on_rollback_tx)) => {
-                        let res = self.rollback().await;
-                        let _ = on_rollback_tx.send(res);
-                    }
+                    Some(msg) => self.handle_message(msg).await,
                     None => {
                         break
                     }
@@ -360,24 +654,157 @@ impl FileSink {
         Ok(())
     }
 
+    async fn handle_message(&mut self, msg: Message) {
+        let mut next = Some(msg);
+        while let Some(msg) = next.take() {
+            match msg {
+                Message::Data(on_write_tx, bytes, correlation_id) => {
+                    // Take the data already queued as well so a busy sink
+                    // flushes once for many messages
+                    let mut batch = vec![(on_write_tx, bytes, correlation_id)];
+                    while batch.len() < self.write_batch_size {
+                        match self.messages.try_recv() {
+                            Ok(Message::Data(on_write_tx, bytes, correlation_id)) => {
+                                batch.push((on_write_tx, bytes, correlation_id))
+                            }
+                            Ok(msg) => {
+                                next = Some(msg);
+                                break;
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    self.write_batch(batch).await;
+                }
+                Message::Commit(on_commit_tx) => {
+                    self.write_overflowed().await;
+                    let res = self.commit().await;
+                    self.file_time = None;
+                    let _ = on_commit_tx.send(res);
+                }
+                Message::Rollback(on_rollback_tx) => {
+                    self.write_overflowed().await;
+                    let res = self.rollback().await;
+                    self.file_time = None;
+                    let _ = on_rollback_tx.send(res);
+                }
+                Message::Close(on_close_tx) => {
+                    self.write_overflowed().await;
+                    let res = self.maybe_close_active_sink().await;
+                    let _ = on_close_tx.send(res);
+                }
+                Message::FileTime(time) => self.file_time = Some(time),
+            }
+        }
+    }
+
+    /// Work through the queue, then write the overflowed writes that
+    /// followed it
+    async fn drain_overflow(&mut self) {
+        while let Ok(msg) = self.messages.try_recv() {
+            self.handle_message(msg).await;
+        }
+        self.write_overflowed().await;
+    }
+
+    /// Write the overflowed writes, which were written by clients before
+    /// anything queued after them
+    async fn write_overflowed(&mut self) {
+        let Some(overflow) = self.overflow.clone() else {
+            return;
+        };
+        let writes = match overflow.take().await {
+            Ok(writes) => writes,
+            Err(err) => {
+                tracing::error!("failed to take the overflow of {}: {err:?}", self.prefix);
+                return;
+            }
+        };
+        let mut writes = writes.into_iter().peekable();
+        let mut written = true;
+        while writes.peek().is_some() {
+            let batch = writes.by_ref().take(self.write_batch_size).collect();
+            written &= self.write_batch(batch).await;
+        }
+        // Spilled writes were acknowledged, so they are replayed until the
+        // file holding them is committed
+        overflow.replayed(written);
+    }
+
+    /// Write the batch, returning whether every write of it was written
+    async fn write_batch(&mut self, batch: Vec<QueuedWrite>) -> bool {
+        let mut fed = Vec::with_capacity(batch.len());
+        let mut written = true;
+        for (on_write_tx, bytes, correlation_id) in batch {
+            match self.feed(Bytes::from(bytes), correlation_id).await {
+                Ok(()) => fed.push(on_write_tx),
+                Err(err) => {
+                    tracing::error!("failed to store {}: {err:?}", &self.prefix);
+                    let _ = on_write_tx.send(Err(err));
+                    written = false;
+                }
+            }
+        }
+        match self.flush().await {
+            Ok(()) => {
+                fed.into_iter().for_each(|on_write_tx| {
+                    let _ = on_write_tx.send(Ok(()));
+                });
+                written
+            }
+            Err(err) => {
+                tracing::error!("failed to flush {}: {err:?}", &self.prefix);
+                for on_write_tx in fed {
+                    let _ = on_write_tx.send(Err(Error::from(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("failed to flush {}", self.prefix),
+                    ))));
+                }
+                false
+            }
+        }
+    }
+
     async fn new_sink(&mut self) -> Result {
-        let sink_time = Utc::now();
+        let now = Utc::now();
+        let sink_time = match (self.file_time, self.aligned_from) {
+            (Some(file_time), _) => {
+                self.file_time = Some(file_time + Duration::milliseconds(1));
+                file_time
+            }
+            // Files rolled by size within a window follow its first file by
+            // a millisecond each
+            (None, Some(from)) => {
+                let sink_time = window_start(now, self.roll_time).max(from);
+                self.aligned_from = Some(sink_time + Duration::milliseconds(1));
+                sink_time
+            }
+            (None, None) => now,
+        };
         let filename = format!("{}.{}.gz", self.prefix, sink_time.timestamp_millis());
         let new_path = self.tmp_path.join(filename);
-        let writer = GzipEncoder::new(BufWriter::new(
+        let file = BufWriter::with_capacity(
+            self.buffer_size,
             OpenOptions::new()
                 .write(true)
                 .create(true)
                 .open(&new_path)
                 .await?,
-        ));
+        );
+        let writer = match self.compression_level {
+            Some(level) => GzipEncoder::with_quality(file, Level::Precise(level)),
+            None => GzipEncoder::new(file),
+        };
 
-        self.staged_files.push(new_path);
+        self.staged_files.push(new_path.clone());
 
         self.active_sink = Some(ActiveSink {
+            path: new_path,
             size: 0,
             time: sink_time,
+            opened: now,
             transport: new_transport(writer),
+            correlation_ids: BTreeSet::new(),
         });
 
         Ok(())
@@ -394,11 +821,34 @@ impl FileSink {
             manifest.push(file_name(&staged_file)?);
         }
 
+        if let Some(dedupe) = self.dedupe.as_mut() {
+            dedupe.committed();
+        }
+        let held = mem::take(&mut self.tee_held);
+        if let Some(tee) = &self.tee {
+            tee.publish(held);
+        }
+        if let Some(overflow) = &self.overflow {
+            if let Err(err) = overflow.committed().await {
+                tracing::error!(
+                    "failed to remove the replayed overflow of {}: {err:?}",
+                    self.prefix
+                );
+            }
+        }
+
         Ok(manifest)
     }
 
     pub async fn rollback(&mut self) -> Result<FileManifest> {
         self.maybe_close_active_sink().await?;
+        self.tee_held.clear();
+        if let Some(dedupe) = self.dedupe.as_mut() {
+            dedupe.rolled_back();
+        }
+        if let Some(overflow) = &self.overflow {
+            overflow.rolled_back();
+        }
 
         let mut manifest: FileManifest = Vec::new();
         let staged_files = mem::take(&mut self.staged_files);
@@ -411,9 +861,17 @@ impl FileSink {
         Ok(manifest)
     }
 
+    /// When the active file rolls
+    fn roll_at(&self, active_sink: &ActiveSink) -> DateTime<Utc> {
+        match self.aligned_from {
+            Some(_) => window_start(active_sink.time, self.roll_time) + self.roll_time,
+            None => active_sink.opened + self.roll_time,
+        }
+    }
+
     pub async fn maybe_roll(&mut self) -> Result {
-        if let Some(active_sink) = self.active_sink.as_mut() {
-            if (active_sink.time + self.roll_time) <= Utc::now() {
+        if let Some(active_sink) = self.active_sink.as_ref() {
+            if self.roll_at(active_sink) <= Utc::now() {
                 if self.auto_commit {
                     self.commit().await?;
                 } else {
@@ -453,15 +911,43 @@ impl FileSink {
         Ok(())
     }
 
-    pub async fn write(&mut self, buf: Bytes) -> Result {
+    pub async fn write(&mut self, buf: Bytes, correlation_id: Option<Arc<str>>) -> Result {
+        self.feed(buf, correlation_id).await?;
+        self.flush().await
+    }
+
+    /// Flush the frames fed to the active sink
+    async fn flush(&mut self) -> Result {
+        if let Some(active_sink) = self.active_sink.as_mut() {
+            active_sink.transport.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Write a frame to the active sink without flushing it
+    async fn feed(&mut self, buf: Bytes, correlation_id: Option<Arc<str>>) -> Result {
+        if let Some(dedupe) = self.dedupe.as_mut() {
+            if dedupe.is_duplicate(&buf, time::Instant::now()) {
+                tracing::debug!("dropping duplicate message for {}", self.prefix);
+                metrics::increment_counter!(DUPLICATES_METRIC, "prefix" => self.prefix.clone());
+                return Ok(());
+            }
+        }
         let buf_len = buf.len();
+        // Aligned sinks do not write past the end of the window of a file,
+        // even when the roll check is late
+        let window_ended = self.aligned_from.is_some()
+            && self
+                .active_sink
+                .as_ref()
+                .map_or(false, |active_sink| self.roll_at(active_sink) <= Utc::now());
 
         match self.active_sink.as_mut() {
             // If there is an active sink check if the write would make it too
-            // large. if so deposit and make a new sink. Otherwise the current
-            // active sink is usable.
+            // large or its window is over. if so deposit and make a new sink.
+            // Otherwise the current active sink is usable.
             Some(active_sink) => {
-                if active_sink.size + buf_len >= self.max_size {
+                if window_ended || active_sink.size + buf_len >= self.max_size {
                     active_sink.shutdown().await?;
                     if self.auto_commit {
                         self.commit().await?;
@@ -476,8 +962,12 @@ impl FileSink {
         }
 
         if let Some(active_sink) = self.active_sink.as_mut() {
-            active_sink.transport.send(buf).await?;
+            active_sink.transport.feed(buf.clone()).await?;
             active_sink.size += buf_len;
+            active_sink.correlation_ids.extend(correlation_id);
+            if let Some(tee) = &self.tee {
+                tee.hold(&mut self.tee_held, buf);
+            }
             Ok(())
         } else {
             Err(Error::from(io::Error::new(
@@ -488,6 +978,79 @@ impl FileSink {
     }
 }
 
+/// Resolves once clients overflowed writes, never for sinks without an
+/// overflow
+async fn overflowed(overflow: &Option<Arc<Overflow>>) {
+    match overflow {
+        Some(overflow) => overflow.notified().await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Hashes of the messages written within a time window, bounded in number
+#[derive(Debug)]
+struct Dedupe {
+    window: std::time::Duration,
+    seen: HashMap<blake3::Hash, time::Instant>,
+    order: VecDeque<(blake3::Hash, time::Instant)>,
+    /// Hashes of the messages written to the files not yet committed
+    staged: Vec<blake3::Hash>,
+}
+
+impl Dedupe {
+    fn new(window: std::time::Duration) -> Self {
+        Self {
+            window,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            staged: Vec::new(),
+        }
+    }
+
+    fn committed(&mut self) {
+        self.staged.clear();
+    }
+
+    /// Forget the messages of rolled back files, so writing them again is
+    /// not taken for a duplicate
+    fn rolled_back(&mut self) {
+        for hash in self.staged.drain(..) {
+            self.seen.remove(&hash);
+        }
+    }
+
+    /// Whether the message was seen within the window, remembering it if not
+    fn is_duplicate(&mut self, msg: &[u8], now: time::Instant) -> bool {
+        while let Some((hash, seen_at)) = self.order.front().copied() {
+            if now.duration_since(seen_at) < self.window && self.order.len() < DEDUPE_CAPACITY {
+                break;
+            }
+            self.order.pop_front();
+            // Rolled back messages written again are seen at a later time
+            if self.seen.get(&hash) == Some(&seen_at) {
+                self.seen.remove(&hash);
+            }
+        }
+
+        let hash = blake3::hash(msg);
+        if self.seen.contains_key(&hash) {
+            return true;
+        }
+        self.seen.insert(hash, now);
+        self.order.push_back((hash, now));
+        self.staged.push(hash);
+        false
+    }
+}
+
+/// Start of the window of the given duration, counted from the unix epoch,
+/// holding the time
+pub(crate) fn window_start(time: DateTime<Utc>, window: Duration) -> DateTime<Utc> {
+    let millis = window.num_milliseconds().max(1);
+    let start = time.timestamp_millis().div_euclid(millis) * millis;
+    Utc.timestamp_millis_opt(start).single().unwrap_or(time)
+}
+
 fn file_name(path_buf: &Path) -> Result<String> {
     path_buf
         .file_name()
@@ -509,6 +1072,124 @@ mod tests {
     use tempfile::TempDir;
     use tokio::fs::DirEntry;
 
+    #[test]
+    fn duplicates_are_detected_within_the_window() {
+        let mut dedupe = Dedupe::new(std::time::Duration::from_secs(10));
+        let start = time::Instant::now();
+
+        assert!(!dedupe.is_duplicate(b"one", start));
+        assert!(!dedupe.is_duplicate(b"two", start));
+        assert!(dedupe.is_duplicate(b"one", start + std::time::Duration::from_secs(9)));
+        assert!(!dedupe.is_duplicate(b"one", start + std::time::Duration::from_secs(10)));
+        assert_eq!(dedupe.seen.len(), 1);
+    }
+
+    #[test]
+    fn rolled_back_messages_are_not_duplicates() {
+        let mut dedupe = Dedupe::new(std::time::Duration::from_secs(10));
+        let start = time::Instant::now();
+
+        assert!(!dedupe.is_duplicate(b"committed", start));
+        dedupe.committed();
+        assert!(!dedupe.is_duplicate(b"rolled back", start));
+        dedupe.rolled_back();
+
+        let later = start + std::time::Duration::from_secs(1);
+        assert!(dedupe.is_duplicate(b"committed", later));
+        assert!(!dedupe.is_duplicate(b"rolled back", later));
+        // expiring the rolled back write keeps the one written again
+        assert!(dedupe.is_duplicate(b"rolled back", start + std::time::Duration::from_secs(10)));
+    }
+
+    #[tokio::test]
+    async fn writes_retried_after_a_rollback_are_written() {
+        let tmp_dir = TempDir::new().expect("Unable to create temp dir");
+        let (_shutdown_trigger, shutdown_listener) = triggered::trigger();
+
+        let (_file_sink_client, mut file_sink) = FileSinkBuilder::new(
+            FileType::EntropyReport,
+            tmp_dir.path(),
+            "fake_metric",
+            shutdown_listener,
+        )
+        .auto_commit(false)
+        .dedupe(Some(std::time::Duration::from_secs(60)))
+        .create()
+        .await
+        .expect("failed to create file sink");
+
+        file_sink
+            .write(Bytes::from_static(b"hello"), None)
+            .await
+            .unwrap();
+        file_sink.rollback().await.unwrap();
+        file_sink
+            .write(Bytes::from_static(b"hello"), None)
+            .await
+            .unwrap();
+        file_sink.commit().await.unwrap();
+
+        let entropy_file = get_entropy_file(&tmp_dir)
+            .await
+            .expect("no entropy available");
+        assert_eq!("hello", read_file(&entropy_file).await);
+    }
+
+    #[test]
+    fn settings_override_the_builder_policies() {
+        let (_trigger, shutdown_listener) = triggered::trigger();
+        let sinks = SinksSettings::from([(
+            "iot_valid_packet".to_string(),
+            SinkSettings {
+                roll_minutes: Some(10),
+                compression_level: Some(9),
+                ack_mode: Some(AckMode::Auto),
+                overflow: Some(OverflowPolicy::DropOldest),
+                ..Default::default()
+            },
+        )]);
+
+        let builder = FileSinkBuilder::from_settings(
+            FileType::IotValidPacket,
+            Path::new("/tmp"),
+            "fake_metric",
+            shutdown_listener.clone(),
+            &sinks,
+        )
+        .roll_time(Duration::minutes(1))
+        .max_size(100)
+        .auto_commit(false)
+        .apply_overrides();
+        assert_eq!(builder.roll_time, Duration::minutes(10));
+        assert_eq!(builder.max_size, 100);
+        assert_eq!(builder.compression_level, Some(9));
+        // the commit behaviour the code relies on is kept
+        assert_eq!(builder.auto_commit, Some(false));
+        assert_eq!(builder.overflow, OverflowPolicy::DropOldest);
+
+        let builder = FileSinkBuilder::from_settings(
+            FileType::IotValidPacket,
+            Path::new("/tmp"),
+            "fake_metric",
+            shutdown_listener.clone(),
+            &sinks,
+        )
+        .apply_overrides();
+        assert_eq!(builder.auto_commit, Some(true));
+
+        let builder = FileSinkBuilder::from_settings(
+            FileType::InvalidPacket,
+            Path::new("/tmp"),
+            "fake_metric",
+            shutdown_listener,
+            &sinks,
+        )
+        .auto_commit(false)
+        .apply_overrides();
+        assert_eq!(builder.roll_time, Duration::minutes(DEFAULT_SINK_ROLL_MINS));
+        assert_eq!(builder.auto_commit, Some(false));
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn writes_a_framed_gzip_encoded_file() {
         let tmp_dir = TempDir::new().expect("Unable to create temp dir");
@@ -539,6 +1220,7 @@ mod tests {
             .try_send(Message::Data(
                 on_write_tx,
                 String::into_bytes("hello".to_string()),
+                None,
             ))
             .expect("failed to send bytes to file sink");
 
@@ -585,6 +1267,7 @@ mod tests {
             .try_send(Message::Data(
                 on_write_tx,
                 String::into_bytes("hello".to_string()),
+                None,
             ))
             .expect("failed to send bytes to file sink");
 
@@ -610,6 +1293,244 @@ mod tests {
         sink_thread.await.expect("file sink did not complete");
     }
 
+    #[tokio::test]
+    async fn files_named_by_a_file_time_are_reproducible() {
+        let epoch_end = Utc.timestamp_millis_opt(1_686_000_000_000).unwrap();
+
+        async fn write_run(epoch_end: DateTime<Utc>) -> (TempDir, Vec<String>) {
+            let tmp_dir = TempDir::new().expect("Unable to create temp dir");
+            let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+            let (file_sink_client, mut file_sink_server) = FileSinkBuilder::new(
+                FileType::EntropyReport,
+                tmp_dir.path(),
+                "fake_metric",
+                shutdown_listener,
+            )
+            .auto_commit(false)
+            .create()
+            .await
+            .expect("failed to create file sink");
+            let sink_thread = tokio::spawn(async move {
+                file_sink_server
+                    .run()
+                    .await
+                    .expect("failed to complete file sink");
+            });
+
+            file_sink_client.file_time(epoch_end).await.unwrap();
+            for data in [vec![1], vec![2, 3]] {
+                let report = helium_proto::EntropyReportV1 {
+                    data,
+                    timestamp: 1,
+                    version: 0,
+                };
+                file_sink_client
+                    .write(report, [])
+                    .await
+                    .unwrap()
+                    .await
+                    .unwrap()
+                    .unwrap();
+            }
+            let manifest = file_sink_client
+                .commit()
+                .await
+                .unwrap()
+                .await
+                .unwrap()
+                .unwrap();
+
+            shutdown_trigger.trigger();
+            sink_thread.await.expect("file sink did not complete");
+            (tmp_dir, manifest)
+        }
+
+        let (first_dir, first) = write_run(epoch_end).await;
+        let (second_dir, second) = write_run(epoch_end).await;
+        assert_eq!(first, vec!["entropy_report.1686000000000.gz".to_string()]);
+        assert_eq!(first, second);
+        assert_eq!(
+            fs::read(first_dir.path().join(&first[0])).await.unwrap(),
+            fs::read(second_dir.path().join(&second[0])).await.unwrap()
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingPublisher(Arc<std::sync::Mutex<Vec<Bytes>>>);
+
+    #[async_trait::async_trait]
+    impl message_bus::Publisher for RecordingPublisher {
+        async fn publish(&self, _topic: &str, payload: Bytes) -> Result {
+            self.0.lock().unwrap().push(payload);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn only_committed_records_are_published() {
+        let tmp_dir = TempDir::new().expect("Unable to create temp dir");
+        let (_shutdown_trigger, shutdown_listener) = triggered::trigger();
+        let publisher = RecordingPublisher::default();
+        let (tee, tee_publisher) = message_bus::tee(publisher.clone(), "entropy", 10);
+        let tee_publisher_shutdown = shutdown_listener.clone();
+        let published =
+            tokio::spawn(async move { tee_publisher.run(&tee_publisher_shutdown).await });
+
+        let (_file_sink_client, mut file_sink) = FileSinkBuilder::new(
+            FileType::EntropyReport,
+            tmp_dir.path(),
+            "fake_metric",
+            shutdown_listener,
+        )
+        .auto_commit(false)
+        .tee(Some(tee))
+        .create()
+        .await
+        .expect("failed to create file sink");
+
+        file_sink
+            .write(Bytes::from_static(b"rolled back"), None)
+            .await
+            .unwrap();
+        file_sink.rollback().await.unwrap();
+        file_sink
+            .write(Bytes::from_static(b"committed"), None)
+            .await
+            .unwrap();
+        assert!(publisher.0.lock().unwrap().is_empty());
+        file_sink.commit().await.unwrap();
+
+        // dropping the sink closes the tee, ending the publisher once it
+        // published everything queued
+        drop(file_sink);
+        published.await.unwrap().unwrap();
+        assert_eq!(
+            *publisher.0.lock().unwrap(),
+            vec![Bytes::from_static(b"committed")]
+        );
+    }
+
+    fn entropy_report(data: &[u8]) -> Vec<u8> {
+        prost::Message::encode_to_vec(&helium_proto::EntropyReportV1 {
+            data: data.to_vec(),
+            timestamp: 1,
+            version: 0,
+        })
+    }
+
+    /// Sink of manual acks spilling the writes overflowing its queue, which
+    /// is not read until the test asks the sink to
+    async fn spilling_sink(
+        tmp_dir: &TempDir,
+        shutdown_listener: triggered::Listener,
+    ) -> (FileSinkClient, FileSink) {
+        FileSinkBuilder::new(
+            FileType::EntropyReport,
+            tmp_dir.path(),
+            "fake_metric",
+            shutdown_listener,
+        )
+        .auto_commit(false)
+        .overflow(OverflowPolicy::Spill)
+        .create()
+        .await
+        .expect("failed to create file sink")
+    }
+
+    /// Fill the queue of the sink and spill a write, which is acknowledged
+    async fn spill(client: &FileSinkClient) {
+        for i in 0..QUEUE_SIZE {
+            client
+                .sender
+                .try_send(Message::Data(
+                    oneshot::channel().0,
+                    entropy_report(&[i as u8]),
+                    None,
+                ))
+                .unwrap();
+        }
+        let (on_write_tx, on_write_rx) = oneshot::channel();
+        client
+            .overflow
+            .as_ref()
+            .unwrap()
+            .send(
+                &client.sender,
+                (on_write_tx, entropy_report(b"spilled"), None),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(on_write_rx.await, Ok(Ok(()))));
+        assert_eq!(client.queue_depth(), QUEUE_SIZE + 1);
+    }
+
+    /// Records of the committed files, oldest first
+    async fn committed_records(tmp_dir: &TempDir) -> Vec<Vec<u8>> {
+        let mut paths = vec![];
+        let mut entries = fs::read_dir(tmp_dir.path()).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            if is_entropy_file(&entry) {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+        file_source::source(paths)
+            .map(|record| record.expect("invalid data in file").to_vec())
+            .collect()
+            .await
+    }
+
+    async fn overflow_files(tmp_dir: &TempDir) -> usize {
+        let mut entries = fs::read_dir(tmp_dir.path().join("overflow")).await.unwrap();
+        let mut files = 0;
+        while entries.next_entry().await.unwrap().is_some() {
+            files += 1;
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn spilled_writes_are_replayed_after_a_rollback() {
+        let tmp_dir = TempDir::new().expect("Unable to create temp dir");
+        let (_shutdown_trigger, shutdown_listener) = triggered::trigger();
+        let (client, mut file_sink) = spilling_sink(&tmp_dir, shutdown_listener).await;
+        spill(&client).await;
+
+        file_sink.drain_overflow().await;
+        assert_eq!(overflow_files(&tmp_dir).await, 1);
+        file_sink.rollback().await.unwrap();
+        assert_eq!(client.queue_depth(), 1);
+
+        file_sink.write_overflowed().await;
+        file_sink.commit().await.unwrap();
+        assert_eq!(
+            committed_records(&tmp_dir).await,
+            vec![entropy_report(b"spilled")]
+        );
+        assert_eq!(overflow_files(&tmp_dir).await, 0);
+    }
+
+    #[tokio::test]
+    async fn spilled_writes_are_replayed_after_a_restart() {
+        let tmp_dir = TempDir::new().expect("Unable to create temp dir");
+        let (_shutdown_trigger, shutdown_listener) = triggered::trigger();
+        let (client, mut file_sink) = spilling_sink(&tmp_dir, shutdown_listener.clone()).await;
+        spill(&client).await;
+        file_sink.drain_overflow().await;
+        drop((client, file_sink));
+
+        // The restart removes the uncommitted file holding the spilled write
+        let (client, mut file_sink) = spilling_sink(&tmp_dir, shutdown_listener).await;
+        assert_eq!(client.queue_depth(), 1);
+        file_sink.write_overflowed().await;
+        file_sink.commit().await.unwrap();
+        assert_eq!(
+            committed_records(&tmp_dir).await,
+            vec![entropy_report(b"spilled")]
+        );
+        assert_eq!(overflow_files(&tmp_dir).await, 0);
+    }
+
     async fn read_file(entry: &DirEntry) -> bytes::BytesMut {
         file_source::source([entry.path()])
             .next()
@@ -0,0 +1,497 @@
+//! Sinks of related outputs that roll and commit together.
+//!
+//! The sinks of a group name their files by the windows of the roll time of
+//! the group, counted from the unix epoch, so the files written by the sinks
+//! in the same window carry the same timestamp and roll at the same time. A
+//! commit of the group first closes the files of every sink and only
+//! deposits them once all of them closed, rolling every sink back otherwise.
+//!
+//! Every commit depositing files is recorded in a json [`GroupCommit`] under
+//! `manifests` in the target directory of the first sink of the group. When a
+//! sink fails to deposit its files the sinks after it are rolled back, so no
+//! later commit deposits their files out of line, and the record names the
+//! sink that failed and the sinks rolled back. Records of successful commits
+//! are removed once all of their files were uploaded, and so removed from the
+//! target directories; records of failed commits are kept for inspection.
+
+use crate::{
+    file_sink::{FileManifest, FileSink, FileSinkClient},
+    Error, FileSinkBuilder, Result,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+
+/// Files of a commit of a sink group, keyed by the prefix of their sink
+pub type GroupManifest = BTreeMap<String, FileManifest>;
+
+/// Record of a commit of a sink group
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupCommit {
+    pub group: String,
+    pub committed_at: DateTime<Utc>,
+    /// Files deposited by the commit
+    pub files: GroupManifest,
+    /// Prefix of the sink that failed to deposit its files, which may have
+    /// deposited some of them
+    pub failed: Option<String>,
+    /// Prefixes of the sinks rolled back after the failed sink
+    pub rolled_back: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SinkGroup {
+    name: String,
+    roll_time: Duration,
+    started: DateTime<Utc>,
+    /// Prefix, target directory and client of every sink
+    sinks: Vec<(String, PathBuf, FileSinkClient)>,
+    manifest_path: Option<PathBuf>,
+}
+
+impl SinkGroup {
+    pub fn new(name: impl ToString, roll_time: Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            roll_time,
+            started: Utc::now(),
+            sinks: Vec::new(),
+            manifest_path: None,
+        }
+    }
+
+    /// Create a sink of the group. The roll time of the group replaces the
+    /// one of the builder and of its settings. Sinks of a group are meant to
+    /// be created with auto commit off and committed through the group
+    pub async fn create(&mut self, builder: FileSinkBuilder) -> Result<(FileSinkClient, FileSink)> {
+        let prefix = builder.prefix().to_string();
+        let target_dir = builder.target_dir().to_path_buf();
+        if self.manifest_path.is_none() {
+            let manifest_path = builder.target_dir().join("manifests");
+            fs::create_dir_all(&manifest_path).await?;
+            self.manifest_path = Some(manifest_path);
+        }
+        let (client, sink) = builder
+            .aligned(self.started, self.roll_time)
+            .create()
+            .await?;
+        self.sinks.push((prefix, target_dir, client.clone()));
+        Ok((client, sink))
+    }
+
+    /// Deposit the files written by all sinks since the last commit, or none
+    /// of them when a sink fails to close its file
+    pub async fn commit(&self) -> Result<GroupManifest> {
+        if let Err(err) = self.close().await {
+            tracing::error!(
+                group = self.name,
+                "failed to close sinks, rolling back: {err:?}"
+            );
+            self.rollback().await?;
+            return Err(err);
+        }
+
+        let mut commit = GroupCommit {
+            group: self.name.clone(),
+            committed_at: Utc::now(),
+            files: GroupManifest::new(),
+            failed: None,
+            rolled_back: Vec::new(),
+        };
+        let mut failure = None;
+        for (prefix, _, client) in &self.sinks {
+            if failure.is_some() {
+                if let Err(err) = rollback_sink(client).await {
+                    tracing::error!(group = self.name, prefix, "failed to roll back: {err:?}");
+                }
+                commit.rolled_back.push(prefix.clone());
+                continue;
+            }
+            match commit_sink(client).await {
+                Ok(files) => {
+                    commit.files.insert(prefix.clone(), files);
+                }
+                Err(err) => {
+                    tracing::error!(
+                        group = self.name,
+                        prefix,
+                        "failed to commit, rolling back the rest: {err:?}"
+                    );
+                    commit.failed = Some(prefix.clone());
+                    failure = Some(err);
+                }
+            }
+        }
+
+        if let Err(err) = self.prune_manifests().await {
+            tracing::warn!(group = self.name, "failed to prune manifests: {err:?}");
+        }
+        let deposited = commit.files.values().any(|files| !files.is_empty());
+        if deposited || failure.is_some() {
+            self.write_manifest(&commit).await?;
+        }
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(commit.files),
+        }
+    }
+
+    /// Remove the files written by all sinks since the last commit. Every
+    /// sink is rolled back, even after one of them failed
+    pub async fn rollback(&self) -> Result<GroupManifest> {
+        let mut manifest = GroupManifest::new();
+        let mut failure = None;
+        for (prefix, _, client) in &self.sinks {
+            match rollback_sink(client).await {
+                Ok(files) => {
+                    manifest.insert(prefix.clone(), files);
+                }
+                Err(err) => {
+                    tracing::error!(group = self.name, prefix, "failed to roll back: {err:?}");
+                    failure.get_or_insert(err);
+                }
+            }
+        }
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(manifest),
+        }
+    }
+
+    async fn close(&self) -> Result {
+        for (_, _, client) in &self.sinks {
+            client
+                .close()
+                .await?
+                .await
+                .map_err(|_| Error::channel())??;
+        }
+        Ok(())
+    }
+
+    /// Write the record of a commit, through a temporary file so a record is
+    /// never read half written
+    async fn write_manifest(&self, commit: &GroupCommit) -> Result {
+        let Some(manifest_path) = &self.manifest_path else {
+            return Ok(());
+        };
+        let name = format!(
+            "{}.{}.json",
+            self.name,
+            commit.committed_at.timestamp_micros()
+        );
+        let tmp_path = manifest_path.join(format!("{name}.tmp"));
+        fs::write(&tmp_path, serde_json::to_vec(commit)?).await?;
+        fs::rename(&tmp_path, manifest_path.join(name)).await?;
+        Ok(())
+    }
+
+    /// Remove the records of successful commits none of whose files are
+    /// left in the target directory of their sink, returning the number
+    /// removed
+    pub async fn prune_manifests(&self) -> Result<usize> {
+        let Some(manifest_path) = &self.manifest_path else {
+            return Ok(0);
+        };
+        let mut pruned = 0;
+        let mut dir = fs::read_dir(manifest_path).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            let commit: GroupCommit = serde_json::from_slice(&fs::read(&path).await?)?;
+            if commit.failed.is_none() && !self.any_file_left(&commit.files).await {
+                fs::remove_file(&path).await?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Whether any file of the manifest is still in the target directory of
+    /// its sink. Files of sinks no longer in the group count as left
+    async fn any_file_left(&self, manifest: &GroupManifest) -> bool {
+        for (prefix, files) in manifest {
+            let Some(target_dir) = self.target_dir(prefix) else {
+                return !files.is_empty();
+            };
+            for file in files {
+                match fs::metadata(target_dir.join(file)).await {
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+                    _ => return true,
+                }
+            }
+        }
+        false
+    }
+
+    fn target_dir(&self, prefix: &str) -> Option<&Path> {
+        self.sinks
+            .iter()
+            .find(|(sink_prefix, ..)| sink_prefix == prefix)
+            .map(|(_, target_dir, _)| target_dir.as_path())
+    }
+}
+
+async fn commit_sink(client: &FileSinkClient) -> Result<FileManifest> {
+    client.commit().await?.await.map_err(|_| Error::channel())?
+}
+
+async fn rollback_sink(client: &FileSinkClient) -> Result<FileManifest> {
+    client
+        .rollback()
+        .await?
+        .await
+        .map_err(|_| Error::channel())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{file_sink::window_start, FileInfo, FileType};
+    use std::{path::Path, str::FromStr};
+    use tempfile::TempDir;
+    use tokio::task::JoinHandle;
+
+    fn file_time(name: &str) -> DateTime<Utc> {
+        FileInfo::from_str(name).expect("sink file name").timestamp
+    }
+
+    /// Create a sink of the group for each file type, depositing its files
+    /// in the given directory
+    async fn create_sinks(
+        group: &mut SinkGroup,
+        sinks: &[(FileType, &Path)],
+        tmp_dir: &TempDir,
+        shutdown_listener: &triggered::Listener,
+    ) -> (Vec<FileSinkClient>, Vec<JoinHandle<Result>>) {
+        let mut clients = Vec::new();
+        let mut servers = Vec::new();
+        for (file_type, target_path) in sinks {
+            let (client, mut server) = group
+                .create(
+                    FileSinkBuilder::new(
+                        file_type,
+                        tmp_dir.path(),
+                        "fake_metric",
+                        shutdown_listener.clone(),
+                    )
+                    .target_path(target_path)
+                    .roll_time(Duration::milliseconds(1))
+                    .auto_commit(false),
+                )
+                .await
+                .expect("failed to create file sink");
+            servers.push(tokio::spawn(async move { server.run().await }));
+            clients.push(client);
+        }
+        (clients, servers)
+    }
+
+    async fn write(client: &FileSinkClient, message: &str) {
+        client
+            .write(message.to_string(), [])
+            .await
+            .expect("failed to write")
+            .await
+            .expect("write didn't complete")
+            .expect("write failed");
+    }
+
+    async fn group_commits(tmp_dir: &TempDir) -> Vec<GroupCommit> {
+        let mut commits = Vec::new();
+        let mut dir = fs::read_dir(tmp_dir.path().join("manifests"))
+            .await
+            .expect("manifest dir");
+        while let Some(entry) = dir.next_entry().await.expect("manifest entry") {
+            let data = fs::read(entry.path()).await.expect("manifest file");
+            commits.push(serde_json::from_slice::<GroupCommit>(&data).expect("manifest"));
+        }
+        commits.sort_by_key(|commit| commit.committed_at);
+        commits
+    }
+
+    async fn stop(shutdown_trigger: triggered::Trigger, servers: Vec<JoinHandle<Result>>) {
+        shutdown_trigger.trigger();
+        for server in servers {
+            server
+                .await
+                .expect("file sink did not complete")
+                .expect("file sink failed");
+        }
+    }
+
+    #[tokio::test]
+    async fn sinks_of_a_group_commit_aligned_files() {
+        let tmp_dir = TempDir::new().expect("Unable to create temp dir");
+        let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        let roll_time = Duration::minutes(10);
+        let mut group = SinkGroup::new("packets", roll_time);
+        let (clients, servers) = create_sinks(
+            &mut group,
+            &[
+                (FileType::IotValidPacket, tmp_dir.path()),
+                (FileType::InvalidPacket, tmp_dir.path()),
+            ],
+            &tmp_dir,
+            &shutdown_listener,
+        )
+        .await;
+
+        let mut manifests = Vec::new();
+        for message in ["hello", "world"] {
+            for client in &clients {
+                write(client, message).await;
+                write(client, message).await;
+            }
+            manifests.push(group.commit().await.expect("commit failed"));
+        }
+
+        for manifest in &manifests {
+            let valid = &manifest[FileType::IotValidPacket.to_str()];
+            let invalid = &manifest[FileType::InvalidPacket.to_str()];
+            assert_eq!(valid.len(), 1);
+            assert_eq!(invalid.len(), 1);
+            // Both files are named by the same time, the start of the group
+            // or of a window of its roll time
+            let time = file_time(&valid[0]);
+            assert_eq!(time, file_time(&invalid[0]));
+            assert!(time >= group.started || time == window_start(time, roll_time));
+            assert!(tmp_dir.path().join(&valid[0]).exists());
+            assert!(tmp_dir.path().join(&invalid[0]).exists());
+        }
+        assert_ne!(manifests[0], manifests[1]);
+
+        // A commit without writes deposits nothing and is not recorded
+        let manifest = group.commit().await.expect("commit failed");
+        assert!(manifest.values().all(|files| files.is_empty()));
+
+        let commits = group_commits(&tmp_dir).await;
+        assert_eq!(commits.len(), 2);
+        for (commit, manifest) in commits.iter().zip(&manifests) {
+            assert_eq!(commit.group, "packets");
+            assert_eq!(&commit.files, manifest);
+            assert_eq!(commit.failed, None);
+            assert!(commit.rolled_back.is_empty());
+        }
+
+        stop(shutdown_trigger, servers).await;
+    }
+
+    #[tokio::test]
+    async fn sinks_after_a_failing_member_are_rolled_back() {
+        let tmp_dir = TempDir::new().expect("Unable to create temp dir");
+        let gone = tmp_dir.path().join("gone");
+        let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        let mut group = SinkGroup::new("packets", Duration::minutes(10));
+        let (clients, servers) = create_sinks(
+            &mut group,
+            &[
+                (FileType::IotValidPacket, tmp_dir.path()),
+                (FileType::InvalidPacket, gone.as_path()),
+                (FileType::IotPacketReport, tmp_dir.path()),
+            ],
+            &tmp_dir,
+            &shutdown_listener,
+        )
+        .await;
+        // The files of the second sink close, but can not be deposited
+        fs::remove_dir_all(&gone).await.expect("remove target");
+
+        for client in &clients {
+            write(client, "hello").await;
+        }
+        assert!(group.commit().await.is_err());
+
+        let commits = group_commits(&tmp_dir).await;
+        assert_eq!(commits.len(), 1);
+        let commit = &commits[0];
+        let valid = &commit.files[FileType::IotValidPacket.to_str()];
+        assert_eq!(valid.len(), 1);
+        assert!(tmp_dir.path().join(&valid[0]).exists());
+        assert_eq!(
+            commit.failed.as_deref(),
+            Some(FileType::InvalidPacket.to_str())
+        );
+        assert_eq!(
+            commit.rolled_back,
+            vec![FileType::IotPacketReport.to_str().to_string()]
+        );
+
+        // Nothing of the rolled back sink is left to be deposited later
+        let mut tmp = fs::read_dir(tmp_dir.path().join("tmp")).await.unwrap();
+        while let Some(entry) = tmp.next_entry().await.unwrap() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            assert!(!name.starts_with(FileType::IotPacketReport.to_str()));
+        }
+        let manifest = group.rollback().await.expect("rollback failed");
+        assert!(manifest.values().all(|files| files.is_empty()));
+
+        // The record of the failure is kept after the files were uploaded
+        fs::remove_file(tmp_dir.path().join(&valid[0]))
+            .await
+            .unwrap();
+        assert_eq!(group.prune_manifests().await.unwrap(), 0);
+        assert_eq!(group_commits(&tmp_dir).await.len(), 1);
+
+        stop(shutdown_trigger, servers).await;
+    }
+
+    #[tokio::test]
+    async fn records_are_pruned_once_their_files_are_uploaded() {
+        let tmp_dir = TempDir::new().expect("Unable to create temp dir");
+        let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        let mut group = SinkGroup::new("packets", Duration::minutes(10));
+        let (clients, servers) = create_sinks(
+            &mut group,
+            &[
+                (FileType::IotValidPacket, tmp_dir.path()),
+                (FileType::InvalidPacket, tmp_dir.path()),
+            ],
+            &tmp_dir,
+            &shutdown_listener,
+        )
+        .await;
+
+        for client in &clients {
+            write(client, "hello").await;
+        }
+        let manifest = group.commit().await.expect("commit failed");
+        assert_eq!(group.prune_manifests().await.unwrap(), 0);
+
+        // Uploading removes the deposited files one at a time
+        let mut files = manifest.values().flatten();
+        let first = files.next().unwrap();
+        fs::remove_file(tmp_dir.path().join(first)).await.unwrap();
+        assert_eq!(group.prune_manifests().await.unwrap(), 0);
+        assert_eq!(group_commits(&tmp_dir).await.len(), 1);
+        for file in files {
+            fs::remove_file(tmp_dir.path().join(file)).await.unwrap();
+        }
+        assert_eq!(group.prune_manifests().await.unwrap(), 1);
+        assert!(group_commits(&tmp_dir).await.is_empty());
+
+        // The next commit prunes the records of uploaded commits itself
+        for client in &clients {
+            write(client, "world").await;
+        }
+        let manifest = group.commit().await.expect("commit failed");
+        for file in manifest.values().flatten() {
+            fs::remove_file(tmp_dir.path().join(file)).await.unwrap();
+        }
+        for client in &clients {
+            write(client, "again").await;
+        }
+        let manifest = group.commit().await.expect("commit failed");
+        let commits = group_commits(&tmp_dir).await;
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].files, manifest);
+
+        stop(shutdown_trigger, servers).await;
+    }
+}
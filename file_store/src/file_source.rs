@@ -1,12 +1,35 @@
-use crate::{file_info_poller::FileInfoPollerBuilder, file_sink, BytesMutStream, Error};
+use crate::{
+    error::DecodeError, file_info_poller::FileInfoPollerBuilder, BytesMutStream, DecodeLimits,
+    Error, Result,
+};
 use async_compression::tokio::bufread::GzipDecoder;
+use bytes::BytesMut;
+use chrono::DateTime;
+use csv::StringRecord;
 use futures::{
     stream::{self},
     StreamExt, TryFutureExt, TryStreamExt,
 };
-use std::path::{Path, PathBuf};
-use tokio::{fs::File, io::BufReader};
-use tokio_util::codec::{length_delimited::LengthDelimitedCodec, FramedRead};
+use helium_crypto::PublicKeyBinary;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    io,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader, ReadBuf},
+};
+use tokio_util::codec::{
+    length_delimited::LengthDelimitedCodec, FramedRead, LengthDelimitedCodecError,
+};
 
 pub fn continuous_source<T>() -> FileInfoPollerBuilder<T>
 where
@@ -15,7 +38,18 @@ where
     FileInfoPollerBuilder::<T>::default()
 }
 
+/// Frames of the local files with the default [`DecodeLimits`]
 pub fn source<I, P>(paths: I) -> BytesMutStream
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    source_with_limits(paths, DecodeLimits::default())
+}
+
+/// Frames of the local files, failing like the files of a store when a file
+/// exceeds the limits
+pub fn source_with_limits<I, P>(paths: I, limits: DecodeLimits) -> BytesMutStream
 where
     I: IntoIterator<Item = P>,
     P: AsRef<Path>,
@@ -27,27 +61,537 @@ where
     stream::iter(paths)
         .map(|path| File::open(path).map_err(Error::from))
         .buffered(2)
-        .flat_map(|file| match file {
-            Ok(file) => {
-                let buf_reader = BufReader::new(file);
-                let codec = LengthDelimitedCodec::builder()
-                    .max_frame_length(file_sink::MAX_FRAME_LENGTH)
-                    .new_codec();
-
-                FramedRead::new(GzipDecoder::new(buf_reader), codec)
-                    .map_err(Error::from)
-                    .boxed()
-            }
+        .flat_map(move |file| match file {
+            Ok(file) => framed(GzipDecoder::new(BufReader::new(file)), limits),
             Err(err) => stream::once(async { Err(err) }).boxed(),
         })
         .boxed()
 }
 
+/// Frames of the decompressed content of a file, failing with
+/// [`DecodeError::FrameTooLarge`] or [`DecodeError::FileTooLarge`] when the
+/// file exceeds the limits
+pub(crate) fn framed<R>(decompressed: R, limits: DecodeLimits) -> BytesMutStream
+where
+    R: AsyncRead + Send + 'static,
+{
+    framed_limited(Limited::new(decompressed, limits), limits)
+}
+
+fn framed_limited<R>(reader: R, limits: DecodeLimits) -> BytesMutStream
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let codec = LengthDelimitedCodec::builder()
+        .max_frame_length(limits.max_frame_length)
+        .new_codec();
+    FramedRead::new(reader, codec)
+        .map_err(move |err| limit_error(err, limits))
+        .boxed()
+}
+
+/// The error of a failed read, the specific decode error when it is due to
+/// the limits
+fn limit_error(err: io::Error, limits: DecodeLimits) -> Error {
+    match err.get_ref() {
+        Some(inner) if inner.is::<LengthDelimitedCodecError>() => {
+            DecodeError::FrameTooLarge(limits.max_frame_length).into()
+        }
+        Some(inner) if inner.is::<FileTooLarge>() => {
+            DecodeError::FileTooLarge(limits.max_file_length).into()
+        }
+        _ => err.into(),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("file too large")]
+struct FileTooLarge;
+
+/// Reader of decompressed content failing once more than the maximum file
+/// length is read, before a gzip bomb fills the memory of the reader
+struct Limited {
+    inner: Pin<Box<dyn AsyncRead + Send>>,
+    remaining: u64,
+}
+
+impl Limited {
+    fn new<R: AsyncRead + Send + 'static>(inner: R, limits: DecodeLimits) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            remaining: limits.max_file_length,
+        }
+    }
+}
+
+impl AsyncRead for Limited {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = self.inner.as_mut().poll_read(cx, buf);
+        let read = (buf.filled().len() - filled) as u64;
+        match self.remaining.checked_sub(read) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                poll
+            }
+            None => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                FileTooLarge,
+            ))),
+        }
+    }
+}
+
+/// Layout of the legacy gzip csv exports of a report type. The first row of
+/// an export holds the column headers
+#[derive(Clone, Debug, Deserialize)]
+pub struct CsvSchema {
+    /// Field delimiter, an ascii character. Default ","
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    /// Header of the column holding each message field, keyed by field name.
+    /// Fields without an entry are read from the column named like the field
+    #[serde(default)]
+    pub columns: HashMap<String, String>,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+impl Default for CsvSchema {
+    fn default() -> Self {
+        Self {
+            delimiter: default_delimiter(),
+            columns: HashMap::new(),
+        }
+    }
+}
+
+/// Report message that can be read from a row of a legacy csv export
+pub trait FromCsv: prost::Message + Sized {
+    fn from_csv(row: &CsvRow) -> Result<Self>;
+}
+
+pub struct CsvRow<'a> {
+    schema: &'a CsvSchema,
+    headers: &'a StringRecord,
+    record: &'a StringRecord,
+}
+
+impl CsvRow<'_> {
+    fn value(&self, field: &str) -> Option<&str> {
+        let column = self.schema.columns.get(field).map_or(field, String::as_str);
+        self.headers
+            .iter()
+            .position(|header| header == column)
+            .and_then(|index| self.record.get(index))
+    }
+
+    /// The value of a field, failing when its column is missing
+    pub fn get<T>(&self, field: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.value(field)
+            .ok_or_else(|| DecodeError::csv_field(field, "missing column"))?
+            .trim()
+            .parse()
+            .map_err(|err| DecodeError::csv_field(field, err))
+    }
+
+    /// The value of a field, the default when its column is missing or empty
+    pub fn get_or_default<T>(&self, field: &str) -> Result<T>
+    where
+        T: FromStr + Default,
+        T::Err: Display,
+    {
+        match self.value(field).map(str::trim) {
+            None | Some("") => Ok(T::default()),
+            Some(_) => self.get(field),
+        }
+    }
+
+    /// The binary helium public key of a field holding a b58 key
+    pub fn public_key(&self, field: &str) -> Result<Vec<u8>> {
+        Ok(self.get::<PublicKeyBinary>(field)?.into())
+    }
+
+    /// Milliseconds since the unix epoch of a field holding either
+    /// milliseconds or an rfc3339 time
+    pub fn timestamp_millis(&self, field: &str) -> Result<u64> {
+        self.timestamp(field, |time| time.timestamp_millis())
+    }
+
+    /// Seconds since the unix epoch of a field holding either seconds or an
+    /// rfc3339 time
+    pub fn timestamp_secs(&self, field: &str) -> Result<u64> {
+        self.timestamp(field, |time| time.timestamp())
+    }
+
+    fn timestamp(
+        &self,
+        field: &str,
+        unix: impl Fn(DateTime<chrono::FixedOffset>) -> i64,
+    ) -> Result<u64> {
+        let value: String = self.get(field)?;
+        if let Ok(timestamp) = value.parse() {
+            return Ok(timestamp);
+        }
+        let time = DateTime::parse_from_rfc3339(&value)
+            .map_err(|err| DecodeError::csv_field(field, err))?;
+        u64::try_from(unix(time)).map_err(|err| DecodeError::csv_field(field, err))
+    }
+}
+
+/// Decoder of report files that also reads the legacy gzip csv exports of
+/// the reports, turning every row into the encoded message `M`. Consumers
+/// decode the messages as they would from a framed protobuf file.
+///
+/// Files are detected as csv when they do not start with a frame length, as
+/// lengths up to the maximum frame length always start with a zero byte.
+/// Csv files are read into memory as a whole.
+pub struct LegacyDecoder<M> {
+    schema: Arc<CsvSchema>,
+    limits: DecodeLimits,
+    message: PhantomData<fn() -> M>,
+}
+
+impl<M> Clone for LegacyDecoder<M> {
+    fn clone(&self) -> Self {
+        Self {
+            schema: self.schema.clone(),
+            limits: self.limits,
+            message: PhantomData,
+        }
+    }
+}
+
+impl<M: FromCsv + 'static> LegacyDecoder<M> {
+    pub fn new(schema: CsvSchema) -> Result<Self> {
+        if !schema.delimiter.is_ascii() {
+            return Err(DecodeError::csv_field(
+                "delimiter",
+                "must be an ascii character",
+            ));
+        }
+        Ok(Self {
+            schema: Arc::new(schema),
+            limits: DecodeLimits::default(),
+            message: PhantomData,
+        })
+    }
+
+    /// Limits on the decompressed size of the files decoded
+    pub fn limits(self, limits: DecodeLimits) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// Encoded messages of a gzip file of either format
+    pub fn decode<R>(&self, reader: R) -> BytesMutStream
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        decode_either::<M, R>(reader, self.schema.clone(), self.limits)
+    }
+
+    /// Like [`source`], reading the legacy csv exports among the files
+    pub fn source<I, P>(&self, paths: I) -> BytesMutStream
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let paths: Vec<PathBuf> = paths
+            .into_iter()
+            .map(|path| path.as_ref().to_path_buf())
+            .collect();
+        let decoder = self.clone();
+        stream::iter(paths)
+            .map(|path| File::open(path).map_err(Error::from))
+            .buffered(2)
+            .flat_map(move |file| match file {
+                Ok(file) => decoder.decode(file),
+                Err(err) => stream::once(async { Err(err) }).boxed(),
+            })
+            .boxed()
+    }
+}
+
+/// A [`LegacyDecoder`] of any message type, so the poller of a report type
+/// can hold the decoder of its messages
+#[derive(Clone)]
+pub(crate) struct AnyLegacyDecoder(
+    Arc<dyn Fn(Pin<Box<dyn AsyncRead + Send>>, DecodeLimits) -> BytesMutStream + Send + Sync>,
+);
+
+impl AnyLegacyDecoder {
+    /// Encoded messages of a gzip file of either format, decoded within the
+    /// given limits
+    pub(crate) fn decode<R>(&self, reader: R, limits: DecodeLimits) -> BytesMutStream
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        (self.0)(Box::pin(reader), limits)
+    }
+}
+
+impl<M: FromCsv + 'static> From<LegacyDecoder<M>> for AnyLegacyDecoder {
+    fn from(decoder: LegacyDecoder<M>) -> Self {
+        let schema = decoder.schema;
+        Self(Arc::new(move |reader, limits| {
+            decode_either::<M, _>(reader, schema.clone(), limits)
+        }))
+    }
+}
+
+impl fmt::Debug for AnyLegacyDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AnyLegacyDecoder")
+    }
+}
+
+fn decode_either<M, R>(reader: R, schema: Arc<CsvSchema>, limits: DecodeLimits) -> BytesMutStream
+where
+    M: FromCsv + 'static,
+    R: AsyncRead + Send + 'static,
+{
+    stream::once(async move {
+        let mut reader = BufReader::new(Limited::new(
+            GzipDecoder::new(BufReader::new(Box::pin(reader))),
+            limits,
+        ));
+        let legacy = reader
+            .fill_buf()
+            .await
+            .map_err(|err| limit_error(err, limits))?
+            .first()
+            .is_some_and(|byte| *byte != 0);
+        if !legacy {
+            return Ok(framed_limited(reader, limits));
+        }
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .await
+            .map_err(|err| limit_error(err, limits))?;
+        csv_messages::<M>(data, schema)
+    })
+    .try_flatten()
+    .boxed()
+}
+
+fn csv_messages<M: FromCsv + 'static>(
+    data: Vec<u8>,
+    schema: Arc<CsvSchema>,
+) -> Result<BytesMutStream> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(schema.delimiter as u8)
+        .from_reader(std::io::Cursor::new(data));
+    let headers = reader.headers()?.clone();
+    let messages = reader.into_records().map(move |record| {
+        let record = record?;
+        let message = M::from_csv(&CsvRow {
+            schema: &schema,
+            headers: &headers,
+            record: &record,
+        })?;
+        Ok(BytesMut::from(message.encode_to_vec().as_slice()))
+    });
+    Ok(stream::iter(messages).boxed())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::{FileInfo, FileInfoStream, FileStore, Settings};
-    use std::str::FromStr;
+    use async_compression::tokio::write::GzipEncoder;
+    use helium_proto::services::poc_mobile::CellHeartbeatIngestReportV1;
+    use hex_literal::hex;
+    use prost::Message;
+    use std::{io::Cursor, str::FromStr};
+    use tokio::io::AsyncWriteExt;
+
+    const PK_BYTES: [u8; 33] =
+        hex!("008f23e96ab6bbff48c8923cac831dc97111bcf33dba9f5a8539c00f9d93551af1");
+
+    async fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzipEncoder::new(Vec::new());
+        encoder.write_all(data).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        encoder.into_inner()
+    }
+
+    async fn decode_heartbeats(
+        decoder: &LegacyDecoder<CellHeartbeatIngestReportV1>,
+        file: Vec<u8>,
+    ) -> Result<Vec<CellHeartbeatIngestReportV1>> {
+        decoder
+            .decode(Cursor::new(file))
+            .map(|message| Ok(CellHeartbeatIngestReportV1::decode(message?)?))
+            .try_collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn legacy_csv_rows_are_decoded_with_the_schema() {
+        let pubkey = PublicKeyBinary::from(PK_BYTES.to_vec());
+        let csv = format!(
+            "hotspot;received_timestamp;timestamp;cell_id;lat;lon;operation_mode;cbsd_id\n\
+             {pubkey};1690000000123;2023-07-22T04:26:40Z;7;1.5;-2.5;true;cbsd-1\n"
+        );
+        let decoder = LegacyDecoder::new(CsvSchema {
+            delimiter: ';',
+            columns: HashMap::from([("pub_key".to_string(), "hotspot".to_string())]),
+        })
+        .unwrap();
+
+        let heartbeats = decode_heartbeats(&decoder, gzip(csv.as_bytes()).await)
+            .await
+            .unwrap();
+        assert_eq!(heartbeats.len(), 1);
+        assert_eq!(heartbeats[0].received_timestamp, 1_690_000_000_123);
+        let report = heartbeats[0].report.as_ref().unwrap();
+        assert_eq!(report.pub_key, PK_BYTES.to_vec());
+        assert_eq!(report.timestamp, 1_690_000_000);
+        assert_eq!(report.cell_id, 7);
+        assert!(report.operation_mode);
+        assert_eq!(report.cbsd_id, "cbsd-1");
+        assert_eq!(report.hotspot_type, "");
+    }
+
+    #[tokio::test]
+    async fn framed_files_are_decoded_as_before() {
+        let heartbeat = CellHeartbeatIngestReportV1 {
+            received_timestamp: 1,
+            report: None,
+        };
+        let message = heartbeat.encode_to_vec();
+        let mut framed = (message.len() as u32).to_be_bytes().to_vec();
+        framed.extend(message);
+        let decoder = LegacyDecoder::new(CsvSchema::default()).unwrap();
+
+        let heartbeats = decode_heartbeats(&decoder, gzip(&framed).await)
+            .await
+            .unwrap();
+        assert_eq!(heartbeats, vec![heartbeat]);
+    }
+
+    #[tokio::test]
+    async fn any_decoder_reads_csv_within_the_given_limits() {
+        let pubkey = PublicKeyBinary::from(PK_BYTES.to_vec());
+        let csv = format!(
+            "pub_key,received_timestamp,timestamp,cell_id,lat,lon,operation_mode,cbsd_id\n\
+             {pubkey},1,1,7,1.5,-2.5,true,cbsd-1\n"
+        );
+        let file = gzip(csv.as_bytes()).await;
+        let decoder = AnyLegacyDecoder::from(
+            LegacyDecoder::<CellHeartbeatIngestReportV1>::new(CsvSchema::default()).unwrap(),
+        );
+
+        let messages: Vec<BytesMut> = decoder
+            .decode(Cursor::new(file.clone()), DecodeLimits::default())
+            .try_collect()
+            .await
+            .unwrap();
+        let heartbeat = CellHeartbeatIngestReportV1::decode(messages[0].clone()).unwrap();
+        assert_eq!(heartbeat.report.unwrap().cbsd_id, "cbsd-1");
+
+        let limits = DecodeLimits {
+            max_frame_length: 64,
+            max_file_length: 10,
+        };
+        let err = decoder
+            .decode(Cursor::new(file), limits)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Decode(DecodeError::FileTooLarge(10))));
+    }
+
+    #[tokio::test]
+    async fn legacy_rows_missing_a_column_fail() {
+        let decoder = LegacyDecoder::new(CsvSchema::default()).unwrap();
+        let csv = "received_timestamp,timestamp\n1,1\n";
+        assert!(decode_heartbeats(&decoder, gzip(csv.as_bytes()).await)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn files_exceeding_the_limits_fail() {
+        let message = CellHeartbeatIngestReportV1 {
+            received_timestamp: 1,
+            report: None,
+        }
+        .encode_to_vec();
+        let mut framed = Vec::new();
+        for _ in 0..10 {
+            framed.extend((message.len() as u32).to_be_bytes());
+            framed.extend(&message);
+        }
+        let file = gzip(&framed).await;
+        let decoder = |max_frame_length, max_file_length| {
+            LegacyDecoder::new(CsvSchema::default())
+                .unwrap()
+                .limits(DecodeLimits {
+                    max_frame_length,
+                    max_file_length,
+                })
+        };
+
+        let heartbeats = decode_heartbeats(&decoder(64, framed.len() as u64), file.clone())
+            .await
+            .unwrap();
+        assert_eq!(heartbeats.len(), 10);
+
+        let err = decode_heartbeats(&decoder(1, 1_000), file.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Decode(DecodeError::FrameTooLarge(1))));
+
+        let err = decode_heartbeats(&decoder(64, framed.len() as u64 - 1), file)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Decode(DecodeError::FileTooLarge(_))));
+
+        let csv = "received_timestamp,timestamp\n1,1\n";
+        let err = decode_heartbeats(&decoder(64, 10), gzip(csv.as_bytes()).await)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Decode(DecodeError::FileTooLarge(10))));
+    }
+
+    #[tokio::test]
+    async fn local_files_are_read_within_the_given_limits() {
+        let message = CellHeartbeatIngestReportV1 {
+            received_timestamp: 1,
+            report: None,
+        }
+        .encode_to_vec();
+        let mut framed = (message.len() as u32).to_be_bytes().to_vec();
+        framed.extend(&message);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cell_heartbeat.1658832527866.gz");
+        tokio::fs::write(&path, gzip(&framed).await).await.unwrap();
+
+        let frames: Vec<BytesMut> = source([&path]).try_collect().await.unwrap();
+        assert_eq!(frames, vec![BytesMut::from(message.as_slice())]);
+
+        let limits = DecodeLimits {
+            max_frame_length: 1,
+            max_file_length: 1_000,
+        };
+        let err = source_with_limits([&path], limits)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Decode(DecodeError::FrameTooLarge(1))));
+    }
 
     fn infos(names: &'static [&str]) -> FileInfoStream {
         futures::stream::iter(names.iter().map(|v| FileInfo::from_str(v))).boxed()
@@ -70,8 +614,14 @@ mod test {
             bucket: "devnet-poc5g-rewards".to_string(),
             endpoint: None,
             region: "us-east-1".to_string(),
+            prefix: None,
             access_key_id: None,
             secret_access_key: None,
+            secondary: None,
+            failover_threshold: 3,
+            failover_replay_period: 60,
+            upload: Default::default(),
+            limits: Default::default(),
         };
 
         let file_store = FileStore::from_settings(&settings)
@@ -1,19 +1,73 @@
 use crate::{
-    error::DecodeError, BytesMutStream, Error, FileInfo, FileInfoStream, FileType, Result, Settings,
+    error::DecodeError,
+    file_source::{self, AnyLegacyDecoder, FromCsv, LegacyDecoder},
+    BytesMutStream, DecodeLimits, Error, FileInfo, FileInfoStream, FileType, Result, Settings,
 };
 use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_s3::{types::ByteStream, Client, Endpoint, Region};
+use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use futures::FutureExt;
-use futures::{stream, StreamExt, TryFutureExt, TryStreamExt};
+use futures::{stream, Future, StreamExt, TryFutureExt, TryStreamExt};
 use http::Uri;
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+/// Prefix, under the prefix of the store, of the objects that failed to
+/// decode within the limits
+pub const QUARANTINE_PREFIX: &str = "quarantine/";
 
 #[derive(Debug, Clone)]
 pub struct FileStore {
     pub(crate) bucket: String,
+    /// Prefix of every key in the bucket, empty or ending in a slash
+    prefix: String,
     client: Client,
+    failover: Option<Arc<Failover>>,
+    limits: DecodeLimits,
+}
+
+/// The secondary bucket of a store and whether writes currently go to it
+#[derive(Debug)]
+struct Failover {
+    secondary: FileStore,
+    state: FailoverState,
+    replay_period: Duration,
+}
+
+#[derive(Debug)]
+struct FailoverState {
+    threshold: u32,
+    failures: AtomicU32,
+}
+
+impl FailoverState {
+    fn new(threshold: u32) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            failures: AtomicU32::new(0),
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.failures.load(Ordering::SeqCst) >= self.threshold
+    }
+
+    /// Record a failed write to the primary, returning whether writes now
+    /// fail over
+    fn failed(&self) -> bool {
+        self.failures.fetch_add(1, Ordering::SeqCst) + 1 >= self.threshold
+    }
+
+    fn recovered(&self) {
+        self.failures.store(0, Ordering::SeqCst);
+    }
 }
 
 pub struct FileData {
@@ -23,40 +77,34 @@ pub struct FileData {
 
 impl FileStore {
     pub async fn from_settings(settings: &Settings) -> Result<Self> {
-        let endpoint: Option<Endpoint> = match &settings.endpoint {
-            Some(endpoint) => Uri::from_str(endpoint)
-                .map(Endpoint::immutable)
-                .map(Some)
-                .map_err(DecodeError::from)?,
-            _ => None,
+        let failover = match &settings.secondary {
+            Some(secondary) => Some(Arc::new(Failover {
+                secondary: Self {
+                    client: client(secondary).await?,
+                    bucket: secondary.bucket.clone(),
+                    prefix: key_prefix(secondary.prefix.as_ref().or(settings.prefix.as_ref())),
+                    failover: None,
+                    limits: settings.limits,
+                },
+                state: FailoverState::new(settings.failover_threshold),
+                replay_period: Duration::from_secs(settings.failover_replay_period),
+            })),
+            None => None,
         };
-        let region = Region::new(settings.region.clone());
-        let region_provider = RegionProviderChain::first_try(region).or_default_provider();
-
-        let mut config = aws_config::from_env().region(region_provider);
-        if let Some(endpoint) = endpoint {
-            config = config.endpoint_resolver(endpoint);
-        }
-
-        #[cfg(feature = "local")]
-        if settings.access_key_id.is_some() && settings.secret_access_key.is_some() {
-            let creds = aws_types::credentials::Credentials::from_keys(
-                settings.access_key_id.as_ref().unwrap(),
-                settings.secret_access_key.as_ref().unwrap(),
-                None,
-            );
-            config = config.credentials_provider(creds);
-        }
-
-        let config = config.load().await;
-
-        let client = Client::new(&config);
         Ok(Self {
-            client,
+            client: client(settings).await?,
             bucket: settings.bucket.clone(),
+            prefix: key_prefix(settings.prefix.as_ref()),
+            failover,
+            limits: settings.limits,
         })
     }
 
+    /// The key of the object in the bucket, under the prefix of the store
+    fn prefixed(&self, key: impl Into<String>) -> String {
+        format!("{}{}", self.prefix, key.into())
+    }
+
     pub async fn list_all<A, B, F>(
         &self,
         file_type: F,
@@ -85,8 +133,9 @@ impl FileStore {
             .client
             .list_objects_v2()
             .bucket(&self.bucket)
-            .prefix(file_type.to_string())
-            .set_start_after(after.map(|dt| FileInfo::from((file_type, dt)).into()));
+            .prefix(self.prefixed(file_type.to_string()))
+            .set_start_after(after.map(|dt| self.prefixed(FileInfo::from((file_type, dt)))));
+        let prefix = self.prefix.clone();
 
         futures::stream::unfold(
             (request, true, None),
@@ -109,17 +158,12 @@ impl FileStore {
         )
         .flat_map(move |entry| match entry {
             Ok(output) => {
+                let prefix = prefix.clone();
                 let filtered = output
                     .contents
                     .unwrap_or_default()
                     .into_iter()
-                    .filter_map(|obj| {
-                        if FileInfo::matches(obj.key().unwrap_or_default()) {
-                            Some(FileInfo::try_from(&obj).unwrap())
-                        } else {
-                            None
-                        }
-                    })
+                    .filter_map(move |obj| file_info(&prefix, &obj))
                     .filter(move |info| after.map_or(true, |v| info.timestamp > v))
                     .filter(move |info| before.map_or(true, |v| info.timestamp <= v))
                     .map(Ok);
@@ -131,16 +175,143 @@ impl FileStore {
     }
 
     pub async fn put(&self, file: &Path) -> Result {
+        self.with_failover(|store| store.put_path(file)).await
+    }
+
+    /// Store `data` under the given key, replacing any existing object
+    pub async fn put_bytes(&self, key: &str, data: Vec<u8>) -> Result {
+        let data = Bytes::from(data);
+        self.with_failover(|store| store.put_object(key, ByteStream::from(data.clone())))
+            .await
+    }
+
+    /// Run a write against this store or, once `failover_threshold` writes
+    /// in a row have failed, against the secondary store
+    async fn with_failover<'a, F, Fut>(&'a self, write: F) -> Result
+    where
+        F: Fn(&'a FileStore) -> Fut,
+        Fut: Future<Output = Result> + 'a,
+    {
+        let Some(failover) = self.failover.as_deref() else {
+            return write(self).await;
+        };
+        if !failover.state.active() {
+            let err = match write(self).await {
+                Ok(()) => {
+                    failover.state.recovered();
+                    return Ok(());
+                }
+                Err(err) => err,
+            };
+            if !failover.state.failed() {
+                return Err(err);
+            }
+            tracing::warn!(
+                primary = %self.bucket,
+                secondary = %failover.secondary.bucket,
+                "failing over writes to secondary bucket: {err}"
+            );
+            metrics::gauge!("file_store_failover_active", 1.0, "bucket" => self.bucket.clone());
+        }
+        metrics::increment_counter!("file_store_failover_writes", "bucket" => self.bucket.clone());
+        write(&failover.secondary).await
+    }
+
+    async fn put_path(&self, file: &Path) -> Result {
         let byte_stream = ByteStream::from_path(&file)
             .await
             .map_err(|_| Error::not_found(format!("could not open {}", file.display())))?;
+        let key = file.file_name().map(|name| name.to_string_lossy()).unwrap();
+        self.put_object(&key, byte_stream).await
+    }
+
+    async fn put_object(&self, key: &str, body: ByteStream) -> Result {
+        poc_metrics::record_duration!(
+            "file_store_put_duration",
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(self.prefixed(key))
+                .body(body)
+                .send()
+                .map_ok(|_| ())
+                .map_err(Error::s3_error)
+                .await
+        )
+    }
+
+    /// Copy the objects written to the secondary store back to this one every
+    /// `failover_replay_period` until shutdown. Without a secondary store
+    /// this just waits for shutdown.
+    pub async fn replay_failover(&self, shutdown: triggered::Listener) {
+        let Some(failover) = self.failover.as_deref() else {
+            return shutdown.await;
+        };
+        let mut trigger = tokio::time::interval(failover.replay_period);
+        trigger.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => return,
+                _ = trigger.tick() => match self.replay(failover).await {
+                    Ok(0) => (),
+                    Ok(replayed) => tracing::info!(
+                        bucket = %self.bucket,
+                        replayed,
+                        "replayed objects from secondary bucket"
+                    ),
+                    Err(err) => tracing::debug!(
+                        bucket = %self.bucket,
+                        "primary bucket not recovered yet: {err}"
+                    ),
+                },
+            }
+        }
+    }
+
+    async fn replay(&self, failover: &Failover) -> Result<usize> {
+        if failover.state.active() {
+            self.client
+                .head_bucket()
+                .bucket(&self.bucket)
+                .send()
+                .await
+                .map_err(Error::s3_error)?;
+            failover.state.recovered();
+            metrics::gauge!("file_store_failover_active", 0.0, "bucket" => self.bucket.clone());
+            tracing::info!(bucket = %self.bucket, "primary bucket recovered");
+        }
+        let keys = failover.secondary.list_keys().await?;
+        let replayed_at = Utc::now();
+        for (index, key) in keys.iter().enumerate() {
+            // Every replayed file is named a millisecond after the previous
+            // one, keeping the order they were written in
+            let replayed_at = replayed_at + chrono::Duration::milliseconds(index as i64);
+            self.copy_from(&failover.secondary, key, &replayed_key(key, replayed_at))
+                .await?;
+            failover.secondary.remove(key).await?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Stream the object with the given key in the source store to this one
+    /// under `target_key`
+    async fn copy_from(&self, source: &FileStore, key: &str, target_key: &str) -> Result {
+        let object = source
+            .client
+            .get_object()
+            .bucket(&source.bucket)
+            .key(source.prefixed(key))
+            .send()
+            .await
+            .map_err(Error::s3_error)?;
         poc_metrics::record_duration!(
             "file_store_put_duration",
             self.client
                 .put_object()
                 .bucket(&self.bucket)
-                .key(file.file_name().map(|name| name.to_string_lossy()).unwrap())
-                .body(byte_stream)
+                .key(self.prefixed(target_key))
+                .content_length(object.content_length)
+                .body(object.body)
                 .send()
                 .map_ok(|_| ())
                 .map_err(Error::s3_error)
@@ -148,13 +319,59 @@ impl FileStore {
         )
     }
 
+    /// Keys of all objects under the prefix of the store, without the prefix
+    async fn list_keys(&self) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut next = None;
+        loop {
+            let output = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&self.prefix)
+                .set_continuation_token(next)
+                .send()
+                .await
+                .map_err(Error::s3_error)?;
+            keys.extend(
+                output
+                    .contents()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|object| object.key()?.strip_prefix(self.prefix.as_str()))
+                    .map(str::to_string),
+            );
+            match output.next_continuation_token() {
+                Some(token) => next = Some(token.to_string()),
+                None => return Ok(keys),
+            }
+        }
+    }
+
     pub async fn remove(&self, key: &str) -> Result {
         poc_metrics::record_duration!(
             "file_store_remove_duration",
             self.client
                 .delete_object()
                 .bucket(&self.bucket)
-                .key(key)
+                .key(self.prefixed(key))
+                .send()
+                .map_ok(|_| ())
+                .map_err(Error::s3_error)
+                .await
+        )
+    }
+
+    /// Copy the object with the given key under the `quarantine/` prefix of
+    /// the store, where it is kept for inspection but never listed as a file
+    pub async fn quarantine(&self, key: &str) -> Result {
+        poc_metrics::record_duration!(
+            "file_store_copy_duration",
+            self.client
+                .copy_object()
+                .bucket(&self.bucket)
+                .copy_source(format!("{}/{}", self.bucket, self.prefixed(key)))
+                .key(self.prefixed(format!("{QUARANTINE_PREFIX}{key}")))
                 .send()
                 .map_ok(|_| ())
                 .map_err(Error::s3_error)
@@ -166,26 +383,69 @@ impl FileStore {
     where
         K: Into<String>,
     {
-        get_byte_stream(self.client.clone(), self.bucket.clone(), key).await
+        get_byte_stream(self.client.clone(), self.bucket.clone(), self.prefixed(key)).await
+    }
+
+    /// The raw content of the object with the given key
+    pub async fn get_bytes<K>(&self, key: K) -> Result<Vec<u8>>
+    where
+        K: Into<String>,
+    {
+        let mut data = Vec::new();
+        tokio_util::io::StreamReader::new(self.get_raw(key).await?)
+            .read_to_end(&mut data)
+            .await?;
+        Ok(data)
     }
 
     pub async fn get<K>(&self, key: K) -> Result<BytesMutStream>
     where
         K: Into<String>,
     {
-        Ok(stream_source(self.get_raw(key).await?))
+        Ok(stream_source(self.get_raw(key).await?, self.limits))
     }
 
     /// Stream a series of ordered items from the store from remote files with
     /// the given keys.
     pub fn source(&self, infos: FileInfoStream) -> BytesMutStream {
-        let bucket = self.bucket.clone();
-        let client = self.client.clone();
+        let store = self.clone();
+        let limits = self.limits;
+        infos
+            .map_ok(move |info| {
+                get_byte_stream(
+                    store.client.clone(),
+                    store.bucket.clone(),
+                    store.prefixed(info),
+                )
+            })
+            .try_buffered(2)
+            .flat_map(move |stream| match stream {
+                Ok(stream) => stream_source(stream, limits),
+                Err(err) => stream::once(async move { Err(err) }).boxed(),
+            })
+            .fuse()
+            .boxed()
+    }
+
+    /// Like [`source`](Self::source), reading the legacy csv exports among
+    /// the files with the decoder
+    pub fn legacy_source<M: FromCsv + 'static>(
+        &self,
+        infos: FileInfoStream,
+        decoder: LegacyDecoder<M>,
+    ) -> BytesMutStream {
+        let store = self.clone();
         infos
-            .map_ok(move |info| get_byte_stream(client.clone(), bucket.clone(), info.key))
+            .map_ok(move |info| {
+                get_byte_stream(
+                    store.client.clone(),
+                    store.bucket.clone(),
+                    store.prefixed(info),
+                )
+            })
             .try_buffered(2)
-            .flat_map(|stream| match stream {
-                Ok(stream) => stream_source(stream),
+            .flat_map(move |stream| match stream {
+                Ok(stream) => decoder.decode(tokio_util::io::StreamReader::new(stream)),
                 Err(err) => stream::once(async move { Err(err) }).boxed(),
             })
             .fuse()
@@ -197,13 +457,19 @@ impl FileStore {
     /// stream of buffers to be produced as soon as available from up to
     /// "worker" number of remote files
     pub fn source_unordered(&self, workers: usize, infos: FileInfoStream) -> BytesMutStream {
-        let bucket = self.bucket.clone();
-        let client = self.client.clone();
+        let store = self.clone();
+        let limits = self.limits;
         infos
-            .map_ok(move |info| get_byte_stream(client.clone(), bucket.clone(), info.key))
+            .map_ok(move |info| {
+                get_byte_stream(
+                    store.client.clone(),
+                    store.bucket.clone(),
+                    store.prefixed(info),
+                )
+            })
             .try_buffer_unordered(workers)
-            .flat_map(|stream| match stream {
-                Ok(stream) => stream_source(stream),
+            .flat_map(move |stream| match stream {
+                Ok(stream) => stream_source(stream, limits),
                 Err(err) => stream::once(async move { Err(err) }).boxed(),
             })
             .fuse()
@@ -211,26 +477,58 @@ impl FileStore {
     }
 
     pub async fn stream_file(&self, file_info: FileInfo) -> Result<BytesMutStream> {
-        get_byte_stream(self.client.clone(), self.bucket.clone(), file_info)
-            .await
-            .map(stream_source)
+        get_byte_stream(
+            self.client.clone(),
+            self.bucket.clone(),
+            self.prefixed(file_info),
+        )
+        .await
+        .map(|stream| stream_source(stream, self.limits))
+    }
+
+    /// Like [`stream_file`](Self::stream_file), reading a legacy csv export
+    /// with the decoder
+    pub(crate) async fn stream_legacy_file(
+        &self,
+        file_info: FileInfo,
+        decoder: &AnyLegacyDecoder,
+    ) -> Result<BytesMutStream> {
+        get_byte_stream(
+            self.client.clone(),
+            self.bucket.clone(),
+            self.prefixed(file_info),
+        )
+        .await
+        .map(|stream| decoder.decode(tokio_util::io::StreamReader::new(stream), self.limits))
     }
 }
 
-fn stream_source(stream: ByteStream) -> BytesMutStream {
+fn stream_source(stream: ByteStream, limits: DecodeLimits) -> BytesMutStream {
     use async_compression::tokio::bufread::GzipDecoder;
-    use tokio_util::{
-        codec::{length_delimited::LengthDelimitedCodec, FramedRead},
-        io::StreamReader,
-    };
+    use tokio_util::io::StreamReader;
 
-    Box::pin(
-        FramedRead::new(
-            GzipDecoder::new(StreamReader::new(stream)),
-            LengthDelimitedCodec::new(),
-        )
-        .map_err(Error::from),
-    )
+    file_source::framed(GzipDecoder::new(StreamReader::new(stream)), limits)
+}
+
+/// The prefix of the keys of a store, normalized to end in a single slash
+fn key_prefix(prefix: Option<&String>) -> String {
+    match prefix.map(|prefix| prefix.trim_matches('/')) {
+        Some(prefix) if !prefix.is_empty() => format!("{prefix}/"),
+        _ => String::new(),
+    }
+}
+
+/// The info of a file stored under the prefix, with the key of the info
+/// relative to the prefix. Objects outside the prefix or not named like files
+/// are skipped
+fn file_info(prefix: &str, object: &aws_sdk_s3::model::Object) -> Option<FileInfo> {
+    let key = object.key()?.strip_prefix(prefix)?;
+    if !FileInfo::matches(key) {
+        return None;
+    }
+    let mut info = FileInfo::from_str(key).ok()?;
+    info.size = object.size() as usize;
+    Some(info)
 }
 
 async fn get_byte_stream<K>(client: Client, bucket: String, key: K) -> Result<ByteStream>
@@ -247,3 +545,127 @@ where
         .fuse()
         .await
 }
+
+async fn client(settings: &Settings) -> Result<Client> {
+    let endpoint: Option<Endpoint> = match &settings.endpoint {
+        Some(endpoint) => Uri::from_str(endpoint)
+            .map(Endpoint::immutable)
+            .map(Some)
+            .map_err(DecodeError::from)?,
+        _ => None,
+    };
+    let region = Region::new(settings.region.clone());
+    let region_provider = RegionProviderChain::first_try(region).or_default_provider();
+
+    let mut config = aws_config::from_env().region(region_provider);
+    if let Some(endpoint) = endpoint {
+        config = config.endpoint_resolver(endpoint);
+    }
+
+    #[cfg(feature = "local")]
+    if settings.access_key_id.is_some() && settings.secret_access_key.is_some() {
+        let creds = aws_types::credentials::Credentials::from_keys(
+            settings.access_key_id.as_ref().unwrap(),
+            settings.secret_access_key.as_ref().unwrap(),
+            None,
+        );
+        config = config.credentials_provider(creds);
+    }
+
+    let config = config.load().await;
+
+    Ok(Client::new(&config))
+}
+
+/// Key an object of the secondary store is replayed under. Pollers only list
+/// the files after the latest one they processed, so files are named by the
+/// time they are replayed to be consumed. Other objects keep their key
+fn replayed_key(key: &str, replayed_at: DateTime<Utc>) -> String {
+    match FileInfo::from_str(key) {
+        Ok(info) if FileInfo::from((info.file_type, info.timestamp)).key == key => {
+            FileInfo::from((info.file_type, replayed_at)).key
+        }
+        _ => key.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn writes_fail_over_after_consecutive_failures() {
+        let state = FailoverState::new(3);
+        assert!(!state.failed());
+        assert!(!state.failed());
+        assert!(!state.active());
+
+        // A successful write starts the count again
+        state.recovered();
+        assert!(!state.failed());
+        assert!(!state.failed());
+        assert!(state.failed());
+        assert!(state.active());
+
+        state.recovered();
+        assert!(!state.active());
+    }
+
+    #[test]
+    fn replayed_files_are_named_by_the_replay_time() {
+        let replayed_at = Utc.timestamp_millis_opt(1690000000000).unwrap();
+        assert_eq!(
+            replayed_key("iot_valid_packet.1658832527866.gz", replayed_at),
+            "iot_valid_packet.1690000000000.gz"
+        );
+        let info = FileInfo::from_str(&replayed_key(
+            "iot_beacon_ingest_report.1658832527866.gz",
+            replayed_at,
+        ))
+        .expect("file info");
+        assert_eq!(info.file_type, FileType::IotBeaconIngestReport);
+        assert_eq!(info.timestamp, replayed_at);
+
+        // objects that are not files keep their key
+        for key in [
+            "_latest/iot_valid_packet",
+            "iot_valid_packet/latest",
+            "quarantine/iot_valid_packet.1658832527866.gz",
+            "readme",
+        ] {
+            assert_eq!(replayed_key(key, replayed_at), key);
+        }
+    }
+
+    #[test]
+    fn files_are_listed_relative_to_the_prefix() {
+        assert_eq!(key_prefix(None), "");
+        assert_eq!(key_prefix(Some(&"/".to_string())), "");
+        assert_eq!(key_prefix(Some(&"mainnet/".to_string())), "mainnet/");
+        let prefix = key_prefix(Some(&"staging2".to_string()));
+
+        let object = |key: &str| {
+            aws_sdk_s3::model::Object::builder()
+                .key(key)
+                .size(10)
+                .build()
+        };
+        let info = file_info(
+            &prefix,
+            &object("staging2/iot_valid_packet.1658832527866.gz"),
+        )
+        .expect("file info");
+        assert_eq!(info.key, "iot_valid_packet.1658832527866.gz");
+        assert_eq!(info.file_type, FileType::IotValidPacket);
+        assert_eq!(info.timestamp.timestamp_millis(), 1658832527866);
+        assert_eq!(info.size, 10);
+
+        assert!(file_info(
+            &prefix,
+            &object("mainnet/iot_valid_packet.1658832527866.gz")
+        )
+        .is_none());
+        assert!(file_info(&prefix, &object("staging2/readme")).is_none());
+    }
+}
@@ -1,12 +1,35 @@
-use crate::{Error, FileStore, Result, Settings};
+use crate::{
+    upload_schedule::UploadSchedule, Error, FileInfo, FileStore, FileType, Result, Settings,
+};
+use async_trait::async_trait;
+use chrono::Utc;
 use futures::StreamExt;
 use std::{
+    collections::HashMap,
     path::{Path, PathBuf},
-    time::Duration,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs,
+    sync::{mpsc, Semaphore},
+    time,
 };
-use tokio::{fs, sync::mpsc, time};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
+const SPOOLED_BYTES_METRIC: &str = "file_upload_spooled_bytes";
+
+/// Files uploaded at the same time
+const MAX_CONCURRENT_UPLOADS: usize = 5;
+
+/// Longest wait between checks of whether a window opened or the spooled
+/// files outgrew `max_spooled_bytes`
+const WINDOW_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
 pub type MessageSender = mpsc::UnboundedSender<PathBuf>;
 pub type MessageReceiver = mpsc::UnboundedReceiver<PathBuf>;
 
@@ -18,68 +41,217 @@ pub async fn upload_file(tx: &MessageSender, file: &Path) -> Result {
     tx.send(file.to_path_buf()).map_err(|_| Error::channel())
 }
 
+/// Run once a file was stored in the bucket, in a task of its own so a slow
+/// hook never holds up the uploads
+#[async_trait]
+pub trait UploadHook: Send + Sync + 'static {
+    async fn uploaded(&self, store: &FileStore, file_info: FileInfo);
+}
+
+/// Hooks of the uploaded files keyed by their file type
+pub type UploadHooks = HashMap<FileType, Arc<dyn UploadHook>>;
+
+/// Paces uploads to a number of bytes per second. Files are sent at full
+/// speed, each starting once the bytes of the files before it are paid for
+#[derive(Debug)]
+struct Bandwidth {
+    bytes_per_sec: u64,
+    next_free: Mutex<Option<Instant>>,
+}
+
+impl Bandwidth {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            next_free: Mutex::new(None),
+        }
+    }
+
+    /// When an upload of `bytes` requested at `now` may start
+    fn reserve(&self, bytes: u64, now: Instant) -> Instant {
+        let mut next_free = self.next_free.lock().expect("upload bandwidth poisoned");
+        let start = next_free.map_or(now, |next_free| next_free.max(now));
+        *next_free =
+            Some(start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64));
+        start
+    }
+
+    async fn acquire(&self, bytes: u64) {
+        let start = self.reserve(bytes, Instant::now());
+        time::sleep_until(time::Instant::from_std(start)).await;
+    }
+}
+
+/// Bytes of the files waiting for a window or being uploaded
+#[derive(Debug)]
+struct Spool {
+    bytes: AtomicU64,
+    max_bytes: Option<u64>,
+}
+
+impl Spool {
+    fn add(self: &Arc<Self>, bytes: u64) -> Spooled {
+        let total = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        metrics::gauge!(SPOOLED_BYTES_METRIC, total as f64);
+        Spooled {
+            spool: self.clone(),
+            bytes,
+        }
+    }
+
+    fn overflowing(&self) -> bool {
+        self.max_bytes
+            .is_some_and(|max_bytes| self.bytes.load(Ordering::Relaxed) > max_bytes)
+    }
+}
+
+/// A spooled file, taken off the spool when dropped
+struct Spooled {
+    spool: Arc<Spool>,
+    bytes: u64,
+}
+
+impl Drop for Spooled {
+    fn drop(&mut self) {
+        let total = self.spool.bytes.fetch_sub(self.bytes, Ordering::Relaxed) - self.bytes;
+        metrics::gauge!(SPOOLED_BYTES_METRIC, total as f64);
+    }
+}
+
 pub struct FileUpload {
     messages: UnboundedReceiverStream<PathBuf>,
     store: FileStore,
+    hooks: Arc<UploadHooks>,
+    schedule: Arc<UploadSchedule>,
+    bandwidth: Option<Arc<Bandwidth>>,
+    spool: Arc<Spool>,
 }
 
 impl FileUpload {
     pub async fn from_settings(settings: &Settings, messages: MessageReceiver) -> Result<Self> {
+        let upload = &settings.upload;
+        if upload.max_bytes_per_sec == Some(0) {
+            return Err(Error::Config(config::ConfigError::Message(
+                "upload.max_bytes_per_sec must be at least 1".to_string(),
+            )));
+        }
         Ok(Self {
             messages: UnboundedReceiverStream::new(messages),
             store: FileStore::from_settings(settings).await?,
+            hooks: Arc::default(),
+            schedule: Arc::new(UploadSchedule::new(&upload.windows)?),
+            bandwidth: upload
+                .max_bytes_per_sec
+                .map(|bytes_per_sec| Arc::new(Bandwidth::new(bytes_per_sec))),
+            spool: Arc::new(Spool {
+                bytes: AtomicU64::new(0),
+                max_bytes: upload.max_spooled_bytes,
+            }),
         })
     }
+
+    /// Run the hook for every uploaded file of the file type
+    pub fn hook(mut self, file_type: FileType, hook: Arc<dyn UploadHook>) -> Self {
+        Arc::make_mut(&mut self.hooks).insert(file_type, hook);
+        self
+    }
+
     pub async fn run(self, shutdown: &triggered::Listener) -> Result {
         tracing::info!("starting file uploader 1");
 
+        // Copies files written to the secondary bucket of the store, if any,
+        // back once the primary bucket has recovered
+        let replay = self.store.replay_failover(shutdown.clone());
+        // Every file deposited is spooled right away, so files waiting for a
+        // window count towards max_spooled_bytes, but only a few are uploaded
+        // at the same time. Files still waiting at shutdown stay on disk and
+        // are deposited again by their sink on startup
+        let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_UPLOADS));
         let uploads = self
             .messages
-            .map(|msg| (self.store.clone(), msg))
-            .for_each_concurrent(5, |(store, path)| async move {
-                let path_str = path.display();
-                let bucket = &store.bucket;
-                if !path.exists() {
-                    tracing::warn!("ignoring absent file {path_str}");
-                    return;
-                }
-                if !path.is_file() {
-                    tracing::warn!("ignoring non file {path_str}");
-                    return;
-                }
-                let mut retry = 0;
-                const MAX_RETRIES: u8 = 5;
-                const RETRY_WAIT: Duration = Duration::from_secs(10);
-                tracing::info!("starting file uploader 2");
-                while retry <= MAX_RETRIES {
-                    tracing::debug!("storing {path_str} in {bucket} retry {retry}");
-                    match store.put(&path).await {
-                        Ok(()) => {
-                            match fs::remove_file(&path).await {
-                                Ok(()) => {
-                                    tracing::info!("stored {path_str} in {bucket}");
+            .map(|msg| {
+                (
+                    self.store.clone(),
+                    self.hooks.clone(),
+                    self.schedule.clone(),
+                    self.bandwidth.clone(),
+                    self.spool.clone(),
+                    permits.clone(),
+                    msg,
+                )
+            })
+            .for_each_concurrent(
+                None,
+                |(store, hooks, schedule, bandwidth, spool, permits, path)| async move {
+                    let path_str = path.display();
+                    let bucket = &store.bucket;
+                    if !path.exists() {
+                        tracing::warn!("ignoring absent file {path_str}");
+                        return;
+                    }
+                    if !path.is_file() {
+                        tracing::warn!("ignoring non file {path_str}");
+                        return;
+                    }
+                    let size = fs::metadata(&path)
+                        .await
+                        .map(|metadata| metadata.len())
+                        .unwrap_or_default();
+                    let _spooled = spool.add(size);
+                    wait_for_window(&schedule, &spool, &path).await;
+                    let Ok(_permit) = permits.acquire().await else {
+                        return;
+                    };
+
+                    let mut retry = 0;
+                    const MAX_RETRIES: u8 = 5;
+                    const RETRY_WAIT: Duration = Duration::from_secs(10);
+                    tracing::info!("starting file uploader 2");
+                    while retry <= MAX_RETRIES {
+                        if let Some(bandwidth) = &bandwidth {
+                            bandwidth.acquire(size).await;
+                        }
+                        tracing::debug!("storing {path_str} in {bucket} retry {retry}");
+                        match store.put(&path).await {
+                            Ok(()) => {
+                                match fs::remove_file(&path).await {
+                                    Ok(()) => {
+                                        tracing::info!("stored {path_str} in {bucket}");
+                                    }
+                                    Err(err) => {
+                                        tracing::error!(
+                                            "failed to remove uploaded file {path_str}: {err:?}"
+                                        );
+                                    }
                                 }
-                                Err(err) => {
-                                    tracing::error!(
-                                        "failed to remove uploaded file {path_str}: {err:?}"
-                                    );
+                                let file_info = path.file_name().and_then(|name| {
+                                    FileInfo::from_str(&name.to_string_lossy()).ok()
+                                });
+                                if let Some(file_info) = file_info {
+                                    if let Some(hook) = hooks.get(&file_info.file_type).cloned() {
+                                        let store = store.clone();
+                                        tokio::spawn(async move {
+                                            hook.uploaded(&store, file_info).await
+                                        });
+                                    }
                                 }
+                                return;
+                            }
+                            Err(err) => {
+                                tracing::error!(
+                                    "failed to store {path_str} in {bucket} retry: {retry}: {err:?}"
+                                );
+                                retry += 1;
+                                time::sleep(RETRY_WAIT).await;
                             }
-                            return;
-                        }
-                        Err(err) => {
-                            tracing::error!(
-                                "failed to store {path_str} in {bucket} retry: {retry}: {err:?}"
-                            );
-                            retry += 1;
-                            time::sleep(RETRY_WAIT).await;
                         }
                     }
-                }
-            });
+                },
+            );
 
         tokio::select! {
             _ = uploads => (),
+            _ = replay => (),
             _ = shutdown.clone() => (),
         }
 
@@ -87,3 +259,70 @@ impl FileUpload {
         Ok(())
     }
 }
+
+/// Wait until the schedule opens, or until the spooled files outgrow the
+/// spool so they are uploaded regardless
+async fn wait_for_window(schedule: &UploadSchedule, spool: &Spool, path: &Path) {
+    let mut waiting = false;
+    loop {
+        let now = Utc::now();
+        if schedule.is_open(now) {
+            return;
+        }
+        if spool.overflowing() {
+            tracing::warn!(
+                "uploading {} outside of the upload windows, spooled files exceed max_spooled_bytes",
+                path.display()
+            );
+            return;
+        }
+        if !waiting {
+            tracing::debug!("{} waiting for an upload window", path.display());
+            waiting = true;
+        }
+        let wait = schedule
+            .next_open(now)
+            .and_then(|open| (open - now).to_std().ok())
+            .map_or(WINDOW_CHECK_INTERVAL, |wait| {
+                wait.min(WINDOW_CHECK_INTERVAL)
+            });
+        time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_are_paced_to_the_bandwidth() {
+        let bandwidth = Bandwidth::new(1000);
+        let now = Instant::now();
+
+        assert_eq!(bandwidth.reserve(2000, now), now);
+        assert_eq!(bandwidth.reserve(500, now), now + Duration::from_secs(2));
+        assert_eq!(
+            bandwidth.reserve(500, now + Duration::from_secs(1)),
+            now + Duration::from_millis(2500)
+        );
+        // Idle time is not saved up
+        let later = now + Duration::from_secs(10);
+        assert_eq!(bandwidth.reserve(1000, later), later);
+    }
+
+    #[test]
+    fn spooled_files_are_counted_until_dropped() {
+        let spool = Arc::new(Spool {
+            bytes: AtomicU64::new(0),
+            max_bytes: Some(100),
+        });
+        let first = spool.add(60);
+        assert!(!spool.overflowing());
+        let second = spool.add(60);
+        assert!(spool.overflowing());
+        drop(first);
+        assert!(!spool.overflowing());
+        drop(second);
+        assert_eq!(spool.bytes.load(Ordering::Relaxed), 0);
+    }
+}
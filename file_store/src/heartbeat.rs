@@ -1,4 +1,5 @@
 use crate::{
+    file_source::{CsvRow, FromCsv},
     traits::{MsgDecode, MsgTimestamp, TimestampDecode},
     Error, Result,
 };
@@ -76,6 +77,28 @@ impl MsgTimestamp<Result<DateTime<Utc>>> for CellHeartbeatIngestReportV1 {
     }
 }
 
+/// Legacy exports hold the received time in milliseconds and the heartbeat
+/// time in seconds
+impl FromCsv for CellHeartbeatIngestReportV1 {
+    fn from_csv(row: &CsvRow) -> Result<Self> {
+        Ok(Self {
+            received_timestamp: row.timestamp_millis("received_timestamp")?,
+            report: Some(CellHeartbeatReqV1 {
+                pub_key: row.public_key("pub_key")?,
+                hotspot_type: row.get_or_default("hotspot_type")?,
+                cell_id: row.get("cell_id")?,
+                timestamp: row.timestamp_secs("timestamp")?,
+                lat: row.get("lat")?,
+                lon: row.get("lon")?,
+                operation_mode: row.get("operation_mode")?,
+                cbsd_category: row.get_or_default("cbsd_category")?,
+                cbsd_id: row.get("cbsd_id")?,
+                ..Default::default()
+            }),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,9 +1,11 @@
+pub mod canary;
 pub mod cli;
 pub mod entropy_report;
 mod error;
 mod file_info;
 pub mod file_info_poller;
 pub mod file_sink;
+pub mod file_sink_group;
 pub mod file_source;
 pub mod file_store;
 pub mod file_upload;
@@ -13,20 +15,28 @@ pub mod iot_invalid_poc;
 pub mod iot_packet;
 pub mod iot_valid_poc;
 pub mod iot_witness_report;
+pub mod message_bus;
 pub mod mobile_session;
 pub mod mobile_subscriber;
 pub mod mobile_transfer;
+#[cfg(feature = "parquet")]
+pub mod parquet_export;
 pub mod reward_manifest;
 mod settings;
+mod sink_overflow;
 pub mod speedtest;
 pub mod traits;
+pub mod upload_schedule;
 
 pub use crate::file_store::FileStore;
-pub use error::{Error, Result};
+pub use error::{register_error_codes, Error, Result};
 pub use file_info::{FileInfo, FileType};
 pub use file_sink::{FileSink, FileSinkBuilder};
+pub use file_sink_group::SinkGroup;
 pub use iot_valid_poc::SCALING_PRECISION;
-pub use settings::Settings;
+pub use settings::{
+    AckMode, DecodeLimits, OverflowPolicy, Settings, SinkSettings, SinksSettings, UploadSettings,
+};
 
 use bytes::BytesMut;
 use futures::stream::BoxStream;
@@ -1,6 +1,6 @@
 use clap::Parser;
 use file_store::{
-    cli::{bucket, dump, info},
+    cli::{bucket, dump, info, tail},
     Result, Settings,
 };
 use std::path;
@@ -29,6 +29,9 @@ pub enum Cmd {
     Info(info::Cmd),
     Dump(dump::Cmd),
     Bucket(Box<bucket::Cmd>),
+    Tail(tail::Cmd),
+    #[cfg(feature = "parquet")]
+    Export(file_store::cli::export::Cmd),
 }
 
 impl Cmd {
@@ -37,12 +40,23 @@ impl Cmd {
             Cmd::Info(cmd) => cmd.run(&settings).await,
             Cmd::Dump(cmd) => cmd.run(&settings).await,
             Cmd::Bucket(cmd) => cmd.run(&settings).await,
+            Cmd::Tail(cmd) => cmd.run(&settings).await,
+            #[cfg(feature = "parquet")]
+            Cmd::Export(cmd) => cmd.run(&settings).await,
         }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result {
+    // Progress is logged to stderr, keeping stdout for command output
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
     let cli = Cli::parse();
     cli.run().await
 }
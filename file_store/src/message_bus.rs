@@ -0,0 +1,326 @@
+//! Publishing of file sink records to a message bus.
+//!
+//! A file sink can tee every record it writes to a [`Publisher`] for
+//! consumers that want a stream of records rather than polling the bucket.
+//! Records are held by the sink until the file they are written to is
+//! committed, so records of files that are rolled back are never published.
+//! The file remains the record of truth: committed records are queued for
+//! publishing without waiting and dropped when the publisher falls behind,
+//! and failed deliveries are retried a few times before being given up on.
+//! Neither ever holds up or fails a write to the file sink.
+//!
+//! Sinks publish to the topic set by `publish_topic` in their
+//! [`SinkSettings`](crate::SinkSettings), using the publisher connected to
+//! with the message bus [`Settings`] of the service.
+
+use crate::{Error, Result, SinksSettings};
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+const TEE_METRIC: &str = "file_sink_tee";
+const MAX_RETRIES: u32 = 3;
+const RETRY_WAIT: Duration = Duration::from_millis(100);
+
+pub const DEFAULT_TEE_CAPACITY: usize = 10_000;
+
+/// Message bus the sinks of a service publish to
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Settings {
+    /// NATS server url. Requires the nats feature
+    Nats { url: String },
+    /// Comma separated Kafka brokers. Requires the kafka feature
+    Kafka { brokers: String },
+}
+
+#[async_trait]
+pub trait Publisher: Send + Sync + 'static {
+    async fn publish(&self, topic: &str, payload: Bytes) -> Result;
+}
+
+/// A publisher shared by the tees of many sinks
+pub type SharedPublisher = Arc<dyn Publisher>;
+
+#[async_trait]
+impl Publisher for SharedPublisher {
+    async fn publish(&self, topic: &str, payload: Bytes) -> Result {
+        (**self).publish(topic, payload).await
+    }
+}
+
+/// Connect to the message bus of the settings
+pub async fn connect(settings: &Settings) -> Result<SharedPublisher> {
+    match settings {
+        #[cfg(feature = "nats")]
+        Settings::Nats { url } => Ok(Arc::new(nats::NatsPublisher::connect(url).await?)),
+        #[cfg(feature = "kafka")]
+        Settings::Kafka { brokers } => Ok(Arc::new(kafka::KafkaPublisher::new(brokers)?)),
+        #[allow(unreachable_patterns)]
+        other => Err(Error::MessageBus(
+            format!("not built with support for {other:?}").into(),
+        )),
+    }
+}
+
+/// Queues records for a [`TeePublisher`]
+#[derive(Debug, Clone)]
+pub struct Tee {
+    topic: String,
+    capacity: usize,
+    sender: mpsc::Sender<Bytes>,
+}
+
+impl Tee {
+    pub(crate) fn send(&self, payload: Bytes) {
+        let status = match self.sender.try_send(payload) {
+            Ok(()) => return,
+            Err(TrySendError::Full(_)) => "dropped",
+            Err(TrySendError::Closed(_)) => "closed",
+        };
+        self.not_published(status);
+    }
+
+    /// Hold a record until the file it was written to is committed. Records
+    /// beyond the capacity of the tee are dropped rather than held.
+    pub(crate) fn hold(&self, held: &mut Vec<Bytes>, payload: Bytes) {
+        if held.len() < self.capacity {
+            held.push(payload);
+        } else {
+            self.not_published("dropped");
+        }
+    }
+
+    /// Queue the held records of committed files for publishing
+    pub(crate) fn publish(&self, held: Vec<Bytes>) {
+        held.into_iter().for_each(|payload| self.send(payload));
+    }
+
+    fn not_published(&self, status: &'static str) {
+        tracing::debug!(topic = self.topic, status, "record not published");
+        metrics::increment_counter!(TEE_METRIC, "topic" => self.topic.clone(), "status" => status);
+    }
+}
+
+pub struct TeePublisher<P> {
+    publisher: P,
+    topic: String,
+    messages: mpsc::Receiver<Bytes>,
+}
+
+/// Create a tee for a file sink publishing to `topic`, queueing at most
+/// `capacity` records. The returned publisher has to be run for records to
+/// be delivered.
+pub fn tee<P: Publisher>(
+    publisher: P,
+    topic: impl ToString,
+    capacity: usize,
+) -> (Tee, TeePublisher<P>) {
+    let (sender, messages) = mpsc::channel(capacity);
+    let topic = topic.to_string();
+    (
+        Tee {
+            topic: topic.clone(),
+            capacity,
+            sender,
+        },
+        TeePublisher {
+            publisher,
+            topic,
+            messages,
+        },
+    )
+}
+
+/// Create a tee for the sink of `prefix` if the message bus is connected and
+/// the settings of the sink name a topic to publish to
+pub fn sink_tee(
+    publisher: Option<&SharedPublisher>,
+    sinks: &SinksSettings,
+    prefix: impl ToString,
+) -> Option<(Tee, TeePublisher<SharedPublisher>)> {
+    let topic = sinks.get(&prefix.to_string())?.publish_topic.as_ref()?;
+    let publisher = publisher?.clone();
+    Some(tee(publisher, topic, DEFAULT_TEE_CAPACITY))
+}
+
+/// Run the publisher of a tee, if there is one
+pub async fn run(
+    publisher: Option<TeePublisher<impl Publisher>>,
+    shutdown: &triggered::Listener,
+) -> Result {
+    match publisher {
+        Some(publisher) => publisher.run(shutdown).await,
+        None => Ok(()),
+    }
+}
+
+impl<P: Publisher> TeePublisher<P> {
+    pub async fn run(mut self, shutdown: &triggered::Listener) -> Result {
+        tracing::info!(topic = self.topic, "starting tee publisher");
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                msg = self.messages.recv() => match msg {
+                    Some(payload) => self.publish(payload).await,
+                    None => break,
+                }
+            }
+        }
+        tracing::info!(topic = self.topic, "stopping tee publisher");
+        Ok(())
+    }
+
+    async fn publish(&self, payload: Bytes) {
+        let mut wait = RETRY_WAIT;
+        for attempt in 0..=MAX_RETRIES {
+            match self.publisher.publish(&self.topic, payload.clone()).await {
+                Ok(()) => {
+                    metrics::increment_counter!(TEE_METRIC, "topic" => self.topic.clone(), "status" => "ok");
+                    return;
+                }
+                Err(err) if attempt < MAX_RETRIES => {
+                    tracing::warn!(
+                        topic = self.topic,
+                        attempt,
+                        "failed to publish record: {err:?}"
+                    );
+                    tokio::time::sleep(wait).await;
+                    wait *= 2;
+                }
+                Err(err) => {
+                    tracing::error!(topic = self.topic, "giving up publishing record: {err:?}");
+                }
+            }
+        }
+        metrics::increment_counter!(TEE_METRIC, "topic" => self.topic.clone(), "status" => "failed");
+    }
+}
+
+#[cfg(feature = "nats")]
+pub mod nats {
+    use super::*;
+
+    pub struct NatsPublisher {
+        client: async_nats::Client,
+    }
+
+    impl NatsPublisher {
+        pub async fn connect(url: &str) -> Result<Self> {
+            let client = async_nats::connect(url).await.map_err(Error::message_bus)?;
+            Ok(Self { client })
+        }
+    }
+
+    #[async_trait]
+    impl Publisher for NatsPublisher {
+        async fn publish(&self, topic: &str, payload: Bytes) -> Result {
+            self.client
+                .publish(topic.to_string(), payload)
+                .await
+                .map_err(Error::message_bus)
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub mod kafka {
+    use super::*;
+    use rdkafka::{
+        producer::{FutureProducer, FutureRecord},
+        ClientConfig,
+    };
+
+    const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub struct KafkaPublisher {
+        producer: FutureProducer,
+    }
+
+    impl KafkaPublisher {
+        pub fn new(brokers: &str) -> Result<Self> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .create()
+                .map_err(Error::message_bus)?;
+            Ok(Self { producer })
+        }
+    }
+
+    #[async_trait]
+    impl Publisher for KafkaPublisher {
+        async fn publish(&self, topic: &str, payload: Bytes) -> Result {
+            let record = FutureRecord::<(), [u8]>::to(topic).payload(&payload);
+            self.producer
+                .send(record, SEND_TIMEOUT)
+                .await
+                .map(|_| ())
+                .map_err(|(err, _)| Error::message_bus(err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    };
+
+    #[derive(Clone, Default)]
+    struct FlakyPublisher {
+        failures: Arc<AtomicU32>,
+        published: Arc<Mutex<Vec<(String, Bytes)>>>,
+    }
+
+    #[async_trait]
+    impl Publisher for FlakyPublisher {
+        async fn publish(&self, topic: &str, payload: Bytes) -> Result {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(Error::channel());
+            }
+            self.published
+                .lock()
+                .unwrap()
+                .push((topic.to_string(), payload));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_retried() {
+        let publisher = FlakyPublisher::default();
+        publisher.failures.store(2, Ordering::SeqCst);
+        let (tee, tee_publisher) = tee(publisher.clone(), "reports", 10);
+        let (trigger, shutdown) = triggered::trigger();
+        let handle = tokio::spawn(async move { tee_publisher.run(&shutdown).await });
+
+        tee.send(Bytes::from_static(b"one"));
+        drop(tee);
+        handle.await.unwrap().unwrap();
+        trigger.trigger();
+
+        assert_eq!(
+            *publisher.published.lock().unwrap(),
+            vec![("reports".to_string(), Bytes::from_static(b"one"))]
+        );
+    }
+
+    #[test]
+    fn records_are_dropped_when_the_publisher_falls_behind() {
+        let (tee, mut tee_publisher) = tee(FlakyPublisher::default(), "reports", 1);
+
+        tee.send(Bytes::from_static(b"one"));
+        tee.send(Bytes::from_static(b"two"));
+
+        assert_eq!(
+            tee_publisher.messages.try_recv().unwrap(),
+            Bytes::from_static(b"one")
+        );
+        assert!(tee_publisher.messages.try_recv().is_err());
+    }
+}
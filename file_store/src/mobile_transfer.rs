@@ -1,5 +1,5 @@
 use crate::{
-    traits::{MsgDecode, TimestampDecode},
+    traits::{MsgDecode, TimestampDecode, TimestampEncode},
     Error, Result,
 };
 use chrono::{DateTime, Utc};
@@ -36,3 +36,62 @@ impl TryFrom<proto::ValidDataTransferSession> for ValidDataTransferSession {
         })
     }
 }
+
+impl From<ValidDataTransferSession> for proto::ValidDataTransferSession {
+    fn from(v: ValidDataTransferSession) -> Self {
+        Self {
+            pub_key: v.pub_key.into(),
+            payer: v.payer.into(),
+            upload_bytes: v.upload_bytes,
+            download_bytes: v.download_bytes,
+            num_dcs: v.num_dcs,
+            first_timestamp: v.first_timestamp.encode_timestamp_millis(),
+            last_timestamp: v.last_timestamp.encode_timestamp_millis(),
+        }
+    }
+}
+
+/// Why the mobile verifier did not accept a data transfer session
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum InvalidDataTransferSessionReason {
+    /// The hotspot of the session is not a known gateway
+    GatewayNotFound = 0,
+    /// The data credits charged to the payer do not cover the transferred bytes
+    InsufficientDcs = 1,
+}
+
+impl InvalidDataTransferSessionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::GatewayNotFound => "gateway_not_found",
+            Self::InsufficientDcs => "insufficient_dcs",
+        }
+    }
+}
+
+/// A data transfer session rejected by the mobile verifier
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InvalidDataTransferSessionV1 {
+    #[prost(message, optional, tag = "1")]
+    pub session: Option<proto::ValidDataTransferSession>,
+    #[prost(enumeration = "InvalidDataTransferSessionReason", tag = "2")]
+    pub reason: i32,
+    /// Milliseconds since the epoch of when the session was rejected
+    #[prost(uint64, tag = "3")]
+    pub timestamp: u64,
+}
+
+impl InvalidDataTransferSessionV1 {
+    pub fn new(
+        session: ValidDataTransferSession,
+        reason: InvalidDataTransferSessionReason,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            session: Some(session.into()),
+            reason: reason as i32,
+            timestamp: timestamp.encode_timestamp_millis(),
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Export of store files to Parquet for analytics.
+//!
+//! Messages are decoded into the same json form the dump command prints and
+//! written with the schema of their file type, whose columns follow the
+//! fields and types of the proto message. Every export of a file type has
+//! the same columns, a field missing from a message is null. Exports are
+//! partitioned by the date of their source file as
+//! `<file_type>/date=<YYYY-MM-DD>/<file_type>.<millis>.parquet`, so the
+//! analytics bucket can be queried as a hive partitioned table. The timestamp
+//! of the latest exported file of each type is kept under `_latest/`, outside
+//! of the partitioned tables, so an exporter picks up where the previous one
+//! stopped.
+
+use crate::{
+    cli::dump::decode_json, error::DecodeError, Error, FileInfo, FileStore, FileType, Result,
+};
+use arrow::{
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    json::ReaderBuilder,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::TryStreamExt;
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+use tokio::io::AsyncReadExt;
+
+const DEFAULT_POLL_DURATION: Duration = Duration::from_secs(5 * 60);
+const BATCH_SIZE: usize = 8192;
+
+pub struct Exporter {
+    source: FileStore,
+    dest: FileStore,
+    file_types: Vec<FileType>,
+    poll_duration: Duration,
+}
+
+impl Exporter {
+    /// Mirror the files of the given types in `source` to Parquet files in
+    /// `dest`. Fails for file types without a Parquet schema
+    pub fn new(source: FileStore, dest: FileStore, file_types: Vec<FileType>) -> Result<Self> {
+        if let Some(file_type) = file_types
+            .iter()
+            .find(|file_type| schema(**file_type).is_none())
+        {
+            return Err(Error::not_found(format!(
+                "no parquet schema for file type {file_type}"
+            )));
+        }
+        Ok(Self {
+            source,
+            dest,
+            file_types,
+            poll_duration: DEFAULT_POLL_DURATION,
+        })
+    }
+
+    pub fn poll_duration(self, poll_duration: Duration) -> Self {
+        Self {
+            poll_duration,
+            ..self
+        }
+    }
+
+    /// Export new files every poll duration until shutdown
+    pub async fn run(self, shutdown: triggered::Listener) -> Result {
+        let mut trigger = tokio::time::interval(self.poll_duration);
+        trigger.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => return Ok(()),
+                _ = trigger.tick() => {
+                    for file_type in &self.file_types {
+                        self.export_new(*file_type).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Export the files of the given type added since the latest export,
+    /// returning the number of exported files
+    pub async fn export_new(&self, file_type: FileType) -> Result<usize> {
+        let after = latest_exported(&self.dest, file_type).await?;
+        let mut files = self.source.list(file_type, after, None);
+        let mut exported = 0;
+        while let Some(info) = files.try_next().await? {
+            self.export_file(&info).await?;
+            set_latest_exported(&self.dest, file_type, info.timestamp).await?;
+            exported += 1;
+        }
+        Ok(exported)
+    }
+
+    async fn export_file(&self, info: &FileInfo) -> Result {
+        let schema = schema(info.file_type).ok_or_else(|| {
+            Error::not_found(format!(
+                "no parquet schema for file type {}",
+                info.file_type
+            ))
+        })?;
+        let mut messages = self.source.stream_file(info.clone()).await?;
+        let mut records = Vec::new();
+        while let Some(msg) = messages.try_next().await? {
+            if let Some(record) = decode_json(&info.file_type, msg)? {
+                records.push(record);
+            }
+        }
+        if records.is_empty() {
+            tracing::debug!(file = %info, "no messages to export");
+            return Ok(());
+        }
+        self.dest
+            .put_bytes(&partition_key(info), to_parquet(schema, &records)?)
+            .await?;
+        tracing::info!(file = %info, records = records.len(), "exported to parquet");
+        Ok(())
+    }
+}
+
+/// Key of the Parquet export of a store file
+pub fn partition_key(info: &FileInfo) -> String {
+    format!(
+        "{}/date={}/{}.{}.parquet",
+        info.file_type,
+        info.timestamp.format("%Y-%m-%d"),
+        info.file_type,
+        info.timestamp.timestamp_millis()
+    )
+}
+
+fn column(name: &str, data_type: DataType) -> Field {
+    Field::new(name, data_type, true)
+}
+
+/// Schema of the Parquet exports of a file type, following the fields of its
+/// proto message as decoded by the dump command. Keys are b58 encoded and
+/// enums are their numeric value. None for file types that are not exported
+pub fn schema(file_type: FileType) -> Option<SchemaRef> {
+    use DataType::{Float32, Int32, UInt32, UInt64, Utf8};
+    let session = || {
+        vec![
+            column("pub_key", Utf8),
+            column("upload_bytes", UInt64),
+            column("download_bytes", UInt64),
+            column("num_dcs", UInt64),
+            column("payer", Utf8),
+            column("first_timestamp", UInt64),
+            column("last_timestamp", UInt64),
+        ]
+    };
+    let fields = match file_type {
+        FileType::ValidDataTransferSession | FileType::VerifiedDataTransferSession => session(),
+        FileType::InvalidVerifiedDataTransferSession => {
+            let mut fields = session();
+            fields.extend([column("reason", Int32), column("timestamp", UInt64)]);
+            fields
+        }
+        FileType::SpeedtestAvg => vec![
+            column("pub_key", Utf8),
+            column("upload_speed_avg_bps", UInt64),
+            column("download_speed_avg_bps", UInt64),
+            column("latency_avg_ms", UInt32),
+            column("validity", Int32),
+            column("number_of_speedtests", UInt64),
+            column("reward_multiplier", Float32),
+        ],
+        FileType::ValidatedHeartbeat => vec![
+            column("cbsd_id", Utf8),
+            column("pub_key", Utf8),
+            column("reward_multiplier", Float32),
+            column("timestamp", UInt64),
+            column("cell_type", Int32),
+            column("validity", Int32),
+        ],
+        FileType::RadioRewardShare => vec![
+            column("owner_key", Utf8),
+            column("hotpost_key", Utf8),
+            column("cbsd_id", Utf8),
+            column("amount", UInt64),
+            column("start_epoch", UInt64),
+            column("end_epoch", UInt64),
+        ],
+        FileType::IotValidPacket => vec![
+            column("payload_size", UInt32),
+            column("gateway", Utf8),
+            column("payload_hash", Utf8),
+            column("num_dcs", UInt32),
+            column("packet_timestamp", UInt64),
+        ],
+        FileType::RewardManifest => vec![
+            column(
+                "written_files",
+                DataType::List(Arc::new(column("item", Utf8))),
+            ),
+            column("start_timestamp", UInt64),
+            column("end_timestamp", UInt64),
+        ],
+        _ => return None,
+    };
+    Some(Arc::new(Schema::new(fields)))
+}
+
+/// Write the records to a snappy compressed Parquet file with the given
+/// schema. Fields of the records outside of the schema are ignored
+pub fn to_parquet(schema: SchemaRef, records: &[Value]) -> Result<Vec<u8>> {
+    let mut decoder = ReaderBuilder::new(schema.clone())
+        .with_batch_size(BATCH_SIZE)
+        .build_decoder()?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+
+    let mut data = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut data, schema, Some(properties))?;
+    for chunk in records.chunks(BATCH_SIZE) {
+        decoder.serialize(chunk)?;
+        if let Some(batch) = decoder.flush()? {
+            writer.write(&batch)?;
+        }
+    }
+    writer.close()?;
+    Ok(data)
+}
+
+fn latest_key(file_type: FileType) -> String {
+    format!("_latest/{file_type}")
+}
+
+/// Where the latest export was kept before it moved out of the partitions
+fn legacy_latest_key(file_type: FileType) -> String {
+    format!("{file_type}/latest")
+}
+
+async fn latest_exported(dest: &FileStore, file_type: FileType) -> Result<Option<DateTime<Utc>>> {
+    let mut keys = [latest_key(file_type), legacy_latest_key(file_type)].into_iter();
+    let stream = loop {
+        let Some(key) = keys.next() else {
+            return Ok(None);
+        };
+        match dest.get_raw(key).await {
+            Ok(stream) => break stream,
+            Err(Error::Aws(aws_sdk_s3::Error::NoSuchKey(_))) => continue,
+            Err(err) => return Err(err),
+        }
+    };
+    let mut contents = String::new();
+    tokio_util::io::StreamReader::new(stream)
+        .read_to_string(&mut contents)
+        .await?;
+    contents
+        .trim()
+        .parse()
+        .ok()
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .map(Some)
+        .ok_or_else(|| DecodeError::file_info(format!("invalid latest export {contents}")))
+}
+
+async fn set_latest_exported(
+    dest: &FileStore,
+    file_type: FileType,
+    timestamp: DateTime<Utc>,
+) -> Result {
+    dest.put_bytes(
+        &latest_key(file_type),
+        timestamp.timestamp_millis().to_string().into_bytes(),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use serde_json::json;
+
+    #[test]
+    fn exports_are_partitioned_by_date() {
+        let info = FileInfo::from((
+            FileType::CellHeartbeatIngestReport,
+            Utc.timestamp_millis_opt(1683158400123).unwrap(),
+        ));
+        assert_eq!(
+            partition_key(&info),
+            "heartbeat_report/date=2023-05-04/heartbeat_report.1683158400123.parquet"
+        );
+    }
+
+    #[test]
+    fn latest_export_is_outside_the_partitions() {
+        let info = FileInfo::from((
+            FileType::SpeedtestAvg,
+            Utc.timestamp_millis_opt(1683158400123).unwrap(),
+        ));
+        assert!(!latest_key(info.file_type).starts_with(&format!("{}/", info.file_type)));
+        assert!(partition_key(&info).starts_with(&format!("{}/", info.file_type)));
+    }
+
+    fn columns(data: Vec<u8>) -> (i64, Vec<String>) {
+        let reader = SerializedFileReader::new(bytes::Bytes::from(data)).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        let columns = metadata
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|column| column.path().string())
+            .collect();
+        (metadata.num_rows(), columns)
+    }
+
+    #[test]
+    fn records_are_written_with_the_file_type_schema() {
+        let schema = schema(FileType::ValidDataTransferSession).unwrap();
+        let records: Vec<_> = (0..10)
+            .map(|i| json!({"pub_key": format!("key{i}"), "upload_bytes": i, "unknown": "x"}))
+            .collect();
+        let (rows, columns) = columns(to_parquet(schema.clone(), &records).unwrap());
+        assert_eq!(rows, 10);
+        let expected: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        // every column of the schema, whether or not the records have it
+        assert_eq!(columns, expected);
+    }
+
+    #[test]
+    fn file_types_without_a_schema_are_not_exported() {
+        assert!(schema(FileType::SpeedtestAvg).is_some());
+        assert!(schema(FileType::SignedPocReceiptTxn).is_none());
+    }
+}
@@ -34,3 +34,51 @@ impl TryFrom<proto::RewardManifest> for RewardManifest {
         })
     }
 }
+
+/// A reward manifest itemizing the reward corrections applied to the epoch.
+/// Encoded like [`proto::RewardManifest`] with the corrections in an extra
+/// field, so readers of the plain manifest are unaffected
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CorrectedRewardManifest {
+    #[prost(string, repeated, tag = "1")]
+    pub written_files: Vec<String>,
+    #[prost(uint64, tag = "2")]
+    pub start_timestamp: u64,
+    #[prost(uint64, tag = "3")]
+    pub end_timestamp: u64,
+    #[prost(message, repeated, tag = "16")]
+    pub corrections: Vec<RewardCorrectionV1>,
+    /// Set when the poc shares of the epoch were smoothed over trailing
+    /// epochs
+    #[prost(message, optional, tag = "17")]
+    pub smoothing: Option<RewardSmoothingV1>,
+}
+
+/// Smoothing of the poc shares of the radios over trailing epochs
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RewardSmoothingV1 {
+    /// Number of epochs the shares were averaged over, the rewarded epoch
+    /// included
+    #[prost(uint32, tag = "1")]
+    pub epochs: u32,
+    /// Decimal weights of the epochs, the rewarded epoch first
+    #[prost(string, repeated, tag = "2")]
+    pub weights: Vec<String>,
+}
+
+/// The corrections of a hotspot applied to an epoch
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RewardCorrectionV1 {
+    #[prost(bytes = "vec", tag = "1")]
+    pub hotspot_key: Vec<u8>,
+    /// Ids of the corrections recorded for the hotspot
+    #[prost(int64, repeated, tag = "2")]
+    pub ids: Vec<i64>,
+    /// Bones the corrections add up to, negative to withhold rewards
+    #[prost(sint64, tag = "3")]
+    pub amount: i64,
+    /// Bones paid, or withheld when negative, in the epoch. The remainder of
+    /// the amount is carried to the next epoch
+    #[prost(sint64, tag = "4")]
+    pub applied: i64,
+}
@@ -1,7 +1,7 @@
 use crate::{Error, Result};
 use config::{Config, File};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Settings {
@@ -12,16 +12,143 @@ pub struct Settings {
     /// Optional region for the endpoint. Default: us-west-2
     #[serde(default = "default_region")]
     pub region: String,
+    /// Optional prefix of all keys written and read, so deployments can share
+    /// a bucket, e.g. "mainnet". Keys are used as is when not set
+    pub prefix: Option<String>,
 
     /// Should only be used for local testing
     pub access_key_id: Option<String>,
     pub secret_access_key: Option<String>,
+
+    /// Optional bucket, usually in another region, that writes fail over to
+    /// when the bucket above keeps failing. Objects written to it are copied
+    /// back once the primary bucket recovers. Default none
+    pub secondary: Option<Box<Settings>>,
+    /// Consecutive failed writes after which writes go to the secondary
+    /// bucket. Default 3
+    #[serde(default = "default_failover_threshold")]
+    pub failover_threshold: u32,
+    /// Seconds between attempts to copy the objects of the secondary bucket
+    /// back to the primary one. Default 60
+    #[serde(default = "default_failover_replay_period")]
+    pub failover_replay_period: u64,
+    /// Bandwidth limit and windows of the uploads to the bucket. Unlimited
+    /// at any time by default
+    #[serde(default)]
+    pub upload: UploadSettings,
+    /// Limits on the decompressed size of the files read from the bucket
+    #[serde(default)]
+    pub limits: DecodeLimits,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct UploadSettings {
+    /// Bytes per second all uploads together are limited to, averaged over
+    /// the files uploaded. Unlimited when not set
+    pub max_bytes_per_sec: Option<u64>,
+    /// Cron-like expressions of the minutes, in UTC, files are uploaded in.
+    /// Files are uploaded at any time when empty
+    #[serde(default)]
+    pub windows: Vec<String>,
+    /// Bytes of files waiting for a window above which files are uploaded
+    /// outside of the windows, so the local disk does not fill up. Files wait
+    /// for a window regardless of their size when not set
+    pub max_spooled_bytes: Option<u64>,
+}
+
+/// Limits on the decompressed size of files read, so a corrupt or malicious
+/// file can not exhaust the memory of a reader. Files exceeding them fail to
+/// decode
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Bytes of a single message frame. Default 15_000_000
+    #[serde(default = "default_max_frame_length")]
+    pub max_frame_length: usize,
+    /// Decompressed bytes of a whole file. Default 4_000_000_000
+    #[serde(default = "default_max_file_length")]
+    pub max_file_length: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_frame_length: default_max_frame_length(),
+            max_file_length: default_max_file_length(),
+        }
+    }
+}
+
+/// Overrides of the output policy of file sinks, keyed by the prefix of the
+/// sink, the file type it writes, e.g. "iot_valid_packet"
+pub type SinksSettings = HashMap<String, SinkSettings>;
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SinkSettings {
+    /// Minutes after which the current sink file is closed
+    pub roll_minutes: Option<i64>,
+    /// Size in bytes after which the current sink file is closed
+    pub max_size: Option<usize>,
+    /// Gzip compression level from 0 (none) to 9 (best)
+    pub compression_level: Option<u32>,
+    /// Size in bytes of the write buffer of sink files
+    pub buffer_size: Option<usize>,
+    /// Seconds within which a message identical to one already written is
+    /// dropped. Duplicates are written when not set
+    pub dedupe_seconds: Option<u64>,
+    /// Whether closed files are deposited for upload right away or only once
+    /// committed. Ignored for sinks whose service sets how they are committed
+    pub ack_mode: Option<AckMode>,
+    /// What writers do when the queue of the sink is full
+    pub overflow: Option<OverflowPolicy>,
+    /// Message bus topic the records of committed files are published to.
+    /// Records are not published when not set
+    pub publish_topic: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AckMode {
+    /// Files are deposited for upload as soon as they are closed
+    Auto,
+    /// Files are only deposited for upload when the writer commits them
+    Manual,
+}
+
+/// What a sink client does with a write when the queue of its sink is full.
+/// Overflowed writes are kept in order and written before any later write
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Wait for room in the queue, failing with a send timeout
+    #[default]
+    Block,
+    /// Hold the write in memory, dropping the oldest held write once too
+    /// many are held
+    DropOldest,
+    /// Append the write to a local overflow file that the sink replays
+    Spill,
 }
 
 fn default_region() -> String {
     "us-west-2".to_string()
 }
 
+fn default_max_frame_length() -> usize {
+    crate::file_sink::MAX_FRAME_LENGTH
+}
+
+fn default_max_file_length() -> u64 {
+    4_000_000_000
+}
+
+fn default_failover_threshold() -> u32 {
+    3
+}
+
+fn default_failover_replay_period() -> u64 {
+    60
+}
+
 impl Settings {
     /// Load Settings from a given path.
     ///
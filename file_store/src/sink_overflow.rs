@@ -0,0 +1,444 @@
+//! Writes overflowed from the queue of a full sink.
+//!
+//! Once a write finds the queue of its sink full, it and every write after it
+//! go to the overflow until the sink takes the overflowed writes back, which
+//! it does as soon as it has worked through its queue, so writes keep their
+//! order. The drop oldest policy holds the writes in memory and fails the
+//! oldest ones beyond [`MAX_HELD_WRITES`]. The spill policy appends them as
+//! length delimited frames to a local file and acknowledges them once they
+//! are in it. The file is renamed to a replay file when the sink takes its
+//! writes back, and the replay file is only removed once the sink file
+//! holding the replayed writes is committed. Replay files left over by a
+//! restart, a rollback or a failed write are replayed again, so spilled
+//! writes may be written twice but are not lost.
+
+use crate::{
+    file_sink::{Message, MessageSender, QueuedWrite},
+    Error, OverflowPolicy, Result,
+};
+use std::{
+    collections::VecDeque,
+    io, mem,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::{self, mpsc::error::TrySendError, Notify},
+};
+
+const DROPPED_METRIC: &str = "file_sink_dropped";
+const SPILLED_METRIC: &str = "file_sink_spilled";
+const OVERFLOW_DEPTH_METRIC: &str = "file_sink_overflow_depth";
+
+/// Most writes held in memory by the drop oldest policy
+pub const MAX_HELD_WRITES: usize = 10_000;
+
+#[derive(Debug)]
+enum Store {
+    Held(Mutex<VecDeque<QueuedWrite>>),
+    Spilled {
+        dir: PathBuf,
+        path: PathBuf,
+        file: sync::Mutex<Option<File>>,
+        replays: Mutex<Replays>,
+    },
+}
+
+/// Spill files renamed to be replayed, kept until the sink file holding
+/// their writes is committed
+#[derive(Debug, Default)]
+struct Replays {
+    /// Number of the next replay file
+    next: u64,
+    /// Files still to be replayed, oldest first
+    pending: Vec<PathBuf>,
+    /// Files taken by the sink, not yet written
+    taken: Vec<PathBuf>,
+    /// Files whose writes are in sink files not yet committed
+    written: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Overflow {
+    prefix: String,
+    store: Store,
+    /// Writes in the overflow, at least one while a spill file is left over
+    depth: AtomicUsize,
+    notify: Notify,
+}
+
+impl Overflow {
+    /// The overflow of the policy, none for sinks that block. Spill files
+    /// are kept in `dir`
+    pub(crate) async fn new(
+        policy: OverflowPolicy,
+        prefix: &str,
+        dir: &Path,
+    ) -> Result<Option<Self>> {
+        let store = match policy {
+            OverflowPolicy::Block => return Ok(None),
+            OverflowPolicy::DropOldest => Store::Held(Mutex::default()),
+            OverflowPolicy::Spill => {
+                fs::create_dir_all(dir).await?;
+                let left_over = replay_files(dir, prefix).await?;
+                Store::Spilled {
+                    dir: dir.to_path_buf(),
+                    path: dir.join(format!("{prefix}.spill")),
+                    file: sync::Mutex::default(),
+                    replays: Mutex::new(Replays {
+                        next: left_over.last().map_or(0, |(number, _)| number + 1),
+                        pending: left_over.into_iter().map(|(_, path)| path).collect(),
+                        ..Default::default()
+                    }),
+                }
+            }
+        };
+        let overflow = Self {
+            prefix: prefix.to_string(),
+            store,
+            depth: AtomicUsize::new(0),
+            notify: Notify::new(),
+        };
+        if overflow.spill_left_over() {
+            overflow.depth.store(1, Ordering::Relaxed);
+            overflow.notify.notify_one();
+        }
+        Ok(Some(overflow))
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.depth() == 0
+    }
+
+    /// Resolves once writes were added to the overflow
+    pub(crate) async fn notified(&self) {
+        self.notify.notified().await
+    }
+
+    /// Queue the write, or add it to the overflow when the queue is full or
+    /// the overflow holds earlier writes
+    pub(crate) async fn send(&self, sender: &MessageSender, write: QueuedWrite) -> Result {
+        if !self.is_empty() {
+            return self.push(write).await;
+        }
+        let (on_write_tx, bytes, correlation_id) = write;
+        match sender.try_send(Message::Data(on_write_tx, bytes, correlation_id)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(Message::Data(on_write_tx, bytes, correlation_id))) => {
+                self.push((on_write_tx, bytes, correlation_id)).await
+            }
+            Err(_) => Err(Error::channel()),
+        }
+    }
+
+    async fn push(&self, write: QueuedWrite) -> Result {
+        match &self.store {
+            Store::Held(held) => {
+                let dropped = {
+                    let mut held = held.lock().expect("sink overflow poisoned");
+                    held.push_back(write);
+                    let dropped = if held.len() > MAX_HELD_WRITES {
+                        held.pop_front()
+                    } else {
+                        None
+                    };
+                    self.depth.store(held.len(), Ordering::Relaxed);
+                    dropped
+                };
+                if let Some((on_write_tx, _, _)) = dropped {
+                    tracing::warn!("dropping the oldest overflowed write of {}", self.prefix);
+                    metrics::increment_counter!(DROPPED_METRIC, "prefix" => self.prefix.clone());
+                    let _ = on_write_tx.send(Err(Error::SinkFull));
+                }
+            }
+            Store::Spilled { path, file, .. } => {
+                let (on_write_tx, bytes, _) = write;
+                let mut file = file.lock().await;
+                if let Err(err) = spill(&mut file, path, &bytes).await {
+                    tracing::error!("failed to spill a write of {}: {err:?}", self.prefix);
+                    // Reopen the file on the next spill
+                    *file = None;
+                    return Err(err);
+                }
+                self.depth.fetch_add(1, Ordering::Relaxed);
+                metrics::increment_counter!(SPILLED_METRIC, "prefix" => self.prefix.clone());
+                let _ = on_write_tx.send(Ok(()));
+            }
+        }
+        metrics::gauge!(OVERFLOW_DEPTH_METRIC, self.depth() as f64, "prefix" => self.prefix.clone());
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Take the overflowed writes, oldest first. Replayed spilled writes
+    /// have no one waiting on their result, they were acknowledged when
+    /// spilled. The sink reports whether it wrote them with [`Self::replayed`]
+    pub(crate) async fn take(&self) -> Result<Vec<QueuedWrite>> {
+        let writes = match &self.store {
+            Store::Held(held) => {
+                let mut held = held.lock().expect("sink overflow poisoned");
+                self.depth.store(0, Ordering::Relaxed);
+                held.drain(..).collect()
+            }
+            Store::Spilled {
+                dir,
+                path,
+                file,
+                replays,
+            } => {
+                // Spilling waits on the file, so no spilled write is missed by the depth
+                {
+                    let mut file = file.lock().await;
+                    if path.exists() {
+                        *file = None;
+                        let next = replays.lock().expect("sink overflow poisoned").next;
+                        let replay_path = dir.join(format!("{}.replay.{next}", self.prefix));
+                        fs::rename(path, &replay_path).await?;
+                        let mut replays = replays.lock().expect("sink overflow poisoned");
+                        replays.next += 1;
+                        replays.pending.push(replay_path);
+                    }
+                    self.depth.store(0, Ordering::Relaxed);
+                }
+                let taken = {
+                    let mut replays = replays.lock().expect("sink overflow poisoned");
+                    let pending = mem::take(&mut replays.pending);
+                    replays.taken.extend(pending.iter().cloned());
+                    pending
+                };
+                let mut writes = vec![];
+                for replay_path in taken {
+                    match fs::read(&replay_path).await {
+                        Ok(spilled) => writes.extend(frames(&self.prefix, &spilled)),
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                        Err(err) => {
+                            self.replayed(false);
+                            return Err(err.into());
+                        }
+                    }
+                }
+                writes
+            }
+        };
+        metrics::gauge!(OVERFLOW_DEPTH_METRIC, self.depth() as f64, "prefix" => self.prefix.clone());
+        Ok(writes)
+    }
+
+    /// Keep the taken replay files until the sink file holding their writes
+    /// is committed, or replay them again when a write of theirs failed
+    pub(crate) fn replayed(&self, written: bool) {
+        if let Store::Spilled { replays, .. } = &self.store {
+            let mut replays = replays.lock().expect("sink overflow poisoned");
+            let taken = mem::take(&mut replays.taken);
+            if written {
+                replays.written.extend(taken);
+            } else if !taken.is_empty() {
+                replays.pending.splice(0..0, taken);
+                self.depth.fetch_max(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Remove the replay files whose writes are in the committed sink files
+    pub(crate) async fn committed(&self) -> Result {
+        if let Store::Spilled { replays, .. } = &self.store {
+            let written = mem::take(&mut replays.lock().expect("sink overflow poisoned").written);
+            for replay_path in written {
+                match fs::remove_file(&replay_path).await {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replay the writes of the replay files in rolled back sink files again
+    pub(crate) fn rolled_back(&self) {
+        if let Store::Spilled { replays, .. } = &self.store {
+            let mut replays = replays.lock().expect("sink overflow poisoned");
+            let written = mem::take(&mut replays.written);
+            if !written.is_empty() {
+                replays.pending.splice(0..0, written);
+                self.depth.fetch_max(1, Ordering::Relaxed);
+                self.notify.notify_one();
+            }
+        }
+    }
+
+    fn spill_left_over(&self) -> bool {
+        match &self.store {
+            Store::Held(_) => false,
+            Store::Spilled { path, replays, .. } => {
+                path.exists()
+                    || !replays
+                        .lock()
+                        .expect("sink overflow poisoned")
+                        .pending
+                        .is_empty()
+            }
+        }
+    }
+}
+
+async fn spill(file: &mut Option<File>, path: &Path, bytes: &[u8]) -> Result {
+    if file.is_none() {
+        *file = Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?,
+        );
+    }
+    if let Some(file) = file.as_mut() {
+        let len = u32::try_from(bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "write too large to spill"))?;
+        let mut frame = Vec::with_capacity(4 + bytes.len());
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.extend_from_slice(bytes);
+        file.write_all(&frame).await?;
+        file.flush().await?;
+    }
+    Ok(())
+}
+
+/// Replay files of the prefix left over in the directory, by their number
+async fn replay_files(dir: &Path, prefix: &str) -> Result<Vec<(u64, PathBuf)>> {
+    let replay_prefix = format!("{prefix}.replay.");
+    let mut files = vec![];
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let number = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix(&replay_prefix))
+            .and_then(|number| number.parse::<u64>().ok());
+        if let Some(number) = number {
+            files.push((number, entry.path()));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The writes of the frames of a spill file, without a frame cut short by a
+/// restart while it was spilled
+fn frames(prefix: &str, mut spilled: &[u8]) -> Vec<QueuedWrite> {
+    let mut writes = vec![];
+    while spilled.len() >= 4 {
+        let len = u32::from_be_bytes([spilled[0], spilled[1], spilled[2], spilled[3]]) as usize;
+        let Some(bytes) = spilled.get(4..4 + len) else {
+            break;
+        };
+        let (on_write_tx, _) = sync::oneshot::channel();
+        writes.push((on_write_tx, bytes.to_vec(), None));
+        spilled = &spilled[4 + len..];
+    }
+    if !spilled.is_empty() {
+        tracing::warn!("ignoring a partial spilled write of {prefix}");
+    }
+    writes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::sync::{mpsc, oneshot};
+
+    fn write(bytes: &[u8]) -> (QueuedWrite, oneshot::Receiver<Result>) {
+        let (on_write_tx, on_write_rx) = oneshot::channel();
+        ((on_write_tx, bytes.to_vec(), None), on_write_rx)
+    }
+
+    fn bytes(writes: Vec<QueuedWrite>) -> Vec<Vec<u8>> {
+        writes.into_iter().map(|(_, bytes, _)| bytes).collect()
+    }
+
+    #[tokio::test]
+    async fn overflowed_writes_keep_their_order() {
+        let tmp_dir = TempDir::new().unwrap();
+        let (sender, mut receiver) = mpsc::channel(1);
+        let overflow = Overflow::new(OverflowPolicy::Spill, "test", tmp_dir.path())
+            .await
+            .unwrap()
+            .unwrap();
+
+        for data in [b"one", b"two", b"333"] {
+            overflow.send(&sender, write(data).0).await.unwrap();
+        }
+        // Room in the queue does not let later writes overtake spilled ones
+        assert!(matches!(receiver.try_recv(), Ok(Message::Data(_, bytes, _)) if bytes == b"one"));
+        let (four, on_four) = write(b"four");
+        overflow.send(&sender, four).await.unwrap();
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(on_four.await.unwrap().ok(), Some(()));
+        assert_eq!(overflow.depth(), 3);
+
+        // Spilled writes survive a restart
+        drop(overflow);
+        let overflow = Overflow::new(OverflowPolicy::Spill, "test", tmp_dir.path())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!overflow.is_empty());
+        assert_eq!(
+            bytes(overflow.take().await.unwrap()),
+            vec![b"two".to_vec(), b"333".to_vec(), b"four".to_vec()]
+        );
+        overflow.replayed(true);
+        assert!(overflow.is_empty());
+        assert!(overflow.take().await.unwrap().is_empty());
+
+        // Replayed writes are kept until committed
+        drop(overflow);
+        let overflow = Overflow::new(OverflowPolicy::Spill, "test", tmp_dir.path())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(overflow.take().await.unwrap().len(), 3);
+        overflow.replayed(true);
+        overflow.committed().await.unwrap();
+        drop(overflow);
+        let overflow = Overflow::new(OverflowPolicy::Spill, "test", tmp_dir.path())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(overflow.is_empty());
+        assert!(overflow.take().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_oldest_held_writes_are_dropped() {
+        let (sender, _receiver) = mpsc::channel(1);
+        let overflow = Overflow::new(OverflowPolicy::DropOldest, "test", Path::new("/tmp"))
+            .await
+            .unwrap()
+            .unwrap();
+        overflow.send(&sender, write(b"queued").0).await.unwrap();
+
+        let (oldest, on_oldest) = write(&[0]);
+        overflow.send(&sender, oldest).await.unwrap();
+        for i in 1..=MAX_HELD_WRITES {
+            overflow
+                .send(&sender, write(&i.to_be_bytes()).0)
+                .await
+                .unwrap();
+        }
+        assert!(matches!(on_oldest.await, Ok(Err(Error::SinkFull))));
+        let held = overflow.take().await.unwrap();
+        assert_eq!(held.len(), MAX_HELD_WRITES);
+        assert_eq!(held[0].1, 1usize.to_be_bytes().to_vec());
+        assert!(overflow.is_empty());
+    }
+}
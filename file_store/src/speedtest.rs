@@ -1,4 +1,5 @@
 use crate::{
+    file_source::{CsvRow, FromCsv},
     traits::{MsgDecode, MsgTimestamp, TimestampDecode, TimestampEncode},
     Error, Result,
 };
@@ -98,6 +99,25 @@ impl TryFrom<SpeedtestIngestReportV1> for CellSpeedtestIngestReport {
     }
 }
 
+/// Legacy exports hold the received time in milliseconds and the speedtest
+/// time in seconds
+impl FromCsv for SpeedtestIngestReportV1 {
+    fn from_csv(row: &CsvRow) -> Result<Self> {
+        Ok(Self {
+            received_timestamp: row.timestamp_millis("received_timestamp")?,
+            report: Some(SpeedtestReqV1 {
+                pub_key: row.public_key("pub_key")?,
+                serial: row.get_or_default("serial")?,
+                timestamp: row.timestamp_secs("timestamp")?,
+                upload_speed: row.get("upload_speed")?,
+                download_speed: row.get("download_speed")?,
+                latency: row.get("latency")?,
+                signature: vec![],
+            }),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -5,5 +5,7 @@ mod report_id;
 
 pub use msg_decode::MsgDecode;
 pub use msg_timestamp::{MsgTimestamp, TimestampDecode, TimestampEncode};
+#[doc(hidden)]
+pub use msg_verify::verify_encoded;
 pub use msg_verify::MsgVerify;
 pub use report_id::{IngestId, ReportId};
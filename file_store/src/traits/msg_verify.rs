@@ -16,19 +16,30 @@ pub trait MsgVerify {
     fn verify(&self, verifier: &PublicKey) -> Result;
 }
 
+/// Implement [`MsgVerify`] for a message signed over its encoding with an
+/// empty signature field, also for messages defined outside of helium-proto
+#[macro_export]
 macro_rules! impl_msg_verify {
     ($msg_type:ty, $sig: ident) => {
-        impl MsgVerify for $msg_type {
-            fn verify(&self, verifier: &PublicKey) -> Result {
-                let mut buf = vec![];
+        impl $crate::traits::MsgVerify for $msg_type {
+            fn verify(&self, verifier: &helium_crypto::PublicKey) -> $crate::Result {
                 let mut msg = self.clone();
                 msg.$sig = vec![];
-                msg.encode(&mut buf)?;
-                verifier.verify(&buf, &self.$sig).map_err(Error::from)
+                $crate::traits::verify_encoded(&msg, &self.$sig, verifier)
             }
         }
     };
 }
+
+/// Verify the signature over the encoding of a message, see
+/// [`impl_msg_verify`]
+#[doc(hidden)]
+pub fn verify_encoded<M: Message>(msg: &M, signature: &[u8], verifier: &PublicKey) -> Result {
+    let mut buf = vec![];
+    msg.encode(&mut buf)?;
+    verifier.verify(&buf, signature).map_err(Error::from)
+}
+
 impl_msg_verify!(SubscriberLocationReqV1, signature);
 impl_msg_verify!(CellHeartbeatReqV1, signature);
 impl_msg_verify!(SpeedtestReqV1, signature);
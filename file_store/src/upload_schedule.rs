@@ -0,0 +1,209 @@
+//! Windows in which files are uploaded, as cron-like expressions.
+//!
+//! An expression has the five fields of cron, minute, hour, day of month,
+//! month and day of week, each `*`, a number, a range `a-b`, a step `*/n` or
+//! `a-b/n`, or a comma separated list of those. Days of the week count from
+//! Sunday as 0, 7 is Sunday as well. Uploads may run in every minute matched
+//! by any of the expressions, in UTC. As in cron, a restricted day of month
+//! and day of week match when either of them does.
+
+use crate::{Error, Result};
+use chrono::{DateTime, Datelike, Duration, DurationRound, Timelike, Utc};
+use std::str::FromStr;
+
+/// Minutes searched for the next opening of a schedule, a leap year
+const MAX_SEARCH_MINUTES: i64 = 366 * 24 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Field {
+    allowed: Vec<bool>,
+    restricted: bool,
+}
+
+impl Field {
+    fn parse(field: &str, min: u32, max: u32) -> std::result::Result<Self, String> {
+        let mut allowed = vec![false; max as usize + 1];
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (
+                    range,
+                    step.parse::<u32>()
+                        .ok()
+                        .filter(|step| *step > 0)
+                        .ok_or_else(|| format!("invalid step {step}"))?,
+                ),
+                None => (part, 1),
+            };
+            let (start, end) = match range {
+                "*" => (min, max),
+                range => match range.split_once('-') {
+                    Some((start, end)) => (parse_value(start)?, parse_value(end)?),
+                    None => {
+                        let value = parse_value(range)?;
+                        (value, value)
+                    }
+                },
+            };
+            if start < min || end > max || start > end {
+                return Err(format!("{range} is not within {min}-{max}"));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                allowed[value as usize] = true;
+            }
+        }
+        Ok(Self {
+            allowed,
+            restricted: field != "*",
+        })
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.allowed.get(value as usize).copied().unwrap_or(false)
+    }
+}
+
+fn parse_value(value: &str) -> std::result::Result<u32, String> {
+    value.parse().map_err(|_| format!("invalid value {value}"))
+}
+
+/// A single cron-like expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    minute: Field,
+    hour: Field,
+    day_of_month: Field,
+    month: Field,
+    day_of_week: Field,
+}
+
+impl FromStr for CronExpr {
+    type Err = Error;
+
+    fn from_str(expr: &str) -> Result<Self> {
+        let invalid = |reason: String| {
+            Error::Config(config::ConfigError::Message(format!(
+                "invalid upload window \"{expr}\": {reason}"
+            )))
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err(invalid(format!("expected 5 fields, got {}", fields.len())));
+        };
+        let mut day_of_week = Field::parse(day_of_week, 0, 7).map_err(invalid)?;
+        if day_of_week.allowed[7] {
+            day_of_week.allowed[0] = true;
+        }
+        Ok(Self {
+            minute: Field::parse(minute, 0, 59).map_err(invalid)?,
+            hour: Field::parse(hour, 0, 23).map_err(invalid)?,
+            day_of_month: Field::parse(day_of_month, 1, 31).map_err(invalid)?,
+            month: Field::parse(month, 1, 12).map_err(invalid)?,
+            day_of_week,
+        })
+    }
+}
+
+impl CronExpr {
+    pub fn matches(&self, time: DateTime<Utc>) -> bool {
+        let day_of_month = self.day_of_month.matches(time.day());
+        let day_of_week = self
+            .day_of_week
+            .matches(time.weekday().num_days_from_sunday());
+        let day = if self.day_of_month.restricted && self.day_of_week.restricted {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        };
+        day && self.minute.matches(time.minute())
+            && self.hour.matches(time.hour())
+            && self.month.matches(time.month())
+    }
+}
+
+/// The windows uploads may run in. Always open without windows
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadSchedule {
+    windows: Vec<CronExpr>,
+}
+
+impl UploadSchedule {
+    pub fn new<S: AsRef<str>>(windows: &[S]) -> Result<Self> {
+        Ok(Self {
+            windows: windows
+                .iter()
+                .map(|window| window.as_ref().parse())
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn is_open(&self, time: DateTime<Utc>) -> bool {
+        self.windows.is_empty() || self.windows.iter().any(|window| window.matches(time))
+    }
+
+    /// Start of the first minute from `time` on the schedule is open in,
+    /// `None` when it does not open within a year
+    pub fn next_open(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.is_open(time) {
+            return Some(time);
+        }
+        let minute = time.duration_trunc(Duration::minutes(1)).ok()?;
+        (1..=MAX_SEARCH_MINUTES)
+            .map(|minutes| minute + Duration::minutes(minutes))
+            .find(|time| self.is_open(*time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2023-05-01 is a Monday
+        Utc.with_ymd_and_hms(2023, 5, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn expressions_match_their_minutes() {
+        let nightly: CronExpr = "*/15 1-4 * * 1-5".parse().unwrap();
+        assert!(nightly.matches(at(1, 1, 0)));
+        assert!(nightly.matches(at(1, 4, 45)));
+        assert!(!nightly.matches(at(1, 4, 46)));
+        assert!(!nightly.matches(at(1, 5, 0)));
+        // Sunday
+        assert!(!nightly.matches(at(7, 2, 0)));
+
+        let weekends: CronExpr = "* * * * 6,7".parse().unwrap();
+        assert!(weekends.matches(at(6, 12, 0)));
+        assert!(weekends.matches(at(7, 12, 0)));
+        assert!(!weekends.matches(at(8, 12, 0)));
+
+        // Either the day of month or the day of week
+        let either: CronExpr = "0 0 15 * 1".parse().unwrap();
+        assert!(either.matches(at(15, 0, 0)));
+        assert!(either.matches(at(8, 0, 0)));
+        assert!(!either.matches(at(9, 0, 0)));
+
+        for invalid in [
+            "* * * *",
+            "60 * * * *",
+            "* 5-1 * * *",
+            "*/0 * * * *",
+            "a * * * *",
+        ] {
+            assert!(invalid.parse::<CronExpr>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn schedules_open_at_the_next_matching_minute() {
+        let always = UploadSchedule::default();
+        assert_eq!(always.next_open(at(1, 12, 30)), Some(at(1, 12, 30)));
+
+        let schedule = UploadSchedule::new(&["0-29 2 * * *", "* 22-23 * * 0"]).unwrap();
+        assert!(schedule.is_open(at(1, 2, 10)));
+        assert_eq!(schedule.next_open(at(1, 2, 30)), Some(at(2, 2, 0)));
+        assert_eq!(schedule.next_open(at(6, 12, 0)), Some(at(7, 2, 0)));
+        assert_eq!(schedule.next_open(at(7, 3, 0)), Some(at(7, 22, 0)));
+    }
+}
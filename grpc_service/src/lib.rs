@@ -0,0 +1,203 @@
+//! Routing of the grpc services hand written on top of the tonic primitives,
+//! for endpoints whose messages are defined by the oracles instead of
+//! helium-proto.
+//!
+//! The `Service` implementation of such a service matches the path of a
+//! request, serves the methods it knows with [`unary`] or
+//! [`server_streaming`] and answers all other paths with [`unimplemented`]:
+//!
+//! ```ignore
+//! fn call(&mut self, req: http::Request<B>) -> Self::Future {
+//!     let svc = self.clone();
+//!     match req.uri().path() {
+//!         LIST_PATH => grpc_service::unary(req, move |request| async move {
+//!             svc.list(request).await
+//!         }),
+//!         _ => grpc_service::unimplemented(),
+//!     }
+//! }
+//! ```
+
+pub mod tls;
+
+use std::{convert::Infallible, future::Future};
+use tonic::{
+    body::BoxBody,
+    codec::ProstCodec,
+    codegen::{empty_body, futures_core::Stream, http, Body, BoxFuture, StdError},
+    server::{Grpc, ServerStreamingService, UnaryService},
+    Request, Response, Status,
+};
+
+/// Future of the response to a request routed by a service
+pub type ResponseFuture = BoxFuture<http::Response<BoxBody>, Infallible>;
+
+/// Serve a unary request with the handler of its method
+pub fn unary<B, Req, Res, F, Fut>(req: http::Request<B>, handler: F) -> ResponseFuture
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+    Req: prost::Message + Default + Send + 'static,
+    Res: prost::Message + Send + 'static,
+    F: FnOnce(Req) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Res, Status>> + Send + 'static,
+{
+    Box::pin(async move {
+        let mut grpc = Grpc::new(ProstCodec::<Res, Req>::default());
+        Ok(grpc.unary(Handler(Some(handler)), req).await)
+    })
+}
+
+/// Serve a server streaming request with the handler of its method
+pub fn server_streaming<B, Req, Res, S, F, Fut>(req: http::Request<B>, handler: F) -> ResponseFuture
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+    Req: prost::Message + Default + Send + 'static,
+    Res: prost::Message + Send + 'static,
+    S: Stream<Item = Result<Res, Status>> + Send + 'static,
+    F: FnOnce(Req) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<S, Status>> + Send + 'static,
+{
+    Box::pin(async move {
+        let mut grpc = Grpc::new(ProstCodec::<Res, Req>::default());
+        Ok(grpc.server_streaming(Handler(Some(handler)), req).await)
+    })
+}
+
+/// Response to a path the service has no method for
+pub fn unimplemented() -> ResponseFuture {
+    Box::pin(async move {
+        Ok(http::Response::builder()
+            .status(200)
+            .header(
+                "grpc-status",
+                (tonic::Code::Unimplemented as i32).to_string(),
+            )
+            .header("content-type", "application/grpc")
+            .body(empty_body())
+            .unwrap())
+    })
+}
+
+/// Service of a single request, which tonic calls once
+struct Handler<F>(Option<F>);
+
+impl<Req, Res, F, Fut> UnaryService<Req> for Handler<F>
+where
+    Req: Send + 'static,
+    Res: Send + 'static,
+    F: FnOnce(Req) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Res, Status>> + Send + 'static,
+{
+    type Response = Res;
+    type Future = BoxFuture<Response<Self::Response>, Status>;
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        let response = self.0.take().map(|handler| handler(request.into_inner()));
+        Box::pin(async move {
+            match response {
+                Some(response) => response.await.map(Response::new),
+                None => Err(Status::internal("unary handler called twice")),
+            }
+        })
+    }
+}
+
+impl<Req, Res, S, F, Fut> ServerStreamingService<Req> for Handler<F>
+where
+    Req: Send + 'static,
+    Res: Send + 'static,
+    S: Stream<Item = Result<Res, Status>> + Send + 'static,
+    F: FnOnce(Req) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<S, Status>> + Send + 'static,
+{
+    type Response = Res;
+    type ResponseStream = S;
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        let response = self.0.take().map(|handler| handler(request.into_inner()));
+        Box::pin(async move {
+            match response {
+                Some(response) => response.await.map(Response::new),
+                None => Err(Status::internal("streaming handler called twice")),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct EchoV1 {
+        #[prost(string, tag = "1")]
+        text: String,
+    }
+
+    /// Grpc frame of an uncompressed message
+    fn frame(message: &EchoV1) -> Vec<u8> {
+        let encoded = message.encode_to_vec();
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&encoded);
+        frame
+    }
+
+    fn request(message: &EchoV1) -> http::Request<hyper::Body> {
+        http::Request::builder()
+            .uri("/echo")
+            .header("content-type", "application/grpc")
+            .body(hyper::Body::from(frame(message)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unary_requests_are_served_by_the_handler() {
+        let request = request(&EchoV1 {
+            text: "hello".to_string(),
+        });
+        let response = unary(request, |request: EchoV1| async move {
+            Ok(EchoV1 {
+                text: request.text.to_uppercase(),
+            })
+        })
+        .await
+        .unwrap();
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            body.as_ref(),
+            frame(&EchoV1 {
+                text: "HELLO".to_string()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn handler_errors_are_returned_as_status() {
+        let request = request(&EchoV1::default());
+        let response = unary(request, |_: EchoV1| async move {
+            Err::<EchoV1, _>(Status::permission_denied("denied"))
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response.headers()["grpc-status"],
+            (tonic::Code::PermissionDenied as i32).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_are_unimplemented() {
+        let response = unimplemented().await.unwrap();
+        assert_eq!(
+            response.headers()["grpc-status"],
+            (tonic::Code::Unimplemented as i32).to_string()
+        );
+    }
+}
@@ -0,0 +1,584 @@
+//! TLS for the grpc servers and clients of the oracles.
+//!
+//! A server given [`TlsSettings`] serves the connections of [`incoming`]
+//! instead of listening in plaintext:
+//!
+//! ```ignore
+//! match &settings.tls {
+//!     Some(tls) => {
+//!         let incoming = grpc_service::tls::incoming(tls, addr, shutdown.clone()).await?;
+//!         router.serve_with_incoming_shutdown(incoming, shutdown).await?
+//!     }
+//!     None => router.serve_with_shutdown(addr, shutdown).await?,
+//! }
+//! ```
+//!
+//! The certificates are loaded again from the same files on SIGHUP. Clients
+//! configure their endpoints with the [`ClientTlsSettings`] of the server.
+
+use rustls::{server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore};
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::BufReader,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    signal::unix::{signal, Signal, SignalKind},
+    sync::mpsc,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Certificate as ClientCertificate, ClientTlsConfig, Identity};
+
+/// Connections that have not completed the handshake by then are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const INCOMING_QUEUE: usize = 128;
+
+/// Connections that completed their TLS handshake
+pub type Incoming = ReceiverStream<Result<TlsStream<TcpStream>, std::io::Error>>;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsSettings {
+    /// PEM file with the server certificate chain
+    pub cert: PathBuf,
+    /// PEM file with the private key of the server certificate
+    pub key: PathBuf,
+    /// Optional PEM file with the CA certificates to verify clients against.
+    /// When set every client must present a valid certificate (mTLS)
+    pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ClientTlsSettings {
+    /// PEM file with the CA certificate to verify the server against. The
+    /// system roots are used when not set
+    pub ca_cert: Option<PathBuf>,
+    /// Name to verify the server certificate for. Defaults to the host of
+    /// the url
+    pub domain: Option<String>,
+    /// PEM certificate and key files presented to servers requiring client
+    /// certificates (mTLS)
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TlsError {
+    #[error("error reading {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("no certificates found in {0}")]
+    NoCertificates(PathBuf),
+    #[error("no private key found in {0}")]
+    NoPrivateKey(PathBuf),
+    #[error("tls cert and key must be set together")]
+    IncompleteIdentity,
+    #[error("invalid tls config: {0}")]
+    Config(#[from] rustls::Error),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl ClientTlsSettings {
+    pub fn tls_config(&self) -> Result<ClientTlsConfig, TlsError> {
+        let mut config = ClientTlsConfig::new();
+        if let Some(ca_cert) = &self.ca_cert {
+            config = config.ca_certificate(ClientCertificate::from_pem(read(ca_cert)?));
+        }
+        if let Some(domain) = &self.domain {
+            config = config.domain_name(domain);
+        }
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => {
+                config = config.identity(Identity::from_pem(read(cert)?, read(key)?));
+            }
+            (None, None) => (),
+            _ => return Err(TlsError::IncompleteIdentity),
+        }
+        Ok(config)
+    }
+}
+
+/// Build the rustls server config for the given settings. Client
+/// certificates are required and verified against the client ca when one
+/// is configured.
+pub fn server_config(settings: &TlsSettings) -> Result<rustls::ServerConfig, TlsError> {
+    let certs = load_certs(&settings.cert)?;
+    let key = load_key(&settings.key)?;
+
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let mut config = match &settings.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            let (added, _ignored) = roots.add_parsable_certificates(&read_certs(client_ca)?);
+            if added == 0 {
+                return Err(TlsError::NoCertificates(client_ca.clone()));
+            }
+            builder
+                .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+                .with_single_cert(certs, key)?
+        }
+        None => builder.with_no_client_auth().with_single_cert(certs, key)?,
+    };
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    Ok(config)
+}
+
+/// Accept connections on `addr` until shutdown and complete their TLS
+/// handshake, with the certificates of the settings reloaded on SIGHUP
+pub async fn incoming(
+    settings: &TlsSettings,
+    addr: SocketAddr,
+    shutdown: triggered::Listener,
+) -> Result<Incoming, TlsError> {
+    let acceptor = ReloadableAcceptor::new(settings)?;
+    let sighup = signal(SignalKind::hangup())?;
+    tokio::spawn(acceptor.clone().reload_on(sighup, shutdown.clone()));
+    let listener = TcpListener::bind(addr).await?;
+    tracing::debug!(%addr, mtls = settings.client_ca.is_some(), "serving grpc over tls");
+    Ok(acceptor.incoming(listener, shutdown))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, TlsError> {
+    std::fs::read(path).map_err(|source| TlsError::Read {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|source| TlsError::Read {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Vec<u8>>, TlsError> {
+    rustls_pemfile::certs(&mut open(path)?).map_err(|source| TlsError::Read {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let certs = read_certs(path)?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_path_buf()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let mut reader = open(path)?;
+    loop {
+        let item = rustls_pemfile::read_one(&mut reader).map_err(|source| TlsError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        match item {
+            Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => return Err(TlsError::NoPrivateKey(path.to_path_buf())),
+        }
+    }
+}
+
+/// TLS acceptor whose certificates can be swapped while the server is
+/// running. New connections use the latest loaded certificates, established
+/// connections keep the ones they were accepted with.
+#[derive(Clone)]
+pub struct ReloadableAcceptor {
+    settings: TlsSettings,
+    acceptor: Arc<RwLock<TlsAcceptor>>,
+}
+
+impl ReloadableAcceptor {
+    pub fn new(settings: &TlsSettings) -> Result<Self, TlsError> {
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(settings)?));
+        Ok(Self {
+            settings: settings.clone(),
+            acceptor: Arc::new(RwLock::new(acceptor)),
+        })
+    }
+
+    /// Load the certificates again from the configured files
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(&self.settings)?));
+        *self.acceptor.write().unwrap() = acceptor;
+        Ok(())
+    }
+
+    fn current(&self) -> TlsAcceptor {
+        self.acceptor.read().unwrap().clone()
+    }
+
+    /// Reload the certificates on every signal until shutdown. A failed
+    /// reload is logged and the previous certificates stay in use.
+    async fn reload_on(self, mut signal: Signal, shutdown: triggered::Listener) {
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => return,
+                _ = signal.recv() => match self.reload() {
+                    Ok(()) => tracing::info!("reloaded tls certificates"),
+                    Err(err) => tracing::error!("failed to reload tls certificates: {err}"),
+                },
+            }
+        }
+    }
+
+    /// Accept connections of the listener until shutdown and complete their
+    /// TLS handshake, for use with `serve_with_incoming_shutdown`.
+    /// Connections failing the handshake are logged and dropped without
+    /// affecting the server.
+    pub fn incoming(&self, listener: TcpListener, shutdown: triggered::Listener) -> Incoming {
+        let (tx, rx) = mpsc::channel(INCOMING_QUEUE);
+        let acceptor = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = tokio::select! {
+                    _ = shutdown.clone() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            tracing::warn!("failed to accept connection: {err}");
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    },
+                };
+                let _ = stream.set_nodelay(true);
+                let handshake = acceptor.current().accept(stream);
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send(Ok(stream)).await;
+                        }
+                        Ok(Err(err)) => tracing::debug!(%peer, "tls handshake failed: {err}"),
+                        Err(_) => tracing::debug!(%peer, "tls handshake timed out"),
+                    }
+                });
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa};
+    use rustls::ServerName;
+    use tempfile::TempDir;
+    use tokio_rustls::{client, TlsConnector};
+    use tokio_stream::StreamExt;
+    use tonic::{
+        codec::ProstCodec,
+        codegen::{http, Service},
+        transport::{Body, Endpoint, Server},
+    };
+
+    fn ca(name: &str) -> rcgen::Certificate {
+        let mut params = CertificateParams::new(vec![]);
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        rcgen::Certificate::from_params(params).unwrap()
+    }
+
+    fn leaf() -> rcgen::Certificate {
+        rcgen::Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()]))
+            .unwrap()
+    }
+
+    /// Write the certificate signed by the ca and its key as `<name>.crt`
+    /// and `<name>.key`, returning their paths
+    fn write(
+        dir: &TempDir,
+        name: &str,
+        cert: &rcgen::Certificate,
+        ca: &rcgen::Certificate,
+    ) -> (PathBuf, PathBuf) {
+        let cert_path = dir.path().join(format!("{name}.crt"));
+        let key_path = dir.path().join(format!("{name}.key"));
+        std::fs::write(&cert_path, cert.serialize_pem_with_signer(ca).unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    fn write_ca(dir: &TempDir, name: &str, ca: &rcgen::Certificate) -> PathBuf {
+        let path = dir.path().join(format!("{name}.crt"));
+        std::fs::write(&path, ca.serialize_pem().unwrap()).unwrap();
+        path
+    }
+
+    fn server_settings(dir: &TempDir, ca: &rcgen::Certificate) -> TlsSettings {
+        let (cert, key) = write(dir, "server", &leaf(), ca);
+        TlsSettings {
+            cert,
+            key,
+            client_ca: None,
+        }
+    }
+
+    async fn listen(
+        acceptor: &ReloadableAcceptor,
+        shutdown: triggered::Listener,
+    ) -> (SocketAddr, Incoming) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        (addr, acceptor.incoming(listener, shutdown))
+    }
+
+    /// Complete a TLS handshake trusting only the ca, presenting the
+    /// certificate signed by its ca when given
+    async fn handshake(
+        addr: SocketAddr,
+        ca: &rcgen::Certificate,
+        identity: Option<(&rcgen::Certificate, &rcgen::Certificate)>,
+    ) -> std::io::Result<client::TlsStream<TcpStream>> {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&Certificate(ca.serialize_der().unwrap()))
+            .unwrap();
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let config = match identity {
+            Some((cert, ca)) => builder
+                .with_single_cert(
+                    vec![Certificate(cert.serialize_der_with_signer(ca).unwrap())],
+                    PrivateKey(cert.serialize_private_key_der()),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        let stream = TcpStream::connect(addr).await?;
+        TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+    }
+
+    #[test]
+    fn server_config_needs_certificates_and_a_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = server_settings(&dir, &ca("server ca"));
+        let config = server_config(&settings).unwrap();
+        assert_eq!(config.alpn_protocols, vec![b"h2".to_vec()]);
+
+        let client_ca = write_ca(&dir, "client-ca", &ca("client ca"));
+        assert!(server_config(&TlsSettings {
+            client_ca: Some(client_ca),
+            ..settings.clone()
+        })
+        .is_ok());
+
+        assert!(matches!(
+            server_config(&TlsSettings {
+                key: settings.cert.clone(),
+                ..settings.clone()
+            }),
+            Err(TlsError::NoPrivateKey(_))
+        ));
+        assert!(matches!(
+            server_config(&TlsSettings {
+                cert: settings.key.clone(),
+                ..settings.clone()
+            }),
+            Err(TlsError::NoCertificates(_))
+        ));
+        assert!(matches!(
+            server_config(&TlsSettings {
+                client_ca: Some(settings.key.clone()),
+                ..settings.clone()
+            }),
+            Err(TlsError::NoCertificates(_))
+        ));
+        assert!(matches!(
+            server_config(&TlsSettings {
+                cert: dir.path().join("missing.crt"),
+                ..settings
+            }),
+            Err(TlsError::Read { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn reloaded_certificates_serve_new_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old_ca, new_ca) = (ca("old ca"), ca("new ca"));
+        let settings = server_settings(&dir, &old_ca);
+        let acceptor = ReloadableAcceptor::new(&settings).unwrap();
+        let (trigger, shutdown) = triggered::trigger();
+        let (addr, _incoming) = listen(&acceptor, shutdown).await;
+
+        assert!(handshake(addr, &old_ca, None).await.is_ok());
+        server_settings(&dir, &new_ca);
+        // The files are only read again when reloaded
+        assert!(handshake(addr, &new_ca, None).await.is_err());
+        acceptor.reload().unwrap();
+        assert!(handshake(addr, &new_ca, None).await.is_ok());
+        assert!(handshake(addr, &old_ca, None).await.is_err());
+
+        // A failed reload keeps the certificates in use
+        std::fs::write(&settings.key, "").unwrap();
+        assert!(matches!(acceptor.reload(), Err(TlsError::NoPrivateKey(_))));
+        assert!(handshake(addr, &new_ca, None).await.is_ok());
+        trigger.trigger();
+    }
+
+    #[tokio::test]
+    async fn clients_must_present_a_certificate_of_the_client_ca() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server_ca, client_ca, other_ca) = (ca("server ca"), ca("client ca"), ca("other ca"));
+        let settings = TlsSettings {
+            client_ca: Some(write_ca(&dir, "client-ca", &client_ca)),
+            ..server_settings(&dir, &server_ca)
+        };
+        let acceptor = ReloadableAcceptor::new(&settings).unwrap();
+        let (trigger, shutdown) = triggered::trigger();
+        let (addr, mut incoming) = listen(&acceptor, shutdown).await;
+
+        // With TLS 1.3 the client finishes before the server checked its
+        // certificate, so only the server side tells these apart
+        let _anonymous = handshake(addr, &server_ca, None).await;
+        let other = leaf();
+        let _other = handshake(addr, &server_ca, Some((&other, &other_ca))).await;
+        let client = leaf();
+        let _client = handshake(addr, &server_ca, Some((&client, &client_ca)))
+            .await
+            .unwrap();
+
+        let accepted = tokio::time::timeout(Duration::from_secs(5), incoming.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let (_, connection) = accepted.get_ref();
+        assert_eq!(
+            connection.peer_certificates().map(|certs| certs.len()),
+            Some(1)
+        );
+        trigger.trigger();
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct EchoV1 {
+        #[prost(string, tag = "1")]
+        text: String,
+    }
+
+    /// Service answering every request as unimplemented
+    #[derive(Clone)]
+    struct Unimplemented;
+
+    impl tonic::server::NamedService for Unimplemented {
+        const NAME: &'static str = "test.Unimplemented";
+    }
+
+    impl Service<http::Request<Body>> for Unimplemented {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = crate::ResponseFuture;
+
+        fn poll_ready(
+            &mut self,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: http::Request<Body>) -> Self::Future {
+            crate::unimplemented()
+        }
+    }
+
+    /// Code of the status of a request over a channel with the settings
+    async fn request_code(addr: SocketAddr, settings: &ClientTlsSettings) -> tonic::Code {
+        let channel = Endpoint::from_shared(format!("https://{addr}"))
+            .unwrap()
+            .tls_config(settings.tls_config().unwrap())
+            .unwrap()
+            .connect_lazy();
+        let mut grpc = tonic::client::Grpc::new(channel);
+        if grpc.ready().await.is_err() {
+            return tonic::Code::Unavailable;
+        }
+        let response = grpc
+            .unary(
+                tonic::Request::new(EchoV1::default()),
+                http::uri::PathAndQuery::from_static("/test.Unimplemented/Echo"),
+                ProstCodec::<EchoV1, EchoV1>::default(),
+            )
+            .await;
+        response.map_or_else(|status| status.code(), |_| tonic::Code::Ok)
+    }
+
+    #[tokio::test]
+    async fn client_settings_connect_to_tls_servers() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server_ca, client_ca) = (ca("server ca"), ca("client ca"));
+        let settings = TlsSettings {
+            client_ca: Some(write_ca(&dir, "client-ca", &client_ca)),
+            ..server_settings(&dir, &server_ca)
+        };
+        let (trigger, shutdown) = triggered::trigger();
+        let acceptor = ReloadableAcceptor::new(&settings).unwrap();
+        let (addr, incoming) = listen(&acceptor, shutdown.clone()).await;
+        tokio::spawn(
+            Server::builder()
+                .add_service(Unimplemented)
+                .serve_with_incoming_shutdown(incoming, shutdown),
+        );
+
+        let (cert, key) = write(&dir, "client", &leaf(), &client_ca);
+        let client = ClientTlsSettings {
+            ca_cert: Some(write_ca(&dir, "server-ca", &server_ca)),
+            domain: Some("localhost".to_string()),
+            cert: Some(cert.clone()),
+            key: Some(key),
+        };
+        // The request reaches the service
+        assert_eq!(
+            request_code(addr, &client).await,
+            tonic::Code::Unimplemented
+        );
+        let anonymous = ClientTlsSettings {
+            cert: None,
+            key: None,
+            ..client.clone()
+        };
+        assert_ne!(
+            request_code(addr, &anonymous).await,
+            tonic::Code::Unimplemented
+        );
+
+        assert!(matches!(
+            ClientTlsSettings {
+                key: None,
+                ..client.clone()
+            }
+            .tls_config(),
+            Err(TlsError::IncompleteIdentity)
+        ));
+        assert!(matches!(
+            ClientTlsSettings {
+                ca_cert: Some(dir.path().join("missing.crt")),
+                ..client
+            }
+            .tls_config(),
+            Err(TlsError::Read { .. })
+        ));
+        trigger.trigger();
+    }
+}
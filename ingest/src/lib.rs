@@ -1,5 +1,32 @@
+pub mod rate_limit;
+pub mod receipt;
+pub mod replay;
 pub mod server_iot;
 pub mod server_mobile;
 pub mod settings;
+pub mod validate;
 
 pub use settings::{Mode, Settings};
+
+use file_store::file_sink::FileSinkClient;
+use prost::Message;
+use tonic::Status;
+
+/// Write the report, only returning once the sink has written it so the
+/// submitter learns about reports that were not stored
+pub(crate) async fn write_report<T>(sink: &FileSinkClient, report: T) -> Result<(), Status>
+where
+    T: Message,
+{
+    let written = match sink.write(report, []).await {
+        Ok(on_write) => on_write
+            .await
+            .map_err(|_| file_store::Error::channel())
+            .and_then(|result| result),
+        Err(err) => Err(err),
+    };
+    written.map_err(|err| {
+        tracing::error!("failed to write report: {err:?}");
+        Status::unavailable("failed to store report")
+    })
+}
@@ -3,7 +3,6 @@ use clap::Parser;
 use ingest::{server_iot, server_mobile, Mode, Settings};
 use std::path;
 use tokio::{self, signal};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Debug, clap::Parser)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
@@ -42,15 +41,14 @@ pub struct Server {}
 
 impl Server {
     pub async fn run(&self, settings: &Settings) -> Result<()> {
-        tracing_subscriber::registry()
-            .with(tracing_subscriber::EnvFilter::new(&settings.log))
-            .with(tracing_subscriber::fmt::layer())
-            .init();
+        custom_tracing::init(&settings.log, &settings.tracing)?;
+        poc_metrics::register_error_codes!(poc_metrics, file_store, oracle_signer);
 
         // Install the prometheus metrics exporter
         poc_metrics::start_metrics(&settings.metrics)?;
 
         let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        poc_metrics::process::start(shutdown_listener.clone());
         let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
         tokio::spawn(async move {
             tokio::select! {
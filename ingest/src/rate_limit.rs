@@ -0,0 +1,106 @@
+use crate::settings::RateLimitSettings;
+use helium_crypto::PublicKey;
+use rate_limit::{throttled_status, RateLimiter};
+use std::time::{Duration, Instant};
+use tonic::Status;
+
+pub use rate_limit::RateLimit;
+
+const RATE_LIMITED_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "_rate_limited");
+
+// Number of tracked public keys above which idle buckets are pruned
+const PRUNE_THRESHOLD: usize = 100_000;
+
+/// Token bucket rate limits per report type and submitting public key. Only
+/// reports with a valid signature are counted, so nobody can use up the
+/// limit of another key.
+pub struct PubKeyRateLimiter {
+    limiter: RateLimiter<Vec<u8>>,
+}
+
+impl PubKeyRateLimiter {
+    pub fn from_settings(settings: &RateLimitSettings) -> Result<Self, rate_limit::Error> {
+        Ok(Self {
+            limiter: RateLimiter::new(settings.default, settings.reports.clone(), PRUNE_THRESHOLD)?,
+        })
+    }
+
+    /// Count a report of the given type from `public_key`, failing with
+    /// RESOURCE_EXHAUSTED and a retry-after (seconds) metadata entry when the
+    /// key is over its limit
+    pub fn check(&self, report: &'static str, public_key: &PublicKey) -> Result<(), Status> {
+        self.check_at(report, public_key.to_vec(), Instant::now())
+            .map_err(|retry_after| {
+                tracing::debug!(report, %public_key, "report rate limited");
+                metrics::increment_counter!(RATE_LIMITED_METRIC, "report" => report);
+                throttled_status(retry_after)
+            })
+    }
+
+    fn check_at(&self, report: &'static str, key: Vec<u8>, now: Instant) -> Result<(), Duration> {
+        self.limiter.check_at(report, key, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEARTBEAT: &str = "cell_heartbeat";
+
+    fn limiter(default: Option<RateLimit>, reports: &[(&str, RateLimit)]) -> PubKeyRateLimiter {
+        PubKeyRateLimiter::from_settings(&RateLimitSettings {
+            default,
+            reports: reports
+                .iter()
+                .map(|(report, limit)| (report.to_string(), *limit))
+                .collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn limits_apply_per_report_and_key() {
+        let strict = RateLimit {
+            per_second: 1.0,
+            burst: 1,
+        };
+        let limiter = limiter(None, &[(HEARTBEAT, strict)]);
+        let now = Instant::now();
+
+        assert!(limiter.check_at(HEARTBEAT, vec![1], now).is_ok());
+        assert_eq!(
+            limiter.check_at(HEARTBEAT, vec![1], now),
+            Err(Duration::from_secs(1))
+        );
+        assert!(limiter.check_at(HEARTBEAT, vec![2], now).is_ok());
+        assert!(limiter
+            .check_at(HEARTBEAT, vec![1], now + Duration::from_secs(1))
+            .is_ok());
+        // reports without a limit and no default are never throttled
+        for _ in 0..10 {
+            assert!(limiter.check_at("speedtest", vec![1], now).is_ok());
+        }
+    }
+
+    #[test]
+    fn report_limit_overrides_default() {
+        let default = RateLimit {
+            per_second: 1.0,
+            burst: 1,
+        };
+        let generous = RateLimit {
+            per_second: 10.0,
+            burst: 3,
+        };
+        let limiter = limiter(Some(default), &[(HEARTBEAT, generous)]);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at(HEARTBEAT, vec![1], now).is_ok());
+        }
+        assert!(limiter.check_at(HEARTBEAT, vec![1], now).is_err());
+        assert!(limiter.check_at("speedtest", vec![1], now).is_ok());
+        assert!(limiter.check_at("speedtest", vec![1], now).is_err());
+    }
+}
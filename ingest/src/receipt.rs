@@ -0,0 +1,220 @@
+//! Signed receipts of accepted reports.
+//!
+//! Once a report is accepted, its response carries a receipt in the
+//! `receipt-bin` metadata entry: the encoded [`SubmissionReceiptV1`] with the
+//! sha256 hash of the submitted report, the time it was received and the
+//! signature of the ingest over the receipt with an empty signature.
+//! Gateways keep it as proof of the submission. Receipts are optionally
+//! written to the ingest receipt files too, for later dispute resolution.
+//! A receipt that fails to sign is logged and left out, the report is
+//! accepted regardless.
+
+use crate::Settings;
+use file_store::{
+    file_sink::{FileSink, FileSinkBuilder, FileSinkClient},
+    file_upload::MessageSender,
+    FileType,
+};
+use helium_crypto::PublicKey;
+use oracle_signer::Signer;
+use prost::Message;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{path::Path, sync::Arc};
+use tonic::{metadata::MetadataValue, Response};
+
+pub const RECEIPT_METADATA_KEY: &str = "receipt-bin";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SubmissionReceiptV1 {
+    /// Type of the report, e.g. "cell_heartbeat"
+    #[prost(string, tag = "1")]
+    pub report_type: String,
+    /// Sha256 hash of the report as submitted
+    #[prost(bytes = "vec", tag = "2")]
+    pub report_hash: Vec<u8>,
+    /// Milliseconds since the epoch of when the report was received
+    #[prost(uint64, tag = "3")]
+    pub received_timestamp: u64,
+    /// Public key the report was signed with
+    #[prost(bytes = "vec", tag = "4")]
+    pub submitter: Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReceiptSettings {
+    /// Key the receipts are signed with
+    pub signer: oracle_signer::Settings,
+    /// Write the receipts to the ingest receipt files. Default is false
+    #[serde(default)]
+    pub write: bool,
+}
+
+struct Issuer {
+    signer: Arc<dyn Signer>,
+    sink: Option<FileSinkClient>,
+}
+
+/// Issuer of the receipts, issuing none when receipts are not configured
+#[derive(Default)]
+pub struct Receipts(Option<Issuer>);
+
+impl Receipts {
+    /// Receipts of the settings, with the sink of the receipt files when
+    /// they are written
+    pub async fn from_settings(
+        settings: &Settings,
+        store_base_path: &Path,
+        file_upload_tx: &MessageSender,
+        shutdown: triggered::Listener,
+    ) -> anyhow::Result<(Self, Option<FileSink>)> {
+        let Some(receipts) = &settings.receipts else {
+            return Ok((Self::default(), None));
+        };
+        let signer = oracle_signer::from_settings(&receipts.signer)?;
+        let (sink, sink_server) = if receipts.write {
+            let (sink, sink_server) = FileSinkBuilder::from_settings(
+                FileType::IngestReceipt,
+                store_base_path,
+                concat!(env!("CARGO_PKG_NAME"), "_receipt"),
+                shutdown,
+                &settings.sinks,
+            )
+            .deposits(Some(file_upload_tx.clone()))
+            .create()
+            .await?;
+            (Some(sink), Some(sink_server))
+        } else {
+            (None, None)
+        };
+        Ok((Self(Some(Issuer { signer, sink })), sink_server))
+    }
+
+    /// The unsigned receipt of a report, none when receipts are disabled
+    pub fn prepare<E: Message>(
+        &self,
+        report_type: &'static str,
+        submitter: &PublicKey,
+        report: &E,
+        received_timestamp: u64,
+    ) -> Option<SubmissionReceiptV1> {
+        self.0.as_ref()?;
+        Some(SubmissionReceiptV1 {
+            report_type: report_type.to_string(),
+            report_hash: Sha256::digest(report.encode_to_vec()).to_vec(),
+            received_timestamp,
+            submitter: submitter.to_vec(),
+            signer: vec![],
+            signature: vec![],
+        })
+    }
+
+    /// Sign the receipt of an accepted report and add it to the response
+    pub async fn attach<T>(
+        &self,
+        receipt: Option<SubmissionReceiptV1>,
+        mut response: Response<T>,
+    ) -> Response<T> {
+        let (Some(issuer), Some(receipt)) = (&self.0, receipt) else {
+            return response;
+        };
+        let receipt = match sign(issuer.signer.as_ref(), receipt).await {
+            Ok(receipt) => receipt,
+            Err(err) => {
+                tracing::error!("failed to sign receipt: {err}");
+                metrics::increment_counter!(concat!(env!("CARGO_PKG_NAME"), "_receipt_failures"));
+                return response;
+            }
+        };
+        response.metadata_mut().insert_bin(
+            RECEIPT_METADATA_KEY,
+            MetadataValue::from_bytes(&receipt.encode_to_vec()),
+        );
+        if let Some(sink) = &issuer.sink {
+            _ = sink.write(receipt, []).await;
+        }
+        response
+    }
+}
+
+/// Run the sink of the receipt files, if they are written
+pub async fn run_sink(sink_server: Option<FileSink>) -> anyhow::Result<()> {
+    if let Some(mut sink_server) = sink_server {
+        sink_server.run().await?;
+    }
+    Ok(())
+}
+
+async fn sign(
+    signer: &dyn Signer,
+    mut receipt: SubmissionReceiptV1,
+) -> oracle_signer::Result<SubmissionReceiptV1> {
+    receipt.signer = signer.public_key().into();
+    receipt.signature = vec![];
+    receipt.signature = signer.sign(&receipt.encode_to_vec()).await?;
+    Ok(receipt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_crypto::{KeyTag, KeyType, Keypair, Network, Verify};
+    use helium_proto::services::poc_mobile::SpeedtestReqV1;
+    use oracle_signer::FileSigner;
+
+    fn keypair() -> Keypair {
+        Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::Ed25519,
+            },
+            &mut rand::rngs::OsRng,
+        )
+    }
+
+    #[tokio::test]
+    async fn accepted_reports_get_a_signed_receipt() {
+        let signer = Arc::new(FileSigner::new(keypair()));
+        let ingest_key = signer.public_key().clone();
+        let receipts = Receipts(Some(Issuer { signer, sink: None }));
+        let gateway = keypair().public_key().clone();
+        let report = SpeedtestReqV1 {
+            pub_key: gateway.to_vec(),
+            upload_speed: 10,
+            ..Default::default()
+        };
+
+        let receipt = receipts.prepare("speedtest", &gateway, &report, 1_000);
+        let response = receipts.attach(receipt, Response::new(())).await;
+        let encoded = response
+            .metadata()
+            .get_bin(RECEIPT_METADATA_KEY)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let receipt = SubmissionReceiptV1::decode(encoded).unwrap();
+
+        assert_eq!(receipt.report_type, "speedtest");
+        assert_eq!(
+            receipt.report_hash,
+            Sha256::digest(report.encode_to_vec()).to_vec()
+        );
+        assert_eq!(receipt.received_timestamp, 1_000);
+        assert_eq!(receipt.submitter, gateway.to_vec());
+        assert_eq!(receipt.signer, ingest_key.to_vec());
+        let unsigned = SubmissionReceiptV1 {
+            signature: vec![],
+            ..receipt.clone()
+        };
+        assert!(ingest_key
+            .verify(&unsigned.encode_to_vec(), &receipt.signature)
+            .is_ok());
+
+        let response = Receipts::default().attach(None, Response::new(())).await;
+        assert!(response.metadata().get_bin(RECEIPT_METADATA_KEY).is_none());
+    }
+}
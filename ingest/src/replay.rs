@@ -0,0 +1,288 @@
+//! Replay protection of submitted reports.
+//!
+//! A report is accepted once from each public key. Reports are recognised by
+//! the hash of the report without its signature, as ECDSA signatures are
+//! malleable and a resubmitted report can carry a different but equally
+//! valid signature. Reports whose timestamp is further than the window from
+//! the ingest clock are rejected outright, so reports only need to be
+//! remembered for as long as they could still pass the timestamp check.
+//! Reports without a timestamp are remembered for the window from when they
+//! were first seen. Rejected reports are answered with ALREADY_EXISTS and
+//! counted by report type and reason.
+
+use crate::validate::TimeUnit;
+use chrono::{DateTime, Duration, Utc};
+use helium_crypto::PublicKey;
+use helium_proto::services::{
+    poc_lora::{LoraBeaconReportReqV1, LoraWitnessReportReqV1},
+    poc_mobile::{
+        CellHeartbeatReqV1, CoverageObjectReqV1, DataTransferSessionReqV1, SpeedtestReqV1,
+        SubscriberLocationReqV1,
+    },
+};
+use prost::Message;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Mutex,
+};
+use tonic::Status;
+
+const REPLAY_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "_replay_rejected");
+
+// Number of tracked public keys above which expired reports are pruned
+const PRUNE_THRESHOLD: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ReplaySettings {
+    /// Seconds a report timestamp may be ahead of or behind the ingest
+    /// clock, and for which reports are remembered. Default is 300
+    #[serde(default = "default_window_secs")]
+    pub window_secs: i64,
+}
+
+pub fn default_window_secs() -> i64 {
+    300
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("replay window_secs must be positive, got {0}")]
+pub struct InvalidWindow(i64);
+
+/// A signed report with the timestamp it was signed at, if it has one
+pub trait Submission: Message + Clone {
+    fn clear_signature(&mut self);
+    fn timestamp(&self) -> Option<DateTime<Utc>>;
+
+    /// Hash of the report without its signature
+    fn report_hash(&self) -> ReportHash {
+        let mut unsigned = self.clone();
+        unsigned.clear_signature();
+        Sha256::digest(unsigned.encode_to_vec()).into()
+    }
+}
+
+pub type ReportHash = [u8; 32];
+
+pub struct ReplayGuard {
+    window: Duration,
+    // Expiry of the reports seen, by public key
+    seen: Mutex<HashMap<Vec<u8>, HashMap<ReportHash, DateTime<Utc>>>>,
+}
+
+impl ReplayGuard {
+    pub fn new(settings: &ReplaySettings) -> Result<Self, InvalidWindow> {
+        if settings.window_secs <= 0 {
+            return Err(InvalidWindow(settings.window_secs));
+        }
+        Ok(Self {
+            window: Duration::seconds(settings.window_secs),
+            seen: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Remember the report of `public_key`, failing with ALREADY_EXISTS if
+    /// it was submitted before or its timestamp is outside the window
+    pub fn check<E: Submission>(
+        &self,
+        report: &'static str,
+        public_key: &PublicKey,
+        event: &E,
+    ) -> Result<(), Status> {
+        self.check_at(
+            public_key.to_vec(),
+            event.report_hash(),
+            event.timestamp(),
+            Utc::now(),
+        )
+        .map_err(|reason| {
+            tracing::debug!(report, %public_key, reason, "report rejected as replay");
+            metrics::increment_counter!(REPLAY_METRIC, "report" => report, "reason" => reason);
+            Status::already_exists(format!("{report} rejected as replay: {reason}"))
+        })
+    }
+
+    /// Forget the report again, so a submission that could not be stored can
+    /// be retried
+    pub fn forget(&self, public_key: &PublicKey, report_hash: &ReportHash) {
+        let mut seen = self.seen.lock().expect("replay reports poisoned");
+        if let Some(reports) = seen.get_mut(&public_key.to_vec()) {
+            reports.remove(report_hash);
+        }
+    }
+
+    fn check_at(
+        &self,
+        key: Vec<u8>,
+        report_hash: ReportHash,
+        timestamp: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<(), &'static str> {
+        if timestamp
+            .is_some_and(|timestamp| timestamp > now + self.window || timestamp < now - self.window)
+        {
+            return Err("timestamp");
+        }
+        let expires = timestamp.unwrap_or(now) + self.window;
+
+        let mut seen = self.seen.lock().expect("replay reports poisoned");
+        if seen.len() > PRUNE_THRESHOLD {
+            seen.retain(|_, reports| {
+                reports.retain(|_, expires| *expires > now);
+                !reports.is_empty()
+            });
+        }
+        let reports = seen.entry(key).or_default();
+        reports.retain(|_, expires| *expires > now);
+        match reports.entry(report_hash) {
+            Entry::Occupied(_) => Err("report"),
+            Entry::Vacant(entry) => {
+                entry.insert(expires);
+                Ok(())
+            }
+        }
+    }
+}
+
+// Reports signed with their `timestamp` field in the given unit
+macro_rules! submission {
+    ($report:ty, $unit:expr) => {
+        impl Submission for $report {
+            fn clear_signature(&mut self) {
+                self.signature.clear();
+            }
+
+            fn timestamp(&self) -> Option<DateTime<Utc>> {
+                $unit.to_datetime(self.timestamp)
+            }
+        }
+    };
+}
+
+submission!(LoraBeaconReportReqV1, TimeUnit::Nanos);
+submission!(LoraWitnessReportReqV1, TimeUnit::Nanos);
+submission!(SpeedtestReqV1, TimeUnit::Seconds);
+submission!(CellHeartbeatReqV1, TimeUnit::Seconds);
+submission!(SubscriberLocationReqV1, TimeUnit::Seconds);
+
+impl Submission for DataTransferSessionReqV1 {
+    fn clear_signature(&mut self) {
+        self.signature.clear();
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        let usage = self.data_transfer_usage.as_ref()?;
+        TimeUnit::Seconds.to_datetime(usage.timestamp)
+    }
+}
+
+impl Submission for CoverageObjectReqV1 {
+    fn clear_signature(&mut self) {
+        self.signature.clear();
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> ReplayGuard {
+        ReplayGuard::new(&ReplaySettings { window_secs: 300 }).unwrap()
+    }
+
+    fn hash(byte: u8) -> ReportHash {
+        [byte; 32]
+    }
+
+    #[test]
+    fn reports_are_accepted_once_per_key() {
+        let guard = guard();
+        let now = Utc::now();
+
+        assert_eq!(guard.check_at(vec![1], hash(1), Some(now), now), Ok(()));
+        assert_eq!(
+            guard.check_at(vec![1], hash(1), Some(now), now),
+            Err("report")
+        );
+        assert_eq!(guard.check_at(vec![1], hash(2), Some(now), now), Ok(()));
+        assert_eq!(guard.check_at(vec![2], hash(1), Some(now), now), Ok(()));
+    }
+
+    #[test]
+    fn timestamps_outside_the_window_are_rejected() {
+        let guard = guard();
+        let now = Utc::now();
+
+        for timestamp in [now - Duration::seconds(301), now + Duration::seconds(301)] {
+            assert_eq!(
+                guard.check_at(vec![1], hash(1), Some(timestamp), now),
+                Err("timestamp")
+            );
+        }
+        // A report is remembered until its timestamp leaves the window
+        let signed_at = now - Duration::seconds(200);
+        assert_eq!(
+            guard.check_at(vec![1], hash(1), Some(signed_at), now),
+            Ok(())
+        );
+        let later = now + Duration::seconds(99);
+        assert_eq!(
+            guard.check_at(vec![1], hash(1), Some(signed_at), later),
+            Err("report")
+        );
+        let expired = now + Duration::seconds(101);
+        assert_eq!(
+            guard.check_at(vec![1], hash(1), Some(signed_at), expired),
+            Err("timestamp")
+        );
+    }
+
+    #[test]
+    fn reports_without_timestamp_are_remembered_for_the_window() {
+        let guard = guard();
+        let now = Utc::now();
+
+        assert_eq!(guard.check_at(vec![1], hash(1), None, now), Ok(()));
+        assert_eq!(
+            guard.check_at(vec![1], hash(1), None, now + Duration::seconds(299)),
+            Err("report")
+        );
+        assert_eq!(
+            guard.check_at(vec![1], hash(1), None, now + Duration::seconds(300)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn resigned_reports_are_replays() {
+        let report = SpeedtestReqV1 {
+            pub_key: vec![1],
+            timestamp: 1,
+            signature: vec![1; 64],
+            ..Default::default()
+        };
+        // Like a malleated ECDSA signature of the same report
+        let resigned = SpeedtestReqV1 {
+            signature: vec![2; 64],
+            ..report.clone()
+        };
+        let other = SpeedtestReqV1 {
+            timestamp: 2,
+            ..report.clone()
+        };
+        assert_eq!(report.report_hash(), resigned.report_hash());
+        assert_ne!(report.report_hash(), other.report_hash());
+    }
+
+    #[test]
+    fn window_must_be_positive() {
+        for window_secs in [0, -1] {
+            assert!(ReplayGuard::new(&ReplaySettings { window_secs }).is_err());
+        }
+    }
+}
@@ -1,18 +1,26 @@
-use crate::Settings;
+use crate::{
+    rate_limit::PubKeyRateLimiter,
+    receipt::{self, Receipts},
+    replay::{ReplayGuard, ReportHash, Submission},
+    validate::{self, Rules, ValidationSettings},
+    Settings,
+};
 use anyhow::{Error, Result};
 use chrono::{Duration, Utc};
 use file_store::{
     file_sink::{self, FileSinkClient},
-    file_upload,
+    file_upload, message_bus,
     traits::MsgVerify,
     FileType,
 };
 use futures_util::TryFutureExt;
+use grpc_service::tls;
 use helium_crypto::{Network, PublicKey};
 use helium_proto::services::poc_lora::{
     self, LoraBeaconIngestReportV1, LoraBeaconReportReqV1, LoraBeaconReportRespV1,
     LoraWitnessIngestReportV1, LoraWitnessReportReqV1, LoraWitnessReportRespV1,
 };
+use prost::Message;
 use std::{convert::TryFrom, path::Path};
 use tonic::{transport, Request, Response, Status};
 
@@ -23,21 +31,52 @@ pub struct GrpcServer {
     beacon_report_sink: FileSinkClient,
     witness_report_sink: FileSinkClient,
     required_network: Network,
+    rate_limiter: PubKeyRateLimiter,
+    replay_guard: Option<ReplayGuard>,
+    receipts: Receipts,
+    max_report_size: usize,
+    beacon_rules: Rules<LoraBeaconReportReqV1>,
+    witness_rules: Rules<LoraWitnessReportReqV1>,
 }
 
 impl GrpcServer {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         beacon_report_sink: FileSinkClient,
         witness_report_sink: FileSinkClient,
         required_network: Network,
+        rate_limiter: PubKeyRateLimiter,
+        replay_guard: Option<ReplayGuard>,
+        receipts: Receipts,
+        max_report_size: usize,
+        validation: ValidationSettings,
     ) -> Result<Self> {
         Ok(Self {
             beacon_report_sink,
             witness_report_sink,
             required_network,
+            rate_limiter,
+            replay_guard,
+            receipts,
+            max_report_size,
+            beacon_rules: validate::lora_beacon(validation),
+            witness_rules: validate::lora_witness(validation),
         })
     }
 
+    fn verify_size<E>(&self, event: E) -> VerifyResult<E>
+    where
+        E: Message,
+    {
+        if event.encoded_len() > self.max_report_size {
+            return Err(Status::invalid_argument(format!(
+                "report larger than {} bytes",
+                self.max_report_size
+            )));
+        }
+        Ok(event)
+    }
+
     fn verify_network(&self, public_key: PublicKey) -> VerifyResult<PublicKey> {
         if self.required_network == public_key.network {
             Ok(public_key)
@@ -59,6 +98,43 @@ impl GrpcServer {
             .map_err(|_| Status::invalid_argument("invalid signature"))?;
         Ok((public_key, event))
     }
+
+    fn verify_rate_limit<E>(
+        &self,
+        report: &'static str,
+        (public_key, event): (PublicKey, E),
+    ) -> VerifyResult<(PublicKey, E)> {
+        self.rate_limiter.check(report, &public_key)?;
+        Ok((public_key, event))
+    }
+
+    fn verify_replay<E>(
+        &self,
+        report: &'static str,
+        (public_key, event): (PublicKey, E),
+    ) -> VerifyResult<(PublicKey, E)>
+    where
+        E: Submission,
+    {
+        if let Some(replay_guard) = &self.replay_guard {
+            replay_guard.check(report, &public_key, &event)?;
+        }
+        Ok((public_key, event))
+    }
+
+    /// Forget the report of a failed write, so the gateway can submit it
+    /// again
+    fn forget_replay(
+        &self,
+        public_key: &PublicKey,
+        report_hash: &ReportHash,
+        status: Status,
+    ) -> Status {
+        if let Some(replay_guard) = &self.replay_guard {
+            replay_guard.forget(public_key, report_hash);
+        }
+        status
+    }
 }
 
 #[tonic::async_trait]
@@ -68,21 +144,31 @@ impl poc_lora::PocLora for GrpcServer {
         request: Request<LoraBeaconReportReqV1>,
     ) -> GrpcResult<LoraBeaconReportRespV1> {
         let timestamp: u64 = Utc::now().timestamp_millis() as u64;
-        let event = request.into_inner();
+        let event = self.verify_size(request.into_inner())?;
+        self.beacon_rules.check(&event)?;
 
-        let report = self
+        let (public_key, event) = self
             .verify_public_key(event.pub_key.as_ref())
             .and_then(|public_key| self.verify_network(public_key))
             .and_then(|public_key| self.verify_signature(public_key, event))
-            .map(|(_, event)| LoraBeaconIngestReportV1 {
-                received_timestamp: timestamp,
-                report: Some(event),
-            })?;
+            .and_then(|verified| self.verify_rate_limit("lora_beacon", verified))
+            .and_then(|verified| self.verify_replay("lora_beacon", verified))?;
+        let receipt = self
+            .receipts
+            .prepare("lora_beacon", &public_key, &event, timestamp);
+        let report_hash = event.report_hash();
+        let report = LoraBeaconIngestReportV1 {
+            received_timestamp: timestamp,
+            report: Some(event),
+        };
 
-        _ = self.beacon_report_sink.write(report, []).await;
+        crate::write_report(&self.beacon_report_sink, report)
+            .await
+            .map_err(|status| self.forget_replay(&public_key, &report_hash, status))?;
 
         let id = timestamp.to_string();
-        Ok(Response::new(LoraBeaconReportRespV1 { id }))
+        let response = Response::new(LoraBeaconReportRespV1 { id });
+        Ok(self.receipts.attach(receipt, response).await)
     }
 
     async fn submit_lora_witness(
@@ -90,21 +176,31 @@ impl poc_lora::PocLora for GrpcServer {
         request: Request<LoraWitnessReportReqV1>,
     ) -> GrpcResult<LoraWitnessReportRespV1> {
         let timestamp: u64 = Utc::now().timestamp_millis() as u64;
-        let event = request.into_inner();
+        let event = self.verify_size(request.into_inner())?;
+        self.witness_rules.check(&event)?;
 
-        let report = self
+        let (public_key, event) = self
             .verify_public_key(event.pub_key.as_ref())
             .and_then(|public_key| self.verify_network(public_key))
             .and_then(|public_key| self.verify_signature(public_key, event))
-            .map(|(_, event)| LoraWitnessIngestReportV1 {
-                received_timestamp: timestamp,
-                report: Some(event),
-            })?;
+            .and_then(|verified| self.verify_rate_limit("lora_witness", verified))
+            .and_then(|verified| self.verify_replay("lora_witness", verified))?;
+        let receipt = self
+            .receipts
+            .prepare("lora_witness", &public_key, &event, timestamp);
+        let report_hash = event.report_hash();
+        let report = LoraWitnessIngestReportV1 {
+            received_timestamp: timestamp,
+            report: Some(event),
+        };
 
-        _ = self.witness_report_sink.write(report, []).await;
+        crate::write_report(&self.witness_report_sink, report)
+            .await
+            .map_err(|status| self.forget_replay(&public_key, &report_hash, status))?;
 
         let id = timestamp.to_string();
-        Ok(Response::new(LoraWitnessReportRespV1 { id }))
+        let response = Response::new(LoraWitnessReportRespV1 { id });
+        Ok(self.receipts.attach(receipt, response).await)
     }
 }
 
@@ -118,48 +214,237 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
 
     let store_base_path = Path::new(&settings.cache);
 
+    let publisher = match &settings.message_bus {
+        Some(message_bus) => Some(message_bus::connect(message_bus).await?),
+        None => None,
+    };
+
     // iot beacon reports
-    let (beacon_report_sink, mut beacon_report_sink_server) = file_sink::FileSinkBuilder::new(
+    let (beacon_report_tee, beacon_report_tee_publisher) = message_bus::sink_tee(
+        publisher.as_ref(),
+        &settings.sinks,
         FileType::IotBeaconIngestReport,
-        store_base_path,
-        concat!(env!("CARGO_PKG_NAME"), "_beacon_report"),
-        shutdown.clone(),
     )
-    .deposits(Some(file_upload_tx.clone()))
-    .roll_time(Duration::minutes(5))
-    .create()
-    .await?;
+    .unzip();
+    let (beacon_report_sink, mut beacon_report_sink_server) =
+        file_sink::FileSinkBuilder::from_settings(
+            FileType::IotBeaconIngestReport,
+            store_base_path,
+            concat!(env!("CARGO_PKG_NAME"), "_beacon_report"),
+            shutdown.clone(),
+            &settings.sinks,
+        )
+        .deposits(Some(file_upload_tx.clone()))
+        .tee(beacon_report_tee)
+        .roll_time(Duration::minutes(5))
+        .create()
+        .await?;
 
     // iot witness reports
-    let (witness_report_sink, mut witness_report_sink_server) = file_sink::FileSinkBuilder::new(
+    let (witness_report_tee, witness_report_tee_publisher) = message_bus::sink_tee(
+        publisher.as_ref(),
+        &settings.sinks,
         FileType::IotWitnessIngestReport,
-        store_base_path,
-        concat!(env!("CARGO_PKG_NAME"), "_witness_report"),
-        shutdown.clone(),
     )
-    .deposits(Some(file_upload_tx.clone()))
-    .roll_time(Duration::minutes(5))
-    .create()
-    .await?;
+    .unzip();
+    let (witness_report_sink, mut witness_report_sink_server) =
+        file_sink::FileSinkBuilder::from_settings(
+            FileType::IotWitnessIngestReport,
+            store_base_path,
+            concat!(env!("CARGO_PKG_NAME"), "_witness_report"),
+            shutdown.clone(),
+            &settings.sinks,
+        )
+        .deposits(Some(file_upload_tx.clone()))
+        .tee(witness_report_tee)
+        .roll_time(Duration::minutes(5))
+        .create()
+        .await?;
 
-    let grpc_server = GrpcServer::new(beacon_report_sink, witness_report_sink, settings.network)?;
+    // signed receipts of the accepted reports
+    let (receipts, receipt_sink_server) =
+        Receipts::from_settings(settings, store_base_path, &file_upload_tx, shutdown.clone())
+            .await?;
+
+    let grpc_server = GrpcServer::new(
+        beacon_report_sink,
+        witness_report_sink,
+        settings.network,
+        PubKeyRateLimiter::from_settings(&settings.rate_limits)?,
+        settings.replay.as_ref().map(ReplayGuard::new).transpose()?,
+        receipts,
+        settings.max_report_size,
+        settings.validation,
+    )?;
 
     tracing::info!(
         "grpc listening on {grpc_addr} and server mode {:?}",
         settings.mode
     );
 
-    let server = transport::Server::builder()
+    let router = transport::Server::builder()
         .layer(poc_metrics::request_layer!("ingest_server_iot_connection"))
-        .add_service(poc_lora::Server::new(grpc_server))
-        .serve_with_shutdown(grpc_addr, shutdown.clone())
-        .map_err(Error::from);
+        .add_service(poc_lora::Server::new(grpc_server));
+    let server = async {
+        match &settings.tls {
+            Some(tls) => {
+                let incoming = tls::incoming(tls, grpc_addr, shutdown.clone()).await?;
+                router
+                    .serve_with_incoming_shutdown(incoming, shutdown.clone())
+                    .await?
+            }
+            None => {
+                router
+                    .serve_with_shutdown(grpc_addr, shutdown.clone())
+                    .await?
+            }
+        }
+        Ok::<(), Error>(())
+    };
 
     tokio::try_join!(
         server,
         beacon_report_sink_server.run().map_err(Error::from),
         witness_report_sink_server.run().map_err(Error::from),
+        message_bus::run(beacon_report_tee_publisher, &shutdown).map_err(Error::from),
+        message_bus::run(witness_report_tee_publisher, &shutdown).map_err(Error::from),
+        receipt::run_sink(receipt_sink_server),
         file_upload.run(&shutdown).map_err(Error::from),
     )
     .map(|_| ())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rate_limit::RateLimit, settings::RateLimitSettings};
+    use file_store::file_sink::FileSink;
+    use helium_crypto::{KeyTag, KeyType, Keypair, Sign};
+    use helium_proto::services::poc_lora::PocLora;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    fn keypair() -> Keypair {
+        Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::Ed25519,
+            },
+            &mut rand::rngs::OsRng,
+        )
+    }
+
+    fn beacon(keypair: &Keypair, data: Vec<u8>) -> LoraBeaconReportReqV1 {
+        let mut report = LoraBeaconReportReqV1 {
+            pub_key: keypair.public_key().to_vec(),
+            data,
+            timestamp: Utc::now().timestamp_nanos() as u64,
+            ..Default::default()
+        };
+        report.signature = keypair.sign(&report.encode_to_vec()).unwrap();
+        report
+    }
+
+    async fn server(
+        tmp_dir: &TempDir,
+        shutdown: &triggered::Listener,
+        max_report_size: usize,
+        limits: HashMap<String, RateLimit>,
+    ) -> (GrpcServer, FileSink, FileSink) {
+        let sink = |file_type: FileType| {
+            file_sink::FileSinkBuilder::new(
+                file_type,
+                tmp_dir.path(),
+                "fake_metric",
+                shutdown.clone(),
+            )
+            .create()
+        };
+        let (beacon_report_sink, beacon_report_sink_server) =
+            sink(FileType::IotBeaconIngestReport).await.unwrap();
+        let (witness_report_sink, witness_report_sink_server) =
+            sink(FileType::IotWitnessIngestReport).await.unwrap();
+        let rate_limiter = PubKeyRateLimiter::from_settings(&RateLimitSettings {
+            default: None,
+            reports: limits,
+        })
+        .unwrap();
+        let server = GrpcServer::new(
+            beacon_report_sink,
+            witness_report_sink,
+            Network::MainNet,
+            rate_limiter,
+            None,
+            Receipts::default(),
+            max_report_size,
+            ValidationSettings::default(),
+        )
+        .unwrap();
+        (
+            server,
+            beacon_report_sink_server,
+            witness_report_sink_server,
+        )
+    }
+
+    #[tokio::test]
+    async fn reports_larger_than_the_max_size_are_rejected() {
+        let tmp_dir = TempDir::new().unwrap();
+        let (_trigger, shutdown) = triggered::trigger();
+        let (server, _beacons, _witnesses) = server(&tmp_dir, &shutdown, 200, HashMap::new()).await;
+
+        let status = server
+            .submit_lora_beacon(Request::new(beacon(&keypair(), vec![0; 200])))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn reports_are_rate_limited_per_key() {
+        let tmp_dir = TempDir::new().unwrap();
+        let (_trigger, shutdown) = triggered::trigger();
+        let limit = RateLimit {
+            per_second: 0.001,
+            burst: 1,
+        };
+        let (server, mut beacons, _witnesses) = server(
+            &tmp_dir,
+            &shutdown,
+            2048,
+            HashMap::from([("lora_beacon".to_string(), limit)]),
+        )
+        .await;
+        tokio::spawn(async move { beacons.run().await });
+        let gateway = keypair();
+
+        assert!(server
+            .submit_lora_beacon(Request::new(beacon(&gateway, vec![1])))
+            .await
+            .is_ok());
+        let status = server
+            .submit_lora_beacon(Request::new(beacon(&gateway, vec![2])))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        // other keys have a limit of their own
+        assert!(server
+            .submit_lora_beacon(Request::new(beacon(&keypair(), vec![1])))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn reports_that_are_not_stored_are_unavailable() {
+        let tmp_dir = TempDir::new().unwrap();
+        let (_trigger, shutdown) = triggered::trigger();
+        let (server, beacons, _witnesses) = server(&tmp_dir, &shutdown, 2048, HashMap::new()).await;
+        drop(beacons);
+
+        let status = server
+            .submit_lora_beacon(Request::new(beacon(&keypair(), vec![1])))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+}
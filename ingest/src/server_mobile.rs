@@ -1,4 +1,10 @@
-use crate::Settings;
+use crate::{
+    rate_limit::PubKeyRateLimiter,
+    receipt::{self, Receipts},
+    replay::{ReplayGuard, ReportHash, Submission},
+    validate::{self, Rules, ValidationSettings},
+    Settings,
+};
 use anyhow::{bail, Error, Result};
 use chrono::{Duration, Utc};
 use file_store::{
@@ -8,6 +14,7 @@ use file_store::{
     FileType,
 };
 use futures_util::TryFutureExt;
+use grpc_service::tls;
 use helium_crypto::{Network, PublicKey};
 use helium_proto::services::poc_mobile::{
     self, CellHeartbeatIngestReportV1, CellHeartbeatReqV1, CellHeartbeatRespV1,
@@ -31,9 +38,18 @@ pub struct GrpcServer {
     subscriber_location_report_sink: FileSinkClient,
     coverage_object_report_sink: FileSinkClient,
     required_network: Network,
+    rate_limiter: PubKeyRateLimiter,
+    replay_guard: Option<ReplayGuard>,
+    receipts: Receipts,
+    speedtest_rules: Rules<SpeedtestReqV1>,
+    heartbeat_rules: Rules<CellHeartbeatReqV1>,
+    data_transfer_session_rules: Rules<DataTransferSessionReqV1>,
+    subscriber_location_rules: Rules<SubscriberLocationReqV1>,
+    coverage_object_rules: Rules<CoverageObjectReqV1>,
 }
 
 impl GrpcServer {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         heartbeat_report_sink: FileSinkClient,
         speedtest_report_sink: FileSinkClient,
@@ -41,6 +57,10 @@ impl GrpcServer {
         subscriber_location_report_sink: FileSinkClient,
         coverage_object_report_sink: FileSinkClient,
         required_network: Network,
+        rate_limiter: PubKeyRateLimiter,
+        replay_guard: Option<ReplayGuard>,
+        receipts: Receipts,
+        validation: ValidationSettings,
     ) -> Result<Self> {
         Ok(Self {
             heartbeat_report_sink,
@@ -49,6 +69,14 @@ impl GrpcServer {
             subscriber_location_report_sink,
             coverage_object_report_sink,
             required_network,
+            rate_limiter,
+            replay_guard,
+            receipts,
+            speedtest_rules: validate::speedtest(validation),
+            heartbeat_rules: validate::cell_heartbeat(validation),
+            data_transfer_session_rules: validate::data_transfer_session(validation),
+            subscriber_location_rules: validate::subscriber_location(validation),
+            coverage_object_rules: validate::coverage_object(validation),
         })
     }
 
@@ -73,6 +101,43 @@ impl GrpcServer {
             .map_err(|_| Status::invalid_argument("invalid signature"))?;
         Ok((public_key, event))
     }
+
+    fn verify_rate_limit<E>(
+        &self,
+        report: &'static str,
+        (public_key, event): (PublicKey, E),
+    ) -> VerifyResult<(PublicKey, E)> {
+        self.rate_limiter.check(report, &public_key)?;
+        Ok((public_key, event))
+    }
+
+    /// Forget the report of a failed write, so the client can submit it
+    /// again
+    fn forget_replay(
+        &self,
+        public_key: &PublicKey,
+        report_hash: &ReportHash,
+        status: Status,
+    ) -> Status {
+        if let Some(replay_guard) = &self.replay_guard {
+            replay_guard.forget(public_key, report_hash);
+        }
+        status
+    }
+
+    fn verify_replay<E>(
+        &self,
+        report: &'static str,
+        (public_key, event): (PublicKey, E),
+    ) -> VerifyResult<(PublicKey, E)>
+    where
+        E: Submission,
+    {
+        if let Some(replay_guard) = &self.replay_guard {
+            replay_guard.check(report, &public_key, &event)?;
+        }
+        Ok((public_key, event))
+    }
 }
 
 #[tonic::async_trait]
@@ -83,20 +148,33 @@ impl poc_mobile::PocMobile for GrpcServer {
     ) -> GrpcResult<SpeedtestRespV1> {
         let timestamp: u64 = Utc::now().timestamp_millis() as u64;
         let event = request.into_inner();
+        self.speedtest_rules.check(&event)?;
 
-        let report = self
+        let (public_key, report_hash, report, receipt) = self
             .verify_public_key(event.pub_key.as_ref())
             .and_then(|public_key| self.verify_network(public_key))
             .and_then(|public_key| self.verify_signature(public_key, event))
-            .map(|(_, event)| SpeedtestIngestReportV1 {
-                received_timestamp: timestamp,
-                report: Some(event),
+            .and_then(|verified| self.verify_rate_limit("speedtest", verified))
+            .and_then(|verified| self.verify_replay("speedtest", verified))
+            .map(|(public_key, event)| {
+                let report_hash = event.report_hash();
+                let receipt = self
+                    .receipts
+                    .prepare("speedtest", &public_key, &event, timestamp);
+                let report = SpeedtestIngestReportV1 {
+                    received_timestamp: timestamp,
+                    report: Some(event),
+                };
+                (public_key, report_hash, report, receipt)
             })?;
 
-        _ = self.speedtest_report_sink.write(report, []).await;
+        crate::write_report(&self.speedtest_report_sink, report)
+            .await
+            .map_err(|status| self.forget_replay(&public_key, &report_hash, status))?;
 
         let id = timestamp.to_string();
-        Ok(Response::new(SpeedtestRespV1 { id }))
+        let response = Response::new(SpeedtestRespV1 { id });
+        Ok(self.receipts.attach(receipt, response).await)
     }
 
     async fn submit_cell_heartbeat(
@@ -105,20 +183,33 @@ impl poc_mobile::PocMobile for GrpcServer {
     ) -> GrpcResult<CellHeartbeatRespV1> {
         let timestamp: u64 = Utc::now().timestamp_millis() as u64;
         let event = request.into_inner();
+        self.heartbeat_rules.check(&event)?;
 
-        let report = self
+        let (public_key, report_hash, report, receipt) = self
             .verify_public_key(event.pub_key.as_ref())
             .and_then(|public_key| self.verify_network(public_key))
             .and_then(|public_key| self.verify_signature(public_key, event))
-            .map(|(_, event)| CellHeartbeatIngestReportV1 {
-                received_timestamp: timestamp,
-                report: Some(event),
+            .and_then(|verified| self.verify_rate_limit("cell_heartbeat", verified))
+            .and_then(|verified| self.verify_replay("cell_heartbeat", verified))
+            .map(|(public_key, event)| {
+                let report_hash = event.report_hash();
+                let receipt =
+                    self.receipts
+                        .prepare("cell_heartbeat", &public_key, &event, timestamp);
+                let report = CellHeartbeatIngestReportV1 {
+                    received_timestamp: timestamp,
+                    report: Some(event),
+                };
+                (public_key, report_hash, report, receipt)
             })?;
 
-        _ = self.heartbeat_report_sink.write(report, []).await;
+        crate::write_report(&self.heartbeat_report_sink, report)
+            .await
+            .map_err(|status| self.forget_replay(&public_key, &report_hash, status))?;
 
         let id = timestamp.to_string();
-        Ok(Response::new(CellHeartbeatRespV1 { id }))
+        let response = Response::new(CellHeartbeatRespV1 { id });
+        Ok(self.receipts.attach(receipt, response).await)
     }
 
     async fn submit_data_transfer_session(
@@ -127,21 +218,34 @@ impl poc_mobile::PocMobile for GrpcServer {
     ) -> GrpcResult<DataTransferSessionRespV1> {
         let timestamp = Utc::now().timestamp_millis() as u64;
         let event = request.into_inner();
+        self.data_transfer_session_rules.check(&event)?;
 
-        let report = self
+        let (public_key, report_hash, report, receipt) = self
             .verify_public_key(event.pub_key.as_ref())
             .and_then(|public_key| self.verify_network(public_key))
             .and_then(|public_key| self.verify_signature(public_key, event))
-            .map(|(_, event)| DataTransferSessionIngestReportV1 {
-                received_timestamp: timestamp,
-                report: Some(event),
+            .and_then(|verified| self.verify_rate_limit("data_transfer_session", verified))
+            .and_then(|verified| self.verify_replay("data_transfer_session", verified))
+            .map(|(public_key, event)| {
+                let report_hash = event.report_hash();
+                let receipt =
+                    self.receipts
+                        .prepare("data_transfer_session", &public_key, &event, timestamp);
+                let report = DataTransferSessionIngestReportV1 {
+                    received_timestamp: timestamp,
+                    report: Some(event),
+                };
+                (public_key, report_hash, report, receipt)
             })?;
 
-        _ = self.data_transfer_session_sink.write(report, []).await;
+        crate::write_report(&self.data_transfer_session_sink, report)
+            .await
+            .map_err(|status| self.forget_replay(&public_key, &report_hash, status))?;
 
-        Ok(Response::new(DataTransferSessionRespV1 {
+        let response = Response::new(DataTransferSessionRespV1 {
             id: timestamp.to_string(),
-        }))
+        });
+        Ok(self.receipts.attach(receipt, response).await)
     }
 
     async fn submit_subscriber_location(
@@ -152,14 +256,24 @@ impl poc_mobile::PocMobile for GrpcServer {
         let event = request.into_inner();
         let subscriber_id = event.subscriber_id.clone();
         let timestamp_millis = event.timestamp;
+        self.subscriber_location_rules.check(&event)?;
 
-        let report = self
+        let (public_key, report_hash, report, receipt) = self
             .verify_public_key(event.carrier_pub_key.as_ref())
             .and_then(|public_key| self.verify_network(public_key))
             .and_then(|public_key| self.verify_signature(public_key, event))
-            .map(|(_, event)| SubscriberLocationIngestReportV1 {
-                received_timestamp: timestamp,
-                report: Some(event),
+            .and_then(|verified| self.verify_rate_limit("subscriber_location", verified))
+            .and_then(|verified| self.verify_replay("subscriber_location", verified))
+            .map(|(public_key, event)| {
+                let report_hash = event.report_hash();
+                let receipt =
+                    self.receipts
+                        .prepare("subscriber_location", &public_key, &event, timestamp);
+                let report = SubscriberLocationIngestReportV1 {
+                    received_timestamp: timestamp,
+                    report: Some(event),
+                };
+                (public_key, report_hash, report, receipt)
             })
             .map_err(|status| {
                 tracing::debug!(
@@ -170,11 +284,14 @@ impl poc_mobile::PocMobile for GrpcServer {
                 status
             })?;
 
-        _ = self.subscriber_location_report_sink.write(report, []).await;
+        crate::write_report(&self.subscriber_location_report_sink, report)
+            .await
+            .map_err(|status| self.forget_replay(&public_key, &report_hash, status))?;
 
-        Ok(Response::new(SubscriberLocationRespV1 {
+        let response = Response::new(SubscriberLocationRespV1 {
             id: timestamp.to_string(),
-        }))
+        });
+        Ok(self.receipts.attach(receipt, response).await)
     }
 
     async fn submit_coverage_object(
@@ -183,20 +300,33 @@ impl poc_mobile::PocMobile for GrpcServer {
     ) -> GrpcResult<CoverageObjectRespV1> {
         let timestamp: u64 = Utc::now().timestamp_millis() as u64;
         let event = request.into_inner();
+        self.coverage_object_rules.check(&event)?;
 
-        let report = self
+        let (public_key, report_hash, report, receipt) = self
             .verify_public_key(event.pub_key.as_ref())
             .and_then(|public_key| self.verify_network(public_key))
             .and_then(|public_key| self.verify_signature(public_key, event))
-            .map(|(_, event)| CoverageObjectIngestReportV1 {
-                received_timestamp: timestamp,
-                report: Some(event),
+            .and_then(|verified| self.verify_rate_limit("coverage_object", verified))
+            .and_then(|verified| self.verify_replay("coverage_object", verified))
+            .map(|(public_key, event)| {
+                let report_hash = event.report_hash();
+                let receipt =
+                    self.receipts
+                        .prepare("coverage_object", &public_key, &event, timestamp);
+                let report = CoverageObjectIngestReportV1 {
+                    received_timestamp: timestamp,
+                    report: Some(event),
+                };
+                (public_key, report_hash, report, receipt)
             })?;
 
-        _ = self.coverage_object_report_sink.write(report, []).await;
+        crate::write_report(&self.coverage_object_report_sink, report)
+            .await
+            .map_err(|status| self.forget_replay(&public_key, &report_hash, status))?;
 
         let id = timestamp.to_string();
-        Ok(Response::new(CoverageObjectRespV1 { id }))
+        let response = Response::new(CoverageObjectRespV1 { id });
+        Ok(self.receipts.attach(receipt, response).await)
     }
 }
 
@@ -211,11 +341,12 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
     let store_base_path = Path::new(&settings.cache);
 
     let (heartbeat_report_sink, mut heartbeat_report_sink_server) =
-        file_sink::FileSinkBuilder::new(
+        file_sink::FileSinkBuilder::from_settings(
             FileType::CellHeartbeatIngestReport,
             store_base_path,
             concat!(env!("CARGO_PKG_NAME"), "_heartbeat_report"),
             shutdown.clone(),
+            &settings.sinks,
         )
         .deposits(Some(file_upload_tx.clone()))
         .roll_time(Duration::minutes(INGEST_WAIT_DURATION_MINUTES))
@@ -224,11 +355,12 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
 
     // speedtests
     let (speedtest_report_sink, mut speedtest_report_sink_server) =
-        file_sink::FileSinkBuilder::new(
+        file_sink::FileSinkBuilder::from_settings(
             FileType::CellSpeedtestIngestReport,
             store_base_path,
             concat!(env!("CARGO_PKG_NAME"), "_speedtest_report"),
             shutdown.clone(),
+            &settings.sinks,
         )
         .deposits(Some(file_upload_tx.clone()))
         .roll_time(Duration::minutes(INGEST_WAIT_DURATION_MINUTES))
@@ -236,7 +368,7 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
         .await?;
 
     let (data_transfer_session_sink, mut data_transfer_session_sink_server) =
-        file_sink::FileSinkBuilder::new(
+        file_sink::FileSinkBuilder::from_settings(
             FileType::DataTransferSessionIngestReport,
             store_base_path,
             concat!(
@@ -244,6 +376,7 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
                 "_mobile_data_transfer_session_report"
             ),
             shutdown.clone(),
+            &settings.sinks,
         )
         .deposits(Some(file_upload_tx.clone()))
         .roll_time(Duration::minutes(INGEST_WAIT_DURATION_MINUTES))
@@ -251,11 +384,12 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
         .await?;
 
     let (subscriber_location_report_sink, mut subscriber_location_report_sink_server) =
-        file_sink::FileSinkBuilder::new(
+        file_sink::FileSinkBuilder::from_settings(
             FileType::SubscriberLocationIngestReport,
             store_base_path,
             concat!(env!("CARGO_PKG_NAME"), "_subscriber_location_report"),
             shutdown.clone(),
+            &settings.sinks,
         )
         .deposits(Some(file_upload_tx.clone()))
         .roll_time(Duration::minutes(INGEST_WAIT_DURATION_MINUTES))
@@ -263,17 +397,23 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
         .await?;
 
     let (coverage_object_report_sink, mut coverage_object_report_sink_server) =
-        file_sink::FileSinkBuilder::new(
+        file_sink::FileSinkBuilder::from_settings(
             FileType::CoverageObjectIngestReport,
             store_base_path,
             concat!(env!("CARGO_PKG_NAME"), "_coverage_object_report"),
             shutdown.clone(),
+            &settings.sinks,
         )
         .deposits(Some(file_upload_tx.clone()))
         .roll_time(Duration::minutes(INGEST_WAIT_DURATION_MINUTES))
         .create()
         .await?;
 
+    // signed receipts of the accepted reports
+    let (receipts, receipt_sink_server) =
+        Receipts::from_settings(settings, store_base_path, &file_upload_tx, shutdown.clone())
+            .await?;
+
     let grpc_server = GrpcServer::new(
         heartbeat_report_sink,
         speedtest_report_sink,
@@ -281,18 +421,19 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
         subscriber_location_report_sink,
         coverage_object_report_sink,
         settings.network,
+        PubKeyRateLimiter::from_settings(&settings.rate_limits)?,
+        settings.replay.as_ref().map(ReplayGuard::new).transpose()?,
+        receipts,
+        settings.validation,
     )?;
 
     let Some(api_token) = settings
         .token
         .as_ref()
-        .and_then(|token| {
-            format!("Bearer {token}")
-                .parse::<MetadataValue<_>>()
-                .ok()
-        }) else {
-            bail!("expected valid api token in settings");
-        };
+        .and_then(|token| format!("Bearer {token}").parse::<MetadataValue<_>>().ok())
+    else {
+        bail!("expected valid api token in settings");
+    };
 
     tracing::info!(
         "grpc listening on {grpc_addr} and server mode {:?}",
@@ -301,7 +442,7 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
 
     //TODO start a service with either the poc mobile or poc iot endpoints only - not both
     //     use _server_mode (set above ) to decide
-    let server = transport::Server::builder()
+    let router = transport::Server::builder()
         .layer(poc_metrics::request_layer!("ingest_server_grpc_connection"))
         .add_service(poc_mobile::Server::with_interceptor(
             grpc_server,
@@ -309,9 +450,23 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
                 Some(t) if api_token == t => Ok(req),
                 _ => Err(Status::unauthenticated("No valid auth token")),
             },
-        ))
-        .serve_with_shutdown(grpc_addr, shutdown.clone())
-        .map_err(Error::from);
+        ));
+    let server = async {
+        match &settings.tls {
+            Some(tls) => {
+                let incoming = tls::incoming(tls, grpc_addr, shutdown.clone()).await?;
+                router
+                    .serve_with_incoming_shutdown(incoming, shutdown.clone())
+                    .await?
+            }
+            None => {
+                router
+                    .serve_with_shutdown(grpc_addr, shutdown.clone())
+                    .await?
+            }
+        }
+        Ok::<(), Error>(())
+    };
 
     tokio::try_join!(
         server,
@@ -324,6 +479,7 @@ pub async fn grpc_server(shutdown: triggered::Listener, settings: &Settings) ->
         coverage_object_report_sink_server
             .run()
             .map_err(Error::from),
+        receipt::run_sink(receipt_sink_server),
         file_upload.run(&shutdown).map_err(Error::from),
     )
     .map(|_| ())
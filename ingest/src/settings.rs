@@ -1,7 +1,13 @@
+use crate::{
+    rate_limit::RateLimit, receipt::ReceiptSettings, replay::ReplaySettings,
+    validate::ValidationSettings,
+};
 use config::{Config, Environment, File};
+use grpc_service::tls::TlsSettings;
 use helium_crypto::Network;
 use serde::Deserialize;
 use std::{
+    collections::HashMap,
     net::{AddrParseError, SocketAddr},
     path::Path,
     str::FromStr,
@@ -13,11 +19,16 @@ pub struct Settings {
     /// "ingest=debug,poc_store=info"
     #[serde(default = "default_log")]
     pub log: String,
+    /// Log output format, per module log levels and runtime diagnostics
+    #[serde(default)]
+    pub tracing: custom_tracing::Settings,
     /// Mode to run the server in (iot or mobile). Required
     pub mode: Mode,
     /// Listen address. Required. Default is 0.0.0.0:9081
     #[serde(default = "default_listen_addr")]
     pub listen: String,
+    /// Serve grpc over TLS when set. Plaintext by default
+    pub tls: Option<TlsSettings>,
     /// Local folder for storing intermediate files
     pub cache: String,
     /// Network required in all public keys:  mainnet | testnet
@@ -25,17 +36,54 @@ pub struct Settings {
     /// Settings for exposed public API
     /// Target bucket for uploads
     pub output: file_store::Settings,
+    /// Output policies of the report sinks keyed by file type
+    #[serde(default)]
+    pub sinks: file_store::SinksSettings,
     /// API token required as part of a Bearer authentication GRPC request
     /// header. Used only by the mobile mode currently
     pub token: Option<String>,
+    /// Per public key rate limits for submitted reports. No limits by default
+    #[serde(default)]
+    pub rate_limits: RateLimitSettings,
+    /// Maximum encoded size in bytes of a submitted report. Used only by the
+    /// iot mode currently. (Default is 2048)
+    #[serde(default = "default_max_report_size")]
+    pub max_report_size: usize,
+    /// Validation of the public keys, timestamps and payloads of submitted
+    /// reports
+    #[serde(default)]
+    pub validation: ValidationSettings,
+    /// Rejection of reports submitted again by the same public key. Disabled
+    /// when not set
+    pub replay: Option<ReplaySettings>,
+    /// Signed receipts returned for accepted reports. No receipts are
+    /// issued when not set
+    pub receipts: Option<ReceiptSettings>,
+    /// Message bus the report sinks with a publish topic publish to. Used
+    /// only by the iot mode currently. Disabled when not set
+    pub message_bus: Option<file_store::message_bus::Settings>,
     /// Target output bucket details Metrics settings
     pub metrics: poc_metrics::Settings,
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct RateLimitSettings {
+    /// Limit applied to each public key for any report type without its own
+    /// limit
+    pub default: Option<RateLimit>,
+    /// Limits for each public key keyed by report type, e.g. "cell_heartbeat"
+    #[serde(default)]
+    pub reports: HashMap<String, RateLimit>,
+}
+
 pub fn default_listen_addr() -> String {
     "0.0.0.0:9081".to_string()
 }
 
+pub fn default_max_report_size() -> usize {
+    2048
+}
+
 pub fn default_log() -> String {
     "ingest=debug,poc_store=info".to_string()
 }
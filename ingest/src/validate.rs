@@ -0,0 +1,271 @@
+//! Validation of submitted reports before their signature is verified.
+//!
+//! The rules of a report type are built up with [`Rules`], each rule named so
+//! a rejected report is answered with INVALID_ARGUMENT naming the failed rule
+//! and counted by report type and rule. Public keys must be present,
+//! timestamps within the allowed clock skew and age of the ingest clock and
+//! payloads within the maximum payload size.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use helium_proto::services::{
+    poc_lora::{LoraBeaconReportReqV1, LoraWitnessReportReqV1},
+    poc_mobile::{
+        CellHeartbeatReqV1, CoverageObjectReqV1, DataTransferSessionReqV1, SpeedtestReqV1,
+        SubscriberLocationReqV1,
+    },
+};
+use serde::Deserialize;
+use tonic::Status;
+
+const REJECTED_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "_validation_rejected");
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ValidationSettings {
+    /// Seconds a report timestamp may be ahead of the ingest clock. Default
+    /// is 300
+    #[serde(default = "default_max_clock_skew_secs")]
+    pub max_clock_skew_secs: i64,
+    /// Seconds a report timestamp may be behind the ingest clock. Default is
+    /// 86400
+    #[serde(default = "default_max_report_age_secs")]
+    pub max_report_age_secs: i64,
+    /// Maximum size in bytes of the payload of a lora report. Default is 256
+    #[serde(default = "default_max_payload_size")]
+    pub max_payload_size: usize,
+}
+
+pub fn default_max_clock_skew_secs() -> i64 {
+    300
+}
+
+pub fn default_max_report_age_secs() -> i64 {
+    86400
+}
+
+pub fn default_max_payload_size() -> usize {
+    256
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            max_clock_skew_secs: default_max_clock_skew_secs(),
+            max_report_age_secs: default_max_report_age_secs(),
+            max_payload_size: default_max_payload_size(),
+        }
+    }
+}
+
+/// Unit of a timestamp field
+#[derive(Debug, Clone, Copy)]
+pub enum TimeUnit {
+    Seconds,
+    Millis,
+    Nanos,
+}
+
+impl TimeUnit {
+    pub(crate) fn to_datetime(self, timestamp: u64) -> Option<DateTime<Utc>> {
+        let timestamp = i64::try_from(timestamp).ok()?;
+        match self {
+            Self::Seconds => Utc.timestamp_opt(timestamp, 0).single(),
+            Self::Millis => Utc.timestamp_millis_opt(timestamp).single(),
+            Self::Nanos => Some(Utc.timestamp_nanos(timestamp)),
+        }
+    }
+}
+
+type Check<T> = Box<dyn Fn(&T, DateTime<Utc>) -> bool + Send + Sync>;
+
+/// Named rules a report of type `T` has to pass
+pub struct Rules<T> {
+    report: &'static str,
+    settings: ValidationSettings,
+    rules: Vec<(&'static str, Check<T>)>,
+}
+
+impl<T> Rules<T> {
+    pub fn new(report: &'static str, settings: ValidationSettings) -> Self {
+        Self {
+            report,
+            settings,
+            rules: vec![],
+        }
+    }
+
+    pub fn rule<F>(mut self, name: &'static str, check: F) -> Self
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        self.rules
+            .push((name, Box::new(move |report, _now| check(report))));
+        self
+    }
+
+    /// The bytes, usually a public key, must not be empty
+    pub fn non_empty<F>(self, name: &'static str, field: F) -> Self
+    where
+        F: Fn(&T) -> &[u8] + Send + Sync + 'static,
+    {
+        self.rule(name, move |report| !field(report).is_empty())
+    }
+
+    /// The payload must be at most the maximum payload size
+    pub fn payload<F>(self, name: &'static str, field: F) -> Self
+    where
+        F: Fn(&T) -> &[u8] + Send + Sync + 'static,
+    {
+        let max_payload_size = self.settings.max_payload_size;
+        self.rule(name, move |report| field(report).len() <= max_payload_size)
+    }
+
+    /// The timestamp must be within the clock skew and age allowed around
+    /// the time the report is received
+    pub fn timestamp<F>(mut self, name: &'static str, unit: TimeUnit, field: F) -> Self
+    where
+        F: Fn(&T) -> u64 + Send + Sync + 'static,
+    {
+        let max_skew = Duration::seconds(self.settings.max_clock_skew_secs);
+        let max_age = Duration::seconds(self.settings.max_report_age_secs);
+        self.rules.push((
+            name,
+            Box::new(move |report, now| {
+                unit.to_datetime(field(report)).map_or(false, |timestamp| {
+                    timestamp <= now + max_skew && timestamp >= now - max_age
+                })
+            }),
+        ));
+        self
+    }
+
+    /// Check the report against every rule, counting a rejection by the name
+    /// of the first failed rule
+    pub fn check(&self, report: &T) -> Result<(), Status> {
+        self.failed_rule(report, Utc::now()).map_or(Ok(()), |rule| {
+            metrics::increment_counter!(REJECTED_METRIC, "report" => self.report, "rule" => rule);
+            Err(Status::invalid_argument(format!(
+                "{} failed validation: {rule}",
+                self.report
+            )))
+        })
+    }
+
+    fn failed_rule(&self, report: &T, now: DateTime<Utc>) -> Option<&'static str> {
+        self.rules
+            .iter()
+            .find(|(_, check)| !check(report, now))
+            .map(|(name, _)| *name)
+    }
+}
+
+pub fn lora_beacon(settings: ValidationSettings) -> Rules<LoraBeaconReportReqV1> {
+    Rules::new("lora_beacon", settings)
+        .non_empty("pub_key", |report| &report.pub_key)
+        .payload("data", |report| &report.data)
+        .timestamp("timestamp", TimeUnit::Nanos, |report| report.timestamp)
+}
+
+pub fn lora_witness(settings: ValidationSettings) -> Rules<LoraWitnessReportReqV1> {
+    Rules::new("lora_witness", settings)
+        .non_empty("pub_key", |report| &report.pub_key)
+        .payload("data", |report| &report.data)
+        .timestamp("timestamp", TimeUnit::Nanos, |report| report.timestamp)
+}
+
+pub fn speedtest(settings: ValidationSettings) -> Rules<SpeedtestReqV1> {
+    Rules::new("speedtest", settings)
+        .non_empty("pub_key", |report| &report.pub_key)
+        .timestamp("timestamp", TimeUnit::Seconds, |report| report.timestamp)
+}
+
+pub fn cell_heartbeat(settings: ValidationSettings) -> Rules<CellHeartbeatReqV1> {
+    Rules::new("cell_heartbeat", settings)
+        .non_empty("pub_key", |report| &report.pub_key)
+        .timestamp("timestamp", TimeUnit::Seconds, |report| report.timestamp)
+}
+
+pub fn data_transfer_session(settings: ValidationSettings) -> Rules<DataTransferSessionReqV1> {
+    Rules::new("data_transfer_session", settings)
+        .non_empty("pub_key", |report| &report.pub_key)
+        .rule("data_transfer_usage", |report| {
+            report.data_transfer_usage.is_some()
+        })
+        .timestamp(
+            "data_transfer_usage.timestamp",
+            TimeUnit::Seconds,
+            |report| {
+                report
+                    .data_transfer_usage
+                    .as_ref()
+                    .map_or(0, |usage| usage.timestamp)
+            },
+        )
+}
+
+pub fn subscriber_location(settings: ValidationSettings) -> Rules<SubscriberLocationReqV1> {
+    Rules::new("subscriber_location", settings)
+        .non_empty("carrier_pub_key", |report| &report.carrier_pub_key)
+        .non_empty("subscriber_id", |report| &report.subscriber_id)
+        .timestamp("timestamp", TimeUnit::Seconds, |report| report.timestamp)
+}
+
+pub fn coverage_object(settings: ValidationSettings) -> Rules<CoverageObjectReqV1> {
+    Rules::new("coverage_object", settings).non_empty("pub_key", |report| &report.pub_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beacon(timestamp: DateTime<Utc>, data: usize) -> LoraBeaconReportReqV1 {
+        LoraBeaconReportReqV1 {
+            pub_key: vec![1],
+            data: vec![0; data],
+            timestamp: timestamp.timestamp_nanos() as u64,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reports_are_rejected_by_first_failed_rule() {
+        let rules = lora_beacon(ValidationSettings::default());
+        let now = Utc::now();
+
+        assert_eq!(rules.failed_rule(&beacon(now, 52), now), None);
+        assert_eq!(
+            rules.failed_rule(
+                &LoraBeaconReportReqV1 {
+                    pub_key: vec![],
+                    ..beacon(now, 1000)
+                },
+                now
+            ),
+            Some("pub_key")
+        );
+        assert_eq!(rules.failed_rule(&beacon(now, 257), now), Some("data"));
+        assert_eq!(
+            rules.failed_rule(&beacon(now + Duration::minutes(6), 52), now),
+            Some("timestamp")
+        );
+        assert_eq!(
+            rules.failed_rule(&beacon(now - Duration::days(2), 52), now),
+            Some("timestamp")
+        );
+    }
+
+    #[test]
+    fn timestamps_are_read_in_their_unit() {
+        let rules = Rules::<u64>::new("test", ValidationSettings::default()).timestamp(
+            "timestamp",
+            TimeUnit::Seconds,
+            |timestamp| *timestamp,
+        );
+        let now = Utc::now();
+        assert_eq!(rules.failed_rule(&(now.timestamp() as u64), now), None);
+        assert_eq!(
+            rules.failed_rule(&(now.timestamp_millis() as u64), now),
+            Some("timestamp")
+        );
+        assert_eq!(rules.failed_rule(&u64::MAX, now), Some("timestamp"));
+    }
+}
@@ -0,0 +1,16 @@
+create table region_params_versions (
+    id bigserial primary key not null,
+    region text not null,
+    params bytea not null,
+    effective_at timestamptz not null default now(),
+
+    inserted_at timestamptz not null default now(),
+    updated_at timestamptz not null default now()
+);
+
+select trigger_updated_at('region_params_versions');
+
+create index region_params_versions_effective_idx on region_params_versions (region, effective_at desc);
+
+insert into region_params_versions (region, params, effective_at)
+    select region, params, updated_at from regions;
@@ -0,0 +1,13 @@
+create table audit_log (
+    id bigserial primary key not null,
+    service text not null,
+    rpc text not null,
+    signer bytea not null,
+    request_hash bytea not null,
+    succeeded bool not null,
+    requested_at timestamptz not null,
+
+    inserted_at timestamptz not null default now()
+);
+
+create index audit_log_signer_idx on audit_log (signer);
@@ -0,0 +1,18 @@
+-- Return the devaddrs of a helium constraint to the pool whenever the
+-- constraint is removed, including when its org is deleted and the
+-- constraint rows are cascaded, so the address space is never leaked
+create or replace function release_constraint_devaddrs()
+    returns trigger as
+$$
+begin
+    delete from helium_used_devaddrs
+        where net_id = old.net_id
+        and devaddr between old.start_addr and old.end_addr;
+    return old;
+end;
+$$ language plpgsql;
+
+create trigger release_constraint_devaddrs
+    after delete on organization_devaddr_constraints
+    for each row execute procedure release_constraint_devaddrs();
+
@@ -0,0 +1,6 @@
+create table denylist_versions (
+    version bigint primary key not null,
+    filter bytea not null,
+
+    inserted_at timestamptz not null default now()
+);
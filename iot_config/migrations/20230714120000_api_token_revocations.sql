@@ -0,0 +1,7 @@
+create table api_token_revocations (
+    id bytea primary key not null,
+    oui bigint not null,
+    expires_at timestamptz not null,
+
+    revoked_at timestamptz not null default now()
+);
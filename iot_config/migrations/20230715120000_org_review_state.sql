@@ -0,0 +1,8 @@
+create type org_review_state as enum (
+    'pending',
+    'approved',
+    'suspended'
+);
+
+alter table organizations
+    add column review_state org_review_state not null default 'approved';
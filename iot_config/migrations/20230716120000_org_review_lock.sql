@@ -0,0 +1,5 @@
+alter table organizations
+    add column review_locked boolean not null default false;
+
+update organizations set review_locked = true
+    where review_state = 'suspended' and locked;
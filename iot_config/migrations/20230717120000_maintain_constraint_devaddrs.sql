@@ -0,0 +1,40 @@
+-- Keep the used helium devaddrs in step with the org constraints: the
+-- devaddrs of a helium constraint are claimed when it is inserted, claimed
+-- and released as its range changes, and released when it is removed.
+-- Constraints checked out by the config service have claimed their devaddrs
+-- already, so claims skip the devaddrs in use
+create or replace function maintain_constraint_devaddrs()
+    returns trigger as
+$$
+begin
+    if tg_op = 'DELETE' then
+        delete from helium_used_devaddrs
+            where net_id = old.net_id
+            and devaddr between old.start_addr and old.end_addr;
+        return old;
+    end if;
+
+    if tg_op = 'UPDATE' then
+        delete from helium_used_devaddrs
+            where net_id = old.net_id
+            and devaddr between old.start_addr and old.end_addr
+            and (new.net_id <> old.net_id
+                or devaddr not between new.start_addr and new.end_addr);
+    end if;
+
+    if new.net_id in (x'00003c'::int, x'60002d'::int, x'c00053'::int) then
+        insert into helium_used_devaddrs (devaddr, net_id)
+            select devaddr, new.net_id
+            from generate_series(new.start_addr, new.end_addr) as devaddr
+            on conflict (devaddr) do nothing;
+    end if;
+    return new;
+end;
+$$ language plpgsql;
+
+drop trigger release_constraint_devaddrs on organization_devaddr_constraints;
+drop function release_constraint_devaddrs();
+
+create trigger maintain_constraint_devaddrs
+    after insert or update or delete on organization_devaddr_constraints
+    for each row execute procedure maintain_constraint_devaddrs();
@@ -33,6 +33,12 @@ impl AuthCache {
         Ok((cache_sender, Self { cache_receiver }))
     }
 
+    /// Cache of a fixed set of keys, never updated from the database
+    pub fn from_keys(keys: CacheKeys) -> Self {
+        let (_cache_sender, cache_receiver) = watch::channel(keys);
+        Self { cache_receiver }
+    }
+
     pub fn verify_signature<R>(&self, signer: &PublicKey, request: &R) -> anyhow::Result<()>
     where
         R: MsgVerify,
@@ -87,6 +93,13 @@ impl AuthCache {
     }
 }
 
+/// Administrator keys may change the log filter of the service at runtime
+impl custom_tracing::admin::AdminKeys for AuthCache {
+    fn is_admin(&self, key: &PublicKey) -> bool {
+        self.cache_receiver.borrow().get(key) == Some(&KeyType::Administrator)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, sqlx::Type)]
 #[sqlx(type_name = "key_type", rename_all = "snake_case")]
 pub enum KeyType {
@@ -1,5 +1,6 @@
 use crate::{
     admin::{self, AuthCache, CacheKeys, KeyType},
+    audit::AuditLogger,
     region_map::{self, RegionMap, RegionMapReader},
     telemetry, verify_public_key, GrpcResult, Settings,
 };
@@ -16,15 +17,17 @@ use helium_proto::{
     Message, Region,
 };
 use sqlx::{Pool, Postgres};
+use std::sync::Arc;
 use tokio::sync::watch;
 use tonic::{Request, Response, Status};
 
 pub struct AdminService {
     auth_cache: AuthCache,
     auth_updater: watch::Sender<CacheKeys>,
+    audit: AuditLogger,
     pool: Pool<Postgres>,
     region_map: RegionMapReader,
-    region_updater: watch::Sender<RegionMap>,
+    region_updater: Arc<watch::Sender<RegionMap>>,
     signing_key: Keypair,
 }
 
@@ -33,13 +36,15 @@ impl AdminService {
         settings: &Settings,
         auth_cache: AuthCache,
         auth_updater: watch::Sender<CacheKeys>,
+        audit: AuditLogger,
         pool: Pool<Postgres>,
         region_map: RegionMapReader,
-        region_updater: watch::Sender<RegionMap>,
+        region_updater: Arc<watch::Sender<RegionMap>>,
     ) -> Result<Self> {
         Ok(Self {
             auth_cache,
             auth_updater,
+            audit,
             pool,
             region_map,
             region_updater,
@@ -83,6 +88,10 @@ impl iot_config::Admin for AdminService {
     async fn add_key(&self, request: Request<AdminAddKeyReqV1>) -> GrpcResult<AdminKeyResV1> {
         let request = request.into_inner();
         telemetry::count_request("admin", "add-key");
+        let audit = self
+            .audit
+            .begin("admin", "add-key")
+            .with_request(&request.signer, &request);
 
         let signer = verify_public_key(&request.signer)?;
         self.verify_admin_request_signature(&signer, &request)?;
@@ -122,12 +131,17 @@ impl iot_config::Admin for AdminService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
     async fn remove_key(&self, request: Request<AdminRemoveKeyReqV1>) -> GrpcResult<AdminKeyResV1> {
         let request = request.into_inner();
         telemetry::count_request("admin", "remove-key");
+        let audit = self
+            .audit
+            .begin("admin", "remove-key")
+            .with_request(&request.signer, &request);
 
         let signer = verify_public_key(&request.signer)?;
         self.verify_admin_request_signature(&signer, &request)?;
@@ -160,6 +174,7 @@ impl iot_config::Admin for AdminService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
@@ -169,6 +184,10 @@ impl iot_config::Admin for AdminService {
     ) -> GrpcResult<AdminLoadRegionResV1> {
         let request = request.into_inner();
         telemetry::count_request("admin", "load-region");
+        let audit = self
+            .audit
+            .begin("admin", "load-region")
+            .with_request(&request.signer, &request);
 
         let signer = verify_public_key(&request.signer)?;
         self.verify_admin_request_signature(&signer, &request)?;
@@ -226,6 +245,7 @@ impl iot_config::Admin for AdminService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
@@ -0,0 +1,508 @@
+//! Org scoped bearer tokens for the query endpoints of the oracles, so web
+//! dashboards do not have to sign every query with a delegate key.
+//!
+//! The owner or a delegate key of an org mints a token for a set of scopes,
+//! signed by the config server and expiring after at most
+//! [`MAX_TOKEN_TTL_SECS`]. Query endpoints check the signature, expiry, org
+//! and scope of a token themselves and ask the config server, signed with a
+//! key it knows such as an oracle key, whether it was revoked. A revocation
+//! carries the token itself and is stored in the database until the signed
+//! token expires.
+//!
+//! Like the route snapshot endpoint the service is hand written on top of the
+//! tonic primitives with its messages defined here.
+
+use crate::{
+    admin::{AuthCache, KeyType},
+    org,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use file_store::traits::MsgVerify;
+use helium_crypto::{Keypair, PublicKey, PublicKeyBinary, Sign};
+use prost::Message;
+use sqlx::{Pool, Postgres};
+use std::{convert::Infallible, sync::Arc};
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const SERVICE_NAME: &str = "helium.iot_config.api_token";
+pub const MINT_PATH: &str = "/helium.iot_config.api_token/mint";
+pub const REVOKE_PATH: &str = "/helium.iot_config.api_token/revoke";
+pub const STATUS_PATH: &str = "/helium.iot_config.api_token/status";
+
+/// Longest lifetime of a minted token, 90 days
+pub const MAX_TOKEN_TTL_SECS: u64 = 90 * 24 * 60 * 60;
+
+/// Scope of the packet statistics query of the iot packet verifier
+pub const PACKET_STATS_SCOPE: &str = "packet_stats";
+/// Scope of the reward queries of the reward index
+pub const REWARDS_SCOPE: &str = "rewards";
+/// Scopes tokens can be minted for
+pub const SCOPES: &[&str] = &[PACKET_STATS_SCOPE, REWARDS_SCOPE];
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OrgTokenV1 {
+    /// Random id of the token, revoked by id
+    #[prost(bytes = "vec", tag = "1")]
+    pub id: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub oui: u64,
+    #[prost(string, repeated, tag = "3")]
+    pub scopes: Vec<String>,
+    /// Seconds since the epoch after which the token is no longer valid
+    #[prost(uint64, tag = "4")]
+    pub expires_at: u64,
+    /// Key of the config server
+    #[prost(bytes = "vec", tag = "5")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MintTokenReqV1 {
+    #[prost(uint64, tag = "1")]
+    pub oui: u64,
+    #[prost(string, repeated, tag = "2")]
+    pub scopes: Vec<String>,
+    /// Seconds the token is valid for, at most 90 days
+    #[prost(uint64, tag = "3")]
+    pub ttl_secs: u64,
+    /// Seconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "4")]
+    pub timestamp: u64,
+    /// Owner or delegate key of the org
+    #[prost(bytes = "vec", tag = "5")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RevokeTokenReqV1 {
+    #[prost(uint64, tag = "1")]
+    pub oui: u64,
+    /// Encoded token to revoke, the revocation is kept until it expires
+    #[prost(bytes = "vec", tag = "2")]
+    pub token: Vec<u8>,
+    /// Seconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "3")]
+    pub timestamp: u64,
+    /// Owner or delegate key of the org, or an administrator key
+    #[prost(bytes = "vec", tag = "4")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RevokeTokenResV1 {
+    #[prost(bytes = "vec", tag = "1")]
+    pub id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TokenStatusReqV1 {
+    #[prost(bytes = "vec", tag = "1")]
+    pub id: Vec<u8>,
+    /// Key of an oracle, or any other key known to the config server
+    #[prost(bytes = "vec", tag = "2")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TokenStatusResV1 {
+    #[prost(bytes = "vec", tag = "1")]
+    pub id: Vec<u8>,
+    #[prost(bool, tag = "2")]
+    pub revoked: bool,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub signature: Vec<u8>,
+}
+
+file_store::impl_msg_verify!(OrgTokenV1, signature);
+file_store::impl_msg_verify!(MintTokenReqV1, signature);
+file_store::impl_msg_verify!(RevokeTokenReqV1, signature);
+file_store::impl_msg_verify!(RevokeTokenResV1, signature);
+file_store::impl_msg_verify!(TokenStatusReqV1, signature);
+file_store::impl_msg_verify!(TokenStatusResV1, signature);
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum TokenError {
+    #[error("invalid token encoding")]
+    Decode,
+    #[error("token not signed by the config server")]
+    Signature,
+    #[error("token expired")]
+    Expired,
+    #[error("token is for oui {0}")]
+    Oui(u64),
+    #[error("token lacks the {0} scope")]
+    Scope(String),
+    #[error("token revoked")]
+    Revoked,
+}
+
+impl From<TokenError> for Status {
+    fn from(err: TokenError) -> Self {
+        Status::unauthenticated(err.to_string())
+    }
+}
+
+impl OrgTokenV1 {
+    pub fn decode_token(token: &[u8]) -> Result<Self, TokenError> {
+        Self::decode(token).map_err(|_| TokenError::Decode)
+    }
+
+    /// Check the token is signed by the config server
+    pub fn verify_signer(&self, config_pubkey: &PublicKey) -> Result<(), TokenError> {
+        if self.signer != config_pubkey.to_vec() || self.verify(config_pubkey).is_err() {
+            return Err(TokenError::Signature);
+        }
+        Ok(())
+    }
+
+    /// Check the token is signed by the config server, not expired and
+    /// grants the scope for the org. Revocation is checked separately
+    pub fn check(
+        &self,
+        config_pubkey: &PublicKey,
+        oui: u64,
+        scope: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), TokenError> {
+        self.check_scope(config_pubkey, scope, now)?;
+        if self.oui != oui {
+            return Err(TokenError::Oui(self.oui));
+        }
+        Ok(())
+    }
+
+    /// Check the token is signed by the config server, not expired and
+    /// grants the scope, for queries that are not scoped to an org
+    pub fn check_scope(
+        &self,
+        config_pubkey: &PublicKey,
+        scope: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), TokenError> {
+        self.verify_signer(config_pubkey)?;
+        if now.timestamp() as u64 >= self.expires_at {
+            return Err(TokenError::Expired);
+        }
+        if !self.scopes.iter().any(|granted| granted == scope) {
+            return Err(TokenError::Scope(scope.to_string()));
+        }
+        Ok(())
+    }
+}
+
+pub async fn revoke(
+    db: impl sqlx::PgExecutor<'_>,
+    id: &[u8],
+    oui: u64,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        insert into api_token_revocations (id, oui, expires_at)
+        values ($1, $2, $3)
+        on conflict (id) do nothing
+        "#,
+    )
+    .bind(id)
+    .bind(oui as i64)
+    .bind(expires_at)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+pub async fn is_revoked(db: impl sqlx::PgExecutor<'_>, id: &[u8]) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("select exists(select 1 from api_token_revocations where id = $1)")
+        .bind(id)
+        .fetch_one(db)
+        .await
+}
+
+/// Remove the revocations of expired tokens
+pub async fn purge_expired(db: impl sqlx::PgExecutor<'_>) -> Result<u64, sqlx::Error> {
+    sqlx::query("delete from api_token_revocations where expires_at < now()")
+        .execute(db)
+        .await
+        .map(|result| result.rows_affected())
+}
+
+/// Decode a token to revoke, checking it was signed by the config server for
+/// the org
+fn revoked_token(
+    token: &[u8],
+    oui: u64,
+    config_pubkey: &PublicKey,
+) -> Result<OrgTokenV1, TokenError> {
+    let token = OrgTokenV1::decode_token(token)?;
+    token.verify_signer(config_pubkey)?;
+    if token.oui != oui {
+        return Err(TokenError::Oui(token.oui));
+    }
+    Ok(token)
+}
+
+fn timestamp(secs: u64) -> Result<DateTime<Utc>, Status> {
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+        .ok_or_else(|| Status::invalid_argument("invalid timestamp"))
+}
+
+#[derive(Clone)]
+pub struct ApiTokenService {
+    auth_cache: AuthCache,
+    signing_key: Arc<Keypair>,
+    pool: Pool<Postgres>,
+}
+
+impl ApiTokenService {
+    pub fn new(auth_cache: AuthCache, signing_key: Arc<Keypair>, pool: Pool<Postgres>) -> Self {
+        Self {
+            auth_cache,
+            signing_key,
+            pool,
+        }
+    }
+
+    fn sign<R: Message>(&self, message: &R) -> Result<Vec<u8>, Status> {
+        self.signing_key
+            .sign(&message.encode_to_vec())
+            .map_err(|_| Status::internal("response signing error"))
+    }
+
+    /// Verify the request is signed by the owner or a delegate key of the org
+    async fn verify_org_signer<R: MsgVerify>(
+        &self,
+        oui: u64,
+        signer: &[u8],
+        request: &R,
+    ) -> Result<(), Status> {
+        let signer =
+            PublicKey::try_from(signer).map_err(|_| Status::invalid_argument("invalid signer"))?;
+        let org = org::get(oui, &self.pool)
+            .await
+            .map_err(|_| Status::internal("auth verification error"))?
+            .ok_or_else(|| Status::not_found(format!("oui: {oui}")))?;
+        let signer_binary = PublicKeyBinary::from(signer.clone());
+        let is_org_key = org.owner == signer_binary
+            || org
+                .delegate_keys
+                .unwrap_or_default()
+                .contains(&signer_binary);
+        if is_org_key && request.verify(&signer).is_ok() {
+            Ok(())
+        } else {
+            Err(Status::permission_denied("unauthorized request signature"))
+        }
+    }
+
+    pub async fn mint(&self, request: MintTokenReqV1) -> Result<OrgTokenV1, Status> {
+        crate::verify_request_timestamp(request.timestamp)?;
+        self.verify_org_signer(request.oui, &request.signer, &request)
+            .await?;
+        if request.ttl_secs == 0 || request.ttl_secs > MAX_TOKEN_TTL_SECS {
+            return Err(Status::invalid_argument(format!(
+                "ttl_secs must be positive and at most {MAX_TOKEN_TTL_SECS}"
+            )));
+        }
+        if request.scopes.is_empty() {
+            return Err(Status::invalid_argument("at least one scope is required"));
+        }
+        if let Some(unknown) = request
+            .scopes
+            .iter()
+            .find(|scope| !SCOPES.contains(&scope.as_str()))
+        {
+            return Err(Status::invalid_argument(format!("unknown scope {unknown}")));
+        }
+
+        let mut token = OrgTokenV1 {
+            id: rand::random::<[u8; 16]>().to_vec(),
+            oui: request.oui,
+            scopes: request.scopes,
+            expires_at: Utc::now().timestamp() as u64 + request.ttl_secs,
+            signer: self.signing_key.public_key().into(),
+            signature: vec![],
+        };
+        token.signature = self.sign(&token)?;
+        metrics::increment_counter!("iot_config_api_tokens_minted");
+        tracing::info!(oui = token.oui, scopes = ?token.scopes, "minted api token");
+        Ok(token)
+    }
+
+    pub async fn revoke(&self, request: RevokeTokenReqV1) -> Result<RevokeTokenResV1, Status> {
+        crate::verify_request_timestamp(request.timestamp)?;
+        let signer = PublicKey::try_from(request.signer.as_slice())
+            .map_err(|_| Status::invalid_argument("invalid signer"))?;
+        if self
+            .auth_cache
+            .verify_signature_with_type(KeyType::Administrator, &signer, &request)
+            .is_err()
+        {
+            self.verify_org_signer(request.oui, &request.signer, &request)
+                .await?;
+        }
+        // The revocation is kept until the signed token expires, an expiry
+        // of the client could have it purged while the token is still valid
+        let token = revoked_token(&request.token, request.oui, self.signing_key.public_key())
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let expires_at = timestamp(token.expires_at)?;
+        revoke(&self.pool, &token.id, token.oui, expires_at)
+            .await
+            .map_err(|err| {
+                tracing::error!(oui = request.oui, "failed to revoke api token: {err:?}");
+                Status::internal("failed to revoke token")
+            })?;
+        tracing::info!(oui = request.oui, "revoked api token");
+        if let Err(err) = purge_expired(&self.pool).await {
+            tracing::warn!("failed to purge expired api token revocations: {err:?}");
+        }
+
+        let mut response = RevokeTokenResV1 {
+            id: token.id,
+            signer: self.signing_key.public_key().into(),
+            signature: vec![],
+        };
+        response.signature = self.sign(&response)?;
+        Ok(response)
+    }
+
+    pub async fn status(&self, request: TokenStatusReqV1) -> Result<TokenStatusResV1, Status> {
+        let signer = crate::verify_public_key(&request.signer)?;
+        self.auth_cache
+            .verify_signature(&signer, &request)
+            .map_err(|_| Status::permission_denied("unauthorized request signature"))?;
+        let revoked = is_revoked(&self.pool, &request.id).await.map_err(|err| {
+            tracing::error!("failed to fetch api token status: {err:?}");
+            Status::internal("failed to fetch token status")
+        })?;
+        let mut response = TokenStatusResV1 {
+            id: request.id,
+            revoked,
+            signer: self.signing_key.public_key().into(),
+            signature: vec![],
+        };
+        response.signature = self.sign(&response)?;
+        Ok(response)
+    }
+}
+
+impl NamedService for ApiTokenService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for ApiTokenService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            MINT_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.mint(request).await })
+            }
+            REVOKE_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.revoke(request).await })
+            }
+            STATUS_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.status(request).await })
+            }
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_crypto::{KeyTag, KeyType, Network};
+    use rand::rngs::OsRng;
+
+    fn keypair() -> Keypair {
+        Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::Ed25519,
+            },
+            &mut OsRng,
+        )
+    }
+
+    fn token(keypair: &Keypair, expires_at: u64) -> OrgTokenV1 {
+        let mut token = OrgTokenV1 {
+            id: vec![1; 16],
+            oui: 1,
+            scopes: vec![PACKET_STATS_SCOPE.to_string()],
+            expires_at,
+            signer: keypair.public_key().into(),
+            signature: vec![],
+        };
+        token.signature = keypair.sign(&token.encode_to_vec()).unwrap();
+        token
+    }
+
+    #[test]
+    fn tokens_are_checked_for_signer_expiry_org_and_scope() {
+        let config = keypair();
+        let now = Utc.timestamp_opt(1_000, 0).unwrap();
+        let token = token(&config, 2_000);
+        let pubkey = config.public_key();
+
+        assert_eq!(token.check(pubkey, 1, PACKET_STATS_SCOPE, now), Ok(()));
+        assert_eq!(
+            token.check(pubkey, 2, PACKET_STATS_SCOPE, now),
+            Err(TokenError::Oui(1))
+        );
+        assert_eq!(
+            token.check(pubkey, 1, "rewards", now),
+            Err(TokenError::Scope("rewards".to_string()))
+        );
+        let expired = Utc.timestamp_opt(2_000, 0).unwrap();
+        assert_eq!(
+            token.check(pubkey, 1, PACKET_STATS_SCOPE, expired),
+            Err(TokenError::Expired)
+        );
+
+        let widened = OrgTokenV1 {
+            expires_at: 3_000,
+            ..token.clone()
+        };
+        assert_eq!(
+            widened.check(pubkey, 1, PACKET_STATS_SCOPE, now),
+            Err(TokenError::Signature)
+        );
+        let forged = self::token(&keypair(), 2_000);
+        assert_eq!(
+            forged.check(pubkey, 1, PACKET_STATS_SCOPE, now),
+            Err(TokenError::Signature)
+        );
+    }
+}
@@ -0,0 +1,415 @@
+use crate::{
+    admin::{AuthCache, KeyType},
+    telemetry, verify_public_key, verify_request_timestamp,
+};
+use chrono::{DateTime, Utc};
+use file_store::{file_sink::FileSinkClient, traits::TimestampEncode};
+use helium_crypto::{Keypair, Sign};
+use helium_proto::Message;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::mpsc;
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const AUDIT_CHANNEL_SIZE: usize = 1000;
+
+const SERVICE_NAME: &str = "helium.iot_config.audit";
+pub const LIST_PATH: &str = "/helium.iot_config.audit/list";
+
+/// Most audit entries returned in a single page
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+/// Audit record written to the audit file sink for each mutating request
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AuditRecordV1 {
+    #[prost(string, tag = "1")]
+    pub service: String,
+    #[prost(string, tag = "2")]
+    pub rpc: String,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub request_hash: Vec<u8>,
+    #[prost(bool, tag = "5")]
+    pub succeeded: bool,
+    /// Seconds since unix epoch the request was received
+    #[prost(uint64, tag = "6")]
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditEvent {
+    pub service: &'static str,
+    pub rpc: &'static str,
+    pub signer: Vec<u8>,
+    pub request_hash: Vec<u8>,
+    pub succeeded: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl From<AuditEvent> for AuditRecordV1 {
+    fn from(event: AuditEvent) -> Self {
+        Self {
+            service: event.service.to_string(),
+            rpc: event.rpc.to_string(),
+            signer: event.signer,
+            request_hash: event.request_hash,
+            succeeded: event.succeeded,
+            timestamp: event.timestamp.encode_timestamp(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditLogger {
+    sender: mpsc::Sender<AuditEvent>,
+}
+
+impl AuditLogger {
+    pub fn new() -> (Self, mpsc::Receiver<AuditEvent>) {
+        let (sender, receiver) = mpsc::channel(AUDIT_CHANNEL_SIZE);
+        (Self { sender }, receiver)
+    }
+
+    /// Start auditing a request. The returned guard records the request as
+    /// failed when dropped unless it is marked as succeeded first, so every
+    /// early return of a handler is captured in the audit trail.
+    pub fn begin(&self, service: &'static str, rpc: &'static str) -> AuditGuard {
+        AuditGuard {
+            service,
+            rpc,
+            signer: None,
+            hasher: Sha256::new(),
+            succeeded: false,
+            timestamp: Utc::now(),
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+pub struct AuditGuard {
+    service: &'static str,
+    rpc: &'static str,
+    signer: Option<Vec<u8>>,
+    hasher: Sha256,
+    succeeded: bool,
+    timestamp: DateTime<Utc>,
+    sender: mpsc::Sender<AuditEvent>,
+}
+
+impl AuditGuard {
+    pub fn with_request<R: Message>(mut self, signer: &[u8], request: &R) -> Self {
+        self.observe(signer, request);
+        self
+    }
+
+    /// Add a request message to the audited request hash. Streamed requests
+    /// observe every message; the signer of the first message is recorded.
+    pub fn observe<R: Message>(&mut self, signer: &[u8], request: &R) {
+        self.signer.get_or_insert_with(|| signer.to_vec());
+        self.hasher.update(request.encode_to_vec());
+    }
+
+    pub fn succeeded(mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for AuditGuard {
+    fn drop(&mut self) {
+        let event = AuditEvent {
+            service: self.service,
+            rpc: self.rpc,
+            signer: self.signer.take().unwrap_or_default(),
+            request_hash: std::mem::take(&mut self.hasher).finalize().to_vec(),
+            succeeded: self.succeeded,
+            timestamp: self.timestamp,
+        };
+        if self.sender.try_send(event).is_err() {
+            tracing::warn!(
+                service = self.service,
+                rpc = self.rpc,
+                "audit channel full or closed; dropping audit event"
+            );
+            telemetry::count_audit_dropped();
+        }
+    }
+}
+
+pub struct AuditDaemon {
+    pool: Pool<Postgres>,
+    events: mpsc::Receiver<AuditEvent>,
+    audit_sink: Option<FileSinkClient>,
+}
+
+impl AuditDaemon {
+    pub fn new(
+        pool: Pool<Postgres>,
+        events: mpsc::Receiver<AuditEvent>,
+        audit_sink: Option<FileSinkClient>,
+    ) -> Self {
+        Self {
+            pool,
+            events,
+            audit_sink,
+        }
+    }
+
+    pub async fn run(mut self, shutdown: triggered::Listener) -> anyhow::Result<()> {
+        tracing::info!("starting audit daemon");
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                event = self.events.recv() => match event {
+                    Some(event) => self.record(event).await?,
+                    None => break,
+                }
+            }
+        }
+        // Record anything still queued from requests completed before shutdown
+        while let Ok(event) = self.events.try_recv() {
+            self.record(event).await?;
+        }
+        tracing::info!("stopping audit daemon");
+        Ok(())
+    }
+
+    async fn record(&self, event: AuditEvent) -> anyhow::Result<()> {
+        if let Err(err) = insert(&self.pool, &event).await {
+            tracing::error!(
+                service = event.service,
+                rpc = event.rpc,
+                "failed to store audit event: {err:?}"
+            );
+            telemetry::count_audit_dropped();
+        }
+        if let Some(audit_sink) = &self.audit_sink {
+            audit_sink.write(AuditRecordV1::from(event), &[]).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn insert(db: impl sqlx::PgExecutor<'_>, event: &AuditEvent) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        insert into audit_log (service, rpc, signer, request_hash, succeeded, requested_at)
+        values ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(event.service)
+    .bind(event.rpc)
+    .bind(&event.signer)
+    .bind(&event.request_hash)
+    .bind(event.succeeded)
+    .bind(event.timestamp)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub service: String,
+    pub rpc: String,
+    pub signer: Vec<u8>,
+    pub request_hash: Vec<u8>,
+    pub succeeded: bool,
+    pub requested_at: DateTime<Utc>,
+}
+
+/// List audit entries newest first. Pass the id of the last entry of the
+/// previous page as `before` to fetch the next page.
+pub async fn list(
+    db: impl sqlx::PgExecutor<'_>,
+    before: Option<i64>,
+    limit: u32,
+) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    sqlx::query_as::<_, AuditEntry>(
+        r#"
+        select id, service, rpc, signer, request_hash, succeeded, requested_at
+        from audit_log
+        where $1::bigint is null or id < $1
+        order by id desc
+        limit $2
+        "#,
+    )
+    .bind(before)
+    .bind(limit as i64)
+    .fetch_all(db)
+    .await
+}
+
+/// Audit entry returned by the list rpc
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AuditEntryV1 {
+    #[prost(int64, tag = "1")]
+    pub id: i64,
+    #[prost(message, optional, tag = "2")]
+    pub record: Option<AuditRecordV1>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AuditListReqV1 {
+    /// Id of the last entry of the previous page, 0 for the first page
+    #[prost(int64, tag = "1")]
+    pub before: i64,
+    /// Entries per page, capped at `MAX_PAGE_SIZE`
+    #[prost(uint32, tag = "2")]
+    pub limit: u32,
+    /// Seconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "3")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "4")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AuditListResV1 {
+    /// Entries newest first
+    #[prost(message, repeated, tag = "1")]
+    pub entries: Vec<AuditEntryV1>,
+    #[prost(uint64, tag = "2")]
+    pub timestamp: u64,
+    /// Key of the config server
+    #[prost(bytes = "vec", tag = "3")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub signature: Vec<u8>,
+}
+
+impl From<AuditEntry> for AuditEntryV1 {
+    fn from(entry: AuditEntry) -> Self {
+        Self {
+            id: entry.id,
+            record: Some(AuditRecordV1 {
+                service: entry.service,
+                rpc: entry.rpc,
+                signer: entry.signer,
+                request_hash: entry.request_hash,
+                succeeded: entry.succeeded,
+                timestamp: entry.requested_at.encode_timestamp(),
+            }),
+        }
+    }
+}
+
+file_store::impl_msg_verify!(AuditListReqV1, signature);
+file_store::impl_msg_verify!(AuditListResV1, signature);
+
+/// Pages through the audit trail. Only administrator keys may list it.
+#[derive(Clone)]
+pub struct AuditService {
+    auth_cache: AuthCache,
+    pool: Pool<Postgres>,
+    signing_key: Arc<Keypair>,
+}
+
+impl AuditService {
+    pub fn new(auth_cache: AuthCache, pool: Pool<Postgres>, signing_key: Arc<Keypair>) -> Self {
+        Self {
+            auth_cache,
+            pool,
+            signing_key,
+        }
+    }
+
+    pub async fn list(&self, request: AuditListReqV1) -> Result<AuditListResV1, Status> {
+        telemetry::count_request("audit", "list");
+
+        let signer = verify_public_key(&request.signer)?;
+        self.auth_cache
+            .verify_signature_with_type(KeyType::Administrator, &signer, &request)
+            .map_err(|_| Status::permission_denied("invalid admin signature"))?;
+        verify_request_timestamp(request.timestamp)?;
+
+        let before = (request.before > 0).then_some(request.before);
+        let limit = request.limit.clamp(1, MAX_PAGE_SIZE);
+        let entries = list(&self.pool, before, limit).await.map_err(|err| {
+            tracing::error!(reason = ?err, "failed to list audit entries");
+            Status::internal("audit list failed")
+        })?;
+
+        let mut response = AuditListResV1 {
+            entries: entries.into_iter().map(AuditEntryV1::from).collect(),
+            timestamp: Utc::now().encode_timestamp(),
+            signer: self.signing_key.public_key().into(),
+            signature: vec![],
+        };
+        response.signature = self
+            .signing_key
+            .sign(&response.encode_to_vec())
+            .map_err(|_| Status::internal("response signing error"))?;
+        Ok(response)
+    }
+}
+
+impl NamedService for AuditService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for AuditService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            LIST_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.list(request).await })
+            }
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_proto::services::iot_config::OrgEnableReqV1;
+
+    #[tokio::test]
+    async fn dropped_guard_records_failure() {
+        let (logger, mut events) = AuditLogger::new();
+        let request = OrgEnableReqV1 {
+            oui: 1,
+            signer: vec![1, 2, 3],
+            ..Default::default()
+        };
+
+        drop(
+            logger
+                .begin("org", "enable")
+                .with_request(&request.signer, &request),
+        );
+        let failed = events.recv().await.unwrap();
+        assert!(!failed.succeeded);
+        assert_eq!(failed.signer, vec![1, 2, 3]);
+
+        logger
+            .begin("org", "enable")
+            .with_request(&request.signer, &request)
+            .succeeded();
+        let succeeded = events.recv().await.unwrap();
+        assert!(succeeded.succeeded);
+        assert_eq!(succeeded.request_hash, failed.request_hash);
+    }
+}
@@ -0,0 +1,147 @@
+use super::{Channel, ClientError, Message, MsgVerify, PublicKey, Settings, Signer};
+use crate::api_token::{OrgTokenV1, TokenError, TokenStatusReqV1, TokenStatusResV1, STATUS_PATH};
+use chrono::Utc;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use tonic::{codec::ProstCodec, codegen::http, Request, Response, Status};
+
+/// Seconds the revocation status of a token is cached for by default
+pub const DEFAULT_STATUS_TTL_SECS: u64 = 60;
+
+/// Client for the revocation status of api tokens
+#[derive(Clone, Debug)]
+pub struct ApiTokenClient {
+    inner: tonic::client::Grpc<Channel>,
+    signer: Arc<dyn Signer>,
+    config_pubkey: PublicKey,
+}
+
+impl ApiTokenClient {
+    pub fn new(channel: Channel, signer: Arc<dyn Signer>, config_pubkey: PublicKey) -> Self {
+        Self {
+            inner: tonic::client::Grpc::new(channel),
+            signer,
+            config_pubkey,
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<Self, ClientError> {
+        Ok(Self::new(
+            settings.channel()?,
+            settings.signer()?,
+            settings.config_pubkey().map_err(Box::new)?,
+        ))
+    }
+
+    pub async fn is_revoked(&mut self, id: &[u8]) -> Result<bool, ClientError> {
+        let mut request = TokenStatusReqV1 {
+            id: id.to_vec(),
+            signer: self.signer.public_key().into(),
+            signature: vec![],
+        };
+        request.signature = self.signer.sign(&request.encode_to_vec()).await?;
+        self.inner
+            .ready()
+            .await
+            .map_err(|err| Status::unknown(format!("service was not ready: {err}")))?;
+        let response: TokenStatusResV1 = self
+            .inner
+            .unary(
+                Request::new(request),
+                http::uri::PathAndQuery::from_static(STATUS_PATH),
+                ProstCodec::default(),
+            )
+            .await
+            .map(Response::into_inner)?;
+        response.verify(&self.config_pubkey)?;
+        if response.id != id {
+            return Err(ClientError::Rpc(Status::internal(
+                "token status of another token",
+            )));
+        }
+        Ok(response.revoked)
+    }
+}
+
+/// Checks the api tokens presented to a query endpoint, caching the
+/// revocation status of a token for a while so not every query asks the
+/// config server
+#[derive(Clone)]
+pub struct TokenVerifier {
+    client: ApiTokenClient,
+    status_ttl: Duration,
+    revoked: Arc<Mutex<HashMap<Vec<u8>, (bool, Instant)>>>,
+}
+
+impl TokenVerifier {
+    pub fn new(client: ApiTokenClient, status_ttl: Duration) -> Self {
+        Self {
+            client,
+            status_ttl,
+            revoked: Arc::default(),
+        }
+    }
+
+    /// Verify the encoded token grants the scope for the org
+    pub async fn verify(&self, token: &[u8], oui: u64, scope: &str) -> Result<(), VerifyError> {
+        let token = OrgTokenV1::decode_token(token)?;
+        token.check(&self.client.config_pubkey, oui, scope, Utc::now())?;
+        self.check_revoked(token.id).await
+    }
+
+    /// Verify the encoded token of any org grants the scope, for queries
+    /// that are not scoped to an org
+    pub async fn verify_scope(&self, token: &[u8], scope: &str) -> Result<(), VerifyError> {
+        let token = OrgTokenV1::decode_token(token)?;
+        token.check_scope(&self.client.config_pubkey, scope, Utc::now())?;
+        self.check_revoked(token.id).await
+    }
+
+    async fn check_revoked(&self, id: Vec<u8>) -> Result<(), VerifyError> {
+        let cached = self
+            .revoked
+            .lock()
+            .await
+            .get(&id)
+            .filter(|(_, checked_at)| checked_at.elapsed() < self.status_ttl)
+            .map(|(revoked, _)| *revoked);
+        let revoked = match cached {
+            Some(revoked) => revoked,
+            None => {
+                let revoked = self.client.clone().is_revoked(&id).await?;
+                let mut statuses = self.revoked.lock().await;
+                statuses.retain(|_, (_, checked_at)| checked_at.elapsed() < self.status_ttl);
+                statuses.insert(id, (revoked, Instant::now()));
+                revoked
+            }
+        };
+        if revoked {
+            return Err(TokenError::Revoked.into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum VerifyError {
+    #[error("invalid token: {0}")]
+    Token(#[from] TokenError),
+    #[error("failed to fetch token status: {0}")]
+    Client(#[from] ClientError),
+}
+
+impl From<VerifyError> for Status {
+    fn from(err: VerifyError) -> Self {
+        match err {
+            VerifyError::Token(err) => err.into(),
+            VerifyError::Client(err) => {
+                tracing::warn!("failed to fetch api token status: {err}");
+                Status::unavailable("failed to fetch token status")
+            }
+        }
+    }
+}
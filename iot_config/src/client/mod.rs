@@ -1,51 +1,98 @@
 use crate::gateway_info;
 use file_store::traits::MsgVerify;
 use futures::stream::{self, StreamExt};
-use helium_crypto::{Keypair, PublicKey, PublicKeyBinary, Sign};
+use helium_crypto::{PublicKey, PublicKeyBinary};
 use helium_proto::{
-    services::{iot_config, Channel, Endpoint},
+    services::{iot_config, Channel},
     BlockchainRegionParamV1, Message, Region,
 };
-use std::{sync::Arc, time::Duration};
+use oracle_signer::Signer;
+use poc_metrics::error_code::{self, ErrorCategory, ErrorCode};
+use std::sync::Arc;
 
+pub mod api_token_client;
 pub mod org_client;
 mod settings;
 
+pub use api_token_client::{ApiTokenClient, TokenVerifier};
+pub use grpc_service::tls::ClientTlsSettings;
 pub use org_client::OrgClient;
 pub use settings::Settings;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ClientError {
     #[error("error signing request: {0}")]
-    Signing(#[from] helium_crypto::Error),
+    Signing(#[from] oracle_signer::Error),
     #[error("grpc error response: {0}")]
     Rpc(#[from] tonic::Status),
     #[error("error verifying response signature: {0}")]
     Verification(#[from] file_store::Error),
     #[error("error resolving region params: {0}")]
     UndefinedRegionParams(String),
+    #[error("error loading client keys: {0}")]
+    Keys(#[from] Box<helium_crypto::Error>),
+    #[error("error configuring client tls: {0}")]
+    Tls(#[from] grpc_service::tls::TlsError),
+    #[error("error configuring client channel: {0}")]
+    Transport(#[from] tonic::transport::Error),
+    #[error("invalid client settings: {0}")]
+    InvalidSettings(String),
+}
+
+impl ErrorCode for ClientError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Signing(err) => err.category(),
+            Self::Rpc(_) | Self::Transport(_) => ErrorCategory::Network,
+            Self::Verification(_) => ErrorCategory::Crypto,
+            Self::UndefinedRegionParams(_) => ErrorCategory::Decode,
+            Self::Keys(_) | Self::Tls(_) | Self::InvalidSettings(_) => ErrorCategory::Config,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Signing(err) => err.code(),
+            Self::Rpc(status) => match status.code() {
+                tonic::Code::NotFound => "config_not_found",
+                tonic::Code::PermissionDenied | tonic::Code::Unauthenticated => {
+                    "config_unauthorized"
+                }
+                tonic::Code::Unavailable | tonic::Code::DeadlineExceeded => "config_unavailable",
+                _ => "config_rpc",
+            },
+            Self::Verification(_) => "response_signature",
+            Self::UndefinedRegionParams(_) => "undefined_region_params",
+            Self::Keys(_) => "client_keys",
+            Self::Tls(_) => "client_tls",
+            Self::Transport(_) => "config_transport",
+            Self::InvalidSettings(_) => "invalid_client_settings",
+        }
+    }
+}
+
+/// Label the errors of the config client in metrics and logs
+pub fn register_error_codes() {
+    error_code::register::<ClientError>();
 }
 
 #[derive(Clone, Debug)]
 pub struct Client {
     pub gateway_client: iot_config::gateway_client::GatewayClient<Channel>,
     pub admin_client: iot_config::admin_client::AdminClient<Channel>,
-    signing_key: Arc<Keypair>,
+    signer: Arc<dyn Signer>,
     config_pubkey: PublicKey,
     batch_size: u32,
 }
 
 impl Client {
-    pub fn from_settings(settings: &Settings) -> Result<Self, Box<helium_crypto::Error>> {
-        let channel = Endpoint::from(settings.url.clone())
-            .connect_timeout(Duration::from_secs(settings.connect_timeout))
-            .timeout(Duration::from_secs(settings.rpc_timeout))
-            .connect_lazy();
+    pub fn from_settings(settings: &Settings) -> Result<Self, ClientError> {
+        let channel = settings.channel()?;
         Ok(Self {
             gateway_client: iot_config::gateway_client::GatewayClient::new(channel.clone()),
             admin_client: iot_config::admin_client::AdminClient::new(channel),
-            signing_key: settings.signing_keypair()?,
-            config_pubkey: settings.config_pubkey()?,
+            signer: settings.signer()?,
+            config_pubkey: settings.config_pubkey().map_err(Box::new)?,
             batch_size: settings.batch_size,
         })
     }
@@ -56,10 +103,10 @@ impl Client {
     ) -> Result<RegionParamsInfo, ClientError> {
         let mut request = iot_config::RegionParamsReqV1 {
             region: region.into(),
-            signer: self.signing_key.public_key().into(),
+            signer: self.signer.public_key().into(),
             signature: vec![],
         };
-        request.signature = self.signing_key.sign(&request.encode_to_vec())?;
+        request.signature = self.signer.sign(&request.encode_to_vec()).await?;
         let response = self.admin_client.region_params(request).await?.into_inner();
         response.verify(&self.config_pubkey)?;
         Ok(RegionParamsInfo {
@@ -82,10 +129,10 @@ impl gateway_info::GatewayInfoResolver for Client {
     ) -> Result<Option<gateway_info::GatewayInfo>, Self::Error> {
         let mut request = iot_config::GatewayInfoReqV1 {
             address: address.clone().into(),
-            signer: self.signing_key.public_key().into(),
+            signer: self.signer.public_key().into(),
             signature: vec![],
         };
-        request.signature = self.signing_key.sign(&request.encode_to_vec())?;
+        request.signature = self.signer.sign(&request.encode_to_vec()).await?;
         tracing::debug!(pubkey = address.to_string(), "fetching gateway info");
         let response = match self.gateway_client.info(request).await {
             Ok(info_resp) => {
@@ -104,10 +151,10 @@ impl gateway_info::GatewayInfoResolver for Client {
     ) -> Result<gateway_info::GatewayInfoStream, Self::Error> {
         let mut request = iot_config::GatewayInfoStreamReqV1 {
             batch_size: self.batch_size,
-            signer: self.signing_key.public_key().into(),
+            signer: self.signer.public_key().into(),
             signature: vec![],
         };
-        request.signature = self.signing_key.sign(&request.encode_to_vec())?;
+        request.signature = self.signer.sign(&request.encode_to_vec()).await?;
         tracing::debug!("fetching gateway info stream");
         let pubkey = Arc::new(self.config_pubkey.clone());
         let response_stream = self
@@ -1,31 +1,40 @@
 use super::{
-    iot_config, Arc, Channel, ClientError, Duration, Endpoint, Keypair, Message, MsgVerify,
-    PublicKey, Settings, Sign,
+    iot_config, Arc, Channel, ClientError, Message, MsgVerify, PublicKey, Settings, Signer,
+};
+use crate::org_batch::{
+    OrgLockResultV1, OrgLockV1, OrgSetLocksReqV1, OrgSetLocksResV1, SET_LOCKS_PATH,
 };
 use chrono::Utc;
 use file_store::traits::TimestampEncode;
 use helium_proto::services::iot_config::{
     OrgDisableReqV1, OrgEnableReqV1, OrgGetReqV1, OrgListReqV1, OrgResV1, OrgV1,
 };
+use tonic::{codec::ProstCodec, codegen::http, Request, Response, Status};
 
 #[derive(Clone)]
 pub struct OrgClient {
     client: iot_config::config_org_client::OrgClient<Channel>,
-    signing_key: Arc<Keypair>,
+    batch: tonic::client::Grpc<Channel>,
+    signer: Arc<dyn Signer>,
     config_pubkey: PublicKey,
 }
 
 impl OrgClient {
-    pub fn from_settings(settings: &Settings) -> Result<Self, Box<helium_crypto::Error>> {
-        let channel = Endpoint::from(settings.url.clone())
-            .connect_timeout(Duration::from_secs(settings.connect_timeout))
-            .timeout(Duration::from_secs(settings.rpc_timeout))
-            .connect_lazy();
-        Ok(Self {
-            client: iot_config::config_org_client::OrgClient::new(channel),
-            signing_key: settings.signing_keypair()?,
-            config_pubkey: settings.config_pubkey()?,
-        })
+    pub fn new(channel: Channel, signer: Arc<dyn Signer>, config_pubkey: PublicKey) -> Self {
+        Self {
+            client: iot_config::config_org_client::OrgClient::new(channel.clone()),
+            batch: tonic::client::Grpc::new(channel),
+            signer,
+            config_pubkey,
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<Self, ClientError> {
+        Ok(Self::new(
+            settings.channel()?,
+            settings.signer()?,
+            settings.config_pubkey().map_err(Box::new)?,
+        ))
     }
 
     pub async fn get(&mut self, oui: u64) -> Result<OrgResV1, ClientError> {
@@ -51,10 +60,10 @@ impl OrgClient {
         let mut req = OrgEnableReqV1 {
             oui,
             timestamp: Utc::now().encode_timestamp(),
-            signer: self.signing_key.public_key().into(),
+            signer: self.signer.public_key().into(),
             signature: vec![],
         };
-        req.signature = self.signing_key.sign(&req.encode_to_vec())?;
+        req.signature = self.signer.sign(&req.encode_to_vec()).await?;
         let res = self.client.enable(req).await?.into_inner();
         res.verify(&self.config_pubkey)?;
         Ok(())
@@ -66,12 +75,44 @@ impl OrgClient {
         let mut req = OrgDisableReqV1 {
             oui,
             timestamp: Utc::now().encode_timestamp(),
-            signer: self.signing_key.public_key().into(),
+            signer: self.signer.public_key().into(),
             signature: vec![],
         };
-        req.signature = self.signing_key.sign(&req.encode_to_vec())?;
+        req.signature = self.signer.sign(&req.encode_to_vec()).await?;
         let res = self.client.disable(req).await?.into_inner();
         res.verify(&self.config_pubkey)?;
         Ok(())
     }
+
+    /// Lock or unlock the orgs with a single request, returning the result
+    /// of every lock in the order given
+    pub async fn set_locks(
+        &mut self,
+        locks: Vec<OrgLockV1>,
+    ) -> Result<Vec<OrgLockResultV1>, ClientError> {
+        tracing::info!(locks = locks.len(), "setting org locks");
+
+        let mut req = OrgSetLocksReqV1 {
+            locks,
+            timestamp: Utc::now().encode_timestamp(),
+            signer: self.signer.public_key().into(),
+            signature: vec![],
+        };
+        req.signature = self.signer.sign(&req.encode_to_vec()).await?;
+        self.batch
+            .ready()
+            .await
+            .map_err(|err| Status::unknown(format!("service was not ready: {err}")))?;
+        let res: OrgSetLocksResV1 = self
+            .batch
+            .unary(
+                Request::new(req),
+                http::uri::PathAndQuery::from_static(SET_LOCKS_PATH),
+                ProstCodec::default(),
+            )
+            .await
+            .map(Response::into_inner)?;
+        res.verify(&self.config_pubkey)?;
+        Ok(res.results)
+    }
 }
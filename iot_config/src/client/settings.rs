@@ -1,13 +1,30 @@
+use super::ClientError;
+use grpc_service::tls::ClientTlsSettings;
+use helium_proto::services::{Channel, Endpoint};
+use oracle_settings::Secret;
 use serde::Deserialize;
-use std::{str::FromStr, sync::Arc};
+use std::{str::FromStr, sync::Arc, time::Duration};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Settings {
     /// grpc url to the iot config oracle server
     #[serde(with = "http_serde::uri")]
     pub url: http::Uri,
-    /// File from which to load keypair for signing config client requests
-    pub signing_keypair: String,
+    /// grpc urls of further replicas of the iot config server. Requests are
+    /// balanced over all healthy urls. Default none
+    #[serde(default)]
+    pub additional_urls: Vec<String>,
+    /// Seconds between connection checks of each url, unhealthy urls are
+    /// taken out of rotation. Default 10
+    #[serde(default = "default_health_check_interval")]
+    pub health_check_interval: u64,
+    /// File from which to load keypair for signing config client requests,
+    /// or a `file:` or `env:` secret reference. Not needed when a signer is
+    /// set
+    #[serde(default)]
+    pub signing_keypair: Secret,
+    /// Signer for config client requests, instead of the keypair file
+    pub signer: Option<oracle_signer::Settings>,
     /// B58 encoded public key of the iot config server for verifying responses
     pub config_pubkey: String,
     /// Connect timeout for the iot config client in seconds. Default 5
@@ -19,6 +36,9 @@ pub struct Settings {
     /// Batch size for gateway info stream results. Default 1000
     #[serde(default = "default_batch_size")]
     pub batch_size: u32,
+    /// TLS options for connecting to a server using TLS. Plaintext when not
+    /// set
+    pub tls: Option<ClientTlsSettings>,
 }
 
 pub fn default_connect_timeout() -> u64 {
@@ -33,15 +53,69 @@ pub fn default_batch_size() -> u32 {
     1000
 }
 
+pub fn default_health_check_interval() -> u64 {
+    10
+}
+
 impl Settings {
     pub fn signing_keypair(
         &self,
     ) -> Result<Arc<helium_crypto::Keypair>, Box<helium_crypto::Error>> {
-        let data = std::fs::read(&self.signing_keypair).map_err(helium_crypto::Error::from)?;
-        Ok(Arc::new(helium_crypto::Keypair::try_from(&data[..])?))
+        crate::settings::read_keypair(&self.signing_keypair).map(Arc::new)
+    }
+
+    /// The configured signer, the keypair otherwise
+    pub fn signer(&self) -> Result<Arc<dyn oracle_signer::Signer>, ClientError> {
+        Ok(match &self.signer {
+            Some(signer) => oracle_signer::from_settings(signer)?,
+            None => Arc::new(oracle_signer::FileSigner::new(
+                crate::settings::read_keypair(&self.signing_keypair)?,
+            )),
+        })
     }
 
     pub fn config_pubkey(&self) -> Result<helium_crypto::PublicKey, helium_crypto::Error> {
         helium_crypto::PublicKey::from_str(&self.config_pubkey)
     }
+
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.health_check_interval == 0 {
+            problems.push("iot config client health_check_interval must be positive".to_string());
+        }
+        problems
+    }
+
+    pub fn endpoints(&self) -> Result<Vec<Endpoint>, ClientError> {
+        let mut uris = vec![self.url.clone()];
+        for url in &self.additional_urls {
+            uris.push(url.parse().map_err(|_| {
+                ClientError::InvalidSettings(format!("invalid additional url: {url}"))
+            })?);
+        }
+        let tls = self.tls.as_ref().map(|tls| tls.tls_config()).transpose()?;
+        uris.into_iter()
+            .map(|uri| {
+                let endpoint = Endpoint::from(uri)
+                    .connect_timeout(Duration::from_secs(self.connect_timeout))
+                    .timeout(Duration::from_secs(self.rpc_timeout));
+                match &tls {
+                    Some(tls) => Ok(endpoint.tls_config(tls.clone())?),
+                    None => Ok(endpoint),
+                }
+            })
+            .collect()
+    }
+
+    /// Channel balanced over all configured urls of the server
+    pub fn channel(&self) -> Result<Channel, ClientError> {
+        if let Some(problem) = self.problems().into_iter().next() {
+            return Err(ClientError::InvalidSettings(problem));
+        }
+        Ok(client_pool::balanced_channel(
+            "iot_config",
+            self.endpoints()?,
+            Duration::from_secs(self.health_check_interval),
+        ))
+    }
 }
@@ -0,0 +1,477 @@
+//! Versioned gateway denylists.
+//!
+//! Administrators upload signed denylist filters through the upload rpc of
+//! the denylist service. Every filter is verified and stored as a new
+//! version, versions must be strictly increasing. The latest version is
+//! streamed to the subscribers of the denylist stream, first when they
+//! subscribe and then whenever a newer version is uploaded, and periodically
+//! exported to the output bucket.
+//!
+//! Like the api token endpoints the service is hand written on top of the
+//! tonic primitives with its messages defined here.
+
+use crate::{
+    admin::{AuthCache, KeyType},
+    audit::AuditLogger,
+    telemetry, verify_public_key, verify_request_timestamp, GrpcStreamResult,
+};
+use chrono::{DateTime, Utc};
+use file_store::{file_sink::FileSinkClient, traits::TimestampEncode};
+use helium_crypto::{Keypair, Sign};
+use prost::Message;
+use sqlx::{Pool, Postgres};
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use tokio::sync::{broadcast, mpsc};
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const EXPORT_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// Length of the filter version and of the signature length preceding the
+// signature of a filter binary
+const FILTER_PREFIX_LEN: usize = 3;
+// Length of the serial number following the signature of a filter binary
+const FILTER_SERIAL_LEN: usize = 4;
+
+const SERVICE_NAME: &str = "helium.iot_config.denylist";
+pub const UPLOAD_PATH: &str = "/helium.iot_config.denylist/upload";
+pub const STREAM_PATH: &str = "/helium.iot_config.denylist/stream";
+
+/// Denylist version exported to the output bucket so verifiers and routers
+/// consume the same filter served by the config service
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DenylistV1 {
+    /// Serial number of the signed filter
+    #[prost(uint64, tag = "1")]
+    pub version: u64,
+    /// The signed filter binary as published, including its signature
+    #[prost(bytes = "vec", tag = "2")]
+    pub filter: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DenylistUploadReqV1 {
+    /// The signed filter binary
+    #[prost(bytes = "vec", tag = "1")]
+    pub filter: Vec<u8>,
+    /// Seconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "2")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DenylistUploadResV1 {
+    /// Version the filter was stored as
+    #[prost(uint64, tag = "1")]
+    pub version: u64,
+    #[prost(uint64, tag = "2")]
+    pub timestamp: u64,
+    /// Key of the config server
+    #[prost(bytes = "vec", tag = "3")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DenylistStreamReqV1 {
+    /// Seconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "1")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "2")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: Vec<u8>,
+}
+
+file_store::impl_msg_verify!(DenylistUploadReqV1, signature);
+file_store::impl_msg_verify!(DenylistUploadResV1, signature);
+file_store::impl_msg_verify!(DenylistStreamReqV1, signature);
+
+#[derive(thiserror::Error, Debug)]
+pub enum DenylistError {
+    #[error("invalid denylist: {0}")]
+    InvalidFilter(#[from] denylist::Error),
+    #[error("denylist of {len} bytes is shorter than its {header_len} byte header")]
+    TruncatedFilter { len: usize, header_len: usize },
+    #[error("denylist version {0} already stored")]
+    DuplicateVersion(u32),
+    #[error("denylist version {new} is not newer than latest {latest}")]
+    StaleVersion { new: u32, latest: i64 },
+    #[error("denylist store error: {0}")]
+    DbStore(#[from] sqlx::Error),
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct DenylistVersion {
+    pub version: i64,
+    pub filter: Vec<u8>,
+    pub inserted_at: DateTime<Utc>,
+}
+
+impl From<DenylistVersion> for DenylistV1 {
+    fn from(version: DenylistVersion) -> Self {
+        Self {
+            version: version.version as u64,
+            filter: version.filter,
+        }
+    }
+}
+
+/// Verify the signature of a denylist filter and store it as a new version.
+/// Versions must be strictly increasing so a replayed older filter can never
+/// replace the current one.
+pub async fn insert(pool: &Pool<Postgres>, filter: &[u8]) -> Result<u32, DenylistError> {
+    check_header(filter)?;
+    let (version, _filter) = denylist::denylist::serial_and_filter_from_bin(filter)?;
+
+    let mut transaction = pool.begin().await?;
+    // serialize concurrent uploads so the version check is reliable
+    sqlx::query("lock table denylist_versions in exclusive mode")
+        .execute(&mut transaction)
+        .await?;
+
+    let latest: Option<i64> = sqlx::query_scalar("select max(version) from denylist_versions")
+        .fetch_one(&mut transaction)
+        .await?;
+    check_version(version, latest)?;
+
+    sqlx::query("insert into denylist_versions (version, filter) values ($1, $2)")
+        .bind(version as i64)
+        .bind(filter)
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+
+    Ok(version)
+}
+
+/// Check the filter binary holds the header the denylist crate reads without
+/// bounds checks: the version, the signature length, the signature and the
+/// serial number
+fn check_header(filter: &[u8]) -> Result<(), DenylistError> {
+    let header_len = match filter.get(1..FILTER_PREFIX_LEN) {
+        Some(signature_len) => {
+            let signature_len = u16::from_le_bytes([signature_len[0], signature_len[1]]);
+            FILTER_PREFIX_LEN + signature_len as usize + FILTER_SERIAL_LEN
+        }
+        None => FILTER_PREFIX_LEN + FILTER_SERIAL_LEN,
+    };
+    if filter.len() < header_len {
+        return Err(DenylistError::TruncatedFilter {
+            len: filter.len(),
+            header_len,
+        });
+    }
+    Ok(())
+}
+
+fn check_version(version: u32, latest: Option<i64>) -> Result<(), DenylistError> {
+    match latest {
+        Some(latest) if latest == version as i64 => Err(DenylistError::DuplicateVersion(version)),
+        Some(latest) if latest > version as i64 => Err(DenylistError::StaleVersion {
+            new: version,
+            latest,
+        }),
+        _ => Ok(()),
+    }
+}
+
+pub async fn latest(db: impl sqlx::PgExecutor<'_>) -> Result<Option<DenylistVersion>, sqlx::Error> {
+    sqlx::query_as::<_, DenylistVersion>(
+        r#"
+        select version, filter, inserted_at from denylist_versions
+        order by version desc
+        limit 1
+        "#,
+    )
+    .fetch_optional(db)
+    .await
+}
+
+impl From<DenylistError> for Status {
+    fn from(err: DenylistError) -> Self {
+        match err {
+            DenylistError::InvalidFilter(_) | DenylistError::TruncatedFilter { .. } => {
+                Status::invalid_argument(err.to_string())
+            }
+            DenylistError::DuplicateVersion(_) => Status::already_exists(err.to_string()),
+            DenylistError::StaleVersion { .. } => Status::failed_precondition(err.to_string()),
+            DenylistError::DbStore(_) => {
+                tracing::error!(reason = ?err, "failed to store denylist");
+                Status::internal("denylist store failed")
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DenylistService {
+    auth_cache: AuthCache,
+    audit: AuditLogger,
+    pool: Pool<Postgres>,
+    signing_key: Arc<Keypair>,
+    denylist_tx: broadcast::Sender<DenylistV1>,
+}
+
+impl DenylistService {
+    pub fn new(
+        auth_cache: AuthCache,
+        audit: AuditLogger,
+        pool: Pool<Postgres>,
+        signing_key: Arc<Keypair>,
+    ) -> Self {
+        Self {
+            auth_cache,
+            audit,
+            pool,
+            signing_key,
+            denylist_tx: crate::update_channel(),
+        }
+    }
+
+    pub async fn upload(
+        &self,
+        request: DenylistUploadReqV1,
+    ) -> Result<DenylistUploadResV1, Status> {
+        telemetry::count_request("denylist", "upload");
+        let audit = self
+            .audit
+            .begin("denylist", "upload")
+            .with_request(&request.signer, &request);
+
+        let signer = verify_public_key(&request.signer)?;
+        self.auth_cache
+            .verify_signature_with_type(KeyType::Administrator, &signer, &request)
+            .map_err(|_| Status::permission_denied("invalid admin signature"))?;
+        verify_request_timestamp(request.timestamp)?;
+
+        let version = insert(&self.pool, &request.filter).await?;
+        tracing::info!(version, "stored denylist");
+        let denylist = DenylistV1 {
+            version: version as u64,
+            filter: request.filter,
+        };
+        if self.denylist_tx.send(denylist).is_err() {
+            tracing::debug!(version, "no denylist subscribers");
+        }
+
+        let mut response = DenylistUploadResV1 {
+            version: version as u64,
+            timestamp: Utc::now().encode_timestamp(),
+            signer: self.signing_key.public_key().into(),
+            signature: vec![],
+        };
+        response.signature = self
+            .signing_key
+            .sign(&response.encode_to_vec())
+            .map_err(|_| Status::internal("response signing error"))?;
+
+        audit.succeeded();
+        Ok(response)
+    }
+
+    pub async fn stream(
+        &self,
+        request: DenylistStreamReqV1,
+    ) -> Result<GrpcStreamResult<DenylistV1>, Status> {
+        telemetry::count_request("denylist", "stream");
+        let signer = verify_public_key(&request.signer)?;
+        self.auth_cache
+            .verify_signature(&signer, &request)
+            .map_err(|_| Status::permission_denied("unauthorized request signature"))?;
+        verify_request_timestamp(request.timestamp)?;
+
+        // subscribe before reading the latest version so no upload is missed
+        let mut updates = self.denylist_tx.subscribe();
+        let current = latest(&self.pool).await.map_err(|err| {
+            tracing::error!(reason = ?err, "failed to read latest denylist");
+            Status::internal("denylist lookup failed")
+        })?;
+
+        tracing::info!("client subscribed to denylist stream");
+        let (tx, rx) = mpsc::channel(2);
+        tokio::spawn(async move {
+            let mut sent_version = None;
+            if let Some(current) = current {
+                sent_version = Some(current.version as u64);
+                if tx.send(Ok(current.into())).await.is_err() {
+                    return;
+                }
+            }
+            loop {
+                let update = match updates.recv().await {
+                    // a version sent on subscribe may also have been broadcast
+                    Ok(update) if Some(update.version) <= sent_version => continue,
+                    Ok(update) => {
+                        sent_version = Some(update.version);
+                        Ok(update)
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => Err(Status::aborted(
+                        format!("denylist stream lagged by {skipped} versions; resubscribe"),
+                    )),
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                let lagged = update.is_err();
+                if tx.send(update).await.is_err() || lagged {
+                    return;
+                }
+            }
+        });
+        Ok(GrpcStreamResult::new(rx))
+    }
+}
+
+impl NamedService for DenylistService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for DenylistService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            UPLOAD_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.upload(request).await })
+            }
+            STREAM_PATH => grpc_service::server_streaming(req, move |request| async move {
+                svc.stream(request).await
+            }),
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
+
+/// Periodically exports the latest denylist version to the output bucket
+/// whenever a newer version has been stored
+pub struct DenylistExporter {
+    pool: Pool<Postgres>,
+    denylist_sink: FileSinkClient,
+    exported_version: Option<i64>,
+}
+
+impl DenylistExporter {
+    pub fn new(pool: Pool<Postgres>, denylist_sink: FileSinkClient) -> Self {
+        Self {
+            pool,
+            denylist_sink,
+            exported_version: None,
+        }
+    }
+
+    pub async fn run(mut self, shutdown: triggered::Listener) -> anyhow::Result<()> {
+        tracing::info!("starting denylist exporter");
+        let mut export_timer = tokio::time::interval(EXPORT_CHECK_INTERVAL);
+        export_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                _ = export_timer.tick() => self.export_latest().await?,
+            }
+        }
+        tracing::info!("stopping denylist exporter");
+        Ok(())
+    }
+
+    async fn export_latest(&mut self) -> anyhow::Result<()> {
+        let latest = match latest(&self.pool).await? {
+            Some(latest) if Some(latest.version) > self.exported_version => latest,
+            _ => return Ok(()),
+        };
+        let version = latest.version;
+        tracing::info!(version, "exporting denylist");
+        self.denylist_sink
+            .write(DenylistV1::from(latest), &[])
+            .await?
+            // wait for the version to be written before committing
+            .await??;
+        self.denylist_sink.commit().await?;
+        self.exported_version = Some(version);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_newer_versions_are_accepted() {
+        assert!(check_version(1, None).is_ok());
+        assert!(check_version(5, Some(4)).is_ok());
+        assert!(matches!(
+            check_version(4, Some(4)),
+            Err(DenylistError::DuplicateVersion(4))
+        ));
+        assert!(matches!(
+            check_version(3, Some(4)),
+            Err(DenylistError::StaleVersion { new: 3, latest: 4 })
+        ));
+    }
+
+    /// Filter binary header with a signature of `signature_len` bytes
+    fn header(signature_len: u16) -> Vec<u8> {
+        let mut header = vec![1];
+        header.extend(signature_len.to_le_bytes());
+        header.extend(vec![0; signature_len as usize]);
+        header.extend(7_u32.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn truncated_filters_are_rejected() {
+        for filter in [vec![], vec![1], vec![1, 64]] {
+            assert!(matches!(
+                check_header(&filter),
+                Err(DenylistError::TruncatedFilter { header_len: 7, .. })
+            ));
+        }
+        let full = header(64);
+        assert!(check_header(&full).is_ok());
+        let short = &full[..full.len() - 1];
+        assert!(matches!(
+            check_header(short),
+            Err(DenylistError::TruncatedFilter {
+                len: 70,
+                header_len: 71
+            })
+        ));
+        let status: Status = check_header(short).unwrap_err().into();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn rejected_uploads_map_to_client_errors() {
+        let duplicate: Status = DenylistError::DuplicateVersion(4).into();
+        assert_eq!(duplicate.code(), tonic::Code::AlreadyExists);
+        let stale: Status = DenylistError::StaleVersion { new: 3, latest: 4 }.into();
+        assert_eq!(stale.code(), tonic::Code::FailedPrecondition);
+        let invalid: Status = DenylistError::from(
+            denylist::denylist::serial_and_filter_from_bin(&[])
+                .err()
+                .unwrap(),
+        )
+        .into();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -26,11 +26,14 @@ use tokio::sync::watch;
 use tonic::{Request, Response, Status};
 
 const CACHE_EVICTION_FREQUENCY: Duration = Duration::from_secs(60 * 60);
-const CACHE_TTL: Duration = Duration::from_secs(60 * 60 * 3);
+// Gateways per info stream response when the request leaves it unset
+const DEFAULT_INFO_STREAM_BATCH_SIZE: u32 = 1000;
+const MAX_INFO_STREAM_BATCH_SIZE: u32 = 5000;
 
 pub struct GatewayService {
     auth_cache: AuthCache,
     gateway_cache: Arc<Cache<PublicKeyBinary, GatewayInfo>>,
+    gateway_cache_ttl: Duration,
     metadata_pool: Pool<Postgres>,
     region_map: RegionMapReader,
     signing_key: Arc<Keypair>,
@@ -52,6 +55,7 @@ impl GatewayService {
         Ok(Self {
             auth_cache,
             gateway_cache,
+            gateway_cache_ttl: settings.gateway_cache_ttl(),
             metadata_pool,
             region_map,
             signing_key: Arc::new(settings.signing_keypair()?),
@@ -91,7 +95,10 @@ impl GatewayService {
 
     async fn resolve_gateway_info(&self, pubkey: &PublicKeyBinary) -> Result<GatewayInfo, Status> {
         match self.gateway_cache.get(pubkey).await {
-            Some(gateway) => Ok(gateway.value().clone()),
+            Some(gateway) => {
+                telemetry::count_gateway_info_lookup("cache-hit");
+                Ok(gateway.value().clone())
+            }
             None => {
                 let metadata = tokio::select! {
                     query_result = gateway_info::db::get_info(&self.metadata_pool, pubkey) => {
@@ -108,7 +115,7 @@ impl GatewayService {
                 };
                 let gateway = GatewayInfo::chain_metadata_to_info(metadata, &self.region_map);
                 self.gateway_cache
-                    .insert(pubkey.clone(), gateway.clone(), CACHE_TTL)
+                    .insert(pubkey.clone(), gateway.clone(), self.gateway_cache_ttl)
                     .await;
                 if gateway.metadata.is_some() {
                     telemetry::count_gateway_info_lookup("asserted");
@@ -273,26 +280,30 @@ impl iot_config::Gateway for GatewayService {
 
         let pool = self.metadata_pool.clone();
         let signing_key = self.signing_key.clone();
-        let batch_size = request.batch_size;
+        let batch_size = info_stream_batch_size(request.batch_size);
         let region_map = self.region_map.clone();
 
         let (tx, rx) = tokio::sync::mpsc::channel(20);
 
         tokio::spawn(async move {
-            stream_all_gateways_info(
-                &pool,
-                tx.clone(),
-                &signing_key,
-                region_map.clone(),
-                batch_size,
-            )
-            .await
+            if let Err(err) =
+                stream_all_gateways_info(&pool, tx, &signing_key, region_map, batch_size).await
+            {
+                tracing::warn!("gateway info stream ended early: {err:?}");
+            }
         });
 
         Ok(Response::new(GrpcStreamResult::new(rx)))
     }
 }
 
+fn info_stream_batch_size(requested: u32) -> u32 {
+    match requested {
+        0 => DEFAULT_INFO_STREAM_BATCH_SIZE,
+        size => size.min(MAX_INFO_STREAM_BATCH_SIZE),
+    }
+}
+
 async fn stream_all_gateways_info(
     pool: &Pool<Postgres>,
     tx: tokio::sync::mpsc::Sender<Result<GatewayInfoStreamResV1, Status>>,
@@ -334,3 +345,15 @@ async fn stream_all_gateways_info(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_stream_batch_size_is_never_zero() {
+        assert_eq!(info_stream_batch_size(0), DEFAULT_INFO_STREAM_BATCH_SIZE);
+        assert_eq!(info_stream_batch_size(10), 10);
+        assert_eq!(info_stream_batch_size(u32::MAX), MAX_INFO_STREAM_BATCH_SIZE);
+    }
+}
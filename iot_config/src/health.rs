@@ -0,0 +1,172 @@
+use crate::{region_map::RegionMapReader, settings};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use sqlx::{Pool, Postgres};
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+use tonic_health::{server::HealthReporter, ServingStatus};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Readiness {
+    pub database: bool,
+    pub region_data: bool,
+    pub signing_key: bool,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.failures().is_empty()
+    }
+
+    pub fn failures(&self) -> Vec<&'static str> {
+        [
+            (self.database, "database"),
+            (self.region_data, "region_data"),
+            (self.signing_key, "signing_key"),
+        ]
+        .into_iter()
+        .filter_map(|(ok, check)| (!ok).then_some(check))
+        .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct HealthChecker {
+    pool: Pool<Postgres>,
+    region_map: RegionMapReader,
+    keypair: oracle_settings::Secret,
+}
+
+impl HealthChecker {
+    pub fn new(
+        settings: &settings::Settings,
+        pool: Pool<Postgres>,
+        region_map: RegionMapReader,
+    ) -> Self {
+        Self {
+            pool,
+            region_map,
+            keypair: settings.keypair.clone(),
+        }
+    }
+
+    pub async fn check(&self) -> Readiness {
+        let database = matches!(
+            tokio::time::timeout(
+                DB_CHECK_TIMEOUT,
+                sqlx::query("select 1").execute(&self.pool)
+            )
+            .await,
+            Ok(Ok(_))
+        );
+        Readiness {
+            database,
+            region_data: self.region_map.is_loaded(),
+            signing_key: settings::read_keypair(&self.keypair).is_ok(),
+        }
+    }
+
+    /// Keep the grpc health-check protocol status of the config service in
+    /// line with its readiness
+    pub async fn report_grpc(
+        self,
+        mut reporter: HealthReporter,
+        shutdown: triggered::Listener,
+    ) -> anyhow::Result<()> {
+        let mut check_timer = tokio::time::interval(CHECK_INTERVAL);
+        check_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => {
+                    reporter.set_service_status("", ServingStatus::NotServing).await;
+                    return Ok(());
+                }
+                _ = check_timer.tick() => {
+                    let readiness = self.check().await;
+                    let status = if readiness.is_ready() {
+                        ServingStatus::Serving
+                    } else {
+                        tracing::warn!(failures = ?readiness.failures(), "service not ready");
+                        ServingStatus::NotServing
+                    };
+                    reporter.set_service_status("", status).await;
+                }
+            }
+        }
+    }
+
+    /// Serve the http liveness (/healthz) and readiness (/readyz) probes
+    pub async fn serve_http(
+        self,
+        addr: SocketAddr,
+        shutdown: triggered::Listener,
+    ) -> anyhow::Result<()> {
+        let make_service = make_service_fn(move |_conn| {
+            let checker = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let checker = checker.clone();
+                    async move { Ok::<_, Infallible>(checker.handle(request).await) }
+                }))
+            }
+        });
+
+        tracing::info!(%addr, "serving health probes");
+        hyper::Server::try_bind(&addr)?
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown)
+            .await?;
+        Ok(())
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        match (request.method(), request.uri().path()) {
+            (&Method::GET, "/healthz") => response(StatusCode::OK, "ok".to_string()),
+            (&Method::GET, "/readyz") => {
+                let readiness = self.check().await;
+                if readiness.is_ready() {
+                    response(StatusCode::OK, "ok".to_string())
+                } else {
+                    response(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        format!("not ready: {}", readiness.failures().join(", ")),
+                    )
+                }
+            }
+            _ => response(StatusCode::NOT_FOUND, "not found".to_string()),
+        }
+    }
+}
+
+fn response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_only_when_all_checks_pass() {
+        let ready = Readiness {
+            database: true,
+            region_data: true,
+            signing_key: true,
+        };
+        assert!(ready.is_ready());
+
+        let starting = Readiness {
+            database: true,
+            ..Default::default()
+        };
+        assert!(!starting.is_ready());
+        assert_eq!(starting.failures(), vec!["region_data", "signing_key"]);
+    }
+}
@@ -1,20 +1,28 @@
 pub mod admin;
 pub mod admin_service;
+pub mod api_token;
+pub mod audit;
 pub mod client;
+pub mod gateway_denylist;
 pub mod gateway_info;
 pub mod gateway_service;
+pub mod health;
 mod helium_netids;
 pub mod lora_field;
 pub mod org;
+pub mod org_batch;
+pub mod org_review;
 pub mod org_service;
+pub mod rate_limit;
 pub mod region_map;
 pub mod route;
 pub mod route_service;
+pub mod route_snapshot;
 pub mod settings;
 pub mod telemetry;
 
 pub use admin_service::AdminService;
-pub use client::{Client, Settings as ClientSettings};
+pub use client::{register_error_codes, Client, Settings as ClientSettings};
 pub use gateway_service::GatewayService;
 pub use org_service::OrgService;
 pub use route_service::RouteService;
@@ -31,6 +39,10 @@ pub type GrpcStreamRequest<T> = tonic::Request<tonic::Streaming<T>>;
 
 pub const BROADCAST_CHANNEL_QUEUE: usize = 1024;
 
+/// Maximum difference in seconds between a signed request's timestamp and the
+/// server clock before the request is rejected as stale or replayed
+pub const REQUEST_TIMESTAMP_TOLERANCE_SECS: u64 = 5 * 60;
+
 pub fn update_channel<T: Clone>() -> broadcast::Sender<T> {
     let (update_tx, _) = broadcast::channel(BROADCAST_CHANNEL_QUEUE);
     update_tx
@@ -52,7 +64,48 @@ fn enqueue_update(queue_size: usize) -> bool {
     (queue_size * 100) / BROADCAST_CHANNEL_QUEUE <= 80
 }
 
+/// Reject signed requests whose timestamp (in seconds) is too far from now,
+/// so a captured request can not be replayed indefinitely.
+pub fn verify_request_timestamp(timestamp: u64) -> Result<(), Status> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    if timestamp_within_tolerance(timestamp, now) {
+        Ok(())
+    } else {
+        Err(Status::invalid_argument(format!(
+            "request timestamp {timestamp} outside of allowed window"
+        )))
+    }
+}
+
+fn timestamp_within_tolerance(timestamp: u64, now: u64) -> bool {
+    timestamp.abs_diff(now) <= REQUEST_TIMESTAMP_TOLERANCE_SECS
+}
+
 pub fn verify_public_key(bytes: &[u8]) -> Result<PublicKey, Status> {
     PublicKey::try_from(bytes)
         .map_err(|_| Status::invalid_argument(format!("invalid public key: {bytes:?}")))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_timestamp_tolerance() {
+        let now = 1_686_000_000;
+        assert!(timestamp_within_tolerance(now, now));
+        assert!(timestamp_within_tolerance(
+            now - REQUEST_TIMESTAMP_TOLERANCE_SECS,
+            now
+        ));
+        assert!(timestamp_within_tolerance(
+            now + REQUEST_TIMESTAMP_TOLERANCE_SECS,
+            now
+        ));
+        assert!(!timestamp_within_tolerance(
+            now - REQUEST_TIMESTAMP_TOLERANCE_SECS - 1,
+            now
+        ));
+        assert!(!timestamp_within_tolerance(0, now));
+    }
+}
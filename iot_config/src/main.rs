@@ -1,16 +1,42 @@
 use anyhow::{Error, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use clap::Parser;
+use custom_tracing::admin::LogFilterService;
+use file_store::{file_upload, file_upload::FileUpload, FileSink, FileSinkBuilder, FileType};
 use futures_util::TryFutureExt;
-use helium_proto::services::iot_config::{AdminServer, GatewayServer, OrgServer, RouteServer};
+use grpc_service::tls;
+use helium_proto::{
+    services::iot_config::{AdminServer, GatewayServer, OrgServer, RouteServer},
+    BlockchainRegionParamsV1, Message, Region,
+};
 use iot_config::{
-    admin::AuthCache, admin_service::AdminService, gateway_service::GatewayService, org,
-    org_service::OrgService, region_map::RegionMapReader, route_service::RouteService,
-    settings::Settings, telemetry,
+    admin::AuthCache,
+    admin_service::AdminService,
+    api_token::ApiTokenService,
+    audit::{AuditDaemon, AuditLogger, AuditService},
+    gateway_denylist::{DenylistExporter, DenylistService},
+    gateway_service::GatewayService,
+    health::HealthChecker,
+    org,
+    org_batch::OrgBatchService,
+    org_review::OrgReviewService,
+    org_service::OrgService,
+    rate_limit::RateLimiter,
+    region_map,
+    region_map::RegionMapReader,
+    route_service::RouteService,
+    route_snapshot::{RouteSnapshotExporter, RouteSnapshotService},
+    settings::Settings,
+    telemetry,
+};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
-use std::{path::PathBuf, time::Duration};
 use tokio::signal;
 use tonic::transport;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Debug, clap::Parser)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
@@ -36,25 +62,66 @@ impl Cli {
 #[derive(Debug, clap::Subcommand)]
 pub enum Cmd {
     Server(Daemon),
+    ScheduleRegionParams(ScheduleRegionParams),
 }
 
 impl Cmd {
     pub async fn run(&self, settings: Settings) -> Result<()> {
         match self {
             Self::Server(cmd) => cmd.run(&settings).await,
+            Self::ScheduleRegionParams(cmd) => cmd.run(&settings).await,
         }
     }
 }
 
+/// Schedule region params taking effect at a later time
+#[derive(Debug, clap::Args)]
+pub struct ScheduleRegionParams {
+    /// Region of the params, e.g. US915
+    #[clap(long)]
+    region: String,
+    /// Path to the protobuf encoded BlockchainRegionParamsV1
+    #[clap(long)]
+    params: PathBuf,
+    /// When the params take effect, in UTC
+    #[clap(long)]
+    effective_at: NaiveDateTime,
+}
+
+impl ScheduleRegionParams {
+    pub async fn run(&self, settings: &Settings) -> Result<()> {
+        let region = Region::from_str(&self.region)
+            .map_err(|_| anyhow::anyhow!("unknown region: {}", self.region))?;
+        let params = BlockchainRegionParamsV1::decode(std::fs::read(&self.params)?.as_slice())?;
+        let effective_at = DateTime::<Utc>::from_utc(self.effective_at, Utc);
+        let (_shutdown_trigger, shutdown_listener) = triggered::trigger();
+        let (pool, _db_join_handle) = settings
+            .database
+            .connect("iot-config-store", shutdown_listener)
+            .await?;
+        let version = region_map::schedule_params(region, &params, effective_at, &pool).await?;
+        println!("scheduled region params version {version} of {region} at {effective_at}");
+        Ok(())
+    }
+}
+
 #[derive(Debug, clap::Args)]
-pub struct Daemon;
+pub struct Daemon {
+    /// Apply database migrations and exit without starting the service
+    #[clap(long)]
+    migrate_only: bool,
+}
 
 impl Daemon {
     pub async fn run(&self, settings: &Settings) -> Result<()> {
-        tracing_subscriber::registry()
-            .with(tracing_subscriber::EnvFilter::new(&settings.log))
-            .with(tracing_subscriber::fmt::layer())
-            .init();
+        custom_tracing::init(&settings.log, &settings.tracing)?;
+        poc_metrics::register_error_codes!(
+            poc_metrics,
+            db_store,
+            file_store,
+            oracle_signer,
+            iot_config,
+        );
 
         // Install prometheus metrics exporter
         poc_metrics::start_metrics(&settings.metrics)?;
@@ -62,6 +129,7 @@ impl Daemon {
 
         // Configure shutdown trigger
         let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        poc_metrics::process::start(shutdown_listener.clone());
         let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
         tokio::spawn(async move {
             tokio::select! {
@@ -75,7 +143,10 @@ impl Daemon {
             .database
             .connect("iot-config-store", shutdown_listener.clone())
             .await?;
-        sqlx::migrate!().run(&pool).await?;
+        db_store::migrate::run(&pool, env!("CARGO_PKG_NAME"), &sqlx::migrate!()).await?;
+        if self.migrate_only {
+            return Ok(());
+        }
 
         // Create on-chain metadata pool
         let (metadata_pool, md_pool_handle) = settings
@@ -87,8 +158,94 @@ impl Daemon {
 
         let (auth_updater, auth_cache) = AuthCache::new(settings, &pool).await?;
         let (region_updater, region_map) = RegionMapReader::new(&pool).await?;
+        let region_updater = Arc::new(region_updater);
         let (delegate_key_updater, delegate_key_cache) = org::delegate_keys_cache(&pool).await?;
 
+        let (audit, audit_events) = AuditLogger::new();
+        let (audit_sink, denylist_exporter, snapshots, file_servers) = match &settings.output {
+            Some(output) => {
+                let (file_upload_tx, file_upload_rx) = file_upload::message_channel();
+                let file_upload = FileUpload::from_settings(output, file_upload_rx).await?;
+                let (audit_sink, audit_sink_server) = FileSinkBuilder::new(
+                    FileType::IotConfigAudit,
+                    Path::new(&settings.cache),
+                    concat!(env!("CARGO_PKG_NAME"), "_audit"),
+                    shutdown_listener.clone(),
+                )
+                .deposits(Some(file_upload_tx.clone()))
+                .create()
+                .await?;
+                let (denylist_sink, denylist_sink_server) = FileSinkBuilder::new(
+                    FileType::IotConfigDenylist,
+                    Path::new(&settings.cache),
+                    concat!(env!("CARGO_PKG_NAME"), "_denylist"),
+                    shutdown_listener.clone(),
+                )
+                .deposits(Some(file_upload_tx.clone()))
+                .auto_commit(false)
+                .create()
+                .await?;
+                let (snapshot_sink, snapshot_sink_server) = FileSinkBuilder::new(
+                    FileType::IotConfigRouteSnapshot,
+                    Path::new(&settings.cache),
+                    concat!(env!("CARGO_PKG_NAME"), "_route_snapshot"),
+                    shutdown_listener.clone(),
+                )
+                .deposits(Some(file_upload_tx))
+                .auto_commit(false)
+                .create()
+                .await?;
+                (
+                    Some(audit_sink),
+                    Some(DenylistExporter::new(pool.clone(), denylist_sink)),
+                    Some(RouteSnapshotExporter::new(
+                        pool.clone(),
+                        snapshot_sink,
+                        settings.route_snapshot_interval(),
+                    )),
+                    Some((
+                        file_upload,
+                        vec![
+                            audit_sink_server,
+                            denylist_sink_server,
+                            snapshot_sink_server,
+                        ],
+                    )),
+                )
+            }
+            None => (None, None, None, None),
+        };
+        let (snapshot_exporter, latest_snapshot) = match snapshots {
+            Some((exporter, latest_snapshot)) => (Some(exporter), latest_snapshot),
+            None => (None, tokio::sync::watch::channel(None).1),
+        };
+        let route_snapshot_svc = RouteSnapshotService::new(
+            auth_cache.clone(),
+            Arc::new(settings.signing_keypair()?),
+            settings.output.as_ref().map(|output| output.bucket.clone()),
+            latest_snapshot,
+            // one failed export is tolerated before routers fall back to
+            // streaming
+            settings.route_snapshot_interval() * 2,
+        );
+        let api_token_svc = ApiTokenService::new(
+            auth_cache.clone(),
+            Arc::new(settings.signing_keypair()?),
+            pool.clone(),
+        );
+        let audit_daemon = AuditDaemon::new(pool.clone(), audit_events, audit_sink);
+        let denylist_svc = DenylistService::new(
+            auth_cache.clone(),
+            audit.clone(),
+            pool.clone(),
+            Arc::new(settings.signing_keypair()?),
+        );
+        let audit_svc = AuditService::new(
+            auth_cache.clone(),
+            pool.clone(),
+            Arc::new(settings.signing_keypair()?),
+        );
+
         let gateway_svc = GatewayService::new(
             settings,
             metadata_pool,
@@ -99,44 +256,127 @@ impl Daemon {
         let route_svc = RouteService::new(
             settings,
             auth_cache.clone(),
+            audit.clone(),
             pool.clone(),
             shutdown_listener.clone(),
         )?;
         let org_svc = OrgService::new(
             settings,
             auth_cache.clone(),
+            audit.clone(),
             pool.clone(),
             route_svc.clone_update_channel(),
             delegate_key_updater,
         )?;
+        let org_batch_svc = OrgBatchService::new(
+            auth_cache.clone(),
+            audit.clone(),
+            pool.clone(),
+            Arc::new(settings.signing_keypair()?),
+            route_svc.clone_update_channel(),
+        );
+        let org_review_svc = OrgReviewService::new(
+            auth_cache.clone(),
+            audit.clone(),
+            pool.clone(),
+            Arc::new(settings.signing_keypair()?),
+            route_svc.clone_update_channel(),
+        );
         let admin_svc = AdminService::new(
             settings,
             auth_cache.clone(),
             auth_updater,
+            audit,
             pool.clone(),
             region_map.clone(),
-            region_updater,
+            region_updater.clone(),
         )?;
 
+        let (health_reporter, health_service) = tonic_health::server::health_reporter();
+        let health_checker = HealthChecker::new(settings, pool.clone(), region_map.clone());
+        let health_grpc = health_checker
+            .clone()
+            .report_grpc(health_reporter, shutdown_listener.clone());
+        let health_http =
+            health_checker.serve_http(settings.health_listen_addr()?, shutdown_listener.clone());
+
+        let file_servers = run_file_servers(file_servers, shutdown_listener.clone());
+        let denylist_export = {
+            let shutdown = shutdown_listener.clone();
+            async move {
+                match denylist_exporter {
+                    Some(exporter) => exporter.run(shutdown).await,
+                    None => Ok(()),
+                }
+            }
+        };
+        let route_snapshot_export = {
+            let shutdown = shutdown_listener.clone();
+            async move {
+                match snapshot_exporter {
+                    Some(exporter) => exporter.run(shutdown).await,
+                    None => Ok(()),
+                }
+            }
+        };
+        let audit_daemon = audit_daemon.run(shutdown_listener.clone());
+        let region_params_refresh =
+            region_map::refresh_params(pool.clone(), region_updater, shutdown_listener.clone());
+
         let pubkey = settings
             .signing_keypair()
             .map(|keypair| keypair.public_key().to_string())?;
         tracing::debug!("listening on {listen_addr}");
         tracing::debug!("signing as {pubkey}");
 
-        let server = transport::Server::builder()
+        let router = transport::Server::builder()
             .http2_keepalive_interval(Some(Duration::from_secs(250)))
             .http2_keepalive_timeout(Some(Duration::from_secs(60)))
+            .layer(RateLimiter::from_settings(
+                &settings.rate_limits,
+                auth_cache.clone(),
+            )?)
+            .add_service(health_service)
             .add_service(GatewayServer::new(gateway_svc))
             .add_service(OrgServer::new(org_svc))
             .add_service(RouteServer::new(route_svc))
             .add_service(AdminServer::new(admin_svc))
-            .serve_with_shutdown(listen_addr, shutdown_listener)
-            .map_err(Error::from);
+            .add_service(route_snapshot_svc)
+            .add_service(api_token_svc)
+            .add_service(org_review_svc)
+            .add_service(org_batch_svc)
+            .add_service(audit_svc)
+            .add_service(denylist_svc)
+            .add_service(LogFilterService::new(auth_cache.clone()));
+
+        let server = async {
+            match &settings.tls {
+                Some(tls) => {
+                    let incoming =
+                        tls::incoming(tls, listen_addr, shutdown_listener.clone()).await?;
+                    router
+                        .serve_with_incoming_shutdown(incoming, shutdown_listener)
+                        .await?
+                }
+                None => {
+                    router
+                        .serve_with_shutdown(listen_addr, shutdown_listener)
+                        .await?
+                }
+            }
+            Ok::<(), Error>(())
+        };
 
         tokio::try_join!(
             db_join_handle.map_err(Error::from),
             md_pool_handle.map_err(Error::from),
+            region_params_refresh,
+            audit_daemon,
+            file_servers,
+            denylist_export,
+            route_snapshot_export,
+            health_grpc,
+            health_http,
             server
         )?;
 
@@ -149,3 +389,19 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
     cli.run().await
 }
+
+async fn run_file_servers(
+    file_servers: Option<(FileUpload, Vec<FileSink>)>,
+    shutdown: triggered::Listener,
+) -> Result<()> {
+    if let Some((file_upload, sink_servers)) = file_servers {
+        let sink_servers = sink_servers
+            .into_iter()
+            .map(|mut sink_server| async move { sink_server.run().await.map_err(Error::from) });
+        tokio::try_join!(
+            futures_util::future::try_join_all(sink_servers),
+            file_upload.run(&shutdown).map_err(Error::from),
+        )?;
+    }
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use crate::{
     helium_netids::{self, is_helium_netid, AddressStore, HeliumNetId},
     lora_field::{DevAddrConstraint, DevAddrField, NetIdField},
+    org_review::OrgReviewStateV1,
     org_service::UpdateAuthorizer,
 };
 use futures::stream::StreamExt;
@@ -81,19 +82,21 @@ pub async fn create_org(
     delegate_keys: Vec<PublicKeyBinary>,
     net_id: NetIdField,
     devaddr_ranges: &[DevAddrConstraint],
+    review_state: OrgReviewStateV1,
     db: impl sqlx::PgExecutor<'_> + sqlx::Acquire<'_, Database = sqlx::Postgres>,
 ) -> Result<Org, OrgStoreError> {
     let mut txn = db.begin().await?;
 
     let oui = sqlx::query(
         r#"
-        insert into organizations (owner_pubkey, payer_pubkey)
-        values ($1, $2)
+        insert into organizations (owner_pubkey, payer_pubkey, review_state)
+        values ($1, $2, $3)
         returning oui
         "#,
     )
     .bind(&owner)
     .bind(&payer)
+    .bind(review_state)
     .fetch_one(&mut txn)
     .await
     .map_err(|_| {
@@ -527,6 +530,31 @@ pub async fn toggle_locked(oui: u64, db: impl sqlx::PgExecutor<'_>) -> Result<()
     Ok(())
 }
 
+/// Lock or unlock the org, returning whether it was locked before. None when
+/// the org does not exist. An org locked while suspended stays locked once
+/// its suspension is lifted
+pub async fn set_locked(
+    oui: u64,
+    locked: bool,
+    db: impl sqlx::PgExecutor<'_>,
+) -> Result<Option<bool>, sqlx::Error> {
+    sqlx::query_scalar::<_, Option<bool>>(
+        r#"
+        with org as (select oui, locked from organizations where oui = $1 for update)
+        update organizations
+        set locked = $2, review_locked = false
+        from org
+        where organizations.oui = org.oui
+        returning org.locked
+        "#,
+    )
+    .bind(oui as i64)
+    .bind(locked)
+    .fetch_optional(db)
+    .await
+    .map(|previous| previous.map(|previous| previous.unwrap_or(false)))
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum OrgStoreError {
     #[error("error retrieving saved org row: {0}")]
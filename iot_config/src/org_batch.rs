@@ -0,0 +1,234 @@
+//! Batched locking and unlocking of orgs.
+//!
+//! Lets the packet verifier lock or unlock many orgs with a single signed
+//! request, rather than one disable or enable request per org when payers
+//! run out of balance at once. Every lock is applied on its own and has its
+//! own result, so a failed lock does not fail the others. Locking an org to
+//! the state it is already in succeeds without changing anything, so a batch
+//! can safely be sent again. Only approved orgs can be unlocked. The routes
+//! of the orgs whose lock changed are streamed to the packet routers again.
+//!
+//! Like the api token endpoints the service is hand written on top of the
+//! tonic primitives with its messages defined here.
+
+use crate::{
+    admin::AuthCache, audit::AuditLogger, org, org_review, route, telemetry, verify_public_key,
+    verify_request_timestamp,
+};
+use chrono::Utc;
+use file_store::traits::TimestampEncode;
+use helium_crypto::{Keypair, Sign};
+use helium_proto::services::iot_config::RouteStreamResV1;
+use prost::Message;
+use sqlx::{Pool, Postgres};
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::broadcast;
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const SERVICE_NAME: &str = "helium.iot_config.org_batch";
+pub const SET_LOCKS_PATH: &str = "/helium.iot_config.org_batch/set_locks";
+
+/// Most locks in a single request
+pub const MAX_BATCH_LOCKS: usize = 1000;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OrgLockV1 {
+    #[prost(uint64, tag = "1")]
+    pub oui: u64,
+    #[prost(bool, tag = "2")]
+    pub locked: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OrgSetLocksReqV1 {
+    #[prost(message, repeated, tag = "1")]
+    pub locks: Vec<OrgLockV1>,
+    /// Seconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "2")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OrgLockResultV1 {
+    #[prost(uint64, tag = "1")]
+    pub oui: u64,
+    #[prost(bool, tag = "2")]
+    pub locked: bool,
+    /// Whether the lock of the org changed, false when it was already in
+    /// the requested state or the lock failed
+    #[prost(bool, tag = "3")]
+    pub changed: bool,
+    /// Reason the lock failed, empty when it was applied
+    #[prost(string, tag = "4")]
+    pub error: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OrgSetLocksResV1 {
+    /// Result of every lock of the request, in the order of the request
+    #[prost(message, repeated, tag = "1")]
+    pub results: Vec<OrgLockResultV1>,
+    #[prost(uint64, tag = "2")]
+    pub timestamp: u64,
+    /// Key of the config server
+    #[prost(bytes = "vec", tag = "3")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub signature: Vec<u8>,
+}
+
+impl OrgLockResultV1 {
+    pub fn is_applied(&self) -> bool {
+        self.error.is_empty()
+    }
+}
+
+file_store::impl_msg_verify!(OrgSetLocksReqV1, signature);
+file_store::impl_msg_verify!(OrgSetLocksResV1, signature);
+
+#[derive(Clone)]
+pub struct OrgBatchService {
+    auth_cache: AuthCache,
+    audit: AuditLogger,
+    pool: Pool<Postgres>,
+    signing_key: Arc<Keypair>,
+    route_update_tx: broadcast::Sender<RouteStreamResV1>,
+}
+
+impl OrgBatchService {
+    pub fn new(
+        auth_cache: AuthCache,
+        audit: AuditLogger,
+        pool: Pool<Postgres>,
+        signing_key: Arc<Keypair>,
+        route_update_tx: broadcast::Sender<RouteStreamResV1>,
+    ) -> Self {
+        Self {
+            auth_cache,
+            audit,
+            pool,
+            signing_key,
+            route_update_tx,
+        }
+    }
+
+    fn sign<R: Message>(&self, message: &R) -> Result<Vec<u8>, Status> {
+        self.signing_key
+            .sign(&message.encode_to_vec())
+            .map_err(|_| Status::internal("response signing error"))
+    }
+
+    pub async fn set_locks(&self, request: OrgSetLocksReqV1) -> Result<OrgSetLocksResV1, Status> {
+        telemetry::count_request("org_batch", "set-locks");
+        let audit = self
+            .audit
+            .begin("org_batch", "set-locks")
+            .with_request(&request.signer, &request);
+
+        let signer = verify_public_key(&request.signer)?;
+        self.auth_cache
+            .verify_signature(&signer, &request)
+            .map_err(|_| Status::permission_denied("invalid request signature"))?;
+        verify_request_timestamp(request.timestamp)?;
+        if request.locks.len() > MAX_BATCH_LOCKS {
+            return Err(Status::invalid_argument(format!(
+                "{} locks requested; at most {MAX_BATCH_LOCKS} allowed",
+                request.locks.len()
+            )));
+        }
+
+        let mut results = Vec::with_capacity(request.locks.len());
+        for OrgLockV1 { oui, locked } in request.locks {
+            let (changed, error) = match self.set_lock(oui, locked).await {
+                Ok(changed) => (changed, String::new()),
+                Err(status) => {
+                    tracing::warn!(oui, locked, "failed to set org lock: {}", status.message());
+                    (false, status.message().to_string())
+                }
+            };
+            results.push(OrgLockResultV1 {
+                oui,
+                locked,
+                changed,
+                error,
+            });
+        }
+        let changed = results.iter().filter(|result| result.changed).count();
+        tracing::info!(locks = results.len(), changed, "set org locks");
+
+        let mut response = OrgSetLocksResV1 {
+            results,
+            timestamp: Utc::now().encode_timestamp(),
+            signer: self.signing_key.public_key().into(),
+            signature: vec![],
+        };
+        response.signature = self.sign(&response)?;
+
+        audit.succeeded();
+        Ok(response)
+    }
+
+    /// Lock or unlock the org, returning whether its lock changed
+    async fn set_lock(&self, oui: u64, locked: bool) -> Result<bool, Status> {
+        if !locked {
+            org_review::ensure_approved(oui, &self.pool).await?;
+        }
+        let previous = org::set_locked(oui, locked, &self.pool)
+            .await
+            .map_err(|err| {
+                tracing::error!(org = oui, reason = ?err, "failed to set org lock");
+                Status::internal(format!("org lock failed for: {oui}"))
+            })?
+            .ok_or_else(|| Status::not_found(format!("oui: {oui}")))?;
+        if previous == locked {
+            return Ok(false);
+        }
+        route::stream_org_routes(oui, &self.pool, &self.signing_key, &self.route_update_tx)
+            .await
+            .map_err(|err| {
+                tracing::error!(org = oui, reason = ?err, "failed to stream routes of org");
+                Status::internal(format!("error streaming routes for org: {oui}"))
+            })?;
+        Ok(true)
+    }
+}
+
+impl NamedService for OrgBatchService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for OrgBatchService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            SET_LOCKS_PATH => {
+                grpc_service::unary(
+                    req,
+                    move |request| async move { svc.set_locks(request).await },
+                )
+            }
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! Review of new orgs before they can route traffic.
+//!
+//! Every org is in one of three review states. Orgs created while
+//! `review_new_orgs` is set start out pending, all others are approved.
+//! Administrators move orgs between the states:
+//!
+//! * pending to approved or suspended
+//! * approved to suspended
+//! * suspended to approved
+//!
+//! Orgs never go back to pending. Only approved orgs can create routes or be
+//! enabled. Suspending an org locks it and approving a suspended org lifts
+//! that lock, but not a lock set otherwise, like the packet verifier locking
+//! an org out of balance before or during its suspension. The routes of the
+//! org are streamed to the packet routers again whenever its lock changes. Every transition is streamed to the subscribers of the review stream
+//! as it happens, earlier transitions are not replayed.
+//!
+//! Like the api token endpoints the service is hand written on top of the
+//! tonic primitives with its messages defined here.
+
+use crate::{
+    admin::{AuthCache, KeyType},
+    audit::AuditLogger,
+    route, telemetry, verify_public_key, verify_request_timestamp, GrpcStreamResult,
+};
+use chrono::Utc;
+use file_store::traits::TimestampEncode;
+use helium_crypto::{Keypair, Sign};
+use helium_proto::services::iot_config::RouteStreamResV1;
+use prost::Message;
+use sqlx::{Pool, Postgres};
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::{broadcast, mpsc};
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const SERVICE_NAME: &str = "helium.iot_config.org_review";
+pub const SET_STATE_PATH: &str = "/helium.iot_config.org_review/set_state";
+pub const STREAM_PATH: &str = "/helium.iot_config.org_review/stream";
+
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration, sqlx::Type,
+)]
+#[sqlx(type_name = "org_review_state", rename_all = "snake_case")]
+#[repr(i32)]
+pub enum OrgReviewStateV1 {
+    Pending = 0,
+    Approved = 1,
+    Suspended = 2,
+}
+
+impl OrgReviewStateV1 {
+    /// Whether an org in this state can be moved to `next`
+    pub fn can_transition(self, next: Self) -> bool {
+        use OrgReviewStateV1::*;
+        matches!(
+            (self, next),
+            (Pending, Approved)
+                | (Pending, Suspended)
+                | (Approved, Suspended)
+                | (Suspended, Approved)
+        )
+    }
+
+    /// Lock of the org after the transition to `next`, and whether its
+    /// review set that lock, from its lock before
+    fn locks(self, next: Self, locked: bool, review_locked: bool) -> (bool, bool) {
+        match (self, next) {
+            (_, Self::Suspended) => (true, !locked),
+            (Self::Suspended, Self::Approved) => (locked && !review_locked, false),
+            _ => (locked, review_locked),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OrgReviewSetStateReqV1 {
+    #[prost(uint64, tag = "1")]
+    pub oui: u64,
+    #[prost(enumeration = "OrgReviewStateV1", tag = "2")]
+    pub state: i32,
+    /// Seconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "3")]
+    pub timestamp: u64,
+    /// Administrator key
+    #[prost(bytes = "vec", tag = "4")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OrgReviewStreamReqV1 {
+    /// Seconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "1")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "2")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: Vec<u8>,
+}
+
+/// Transition of an org, the response of a state change and the message of
+/// the review stream
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OrgReviewEventV1 {
+    #[prost(uint64, tag = "1")]
+    pub oui: u64,
+    #[prost(enumeration = "OrgReviewStateV1", tag = "2")]
+    pub state: i32,
+    #[prost(enumeration = "OrgReviewStateV1", tag = "3")]
+    pub previous_state: i32,
+    /// Seconds since the epoch of the transition
+    #[prost(uint64, tag = "4")]
+    pub timestamp: u64,
+    /// Key of the config server
+    #[prost(bytes = "vec", tag = "5")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub signature: Vec<u8>,
+}
+
+file_store::impl_msg_verify!(OrgReviewSetStateReqV1, signature);
+file_store::impl_msg_verify!(OrgReviewStreamReqV1, signature);
+file_store::impl_msg_verify!(OrgReviewEventV1, signature);
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReviewError {
+    #[error("org not found: {0}")]
+    NotFound(u64),
+    #[error("org {oui} can not move from {from:?} to {to:?}")]
+    Transition {
+        oui: u64,
+        from: OrgReviewStateV1,
+        to: OrgReviewStateV1,
+    },
+    #[error("review store error: {0}")]
+    Store(#[from] sqlx::Error),
+}
+
+impl From<ReviewError> for Status {
+    fn from(err: ReviewError) -> Self {
+        match err {
+            ReviewError::NotFound(oui) => Status::not_found(format!("oui: {oui}")),
+            err @ ReviewError::Transition { .. } => Status::failed_precondition(err.to_string()),
+            ReviewError::Store(err) => {
+                tracing::error!(reason = ?err, "org review store error");
+                Status::internal("org review store error")
+            }
+        }
+    }
+}
+
+pub async fn get_state(
+    oui: u64,
+    db: impl sqlx::PgExecutor<'_>,
+) -> Result<Option<OrgReviewStateV1>, sqlx::Error> {
+    sqlx::query_scalar("select review_state from organizations where oui = $1")
+        .bind(oui as i64)
+        .fetch_optional(db)
+        .await
+}
+
+/// Reject requests for orgs which are not approved
+pub async fn ensure_approved(oui: u64, db: impl sqlx::PgExecutor<'_>) -> Result<(), Status> {
+    match get_state(oui, db).await.map_err(ReviewError::from)? {
+        Some(OrgReviewStateV1::Approved) => Ok(()),
+        Some(state) => Err(Status::failed_precondition(format!(
+            "org {oui} is {state:?}, not approved"
+        ))),
+        None => Err(ReviewError::NotFound(oui).into()),
+    }
+}
+
+/// Move the org to the next state, locking or unlocking it along the way.
+/// Returns the previous state and whether the lock of the org changed
+pub async fn set_state(
+    oui: u64,
+    next: OrgReviewStateV1,
+    db: impl sqlx::PgExecutor<'_> + sqlx::Acquire<'_, Database = sqlx::Postgres>,
+) -> Result<(OrgReviewStateV1, bool), ReviewError> {
+    let mut txn = db.begin().await?;
+    let (previous, locked, review_locked): (OrgReviewStateV1, Option<bool>, bool) = sqlx::query_as(
+        r#"
+        select review_state, locked, review_locked from organizations
+        where oui = $1 for update
+        "#,
+    )
+    .bind(oui as i64)
+    .fetch_optional(&mut txn)
+    .await?
+    .ok_or(ReviewError::NotFound(oui))?;
+    let locked = locked.unwrap_or(false);
+    if !previous.can_transition(next) {
+        return Err(ReviewError::Transition {
+            oui,
+            from: previous,
+            to: next,
+        });
+    }
+    let (lock, review_lock) = previous.locks(next, locked, review_locked);
+    sqlx::query(
+        r#"
+        update organizations
+        set review_state = $2, locked = $3, review_locked = $4
+        where oui = $1
+        "#,
+    )
+    .bind(oui as i64)
+    .bind(next)
+    .bind(lock)
+    .bind(review_lock)
+    .execute(&mut txn)
+    .await?;
+    txn.commit().await?;
+    Ok((previous, lock != locked))
+}
+
+/// Keep the org locked once its suspension is lifted, for orgs disabled
+/// while suspended
+pub async fn hold_lock(oui: u64, db: impl sqlx::PgExecutor<'_>) -> Result<(), sqlx::Error> {
+    sqlx::query("update organizations set review_locked = false where oui = $1")
+        .bind(oui as i64)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct OrgReviewService {
+    auth_cache: AuthCache,
+    audit: AuditLogger,
+    pool: Pool<Postgres>,
+    signing_key: Arc<Keypair>,
+    route_update_tx: broadcast::Sender<RouteStreamResV1>,
+    review_tx: broadcast::Sender<OrgReviewEventV1>,
+}
+
+impl OrgReviewService {
+    pub fn new(
+        auth_cache: AuthCache,
+        audit: AuditLogger,
+        pool: Pool<Postgres>,
+        signing_key: Arc<Keypair>,
+        route_update_tx: broadcast::Sender<RouteStreamResV1>,
+    ) -> Self {
+        Self {
+            auth_cache,
+            audit,
+            pool,
+            signing_key,
+            route_update_tx,
+            review_tx: crate::update_channel(),
+        }
+    }
+
+    fn sign<R: Message>(&self, message: &R) -> Result<Vec<u8>, Status> {
+        self.signing_key
+            .sign(&message.encode_to_vec())
+            .map_err(|_| Status::internal("response signing error"))
+    }
+
+    pub async fn set_state(
+        &self,
+        request: OrgReviewSetStateReqV1,
+    ) -> Result<OrgReviewEventV1, Status> {
+        telemetry::count_request("org_review", "set-state");
+        let audit = self
+            .audit
+            .begin("org_review", "set-state")
+            .with_request(&request.signer, &request);
+
+        let signer = verify_public_key(&request.signer)?;
+        self.auth_cache
+            .verify_signature_with_type(KeyType::Administrator, &signer, &request)
+            .map_err(|_| Status::permission_denied("invalid admin signature"))?;
+        verify_request_timestamp(request.timestamp)?;
+        let next = OrgReviewStateV1::from_i32(request.state).ok_or_else(|| {
+            Status::invalid_argument(format!("unknown review state {}", request.state))
+        })?;
+
+        let (previous, lock_changed) = set_state(request.oui, next, &self.pool).await?;
+        tracing::info!(oui = request.oui, ?previous, state = ?next, "org review state changed");
+        if lock_changed {
+            self.stream_routes(request.oui).await?;
+        }
+
+        let mut event = OrgReviewEventV1 {
+            oui: request.oui,
+            state: next.into(),
+            previous_state: previous.into(),
+            timestamp: Utc::now().encode_timestamp(),
+            signer: self.signing_key.public_key().into(),
+            signature: vec![],
+        };
+        event.signature = self.sign(&event)?;
+        if self.review_tx.send(event.clone()).is_err() {
+            tracing::debug!(oui = request.oui, "no org review subscribers");
+        }
+
+        audit.succeeded();
+        Ok(event)
+    }
+
+    /// Stream the routes of the org with its new lock to the packet routers
+    async fn stream_routes(&self, oui: u64) -> Result<(), Status> {
+        route::stream_org_routes(oui, &self.pool, &self.signing_key, &self.route_update_tx)
+            .await
+            .map_err(|err| {
+                tracing::error!(org = oui, reason = ?err, "failed to stream routes of reviewed org");
+                Status::internal(format!("error streaming routes for reviewed org: {oui}"))
+            })
+    }
+
+    pub async fn stream(
+        &self,
+        request: OrgReviewStreamReqV1,
+    ) -> Result<GrpcStreamResult<OrgReviewEventV1>, Status> {
+        telemetry::count_request("org_review", "stream");
+        let signer = verify_public_key(&request.signer)?;
+        self.auth_cache
+            .verify_signature(&signer, &request)
+            .map_err(|_| Status::permission_denied("unauthorized request signature"))?;
+        verify_request_timestamp(request.timestamp)?;
+
+        tracing::info!("client subscribed to org review stream");
+        let (tx, rx) = mpsc::channel(20);
+        let mut events = self.review_tx.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => Ok(event),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => Err(Status::aborted(
+                        format!("org review stream lagged by {skipped} events; resubscribe"),
+                    )),
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                let lagged = event.is_err();
+                if tx.send(event).await.is_err() || lagged {
+                    return;
+                }
+            }
+        });
+        Ok(GrpcStreamResult::new(rx))
+    }
+}
+
+impl NamedService for OrgReviewService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for OrgReviewService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            SET_STATE_PATH => {
+                grpc_service::unary(
+                    req,
+                    move |request| async move { svc.set_state(request).await },
+                )
+            }
+            STREAM_PATH => grpc_service::server_streaming(req, move |request| async move {
+                svc.stream(request).await
+            }),
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use OrgReviewStateV1::*;
+
+    #[test]
+    fn orgs_never_go_back_to_pending() {
+        let states = [Pending, Approved, Suspended];
+        let allowed: Vec<_> = states
+            .iter()
+            .flat_map(|from| states.iter().map(move |to| (*from, *to)))
+            .filter(|(from, to)| from.can_transition(*to))
+            .collect();
+        assert_eq!(
+            allowed,
+            vec![
+                (Pending, Approved),
+                (Pending, Suspended),
+                (Approved, Suspended),
+                (Suspended, Approved)
+            ]
+        );
+
+        assert_eq!(Pending.locks(Approved, false, false), (false, false));
+        assert_eq!(Approved.locks(Suspended, false, false), (true, true));
+        assert_eq!(Suspended.locks(Approved, true, true), (false, false));
+        // Locks set before or during the suspension outlive it
+        assert_eq!(Approved.locks(Suspended, true, false), (true, false));
+        assert_eq!(Suspended.locks(Approved, true, false), (true, false));
+    }
+}
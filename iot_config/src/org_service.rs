@@ -1,13 +1,15 @@
 use crate::{
     admin::{AuthCache, KeyType},
+    audit::AuditLogger,
     helium_netids, lora_field, org,
+    org_review::{self, OrgReviewStateV1},
     route::list_routes,
-    telemetry, verify_public_key, GrpcResult, Settings,
+    telemetry, verify_public_key, verify_request_timestamp, GrpcResult, Settings,
 };
 use anyhow::Result;
 use chrono::Utc;
 use file_store::traits::{MsgVerify, TimestampEncode};
-use helium_crypto::{Keypair, PublicKey, Sign};
+use helium_crypto::{Keypair, PublicKey, PublicKeyBinary, Sign};
 use helium_proto::{
     services::iot_config::{
         self, route_stream_res_v1, ActionV1, DevaddrConstraintV1, OrgCreateHeliumReqV1,
@@ -22,10 +24,12 @@ use tonic::{Request, Response, Status};
 
 pub struct OrgService {
     auth_cache: AuthCache,
+    audit: AuditLogger,
     pool: Pool<Postgres>,
     route_update_tx: broadcast::Sender<RouteStreamResV1>,
     signing_key: Keypair,
     delegate_updater: watch::Sender<org::DelegateCache>,
+    new_org_state: OrgReviewStateV1,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -38,16 +42,23 @@ impl OrgService {
     pub fn new(
         settings: &Settings,
         auth_cache: AuthCache,
+        audit: AuditLogger,
         pool: Pool<Postgres>,
         route_update_tx: broadcast::Sender<RouteStreamResV1>,
         delegate_updater: watch::Sender<org::DelegateCache>,
     ) -> Result<Self> {
         Ok(Self {
             auth_cache,
+            audit,
             pool,
             route_update_tx,
             signing_key: settings.signing_keypair()?,
             delegate_updater,
+            new_org_state: if settings.review_new_orgs {
+                OrgReviewStateV1::Pending
+            } else {
+                OrgReviewStateV1::Approved
+            },
         })
     }
 
@@ -106,6 +117,21 @@ impl OrgService {
         Err(Status::permission_denied("unauthorized request signature"))
     }
 
+    /// Bring the delegate key cache in line with an org's delegate keys after
+    /// an update so added keys are authorized and removed keys are not.
+    fn sync_delegate_cache(&self, prior: &[PublicKeyBinary], current: &[PublicKeyBinary]) {
+        self.delegate_updater.send_if_modified(|cache| {
+            let removed = prior
+                .iter()
+                .filter(|key| !current.contains(key))
+                .fold(false, |acc, key| cache.remove(key) || acc);
+            current
+                .iter()
+                .filter(|key| !prior.contains(key))
+                .fold(removed, |acc, key| cache.insert(key.clone()) || acc)
+        });
+    }
+
     fn sign_response(&self, response: &[u8]) -> Result<Vec<u8>, Status> {
         self.signing_key
             .sign(response)
@@ -180,6 +206,10 @@ impl iot_config::Org for OrgService {
     async fn create_helium(&self, request: Request<OrgCreateHeliumReqV1>) -> GrpcResult<OrgResV1> {
         let request = request.into_inner();
         telemetry::count_request("org", "create-helium");
+        let audit = self
+            .audit
+            .begin("org", "create-helium")
+            .with_request(&request.signer, &request);
 
         let signer = verify_public_key(&request.signer)?;
         self.verify_admin_request_signature(&signer, &request)?;
@@ -236,6 +266,7 @@ impl iot_config::Org for OrgService {
                 .collect(),
             helium_netid_field,
             &devaddr_constraints,
+            self.new_org_state,
             &mut txn,
         )
         .await
@@ -280,12 +311,17 @@ impl iot_config::Org for OrgService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
     async fn create_roamer(&self, request: Request<OrgCreateRoamerReqV1>) -> GrpcResult<OrgResV1> {
         let request = request.into_inner();
         telemetry::count_request("org", "create-roamer");
+        let audit = self
+            .audit
+            .begin("org", "create-roamer")
+            .with_request(&request.signer, &request);
 
         let signer = verify_public_key(&request.signer)?;
         self.verify_admin_request_signature(&signer, &request)?;
@@ -323,6 +359,7 @@ impl iot_config::Org for OrgService {
                 .collect(),
             net_id,
             &[devaddr_range],
+            self.new_org_state,
             &self.pool,
         )
         .await
@@ -363,18 +400,29 @@ impl iot_config::Org for OrgService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
     async fn update(&self, request: Request<OrgUpdateReqV1>) -> GrpcResult<OrgResV1> {
         let request = request.into_inner();
         telemetry::count_request("org", "update");
+        let audit = self
+            .audit
+            .begin("org", "update")
+            .with_request(&request.signer, &request);
 
         let signer = verify_public_key(&request.signer)?;
         let authorizer = self
             .verify_update_request_signature(&signer, &request)
             .await?;
 
+        let prior_delegates = org::get(request.oui, &self.pool)
+            .await
+            .map_err(|_| Status::internal("error retrieving current org"))?
+            .and_then(|org| org.delegate_keys)
+            .unwrap_or_default();
+
         let org = org::update_org(request.oui, authorizer, request.updates, &self.pool)
             .await
             .map_err(|err| {
@@ -382,6 +430,11 @@ impl iot_config::Org for OrgService {
                 Status::internal(format!("org update failed: {err:?}"))
             })?;
 
+        self.sync_delegate_cache(
+            &prior_delegates,
+            org.delegate_keys.as_deref().unwrap_or_default(),
+        );
+
         let net_id = org::get_org_netid(org.oui, &self.pool)
             .await
             .map_err(|err| {
@@ -406,16 +459,25 @@ impl iot_config::Org for OrgService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
     async fn disable(&self, request: Request<OrgDisableReqV1>) -> GrpcResult<OrgDisableResV1> {
         let request = request.into_inner();
         telemetry::count_request("org", "disable");
+        let audit = self
+            .audit
+            .begin("org", "disable")
+            .with_request(&request.signer, &request);
 
         let signer = verify_public_key(&request.signer)?;
         self.verify_request_signature(&signer, &request)?;
+        verify_request_timestamp(request.timestamp)?;
 
+        org_review::hold_lock(request.oui, &self.pool)
+            .await
+            .map_err(|_| Status::internal("error holding the org lock"))?;
         if !org::is_locked(request.oui, &self.pool)
             .await
             .map_err(|_| Status::internal("error retrieving current status"))?
@@ -474,15 +536,22 @@ impl iot_config::Org for OrgService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
     async fn enable(&self, request: Request<OrgEnableReqV1>) -> GrpcResult<OrgEnableResV1> {
         let request = request.into_inner();
         telemetry::count_request("org", "enable");
+        let audit = self
+            .audit
+            .begin("org", "enable")
+            .with_request(&request.signer, &request);
 
         let signer = verify_public_key(&request.signer)?;
         self.verify_request_signature(&signer, &request)?;
+        verify_request_timestamp(request.timestamp)?;
+        org_review::ensure_approved(request.oui, &self.pool).await?;
 
         if org::is_locked(request.oui, &self.pool)
             .await
@@ -542,6 +611,7 @@ impl iot_config::Org for OrgService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 }
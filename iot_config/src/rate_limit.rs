@@ -0,0 +1,495 @@
+use crate::{
+    admin::AuthCache, api_token, audit, gateway_denylist, org_batch, org_review, route_snapshot,
+    settings::RateLimitSettings, telemetry,
+};
+use file_store::traits::MsgVerify;
+use futures::future::BoxFuture;
+use helium_crypto::PublicKey;
+use helium_proto::{services::iot_config, Message};
+use hyper::body::HttpBody;
+use rate_limit::throttled_status;
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tonic::{
+    body::BoxBody,
+    transport::{
+        server::{TcpConnectInfo, TlsConnectInfo},
+        Body,
+    },
+    Status,
+};
+use tower::{Layer, Service};
+
+pub use rate_limit::RateLimit;
+
+// Number of tracked callers above which idle buckets are pruned
+const PRUNE_THRESHOLD: usize = 10_000;
+
+// Length of the grpc frame header, a compression flag and the message length
+const GRPC_HEADER_LEN: usize = 5;
+
+// Largest request body buffered to find its signer, the largest message the
+// grpc servers decode plus its frame header
+const MAX_BODY_LEN: usize = 4 * 1024 * 1024 + GRPC_HEADER_LEN;
+
+/// Who a request is counted against
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Caller {
+    /// Key of the auth cache that signed the request
+    Signer(Vec<u8>),
+    /// Peer address of the request
+    Peer(Option<IpAddr>),
+}
+
+/// Token bucket rate limits per endpoint and caller. Every request is
+/// counted against its peer address before its body is read. Requests to
+/// unary endpoints taking signed requests are also counted against the
+/// signing key, but only when it is a key of the auth cache and the request
+/// is signed by it, so nobody can use up the limit of another key or escape
+/// the limits by signing with fresh keys.
+#[derive(Clone)]
+pub struct RateLimiter {
+    limiter: Arc<rate_limit::RateLimiter<Caller>>,
+    auth_cache: AuthCache,
+}
+
+impl RateLimiter {
+    pub fn from_settings(
+        settings: &RateLimitSettings,
+        auth_cache: AuthCache,
+    ) -> Result<Self, rate_limit::Error> {
+        Ok(Self {
+            limiter: Arc::new(rate_limit::RateLimiter::new(
+                settings.default,
+                settings.endpoints.clone(),
+                PRUNE_THRESHOLD,
+            )?),
+            auth_cache,
+        })
+    }
+
+    fn check(&self, endpoint: &str, caller: Caller, now: Instant) -> Result<(), Status> {
+        self.limiter
+            .check_at(endpoint, caller.clone(), now)
+            .map_err(|retry_after| {
+                tracing::debug!(endpoint, ?caller, "request rate limited");
+                telemetry::count_rate_limited(endpoint);
+                throttled_status(retry_after)
+            })
+    }
+}
+
+trait SignedRequest: Message + Default + MsgVerify {
+    fn signer(&self) -> &[u8];
+}
+
+macro_rules! impl_signed_request {
+    ($($msg:ty),*) => {$(
+        impl SignedRequest for $msg {
+            fn signer(&self) -> &[u8] {
+                &self.signer
+            }
+        }
+    )*};
+}
+
+impl_signed_request!(
+    iot_config::OrgCreateHeliumReqV1,
+    iot_config::OrgCreateRoamerReqV1,
+    iot_config::OrgUpdateReqV1,
+    iot_config::OrgDisableReqV1,
+    iot_config::OrgEnableReqV1,
+    iot_config::RouteListReqV1,
+    iot_config::RouteGetReqV1,
+    iot_config::RouteCreateReqV1,
+    iot_config::RouteUpdateReqV1,
+    iot_config::RouteDeleteReqV1,
+    iot_config::RouteStreamReqV1,
+    iot_config::RouteGetEuisReqV1,
+    iot_config::RouteGetDevaddrRangesReqV1,
+    iot_config::RouteSkfListReqV1,
+    iot_config::RouteSkfGetReqV1,
+    iot_config::RouteSkfUpdateReqV1,
+    iot_config::GatewayLocationReqV1,
+    iot_config::GatewayInfoReqV1,
+    iot_config::GatewayInfoStreamReqV1,
+    iot_config::AdminAddKeyReqV1,
+    iot_config::AdminRemoveKeyReqV1,
+    iot_config::AdminLoadRegionReqV1,
+    iot_config::RegionParamsReqV1,
+    api_token::MintTokenReqV1,
+    api_token::RevokeTokenReqV1,
+    api_token::TokenStatusReqV1,
+    audit::AuditListReqV1,
+    gateway_denylist::DenylistUploadReqV1,
+    gateway_denylist::DenylistStreamReqV1,
+    org_batch::OrgSetLocksReqV1,
+    org_review::OrgReviewSetStateReqV1,
+    org_review::OrgReviewStreamReqV1,
+    route_snapshot::RouteSnapshotReqV1
+);
+
+/// Decodes the request of a grpc frame, returning its signer if it is a key
+/// of the auth cache and the request is signed by it
+type SignerFn = fn(&[u8], &AuthCache) -> Option<Vec<u8>>;
+
+fn verified_signer<R: SignedRequest>(frame: &[u8], auth_cache: &AuthCache) -> Option<Vec<u8>> {
+    // compressed messages are not decoded here
+    if frame.len() < GRPC_HEADER_LEN {
+        return None;
+    }
+    let (header, message) = frame.split_at(GRPC_HEADER_LEN);
+    if header[0] != 0 {
+        return None;
+    }
+    let len = u32::from_be_bytes(header[1..].try_into().ok()?) as usize;
+    let request = R::decode(message.get(..len)?).ok()?;
+    let signer = PublicKey::try_from(request.signer()).ok()?;
+    auth_cache.verify_signature(&signer, &request).ok()?;
+    Some(request.signer().to_vec())
+}
+
+/// Signer lookup of the unary requests of an endpoint. Client streamed
+/// requests are never buffered.
+fn signer_fn(endpoint: &str) -> Option<SignerFn> {
+    let signer_fn: SignerFn = match endpoint {
+        "/helium.iot_config.org/create_helium" => {
+            verified_signer::<iot_config::OrgCreateHeliumReqV1>
+        }
+        "/helium.iot_config.org/create_roamer" => {
+            verified_signer::<iot_config::OrgCreateRoamerReqV1>
+        }
+        "/helium.iot_config.org/update" => verified_signer::<iot_config::OrgUpdateReqV1>,
+        "/helium.iot_config.org/disable" => verified_signer::<iot_config::OrgDisableReqV1>,
+        "/helium.iot_config.org/enable" => verified_signer::<iot_config::OrgEnableReqV1>,
+        "/helium.iot_config.route/list" => verified_signer::<iot_config::RouteListReqV1>,
+        "/helium.iot_config.route/get" => verified_signer::<iot_config::RouteGetReqV1>,
+        "/helium.iot_config.route/create" => verified_signer::<iot_config::RouteCreateReqV1>,
+        "/helium.iot_config.route/update" => verified_signer::<iot_config::RouteUpdateReqV1>,
+        "/helium.iot_config.route/delete" => verified_signer::<iot_config::RouteDeleteReqV1>,
+        "/helium.iot_config.route/stream" => verified_signer::<iot_config::RouteStreamReqV1>,
+        "/helium.iot_config.route/get_euis" => verified_signer::<iot_config::RouteGetEuisReqV1>,
+        "/helium.iot_config.route/get_devaddr_ranges" => {
+            verified_signer::<iot_config::RouteGetDevaddrRangesReqV1>
+        }
+        "/helium.iot_config.route/list_skfs" => verified_signer::<iot_config::RouteSkfListReqV1>,
+        "/helium.iot_config.route/get_skfs" => verified_signer::<iot_config::RouteSkfGetReqV1>,
+        "/helium.iot_config.route/update_skfs" => {
+            verified_signer::<iot_config::RouteSkfUpdateReqV1>
+        }
+        "/helium.iot_config.gateway/location" => {
+            verified_signer::<iot_config::GatewayLocationReqV1>
+        }
+        "/helium.iot_config.gateway/info" => verified_signer::<iot_config::GatewayInfoReqV1>,
+        "/helium.iot_config.gateway/info_stream" => {
+            verified_signer::<iot_config::GatewayInfoStreamReqV1>
+        }
+        "/helium.iot_config.admin/add_key" => verified_signer::<iot_config::AdminAddKeyReqV1>,
+        "/helium.iot_config.admin/remove_key" => verified_signer::<iot_config::AdminRemoveKeyReqV1>,
+        "/helium.iot_config.admin/load_region" => {
+            verified_signer::<iot_config::AdminLoadRegionReqV1>
+        }
+        "/helium.iot_config.admin/region_params" => {
+            verified_signer::<iot_config::RegionParamsReqV1>
+        }
+        api_token::MINT_PATH => verified_signer::<api_token::MintTokenReqV1>,
+        api_token::REVOKE_PATH => verified_signer::<api_token::RevokeTokenReqV1>,
+        api_token::STATUS_PATH => verified_signer::<api_token::TokenStatusReqV1>,
+        audit::LIST_PATH => verified_signer::<audit::AuditListReqV1>,
+        gateway_denylist::UPLOAD_PATH => verified_signer::<gateway_denylist::DenylistUploadReqV1>,
+        gateway_denylist::STREAM_PATH => verified_signer::<gateway_denylist::DenylistStreamReqV1>,
+        org_batch::SET_LOCKS_PATH => verified_signer::<org_batch::OrgSetLocksReqV1>,
+        org_review::SET_STATE_PATH => verified_signer::<org_review::OrgReviewSetStateReqV1>,
+        org_review::STREAM_PATH => verified_signer::<org_review::OrgReviewStreamReqV1>,
+        route_snapshot::LATEST_PATH => verified_signer::<route_snapshot::RouteSnapshotReqV1>,
+        _ => return None,
+    };
+    Some(signer_fn)
+}
+
+/// Read a request body of at most `MAX_BODY_LEN` bytes
+async fn read_body(mut body: Body) -> Result<Vec<u8>, Status> {
+    let mut frame = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| {
+            tracing::debug!(?err, "failed to read request body");
+            Status::invalid_argument("unreadable request body")
+        })?;
+        if frame.len() + chunk.len() > MAX_BODY_LEN {
+            return Err(Status::resource_exhausted(format!(
+                "request body larger than {MAX_BODY_LEN} bytes"
+            )));
+        }
+        frame.extend_from_slice(&chunk);
+    }
+    Ok(frame)
+}
+
+fn peer_addr(request: &http::Request<Body>) -> Option<IpAddr> {
+    let extensions = request.extensions();
+    extensions
+        .get::<TcpConnectInfo>()
+        .or_else(|| {
+            extensions
+                .get::<TlsConnectInfo<TcpConnectInfo>>()
+                .map(|info| info.get_ref())
+        })
+        .and_then(|info| info.remote_addr())
+        .map(|addr| addr.ip())
+}
+
+impl<S> Layer<S> for RateLimiter {
+    type Service = RateLimited<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimited {
+            limiter: self.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimited<S> {
+    limiter: RateLimiter,
+    inner: S,
+}
+
+impl<S> Service<http::Request<Body>> for RateLimited<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(ctx)
+    }
+
+    fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+        let limiter = self.limiter.clone();
+        let clone = self.inner.clone();
+        // take the service that was ready
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let endpoint = req.uri().path().to_string();
+        let peer = Caller::Peer(peer_addr(&req));
+        if let Err(status) = limiter.check(&endpoint, peer, Instant::now()) {
+            return Box::pin(async move { Ok(status.to_http()) });
+        }
+        let signer_fn = match signer_fn(&endpoint) {
+            Some(signer_fn) => signer_fn,
+            None => return Box::pin(inner.call(req)),
+        };
+
+        Box::pin(async move {
+            // unary requests are small, buffer them to find their signer
+            let (parts, body) = req.into_parts();
+            let frame = match read_body(body).await {
+                Ok(frame) => frame,
+                Err(status) => return Ok(status.to_http()),
+            };
+            if let Some(signer) = signer_fn(&frame, &limiter.auth_cache) {
+                if let Err(status) =
+                    limiter.check(&endpoint, Caller::Signer(signer), Instant::now())
+                {
+                    return Ok(status.to_http());
+                }
+            }
+            inner
+                .call(http::Request::from_parts(parts, Body::from(frame)))
+                .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::{self, CacheKeys};
+    use helium_crypto::{KeyTag, KeyType, Keypair, Network, Sign};
+    use rand::rngs::OsRng;
+    use std::convert::Infallible;
+
+    const ORG_GET: &str = "/helium.iot_config.org/get";
+    const ROUTE_LIST: &str = "/helium.iot_config.route/list";
+
+    fn keypair() -> Keypair {
+        Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::Ed25519,
+            },
+            &mut OsRng,
+        )
+    }
+
+    fn auth_cache(keypair: &Keypair) -> AuthCache {
+        AuthCache::from_keys(CacheKeys::from([(
+            keypair.public_key().clone(),
+            admin::KeyType::PacketRouter,
+        )]))
+    }
+
+    fn frame(request: &impl Message) -> Vec<u8> {
+        let message = request.encode_to_vec();
+        let mut frame = vec![0];
+        frame.extend((message.len() as u32).to_be_bytes());
+        frame.extend(message);
+        frame
+    }
+
+    fn signed_route_list(keypair: &Keypair, oui: u64) -> iot_config::RouteListReqV1 {
+        let mut request = iot_config::RouteListReqV1 {
+            oui,
+            timestamp: 1,
+            signer: keypair.public_key().into(),
+            signature: vec![],
+        };
+        request.signature = keypair.sign(&request.encode_to_vec()).unwrap();
+        request
+    }
+
+    #[test]
+    fn limits_apply_per_endpoint_and_caller() {
+        let strict = RateLimit {
+            per_second: 1.0,
+            burst: 1,
+        };
+        let limiter = RateLimiter::from_settings(
+            &RateLimitSettings {
+                default: None,
+                endpoints: [(ORG_GET.to_string(), strict)].into_iter().collect(),
+            },
+            AuthCache::from_keys(CacheKeys::new()),
+        )
+        .unwrap();
+        let now = Instant::now();
+        let caller_1 = Caller::Peer(Some(IpAddr::from([10, 0, 0, 1])));
+        let caller_2 = Caller::Peer(Some(IpAddr::from([10, 0, 0, 2])));
+
+        assert!(limiter.check(ORG_GET, caller_1.clone(), now).is_ok());
+        assert!(limiter.check(ORG_GET, caller_1.clone(), now).is_err());
+        assert!(limiter.check(ORG_GET, caller_2, now).is_ok());
+        assert!(limiter.check(ORG_GET, Caller::Signer(vec![1]), now).is_ok());
+        // endpoints without a limit and no default are never throttled
+        for _ in 0..10 {
+            assert!(limiter
+                .check("/helium.iot_config.org/list", caller_1.clone(), now)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn zero_rates_are_rejected() {
+        let settings = RateLimitSettings {
+            default: Some(RateLimit {
+                per_second: 0.0,
+                burst: 1,
+            }),
+            endpoints: Default::default(),
+        };
+        assert!(
+            RateLimiter::from_settings(&settings, AuthCache::from_keys(CacheKeys::new())).is_err()
+        );
+    }
+
+    #[test]
+    fn signed_requests_are_counted_against_their_signer() {
+        let keypair = keypair();
+        let auth_cache = auth_cache(&keypair);
+        let signer_fn = signer_fn(ROUTE_LIST).unwrap();
+        let request = signed_route_list(&keypair, 1);
+        assert_eq!(
+            signer_fn(&frame(&request), &auth_cache),
+            Some(keypair.public_key().to_vec())
+        );
+
+        // a request not signed by its signer is only counted against the peer
+        let tampered = iot_config::RouteListReqV1 { oui: 2, ..request };
+        assert_eq!(signer_fn(&frame(&tampered), &auth_cache), None);
+        // as are requests of unknown keys
+        let unknown = signed_route_list(&self::keypair(), 1);
+        assert_eq!(signer_fn(&frame(&unknown), &auth_cache), None);
+        // and compressed and truncated frames
+        let mut compressed = frame(&signed_route_list(&keypair, 1));
+        compressed[0] = 1;
+        assert_eq!(signer_fn(&compressed, &auth_cache), None);
+        assert_eq!(signer_fn(&compressed[..3], &auth_cache), None);
+    }
+
+    /// Inner service answering every request
+    #[derive(Clone)]
+    struct Answer;
+
+    impl Service<http::Request<Body>> for Answer {
+        type Response = http::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: http::Request<Body>) -> Self::Future {
+            futures::future::ready(Ok(http::Response::new(tonic::body::empty_body())))
+        }
+    }
+
+    fn route_list_limiter() -> RateLimited<Answer> {
+        let strict = RateLimit {
+            per_second: 0.001,
+            burst: 2,
+        };
+        RateLimiter::from_settings(
+            &RateLimitSettings {
+                default: None,
+                endpoints: [(ROUTE_LIST.to_string(), strict)].into_iter().collect(),
+            },
+            AuthCache::from_keys(CacheKeys::new()),
+        )
+        .unwrap()
+        .layer(Answer)
+    }
+
+    async fn rejected(service: &mut RateLimited<Answer>, body: Vec<u8>) -> bool {
+        let request = http::Request::builder()
+            .uri(ROUTE_LIST)
+            .body(Body::from(body))
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        response
+            .headers()
+            .get("grpc-status")
+            .map_or(false, |status| status != "0")
+    }
+
+    #[tokio::test]
+    async fn fresh_signing_keys_are_throttled_by_peer() {
+        let mut service = route_list_limiter();
+        let mut results = vec![];
+        for _ in 0..3 {
+            let request = signed_route_list(&keypair(), 1);
+            results.push(rejected(&mut service, frame(&request)).await);
+        }
+        assert_eq!(results, vec![false, false, true]);
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_rejected_unread() {
+        let mut service = route_list_limiter();
+        assert!(rejected(&mut service, vec![0; MAX_BODY_LEN + 1]).await);
+    }
+
+    #[test]
+    fn unsigned_and_client_streamed_requests_are_not_buffered() {
+        assert!(signer_fn(ORG_GET).is_none());
+        assert!(signer_fn("/helium.iot_config.route/update_euis").is_none());
+    }
+}
@@ -1,11 +1,16 @@
 use anyhow::anyhow;
+use chrono::{DateTime, Utc};
 use futures::stream::TryStreamExt;
 use helium_proto::{BlockchainRegionParamsV1, Message, Region};
 use hextree::{compaction::EqCompactor, Cell, HexTreeMap};
 use libflate::gzip::Decoder;
-use std::{collections::HashMap, io::Read, str::FromStr};
+use std::{collections::HashMap, io::Read, str::FromStr, sync::Arc, time::Duration};
 use tokio::sync::watch;
 
+/// How often scheduled region params versions are checked for having become
+/// effective
+const PARAMS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Clone, Debug)]
 pub struct RegionMap {
     region_hextree: HexTreeMap<Region, EqCompactor>,
@@ -33,6 +38,10 @@ impl RegionMapReader {
     pub fn get_params(&self, region: &Region) -> Option<BlockchainRegionParamsV1> {
         self.map_receiver.borrow().get_params(region)
     }
+
+    pub fn is_loaded(&self) -> bool {
+        self.map_receiver.borrow().is_loaded()
+    }
 }
 
 impl RegionMap {
@@ -53,6 +62,15 @@ impl RegionMap {
         self.params_map.get(region).cloned()
     }
 
+    /// True once at least one region has both hexes and params loaded
+    pub fn is_loaded(&self) -> bool {
+        self.region_hextree.len() > 0
+            && self
+                .params_map
+                .keys()
+                .any(|region| *region != Region::Unknown)
+    }
+
     pub fn insert_params(&mut self, region: Region, params: BlockchainRegionParamsV1) {
         _ = self.params_map.insert(region, params)
     }
@@ -60,6 +78,19 @@ impl RegionMap {
     pub fn replace_tree(&mut self, new_map: HexTreeMap<Region, EqCompactor>) {
         self.region_hextree = new_map
     }
+
+    /// Replace the region params, returning true if any of them changed
+    pub fn replace_params(
+        &mut self,
+        params_map: HashMap<Region, BlockchainRegionParamsV1>,
+    ) -> bool {
+        if self.params_map == params_map {
+            false
+        } else {
+            self.params_map = params_map;
+            true
+        }
+    }
 }
 
 #[derive(sqlx::FromRow)]
@@ -109,12 +140,28 @@ pub async fn build_region_tree(
     Ok(region_tree)
 }
 
+#[derive(sqlx::FromRow)]
+pub struct RegionParamsVersion {
+    pub region: String,
+    pub params: Vec<u8>,
+}
+
+/// Build the map of region params from the most recent version of each
+/// region's params which has already taken effect.
 pub async fn build_params_map(
     db: impl sqlx::PgExecutor<'_>,
 ) -> anyhow::Result<HashMap<Region, BlockchainRegionParamsV1>> {
     let mut params_map: HashMap<Region, BlockchainRegionParamsV1> = HashMap::new();
 
-    let mut regions = sqlx::query_as::<_, HexRegion>("select * from regions").fetch(db);
+    let mut regions = sqlx::query_as::<_, RegionParamsVersion>(
+        r#"
+        select distinct on (region) region, params
+        from region_params_versions
+        where effective_at <= now()
+        order by region, effective_at desc, id desc
+        "#,
+    )
+    .fetch(db);
 
     while let Some(region_row) = regions.try_next().await? {
         let region = Region::from_str(&region_row.region)?;
@@ -158,6 +205,17 @@ pub async fn update_region(
     .execute(&mut transaction)
     .await?;
 
+    sqlx::query(
+        r#"
+        insert into region_params_versions (region, params)
+        values ($1, $2)
+        "#,
+    )
+    .bind(region.to_string())
+    .bind(params.encode_to_vec())
+    .execute(&mut transaction)
+    .await?;
+
     let updated_region = if indexes.is_some() {
         Some(build_region_tree(&mut transaction).await?)
     } else {
@@ -169,3 +227,59 @@ pub async fn update_region(
 
     Ok(updated_region)
 }
+
+/// Schedule a version of the region params taking effect at the given time.
+/// The refresh of the running services picks it up once it is effective
+pub async fn schedule_params(
+    region: Region,
+    params: &BlockchainRegionParamsV1,
+    effective_at: DateTime<Utc>,
+    db: impl sqlx::PgExecutor<'_>,
+) -> anyhow::Result<i64> {
+    let id = sqlx::query_scalar(
+        r#"
+        insert into region_params_versions (region, params, effective_at)
+        values ($1, $2, $3)
+        returning id
+        "#,
+    )
+    .bind(region.to_string())
+    .bind(params.encode_to_vec())
+    .bind(effective_at)
+    .fetch_one(db)
+    .await?;
+    Ok(id)
+}
+
+/// Periodically reload the region params so that versions scheduled with a
+/// future effective time are served once they take effect.
+pub async fn refresh_params(
+    db: sqlx::Pool<sqlx::Postgres>,
+    region_updater: Arc<watch::Sender<RegionMap>>,
+    shutdown: triggered::Listener,
+) -> anyhow::Result<()> {
+    let mut refresh_timer = tokio::time::interval(PARAMS_REFRESH_INTERVAL);
+    refresh_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.clone() => return Ok(()),
+            _ = refresh_timer.tick() => {
+                // The current params stay in use until a later refresh
+                // succeeds
+                let params_map = match build_params_map(&db).await {
+                    Ok(params_map) => params_map,
+                    Err(err) => {
+                        tracing::warn!(?err, "failed to refresh region params");
+                        continue;
+                    }
+                };
+                let updated = region_updater
+                    .send_if_modified(|region_map| region_map.replace_params(params_map));
+                if updated {
+                    tracing::info!("region params updated from newly effective version");
+                }
+            }
+        }
+    }
+}
@@ -14,7 +14,10 @@ use helium_proto::Message;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::{types::Uuid, Row};
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 use tokio::sync::broadcast::Sender;
 
 pub mod proto {
@@ -481,6 +484,37 @@ pub async fn list_routes(oui: u64, db: impl sqlx::PgExecutor<'_>) -> anyhow::Res
     .await)
 }
 
+/// Stream the routes of the org to the route stream subscribers again, e.g.
+/// after the org was locked or unlocked
+pub async fn stream_org_routes(
+    oui: u64,
+    db: impl sqlx::PgExecutor<'_>,
+    signing_key: &Keypair,
+    update_tx: &Sender<proto::RouteStreamResV1>,
+) -> anyhow::Result<()> {
+    let timestamp = Utc::now().encode_timestamp();
+    let signer: Vec<u8> = signing_key.public_key().into();
+    for route in list_routes(oui, db).await? {
+        let route_id = route.id.clone();
+        let mut update = proto::RouteStreamResV1 {
+            action: proto::ActionV1::Add.into(),
+            data: Some(proto::route_stream_res_v1::Data::Route(route.into())),
+            timestamp,
+            signer: signer.clone(),
+            signature: vec![],
+        };
+        update.signature = signing_key.sign(&update.encode_to_vec())?;
+        if update_tx.send(update).is_err() {
+            tracing::info!(
+                route_id,
+                "all subscribers disconnected; org route update incomplete"
+            );
+            break;
+        }
+    }
+    Ok(())
+}
+
 pub fn list_euis_for_route<'a>(
     id: &str,
     db: impl sqlx::PgExecutor<'a> + 'a + Copy,
@@ -546,6 +580,16 @@ pub fn active_route_stream<'a>(
 pub fn eui_stream<'a>(
     db: impl sqlx::PgExecutor<'a> + 'a + Copy,
 ) -> impl Stream<Item = EuiPair> + 'a {
+    all_euis(db)
+        .filter_map(|eui| async move { eui.ok() })
+        .boxed()
+}
+
+/// Every eui pair, failing the stream on a database error instead of
+/// skipping the rows that could not be read
+pub fn all_euis<'a>(
+    db: impl sqlx::PgExecutor<'a> + 'a,
+) -> impl Stream<Item = Result<EuiPair, sqlx::Error>> + 'a {
     sqlx::query_as::<_, EuiPair>(
         r#"
         select eui.route_id, eui.app_eui, eui.dev_eui
@@ -553,14 +597,20 @@ pub fn eui_stream<'a>(
         "#,
     )
     .fetch(db)
-    .map_err(sqlx::Error::from)
-    .filter_map(|eui| async move { eui.ok() })
-    .boxed()
 }
 
 pub fn devaddr_range_stream<'a>(
     db: impl sqlx::PgExecutor<'a> + 'a + Copy,
 ) -> impl Stream<Item = DevAddrRange> + 'a {
+    all_devaddr_ranges(db)
+        .filter_map(|devaddr| async move { devaddr.ok() })
+        .boxed()
+}
+
+/// Every devaddr range, failing the stream on a database error
+pub fn all_devaddr_ranges<'a>(
+    db: impl sqlx::PgExecutor<'a> + 'a,
+) -> impl Stream<Item = Result<DevAddrRange, sqlx::Error>> + 'a {
     sqlx::query_as::<_, DevAddrRange>(
         r#"
         select devaddr.route_id, devaddr.start_addr, devaddr.end_addr
@@ -568,12 +618,18 @@ pub fn devaddr_range_stream<'a>(
         "#,
     )
     .fetch(db)
-    .map_err(sqlx::Error::from)
-    .filter_map(|devaddr| async move { devaddr.ok() })
-    .boxed()
 }
 
 pub fn skf_stream<'a>(db: impl sqlx::PgExecutor<'a> + 'a + Copy) -> impl Stream<Item = Skf> + 'a {
+    all_skfs(db)
+        .filter_map(|skf| async move { skf.ok() })
+        .boxed()
+}
+
+/// Every session key filter, failing the stream on a database error
+pub fn all_skfs<'a>(
+    db: impl sqlx::PgExecutor<'a> + 'a,
+) -> impl Stream<Item = Result<Skf, sqlx::Error>> + 'a {
     sqlx::query_as::<_, Skf>(
         r#"
         select skf.route_id, skf.devaddr, skf.session_key, skf.max_copies
@@ -581,8 +637,6 @@ pub fn skf_stream<'a>(db: impl sqlx::PgExecutor<'a> + 'a + Copy) -> impl Stream<
         "#,
     )
     .fetch(db)
-    .filter_map(|skf| async move { skf.ok() })
-    .boxed()
 }
 
 pub async fn get_route(id: &str, db: impl sqlx::PgExecutor<'_>) -> anyhow::Result<Route> {
@@ -758,15 +812,33 @@ async fn insert_skfs(skfs: &[Skf], db: impl sqlx::PgExecutor<'_>) -> anyhow::Res
         return Ok(vec![]);
     }
 
+    // An upsert can only touch each row once per statement so collapse any
+    // repeated filters in the request, keeping the last max_copies given
     let skfs = skfs
         .iter()
         .map(|filter| filter.try_into())
-        .collect::<Result<Vec<(Uuid, i32, String, i32)>, _>>()?;
+        .collect::<Result<Vec<(Uuid, i32, String, i32)>, _>>()?
+        .into_iter()
+        .map(|(route_id, devaddr, session_key, max_copies)| {
+            ((route_id, devaddr, session_key), max_copies)
+        })
+        .collect::<HashMap<_, _>>()
+        .into_iter()
+        .map(|((route_id, devaddr, session_key), max_copies)| {
+            (route_id, devaddr, session_key, max_copies)
+        })
+        .collect::<Vec<_>>();
 
     const SKF_INSERT_VALS: &str =
         " insert into route_session_key_filters (route_id, devaddr, session_key, max_copies) ";
-    const SKF_INSERT_CONFLICT: &str =
-        " on conflict (route_id, devaddr, session_key) do nothing returning * ";
+    // Re-adding an existing filter with a different max_copies updates it in
+    // place so the change is broadcast to stream subscribers
+    const SKF_INSERT_CONFLICT: &str = r#"
+        on conflict (route_id, devaddr, session_key) do update
+        set max_copies = excluded.max_copies
+        where route_session_key_filters.max_copies <> excluded.max_copies
+        returning *
+        "#;
 
     let mut query_builder: sqlx::QueryBuilder<sqlx::Postgres> =
         sqlx::QueryBuilder::new(SKF_INSERT_VALS);
@@ -1,7 +1,9 @@
 use crate::{
     admin::{AuthCache, KeyType},
+    audit::AuditLogger,
     lora_field::{DevAddrConstraint, DevAddrRange, EuiPair, Skf},
     org::{self, OrgStoreError},
+    org_review,
     route::{self, Route, RouteStorageError},
     telemetry, update_channel, verify_public_key, GrpcResult, GrpcStreamRequest, GrpcStreamResult,
     Settings,
@@ -35,6 +37,7 @@ const SKF_UPDATE_LIMIT: usize = 100;
 
 pub struct RouteService {
     auth_cache: AuthCache,
+    audit: AuditLogger,
     pool: Pool<Postgres>,
     update_channel: broadcast::Sender<RouteStreamResV1>,
     shutdown: triggered::Listener,
@@ -51,11 +54,13 @@ impl RouteService {
     pub fn new(
         settings: &Settings,
         auth_cache: AuthCache,
+        audit: AuditLogger,
         pool: Pool<Postgres>,
         shutdown: triggered::Listener,
     ) -> Result<Self> {
         Ok(Self {
             auth_cache,
+            audit,
             pool,
             update_channel: update_channel(),
             shutdown,
@@ -233,6 +238,10 @@ impl iot_config::Route for RouteService {
     async fn create(&self, request: Request<RouteCreateReqV1>) -> GrpcResult<RouteResV1> {
         let request = request.into_inner();
         telemetry::count_request("route", "create");
+        let audit = self
+            .audit
+            .begin("route", "create")
+            .with_request(&request.signer, &request);
 
         let signer = verify_public_key(&request.signer)?;
         self.verify_request_signature(&signer, &request, OrgId::Oui(request.oui))
@@ -255,6 +264,7 @@ impl iot_config::Route for RouteService {
                 "request oui does not match route oui",
             ));
         }
+        org_review::ensure_approved(request.oui, &self.pool).await?;
 
         let new_route: Route = route::create_route(
             route,
@@ -276,12 +286,17 @@ impl iot_config::Route for RouteService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
     async fn update(&self, request: Request<RouteUpdateReqV1>) -> GrpcResult<RouteResV1> {
         let request = request.into_inner();
         telemetry::count_request("route", "update");
+        let audit = self
+            .audit
+            .begin("route", "update")
+            .with_request(&request.signer, &request);
 
         let route: Route = request
             .clone()
@@ -319,12 +334,17 @@ impl iot_config::Route for RouteService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
     async fn delete(&self, request: Request<RouteDeleteReqV1>) -> GrpcResult<RouteResV1> {
         let request = request.into_inner();
         telemetry::count_request("route", "delete");
+        let audit = self
+            .audit
+            .begin("route", "delete")
+            .with_request(&request.signer, &request);
 
         let signer = verify_public_key(&request.signer)?;
         self.verify_request_signature(&signer, &request, OrgId::RouteId(&request.id))
@@ -356,6 +376,7 @@ impl iot_config::Route for RouteService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
@@ -396,8 +417,28 @@ impl iot_config::Route for RouteService {
                         telemetry::route_stream_unsubscribe();
                         return
                     }
-                    msg = route_updates.recv() => if let Ok(update) = msg {
-                        if tx.send(Ok(update)).await.is_err() {
+                    msg = route_updates.recv() => match msg {
+                        Ok(update) => {
+                            if tx.send(Ok(update)).await.is_err() {
+                                telemetry::route_stream_unsubscribe();
+                                return;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            // The subscriber has missed updates and can no longer be
+                            // kept consistent; end the stream so it reconnects and
+                            // receives a fresh snapshot.
+                            tracing::warn!(skipped, "route stream subscriber lagged; closing stream");
+                            telemetry::route_stream_lagged();
+                            _ = tx
+                                .send(Err(Status::aborted(format!(
+                                    "route stream lagged by {skipped} updates; resubscribe"
+                                ))))
+                                .await;
+                            telemetry::route_stream_unsubscribe();
+                            return;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
                             telemetry::route_stream_unsubscribe();
                             return;
                         }
@@ -466,6 +507,7 @@ impl iot_config::Route for RouteService {
     ) -> GrpcResult<RouteEuisResV1> {
         let request = request.into_inner();
         telemetry::count_request("route", "update-euis");
+        let mut audit = self.audit.begin("route", "update-euis");
 
         let mut incoming_stream = request.peekable();
         let mut validator: DevAddrEuiValidator = Pin::new(&mut incoming_stream)
@@ -489,11 +531,14 @@ impl iot_config::Route for RouteService {
             .await?;
 
         incoming_stream
-            .map_ok(|update| match validator.validate_update(&update) {
-                Ok(()) => Ok(update),
-                Err(reason) => Err(Status::invalid_argument(format!(
-                    "invalid update request: {reason:?}"
-                ))),
+            .map_ok(|update| {
+                audit.observe(&update.signer, &update);
+                match validator.validate_update(&update) {
+                    Ok(()) => Ok(update),
+                    Err(reason) => Err(Status::invalid_argument(format!(
+                        "invalid update request: {reason:?}"
+                    ))),
+                }
             })
             .try_chunks(UPDATE_BATCH_LIMIT)
             .map_err(|err| Status::internal(format!("eui pair updates failed to batch: {err:?}")))
@@ -553,6 +598,7 @@ impl iot_config::Route for RouteService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
@@ -614,6 +660,7 @@ impl iot_config::Route for RouteService {
     ) -> GrpcResult<RouteDevaddrRangesResV1> {
         let request = request.into_inner();
         telemetry::count_request("route", "update-devaddr-ranges");
+        let mut audit = self.audit.begin("route", "update-devaddr-ranges");
 
         let mut incoming_stream = request.peekable();
         let mut validator: DevAddrEuiValidator = Pin::new(&mut incoming_stream)
@@ -637,11 +684,14 @@ impl iot_config::Route for RouteService {
             .await?;
 
         incoming_stream
-            .map_ok(|update| match validator.validate_update(&update) {
-                Ok(()) => Ok(update),
-                Err(reason) => Err(Status::invalid_argument(format!(
-                    "invalid update request: {reason:?}"
-                ))),
+            .map_ok(|update| {
+                audit.observe(&update.signer, &update);
+                match validator.validate_update(&update) {
+                    Ok(()) => Ok(update),
+                    Err(reason) => Err(Status::invalid_argument(format!(
+                        "invalid update request: {reason:?}"
+                    ))),
+                }
             })
             .try_chunks(UPDATE_BATCH_LIMIT)
             .map_err(|err| {
@@ -707,6 +757,7 @@ impl iot_config::Route for RouteService {
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
 
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 
@@ -828,6 +879,10 @@ impl iot_config::Route for RouteService {
     ) -> GrpcResult<RouteSkfUpdateResV1> {
         let request = request.into_inner();
         telemetry::count_request("route", "update-skfs");
+        let audit = self
+            .audit
+            .begin("route", "update-skfs")
+            .with_request(&request.signer, &request);
 
         if request.updates.len() > SKF_UPDATE_LIMIT {
             return Err(Status::invalid_argument(
@@ -884,6 +939,7 @@ impl iot_config::Route for RouteService {
             signature: vec![],
         };
         resp.signature = self.sign_response(&resp.encode_to_vec())?;
+        audit.succeeded();
         Ok(Response::new(resp))
     }
 }
@@ -0,0 +1,476 @@
+//! Periodic snapshots of the full routing state exported to the output
+//! bucket, so routers bootstrap from a single file instead of streaming
+//! every route, eui pair, devaddr range and session key filter.
+//!
+//! A snapshot holds one entry per org first, each followed by the routes of
+//! the org, then every eui pair, devaddr range and session key filter, all
+//! read in a single repeatable read transaction. The route stream is still the source of updates after a
+//! snapshot. The checksum is the sha256 of the encoded entries in file
+//! order, so a router can check the snapshot it decoded is complete.
+//!
+//! The location and checksum of the latest snapshot are served by an
+//! endpoint hand written on top of the tonic primitives like the log filter
+//! endpoint, signed by any key known to the config service. A snapshot
+//! older than two export intervals is not served.
+
+use crate::{admin::AuthCache, org, route};
+use chrono::{DateTime, Utc};
+use file_store::{file_sink::FileSinkClient, traits::MsgVerify};
+use futures::TryStreamExt;
+use helium_crypto::{Keypair, PublicKey, Sign, Verify};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres, Transaction};
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use tokio::sync::watch;
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const SERVICE_NAME: &str = "helium.iot_config.route_snapshot";
+pub const LATEST_PATH: &str = "/helium.iot_config.route_snapshot/latest";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RouteSnapshotEntryV1 {
+    #[prost(oneof = "route_snapshot_entry_v1::Entry", tags = "1, 2, 3, 4, 5")]
+    pub entry: Option<route_snapshot_entry_v1::Entry>,
+}
+
+pub mod route_snapshot_entry_v1 {
+    use helium_proto::services::iot_config::{DevaddrRangeV1, EuiPairV1, OrgV1, RouteV1, SkfV1};
+
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Entry {
+        #[prost(message, tag = "1")]
+        Org(OrgV1),
+        #[prost(message, tag = "2")]
+        Route(RouteV1),
+        #[prost(message, tag = "3")]
+        EuiPair(EuiPairV1),
+        #[prost(message, tag = "4")]
+        DevaddrRange(DevaddrRangeV1),
+        #[prost(message, tag = "5")]
+        Skf(SkfV1),
+    }
+}
+
+use route_snapshot_entry_v1::Entry;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RouteSnapshotReqV1 {
+    /// Milliseconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "1")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "2")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RouteSnapshotResV1 {
+    #[prost(string, tag = "1")]
+    pub bucket: String,
+    /// Keys of the snapshot files in the bucket, the entries continue from
+    /// one file to the next. A single key encodes like the former `key`
+    /// string field
+    #[prost(string, repeated, tag = "2")]
+    pub keys: Vec<String>,
+    /// Sha256 of the encoded entries of the snapshot in file order
+    #[prost(bytes = "vec", tag = "3")]
+    pub sha256: Vec<u8>,
+    #[prost(uint64, tag = "4")]
+    pub entries: u64,
+    /// Seconds since the epoch of when the snapshot was taken
+    #[prost(uint64, tag = "5")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "6")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "7")]
+    pub signature: Vec<u8>,
+}
+
+impl MsgVerify for RouteSnapshotReqV1 {
+    fn verify(&self, verifier: &PublicKey) -> file_store::Result {
+        let unsigned = Self {
+            signature: vec![],
+            ..self.clone()
+        };
+        verifier
+            .verify(&unsigned.encode_to_vec(), &self.signature)
+            .map_err(file_store::Error::from)
+    }
+}
+
+/// Location and checksum of an exported snapshot
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub keys: Vec<String>,
+    pub sha256: Vec<u8>,
+    pub entries: u64,
+    pub timestamp: DateTime<Utc>,
+}
+
+pub type LatestSnapshot = watch::Receiver<Option<SnapshotInfo>>;
+
+/// Periodically exports a snapshot of the routing state to the output bucket
+pub struct RouteSnapshotExporter {
+    pool: Pool<Postgres>,
+    snapshot_sink: FileSinkClient,
+    interval: Duration,
+    latest: watch::Sender<Option<SnapshotInfo>>,
+}
+
+impl RouteSnapshotExporter {
+    pub fn new(
+        pool: Pool<Postgres>,
+        snapshot_sink: FileSinkClient,
+        interval: Duration,
+    ) -> (Self, LatestSnapshot) {
+        let (latest, latest_receiver) = watch::channel(None);
+        let exporter = Self {
+            pool,
+            snapshot_sink,
+            interval,
+            latest,
+        };
+        (exporter, latest_receiver)
+    }
+
+    pub async fn run(self, shutdown: triggered::Listener) -> anyhow::Result<()> {
+        tracing::info!("starting route snapshot exporter");
+        let mut export_timer = tokio::time::interval(self.interval);
+        export_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                _ = export_timer.tick() => match self.export().await {
+                    Ok(Some(snapshot)) => {
+                        tracing::info!(keys = ?snapshot.keys, entries = snapshot.entries, "exported route snapshot");
+                        metrics::gauge!("route_snapshot_entries", snapshot.entries as f64);
+                        self.latest.send_replace(Some(snapshot));
+                    }
+                    Ok(None) => tracing::info!("no orgs, skipping route snapshot"),
+                    // The previous snapshot is served until the next one
+                    Err(err) => tracing::error!("failed to export route snapshot: {err:?}"),
+                },
+            }
+        }
+        tracing::info!("stopping route snapshot exporter");
+        Ok(())
+    }
+
+    pub async fn export(&self) -> anyhow::Result<Option<SnapshotInfo>> {
+        let timestamp = Utc::now();
+        // Every query of the export reads the same state, so routes of an
+        // org deleted halfway through the export are never left behind
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("set transaction isolation level repeatable read, read only")
+            .execute(&mut transaction)
+            .await?;
+        self.snapshot_sink.file_time(timestamp).await?;
+
+        let mut hasher = Sha256::new();
+        let entries = match self.write_entries(&mut transaction, &mut hasher).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                // Drop the partial file instead of shipping it with the next
+                // snapshot
+                self.snapshot_sink.rollback().await?.await??;
+                return Err(err);
+            }
+        };
+        transaction.commit().await?;
+        if entries == 0 {
+            return Ok(None);
+        }
+
+        // A snapshot rolling over to more than one file is still complete,
+        // the entries continue from one file to the next
+        let keys = self.snapshot_sink.commit().await?.await??;
+        Ok(Some(SnapshotInfo {
+            keys,
+            sha256: hasher.finalize().to_vec(),
+            entries,
+            timestamp,
+        }))
+    }
+
+    async fn write_entries(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        hasher: &mut Sha256,
+    ) -> anyhow::Result<u64> {
+        let mut entries = 0;
+        for org in org::list(&mut *transaction).await? {
+            let routes = route::list_routes(org.oui, &mut *transaction).await?;
+            self.write(Entry::Org(org.into()), hasher).await?;
+            entries += 1;
+            for route in routes {
+                self.write(Entry::Route(route.into()), hasher).await?;
+                entries += 1;
+            }
+        }
+        if entries == 0 {
+            return Ok(0);
+        }
+
+        let mut euis = route::all_euis(&mut *transaction);
+        while let Some(eui) = euis.try_next().await? {
+            self.write(Entry::EuiPair(eui.into()), hasher).await?;
+            entries += 1;
+        }
+        drop(euis);
+        let mut devaddr_ranges = route::all_devaddr_ranges(&mut *transaction);
+        while let Some(devaddr_range) = devaddr_ranges.try_next().await? {
+            self.write(Entry::DevaddrRange(devaddr_range.into()), hasher)
+                .await?;
+            entries += 1;
+        }
+        drop(devaddr_ranges);
+        let mut skfs = route::all_skfs(&mut *transaction);
+        while let Some(skf) = skfs.try_next().await? {
+            self.write(Entry::Skf(skf.into()), hasher).await?;
+            entries += 1;
+        }
+        Ok(entries)
+    }
+
+    async fn write(&self, entry: Entry, hasher: &mut Sha256) -> anyhow::Result<()> {
+        let entry = RouteSnapshotEntryV1 { entry: Some(entry) };
+        hasher.update(entry.encode_to_vec());
+        self.snapshot_sink
+            .write(entry, &[])
+            .await?
+            // a failed write would leave a snapshot not matching its checksum
+            .await??;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct RouteSnapshotService {
+    auth_cache: AuthCache,
+    signing_key: Arc<Keypair>,
+    /// Output bucket of the snapshots, none when they are not exported
+    bucket: Option<String>,
+    latest: LatestSnapshot,
+    /// Age past which the latest snapshot is no longer served, so routers
+    /// fall back to streaming when the exports keep failing
+    max_age: Duration,
+}
+
+impl RouteSnapshotService {
+    pub fn new(
+        auth_cache: AuthCache,
+        signing_key: Arc<Keypair>,
+        bucket: Option<String>,
+        latest: LatestSnapshot,
+        max_age: Duration,
+    ) -> Self {
+        Self {
+            auth_cache,
+            signing_key,
+            bucket,
+            latest,
+            max_age,
+        }
+    }
+
+    pub async fn latest(&self, request: RouteSnapshotReqV1) -> Result<RouteSnapshotResV1, Status> {
+        let signer = PublicKey::try_from(request.signer.as_slice())
+            .map_err(|_| Status::invalid_argument("invalid signer"))?;
+        self.auth_cache
+            .verify_signature(&signer, &request)
+            .map_err(|_| Status::permission_denied("unauthorized request signature"))?;
+
+        let Some(bucket) = self.bucket.clone() else {
+            return Err(Status::unimplemented("route snapshots are not exported"));
+        };
+        let snapshot = self
+            .latest
+            .borrow()
+            .clone()
+            .ok_or_else(|| Status::not_found("no route snapshot exported yet"))?;
+        let stale = (Utc::now() - snapshot.timestamp)
+            .to_std()
+            .map_or(false, |age| age > self.max_age);
+        if stale {
+            return Err(Status::unavailable("latest route snapshot is stale"));
+        }
+        let mut response = RouteSnapshotResV1 {
+            bucket,
+            keys: snapshot.keys,
+            sha256: snapshot.sha256,
+            entries: snapshot.entries,
+            timestamp: snapshot.timestamp.timestamp() as u64,
+            signer: self.signing_key.public_key().into(),
+            signature: vec![],
+        };
+        response.signature = self
+            .signing_key
+            .sign(&response.encode_to_vec())
+            .map_err(|_| Status::internal("response signing error"))?;
+        Ok(response)
+    }
+}
+
+impl NamedService for RouteSnapshotService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for RouteSnapshotService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            LATEST_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.latest(request).await })
+            }
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::KeyType;
+    use helium_crypto::{KeyTag, KeyType as CryptoKeyType, Network};
+    use rand::rngs::OsRng;
+    use std::collections::HashMap;
+
+    fn keypair() -> Keypair {
+        Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: CryptoKeyType::Ed25519,
+            },
+            &mut OsRng,
+        )
+    }
+
+    fn request(keypair: &Keypair) -> RouteSnapshotReqV1 {
+        let mut request = RouteSnapshotReqV1 {
+            timestamp: Utc::now().timestamp_millis() as u64,
+            signer: keypair.public_key().into(),
+            signature: vec![],
+        };
+        request.signature = keypair.sign(&request.encode_to_vec()).unwrap();
+        request
+    }
+
+    fn snapshot(age: chrono::Duration) -> SnapshotInfo {
+        SnapshotInfo {
+            keys: vec!["iot_config_route_snapshot.1.gz".to_string()],
+            sha256: vec![1; 32],
+            entries: 3,
+            timestamp: Utc::now() - age,
+        }
+    }
+
+    fn snapshot_service(
+        client: &Keypair,
+        bucket: Option<&str>,
+        snapshot: Option<SnapshotInfo>,
+    ) -> (RouteSnapshotService, Arc<Keypair>) {
+        let auth_cache = AuthCache::from_keys(HashMap::from([(
+            client.public_key().clone(),
+            KeyType::Administrator,
+        )]));
+        let signing_key = Arc::new(keypair());
+        let (_latest_tx, latest) = watch::channel(snapshot);
+        let service = RouteSnapshotService::new(
+            auth_cache,
+            signing_key.clone(),
+            bucket.map(str::to_string),
+            latest,
+            Duration::from_secs(60 * 60),
+        );
+        (service, signing_key)
+    }
+
+    #[test]
+    fn signed_requests_verify() {
+        let keypair = keypair();
+        let request = request(&keypair);
+        assert!(request.verify(keypair.public_key()).is_ok());
+
+        let tampered = RouteSnapshotReqV1 {
+            timestamp: 2,
+            ..request
+        };
+        assert!(tampered.verify(keypair.public_key()).is_err());
+    }
+
+    #[tokio::test]
+    async fn latest_snapshot_is_signed_by_the_service() {
+        let client = keypair();
+        let fresh = snapshot(chrono::Duration::minutes(5));
+        let (service, signing_key) = snapshot_service(&client, Some("bucket"), Some(fresh.clone()));
+
+        let response = service.latest(request(&client)).await.unwrap();
+        assert_eq!(response.bucket, "bucket");
+        assert_eq!(response.keys, fresh.keys);
+        assert_eq!(response.sha256, fresh.sha256);
+        assert_eq!(response.entries, 3);
+        assert_eq!(response.timestamp, fresh.timestamp.timestamp() as u64);
+
+        let unsigned = RouteSnapshotResV1 {
+            signature: vec![],
+            ..response.clone()
+        };
+        assert_eq!(response.signer, Vec::<u8>::from(signing_key.public_key()));
+        assert!(signing_key
+            .public_key()
+            .verify(&unsigned.encode_to_vec(), &response.signature)
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn stale_snapshots_are_not_served() {
+        let client = keypair();
+        let stale = snapshot(chrono::Duration::hours(2));
+        let (service, _) = snapshot_service(&client, Some("bucket"), Some(stale));
+
+        let status = service.latest(request(&client)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn missing_snapshots_are_reported() {
+        let client = keypair();
+        let (service, _) = snapshot_service(&client, Some("bucket"), None);
+        let status = service.latest(request(&client)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let fresh = snapshot(chrono::Duration::zero());
+        let (service, _) = snapshot_service(&client, None, Some(fresh));
+        let status = service.latest(request(&client)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unimplemented);
+    }
+
+    #[tokio::test]
+    async fn unknown_signers_are_rejected() {
+        let fresh = snapshot(chrono::Duration::zero());
+        let (service, _) = snapshot_service(&keypair(), Some("bucket"), Some(fresh));
+
+        let status = service.latest(request(&keypair())).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+}
@@ -1,6 +1,10 @@
+use crate::rate_limit::RateLimit;
 use config::{Config, Environment, File};
+use grpc_service::tls::TlsSettings;
+use oracle_settings::Secret;
 use serde::Deserialize;
 use std::{
+    collections::HashMap,
     net::{AddrParseError, SocketAddr},
     path::Path,
     str::FromStr,
@@ -12,11 +16,21 @@ pub struct Settings {
     /// "iot_config=info"
     #[serde(default = "default_log")]
     pub log: String,
+    /// Log output format and per module log levels
+    #[serde(default)]
+    pub tracing: custom_tracing::Settings,
     /// Listen address. Required. Default is 0.0.0.0:8080
     #[serde(default = "default_listen_addr")]
     pub listen: String,
-    /// File from which to load config server signing keypair
-    pub keypair: String,
+    /// Serve grpc over TLS when set. Plaintext by default
+    pub tls: Option<TlsSettings>,
+    /// Listen address for the http health (/healthz) and readiness (/readyz)
+    /// probes. Default is 0.0.0.0:8081
+    #[serde(default = "default_health_listen_addr")]
+    pub health_listen: String,
+    /// File from which to load config server signing keypair, or a `file:`
+    /// or `env:` secret reference
+    pub keypair: Secret,
     /// B58 encoded public key of the admin keypair
     pub admin: String,
     pub database: db_store::Settings,
@@ -24,12 +38,68 @@ pub struct Settings {
     /// the database for Solana on-chain data
     pub metadata: db_store::Settings,
     pub metrics: poc_metrics::Settings,
+    /// Local folder for output files before they are uploaded. Default is
+    /// "/var/data/iot-config"
+    #[serde(default = "default_cache")]
+    pub cache: String,
+    /// Optional bucket to upload audit and denylist files to. Audit events
+    /// are always stored in the database; files are only written when this
+    /// is set
+    pub output: Option<file_store::Settings>,
+    /// Per caller rate limits for the grpc endpoints. No limits by default
+    #[serde(default)]
+    pub rate_limits: RateLimitSettings,
+    /// How long resolved gateway info (location, region and gain) is cached
+    /// in seconds before being looked up again. Default is 3 hours
+    #[serde(default = "default_gateway_cache_ttl_secs")]
+    pub gateway_cache_ttl_secs: u64,
+    /// How often a snapshot of the routing state is exported to the output
+    /// bucket, in minutes. Only exported when output is set. Default is 60
+    #[serde(default = "default_route_snapshot_mins")]
+    pub route_snapshot_mins: u64,
+    /// Create new orgs pending review, they can not create routes until an
+    /// administrator approves them. Default is false
+    #[serde(default)]
+    pub review_new_orgs: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RateLimitSettings {
+    /// Limit applied to each caller of any endpoint without its own limit
+    pub default: Option<RateLimit>,
+    /// Limits for each caller of an endpoint keyed by the grpc path, e.g.
+    /// "/helium.iot_config.org/get"
+    #[serde(default)]
+    pub endpoints: HashMap<String, RateLimit>,
+}
+
+pub fn default_gateway_cache_ttl_secs() -> u64 {
+    60 * 60 * 3
+}
+
+pub fn default_route_snapshot_mins() -> u64 {
+    60
+}
+
+pub fn default_cache() -> String {
+    "/var/data/iot-config".to_string()
 }
 
 pub fn default_log() -> String {
     "iot_config=debug".to_string()
 }
 
+pub fn default_health_listen_addr() -> String {
+    "0.0.0.0:8081".to_string()
+}
+
+pub fn read_keypair(keypair: &Secret) -> Result<helium_crypto::Keypair, Box<helium_crypto::Error>> {
+    let data = keypair.resolve_keypair().map_err(|err| {
+        helium_crypto::Error::from(std::io::Error::new(std::io::ErrorKind::Other, err))
+    })?;
+    Ok(helium_crypto::Keypair::try_from(&data[..])?)
+}
+
 pub fn default_listen_addr() -> String {
     "0.0.0.0:8080".to_string()
 }
@@ -63,8 +133,19 @@ impl Settings {
     }
 
     pub fn signing_keypair(&self) -> Result<helium_crypto::Keypair, Box<helium_crypto::Error>> {
-        let data = std::fs::read(&self.keypair).map_err(helium_crypto::Error::from)?;
-        Ok(helium_crypto::Keypair::try_from(&data[..])?)
+        read_keypair(&self.keypair)
+    }
+
+    pub fn health_listen_addr(&self) -> Result<SocketAddr, AddrParseError> {
+        SocketAddr::from_str(&self.health_listen)
+    }
+
+    pub fn route_snapshot_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(60 * self.route_snapshot_mins.max(1))
+    }
+
+    pub fn gateway_cache_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.gateway_cache_ttl_secs)
     }
 
     pub fn admin_pubkey(&self) -> Result<helium_crypto::PublicKey, helium_crypto::Error> {
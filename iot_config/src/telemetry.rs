@@ -1,5 +1,6 @@
 const RPC_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "-", "grpc-request");
 const STREAM_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "-", "grpc-stream");
+const STREAM_LAGGED_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "-", "grpc-stream-lagged");
 const REGION_HEX_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "-", "region-hexes");
 const REGION_LOOKUP_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "-", "region-lookup");
 const SKF_ADD_COUNT_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "-", "skfs-added");
@@ -12,6 +13,8 @@ const GATEWAY_CHAIN_LOOKUP_METRIC: &str =
     concat!(env!("CARGO_PKG_NAME"), "-", "gateway-info-lookup");
 const GATEWAY_CHAIN_LOOKUP_DURATION_METRIC: &str =
     concat!(env!("CARGO_PKG_NAME"), "-", "gateway-info-lookup-duration");
+const RATE_LIMITED_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "-", "grpc-rate-limited");
+const AUDIT_DROPPED_METRIC: &str = concat!(env!("CARGO_PKG_NAME"), "-", "audit-events-dropped");
 
 pub fn initialize() {
     metrics::gauge!(STREAM_METRIC, 0.0);
@@ -21,6 +24,10 @@ pub fn count_request(service: &'static str, rpc: &'static str) {
     metrics::increment_counter!(RPC_METRIC, "service" => service, "rpc" => rpc);
 }
 
+pub fn count_rate_limited(endpoint: &str) {
+    metrics::increment_counter!(RATE_LIMITED_METRIC, "endpoint" => endpoint.to_string());
+}
+
 pub fn count_gateway_info_lookup(result: &'static str) {
     metrics::increment_counter!(GATEWAY_CHAIN_LOOKUP_METRIC, "result" => result);
 }
@@ -47,6 +54,10 @@ pub fn duration_gateway_info_lookup(start: std::time::Instant) {
     metrics::histogram!(GATEWAY_CHAIN_LOOKUP_DURATION_METRIC, start.elapsed());
 }
 
+pub fn count_audit_dropped() {
+    metrics::increment_counter!(AUDIT_DROPPED_METRIC);
+}
+
 pub fn count_skf_updates(adds: usize, removes: usize) {
     metrics::counter!(SKF_ADD_COUNT_METRIC, adds as u64);
     metrics::counter!(SKF_REMOVE_COUNT_METRIC, removes as u64);
@@ -66,6 +77,10 @@ pub fn route_stream_subscribe() {
     metrics::increment_gauge!(STREAM_METRIC, 1.0);
 }
 
+pub fn route_stream_lagged() {
+    metrics::increment_counter!(STREAM_LAGGED_METRIC);
+}
+
 pub fn route_stream_unsubscribe() {
     metrics::decrement_gauge!(STREAM_METRIC, 1.0);
 }
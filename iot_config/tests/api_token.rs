@@ -0,0 +1,158 @@
+use chrono::{Duration, TimeZone, Utc};
+use file_store::traits::MsgVerify;
+use helium_crypto::{KeyTag, KeyType as CryptoKeyType, Keypair, Network, Sign};
+use iot_config::{
+    admin::{AuthCache, CacheKeys, KeyType},
+    api_token::{
+        self, ApiTokenService, OrgTokenV1, RevokeTokenReqV1, TokenStatusReqV1, PACKET_STATS_SCOPE,
+    },
+};
+use prost::Message;
+use rand::rngs::OsRng;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+fn keypair() -> Keypair {
+    Keypair::generate(
+        KeyTag {
+            network: Network::MainNet,
+            key_type: CryptoKeyType::Ed25519,
+        },
+        &mut OsRng,
+    )
+}
+
+fn token(config: &Keypair, expires_at: u64) -> OrgTokenV1 {
+    let mut token = OrgTokenV1 {
+        id: rand::random::<[u8; 16]>().to_vec(),
+        oui: 1,
+        scopes: vec![PACKET_STATS_SCOPE.to_string()],
+        expires_at,
+        signer: config.public_key().into(),
+        signature: vec![],
+    };
+    token.signature = config.sign(&token.encode_to_vec()).unwrap();
+    token
+}
+
+fn revoke_request(admin: &Keypair, oui: u64, token: &OrgTokenV1) -> RevokeTokenReqV1 {
+    let mut request = RevokeTokenReqV1 {
+        oui,
+        token: token.encode_to_vec(),
+        timestamp: Utc::now().timestamp() as u64,
+        signer: admin.public_key().into(),
+        signature: vec![],
+    };
+    request.signature = admin.sign(&request.encode_to_vec()).unwrap();
+    request
+}
+
+fn status_request(signer: &Keypair, id: &[u8]) -> TokenStatusReqV1 {
+    let mut request = TokenStatusReqV1 {
+        id: id.to_vec(),
+        signer: signer.public_key().into(),
+        signature: vec![],
+    };
+    request.signature = signer.sign(&request.encode_to_vec()).unwrap();
+    request
+}
+
+/// Token service signing with the config key and accepting requests of the
+/// admin key
+fn service(pool: PgPool, config: Keypair, admin: &Keypair) -> ApiTokenService {
+    let keys = CacheKeys::from([(admin.public_key().clone(), KeyType::Administrator)]);
+    ApiTokenService::new(AuthCache::from_keys(keys), Arc::new(config), pool)
+}
+
+fn in_hours(hours: i64) -> u64 {
+    (Utc::now() + Duration::hours(hours)).timestamp() as u64
+}
+
+#[sqlx::test]
+async fn revocations_are_kept_until_the_token_expires(pool: PgPool) {
+    let config = keypair();
+    let admin = keypair();
+    let token = token(&config, in_hours(1));
+    let service = service(pool.clone(), config, &admin);
+
+    let response = service
+        .revoke(revoke_request(&admin, 1, &token))
+        .await
+        .unwrap();
+    assert_eq!(response.id, token.id);
+
+    let expires_at: chrono::DateTime<Utc> =
+        sqlx::query_scalar("select expires_at from api_token_revocations where id = $1")
+            .bind(&token.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(expires_at.timestamp() as u64, token.expires_at);
+    assert_eq!(api_token::purge_expired(&pool).await.unwrap(), 0);
+    assert!(api_token::is_revoked(&pool, &token.id).await.unwrap());
+}
+
+#[sqlx::test]
+async fn tokens_of_other_signers_or_orgs_are_not_revoked(pool: PgPool) {
+    let config = keypair();
+    let admin = keypair();
+    let forged = token(&keypair(), in_hours(1));
+    let other_org = OrgTokenV1 {
+        oui: 2,
+        ..token(&config, in_hours(1))
+    };
+    let service = service(pool.clone(), config, &admin);
+
+    for token in [forged, other_org] {
+        let request = revoke_request(&admin, 1, &token);
+        let status = service.revoke(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(!api_token::is_revoked(&pool, &token.id).await.unwrap());
+    }
+}
+
+#[sqlx::test]
+async fn expired_revocations_are_purged(pool: PgPool) {
+    let expired = Utc.timestamp_opt(in_hours(-1) as i64, 0).unwrap();
+    let valid = Utc.timestamp_opt(in_hours(1) as i64, 0).unwrap();
+    api_token::revoke(&pool, b"expired", 1, expired)
+        .await
+        .unwrap();
+    api_token::revoke(&pool, b"valid", 1, valid).await.unwrap();
+
+    assert_eq!(api_token::purge_expired(&pool).await.unwrap(), 1);
+    assert!(!api_token::is_revoked(&pool, b"expired").await.unwrap());
+    assert!(api_token::is_revoked(&pool, b"valid").await.unwrap());
+}
+
+#[sqlx::test]
+async fn status_is_only_served_to_known_signers(pool: PgPool) {
+    let config = keypair();
+    let config_pubkey = config.public_key().clone();
+    let admin = keypair();
+    let token = token(&config, in_hours(1));
+    let service = service(pool.clone(), config, &admin);
+
+    let status = service
+        .status(status_request(&keypair(), &token.id))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+    let response = service
+        .status(status_request(&admin, &token.id))
+        .await
+        .unwrap();
+    assert!(!response.revoked);
+
+    service
+        .revoke(revoke_request(&admin, 1, &token))
+        .await
+        .unwrap();
+    let response = service
+        .status(status_request(&admin, &token.id))
+        .await
+        .unwrap();
+    assert!(response.revoked);
+    assert!(response.verify(&config_pubkey).is_ok());
+}
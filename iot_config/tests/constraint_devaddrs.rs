@@ -0,0 +1,130 @@
+use sqlx::PgPool;
+
+/// Net id of the helium type 0 devaddrs
+const HELIUM_NET_ID: i32 = 0x00003c;
+const ROAMER_NET_ID: i32 = 0x000024;
+const START_ADDR: i32 = 0x78000000;
+
+async fn create_org(pool: &PgPool) -> i64 {
+    sqlx::query_scalar(
+        r#"
+        insert into organizations (owner_pubkey, payer_pubkey)
+        values ('owner', 'payer') returning oui
+        "#,
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+async fn insert_constraint(pool: &PgPool, oui: i64, net_id: i32, start: i32, end: i32) {
+    sqlx::query(
+        r#"
+        insert into organization_devaddr_constraints (oui, net_id, start_addr, end_addr)
+        values ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(oui)
+    .bind(net_id)
+    .bind(start)
+    .bind(end)
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+async fn used_devaddrs(pool: &PgPool) -> Vec<i32> {
+    sqlx::query_scalar("select devaddr from helium_used_devaddrs order by devaddr")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test]
+async fn inserted_helium_constraints_claim_their_devaddrs(pool: PgPool) {
+    let oui = create_org(&pool).await;
+    insert_constraint(&pool, oui, HELIUM_NET_ID, START_ADDR, START_ADDR + 7).await;
+    assert_eq!(
+        used_devaddrs(&pool).await,
+        (START_ADDR..=START_ADDR + 7).collect::<Vec<_>>()
+    );
+
+    // Devaddrs claimed before the constraint is inserted are kept
+    sqlx::query("insert into helium_used_devaddrs (devaddr, net_id) values ($1, $2)")
+        .bind(START_ADDR + 8)
+        .bind(HELIUM_NET_ID)
+        .execute(&pool)
+        .await
+        .unwrap();
+    insert_constraint(&pool, oui, HELIUM_NET_ID, START_ADDR + 8, START_ADDR + 15).await;
+    assert_eq!(used_devaddrs(&pool).await.len(), 16);
+
+    // Roamer constraints do not use the helium devaddrs
+    let roamer = create_org(&pool).await;
+    insert_constraint(&pool, roamer, ROAMER_NET_ID, 0x48000000, 0x48000007).await;
+    assert_eq!(used_devaddrs(&pool).await.len(), 16);
+}
+
+#[sqlx::test]
+async fn updated_constraints_move_their_devaddrs(pool: PgPool) {
+    let oui = create_org(&pool).await;
+    insert_constraint(&pool, oui, HELIUM_NET_ID, START_ADDR, START_ADDR + 7).await;
+
+    sqlx::query("update organization_devaddr_constraints set end_addr = $1 where oui = $2")
+        .bind(START_ADDR + 3)
+        .bind(oui)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        used_devaddrs(&pool).await,
+        (START_ADDR..=START_ADDR + 3).collect::<Vec<_>>()
+    );
+
+    sqlx::query(
+        "update organization_devaddr_constraints set start_addr = $1, end_addr = $2 where oui = $3",
+    )
+    .bind(START_ADDR + 2)
+    .bind(START_ADDR + 5)
+    .bind(oui)
+    .execute(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        used_devaddrs(&pool).await,
+        (START_ADDR + 2..=START_ADDR + 5).collect::<Vec<_>>()
+    );
+}
+
+#[sqlx::test]
+async fn deleted_constraints_release_their_devaddrs(pool: PgPool) {
+    let kept = create_org(&pool).await;
+    insert_constraint(&pool, kept, HELIUM_NET_ID, START_ADDR, START_ADDR + 7).await;
+    let deleted = create_org(&pool).await;
+    insert_constraint(
+        &pool,
+        deleted,
+        HELIUM_NET_ID,
+        START_ADDR + 8,
+        START_ADDR + 15,
+    )
+    .await;
+
+    // The constraints of a deleted org are cascaded
+    sqlx::query("delete from organizations where oui = $1")
+        .bind(deleted)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        used_devaddrs(&pool).await,
+        (START_ADDR..=START_ADDR + 7).collect::<Vec<_>>()
+    );
+
+    sqlx::query("delete from organization_devaddr_constraints where oui = $1")
+        .bind(kept)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(used_devaddrs(&pool).await.is_empty());
+}
@@ -0,0 +1,104 @@
+use helium_crypto::{KeyTag, KeyType, Keypair, Network, PublicKeyBinary};
+use iot_config::{
+    org,
+    org_review::{self, OrgReviewStateV1, ReviewError},
+};
+use rand::rngs::OsRng;
+use sqlx::PgPool;
+
+async fn create_org(pool: &PgPool, review_state: OrgReviewStateV1) -> u64 {
+    let keypair = Keypair::generate(
+        KeyTag {
+            network: Network::MainNet,
+            key_type: KeyType::Ed25519,
+        },
+        &mut OsRng,
+    );
+    let owner = PublicKeyBinary::from(keypair.public_key().to_vec()).to_string();
+    let oui: i64 = sqlx::query_scalar(
+        r#"
+        insert into organizations (owner_pubkey, payer_pubkey, review_state)
+        values ($1, $1, $2) returning oui
+        "#,
+    )
+    .bind(owner)
+    .bind(review_state)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    oui as u64
+}
+
+async fn set_state(pool: &PgPool, oui: u64, next: OrgReviewStateV1) -> (OrgReviewStateV1, bool) {
+    org_review::set_state(oui, next, pool).await.unwrap()
+}
+
+async fn is_locked(pool: &PgPool, oui: u64) -> bool {
+    org::is_locked(oui, pool).await.unwrap()
+}
+
+#[sqlx::test]
+async fn only_approved_orgs_pass(pool: PgPool) {
+    let oui = create_org(&pool, OrgReviewStateV1::Pending).await;
+    let status = org_review::ensure_approved(oui, &pool).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+    assert_eq!(
+        set_state(&pool, oui, OrgReviewStateV1::Approved).await,
+        (OrgReviewStateV1::Pending, false)
+    );
+    org_review::ensure_approved(oui, &pool).await.unwrap();
+    assert!(matches!(
+        org_review::set_state(oui, OrgReviewStateV1::Pending, &pool).await,
+        Err(ReviewError::Transition { .. })
+    ));
+
+    let status = org_review::ensure_approved(oui + 1, &pool)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
+#[sqlx::test]
+async fn approval_lifts_only_the_lock_of_the_suspension(pool: PgPool) {
+    let oui = create_org(&pool, OrgReviewStateV1::Approved).await;
+    assert_eq!(
+        set_state(&pool, oui, OrgReviewStateV1::Suspended).await,
+        (OrgReviewStateV1::Approved, true)
+    );
+    assert!(is_locked(&pool, oui).await);
+    assert_eq!(
+        set_state(&pool, oui, OrgReviewStateV1::Approved).await,
+        (OrgReviewStateV1::Suspended, true)
+    );
+    assert!(!is_locked(&pool, oui).await);
+
+    // Locked for its balance before the suspension
+    org::set_locked(oui, true, &pool).await.unwrap();
+    assert_eq!(
+        set_state(&pool, oui, OrgReviewStateV1::Suspended).await,
+        (OrgReviewStateV1::Approved, false)
+    );
+    assert_eq!(
+        set_state(&pool, oui, OrgReviewStateV1::Approved).await,
+        (OrgReviewStateV1::Suspended, false)
+    );
+    assert!(is_locked(&pool, oui).await);
+
+    // Locked for its balance during the suspension
+    org::set_locked(oui, false, &pool).await.unwrap();
+    set_state(&pool, oui, OrgReviewStateV1::Suspended).await;
+    assert_eq!(org::set_locked(oui, true, &pool).await.unwrap(), Some(true));
+    assert_eq!(
+        set_state(&pool, oui, OrgReviewStateV1::Approved).await,
+        (OrgReviewStateV1::Suspended, false)
+    );
+    assert!(is_locked(&pool, oui).await);
+
+    // Disabled during the suspension
+    org::set_locked(oui, false, &pool).await.unwrap();
+    set_state(&pool, oui, OrgReviewStateV1::Suspended).await;
+    org_review::hold_lock(oui, &pool).await.unwrap();
+    set_state(&pool, oui, OrgReviewStateV1::Approved).await;
+    assert!(is_locked(&pool, oui).await);
+}
@@ -0,0 +1,125 @@
+use file_store::{file_sink::FileSinkBuilder, file_source, FileType};
+use futures::TryStreamExt;
+use helium_crypto::{KeyTag, KeyType, Keypair, Network, PublicKeyBinary};
+use iot_config::{
+    lora_field,
+    route::{self, Protocol, Route, RouteServer},
+    route_snapshot::{route_snapshot_entry_v1::Entry, RouteSnapshotEntryV1, RouteSnapshotExporter},
+};
+use prost::Message;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::time::Duration;
+use tempfile::TempDir;
+
+async fn create_org(pool: &PgPool) -> u64 {
+    let keypair = Keypair::generate(
+        KeyTag {
+            network: Network::MainNet,
+            key_type: KeyType::Ed25519,
+        },
+        &mut OsRng,
+    );
+    let owner = PublicKeyBinary::from(keypair.public_key().to_vec()).to_string();
+    let oui: i64 = sqlx::query_scalar(
+        "insert into organizations (owner_pubkey, payer_pubkey) values ($1, $1) returning oui",
+    )
+    .bind(owner)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    oui as u64
+}
+
+async fn create_route(pool: &PgPool, oui: u64) -> String {
+    let mut route = Route::new(lora_field::net_id(0xC00053), oui, 1);
+    route.set_server(RouteServer::new(
+        "router.example".to_string(),
+        8080,
+        Protocol::default_packet_router(),
+    ));
+    let (update_tx, _update_rx) = tokio::sync::broadcast::channel(10);
+    let signing_key = Keypair::generate(
+        KeyTag {
+            network: Network::MainNet,
+            key_type: KeyType::Ed25519,
+        },
+        &mut OsRng,
+    );
+    route::create_route(route, pool, &signing_key, update_tx)
+        .await
+        .unwrap()
+        .id
+}
+
+async fn exporter(pool: &PgPool, tmp_dir: &TempDir) -> RouteSnapshotExporter {
+    let (_trigger, shutdown) = triggered::trigger();
+    let (snapshot_sink, mut snapshot_sink_server) = FileSinkBuilder::new(
+        FileType::IotConfigRouteSnapshot,
+        tmp_dir.path(),
+        "route_snapshot",
+        shutdown,
+    )
+    .auto_commit(false)
+    .create()
+    .await
+    .unwrap();
+    tokio::spawn(async move { snapshot_sink_server.run().await });
+    RouteSnapshotExporter::new(pool.clone(), snapshot_sink, Duration::from_secs(60)).0
+}
+
+#[sqlx::test]
+async fn snapshot_holds_the_routing_state_matching_its_checksum(pool: PgPool) {
+    let oui = create_org(&pool).await;
+    let route_id = create_route(&pool, oui).await;
+    for dev_eui in [1_i64, 2] {
+        sqlx::query(
+            "insert into route_eui_pairs (route_id, app_eui, dev_eui) values ($1::uuid, 1, $2)",
+        )
+        .bind(&route_id)
+        .bind(dev_eui)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+    let tmp_dir = TempDir::new().unwrap();
+    let exporter = exporter(&pool, &tmp_dir).await;
+
+    let snapshot = exporter.export().await.unwrap().expect("snapshot exported");
+    assert_eq!(snapshot.entries, 4);
+    assert_eq!(snapshot.keys.len(), 1);
+
+    let entries: Vec<RouteSnapshotEntryV1> =
+        file_source::source([tmp_dir.path().join(&snapshot.keys[0])])
+            .map_ok(|buf| RouteSnapshotEntryV1::decode(buf).unwrap())
+            .try_collect()
+            .await
+            .unwrap();
+    let kinds: Vec<&str> = entries
+        .iter()
+        .map(|entry| match entry.entry {
+            Some(Entry::Org(_)) => "org",
+            Some(Entry::Route(_)) => "route",
+            Some(Entry::EuiPair(_)) => "eui",
+            Some(Entry::DevaddrRange(_)) => "devaddr",
+            Some(Entry::Skf(_)) => "skf",
+            None => "none",
+        })
+        .collect();
+    assert_eq!(kinds, vec!["org", "route", "eui", "eui"]);
+
+    let mut hasher = Sha256::new();
+    for entry in &entries {
+        hasher.update(entry.encode_to_vec());
+    }
+    assert_eq!(hasher.finalize().to_vec(), snapshot.sha256);
+}
+
+#[sqlx::test]
+async fn nothing_is_exported_without_orgs(pool: PgPool) {
+    let tmp_dir = TempDir::new().unwrap();
+    let exporter = exporter(&pool, &tmp_dir).await;
+
+    assert!(exporter.export().await.unwrap().is_none());
+}
@@ -0,0 +1,2 @@
+CREATE TABLE burn_journal_archive (LIKE burn_journal);
+CREATE TABLE packet_stats_archive (LIKE packet_stats);
@@ -0,0 +1,9 @@
+CREATE TABLE burn_journal (
+       signature TEXT PRIMARY KEY,
+       payer TEXT NOT NULL,
+       amount BIGINT NOT NULL,
+       burned_at TIMESTAMPTZ NOT NULL,
+       confirmed_at TIMESTAMPTZ
+);
+
+CREATE INDEX burn_journal_burned_at_idx ON burn_journal (burned_at);
@@ -0,0 +1,8 @@
+CREATE TABLE packet_stats (
+       oui BIGINT NOT NULL,
+       hour TIMESTAMPTZ NOT NULL,
+       outcome TEXT NOT NULL,
+       packets BIGINT NOT NULL,
+       dcs BIGINT NOT NULL,
+       PRIMARY KEY (oui, hour, outcome)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE org_lock_transitions (
+       oui BIGINT NOT NULL,
+       state TEXT NOT NULL,
+       balance BIGINT,
+       failures INTEGER NOT NULL,
+       recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX org_lock_transitions_oui_idx ON org_lock_transitions (oui, recorded_at);
@@ -0,0 +1,8 @@
+CREATE TABLE org_intents (
+       oui BIGINT PRIMARY KEY,
+       enabled BOOLEAN NOT NULL,
+       recorded_at TIMESTAMPTZ NOT NULL,
+       sent_at TIMESTAMPTZ
+);
+
+CREATE INDEX org_intents_unsent_idx ON org_intents (recorded_at) WHERE sent_at IS NULL;
@@ -0,0 +1,6 @@
+CREATE TABLE packets_seen (
+       id BYTEA PRIMARY KEY,
+       seen_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX packets_seen_seen_at_idx ON packets_seen (seen_at);
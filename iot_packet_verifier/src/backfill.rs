@@ -0,0 +1,371 @@
+//! Recovery of the pending burns from the verified packet files.
+//!
+//! The valid packets written for a range of time are replayed and matched to
+//! the packet reports they were verified from, which carry the oui, to total
+//! the data credits charged per payer. The journaled burns of the range that
+//! were successful on chain are subtracted, leaving what is still owed.
+//!
+//! The range should start when nothing was owed, for example right after a
+//! burn of every payer, as burns in the range are taken to pay for packets in
+//! the range. Without `--commit` the owed amounts are only reported.
+
+use crate::{reconcile::JournaledBurn, settings::Settings};
+use anyhow::{bail, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use file_store::{
+    iot_packet::PacketRouterPacketReport,
+    traits::{MsgDecode, MsgTimestamp},
+    FileStore, FileType,
+};
+use futures::{Stream, StreamExt, TryStreamExt};
+use helium_crypto::PublicKeyBinary;
+use helium_proto::services::packet_verifier::ValidPacket;
+use iot_config::client::OrgClient;
+use prost::Message;
+use serde::Serialize;
+use solana::{SolanaBackend, SolanaNetwork};
+use sqlx::{Postgres, Transaction};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Packet reports are written before the packets verified from them, so
+/// reports are read from this many minutes before the range
+const REPORT_LOOKBACK_MINUTES: i64 = 60;
+
+/// Milliseconds the reports and the valid packets may be out of order, the
+/// reports are kept this long around the packets being matched
+const MATCH_WINDOW_MILLIS: u64 = 10 * 60 * 1000;
+
+/// Identity of a verified packet, the gateway, payload hash and millisecond
+/// the packet router received it
+type PacketKey = (Vec<u8>, Vec<u8>, u64);
+
+/// Data credits charged per oui, from valid packets matched to their reports
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Charges {
+    pub by_oui: HashMap<u64, u64>,
+    /// Valid packets without a report and the data credits charged for them
+    pub unmatched: (usize, u64),
+}
+
+/// Ouis of the packet reports received around the valid packets being
+/// matched
+#[derive(Default)]
+struct ReportWindow {
+    ouis: HashMap<PacketKey, u64>,
+    by_timestamp: BTreeMap<u64, Vec<PacketKey>>,
+    latest: u64,
+}
+
+impl ReportWindow {
+    fn insert(&mut self, report: &PacketRouterPacketReport) {
+        let timestamp = report.timestamp();
+        let key = (
+            report.gateway.as_ref().to_vec(),
+            report.payload_hash.clone(),
+            timestamp,
+        );
+        self.ouis.insert(key.clone(), report.oui);
+        self.by_timestamp.entry(timestamp).or_default().push(key);
+        self.latest = self.latest.max(timestamp);
+    }
+
+    /// Forget the reports received before the timestamp
+    fn evict_before(&mut self, timestamp: u64) {
+        let kept = self.by_timestamp.split_off(&timestamp);
+        for key in std::mem::replace(&mut self.by_timestamp, kept)
+            .into_values()
+            .flatten()
+        {
+            self.ouis.remove(&key);
+        }
+    }
+}
+
+/// Charge the valid packets to the oui of the reports they were verified
+/// from. Both are streamed in the order of their files, only the reports
+/// within [`MATCH_WINDOW_MILLIS`] of the packets are held in memory
+pub async fn charge<E>(
+    valid_packets: impl Stream<Item = Result<ValidPacket, E>>,
+    reports: impl Stream<Item = Result<PacketRouterPacketReport, E>>,
+) -> Result<Charges, E> {
+    tokio::pin!(valid_packets);
+    tokio::pin!(reports);
+    let mut window = ReportWindow::default();
+    let mut reports_done = false;
+    let mut charges = Charges::default();
+    while let Some(packet) = valid_packets.try_next().await? {
+        let timestamp = packet.packet_timestamp;
+        while !reports_done && window.latest <= timestamp + MATCH_WINDOW_MILLIS {
+            match reports.try_next().await? {
+                Some(report) => window.insert(&report),
+                None => reports_done = true,
+            }
+        }
+        let key = (packet.gateway, packet.payload_hash, timestamp);
+        match window.ouis.get(&key) {
+            Some(oui) => *charges.by_oui.entry(*oui).or_default() += packet.num_dcs as u64,
+            None => {
+                charges.unmatched.0 += 1;
+                charges.unmatched.1 += packet.num_dcs as u64;
+            }
+        }
+        window.evict_before(timestamp.saturating_sub(MATCH_WINDOW_MILLIS));
+    }
+    Ok(charges)
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct OwedBurn {
+    pub payer: String,
+    pub charged: u64,
+    pub burned: u64,
+    pub owed: u64,
+}
+
+/// What each payer owes given its charges and burns. Payers having burned
+/// more than charged owe nothing
+pub fn owed(
+    charged: &HashMap<PublicKeyBinary, u64>,
+    burned: &HashMap<String, u64>,
+) -> Vec<OwedBurn> {
+    let mut owed: Vec<OwedBurn> = charged
+        .iter()
+        .map(|(payer, charged)| {
+            let payer = payer.to_string();
+            let burned = burned.get(&payer).copied().unwrap_or_default();
+            OwedBurn {
+                charged: *charged,
+                burned,
+                owed: charged.saturating_sub(burned),
+                payer,
+            }
+        })
+        .collect();
+    owed.sort_by(|a, b| a.payer.cmp(&b.payer));
+    owed
+}
+
+/// Journaled burns successful on chain, totalled per payer
+pub fn confirmed_burns(
+    journal: &[JournaledBurn],
+    successful: &HashSet<String>,
+) -> HashMap<String, u64> {
+    let mut burned = HashMap::<String, u64>::new();
+    for burn in journal {
+        if burn.confirmed_at.is_some() || successful.contains(&burn.signature) {
+            *burned.entry(burn.payer.to_string()).or_default() += burn.amount as u64;
+        }
+    }
+    burned
+}
+
+/// Replace the pending burns with the owed amounts. Payers without charges
+/// in the range owe nothing, their pending amounts are zeroed
+pub async fn write_pending_burns(
+    transaction: &mut Transaction<'_, Postgres>,
+    owed: &[OwedBurn],
+) -> Result<(), sqlx::Error> {
+    let payers: Vec<&str> = owed.iter().map(|burn| burn.payer.as_str()).collect();
+    sqlx::query("UPDATE pending_burns SET amount = 0 WHERE payer <> ALL($1)")
+        .bind(&payers)
+        .execute(&mut *transaction)
+        .await?;
+    for burn in owed {
+        sqlx::query(
+            r#"
+            INSERT INTO pending_burns (payer, amount, last_burn)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (payer) DO UPDATE SET
+            amount = EXCLUDED.amount
+            "#,
+        )
+        .bind(&burn.payer)
+        .bind(burn.owed as i64)
+        .bind(Utc::now().naive_utc())
+        .execute(&mut *transaction)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Recompute the pending burns from the valid packet files of a range
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    #[clap(long)]
+    start: NaiveDateTime,
+    #[clap(long)]
+    end: NaiveDateTime,
+    /// Write the owed amounts to pending_burns, zeroing the payers without
+    /// charges in the range. Only reported by default
+    #[clap(long)]
+    commit: bool,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let start = DateTime::from_utc(self.start, Utc);
+        let end = DateTime::from_utc(self.end, Utc);
+        if end <= start {
+            bail!("end must be after start");
+        }
+        tracing::info!("Recovering pending burns from {start} to {end}");
+
+        let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        let (pool, _join_handle) = settings
+            .database
+            .connect(env!("CARGO_PKG_NAME"), shutdown_listener)
+            .await?;
+
+        let output = FileStore::from_settings(&settings.output).await?;
+        let valid_packets = output
+            .source(output.list(FileType::IotValidPacket, start, end))
+            .map(|buf| -> Result<_> { Ok(ValidPacket::decode(buf?)?) });
+        let ingest = FileStore::from_settings(&settings.ingest).await?;
+        let report_start = start - chrono::Duration::minutes(REPORT_LOOKBACK_MINUTES);
+        let reports = ingest
+            .source(ingest.list(FileType::IotPacketReport, report_start, end))
+            .map(|buf| -> Result<_> { Ok(PacketRouterPacketReport::decode(buf?)?) });
+        let charges = charge(valid_packets, reports).await?;
+        let (unmatched, unmatched_dcs) = charges.unmatched;
+        if unmatched > 0 {
+            tracing::warn!(
+                packets = unmatched,
+                dcs = unmatched_dcs,
+                "valid packets without a packet report are not owed"
+            );
+        }
+
+        let mut org_client = OrgClient::from_settings(&settings.iot_config_client)?;
+        let mut charged = HashMap::<PublicKeyBinary, u64>::new();
+        for (oui, dcs) in &charges.by_oui {
+            let payer = org_client
+                .get(*oui)
+                .await?
+                .org
+                .map(|org| PublicKeyBinary::from(org.payer));
+            match payer {
+                Some(payer) => *charged.entry(payer).or_default() += dcs,
+                None => bail!("unknown oui {oui}"),
+            }
+        }
+
+        let journal: Vec<JournaledBurn> =
+            sqlx::query_as("SELECT * FROM burn_journal WHERE burned_at >= $1 AND burned_at < $2")
+                .bind(start)
+                .bind(end)
+                .fetch_all(&pool)
+                .await?;
+        let solana = SolanaBackend::new(
+            settings.enable_solana_integration,
+            settings.solana.as_ref(),
+            settings.mock_solana.as_ref(),
+        )
+        .await?;
+        let successful: HashSet<String> = solana
+            .burn_history(start.timestamp())
+            .await?
+            .into_iter()
+            .filter(|burn| !burn.failed)
+            .map(|burn| burn.signature)
+            .collect();
+        let owed = owed(&charged, &confirmed_burns(&journal, &successful));
+
+        println!("{}", serde_json::to_string_pretty(&owed)?);
+        if self.commit {
+            let mut transaction = pool.begin().await?;
+            write_pending_burns(&mut transaction, &owed).await?;
+            transaction.commit().await?;
+            tracing::info!(payers = owed.len(), "wrote recovered pending burns");
+        } else {
+            tracing::info!("dry run, pass --commit to write the pending burns");
+        }
+
+        shutdown_trigger.trigger();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn report(oui: u64, gateway: u8, hash: u8) -> PacketRouterPacketReport {
+        PacketRouterPacketReport {
+            oui,
+            net_id: 0,
+            rssi: 0,
+            frequency: 0,
+            snr: 0.0,
+            data_rate: Default::default(),
+            region: Default::default(),
+            gateway: PublicKeyBinary::from(vec![gateway]),
+            payload_hash: vec![hash],
+            payload_size: 24,
+            received_timestamp: Utc.timestamp_opt(1, 0).unwrap(),
+        }
+    }
+
+    fn valid_packet(report: &PacketRouterPacketReport, num_dcs: u32) -> ValidPacket {
+        ValidPacket {
+            packet_timestamp: report.timestamp(),
+            payload_size: report.payload_size,
+            gateway: report.gateway.as_ref().to_vec(),
+            payload_hash: report.payload_hash.clone(),
+            num_dcs,
+        }
+    }
+
+    #[tokio::test]
+    async fn valid_packets_are_charged_to_the_oui_of_their_report() {
+        let charged = [report(1, 1, 1), report(1, 2, 2), report(2, 1, 3)];
+        let valid_packets = charged
+            .iter()
+            .map(|report| valid_packet(report, 2))
+            .chain([valid_packet(&report(3, 9, 9), 5)]);
+        let reports = charged.iter().cloned().chain([report(1, 1, 4)]);
+
+        let charges = charge(
+            futures::stream::iter(valid_packets.map(Ok::<_, ()>)),
+            futures::stream::iter(reports.map(Ok)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            charges,
+            Charges {
+                by_oui: HashMap::from([(1, 4), (2, 2)]),
+                unmatched: (1, 5),
+            }
+        );
+    }
+
+    #[test]
+    fn confirmed_burns_are_subtracted_from_charges() {
+        let payer = PublicKeyBinary::from(vec![1]);
+        let journaled = |signature: &str, amount, confirmed: bool| JournaledBurn {
+            signature: signature.to_string(),
+            payer: payer.clone(),
+            amount,
+            burned_at: Utc.timestamp_opt(1, 0).unwrap(),
+            confirmed_at: confirmed.then(Utc::now),
+        };
+        let journal = [
+            journaled("confirmed", 10, true),
+            journaled("on-chain", 20, false),
+            journaled("missing", 40, false),
+        ];
+        let burned = confirmed_burns(&journal, &HashSet::from(["on-chain".to_string()]));
+
+        let owed = owed(&HashMap::from([(payer.clone(), 100)]), &burned);
+        assert_eq!(
+            owed,
+            vec![OwedBurn {
+                payer: payer.to_string(),
+                charged: 100,
+                burned: 30,
+                owed: 70,
+            }]
+        );
+    }
+}
@@ -1,63 +1,58 @@
 use crate::{
     pending_burns::{Burn, PendingBurns},
-    verifier::Debiter,
+    verifier::{BalanceStore, Debiter},
 };
-use futures_util::StreamExt;
+use futures_util::TryStreamExt;
 use helium_crypto::PublicKeyBinary;
 use solana::SolanaNetwork;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::Mutex;
 
-/// Caches balances fetched from the solana chain and debits made by the
-/// packet verifier.
-pub struct BalanceCache<S> {
-    balances: BalanceStore,
-    solana: S,
-}
-
-pub type BalanceStore = Arc<Mutex<HashMap<PublicKeyBinary, Balance>>>;
+pub use balance_cache::{Balance, BalanceCache};
 
-impl<S> BalanceCache<S>
+/// Fetch all of the current balances that have been actively burned so that
+/// we have an accurate cache. The debits still pending from before a restart
+/// are taken off the chain balances, so they can not be spent again before
+/// the burner catches up.
+pub async fn load<P, S>(pending_burns: &mut P, solana: S) -> anyhow::Result<BalanceCache<S>>
 where
+    P: PendingBurns,
     S: SolanaNetwork,
 {
-    /// Fetch all of the current balances that have been actively burned so that
-    /// we have an accurate cache.
-    pub async fn new<P>(pending_burns: &mut P, solana: S) -> anyhow::Result<Self>
-    where
-        P: PendingBurns,
-    {
-        let mut balances = HashMap::new();
-        let mut burns = pending_burns.fetch_all().await;
-
-        while let Some(Burn {
-            payer,
-            amount: burn_amount,
-            ..
-        }) = burns.next().await.transpose()?
-        {
-            // Look up the current balance of the payer
-            let balance = solana.payer_balance(&payer).await?;
-            balances.insert(
-                payer,
-                Balance {
-                    burned: burn_amount as u64,
-                    balance,
-                },
-            );
-        }
-
-        Ok(Self {
-            balances: Arc::new(Mutex::new(balances)),
-            solana,
-        })
-    }
+    // More can have been burned than was pending when a burn was retried,
+    // which leaves nothing pending rather than a wrapped around amount
+    let burns: Vec<_> = pending_burns
+        .fetch_all()
+        .await
+        .map_ok(|Burn { payer, amount }| (payer, amount.max(0) as u64))
+        .try_collect()
+        .await?;
+    let payers = burns.len();
+    let pending: u64 = burns.iter().map(|(_, amount)| amount).sum();
+    let cache = BalanceCache::with_pending_burns(solana, burns).await?;
+    tracing::info!(payers, pending, "settled balances with the pending burns");
+    Ok(cache)
 }
 
-impl<S> BalanceCache<S> {
-    pub fn balances(&self) -> BalanceStore {
-        self.balances.clone()
+/// Cap the available balances of the cache by the chain balance minus the
+/// pending burns, so a failing burner can not let the verifier debit more than
+/// is actually escrowed. Done before verifying every file.
+pub async fn cap_by_pending_burns<P, S>(
+    pending_burns: &mut P,
+    balances: &BalanceCache<S>,
+) -> anyhow::Result<()>
+where
+    P: PendingBurns,
+{
+    let burns: Vec<_> = pending_burns
+        .fetch_all()
+        .await
+        .map_ok(|Burn { payer, amount }| (payer, amount.max(0) as u64))
+        .try_collect()
+        .await?;
+    let corrected = balances.cap_by_pending_burns(burns).await;
+    if corrected > 0 {
+        metrics::counter!("balance_cache_corrections", corrected as u64);
     }
+    Ok(())
 }
 
 #[async_trait::async_trait]
@@ -74,40 +69,16 @@ where
         payer: &PublicKeyBinary,
         amount: u64,
     ) -> Result<Option<u64>, S::Error> {
-        let mut balances = self.balances.lock().await;
-
-        let balance = if !balances.contains_key(payer) {
-            let new_balance = self.solana.payer_balance(payer).await?;
-            balances.insert(payer.clone(), Balance::new(new_balance));
-            balances.get_mut(payer).unwrap()
-        } else {
-            let balance = balances.get_mut(payer).unwrap();
-
-            // If the balance is not sufficient, check to see if it has been increased
-            if balance.balance < amount + balance.burned {
-                balance.balance = self.solana.payer_balance(payer).await?;
-            }
-
-            balance
-        };
-
-        Ok(if balance.balance >= amount + balance.burned {
-            balance.burned += amount;
-            Some(balance.balance - balance.burned)
-        } else {
-            None
-        })
+        BalanceCache::debit_if_sufficient(self, payer, amount).await
     }
 }
 
-#[derive(Copy, Clone, Debug, Default)]
-pub struct Balance {
-    pub balance: u64,
-    pub burned: u64,
-}
-
-impl Balance {
-    pub fn new(balance: u64) -> Self {
-        Self { balance, burned: 0 }
+#[async_trait::async_trait]
+impl<S> BalanceStore for BalanceCache<S>
+where
+    S: Send + Sync + 'static,
+{
+    async fn set_balance(&self, payer: &PublicKeyBinary, balance: u64) {
+        BalanceCache::set_balance(self, payer, balance).await
     }
 }
@@ -1,15 +1,20 @@
 use crate::{
-    balances::{BalanceCache, BalanceStore},
+    balances::BalanceCache,
     pending_burns::{Burn, PendingBurns},
+    reconcile,
+    settings::LiveSettings,
 };
 use solana::SolanaNetwork;
+use sqlx::{Pool, Postgres};
 use std::time::Duration;
-use tokio::task;
+use tokio::{sync::watch, task};
 
 pub struct Burner<P, S> {
     pending_burns: P,
-    balances: BalanceStore,
+    balances: BalanceCache<S>,
     burn_period: Duration,
+    live_settings: Option<watch::Receiver<LiveSettings>>,
+    journal: Option<Pool<Postgres>>,
     solana: S,
 }
 
@@ -27,11 +32,37 @@ impl<P, S> Burner<P, S> {
     pub fn new(pending_burns: P, balances: &BalanceCache<S>, burn_period: u64, solana: S) -> Self {
         Self {
             pending_burns,
-            balances: balances.balances(),
+            balances: balances.clone(),
             burn_period: Duration::from_secs(60 * burn_period),
+            live_settings: None,
+            journal: None,
             solana,
         }
     }
+
+    /// Take the burn period from the reloaded settings instead
+    pub fn live_settings(self, live_settings: watch::Receiver<LiveSettings>) -> Self {
+        Self {
+            live_settings: Some(live_settings),
+            ..self
+        }
+    }
+
+    /// Record the signature of every burn transaction in the burn journal so
+    /// it can be reconciled against the chain
+    pub fn journal(self, pool: Pool<Postgres>) -> Self {
+        Self {
+            journal: Some(pool),
+            ..self
+        }
+    }
+
+    fn burn_period(&self) -> Duration {
+        self.live_settings
+            .as_ref()
+            .map(|live| Duration::from_secs(60 * live.borrow().burn_period))
+            .unwrap_or(self.burn_period)
+    }
 }
 
 impl<P, S> Burner<P, S>
@@ -48,7 +79,7 @@ where
                 if let Err(e) = self.burn().await {
                     tracing::error!("Failed to burn: {e:?}");
                 }
-                tokio::time::sleep(self.burn_period).await;
+                tokio::time::sleep(self.burn_period()).await;
             }
         });
 
@@ -61,8 +92,12 @@ where
     pub async fn burn(&mut self) -> Result<(), BurnError<P::Error, S::Error>> {
         // Create burn transaction and execute it:
 
-        let Some(Burn { payer, amount }) = self.pending_burns.fetch_next().await
-            .map_err(BurnError::SqlError)? else {
+        let Some(Burn { payer, amount }) = self
+            .pending_burns
+            .fetch_next()
+            .await
+            .map_err(BurnError::SqlError)?
+        else {
             return Ok(());
         };
 
@@ -70,11 +105,20 @@ where
 
         let amount = amount as u64;
 
-        self.solana
+        let signature = self
+            .solana
             .burn_data_credits(&payer, amount)
             .await
             .map_err(BurnError::SolanaError)?;
 
+        // The burn went through, failing to journal it must not lead to it
+        // being burned twice. The reconciler reports it as unknown instead.
+        if let (Some(pool), Some(signature)) = (&self.journal, &signature) {
+            if let Err(err) = reconcile::record_burn(pool, signature, &payer, amount).await {
+                tracing::error!(%signature, %payer, "failed to journal burn: {err}");
+            }
+        }
+
         // Now that we have successfully executed the burn and are no long in
         // sync land, we can remove the amount burned.
         self.pending_burns
@@ -82,11 +126,8 @@ where
             .await
             .map_err(BurnError::SqlError)?;
 
-        let mut balance_lock = self.balances.lock().await;
-        let balances = balance_lock.get_mut(&payer).unwrap();
-        balances.burned -= amount;
-        // Zero the balance in order to force a reset:
-        balances.balance = 0;
+        // Zeroes the balance in order to force a reset:
+        self.balances.settle_burn(&payer, amount).await;
 
         metrics::counter!("burned", amount, "payer" => payer.to_string());
 
@@ -1,33 +1,56 @@
 use crate::{
-    balances::BalanceCache,
+    balances::{self, BalanceCache},
     burner::Burner,
-    settings::Settings,
-    verifier::{ConfigServer, Verifier},
+    org_events::OrgEvents,
+    org_intents::IntentReconciler,
+    org_lock::OrgLocks,
+    packets_seen::Deduper,
+    reconcile::Reconciler,
+    settings::{LiveSettings, Settings},
+    stats_service::StatsService,
+    top_ups::TopUpNotifier,
+    verifier::{self, ConfigServer, Verifier},
 };
-use anyhow::{bail, Error, Result};
+use anyhow::{bail, Result};
+use custom_tracing::{admin::LogFilterService, correlation};
+use db_store::{maintenance::Table, LeaderElection, Maintenance};
 use file_store::{
     file_info_poller::{FileInfoStream, LookbackBehavior},
     file_sink::FileSinkClient,
     file_source, file_upload,
     iot_packet::PacketRouterPacketReport,
-    FileSinkBuilder, FileStore, FileType,
+    FileSinkBuilder, FileStore, FileType, SinkGroup,
 };
-use futures_util::TryFutureExt;
-use iot_config::client::OrgClient;
-use solana::SolanaRpc;
+use grpc_service::tls;
+use iot_config::client::{ApiTokenClient, OrgClient, TokenVerifier};
+use oracle_settings::SettingsWatcher;
+use solana::SolanaBackend;
 use sqlx::{Pool, Postgres};
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use task_manager::{Stage, TaskManager};
 use tokio::{
     signal,
-    sync::{mpsc::Receiver, Mutex},
+    sync::{mpsc::Receiver, watch, Mutex, Notify},
 };
 
+/// Tables purged by the maintenance. Pending burns are only purged once
+/// everything owed by the payer has been burned
+const MAINTAINED_TABLES: &[Table] = &[
+    Table::new("pending_burns", "last_burn").condition("amount = 0"),
+    Table::new("burn_journal", "burned_at").archived(),
+    Table::new("packet_stats", "hour").archived(),
+    Table::new("org_intents", "sent_at").condition("sent_at IS NOT NULL"),
+    Table::new("packets_seen", "seen_at"),
+];
+
 struct Daemon {
     pool: Pool<Postgres>,
-    verifier: Verifier<BalanceCache<Option<Arc<SolanaRpc>>>, Arc<Mutex<OrgClient>>>,
+    verifier: Verifier<BalanceCache<SolanaBackend>, Arc<Mutex<OrgClient>>, Deduper>,
     report_files: Receiver<FileInfoStream<PacketRouterPacketReport>>,
     valid_packets: FileSinkClient,
     invalid_packets: FileSinkClient,
+    /// Commits the valid and invalid packets of a report file together
+    packet_sinks: SinkGroup,
     minimum_allowed_balance: u64,
 }
 
@@ -38,7 +61,8 @@ impl Daemon {
                 _ = shutdown.clone() => break,
                 file = self.report_files.recv() => {
                     if let Some(file) = file {
-                        self.handle_file(file).await?
+                        let correlation_id = file.file_info.key.clone();
+                        correlation::scope(correlation_id, self.handle_file(file)).await?
                     } else {
                         bail!("Report file stream was dropped")
                     }
@@ -57,9 +81,11 @@ impl Daemon {
         tracing::info!(file = %report_file.file_info, "Verifying file");
 
         let mut transaction = self.pool.begin().await?;
-        let reports = report_file.into_stream(&mut transaction).await?;
+        balances::cap_by_pending_burns(&mut &mut transaction, &self.verifier.debiter).await?;
+        let (reports, completion) = report_file.into_stream(&mut transaction).await?;
 
-        self.verifier
+        let stats = self
+            .verifier
             .verify(
                 self.minimum_allowed_balance,
                 &mut transaction,
@@ -68,22 +94,54 @@ impl Daemon {
                 &self.invalid_packets,
             )
             .await?;
-        transaction.commit().await?;
-        self.valid_packets.commit().await?;
-        self.invalid_packets.commit().await?;
+        stats.save(&mut transaction).await?;
+        completion.commit(transaction).await?;
+        self.packet_sinks.commit().await?;
 
         Ok(())
     }
 }
 
+/// Replace the log filter whenever the reloaded settings change it
+async fn apply_log_filter(
+    mut live_settings: watch::Receiver<LiveSettings>,
+    shutdown: triggered::Listener,
+) -> Result<()> {
+    let mut applied = live_settings.borrow().clone();
+    loop {
+        tokio::select! {
+            _ = shutdown.clone() => return Ok(()),
+            changed = live_settings.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+                let live = live_settings.borrow().clone();
+                if live.log == applied.log && live.tracing == applied.tracing {
+                    continue;
+                }
+                match custom_tracing::set_filter(&live.log, &live.tracing) {
+                    Ok(()) => tracing::info!(log = %live.log, "updated log filter"),
+                    Err(err) => tracing::error!("failed to update log filter: {err}"),
+                }
+                applied = live;
+            }
+        }
+    }
+}
+
 #[derive(Debug, clap::Args)]
-pub struct Cmd {}
+pub struct Cmd {
+    /// Apply database migrations and exit without starting the service
+    #[clap(long)]
+    migrate_only: bool,
+}
 
 impl Cmd {
-    pub async fn run(self, settings: &Settings) -> Result<()> {
-        poc_metrics::start_metrics(&settings.metrics)?;
+    pub async fn run(self, settings: &Settings, watcher: SettingsWatcher<Settings>) -> Result<()> {
+        poc_metrics::start_metrics_with_buckets(&settings.metrics, verifier::HISTOGRAM_BUCKETS)?;
 
         let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        poc_metrics::process::start(shutdown_listener.clone());
         let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
         tokio::spawn(async move {
             tokio::select! {
@@ -91,33 +149,60 @@ impl Cmd {
                 _ = signal::ctrl_c() => shutdown_trigger.trigger(),
             }
         });
+        let mut task_manager = TaskManager::new();
 
         // Set up the postgres pool:
         let (mut pool, db_handle) = settings
             .database
-            .connect(env!("CARGO_PKG_NAME"), shutdown_listener.clone())
+            .connect(
+                env!("CARGO_PKG_NAME"),
+                task_manager.listener(Stage::Producer),
+            )
             .await?;
-        sqlx::migrate!().run(&pool).await?;
+        db_store::migrate::run(&pool, env!("CARGO_PKG_NAME"), &sqlx::migrate!()).await?;
+        if self.migrate_only {
+            return Ok(());
+        }
 
-        let solana = if settings.enable_solana_integration {
-            let Some(ref solana_settings) = settings.solana else {
-                bail!("Missing solana section in settings");
-            };
-            // Set up the solana RpcClient:
-            Some(SolanaRpc::new(solana_settings).await?)
-        } else {
-            None
-        };
+        if let Some(leader_election) = &settings.leader_election {
+            let election =
+                LeaderElection::new(pool.clone(), env!("CARGO_PKG_NAME"), leader_election);
+            if !election.wait_for_leadership(&shutdown_listener).await? {
+                return Ok(());
+            }
+            // Hold the lease until every stage has stopped
+            task_manager.add("leader_lease", Stage::Upload, |shutdown| {
+                election.maintain(shutdown)
+            });
+        }
+
+        if settings.enable_solana_integration && settings.solana.is_none() {
+            bail!("Missing solana section in settings");
+        }
+        // Set up the solana RpcClient, or the mock chain of staging:
+        let solana = SolanaBackend::new(
+            settings.enable_solana_integration,
+            settings.solana.as_ref(),
+            settings.mock_solana.as_ref(),
+        )
+        .await?;
 
         let sol_balance_monitor = solana::balance_monitor::start(
             env!("CARGO_PKG_NAME"),
-            solana.clone(),
-            shutdown_listener.clone(),
+            solana.rpc(),
+            task_manager.listener(Stage::Producer),
         )
         .await?;
 
+        let maintenance = Maintenance::new(
+            pool.clone(),
+            env!("CARGO_PKG_NAME"),
+            &settings.maintenance,
+            MAINTAINED_TABLES,
+        )?;
+
         // Set up the balance cache:
-        let balances = BalanceCache::new(&mut pool, solana.clone()).await?;
+        let balances = balances::load(&mut pool, solana.clone()).await?;
 
         // Set up the balance burner:
         let burner = Burner::new(
@@ -125,6 +210,17 @@ impl Cmd {
             &balances,
             settings.burn_period,
             solana.clone(),
+        )
+        .journal(pool.clone())
+        .live_settings(watcher.subscribe());
+
+        // Reconcile the journaled burns against the chain:
+        let reconciler = Reconciler::new(
+            pool.clone(),
+            solana.clone(),
+            settings.reconcile_period,
+            settings.reconcile_lookback,
+            std::path::Path::new(&settings.cache).join("burn_reconciliation"),
         );
 
         let (file_upload_tx, file_upload_rx) = file_upload::message_channel();
@@ -133,28 +229,74 @@ impl Cmd {
 
         let store_base_path = std::path::Path::new(&settings.cache);
 
-        // Verified packets:
-        let (valid_packets, mut valid_packets_server) = FileSinkBuilder::new(
-            FileType::IotValidPacket,
+        // Verified packets, rolled and committed together so the valid and
+        // invalid packets of a window end up in files of the same time:
+        let mut packet_sinks = SinkGroup::new(
+            "packets",
+            chrono::Duration::minutes(settings.packet_roll_minutes as i64),
+        );
+        let (valid_packets, mut valid_packets_server) = packet_sinks
+            .create(
+                FileSinkBuilder::from_settings(
+                    FileType::IotValidPacket,
+                    store_base_path,
+                    concat!(env!("CARGO_PKG_NAME"), "_valid_packets"),
+                    task_manager.listener(Stage::Sink),
+                    &settings.sinks,
+                )
+                .deposits(Some(file_upload_tx.clone()))
+                .auto_commit(false),
+            )
+            .await?;
+
+        let (invalid_packets, mut invalid_packets_server) = packet_sinks
+            .create(
+                FileSinkBuilder::from_settings(
+                    FileType::InvalidPacket,
+                    store_base_path,
+                    concat!(env!("CARGO_PKG_NAME"), "_invalid_packets"),
+                    task_manager.listener(Stage::Sink),
+                    &settings.sinks,
+                )
+                .deposits(Some(file_upload_tx.clone()))
+                .auto_commit(false),
+            )
+            .await?;
+
+        // Top ups of the payer escrows seen by the balance cache:
+        let (top_up_sink, mut top_up_sink_server) = FileSinkBuilder::from_settings(
+            FileType::IotBalanceTopUp,
             store_base_path,
-            concat!(env!("CARGO_PKG_NAME"), "_valid_packets"),
-            shutdown_listener.clone(),
+            concat!(env!("CARGO_PKG_NAME"), "_balance_top_ups"),
+            task_manager.listener(Stage::Sink),
+            &settings.sinks,
         )
         .deposits(Some(file_upload_tx.clone()))
-        .auto_commit(false)
         .create()
         .await?;
+        let top_up_wake = Arc::new(Notify::new());
+        let top_up_notifier = TopUpNotifier::new(
+            balances.subscribe_top_ups(),
+            top_up_sink,
+            top_up_wake.clone(),
+        );
 
-        let (invalid_packets, mut invalid_packets_server) = FileSinkBuilder::new(
-            FileType::InvalidPacket,
+        // Org state changes, signed with the key of the verifier:
+        let (org_event_sink, mut org_event_sink_server) = FileSinkBuilder::from_settings(
+            FileType::IotOrgStateChange,
             store_base_path,
-            concat!(env!("CARGO_PKG_NAME"), "_invalid_packets"),
-            shutdown_listener.clone(),
+            concat!(env!("CARGO_PKG_NAME"), "_org_state_changes"),
+            task_manager.listener(Stage::Sink),
+            &settings.sinks,
         )
         .deposits(Some(file_upload_tx.clone()))
-        .auto_commit(false)
         .create()
         .await?;
+        let (org_events, org_webhook) = OrgEvents::new(
+            org_event_sink,
+            settings.iot_config_client.signer()?,
+            settings.org_webhook.as_ref(),
+        )?;
 
         let org_client = Arc::new(Mutex::new(OrgClient::from_settings(
             &settings.iot_config_client,
@@ -169,43 +311,176 @@ impl Cmd {
                 .lookback(LookbackBehavior::StartAfter(settings.start_after()))
                 .file_type(FileType::IotPacketReport)
                 .build()?
-                .start(shutdown_listener.clone())
+                .start(task_manager.listener(Stage::Producer))
                 .await?;
 
-        let balance_store = balances.balances();
+        let stats_server = {
+            let listen_addr = settings.listen_addr()?;
+            let stats_service = StatsService::new(pool.clone(), org_client.clone());
+            let stats_service = if settings.api_tokens {
+                stats_service.api_tokens(TokenVerifier::new(
+                    ApiTokenClient::from_settings(&settings.iot_config_client)?,
+                    settings.api_token_status_ttl,
+                ))
+            } else {
+                stats_service
+            };
+            let admin_keys: HashSet<_> = settings.admin_keys.iter().cloned().collect();
+            let log_filter_service =
+                (!admin_keys.is_empty()).then(|| LogFilterService::new(Arc::new(admin_keys)));
+            let tls = settings.tls.clone();
+            move |shutdown: triggered::Listener| async move {
+                let Some(listen_addr) = listen_addr else {
+                    return Ok(());
+                };
+                tracing::info!("serving packet stats on {listen_addr}");
+                let router = tonic::transport::Server::builder()
+                    .add_service(stats_service)
+                    .add_optional_service(log_filter_service);
+                match tls {
+                    Some(tls) => {
+                        let incoming = tls::incoming(&tls, listen_addr, shutdown.clone()).await?;
+                        router
+                            .serve_with_incoming_shutdown(incoming, shutdown)
+                            .await?
+                    }
+                    None => router.serve_with_shutdown(listen_addr, shutdown).await?,
+                }
+                Ok::<(), anyhow::Error>(())
+            }
+        };
+
+        // Send the org state changes left over by a failed config service,
+        // or all of them in batches:
+        let intent_reconciler = IntentReconciler::new(
+            pool.clone(),
+            settings
+                .org_batch_secs
+                .map(Duration::from_secs)
+                .unwrap_or_else(|| Duration::from_secs(60 * settings.org_intents_period)),
+        )
+        .batched(settings.org_batch_secs.is_some());
+
+        let org_locks = OrgLocks::new(settings.org_lock.clone())
+            .journal(pool.clone())
+            .events(org_events);
+        let balance_store = balances.clone();
+        let refresh_balances = balances.clone();
+        let refresh_balances_period = Duration::from_secs(60 * settings.refresh_balances_period);
+        let low_balances = balances.clone();
+        let low_balance_threshold = org_locks.enable_threshold(settings.minimum_allowed_balance);
+        let top_up_period = settings.top_up_period;
+        #[cfg(feature = "explorer")]
+        let explorer = settings.explorer_listen_addr()?.map(|listen_addr| {
+            (
+                listen_addr,
+                crate::explorer::Explorer::new(pool.clone(), balances.clone()),
+            )
+        });
+        let packets_seen = settings.packets_seen.as_ref().map(Deduper::new);
         let verifier_daemon = Daemon {
-            pool,
+            pool: pool.clone(),
             report_files,
             valid_packets,
             invalid_packets,
+            packet_sinks,
             verifier: Verifier {
                 debiter: balances,
                 config_server: org_client.clone(),
+                pricing: settings.dc_pricing,
+                verify_reports: settings.verify_reports,
+                org_locks: org_locks.clone(),
+                packets_seen,
+                unknown_ouis: Default::default(),
+                batch_org_intents: settings.org_batch_secs.is_some(),
             },
             minimum_allowed_balance: settings.minimum_allowed_balance,
         };
 
-        // Run the services:
-        tokio::try_join!(
-            db_handle.map_err(Error::from),
-            burner.run(&shutdown_listener).map_err(Error::from),
-            file_upload.run(&shutdown_listener).map_err(Error::from),
-            verifier_daemon.run(&shutdown_listener).map_err(Error::from),
-            valid_packets_server.run().map_err(Error::from),
-            invalid_packets_server.run().map_err(Error::from),
-            org_client
-                .monitor_funds(
-                    solana,
-                    balance_store,
-                    settings.minimum_allowed_balance,
-                    Duration::from_secs(60 * settings.monitor_funds_period),
-                    shutdown_listener.clone(),
-                )
-                .map_err(Error::from),
-            source_join_handle.map_err(Error::from),
-            sol_balance_monitor.map_err(Error::from),
-        )?;
+        let live_settings = watcher.subscribe();
+        task_manager.add("settings_watcher", Stage::Producer, |shutdown| {
+            watcher.run(shutdown)
+        });
+        task_manager.add("log_filter", Stage::Producer, |shutdown| {
+            apply_log_filter(live_settings, shutdown)
+        });
+        task_manager.add("db_metrics", Stage::Producer, |_| db_handle);
+        task_manager.add("db_maintenance", Stage::Producer, |shutdown| {
+            maintenance.run(shutdown)
+        });
+        task_manager.add("burner", Stage::Producer, |shutdown| async move {
+            burner.run(&shutdown).await
+        });
+        task_manager.add("burn_reconciler", Stage::Producer, |shutdown| {
+            reconciler.run(shutdown)
+        });
+        task_manager.add("stats_server", Stage::Producer, stats_server);
+        if let Some(org_webhook) = org_webhook {
+            task_manager.add("org_webhook", Stage::Producer, |shutdown| {
+                org_webhook.run(shutdown)
+            });
+        }
+        #[cfg(feature = "explorer")]
+        if let Some((listen_addr, explorer)) = explorer {
+            task_manager.add("explorer", Stage::Producer, move |shutdown| {
+                explorer.serve(listen_addr, shutdown)
+            });
+        }
+        let intents_config_server = org_client.clone();
+        task_manager.add("org_intents", Stage::Producer, |shutdown| {
+            intent_reconciler.run(intents_config_server, shutdown)
+        });
+        task_manager.add("verifier", Stage::Producer, |shutdown| async move {
+            verifier_daemon.run(&shutdown).await
+        });
+        task_manager.add("monitor_funds", Stage::Producer, |shutdown| {
+            org_client.monitor_funds(
+                solana,
+                balance_store,
+                pool,
+                settings.org_batch_secs.is_some(),
+                org_locks,
+                settings.minimum_allowed_balance,
+                Duration::from_secs(60 * settings.monitor_funds_period),
+                top_up_wake,
+                shutdown,
+            )
+        });
+        task_manager.add("refresh_balances", Stage::Producer, |shutdown| async move {
+            refresh_balances
+                .watch(refresh_balances_period, shutdown)
+                .await;
+            anyhow::Ok(())
+        });
+        task_manager.add("low_balances", Stage::Producer, |shutdown| async move {
+            low_balances
+                .watch_below(low_balance_threshold, top_up_period, shutdown)
+                .await;
+            anyhow::Ok(())
+        });
+        task_manager.add("top_ups", Stage::Producer, |shutdown| {
+            top_up_notifier.run(shutdown)
+        });
+        task_manager.add("report_source", Stage::Producer, |_| source_join_handle);
+        task_manager.add("sol_balance_monitor", Stage::Producer, |_| {
+            sol_balance_monitor
+        });
+        task_manager.add("valid_packets_sink", Stage::Sink, |_| async move {
+            valid_packets_server.run().await
+        });
+        task_manager.add("invalid_packets_sink", Stage::Sink, |_| async move {
+            invalid_packets_server.run().await
+        });
+        task_manager.add("balance_top_ups_sink", Stage::Sink, |_| async move {
+            top_up_sink_server.run().await
+        });
+        task_manager.add("org_state_changes_sink", Stage::Sink, |_| async move {
+            org_event_sink_server.run().await
+        });
+        task_manager.add("file_upload", Stage::Upload, |shutdown| async move {
+            file_upload.run(&shutdown).await
+        });
 
-        Ok(())
+        task_manager.run(shutdown_listener).await
     }
 }
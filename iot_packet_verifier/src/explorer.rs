@@ -0,0 +1,224 @@
+//! Read-only json api of the verifier state for internal dashboards.
+//!
+//! Served on `explorer_listen` when the verifier is built with the `explorer`
+//! feature. Every endpoint is a GET returning a json array:
+//!
+//! * `/balances` the cached escrow balances of the payers
+//! * `/pending_burns` the data credits of every payer waiting to be burned
+//! * `/orgs` the latest state the verifier disabled or enabled every org in
+//! * `/burns?limit=` the most recent burns in the burn journal, at most
+//!   [`MAX_BURNS`], by default [`DEFAULT_BURNS`]
+//!
+//! Nothing is written and the api is unauthenticated, so it must only be
+//! exposed to internal networks.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use balance_cache::BalanceCache;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use helium_crypto::PublicKeyBinary;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres};
+use std::net::SocketAddr;
+
+/// Burns returned when the request has no limit
+pub const DEFAULT_BURNS: i64 = 100;
+/// Most burns returned by a single request
+pub const MAX_BURNS: i64 = 1000;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PayerBalance {
+    pub payer: String,
+    /// Escrow balance on chain as last fetched
+    pub balance: u64,
+    /// Debits not yet burned on chain
+    pub burned: u64,
+    pub available: u64,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct PendingBurn {
+    pub payer: String,
+    pub amount: i64,
+    pub last_burn: NaiveDateTime,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct OrgState {
+    pub oui: i64,
+    /// "enabled" or "disabled"
+    pub state: String,
+    /// Balance of the payer of the org when its state changed, if known
+    pub balance: Option<i64>,
+    pub failures: i32,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct BurnReceipt {
+    pub signature: String,
+    pub payer: String,
+    pub amount: i64,
+    pub burned_at: DateTime<Utc>,
+    /// Set once the burn was found on chain by the reconciler
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct BurnsQuery {
+    pub limit: Option<i64>,
+}
+
+impl BurnsQuery {
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_BURNS).clamp(1, MAX_BURNS)
+    }
+}
+
+pub struct Explorer<S> {
+    pool: Pool<Postgres>,
+    balances: BalanceCache<S>,
+}
+
+impl<S> Clone for Explorer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            balances: self.balances.clone(),
+        }
+    }
+}
+
+type ApiResult<T> = Result<Json<Vec<T>>, StatusCode>;
+
+impl<S> Explorer<S>
+where
+    S: Send + Sync + 'static,
+{
+    pub fn new(pool: Pool<Postgres>, balances: BalanceCache<S>) -> Self {
+        Self { pool, balances }
+    }
+
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/balances", get(balances::<S>))
+            .route("/pending_burns", get(pending_burns::<S>))
+            .route("/orgs", get(orgs::<S>))
+            .route("/burns", get(burns::<S>))
+            .with_state(self)
+    }
+
+    pub async fn serve(
+        self,
+        addr: SocketAddr,
+        shutdown: triggered::Listener,
+    ) -> anyhow::Result<()> {
+        tracing::info!("serving the explorer api on {addr}");
+        axum::Server::bind(&addr)
+            .serve(self.router().into_make_service())
+            .with_graceful_shutdown(shutdown)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn balances<S: Send + Sync + 'static>(
+    State(explorer): State<Explorer<S>>,
+) -> ApiResult<PayerBalance> {
+    let mut balances: Vec<_> = explorer
+        .balances
+        .balances()
+        .await
+        .into_iter()
+        .map(|(payer, balance)| payer_balance(&payer, balance))
+        .collect();
+    balances.sort_by(|a, b| a.payer.cmp(&b.payer));
+    Ok(Json(balances))
+}
+
+fn payer_balance(payer: &PublicKeyBinary, balance: balance_cache::Balance) -> PayerBalance {
+    PayerBalance {
+        payer: payer.to_string(),
+        balance: balance.balance,
+        burned: balance.burned,
+        available: balance.available(),
+    }
+}
+
+async fn pending_burns<S: Send + Sync + 'static>(
+    State(explorer): State<Explorer<S>>,
+) -> ApiResult<PendingBurn> {
+    sqlx::query_as("SELECT payer, amount, last_burn FROM pending_burns ORDER BY payer")
+        .fetch_all(&explorer.pool)
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
+
+async fn orgs<S: Send + Sync + 'static>(
+    State(explorer): State<Explorer<S>>,
+) -> ApiResult<OrgState> {
+    sqlx::query_as(
+        r#"
+        SELECT DISTINCT ON (oui) oui, state, balance, failures, recorded_at
+        FROM org_lock_transitions
+        ORDER BY oui, recorded_at DESC
+        "#,
+    )
+    .fetch_all(&explorer.pool)
+    .await
+    .map(Json)
+    .map_err(internal_error)
+}
+
+async fn burns<S: Send + Sync + 'static>(
+    State(explorer): State<Explorer<S>>,
+    Query(query): Query<BurnsQuery>,
+) -> ApiResult<BurnReceipt> {
+    sqlx::query_as(
+        r#"
+        SELECT signature, payer, amount, burned_at, confirmed_at FROM burn_journal
+        ORDER BY burned_at DESC
+        LIMIT $1
+        "#,
+    )
+    .bind(query.limit())
+    .fetch_all(&explorer.pool)
+    .await
+    .map(Json)
+    .map_err(internal_error)
+}
+
+fn internal_error(err: sqlx::Error) -> StatusCode {
+    tracing::error!("explorer query failed: {err}");
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burn_limits_are_capped() {
+        let limit = |limit| BurnsQuery { limit }.limit();
+        assert_eq!(limit(None), DEFAULT_BURNS);
+        assert_eq!(limit(Some(5)), 5);
+        assert_eq!(limit(Some(0)), 1);
+        assert_eq!(limit(Some(MAX_BURNS + 1)), MAX_BURNS);
+
+        let payer = PublicKeyBinary::from(vec![1]);
+        let balance = payer_balance(
+            &payer,
+            balance_cache::Balance {
+                balance: 10,
+                burned: 4,
+            },
+        );
+        assert_eq!(balance.available, 6);
+        assert_eq!(balance.payer, payer.to_string());
+    }
+}
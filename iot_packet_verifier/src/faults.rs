@@ -0,0 +1,155 @@
+//! Debiter, config server, pending burns and packet writers disturbed by
+//! programmable faults, for tests of the retry and rollback paths of the
+//! verifier and the burner. Packet writers stand in for the file sinks: a
+//! partial write hands the packet to the sink and fails anyway.
+
+use crate::{
+    pending_burns::{Burn, PendingBurns},
+    verifier::{ConfigServer, Debiter, Org, PacketWriter},
+};
+use async_trait::async_trait;
+use fault_injection::{Fault, Faulty, FaultyError};
+use futures::{stream, Stream, StreamExt};
+use helium_crypto::PublicKeyBinary;
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+};
+
+#[async_trait]
+impl<D> Debiter for Faulty<D>
+where
+    D: Debiter + Send + Sync,
+    D::Error: Send,
+{
+    type Error = FaultyError<D::Error>;
+
+    async fn debit_if_sufficient(
+        &self,
+        payer: &PublicKeyBinary,
+        amount: u64,
+    ) -> Result<Option<u64>, Self::Error> {
+        self.faults
+            .apply(self.inner.debit_if_sufficient(payer, amount))
+            .await
+    }
+}
+
+#[async_trait]
+impl<C: ConfigServer> ConfigServer for Faulty<C> {
+    type Error = FaultyError<C::Error>;
+
+    async fn fetch_org(
+        &self,
+        oui: u64,
+        cache: &mut HashMap<u64, PublicKeyBinary>,
+    ) -> Result<Option<PublicKeyBinary>, Self::Error> {
+        self.faults.apply(self.inner.fetch_org(oui, cache)).await
+    }
+
+    async fn fetch_net_id(
+        &self,
+        oui: u64,
+        cache: &mut HashMap<u64, Option<u32>>,
+    ) -> Result<Option<u32>, Self::Error> {
+        self.faults.apply(self.inner.fetch_net_id(oui, cache)).await
+    }
+
+    async fn disable_org(&self, oui: u64) -> Result<(), Self::Error> {
+        self.faults.apply(self.inner.disable_org(oui)).await
+    }
+
+    async fn enable_org(&self, oui: u64) -> Result<(), Self::Error> {
+        self.faults.apply(self.inner.enable_org(oui)).await
+    }
+
+    async fn list_orgs(&self) -> Result<Vec<Org>, Self::Error> {
+        self.faults.apply(self.inner.list_orgs()).await
+    }
+}
+
+#[async_trait]
+impl<P> PendingBurns for Faulty<P>
+where
+    P: PendingBurns + Send,
+{
+    type Error = FaultyError<P::Error>;
+
+    /// A partial fetch yields the first burn and then fails
+    async fn fetch_all<'a>(
+        &'a mut self,
+    ) -> Pin<Box<dyn Stream<Item = Result<Burn, Self::Error>> + Send + 'a>> {
+        let fault = self.faults.next_call().await;
+        if let Some(Fault::Fail(fault)) = fault {
+            return stream::once(async move { Err(FaultyError::Injected(fault)) }).boxed();
+        }
+        let burns = self
+            .inner
+            .fetch_all()
+            .await
+            .map(|burn| burn.map_err(FaultyError::Inner));
+        match fault {
+            Some(Fault::Partial(fault)) => burns
+                .take(1)
+                .chain(stream::once(
+                    async move { Err(FaultyError::Injected(fault)) },
+                ))
+                .boxed(),
+            _ => burns.boxed(),
+        }
+    }
+
+    async fn fetch_next(&mut self) -> Result<Option<Burn>, Self::Error> {
+        self.faults.apply(self.inner.fetch_next()).await
+    }
+
+    async fn subtract_burned_amount(
+        &mut self,
+        payer: &PublicKeyBinary,
+        amount: u64,
+    ) -> Result<(), Self::Error> {
+        self.faults
+            .apply(self.inner.subtract_burned_amount(payer, amount))
+            .await
+    }
+
+    async fn add_burned_amount(
+        &mut self,
+        payer: &PublicKeyBinary,
+        amount: u64,
+    ) -> Result<(), Self::Error> {
+        self.faults
+            .apply(self.inner.add_burned_amount(payer, amount))
+            .await
+    }
+
+    async fn record_org_intent(&mut self, oui: u64, enabled: bool) -> Result<(), Self::Error> {
+        self.faults
+            .apply(self.inner.record_org_intent(oui, enabled))
+            .await
+    }
+
+    async fn org_intent_sent(&mut self, oui: u64, enabled: bool) -> Result<(), Self::Error> {
+        self.faults
+            .apply(self.inner.org_intent_sent(oui, enabled))
+            .await
+    }
+
+    async fn claim_packets(&mut self, ids: &[u128]) -> Result<HashSet<u128>, Self::Error> {
+        self.faults.apply(self.inner.claim_packets(ids)).await
+    }
+}
+
+#[async_trait]
+impl<T, W> PacketWriter<T> for Faulty<W>
+where
+    T: Send,
+    W: PacketWriter<T> + Send,
+    W::Error: Send,
+{
+    type Error = FaultyError<W::Error>;
+
+    async fn write(&mut self, packet: T) -> Result<(), Self::Error> {
+        self.faults.apply(self.inner.write(packet)).await
+    }
+}
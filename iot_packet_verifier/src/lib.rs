@@ -1,6 +1,25 @@
+pub mod backfill;
 pub mod balances;
 pub mod burner;
 pub mod daemon;
+#[cfg(feature = "explorer")]
+pub mod explorer;
+#[cfg(feature = "test-utils")]
+pub mod faults;
+pub mod org_events;
+pub mod org_intents;
+pub mod org_lock;
+pub mod packets_seen;
 pub mod pending_burns;
+pub mod reconcile;
+pub mod report_check;
 pub mod settings;
+pub mod stats;
+pub mod stats_service;
+pub mod top_ups;
 pub mod verifier;
+
+/// Label the errors of the verifier in metrics and logs
+pub fn register_error_codes() {
+    poc_metrics::error_code::register::<verifier::ConfigServerError>();
+}
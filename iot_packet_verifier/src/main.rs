@@ -1,8 +1,8 @@
 use anyhow::Result;
 use clap::Parser;
-use iot_packet_verifier::{daemon, settings::Settings};
+use iot_packet_verifier::{backfill, daemon, settings::Settings};
+use oracle_settings::{Overrides, SettingsWatcher};
 use std::path::PathBuf;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(clap::Parser)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
@@ -14,30 +14,40 @@ pub struct Cli {
     #[clap(short = 'c')]
     config: Option<PathBuf>,
 
+    #[clap(flatten)]
+    overrides: Overrides,
+
     #[clap(subcommand)]
     cmd: Cmd,
 }
 
 impl Cli {
     pub async fn run(self) -> Result<()> {
-        let settings = Settings::new(self.config)?;
-        tracing_subscriber::registry()
-            .with(tracing_subscriber::EnvFilter::new(&settings.log))
-            .with(tracing_subscriber::fmt::layer())
-            .init();
-        self.cmd.run(settings).await
+        let (settings, watcher) = Settings::watch(self.config, &self.overrides)?;
+        custom_tracing::init(&settings.log, &settings.tracing)?;
+        poc_metrics::register_error_codes!(
+            poc_metrics,
+            db_store,
+            file_store,
+            solana,
+            iot_config,
+            iot_packet_verifier,
+        );
+        self.cmd.run(settings, watcher).await
     }
 }
 
 #[derive(clap::Subcommand)]
 pub enum Cmd {
     Server(daemon::Cmd),
+    BackfillBurns(backfill::Cmd),
 }
 
 impl Cmd {
-    async fn run(self, settings: Settings) -> Result<()> {
+    async fn run(self, settings: Settings, watcher: SettingsWatcher<Settings>) -> Result<()> {
         match self {
-            Self::Server(cmd) => cmd.run(&settings).await,
+            Self::Server(cmd) => cmd.run(&settings, watcher).await,
+            Self::BackfillBurns(cmd) => cmd.run(&settings).await,
         }
     }
 }
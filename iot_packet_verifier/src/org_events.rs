@@ -0,0 +1,283 @@
+//! Events of the orgs disabled and enabled by the verifier.
+//!
+//! Every time the verifier or the fund monitor flips the enabled state of an
+//! org, an event signed with the key of the verifier is written to the org
+//! state change files and, when a webhook is configured, posted to it as the
+//! encoded protobuf. The webhook is best effort: events are posted one at a
+//! time from a bounded queue, dropped when the queue is full and not retried.
+//! The files are the record of the transitions.
+
+use chrono::Utc;
+use file_store::file_sink::FileSinkClient;
+use oracle_signer::Signer;
+use prost::Message;
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OrgStateChangeV1 {
+    #[prost(uint64, tag = "1")]
+    pub oui: u64,
+    #[prost(bool, tag = "2")]
+    pub enabled: bool,
+    /// Balance of the payer of the org when its state changed, if known
+    #[prost(uint64, optional, tag = "3")]
+    pub balance: Option<u64>,
+    /// Low balance packets of the org before it was disabled
+    #[prost(uint32, tag = "4")]
+    pub failures: u32,
+    /// Milliseconds since the epoch of the change
+    #[prost(uint64, tag = "5")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "6")]
+    pub signer: Vec<u8>,
+    /// Signature over the event with an empty signature
+    #[prost(bytes = "vec", tag = "7")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhookSettings {
+    /// Url the events are posted to
+    pub url: String,
+    /// Timeout of a post in seconds. Default is 5
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: u64,
+    /// Events waiting to be posted, further events are dropped while the
+    /// queue is full. Default is 100
+    #[serde(default = "default_webhook_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_webhook_timeout() -> u64 {
+    5
+}
+
+fn default_webhook_queue_size() -> usize {
+    100
+}
+
+impl WebhookSettings {
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.queue_size == 0 {
+            problems.push("org_webhook.queue_size must be at least 1".to_string());
+        }
+        problems
+    }
+}
+
+/// Worker posting the queued events to the webhook one at a time
+pub struct WebhookPoster {
+    client: reqwest::Client,
+    url: String,
+    events: mpsc::Receiver<OrgStateChangeV1>,
+}
+
+impl WebhookPoster {
+    pub async fn run(mut self, shutdown: triggered::Listener) -> anyhow::Result<()> {
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                event = self.events.recv() => match event {
+                    Some(event) => self.post(event).await,
+                    None => break,
+                },
+            }
+        }
+        Ok(())
+    }
+
+    async fn post(&self, event: OrgStateChangeV1) {
+        let result = self
+            .client
+            .post(&self.url)
+            .header(http::header::CONTENT_TYPE, "application/x-protobuf")
+            .body(event.encode_to_vec())
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        match result {
+            Ok(_) => metrics::increment_counter!("org_state_webhook", "status" => "ok"),
+            Err(err) => {
+                metrics::increment_counter!("org_state_webhook", "status" => "error");
+                tracing::warn!(
+                    oui = event.oui,
+                    enabled = event.enabled,
+                    "failed to post org state change: {err}"
+                );
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OrgEvents {
+    sink: FileSinkClient,
+    signer: Arc<dyn Signer>,
+    webhook: Option<mpsc::Sender<OrgStateChangeV1>>,
+}
+
+impl OrgEvents {
+    /// Events written to the sink, and the poster of the events to the
+    /// webhook when one is configured, which has to be run for the events to
+    /// be posted
+    pub fn new(
+        sink: FileSinkClient,
+        signer: Arc<dyn Signer>,
+        webhook: Option<&WebhookSettings>,
+    ) -> reqwest::Result<(Self, Option<WebhookPoster>)> {
+        let (webhook, poster) = match webhook {
+            Some(settings) => {
+                let (sender, events) = mpsc::channel(settings.queue_size);
+                let poster = WebhookPoster {
+                    client: reqwest::Client::builder()
+                        .timeout(Duration::from_secs(settings.timeout))
+                        .build()?,
+                    url: settings.url.clone(),
+                    events,
+                };
+                (Some(sender), Some(poster))
+            }
+            None => (None, None),
+        };
+        Ok((
+            Self {
+                sink,
+                signer,
+                webhook,
+            },
+            poster,
+        ))
+    }
+
+    /// Publish the change of the org state. Failures are logged, they never
+    /// hold up the verifier
+    pub async fn publish(&self, oui: u64, enabled: bool, balance: Option<u64>, failures: u32) {
+        let event = match signed_event(self.signer.as_ref(), oui, enabled, balance, failures).await
+        {
+            Ok(event) => event,
+            Err(err) => {
+                tracing::error!(oui, enabled, "failed to sign org state change: {err}");
+                return;
+            }
+        };
+        if let Some(webhook) = &self.webhook {
+            match webhook.try_send(event.clone()) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    metrics::increment_counter!("org_state_webhook", "status" => "dropped");
+                    tracing::warn!(
+                        oui,
+                        enabled,
+                        "org webhook queue full, dropping state change"
+                    );
+                }
+                // The poster has shut down
+                Err(TrySendError::Closed(_)) => (),
+            }
+        }
+        if let Err(err) = self.sink.write(event, &[]).await {
+            tracing::error!(oui, enabled, "failed to write org state change: {err}");
+        }
+    }
+}
+
+async fn signed_event(
+    signer: &dyn Signer,
+    oui: u64,
+    enabled: bool,
+    balance: Option<u64>,
+    failures: u32,
+) -> oracle_signer::Result<OrgStateChangeV1> {
+    let mut event = OrgStateChangeV1 {
+        oui,
+        enabled,
+        balance,
+        failures,
+        timestamp: Utc::now().timestamp_millis() as u64,
+        signer: signer.public_key().into(),
+        signature: vec![],
+    };
+    event.signature = signer.sign(&event.encode_to_vec()).await?;
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::org_lock::OrgLocks;
+    use file_store::{file_sink::FileSinkBuilder, FileType};
+    use helium_crypto::{KeyTag, KeyType, Keypair, Network, PublicKey, Verify};
+    use oracle_signer::FileSigner;
+
+    fn signer() -> FileSigner {
+        FileSigner::new(Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::Ed25519,
+            },
+            &mut rand::rngs::OsRng,
+        ))
+    }
+
+    #[tokio::test]
+    async fn events_are_signed_without_their_signature() {
+        let signer = signer();
+        let event = signed_event(&signer, 7, false, Some(10), 3).await.unwrap();
+        assert_eq!(event.oui, 7);
+        assert_eq!(event.balance, Some(10));
+
+        let signer = PublicKey::try_from(event.signer.as_slice()).unwrap();
+        let unsigned = OrgStateChangeV1 {
+            signature: vec![],
+            ..event.clone()
+        };
+        assert!(signer
+            .verify(&unsigned.encode_to_vec(), &event.signature)
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn org_lock_transitions_are_queued_for_the_webhook() {
+        let (_trigger, shutdown) = triggered::trigger();
+        // The sink is never run, its client only queues the writes
+        let (sink, _file_sink) = FileSinkBuilder::new(
+            FileType::IotOrgStateChange,
+            &std::env::temp_dir(),
+            "org_state_changes_test",
+            shutdown,
+        )
+        .create()
+        .await
+        .unwrap();
+        let (events, poster) = OrgEvents::new(
+            sink,
+            Arc::new(signer()),
+            Some(&WebhookSettings {
+                url: "http://127.0.0.1:1".to_string(),
+                timeout: 1,
+                queue_size: 2,
+            }),
+        )
+        .unwrap();
+        let mut poster = poster.expect("webhook poster");
+        let locks = OrgLocks::default().events(events);
+
+        locks.disabled(1, Some(4)).await;
+        locks.enabled(1, 10).await;
+        // The queue is full until the poster catches up
+        locks.disabled(2, None).await;
+
+        let queued: Vec<(u64, bool, Option<u64>)> = [
+            poster.events.try_recv().unwrap(),
+            poster.events.try_recv().unwrap(),
+        ]
+        .into_iter()
+        .map(|event| (event.oui, event.enabled, event.balance))
+        .collect();
+        assert_eq!(queued, vec![(1, false, Some(4)), (1, true, Some(10))]);
+        assert!(poster.events.try_recv().is_err());
+    }
+}
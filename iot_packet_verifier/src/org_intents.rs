@@ -0,0 +1,185 @@
+//! Write-ahead log of the org state changes of the verifier.
+//!
+//! Before the verifier asks the config service to disable an org it records
+//! the intent in `org_intents`, in the transaction holding the debits of the
+//! report file, and marks the intent sent in the same transaction once the
+//! config service disabled the org. The funds monitor records and marks its
+//! intents to enable an org the same way. An intent that is committed without
+//! being sent, because the config service failed, is sent again by the
+//! reconciler on startup and every `org_intents_period` minutes. Only the
+//! latest intent of an org is kept, so a stale intent never overrides a
+//! newer one.
+//!
+//! With `org_batch_secs` set the verifier and the funds monitor only record
+//! their intents, and the reconciler sends every unsent intent, enable or
+//! disable, in batches every `org_batch_secs` seconds instead. An org
+//! disabled and enabled again between two batches is only sent in its latest
+//! state. The intents the config service fails on are left unsent for the
+//! next batch.
+
+use crate::verifier::ConfigServer;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, Pool, Postgres};
+use std::time::Duration;
+
+#[derive(FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct OrgIntent {
+    pub oui: i64,
+    pub enabled: bool,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// Record the intended state of the org, replacing any earlier intent
+pub async fn record(
+    exec: impl sqlx::PgExecutor<'_>,
+    oui: u64,
+    enabled: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO org_intents (oui, enabled, recorded_at, sent_at)
+        VALUES ($1, $2, $3, NULL)
+        ON CONFLICT (oui) DO UPDATE SET
+        enabled = EXCLUDED.enabled,
+        recorded_at = EXCLUDED.recorded_at,
+        sent_at = NULL
+        "#,
+    )
+    .bind(oui as i64)
+    .bind(enabled)
+    .bind(Utc::now())
+    .execute(exec)
+    .await?;
+    Ok(())
+}
+
+/// Mark the intent sent, unless it was replaced by one of another state
+pub async fn mark_sent(
+    exec: impl sqlx::PgExecutor<'_>,
+    oui: u64,
+    enabled: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE org_intents SET sent_at = $3 WHERE oui = $1 AND enabled = $2")
+        .bind(oui as i64)
+        .bind(enabled)
+        .bind(Utc::now())
+        .execute(exec)
+        .await?;
+    Ok(())
+}
+
+pub async fn unsent(exec: impl sqlx::PgExecutor<'_>) -> Result<Vec<OrgIntent>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT oui, enabled, recorded_at FROM org_intents
+        WHERE sent_at IS NULL
+        ORDER BY recorded_at
+        "#,
+    )
+    .fetch_all(exec)
+    .await
+}
+
+pub struct IntentReconciler {
+    pool: Pool<Postgres>,
+    period: Duration,
+    batched: bool,
+}
+
+impl IntentReconciler {
+    pub fn new(pool: Pool<Postgres>, period: Duration) -> Self {
+        Self {
+            pool,
+            period,
+            batched: false,
+        }
+    }
+
+    /// Send the unsent intents in batches
+    pub fn batched(self, batched: bool) -> Self {
+        Self { batched, ..self }
+    }
+
+    /// Send every unsent intent to the config service, returning the number
+    /// of intents sent. Intents the config service fails on are left for the
+    /// next reconciliation
+    pub async fn reconcile<C: ConfigServer>(
+        &self,
+        config_server: &C,
+    ) -> Result<usize, sqlx::Error> {
+        if self.batched {
+            return self.reconcile_batch(config_server).await;
+        }
+        let mut sent = 0;
+        for OrgIntent { oui, enabled, .. } in unsent(&self.pool).await? {
+            let oui = oui as u64;
+            let result = if enabled {
+                config_server.enable_org(oui).await
+            } else {
+                config_server.disable_org(oui).await
+            };
+            match result {
+                Ok(()) => {
+                    mark_sent(&self.pool, oui, enabled).await?;
+                    tracing::info!(oui, enabled, "sent org intent");
+                    sent += 1;
+                }
+                Err(err) => tracing::warn!(oui, enabled, "failed to send org intent: {err:?}"),
+            }
+        }
+        Ok(sent)
+    }
+
+    async fn reconcile_batch<C: ConfigServer>(
+        &self,
+        config_server: &C,
+    ) -> Result<usize, sqlx::Error> {
+        let changes: Vec<_> = unsent(&self.pool)
+            .await?
+            .into_iter()
+            .map(|intent| (intent.oui as u64, intent.enabled))
+            .collect();
+        if changes.is_empty() {
+            return Ok(0);
+        }
+        let applied = match config_server.set_org_states(&changes).await {
+            Ok(applied) => applied,
+            Err(err) => {
+                tracing::warn!(
+                    intents = changes.len(),
+                    "failed to send org intents: {err:?}"
+                );
+                return Ok(0);
+            }
+        };
+        for &(oui, enabled) in &applied {
+            mark_sent(&self.pool, oui, enabled).await?;
+        }
+        tracing::info!(
+            intents = changes.len(),
+            sent = applied.len(),
+            "sent batch of org intents"
+        );
+        Ok(applied.len())
+    }
+
+    /// Reconcile on startup and then every period until shutdown
+    pub async fn run<C: ConfigServer>(
+        self,
+        config_server: C,
+        shutdown: triggered::Listener,
+    ) -> anyhow::Result<()> {
+        let mut trigger = tokio::time::interval(self.period);
+        trigger.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => return Ok(()),
+                _ = trigger.tick() => {
+                    if let Err(err) = self.reconcile(&config_server).await {
+                        tracing::error!("failed to reconcile org intents: {err}");
+                    }
+                }
+            }
+        }
+    }
+}
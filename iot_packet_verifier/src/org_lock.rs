@@ -0,0 +1,246 @@
+//! Grace policy for disabling orgs whose payer runs low on data credits.
+//!
+//! A packet leaving the payer of an org below the minimum allowed balance,
+//! or not paid for at all, starts or extends a low balance streak of the
+//! org. The org is only disabled once the streak reaches the configured
+//! number of packets or lasts the configured time, so a briefly stale
+//! balance does not make an org flap. A streak ends once a packet leaves
+//! the payer with the minimum plus the hysteresis, which is also the balance
+//! the fund monitor requires before it re-enables an org. Every disable and
+//! enable is recorded in `org_lock_transitions` and published as an org
+//! state change event.
+
+use crate::org_events::OrgEvents;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::Mutex;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct OrgLockSettings {
+    /// Consecutive low balance packets of an org after which it is disabled
+    pub failures: Option<u32>,
+    /// Seconds of packet time an org may have a low balance before it is
+    /// disabled. An org is disabled on its first low balance packet when
+    /// neither this nor failures is set
+    pub grace_seconds: Option<u64>,
+    /// Data credits above the minimum allowed balance a payer needs to end a
+    /// low balance streak and to have its orgs re-enabled. Default is 0
+    #[serde(default)]
+    pub hysteresis: u64,
+}
+
+impl OrgLockSettings {
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.failures == Some(0) {
+            problems.push("org_lock.failures must be at least 1".to_string());
+        }
+        if self.grace_seconds == Some(0) {
+            problems.push("org_lock.grace_seconds must be at least 1".to_string());
+        }
+        problems
+    }
+}
+
+#[derive(Debug)]
+struct Streak {
+    failures: u32,
+    since: DateTime<Utc>,
+    disabled: bool,
+}
+
+/// Low balance streaks of the orgs, shared by the verifier disabling orgs
+/// and the fund monitor enabling them
+#[derive(Clone, Debug, Default)]
+pub struct OrgLocks {
+    settings: OrgLockSettings,
+    streaks: Arc<Mutex<HashMap<u64, Streak>>>,
+    journal: Option<Pool<Postgres>>,
+    events: Option<OrgEvents>,
+}
+
+impl OrgLocks {
+    pub fn new(settings: OrgLockSettings) -> Self {
+        Self {
+            settings,
+            ..Default::default()
+        }
+    }
+
+    /// Record the disabled and enabled orgs in `org_lock_transitions`
+    pub fn journal(self, pool: Pool<Postgres>) -> Self {
+        Self {
+            journal: Some(pool),
+            ..self
+        }
+    }
+
+    /// Publish the disabled and enabled orgs as org state change events
+    pub fn events(self, events: OrgEvents) -> Self {
+        Self {
+            events: Some(events),
+            ..self
+        }
+    }
+
+    /// Balance a payer needs for its orgs to be enabled again
+    pub fn enable_threshold(&self, minimum_allowed_balance: u64) -> u64 {
+        minimum_allowed_balance.saturating_add(self.settings.hysteresis)
+    }
+
+    /// Account a packet of the org received at the given time, leaving its
+    /// payer with the given balance or none when it could not be paid.
+    /// Returns whether the org has to be disabled
+    pub async fn should_disable(
+        &self,
+        oui: u64,
+        balance: Option<u64>,
+        minimum_allowed_balance: u64,
+        received: DateTime<Utc>,
+    ) -> bool {
+        let mut streaks = self.streaks.lock().await;
+        match balance {
+            Some(balance) if balance >= self.enable_threshold(minimum_allowed_balance) => {
+                streaks.remove(&oui);
+                return false;
+            }
+            // Within the hysteresis a streak neither grows nor ends
+            Some(balance) if balance >= minimum_allowed_balance => return false,
+            _ => (),
+        }
+
+        let streak = streaks.entry(oui).or_insert(Streak {
+            failures: 0,
+            since: received,
+            disabled: false,
+        });
+        streak.failures += 1;
+        if streak.disabled {
+            return false;
+        }
+        let OrgLockSettings {
+            failures,
+            grace_seconds,
+            ..
+        } = self.settings;
+        match (failures, grace_seconds) {
+            (None, None) => true,
+            (failures, grace_seconds) => {
+                failures.map_or(false, |failures| streak.failures >= failures)
+                    || grace_seconds.map_or(false, |seconds| {
+                        received - streak.since >= Duration::seconds(seconds as i64)
+                    })
+            }
+        }
+    }
+
+    /// Note the org was disabled, so it is not disabled again during its
+    /// streak
+    pub async fn disabled(&self, oui: u64, balance: Option<u64>) {
+        let failures = match self.streaks.lock().await.get_mut(&oui) {
+            Some(streak) => {
+                streak.disabled = true;
+                streak.failures
+            }
+            None => 0,
+        };
+        tracing::info!(oui, ?balance, failures, "disabled org");
+        self.record(oui, "disabled", balance, failures).await;
+        if let Some(events) = &self.events {
+            events.publish(oui, false, balance, failures).await;
+        }
+    }
+
+    /// Note the org was enabled, ending its streak
+    pub async fn enabled(&self, oui: u64, balance: u64) {
+        self.streaks.lock().await.remove(&oui);
+        tracing::info!(oui, balance, "enabled org");
+        self.record(oui, "enabled", Some(balance), 0).await;
+        if let Some(events) = &self.events {
+            events.publish(oui, true, Some(balance), 0).await;
+        }
+    }
+
+    async fn record(&self, oui: u64, state: &str, balance: Option<u64>, failures: u32) {
+        let Some(pool) = &self.journal else {
+            return;
+        };
+        let result = sqlx::query(
+            r#"
+            INSERT INTO org_lock_transitions (oui, state, balance, failures)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(oui as i64)
+        .bind(state)
+        .bind(balance.map(|balance| balance as i64))
+        .bind(failures as i32)
+        .execute(pool)
+        .await;
+        if let Err(err) = result {
+            tracing::error!(oui, state, "failed to record org lock transition: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_686_000_000 + seconds, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn disables_on_the_first_low_balance_by_default() {
+        let locks = OrgLocks::default();
+        assert!(!locks.should_disable(1, Some(10), 5, at(0)).await);
+        assert!(locks.should_disable(1, Some(4), 5, at(1)).await);
+        locks.disabled(1, Some(4)).await;
+        // Not disabled twice during a streak
+        assert!(!locks.should_disable(1, None, 5, at(2)).await);
+
+        // Until it was enabled again
+        locks.enabled(1, 5).await;
+        assert!(locks.should_disable(1, None, 5, at(3)).await);
+    }
+
+    #[tokio::test]
+    async fn disables_after_consecutive_failures() {
+        let locks = OrgLocks::new(OrgLockSettings {
+            failures: Some(3),
+            ..Default::default()
+        });
+        assert!(!locks.should_disable(1, Some(4), 5, at(0)).await);
+        assert!(!locks.should_disable(1, None, 5, at(1)).await);
+        // A sufficient balance ends the streak
+        assert!(!locks.should_disable(1, Some(5), 5, at(2)).await);
+        assert!(!locks.should_disable(1, Some(4), 5, at(3)).await);
+        assert!(!locks.should_disable(1, Some(3), 5, at(4)).await);
+        // Streaks are per org
+        assert!(!locks.should_disable(2, Some(3), 5, at(4)).await);
+        assert!(locks.should_disable(1, Some(2), 5, at(5)).await);
+    }
+
+    #[tokio::test]
+    async fn disables_after_the_grace_period() {
+        let locks = OrgLocks::new(OrgLockSettings {
+            grace_seconds: Some(60),
+            hysteresis: 10,
+            ..Default::default()
+        });
+        assert!(!locks.should_disable(1, Some(4), 5, at(0)).await);
+        // Within the hysteresis the streak goes on
+        assert!(!locks.should_disable(1, Some(14), 5, at(30)).await);
+        assert!(!locks.should_disable(1, Some(4), 5, at(59)).await);
+        assert!(locks.should_disable(1, Some(4), 5, at(60)).await);
+
+        assert!(!locks.should_disable(2, Some(4), 5, at(0)).await);
+        assert!(!locks.should_disable(2, Some(15), 5, at(30)).await);
+        assert!(!locks.should_disable(2, Some(4), 5, at(60)).await);
+        assert_eq!(locks.enable_threshold(5), 15);
+    }
+}
@@ -0,0 +1,384 @@
+//! Bounded memory of the packets the verifier has seen, to charge a packet
+//! reported more than once only for its first report.
+//!
+//! The most recent packets are remembered exactly, in a window of a fixed
+//! number of packets. Packets leaving the window are optionally remembered
+//! by a bloom filter sized for a false positive rate, so a backlog of
+//! reports needs a bounded amount of memory. A false positive marks a new
+//! packet as duplicate, so the rate is the share of the older packets that
+//! may go uncharged. A full filter is cleared, forgetting the packets that
+//! left the window before.
+//!
+//! The verifier consumes any [`PacketDeduper`], handing it the packets of a
+//! batch of reports at a time. Besides the memory of [`PacketsSeen`] the
+//! packets can be remembered in Postgres by [`PacketsSeenDb`] so they are
+//! remembered across restarts and replicas. A batch is claimed by a single
+//! insert in the transaction of the verified report file: a packet claimed
+//! by another transaction at the same time waits for it and is a duplicate
+//! once it commits, and the packets of a file that fails to verify are
+//! rolled back with it. Rows are purged by the maintenance retention of the
+//! `packets_seen` table.
+
+use crate::pending_burns::PendingBurns;
+use async_trait::async_trait;
+use chrono::Utc;
+use file_store::iot_packet::PacketRouterPacketReport;
+use serde::Deserialize;
+use std::{
+    collections::{HashSet, VecDeque},
+    hash::Hasher,
+};
+use twox_hash::XxHash64;
+
+/// Estimated bytes of a packet in the window, both in the set and the queue
+const WINDOW_ENTRY_BYTES: usize = 3 * std::mem::size_of::<u128>();
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupeBackend {
+    /// Bounded memory of the verifier, lost on restart
+    #[default]
+    Memory,
+    /// The packets_seen table, shared by replicas and kept across restarts
+    Postgres,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PacketsSeenSettings {
+    /// Where the packets are remembered. Default memory
+    #[serde(default)]
+    pub backend: DedupeBackend,
+    /// Number of the most recent packets remembered exactly. Default 1_000_000
+    #[serde(default = "default_window")]
+    pub window: usize,
+    /// Number of packets that left the window remembered by a bloom filter.
+    /// Packets are only remembered in the window when not set
+    pub filter_capacity: Option<usize>,
+    /// False positive rate of the filter once it holds its capacity.
+    /// Default 0.0001
+    #[serde(default = "default_false_positive_rate")]
+    pub false_positive_rate: f64,
+}
+
+fn default_window() -> usize {
+    1_000_000
+}
+
+fn default_false_positive_rate() -> f64 {
+    0.0001
+}
+
+impl Default for PacketsSeenSettings {
+    fn default() -> Self {
+        Self {
+            backend: DedupeBackend::default(),
+            window: default_window(),
+            filter_capacity: None,
+            false_positive_rate: default_false_positive_rate(),
+        }
+    }
+}
+
+impl PacketsSeenSettings {
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.window == 0 {
+            problems.push("packets_seen window must be positive".to_string());
+        }
+        if self.filter_capacity == Some(0) {
+            problems.push("packets_seen filter_capacity must be positive".to_string());
+        }
+        if !(self.false_positive_rate > 0.0 && self.false_positive_rate < 1.0) {
+            problems.push(format!(
+                "packets_seen false_positive_rate must be between 0 and 1, got {}",
+                self.false_positive_rate
+            ));
+        }
+        problems
+    }
+}
+
+/// Identity of a packet, hashed from the gateway, payload hash and time the
+/// packet router received it
+pub fn packet_id(report: &PacketRouterPacketReport) -> u128 {
+    let hash = |seed| {
+        let mut hasher = XxHash64::with_seed(seed);
+        hasher.write(report.gateway.as_ref());
+        hasher.write(&report.payload_hash);
+        hasher.write_i64(report.received_timestamp.timestamp_millis());
+        hasher.finish() as u128
+    };
+    hash(0) << 64 | hash(1)
+}
+
+#[async_trait]
+pub trait PacketDeduper: Send {
+    /// Remember the packets of a batch of reports, returning for each whether
+    /// it was seen before, repeats within the batch included. Packets are
+    /// remembered through the pending burns of the report file, so they are
+    /// committed or rolled back with its burns
+    async fn insert_batch<B>(
+        &mut self,
+        ids: &[u128],
+        pending_burns: &mut B,
+    ) -> Result<Vec<bool>, B::Error>
+    where
+        B: PendingBurns + Send;
+
+    fn record_metrics(&self) {}
+}
+
+pub struct PacketsSeen {
+    window: usize,
+    recent: HashSet<u128>,
+    order: VecDeque<u128>,
+    filter: Option<BloomFilter>,
+}
+
+impl PacketsSeen {
+    pub fn new(settings: &PacketsSeenSettings) -> Self {
+        Self {
+            window: settings.window.max(1),
+            recent: HashSet::new(),
+            order: VecDeque::new(),
+            filter: settings
+                .filter_capacity
+                .map(|capacity| BloomFilter::new(capacity, settings.false_positive_rate)),
+        }
+    }
+
+    /// Remember the packet, returning whether it was seen before
+    pub fn insert(&mut self, id: u128) -> bool {
+        if self.recent.contains(&id) || self.filter.as_ref().map_or(false, |f| f.contains(id)) {
+            return true;
+        }
+        self.recent.insert(id);
+        self.order.push_back(id);
+        if self.order.len() > self.window {
+            if let Some(old) = self.order.pop_front() {
+                self.recent.remove(&old);
+                if let Some(filter) = &mut self.filter {
+                    filter.insert(old);
+                }
+            }
+        }
+        false
+    }
+
+    /// Estimated bytes used to remember the packets
+    pub fn memory_bytes(&self) -> usize {
+        self.order.len() * WINDOW_ENTRY_BYTES
+            + self.filter.as_ref().map_or(0, BloomFilter::memory_bytes)
+    }
+
+    pub fn record_metrics(&self) {
+        metrics::gauge!("packets_seen_window", self.order.len() as f64);
+        metrics::gauge!(
+            "packets_seen_filter",
+            self.filter.as_ref().map_or(0, |filter| filter.len) as f64
+        );
+        metrics::gauge!("packets_seen_bytes", self.memory_bytes() as f64);
+    }
+}
+
+#[async_trait]
+impl PacketDeduper for PacketsSeen {
+    async fn insert_batch<B>(
+        &mut self,
+        ids: &[u128],
+        _pending_burns: &mut B,
+    ) -> Result<Vec<bool>, B::Error>
+    where
+        B: PendingBurns + Send,
+    {
+        Ok(ids.iter().map(|id| self.insert(*id)).collect())
+    }
+
+    fn record_metrics(&self) {
+        PacketsSeen::record_metrics(self)
+    }
+}
+
+/// Packets remembered by the packets_seen table, claimed through the pending
+/// burns of the report file
+pub struct PacketsSeenDb;
+
+#[async_trait]
+impl PacketDeduper for PacketsSeenDb {
+    async fn insert_batch<B>(
+        &mut self,
+        ids: &[u128],
+        pending_burns: &mut B,
+    ) -> Result<Vec<bool>, B::Error>
+    where
+        B: PendingBurns + Send,
+    {
+        let mut claimed = pending_burns.claim_packets(ids).await?;
+        // only the first report of a packet claimed by the batch is new
+        Ok(ids.iter().map(|id| !claimed.remove(id)).collect())
+    }
+}
+
+/// Claim the packets in the packets_seen table, returning those that were
+/// not claimed before
+pub async fn claim(
+    db: impl sqlx::PgExecutor<'_>,
+    ids: &[u128],
+) -> Result<HashSet<u128>, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let ids: Vec<Vec<u8>> = ids.iter().map(|id| id.to_be_bytes().to_vec()).collect();
+    let claimed: Vec<Vec<u8>> = sqlx::query_scalar(
+        r#"
+        INSERT INTO packets_seen (id, seen_at)
+        SELECT DISTINCT id, $2 FROM UNNEST($1::bytea[]) AS id
+        ON CONFLICT (id) DO NOTHING
+        RETURNING id
+        "#,
+    )
+    .bind(ids)
+    .bind(Utc::now())
+    .fetch_all(db)
+    .await?;
+    Ok(claimed
+        .into_iter()
+        .filter_map(|id| id.try_into().ok().map(u128::from_be_bytes))
+        .collect())
+}
+
+/// The deduper of the configured backend
+pub enum Deduper {
+    Memory(PacketsSeen),
+    Postgres(PacketsSeenDb),
+}
+
+impl Deduper {
+    pub fn new(settings: &PacketsSeenSettings) -> Self {
+        match settings.backend {
+            DedupeBackend::Memory => Self::Memory(PacketsSeen::new(settings)),
+            DedupeBackend::Postgres => Self::Postgres(PacketsSeenDb),
+        }
+    }
+}
+
+#[async_trait]
+impl PacketDeduper for Deduper {
+    async fn insert_batch<B>(
+        &mut self,
+        ids: &[u128],
+        pending_burns: &mut B,
+    ) -> Result<Vec<bool>, B::Error>
+    where
+        B: PendingBurns + Send,
+    {
+        match self {
+            Self::Memory(seen) => seen.insert_batch(ids, pending_burns).await,
+            Self::Postgres(seen) => seen.insert_batch(ids, pending_burns).await,
+        }
+    }
+
+    fn record_metrics(&self) {
+        match self {
+            Self::Memory(seen) => seen.record_metrics(),
+            Self::Postgres(_) => (),
+        }
+    }
+}
+
+struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    hashes: u32,
+    capacity: usize,
+    len: usize,
+}
+
+impl BloomFilter {
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-(capacity as f64) * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let hashes = ((num_bits as f64 / capacity as f64) * ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; ((num_bits + 63) / 64) as usize],
+            num_bits,
+            hashes,
+            capacity,
+            len: 0,
+        }
+    }
+
+    /// The bits of the id, double hashed from its two halves
+    fn positions(&self, id: u128) -> impl Iterator<Item = u64> + '_ {
+        let (h1, h2) = ((id >> 64) as u64, id as u64 | 1);
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    fn contains(&self, id: u128) -> bool {
+        self.positions(id)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    fn insert(&mut self, id: u128) {
+        if self.len >= self.capacity {
+            self.bits.iter_mut().for_each(|word| *word = 0);
+            self.len = 0;
+        }
+        let positions: Vec<u64> = self.positions(id).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.bits.len() * std::mem::size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_are_remembered_in_window_only_without_filter() {
+        let mut seen = PacketsSeen::new(&PacketsSeenSettings {
+            window: 2,
+            ..Default::default()
+        });
+        assert!(!seen.insert(1));
+        assert!(!seen.insert(2));
+        assert!(seen.insert(1));
+        assert!(!seen.insert(3));
+        // 1 left the window
+        assert!(!seen.insert(1));
+        assert_eq!(seen.memory_bytes(), 2 * WINDOW_ENTRY_BYTES);
+    }
+
+    #[test]
+    fn packets_leaving_window_are_remembered_by_filter() {
+        let mut seen = PacketsSeen::new(&PacketsSeenSettings {
+            window: 10,
+            filter_capacity: Some(10_000),
+            false_positive_rate: 0.001,
+            ..Default::default()
+        });
+        let ids: Vec<u128> = (0..10_000u128)
+            .map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c835))
+            .collect();
+        for id in &ids {
+            assert!(!seen.insert(*id));
+        }
+        assert!(ids.iter().all(|id| seen.insert(*id)));
+
+        let false_positives = (10_000..110_000u128)
+            .map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c835))
+            .filter(|id| seen.filter.as_ref().unwrap().contains(*id))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+        // About 14.4 bits per packet for a rate of 0.001
+        assert!(seen.memory_bytes() < 10 * WINDOW_ENTRY_BYTES + 10_000 * 2);
+    }
+}
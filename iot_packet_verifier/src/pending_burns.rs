@@ -1,9 +1,15 @@
+use crate::{org_intents, packets_seen};
 use async_trait::async_trait;
 use chrono::Utc;
 use futures::{stream, Stream, StreamExt};
 use helium_crypto::PublicKeyBinary;
 use sqlx::{FromRow, Pool, Postgres, Transaction};
-use std::{collections::HashMap, convert::Infallible, pin::Pin, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    pin::Pin,
+    sync::Arc,
+};
 use tokio::sync::Mutex;
 
 #[async_trait]
@@ -27,6 +33,18 @@ pub trait PendingBurns {
         payer: &PublicKeyBinary,
         amount: u64,
     ) -> Result<(), Self::Error>;
+
+    /// Record the intended state of an org, committed together with the
+    /// burned amounts. See [crate::org_intents]
+    async fn record_org_intent(&mut self, oui: u64, enabled: bool) -> Result<(), Self::Error>;
+
+    /// Note the config service applied the intended state of the org
+    async fn org_intent_sent(&mut self, oui: u64, enabled: bool) -> Result<(), Self::Error>;
+
+    /// Claim the packets as seen, committed together with the burned
+    /// amounts. Returns the packets not claimed before. See
+    /// [crate::packets_seen]
+    async fn claim_packets(&mut self, ids: &[u128]) -> Result<HashSet<u128>, Self::Error>;
 }
 
 const BURN_THRESHOLD: i64 = 10_000;
@@ -91,6 +109,18 @@ impl PendingBurns for Pool<Postgres> {
         .await?;
         Ok(())
     }
+
+    async fn record_org_intent(&mut self, oui: u64, enabled: bool) -> Result<(), Self::Error> {
+        org_intents::record(&*self, oui, enabled).await
+    }
+
+    async fn org_intent_sent(&mut self, oui: u64, enabled: bool) -> Result<(), Self::Error> {
+        org_intents::mark_sent(&*self, oui, enabled).await
+    }
+
+    async fn claim_packets(&mut self, ids: &[u128]) -> Result<HashSet<u128>, Self::Error> {
+        packets_seen::claim(&*self, ids).await
+    }
 }
 
 #[async_trait]
@@ -153,6 +183,18 @@ impl PendingBurns for &'_ mut Transaction<'_, Postgres> {
         .await?;
         Ok(())
     }
+
+    async fn record_org_intent(&mut self, oui: u64, enabled: bool) -> Result<(), Self::Error> {
+        org_intents::record(&mut **self, oui, enabled).await
+    }
+
+    async fn org_intent_sent(&mut self, oui: u64, enabled: bool) -> Result<(), Self::Error> {
+        org_intents::mark_sent(&mut **self, oui, enabled).await
+    }
+
+    async fn claim_packets(&mut self, ids: &[u128]) -> Result<HashSet<u128>, Self::Error> {
+        packets_seen::claim(&mut **self, ids).await
+    }
 }
 
 #[async_trait]
@@ -209,6 +251,19 @@ impl PendingBurns for Arc<Mutex<HashMap<PublicKeyBinary, u64>>> {
         *map.entry(payer.clone()).or_default() += amount;
         Ok(())
     }
+
+    async fn record_org_intent(&mut self, _oui: u64, _enabled: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn org_intent_sent(&mut self, _oui: u64, _enabled: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Packets are not remembered, every packet is claimed
+    async fn claim_packets(&mut self, ids: &[u128]) -> Result<HashSet<u128>, Self::Error> {
+        Ok(ids.iter().copied().collect())
+    }
 }
 
 #[derive(FromRow, Debug)]
@@ -0,0 +1,341 @@
+//! Reconciliation of the burn journal against the burns on chain.
+//!
+//! The burner records the signature of every burn transaction in the burn
+//! journal. The reconciler periodically fetches the transactions signed by
+//! the burn authority and compares them with the journal. Journaled burns
+//! that do not show up on chain are missing confirmations, successful
+//! transactions that were never journaled are unknown burns. Both are
+//! reported as gauges and, when there are any, in a report file.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use helium_crypto::PublicKeyBinary;
+use serde::Serialize;
+use solana::{ChainBurn, SolanaNetwork};
+use sqlx::{FromRow, Pool, Postgres};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+const MISSING_CONFIRMATIONS_METRIC: &str = "burn_reconciliation_missing_confirmations";
+const UNKNOWN_BURNS_METRIC: &str = "burn_reconciliation_unknown_burns";
+
+/// Time a journaled burn has to show up in the finalized history before it
+/// is reported as missing
+const CONFIRMATION_GRACE_MINUTES: i64 = 10;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReconcileError<S> {
+    #[error("Sql error: {0}")]
+    SqlError(#[from] sqlx::Error),
+    #[error("Solana error: {0}")]
+    SolanaError(S),
+    #[error("Io error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Json error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+/// Record a burn transaction submitted by the burner
+pub async fn record_burn(
+    pool: &Pool<Postgres>,
+    signature: &str,
+    payer: &PublicKeyBinary,
+    amount: u64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO burn_journal (signature, payer, amount, burned_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (signature) DO NOTHING
+        "#,
+    )
+    .bind(signature)
+    .bind(payer)
+    .bind(amount as i64)
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(FromRow, Debug, Clone)]
+pub struct JournaledBurn {
+    pub signature: String,
+    pub payer: PublicKeyBinary,
+    pub amount: i64,
+    pub burned_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MissingBurn {
+    pub signature: String,
+    pub payer: String,
+    pub amount: i64,
+    pub burned_at: DateTime<Utc>,
+    /// The transaction is on chain but failed
+    pub failed: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct UnknownBurn {
+    pub signature: String,
+    pub block_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct Reconciliation {
+    /// Signatures of the journaled burns seen on chain for the first time
+    #[serde(skip)]
+    pub confirmed: Vec<String>,
+    pub missing: Vec<MissingBurn>,
+    pub unknown: Vec<UnknownBurn>,
+}
+
+impl Reconciliation {
+    pub fn has_discrepancies(&self) -> bool {
+        !self.missing.is_empty() || !self.unknown.is_empty()
+    }
+}
+
+/// Compare the journal with the chain history. Journaled burns made before
+/// `confirm_by` must be on chain, and successful chain transactions made at
+/// or after `since` must be journaled.
+pub fn reconcile(
+    journal: &[JournaledBurn],
+    chain: &[ChainBurn],
+    since: DateTime<Utc>,
+    confirm_by: DateTime<Utc>,
+) -> Reconciliation {
+    let on_chain: HashMap<&str, &ChainBurn> = chain
+        .iter()
+        .map(|burn| (burn.signature.as_str(), burn))
+        .collect();
+    let journaled: HashSet<&str> = journal.iter().map(|burn| burn.signature.as_str()).collect();
+
+    let mut reconciliation = Reconciliation::default();
+    for burn in journal {
+        match on_chain.get(burn.signature.as_str()) {
+            Some(chain_burn) if !chain_burn.failed => {
+                if burn.confirmed_at.is_none() {
+                    reconciliation.confirmed.push(burn.signature.clone());
+                }
+            }
+            // Confirmed by an earlier run and since fallen out of the history
+            None if burn.confirmed_at.is_some() => (),
+            None if burn.burned_at >= confirm_by => (),
+            chain_burn => reconciliation.missing.push(MissingBurn {
+                signature: burn.signature.clone(),
+                payer: burn.payer.to_string(),
+                amount: burn.amount,
+                burned_at: burn.burned_at,
+                failed: chain_burn.is_some(),
+            }),
+        }
+    }
+
+    for burn in chain {
+        let block_time = burn
+            .block_time
+            .and_then(|block_time| Utc.timestamp_opt(block_time, 0).single());
+        if burn.failed
+            || journaled.contains(burn.signature.as_str())
+            || block_time.is_some_and(|block_time| block_time < since)
+        {
+            continue;
+        }
+        reconciliation.unknown.push(UnknownBurn {
+            signature: burn.signature.clone(),
+            block_time,
+        });
+    }
+    reconciliation
+}
+
+pub struct Reconciler<S> {
+    pool: Pool<Postgres>,
+    solana: S,
+    period: std::time::Duration,
+    lookback: Duration,
+    reports: PathBuf,
+}
+
+impl<S> Reconciler<S>
+where
+    S: SolanaNetwork,
+{
+    /// Reconcile the burns of the last `lookback_hours` every
+    /// `period_minutes`, writing reports of discrepancies to `reports`
+    pub fn new(
+        pool: Pool<Postgres>,
+        solana: S,
+        period_minutes: u64,
+        lookback_hours: u64,
+        reports: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            pool,
+            solana,
+            period: std::time::Duration::from_secs(60 * period_minutes),
+            lookback: Duration::hours(lookback_hours as i64),
+            reports: reports.into(),
+        }
+    }
+
+    pub async fn run(self, shutdown: triggered::Listener) -> anyhow::Result<()> {
+        let mut trigger = tokio::time::interval(self.period);
+        trigger.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => return Ok(()),
+                _ = trigger.tick() => {
+                    if let Err(err) = self.reconcile().await {
+                        tracing::error!("failed to reconcile burns: {err}");
+                    }
+                }
+            }
+        }
+    }
+
+    pub async fn reconcile(&self) -> Result<Reconciliation, ReconcileError<S::Error>> {
+        let now = Utc::now();
+        // Burns made before the journal existed are not unknown
+        let first_journaled: Option<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT MIN(burned_at) FROM burn_journal")
+                .fetch_one(&self.pool)
+                .await?;
+        let since = first_journaled.unwrap_or(now).max(now - self.lookback);
+        let grace = Duration::minutes(CONFIRMATION_GRACE_MINUTES);
+
+        let journal: Vec<JournaledBurn> =
+            sqlx::query_as("SELECT * FROM burn_journal WHERE burned_at >= $1")
+                .bind(since)
+                .fetch_all(&self.pool)
+                .await?;
+        // Transactions land on chain before they are journaled
+        let chain = self
+            .solana
+            .burn_history((since - grace).timestamp())
+            .await
+            .map_err(ReconcileError::SolanaError)?;
+
+        let reconciliation = reconcile(&journal, &chain, since, now - grace);
+
+        if !reconciliation.confirmed.is_empty() {
+            sqlx::query("UPDATE burn_journal SET confirmed_at = $1 WHERE signature = ANY($2)")
+                .bind(now)
+                .bind(&reconciliation.confirmed)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        metrics::gauge!(
+            MISSING_CONFIRMATIONS_METRIC,
+            reconciliation.missing.len() as f64
+        );
+        metrics::gauge!(UNKNOWN_BURNS_METRIC, reconciliation.unknown.len() as f64);
+
+        if reconciliation.has_discrepancies() {
+            let path = self.write_report(now, &reconciliation).await?;
+            tracing::warn!(
+                missing = reconciliation.missing.len(),
+                unknown = reconciliation.unknown.len(),
+                report = %path.display(),
+                "burn journal does not match chain"
+            );
+        }
+        Ok(reconciliation)
+    }
+
+    async fn write_report(
+        &self,
+        now: DateTime<Utc>,
+        reconciliation: &Reconciliation,
+    ) -> Result<PathBuf, ReconcileError<S::Error>> {
+        tokio::fs::create_dir_all(&self.reports).await?;
+        let path = self.reports.join(format!(
+            "burn_reconciliation.{}.json",
+            now.timestamp_millis()
+        ));
+        tokio::fs::write(&path, serde_json::to_vec_pretty(reconciliation)?).await?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journaled(signature: &str, burned_at: DateTime<Utc>, confirmed: bool) -> JournaledBurn {
+        JournaledBurn {
+            signature: signature.to_string(),
+            payer: PublicKeyBinary::from(vec![1]),
+            amount: 10_000,
+            burned_at,
+            confirmed_at: confirmed.then_some(burned_at),
+        }
+    }
+
+    fn on_chain(signature: &str, block_time: DateTime<Utc>, failed: bool) -> ChainBurn {
+        ChainBurn {
+            signature: signature.to_string(),
+            block_time: Some(block_time.timestamp()),
+            failed,
+        }
+    }
+
+    #[test]
+    fn discrepancies_are_found_in_both_directions() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let since = now - Duration::hours(24);
+        let confirm_by = now - Duration::minutes(10);
+        let earlier = now - Duration::hours(1);
+
+        let journal = vec![
+            journaled("confirmed", earlier, false),
+            journaled("already-confirmed", earlier, true),
+            journaled("failed", earlier, false),
+            journaled("missing", earlier, false),
+            journaled("recent", now, false),
+        ];
+        let chain = vec![
+            on_chain("confirmed", earlier, false),
+            on_chain("failed", earlier, true),
+            on_chain("unknown", earlier, false),
+            on_chain("unknown-failed", earlier, true),
+            on_chain("before-journal", since - Duration::minutes(1), false),
+        ];
+
+        let reconciliation = reconcile(&journal, &chain, since, confirm_by);
+
+        assert_eq!(reconciliation.confirmed, vec!["confirmed".to_string()]);
+        let missing: Vec<_> = reconciliation
+            .missing
+            .iter()
+            .map(|burn| (burn.signature.as_str(), burn.failed))
+            .collect();
+        assert_eq!(missing, vec![("failed", true), ("missing", false)]);
+        assert_eq!(
+            reconciliation.unknown,
+            vec![UnknownBurn {
+                signature: "unknown".to_string(),
+                block_time: Some(earlier),
+            }]
+        );
+        assert!(reconciliation.has_discrepancies());
+    }
+
+    #[test]
+    fn matching_history_has_no_discrepancies() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let earlier = now - Duration::hours(1);
+        let reconciliation = reconcile(
+            &[journaled("a", earlier, true)],
+            &[on_chain("a", earlier, false)],
+            now - Duration::hours(24),
+            now,
+        );
+        assert_eq!(reconciliation, Reconciliation::default());
+    }
+}
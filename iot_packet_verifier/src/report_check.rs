@@ -0,0 +1,102 @@
+//! Optional verification of the packet reports before they are charged.
+//!
+//! Packet router reports do not carry a gateway signature in the current
+//! proto, so the gateway bytes are only checked to be a valid public key. The
+//! net id must be a 24 bit LoRaWAN NetID and match the net id of the org when
+//! the config service knows it. Reports with an unsupported region or data
+//! rate are already dropped when decoded.
+
+use file_store::iot_packet::PacketRouterPacketReport;
+use helium_crypto::PublicKey;
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportCheckError {
+    #[error("invalid gateway key")]
+    InvalidGateway,
+    #[error("invalid net id {0:#08x}")]
+    InvalidNetId(u32),
+    #[error("net id {net_id:#08x} does not match org net id {org_net_id:#08x}")]
+    NetIdMismatch { net_id: u32, org_net_id: u32 },
+}
+
+pub fn check_report(
+    report: &PacketRouterPacketReport,
+    org_net_id: Option<u32>,
+) -> Result<(), ReportCheckError> {
+    PublicKey::try_from(report.gateway.as_ref()).map_err(|_| ReportCheckError::InvalidGateway)?;
+    if report.net_id > 0xFF_FFFF {
+        return Err(ReportCheckError::InvalidNetId(report.net_id));
+    }
+    match org_net_id {
+        Some(org_net_id) if org_net_id != report.net_id => Err(ReportCheckError::NetIdMismatch {
+            net_id: report.net_id,
+            org_net_id,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use helium_crypto::{KeyTag, KeyType, Keypair, Network, PublicKeyBinary};
+    use helium_proto::{DataRate, Region};
+    use rand::rngs::OsRng;
+
+    fn report(gateway: Vec<u8>, net_id: u32) -> PacketRouterPacketReport {
+        PacketRouterPacketReport {
+            received_timestamp: Utc::now(),
+            oui: 1,
+            net_id,
+            rssi: 0,
+            frequency: 0,
+            snr: 0.0,
+            data_rate: DataRate::Fsk50,
+            region: Region::Us915,
+            gateway: PublicKeyBinary::from(gateway),
+            payload_hash: vec![],
+            payload_size: 24,
+        }
+    }
+
+    fn gateway() -> Vec<u8> {
+        Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::Ed25519,
+            },
+            &mut OsRng,
+        )
+        .public_key()
+        .to_vec()
+    }
+
+    #[test]
+    fn gateway_must_be_a_public_key() {
+        assert_eq!(check_report(&report(gateway(), 0xC00053), None), Ok(()));
+        assert_eq!(
+            check_report(&report(vec![1, 2, 3], 0xC00053), None),
+            Err(ReportCheckError::InvalidGateway)
+        );
+    }
+
+    #[test]
+    fn net_id_must_be_valid_and_match_the_org() {
+        assert_eq!(
+            check_report(&report(gateway(), 0x100_0000), None),
+            Err(ReportCheckError::InvalidNetId(0x100_0000))
+        );
+        assert_eq!(
+            check_report(&report(gateway(), 0xC00053), Some(0xC00053)),
+            Ok(())
+        );
+        assert_eq!(
+            check_report(&report(gateway(), 0x00003C), Some(0xC00053)),
+            Err(ReportCheckError::NetIdMismatch {
+                net_id: 0x00003C,
+                org_net_id: 0xC00053
+            })
+        );
+    }
+}
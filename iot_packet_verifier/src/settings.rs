@@ -1,7 +1,14 @@
 use chrono::{DateTime, TimeZone, Utc};
-use config::{Config, ConfigError, Environment, File};
+use grpc_service::tls::TlsSettings;
+use helium_crypto::PublicKey;
+use oracle_settings::{Overrides, Reload, SettingsLoader, SettingsWatcher, Validate};
 use serde::Deserialize;
-use std::path::Path;
+use std::{
+    net::{AddrParseError, SocketAddr},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
@@ -9,6 +16,9 @@ pub struct Settings {
     /// "iot_packet_verifier=debug,poc_store=info"
     #[serde(default = "default_log")]
     pub log: String,
+    /// Log output format and per module log levels
+    #[serde(default)]
+    pub tracing: custom_tracing::Settings,
     /// Cache location for generated verified reports
     pub cache: String,
     /// Data credit burn period in minutes. Default is 1.
@@ -18,19 +28,104 @@ pub struct Settings {
     pub ingest: file_store::Settings,
     pub iot_config_client: iot_config::client::Settings,
     pub output: file_store::Settings,
+    /// Output policies of the verified packet sinks keyed by file type
+    #[serde(default)]
+    pub sinks: file_store::SinksSettings,
+    /// Minutes of the windows the valid and invalid packet sinks roll
+    /// together in. Default is 3
+    #[serde(default = "default_packet_roll_minutes")]
+    pub packet_roll_minutes: u64,
     pub metrics: poc_metrics::Settings,
+    /// Leader election between replicas. Every instance is active when not set
+    pub leader_election: Option<db_store::LeaderElectionSettings>,
+    /// Retention of the pending burns, burn journal and packet statistics.
+    /// Nothing is purged by default
+    #[serde(default)]
+    pub maintenance: db_store::MaintenanceSettings,
     #[serde(default)]
     pub enable_solana_integration: bool,
     /// Minimum data credit balance required for a payer before we disable them
     #[serde(default = "default_minimum_allowed_balance")]
     pub minimum_allowed_balance: u64,
+    /// When orgs whose payer is below the minimum allowed balance are
+    /// disabled and re-enabled. Disabled on the first low balance by default
+    #[serde(default)]
+    pub org_lock: crate::org_lock::OrgLockSettings,
+    /// Webhook the org state change events are posted to, besides being
+    /// written to the org state change files. Not posted when not set
+    pub org_webhook: Option<crate::org_events::WebhookSettings>,
+    /// Data credits charged per packet payload. Default is a data credit per
+    /// 24 bytes, rounded up, at least one per packet
+    #[serde(default = "default_dc_pricing")]
+    pub dc_pricing: dc_pricing::DcPricing,
+    /// Check the gateway key and net id of packet reports, writing failing
+    /// packets as invalid with an invalid report reason. Default is false.
+    #[serde(default)]
+    pub verify_reports: bool,
+    /// Remember the packets verified, charging a packet reported more than
+    /// once only for its first report. Disabled by default
+    pub packets_seen: Option<crate::packets_seen::PacketsSeenSettings>,
     pub solana: Option<solana::Settings>,
+    /// Burn on a simulated chain instead of solana, for staging environments.
+    /// Cannot be combined with the solana integration
+    pub mock_solana: Option<solana::mock::Settings>,
     #[serde(default = "default_start_after")]
     pub start_after: u64,
     /// Number of minutes we should sleep before checking to re-enable
     /// any disabled orgs.
     #[serde(default = "default_monitor_funds_period")]
     pub monitor_funds_period: u64,
+    /// Number of minutes between fetching the balances of all cached payers
+    /// from chain. Default is 10.
+    #[serde(default = "default_refresh_balances_period")]
+    pub refresh_balances_period: u64,
+    /// Period between fetching the balances of the cached payers too low for
+    /// their orgs to be enabled from chain, to pick up their top ups quickly.
+    /// Default is 30 seconds.
+    #[serde(with = "oracle_settings::duration", default = "default_top_up_period")]
+    pub top_up_period: Duration,
+    /// Number of minutes between reconciling the burn journal against the
+    /// burns on chain. Default is 60.
+    #[serde(default = "default_reconcile_period")]
+    pub reconcile_period: u64,
+    /// Number of hours of burns compared by each reconciliation. Default is 24.
+    #[serde(default = "default_reconcile_lookback")]
+    pub reconcile_lookback: u64,
+    /// Number of minutes between sending the org state changes the config
+    /// service failed to apply again. Default is 5.
+    #[serde(default = "default_org_intents_period")]
+    pub org_intents_period: u64,
+    /// Number of seconds between sending the org state changes of the
+    /// verifier to the config service in a single batch, rather than one
+    /// request per org as they happen. Disabled when not set
+    pub org_batch_secs: Option<u64>,
+    /// Listen address of the signed packet statistics grpc endpoint for
+    /// orgs. Disabled when not set
+    pub listen: Option<String>,
+    /// Serve the grpc endpoint over TLS when set. Plaintext by default
+    pub tls: Option<TlsSettings>,
+    /// Accept api tokens minted by the config service in place of signed
+    /// packet statistics requests. Default is false
+    #[serde(default)]
+    pub api_tokens: bool,
+    /// How long the revocation status of an api token is cached. Default is
+    /// 60 seconds
+    #[serde(
+        with = "oracle_settings::duration",
+        default = "default_api_token_status_ttl"
+    )]
+    pub api_token_status_ttl: Duration,
+    /// B58 encoded admin keys allowed to change the log filter at runtime
+    /// through the listen address. The endpoint is disabled when empty
+    #[serde(default, deserialize_with = "oracle_settings::pubkey::deserialize_vec")]
+    pub admin_keys: Vec<PublicKey>,
+    /// Listen address of the read-only json api of the verifier state, only
+    /// served when built with the explorer feature. Disabled when not set
+    pub explorer_listen: Option<String>,
+}
+
+pub fn default_api_token_status_ttl() -> Duration {
+    Duration::from_secs(iot_config::client::api_token_client::DEFAULT_STATUS_TTL_SECS)
 }
 
 pub fn default_start_after() -> u64 {
@@ -45,6 +140,10 @@ pub fn default_log() -> String {
     "iot_packet_verifier=debug".to_string()
 }
 
+pub fn default_dc_pricing() -> dc_pricing::DcPricing {
+    dc_pricing::DcPricing::iot()
+}
+
 pub fn default_minimum_allowed_balance() -> u64 {
     3_500_000
 }
@@ -53,27 +152,69 @@ pub fn default_monitor_funds_period() -> u64 {
     30
 }
 
+pub fn default_refresh_balances_period() -> u64 {
+    10
+}
+
+pub fn default_top_up_period() -> Duration {
+    Duration::from_secs(30)
+}
+
+pub fn default_reconcile_period() -> u64 {
+    60
+}
+
+pub fn default_reconcile_lookback() -> u64 {
+    24
+}
+
+pub fn default_org_intents_period() -> u64 {
+    5
+}
+
+pub fn default_packet_roll_minutes() -> u64 {
+    file_store::file_sink::DEFAULT_SINK_ROLL_MINS as u64
+}
+
 impl Settings {
     /// Load Settings from a given path. Settings are loaded from a given
-    /// optional path and can be overriden with environment variables.
+    /// optional path and can be overriden with environment variables and
+    /// `--set` command line overrides.
     ///
     /// Environemnt overrides have the same name as the entries in the settings
-    /// file in uppercase and prefixed with "VERIFY_". For example
-    /// "VERIFY_DATABASE_URL" will override the data base url.
-    pub fn new(path: Option<impl AsRef<Path>>) -> Result<Self, ConfigError> {
-        let mut builder = Config::builder();
-
-        if let Some(file) = path {
-            // Add optional settings file
-            builder = builder
-                .add_source(File::with_name(&file.as_ref().to_string_lossy()).required(false));
-        }
-        // Add in settings from the environment (with a prefix of VERIFY)
-        // Eg.. `INJECT_DEBUG=1 ./target/app` would set the `debug` key
-        builder
-            .add_source(Environment::with_prefix("PACKET_VERIFY").separator("_"))
-            .build()
-            .and_then(|config| config.try_deserialize())
+    /// file in uppercase and prefixed with "PACKET_VERIFY_". For example
+    /// "PACKET_VERIFY_DATABASE_URL" will override the data base url.
+    pub fn new(
+        path: Option<impl AsRef<Path>>,
+        overrides: &Overrides,
+    ) -> Result<Self, oracle_settings::Error> {
+        Self::loader(path, overrides).load()
+    }
+
+    /// Load the settings together with a watcher reloading the live settings
+    /// on SIGHUP or when the settings file changes
+    pub fn watch(
+        path: Option<impl AsRef<Path>>,
+        overrides: &Overrides,
+    ) -> Result<(Self, SettingsWatcher<Self>), oracle_settings::Error> {
+        SettingsWatcher::load(Self::loader(path, overrides))
+    }
+
+    fn loader(path: Option<impl AsRef<Path>>, overrides: &Overrides) -> SettingsLoader {
+        SettingsLoader::new("PACKET_VERIFY")
+            .file(path)
+            .overrides(overrides)
+    }
+
+    pub fn listen_addr(&self) -> Result<Option<SocketAddr>, AddrParseError> {
+        self.listen.as_deref().map(SocketAddr::from_str).transpose()
+    }
+
+    pub fn explorer_listen_addr(&self) -> Result<Option<SocketAddr>, AddrParseError> {
+        self.explorer_listen
+            .as_deref()
+            .map(SocketAddr::from_str)
+            .transpose()
     }
 
     pub fn start_after(&self) -> DateTime<Utc> {
@@ -82,3 +223,98 @@ impl Settings {
             .unwrap()
     }
 }
+
+/// Settings that are applied to the running verifier when reloaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveSettings {
+    pub log: String,
+    pub tracing: custom_tracing::Settings,
+    pub burn_period: u64,
+}
+
+impl Reload for Settings {
+    type Live = LiveSettings;
+    // The log output format is only set at startup
+    const LIVE_KEYS: &'static [&'static str] = &["log", "tracing.modules", "burn_period"];
+
+    fn live(&self) -> LiveSettings {
+        LiveSettings {
+            log: self.log.clone(),
+            tracing: self.tracing.clone(),
+            burn_period: self.burn_period,
+        }
+    }
+}
+
+impl Validate for Settings {
+    fn validate(&self) -> Result<(), oracle_settings::Error> {
+        if self.enable_solana_integration && self.solana.is_none() {
+            return Err(oracle_settings::Error::Invalid(
+                "solana section is required when solana integration is enabled".to_string(),
+            ));
+        }
+        if let Some(mock_solana) = &self.mock_solana {
+            if self.enable_solana_integration {
+                return Err(oracle_settings::Error::Invalid(
+                    "mock_solana cannot be set with the solana integration enabled".to_string(),
+                ));
+            }
+            if let Some(problem) = mock_solana.problems().into_iter().next() {
+                return Err(oracle_settings::Error::Invalid(problem));
+            }
+        }
+        if self.burn_period == 0
+            || self.monitor_funds_period == 0
+            || self.refresh_balances_period == 0
+            || self.top_up_period.is_zero()
+            || self.reconcile_period == 0
+            || self.org_intents_period == 0
+            || self.packet_roll_minutes == 0
+        {
+            return Err(oracle_settings::Error::Invalid(
+                "burn_period, monitor_funds_period, refresh_balances_period, top_up_period, reconcile_period, org_intents_period and packet_roll_minutes must be positive"
+                    .to_string(),
+            ));
+        }
+        if self.org_batch_secs == Some(0) {
+            return Err(oracle_settings::Error::Invalid(
+                "org_batch_secs must be positive".to_string(),
+            ));
+        }
+        if self.explorer_listen.is_some() && !cfg!(feature = "explorer") {
+            return Err(oracle_settings::Error::Invalid(
+                "explorer_listen requires the explorer feature".to_string(),
+            ));
+        }
+        if let Err(err) = self.explorer_listen_addr() {
+            return Err(oracle_settings::Error::Invalid(format!(
+                "invalid explorer_listen address: {err}"
+            )));
+        }
+        if let Some(packets_seen) = &self.packets_seen {
+            if let Some(problem) = packets_seen.problems().into_iter().next() {
+                return Err(oracle_settings::Error::Invalid(problem));
+            }
+        }
+        if let Some(leader_election) = &self.leader_election {
+            if let Some(problem) = leader_election.problems().into_iter().next() {
+                return Err(oracle_settings::Error::Invalid(problem));
+            }
+        }
+        if let Some(problem) = self.org_lock.problems().into_iter().next() {
+            return Err(oracle_settings::Error::Invalid(problem));
+        }
+        if let Some(org_webhook) = &self.org_webhook {
+            if let Some(problem) = org_webhook.problems().into_iter().next() {
+                return Err(oracle_settings::Error::Invalid(problem));
+            }
+        }
+        if let Some(problem) = self.iot_config_client.problems().into_iter().next() {
+            return Err(oracle_settings::Error::Invalid(problem));
+        }
+        if let Some(problem) = self.dc_pricing.problems().into_iter().next() {
+            return Err(oracle_settings::Error::Invalid(problem));
+        }
+        Ok(())
+    }
+}
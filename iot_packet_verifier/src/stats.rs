@@ -0,0 +1,148 @@
+//! Hourly packet verification statistics of each org.
+//!
+//! The verifier counts the packets of every report file by oui, hour and
+//! outcome. The counts are added to the totals in the database in the same
+//! transaction that marks the file as processed, so they match the verified
+//! and invalid packets written out.
+
+use chrono::{DateTime, DurationRound, Utc};
+use helium_proto::services::packet_verifier::InvalidPacketReason;
+use sqlx::{FromRow, Pool, Postgres, Transaction};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    Valid,
+    Invalid(InvalidPacketReason),
+    /// The report itself failed the report check
+    InvalidReport,
+    /// The packet was reported before
+    Duplicate,
+    /// The org is unknown to the config service
+    UnknownOui,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Valid => "valid",
+            Self::Invalid(reason) => reason.as_str_name(),
+            Self::InvalidReport => "invalid_report",
+            Self::Duplicate => "duplicate",
+            Self::UnknownOui => "unknown_oui",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub packets: u64,
+    /// Data credits debited for the packets, zero for invalid packets
+    pub dcs: u64,
+}
+
+/// Packet counts keyed by oui, hour and outcome
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PacketStats(HashMap<(u64, DateTime<Utc>, Outcome), Counts>);
+
+impl PacketStats {
+    pub fn record(&mut self, oui: u64, received: DateTime<Utc>, outcome: Outcome, dcs: u64) {
+        let hour = received
+            .duration_trunc(chrono::Duration::hours(1))
+            .unwrap_or(received);
+        let counts = self.0.entry((oui, hour, outcome)).or_default();
+        counts.packets += 1;
+        counts.dcs += dcs;
+    }
+
+    pub fn get(&self, oui: u64, hour: DateTime<Utc>, outcome: Outcome) -> Option<Counts> {
+        self.0.get(&(oui, hour, outcome)).copied()
+    }
+
+    /// Add the counts to the totals in the database
+    pub async fn save(&self, transaction: &mut Transaction<'_, Postgres>) -> sqlx::Result<()> {
+        for ((oui, hour, outcome), counts) in &self.0 {
+            sqlx::query(
+                r#"
+                INSERT INTO packet_stats (oui, hour, outcome, packets, dcs)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (oui, hour, outcome) DO UPDATE SET
+                packets = packet_stats.packets + EXCLUDED.packets,
+                dcs = packet_stats.dcs + EXCLUDED.dcs
+                "#,
+            )
+            .bind(*oui as i64)
+            .bind(hour)
+            .bind(outcome.as_str())
+            .bind(counts.packets as i64)
+            .bind(counts.dcs as i64)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct HourlyStats {
+    pub hour: DateTime<Utc>,
+    pub outcome: String,
+    pub packets: i64,
+    pub dcs: i64,
+}
+
+/// The totals of the org for the hours in `[after, before)`, oldest first
+pub async fn fetch(
+    pool: &Pool<Postgres>,
+    oui: u64,
+    after: DateTime<Utc>,
+    before: DateTime<Utc>,
+) -> sqlx::Result<Vec<HourlyStats>> {
+    sqlx::query_as(
+        r#"
+        SELECT hour, outcome, packets, dcs FROM packet_stats
+        WHERE oui = $1 AND hour >= $2 AND hour < $3
+        ORDER BY hour, outcome
+        "#,
+    )
+    .bind(oui as i64)
+    .bind(after)
+    .bind(before)
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn packets_are_counted_per_hour_and_outcome() {
+        let hour = Utc.with_ymd_and_hms(2023, 9, 1, 10, 0, 0).unwrap();
+        let insufficient = Outcome::Invalid(InvalidPacketReason::InsufficientBalance);
+        let mut stats = PacketStats::default();
+        stats.record(1, hour + chrono::Duration::minutes(5), Outcome::Valid, 2);
+        stats.record(1, hour + chrono::Duration::minutes(59), Outcome::Valid, 3);
+        stats.record(1, hour + chrono::Duration::minutes(60), Outcome::Valid, 1);
+        stats.record(1, hour, insufficient, 0);
+        stats.record(2, hour, Outcome::Valid, 1);
+
+        assert_eq!(
+            stats.get(1, hour, Outcome::Valid),
+            Some(Counts { packets: 2, dcs: 5 })
+        );
+        assert_eq!(
+            stats.get(1, hour + chrono::Duration::hours(1), Outcome::Valid),
+            Some(Counts { packets: 1, dcs: 1 })
+        );
+        assert_eq!(
+            stats.get(1, hour, insufficient),
+            Some(Counts { packets: 1, dcs: 0 })
+        );
+        assert_eq!(
+            stats.get(2, hour, Outcome::Valid),
+            Some(Counts { packets: 1, dcs: 1 })
+        );
+    }
+}
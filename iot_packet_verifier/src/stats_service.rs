@@ -0,0 +1,303 @@
+//! Signed grpc query of the packet statistics of an org. Like the reward
+//! index query the service is hand written on top of the tonic primitives
+//! with its messages defined here.
+//!
+//! Requests are signed by the owner or one of the delegate keys of the org,
+//! so orgs can only query their own statistics. When api tokens are accepted
+//! a request may carry a token of the org with the packet stats scope,
+//! minted by the config service, instead of a signature.
+
+use crate::stats;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use helium_crypto::{PublicKey, PublicKeyBinary, Verify};
+use helium_proto::services::iot_config::OrgV1;
+use iot_config::{
+    api_token::PACKET_STATS_SCOPE,
+    client::{OrgClient, TokenVerifier},
+};
+use prost::Message;
+use sqlx::{Pool, Postgres};
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::Mutex;
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const SERVICE_NAME: &str = "helium.packet_verifier.stats";
+const QUERY_PATH: &str = "/helium.packet_verifier.stats/query";
+
+/// Longest range of hours a single request can query
+pub const MAX_QUERY_HOURS: i64 = 24 * 31;
+/// Requests signed longer ago, or further in the future, are rejected
+pub const MAX_SIGNATURE_AGE_SECS: i64 = 5 * 60;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PacketStatsReqV1 {
+    #[prost(uint64, tag = "1")]
+    pub oui: u64,
+    /// Seconds since the epoch of the first hour to return
+    #[prost(uint64, tag = "2")]
+    pub after: u64,
+    /// Seconds since the epoch of the end of the range, exclusive
+    #[prost(uint64, tag = "3")]
+    pub before: u64,
+    /// Milliseconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "4")]
+    pub timestamp: u64,
+    /// Owner or delegate key of the org
+    #[prost(bytes = "vec", tag = "5")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub signature: Vec<u8>,
+    /// Encoded api token of the org, in place of the timestamp, signer and
+    /// signature
+    #[prost(bytes = "vec", tag = "7")]
+    pub token: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PacketStatsV1 {
+    /// Seconds since the epoch of the start of the hour
+    #[prost(uint64, tag = "1")]
+    pub hour: u64,
+    /// "valid" or the reason the packets were invalid
+    #[prost(string, tag = "2")]
+    pub outcome: String,
+    #[prost(uint64, tag = "3")]
+    pub packets: u64,
+    /// Data credits spent on the packets
+    #[prost(uint64, tag = "4")]
+    pub dcs: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PacketStatsResV1 {
+    #[prost(uint64, tag = "1")]
+    pub oui: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub stats: Vec<PacketStatsV1>,
+}
+
+impl PacketStatsReqV1 {
+    /// The signer of the request if the signature is valid
+    fn verify(&self) -> Result<PublicKeyBinary, Status> {
+        let signer = PublicKey::try_from(self.signer.as_slice())
+            .map_err(|_| Status::invalid_argument("invalid signer"))?;
+        let unsigned = Self {
+            signature: vec![],
+            ..self.clone()
+        };
+        signer
+            .verify(&unsigned.encode_to_vec(), &self.signature)
+            .map_err(|_| Status::unauthenticated("invalid signature"))?;
+        Ok(signer.into())
+    }
+}
+
+/// Whether the key may query the statistics of the org
+fn is_authorized(org: &OrgV1, signer: &PublicKeyBinary) -> bool {
+    let signer: &[u8] = signer.as_ref();
+    org.owner == signer || org.delegate_keys.iter().any(|key| key == signer)
+}
+
+fn timestamp(secs: u64) -> Result<DateTime<Utc>, Status> {
+    Utc.timestamp_opt(secs as i64, 0)
+        .single()
+        .ok_or_else(|| Status::invalid_argument("invalid timestamp"))
+}
+
+#[derive(Clone)]
+pub struct StatsService {
+    pool: Pool<Postgres>,
+    org_client: Arc<Mutex<OrgClient>>,
+    tokens: Option<TokenVerifier>,
+}
+
+impl StatsService {
+    pub fn new(pool: Pool<Postgres>, org_client: Arc<Mutex<OrgClient>>) -> Self {
+        Self {
+            pool,
+            org_client,
+            tokens: None,
+        }
+    }
+
+    /// Accept api tokens of the org in place of signed requests
+    pub fn api_tokens(self, tokens: TokenVerifier) -> Self {
+        Self {
+            tokens: Some(tokens),
+            ..self
+        }
+    }
+
+    pub async fn query(&self, request: PacketStatsReqV1) -> Result<PacketStatsResV1, Status> {
+        if request.token.is_empty() {
+            self.authorize_signature(&request).await?;
+        } else {
+            let tokens = self
+                .tokens
+                .as_ref()
+                .ok_or_else(|| Status::unauthenticated("api tokens are not accepted"))?;
+            tokens
+                .verify(&request.token, request.oui, PACKET_STATS_SCOPE)
+                .await?;
+        }
+
+        let after = timestamp(request.after)?;
+        let before = timestamp(request.before)?;
+        if before <= after || before - after > Duration::hours(MAX_QUERY_HOURS) {
+            return Err(Status::invalid_argument(format!(
+                "range must be positive and at most {MAX_QUERY_HOURS} hours"
+            )));
+        }
+
+        let stats = stats::fetch(&self.pool, request.oui, after, before)
+            .await
+            .map_err(|err| {
+                tracing::error!(oui = request.oui, "failed to fetch packet stats: {err:?}");
+                Status::internal("failed to fetch packet stats")
+            })?
+            .into_iter()
+            .map(|stats| PacketStatsV1 {
+                hour: stats.hour.timestamp() as u64,
+                outcome: stats.outcome,
+                packets: stats.packets as u64,
+                dcs: stats.dcs as u64,
+            })
+            .collect();
+        Ok(PacketStatsResV1 {
+            oui: request.oui,
+            stats,
+        })
+    }
+
+    async fn authorize_signature(&self, request: &PacketStatsReqV1) -> Result<(), Status> {
+        let signer = request.verify()?;
+        let signed_at = Utc
+            .timestamp_millis_opt(request.timestamp as i64)
+            .single()
+            .ok_or_else(|| Status::invalid_argument("invalid timestamp"))?;
+        if (Utc::now() - signed_at).num_seconds().abs() > MAX_SIGNATURE_AGE_SECS {
+            return Err(Status::unauthenticated("request expired"));
+        }
+
+        let org = self
+            .org_client
+            .lock()
+            .await
+            .get(request.oui)
+            .await
+            .map_err(|err| {
+                tracing::warn!(oui = request.oui, "failed to fetch org: {err}");
+                Status::unavailable("failed to fetch org")
+            })?
+            .org
+            .ok_or_else(|| Status::not_found("unknown oui"))?;
+        if !is_authorized(&org, &signer) {
+            return Err(Status::permission_denied("signer is not a key of the org"));
+        }
+        Ok(())
+    }
+}
+
+impl NamedService for StatsService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for StatsService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            QUERY_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.query(request).await })
+            }
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_crypto::{KeyTag, KeyType, Keypair, Network, Sign};
+    use rand::rngs::OsRng;
+
+    fn keypair() -> Keypair {
+        Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::Ed25519,
+            },
+            &mut OsRng,
+        )
+    }
+
+    fn signed_request(keypair: &Keypair) -> PacketStatsReqV1 {
+        let mut request = PacketStatsReqV1 {
+            oui: 1,
+            after: 0,
+            before: 3600,
+            timestamp: Utc::now().timestamp_millis() as u64,
+            signer: keypair.public_key().to_vec(),
+            signature: vec![],
+            token: vec![],
+        };
+        request.signature = keypair.sign(&request.encode_to_vec()).unwrap();
+        request
+    }
+
+    #[test]
+    fn only_correctly_signed_requests_verify() {
+        let keypair = keypair();
+        let request = signed_request(&keypair);
+        assert_eq!(
+            request.verify().unwrap(),
+            PublicKeyBinary::from(keypair.public_key().to_vec())
+        );
+
+        let tampered = PacketStatsReqV1 {
+            oui: 2,
+            ..request.clone()
+        };
+        assert!(tampered.verify().is_err());
+
+        let other = keypair();
+        let impersonated = PacketStatsReqV1 {
+            signer: other.public_key().to_vec(),
+            ..request
+        };
+        assert!(impersonated.verify().is_err());
+    }
+
+    #[test]
+    fn owner_and_delegates_are_authorized() {
+        let owner = PublicKeyBinary::from(keypair().public_key().to_vec());
+        let delegate = PublicKeyBinary::from(keypair().public_key().to_vec());
+        let stranger = PublicKeyBinary::from(keypair().public_key().to_vec());
+        let org = OrgV1 {
+            oui: 1,
+            owner: owner.clone().into(),
+            delegate_keys: vec![delegate.clone().into()],
+            ..Default::default()
+        };
+
+        assert!(is_authorized(&org, &owner));
+        assert!(is_authorized(&org, &delegate));
+        assert!(!is_authorized(&org, &stranger));
+    }
+}
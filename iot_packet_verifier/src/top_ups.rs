@@ -0,0 +1,88 @@
+//! Top ups of the data credit escrow of payers, as seen by the balance cache.
+//!
+//! Every top up is written to the balance top up files for downstream
+//! systems and wakes the task re-enabling orgs, so an org locked for a low
+//! balance does not wait for the next monitor period once its payer is
+//! funded again.
+
+use balance_cache::BalanceTopUp;
+use chrono::Utc;
+use file_store::file_sink::FileSinkClient;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Notify};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BalanceTopUpV1 {
+    #[prost(bytes = "vec", tag = "1")]
+    pub payer: Vec<u8>,
+    /// Data credits added to the escrow
+    #[prost(uint64, tag = "2")]
+    pub amount: u64,
+    /// Escrow balance after the top up
+    #[prost(uint64, tag = "3")]
+    pub balance: u64,
+    /// Milliseconds since the epoch of when the top up was seen
+    #[prost(uint64, tag = "4")]
+    pub timestamp: u64,
+}
+
+pub struct TopUpNotifier {
+    top_ups: broadcast::Receiver<BalanceTopUp>,
+    top_up_sink: FileSinkClient,
+    monitor_funds: Arc<Notify>,
+}
+
+impl TopUpNotifier {
+    pub fn new(
+        top_ups: broadcast::Receiver<BalanceTopUp>,
+        top_up_sink: FileSinkClient,
+        monitor_funds: Arc<Notify>,
+    ) -> Self {
+        Self {
+            top_ups,
+            top_up_sink,
+            monitor_funds,
+        }
+    }
+
+    pub async fn run(mut self, shutdown: triggered::Listener) -> anyhow::Result<()> {
+        loop {
+            let top_up = tokio::select! {
+                _ = shutdown.clone() => break,
+                top_up = self.top_ups.recv() => top_up,
+            };
+            match top_up {
+                Ok(top_up) => self.top_up(top_up).await?,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "missed balance top ups");
+                    self.monitor_funds.notify_one();
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+        Ok(())
+    }
+
+    async fn top_up(&self, top_up: BalanceTopUp) -> anyhow::Result<()> {
+        let BalanceTopUp {
+            payer,
+            amount,
+            balance,
+        } = top_up;
+        tracing::info!(%payer, amount, balance, "payer balance topped up");
+        metrics::counter!("balance_top_ups", amount);
+        self.monitor_funds.notify_one();
+        self.top_up_sink
+            .write(
+                BalanceTopUpV1 {
+                    payer: payer.into(),
+                    amount,
+                    balance,
+                    timestamp: Utc::now().timestamp_millis() as u64,
+                },
+                &[],
+            )
+            .await?;
+        Ok(())
+    }
+}
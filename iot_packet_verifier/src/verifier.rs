@@ -1,12 +1,23 @@
-use crate::pending_burns::PendingBurns;
+use crate::{
+    org_lock::OrgLocks,
+    packets_seen::{self, PacketDeduper, PacketsSeen},
+    pending_burns::PendingBurns,
+    report_check,
+    stats::{Outcome, PacketStats},
+};
 use async_trait::async_trait;
+use dc_pricing::DcPricing;
 use file_store::{
     file_sink::FileSinkClient, iot_packet::PacketRouterPacketReport, traits::MsgTimestamp,
 };
 use futures::{Stream, StreamExt};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::services::packet_verifier::{InvalidPacket, InvalidPacketReason, ValidPacket};
-use iot_config::client::{ClientError, OrgClient};
+use iot_config::{
+    client::{ClientError, OrgClient},
+    org_batch::{OrgLockV1, MAX_BATCH_LOCKS},
+};
+use poc_metrics::error_code::{ErrorCategory, ErrorCode};
 use solana::SolanaNetwork;
 use std::{
     collections::{hash_map::Entry, HashMap},
@@ -15,14 +26,80 @@ use std::{
     sync::Arc,
 };
 use tokio::{
-    sync::Mutex,
+    sync::{Mutex, Notify},
     task::JoinError,
     time::{sleep_until, Duration, Instant},
 };
 
-pub struct Verifier<D, C> {
+const PAYLOAD_SIZE_METRIC: &str = "iot_packet_verifier_payload_size_bytes";
+const DC_CHARGED_METRIC: &str = "iot_packet_verifier_dc_charged";
+
+/// Bucket bounds of the traffic histograms. Payloads go up to the largest
+/// LoRaWAN payload of 242 bytes, charged by the 24 bytes
+pub const HISTOGRAM_BUCKETS: &[(&str, &[f64])] = &[
+    (
+        PAYLOAD_SIZE_METRIC,
+        &[12.0, 24.0, 48.0, 72.0, 96.0, 128.0, 192.0, 256.0],
+    ),
+    (
+        DC_CHARGED_METRIC,
+        &[1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 11.0, 16.0, 32.0],
+    ),
+];
+
+/// Reason of every packet that is not charged. The proto only defines the
+/// insufficient balance reason, so packets failing the report check, reported
+/// before or of unknown orgs are written with it as well. Why a packet was not
+/// charged is told apart by the outcome of the packet statistics.
+const NOT_CHARGED_REASON: InvalidPacketReason = InvalidPacketReason::InsufficientBalance;
+
+/// Reports whose packets are claimed at once, see [packets_seen]
+const DEDUPE_BATCH_SIZE: usize = 1_000;
+
+/// Time an oui unknown to the config service is not looked up again
+const UNKNOWN_OUI_TTL: Duration = Duration::from_secs(60);
+
+/// Ouis the config service did not know, remembered for a short while so
+/// their packets do not each cost a lookup
+#[derive(Clone, Debug, Default)]
+pub struct UnknownOuis(Arc<std::sync::Mutex<HashMap<u64, Instant>>>);
+
+impl UnknownOuis {
+    fn contains(&self, oui: u64, now: Instant) -> bool {
+        let mut ouis = self.0.lock().expect("unknown ouis poisoned");
+        match ouis.get(&oui) {
+            Some(since) if now.duration_since(*since) < UNKNOWN_OUI_TTL => true,
+            Some(_) => {
+                ouis.remove(&oui);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&self, oui: u64, now: Instant) {
+        self.0
+            .lock()
+            .expect("unknown ouis poisoned")
+            .insert(oui, now);
+    }
+}
+
+pub struct Verifier<D, C, P = PacketsSeen> {
     pub debiter: D,
     pub config_server: C,
+    pub pricing: DcPricing,
+    /// Check the gateway key and net id of every report before charging it
+    pub verify_reports: bool,
+    /// Decides when orgs with a low balance are disabled
+    pub org_locks: OrgLocks,
+    /// Packets verified before, not charged again when set
+    pub packets_seen: Option<P>,
+    /// Ouis recently found unknown to the config service
+    pub unknown_ouis: UnknownOuis,
+    /// Leave disabling orgs to the batches of the intent reconciler rather
+    /// than disabling them one at a time as their balance runs out
+    pub batch_org_intents: bool,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -37,14 +114,19 @@ pub enum VerificationError<DE, CE, BE, VPE, IPE> {
     ValidPacketWriterError(VPE),
     #[error("Invalid packet writer error: {0}")]
     InvalidPacketWriterError(IPE),
+    /// Claiming the packets through the pending burns failed
+    #[error("Packet deduper error: {0}")]
+    DeduperError(BE),
 }
 
-impl<D, C> Verifier<D, C>
+impl<D, C, P> Verifier<D, C, P>
 where
     D: Debiter,
     C: ConfigServer,
+    P: PacketDeduper,
 {
-    /// Verify a stream of packet reports. Writes out `valid_packets` and `invalid_packets`.
+    /// Verify a stream of packet reports. Writes out `valid_packets` and
+    /// `invalid_packets`, returning the statistics of the verified packets.
     pub async fn verify<B, R, VP, IP>(
         &mut self,
         minimum_allowed_balance: u64,
@@ -52,76 +134,217 @@ where
         reports: R,
         mut valid_packets: VP,
         mut invalid_packets: IP,
-    ) -> Result<(), VerificationError<D::Error, C::Error, B::Error, VP::Error, IP::Error>>
+    ) -> Result<PacketStats, VerificationError<D::Error, C::Error, B::Error, VP::Error, IP::Error>>
     where
-        B: PendingBurns,
+        B: PendingBurns + Send,
         R: Stream<Item = PacketRouterPacketReport>,
         VP: PacketWriter<ValidPacket>,
         IP: PacketWriter<InvalidPacket>,
     {
         let mut org_cache = HashMap::<u64, PublicKeyBinary>::new();
+        let mut net_id_cache = HashMap::<u64, Option<u32>>::new();
+        let mut stats = PacketStats::default();
+
+        let batches = reports.chunks(DEDUPE_BATCH_SIZE);
+        tokio::pin!(batches);
+
+        while let Some(batch) = batches.next().await {
+            let mut checked = Vec::with_capacity(batch.len());
+            for report in batch {
+                if self.verify_reports {
+                    let org_net_id = self
+                        .config_server
+                        .fetch_net_id(report.oui, &mut net_id_cache)
+                        .await
+                        .map_err(VerificationError::ConfigError)?;
+                    if let Err(err) = report_check::check_report(&report, org_net_id) {
+                        tracing::debug!(oui = report.oui, gateway = %report.gateway, "rejecting packet report: {err}");
+                        stats.record(
+                            report.oui,
+                            report.received_timestamp,
+                            Outcome::InvalidReport,
+                            0,
+                        );
+                        invalid_packets
+                            .write(InvalidPacket {
+                                payload_size: report.payload_size,
+                                gateway: report.gateway.into(),
+                                payload_hash: report.payload_hash,
+                                reason: NOT_CHARGED_REASON as i32,
+                            })
+                            .await
+                            .map_err(VerificationError::InvalidPacketWriterError)?;
+                        continue;
+                    }
+                }
+                checked.push(report);
+            }
+
+            // Only the packets of reports passing the check are claimed
+            let seen = match &mut self.packets_seen {
+                Some(packets_seen) => {
+                    let ids: Vec<u128> = checked.iter().map(packets_seen::packet_id).collect();
+                    packets_seen
+                        .insert_batch(&ids, &mut pending_burns)
+                        .await
+                        .map_err(VerificationError::DeduperError)?
+                }
+                None => vec![false; checked.len()],
+            };
+
+            for (report, seen) in checked.into_iter().zip(seen) {
+                if seen {
+                    tracing::debug!(oui = report.oui, gateway = %report.gateway, "duplicate packet report");
+                    stats.record(report.oui, report.received_timestamp, Outcome::Duplicate, 0);
+                    invalid_packets
+                        .write(InvalidPacket {
+                            payload_size: report.payload_size,
+                            gateway: report.gateway.into(),
+                            payload_hash: report.payload_hash,
+                            reason: NOT_CHARGED_REASON as i32,
+                        })
+                        .await
+                        .map_err(VerificationError::InvalidPacketWriterError)?;
+                    continue;
+                }
 
-        tokio::pin!(reports);
-
-        while let Some(report) = reports.next().await {
-            let debit_amount = payload_size_to_dc(report.payload_size as u64);
-
-            let payer = self
-                .config_server
-                .fetch_org(report.oui, &mut org_cache)
-                .await
-                .map_err(VerificationError::ConfigError)?;
-            let remaining_balance = self
-                .debiter
-                .debit_if_sufficient(&payer, debit_amount)
-                .await
-                .map_err(VerificationError::DebitError)?;
-
-            if let Some(remaining_balance) = remaining_balance {
-                pending_burns
-                    .add_burned_amount(&payer, debit_amount)
+                let debit_amount = self.pricing.bytes_to_dc(report.payload_size as u64);
+                record_traffic(&report, PAYLOAD_SIZE_METRIC, report.payload_size as f64);
+
+                let Some(payer) = self
+                    .fetch_payer(report.oui, &mut org_cache)
                     .await
-                    .map_err(VerificationError::BurnError)?;
-                valid_packets
-                    .write(ValidPacket {
-                        packet_timestamp: report.timestamp(),
-                        payload_size: report.payload_size,
-                        gateway: report.gateway.into(),
-                        payload_hash: report.payload_hash,
-                        num_dcs: debit_amount as u32,
-                    })
+                    .map_err(VerificationError::ConfigError)?
+                else {
+                    tracing::debug!(oui = report.oui, gateway = %report.gateway, "packet of unknown org");
+                    stats.record(
+                        report.oui,
+                        report.received_timestamp,
+                        Outcome::UnknownOui,
+                        0,
+                    );
+                    invalid_packets
+                        .write(InvalidPacket {
+                            payload_size: report.payload_size,
+                            gateway: report.gateway.into(),
+                            payload_hash: report.payload_hash,
+                            reason: NOT_CHARGED_REASON as i32,
+                        })
+                        .await
+                        .map_err(VerificationError::InvalidPacketWriterError)?;
+                    continue;
+                };
+                let remaining_balance = self
+                    .debiter
+                    .debit_if_sufficient(&payer, debit_amount)
                     .await
-                    .map_err(VerificationError::ValidPacketWriterError)?;
-
-                if remaining_balance < minimum_allowed_balance {
-                    self.config_server
-                        .disable_org(report.oui)
+                    .map_err(VerificationError::DebitError)?;
+
+                if remaining_balance.is_some() {
+                    record_traffic(&report, DC_CHARGED_METRIC, debit_amount as f64);
+                    stats.record(
+                        report.oui,
+                        report.received_timestamp,
+                        Outcome::Valid,
+                        debit_amount,
+                    );
+                    pending_burns
+                        .add_burned_amount(&payer, debit_amount)
                         .await
-                        .map_err(VerificationError::ConfigError)?;
+                        .map_err(VerificationError::BurnError)?;
+                    valid_packets
+                        .write(ValidPacket {
+                            packet_timestamp: report.timestamp(),
+                            payload_size: report.payload_size,
+                            gateway: report.gateway.into(),
+                            payload_hash: report.payload_hash,
+                            num_dcs: debit_amount as u32,
+                        })
+                        .await
+                        .map_err(VerificationError::ValidPacketWriterError)?;
+                } else {
+                    stats.record(
+                        report.oui,
+                        report.received_timestamp,
+                        Outcome::Invalid(InvalidPacketReason::InsufficientBalance),
+                        0,
+                    );
+                    invalid_packets
+                        .write(InvalidPacket {
+                            payload_size: report.payload_size,
+                            gateway: report.gateway.into(),
+                            payload_hash: report.payload_hash,
+                            reason: InvalidPacketReason::InsufficientBalance as i32,
+                        })
+                        .await
+                        .map_err(VerificationError::InvalidPacketWriterError)?;
                 }
-            } else {
-                invalid_packets
-                    .write(InvalidPacket {
-                        payload_size: report.payload_size,
-                        gateway: report.gateway.into(),
-                        payload_hash: report.payload_hash,
-                        reason: InvalidPacketReason::InsufficientBalance as i32,
-                    })
+
+                if self
+                    .org_locks
+                    .should_disable(
+                        report.oui,
+                        remaining_balance,
+                        minimum_allowed_balance,
+                        report.received_timestamp,
+                    )
                     .await
-                    .map_err(VerificationError::InvalidPacketWriterError)?;
+                {
+                    pending_burns
+                        .record_org_intent(report.oui, false)
+                        .await
+                        .map_err(VerificationError::BurnError)?;
+                    if !self.batch_org_intents {
+                        match self.config_server.disable_org(report.oui).await {
+                            Ok(()) => pending_burns
+                                .org_intent_sent(report.oui, false)
+                                .await
+                                .map_err(VerificationError::BurnError)?,
+                            // The intent is sent again by the intent reconciler
+                            Err(err) => {
+                                tracing::warn!(oui = report.oui, "failed to disable org: {err:?}")
+                            }
+                        }
+                    }
+                    self.org_locks.disabled(report.oui, remaining_balance).await;
+                }
             }
         }
 
-        Ok(())
+        if let Some(packets_seen) = &self.packets_seen {
+            packets_seen.record_metrics();
+        }
+
+        Ok(stats)
     }
-}
 
-pub const BYTES_PER_DC: u64 = 24;
+    /// Payer of the org, none when the config service does not know the oui
+    async fn fetch_payer(
+        &self,
+        oui: u64,
+        cache: &mut HashMap<u64, PublicKeyBinary>,
+    ) -> Result<Option<PublicKeyBinary>, C::Error> {
+        if self.unknown_ouis.contains(oui, Instant::now()) {
+            return Ok(None);
+        }
+        let payer = self.config_server.fetch_org(oui, cache).await?;
+        if payer.is_none() {
+            tracing::warn!(oui, "org unknown to the config service");
+            self.unknown_ouis.insert(oui, Instant::now());
+        }
+        Ok(payer)
+    }
+}
 
-pub fn payload_size_to_dc(payload_size: u64) -> u64 {
-    let payload_size = payload_size.max(BYTES_PER_DC);
-    // Integer div/ceil from: https://stackoverflow.com/a/2745086
-    (payload_size + BYTES_PER_DC - 1) / BYTES_PER_DC
+/// Record a value of a traffic histogram, labeled by the net id and region
+/// of the packet rather than by org to keep the number of series bounded
+fn record_traffic(report: &PacketRouterPacketReport, metric: &'static str, value: f64) {
+    metrics::histogram!(
+        metric,
+        value,
+        "net_id" => format!("{:06x}", report.net_id),
+        "region" => report.region.as_str_name()
+    );
 }
 
 #[async_trait]
@@ -163,31 +386,77 @@ pub struct Org {
 
 #[async_trait]
 pub trait ConfigServer: Sized + Send + Sync + 'static {
-    type Error: Send + Sync + 'static;
+    type Error: Debug + Send + Sync + 'static;
 
+    /// Payer of the org, none when the config service does not know it
     async fn fetch_org(
         &self,
         oui: u64,
         cache: &mut HashMap<u64, PublicKeyBinary>,
-    ) -> Result<PublicKeyBinary, Self::Error>;
+    ) -> Result<Option<PublicKeyBinary>, Self::Error>;
+
+    /// Net id of the org, none when it is not known in which case the net id
+    /// of reports is not compared against it
+    async fn fetch_net_id(
+        &self,
+        _oui: u64,
+        _cache: &mut HashMap<u64, Option<u32>>,
+    ) -> Result<Option<u32>, Self::Error> {
+        Ok(None)
+    }
 
     async fn disable_org(&self, oui: u64) -> Result<(), Self::Error>;
 
     async fn enable_org(&self, oui: u64) -> Result<(), Self::Error>;
 
+    /// Enable or disable the orgs, returning the changes applied. Changes
+    /// the config service failed on are logged and left out. Sends a request
+    /// per org unless the config server batches them
+    async fn set_org_states(
+        &self,
+        changes: &[(u64, bool)],
+    ) -> Result<Vec<(u64, bool)>, Self::Error> {
+        let mut applied = Vec::with_capacity(changes.len());
+        for &(oui, enabled) in changes {
+            let result = if enabled {
+                self.enable_org(oui).await
+            } else {
+                self.disable_org(oui).await
+            };
+            match result {
+                Ok(()) => applied.push((oui, enabled)),
+                Err(err) => tracing::warn!(oui, enabled, "failed to set org state: {err:?}"),
+            }
+        }
+        Ok(applied)
+    }
+
     async fn list_orgs(&self) -> Result<Vec<Org>, Self::Error>;
 
-    async fn monitor_funds<S, B>(
+    /// Re-enable locked orgs once their payer has the minimum allowed
+    /// balance plus the hysteresis of the org locks, checked every monitor
+    /// period and whenever `top_ups` is notified. The enable intent is
+    /// recorded in `intents` first, so the intent reconciler sends it again
+    /// when the config service fails and never sends an older disable over
+    /// it. With `batch_org_intents` the enable is only recorded and sent in
+    /// the next batch of the reconciler, together with the disables
+    #[allow(clippy::too_many_arguments)]
+    async fn monitor_funds<S, B, I>(
         self,
         solana: S,
         balances: B,
+        mut intents: I,
+        batch_org_intents: bool,
+        org_locks: OrgLocks,
         minimum_allowed_balance: u64,
         monitor_period: Duration,
+        top_ups: Arc<Notify>,
         shutdown: triggered::Listener,
-    ) -> Result<(), MonitorError<S::Error, Self::Error>>
+    ) -> Result<(), MonitorError<S::Error, Self::Error, I::Error>>
     where
         S: SolanaNetwork,
         B: BalanceStore,
+        I: PendingBurns + Send + 'static,
     {
         let join_handle = tokio::spawn(async move {
             loop {
@@ -204,16 +473,35 @@ pub trait ConfigServer: Sized + Send + Sync + 'static {
                             .payer_balance(&payer)
                             .await
                             .map_err(MonitorError::SolanaError)?;
-                        if balance >= minimum_allowed_balance {
+                        if balance >= org_locks.enable_threshold(minimum_allowed_balance) {
                             balances.set_balance(&payer, balance).await;
-                            self.enable_org(oui)
+                            intents
+                                .record_org_intent(oui, true)
                                 .await
-                                .map_err(MonitorError::ConfigClientError)?;
+                                .map_err(MonitorError::IntentError)?;
+                            if !batch_org_intents {
+                                match self.enable_org(oui).await {
+                                    Ok(()) => intents
+                                        .org_intent_sent(oui, true)
+                                        .await
+                                        .map_err(MonitorError::IntentError)?,
+                                    // The intent is sent again by the intent
+                                    // reconciler
+                                    Err(err) => {
+                                        tracing::warn!(oui, "failed to enable org: {err:?}")
+                                    }
+                                }
+                            }
+                            org_locks.enabled(oui, balance).await;
                         }
                     }
                 }
-                // Sleep until we should re-check the monitor
-                sleep_until(Instant::now() + monitor_period).await;
+                // Sleep until we should re-check the monitor or a payer was
+                // topped up
+                tokio::select! {
+                    _ = sleep_until(Instant::now() + monitor_period) => (),
+                    _ = top_ups.notified() => (),
+                }
             }
         });
         tokio::select! {
@@ -233,14 +521,7 @@ pub trait BalanceStore: Send + Sync + 'static {
 }
 
 #[async_trait]
-impl BalanceStore for crate::balances::BalanceStore {
-    async fn set_balance(&self, payer: &PublicKeyBinary, balance: u64) {
-        self.lock().await.entry(payer.clone()).or_default().balance = balance;
-    }
-}
-
-#[async_trait]
-// differs from the BalanceStore in the value stored in the contained HashMap; a u64 here instead of a Balance {} struct
+// differs from the BalanceCache in the value stored in the contained HashMap; a u64 here instead of a Balance {} struct
 impl BalanceStore for Arc<Mutex<HashMap<PublicKeyBinary, u64>>> {
     async fn set_balance(&self, payer: &PublicKeyBinary, balance: u64) {
         *self.lock().await.entry(payer.clone()).or_default() = balance;
@@ -248,21 +529,39 @@ impl BalanceStore for Arc<Mutex<HashMap<PublicKeyBinary, u64>>> {
 }
 
 #[derive(thiserror::Error, Debug)]
-pub enum MonitorError<S, E> {
+pub enum MonitorError<S, E, I> {
     #[error("Join error: {0}")]
     JoinError(#[from] JoinError),
     #[error("Config client error: {0}")]
     ConfigClientError(E),
     #[error("Solana error: {0}")]
     SolanaError(S),
+    #[error("Org intent error: {0}")]
+    IntentError(I),
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum ConfigServerError {
     #[error("org client error: {0}")]
     Client(#[from] ClientError),
-    #[error("not found: {0}")]
-    NotFound(u64),
+}
+
+impl ErrorCode for ConfigServerError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Client(err) => err.category(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Client(err) => err.code(),
+        }
+    }
+}
+
+fn is_not_found(err: &ClientError) -> bool {
+    matches!(err, ClientError::Rpc(status) if status.code() == tonic::Code::NotFound)
 }
 
 #[async_trait]
@@ -273,20 +572,37 @@ impl ConfigServer for Arc<Mutex<OrgClient>> {
         &self,
         oui: u64,
         cache: &mut HashMap<u64, PublicKeyBinary>,
-    ) -> Result<PublicKeyBinary, Self::Error> {
+    ) -> Result<Option<PublicKeyBinary>, Self::Error> {
         if let Entry::Vacant(e) = cache.entry(oui) {
-            let pubkey = PublicKeyBinary::from(
-                self.lock()
-                    .await
-                    .get(oui)
-                    .await?
-                    .org
-                    .ok_or(ConfigServerError::NotFound(oui))?
-                    .payer,
-            );
-            e.insert(pubkey);
+            let org = match self.lock().await.get(oui).await {
+                Ok(res) => res.org,
+                Err(err) if is_not_found(&err) => None,
+                Err(err) => return Err(err.into()),
+            };
+            let Some(org) = org else {
+                return Ok(None);
+            };
+            e.insert(PublicKeyBinary::from(org.payer));
+        }
+        Ok(cache.get(&oui).cloned())
+    }
+
+    async fn fetch_net_id(
+        &self,
+        oui: u64,
+        cache: &mut HashMap<u64, Option<u32>>,
+    ) -> Result<Option<u32>, Self::Error> {
+        if let Entry::Vacant(e) = cache.entry(oui) {
+            // The packets of an unknown org are rejected once its payer is
+            // looked up
+            let net_id = match self.lock().await.get(oui).await {
+                Ok(res) => Some(res.net_id),
+                Err(err) if is_not_found(&err) => None,
+                Err(err) => return Err(err.into()),
+            };
+            e.insert(net_id);
         }
-        Ok(cache.get(&oui).unwrap().clone())
+        Ok(*cache.get(&oui).unwrap())
     }
 
     async fn disable_org(&self, oui: u64) -> Result<(), Self::Error> {
@@ -299,6 +615,35 @@ impl ConfigServer for Arc<Mutex<OrgClient>> {
         Ok(())
     }
 
+    async fn set_org_states(
+        &self,
+        changes: &[(u64, bool)],
+    ) -> Result<Vec<(u64, bool)>, Self::Error> {
+        let mut applied = Vec::with_capacity(changes.len());
+        for batch in changes.chunks(MAX_BATCH_LOCKS) {
+            let locks = batch
+                .iter()
+                .map(|&(oui, enabled)| OrgLockV1 {
+                    oui,
+                    locked: !enabled,
+                })
+                .collect();
+            for result in self.lock().await.set_locks(locks).await? {
+                if result.is_applied() {
+                    applied.push((result.oui, !result.locked));
+                } else {
+                    tracing::warn!(
+                        oui = result.oui,
+                        enabled = !result.locked,
+                        "failed to set org state: {}",
+                        result.error
+                    );
+                }
+            }
+        }
+        Ok(applied)
+    }
+
     async fn list_orgs(&self) -> Result<Vec<Org>, Self::Error> {
         Ok(self
             .lock()
@@ -0,0 +1,48 @@
+use chrono::Utc;
+use iot_packet_verifier::backfill::{self, OwedBurn};
+use sqlx::PgPool;
+
+fn owed(payer: &str, owed: u64) -> OwedBurn {
+    OwedBurn {
+        payer: payer.to_string(),
+        charged: owed,
+        burned: 0,
+        owed,
+    }
+}
+
+async fn pending_burns(pool: &PgPool) -> Vec<(String, i64)> {
+    sqlx::query_as("SELECT payer, amount FROM pending_burns ORDER BY payer")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test]
+async fn recovered_burns_replace_the_pending_burns(pool: PgPool) {
+    for (payer, amount) in [("a", 10_i64), ("b", 20)] {
+        sqlx::query("INSERT INTO pending_burns (payer, amount, last_burn) VALUES ($1, $2, $3)")
+            .bind(payer)
+            .bind(amount)
+            .bind(Utc::now().naive_utc())
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+
+    let mut transaction = pool.begin().await.unwrap();
+    backfill::write_pending_burns(&mut transaction, &[owed("a", 5), owed("c", 7)])
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+
+    // b was not charged in the range and owes nothing
+    assert_eq!(
+        pending_burns(&pool).await,
+        vec![
+            ("a".to_string(), 5),
+            ("b".to_string(), 0),
+            ("c".to_string(), 7)
+        ]
+    );
+}
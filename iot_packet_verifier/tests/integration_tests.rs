@@ -1,5 +1,7 @@
 use async_trait::async_trait;
 use chrono::{TimeZone, Utc};
+use dc_pricing::{DcPricing, IOT_BYTES_PER_DC};
+use fault_injection::{Faults, Faulty, FaultyError};
 use file_store::iot_packet::PacketRouterPacketReport;
 use futures::{Stream, StreamExt};
 use futures_util::stream;
@@ -9,13 +11,22 @@ use helium_proto::{
     DataRate, Region,
 };
 use iot_packet_verifier::{
-    balances::BalanceCache,
+    balances,
     burner::Burner,
+    org_lock::{OrgLockSettings, OrgLocks},
+    packets_seen::{PacketsSeen, PacketsSeenSettings},
     pending_burns::{Burn, PendingBurns},
-    verifier::{payload_size_to_dc, ConfigServer, Debiter, Org, Verifier, BYTES_PER_DC},
+    stats::Outcome,
+    verifier::{ConfigServer, Debiter, Org, VerificationError, Verifier},
 };
-use std::{collections::HashMap, pin::Pin, sync::Arc, time::Duration};
-use tokio::sync::Mutex;
+use solana::mock::MockSolanaNetwork;
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{Mutex, Notify};
 
 struct MockConfig {
     payer: PublicKeyBinary,
@@ -47,8 +58,13 @@ impl ConfigServer for MockConfigServer {
         &self,
         oui: u64,
         _cache: &mut HashMap<u64, PublicKeyBinary>,
-    ) -> Result<PublicKeyBinary, ()> {
-        Ok(self.payers.lock().await.get(&oui).unwrap().payer.clone())
+    ) -> Result<Option<PublicKeyBinary>, ()> {
+        Ok(self
+            .payers
+            .lock()
+            .await
+            .get(&oui)
+            .map(|config| config.payer.clone()))
     }
 
     async fn disable_org(&self, oui: u64) -> Result<(), ()> {
@@ -127,6 +143,121 @@ impl PendingBurns for InstantBurnedBalance {
         *balance -= amount;
         Ok(())
     }
+
+    async fn record_org_intent(&mut self, _oui: u64, _enabled: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn org_intent_sent(&mut self, _oui: u64, _enabled: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn claim_packets(&mut self, ids: &[u128]) -> Result<HashSet<u128>, Self::Error> {
+        Ok(ids.iter().copied().collect())
+    }
+}
+
+/// Pending burns as stored in the database, amounts can be negative
+struct StoredBurns(Vec<(PublicKeyBinary, i64)>);
+
+#[async_trait::async_trait]
+impl PendingBurns for StoredBurns {
+    type Error = std::convert::Infallible;
+
+    async fn fetch_all<'a>(
+        &'a mut self,
+    ) -> Pin<Box<dyn Stream<Item = Result<Burn, Self::Error>> + Send + 'a>> {
+        stream::iter(self.0.iter().map(|(payer, amount)| {
+            Ok(Burn {
+                payer: payer.clone(),
+                amount: *amount,
+            })
+        }))
+        .boxed()
+    }
+
+    async fn fetch_next(&mut self) -> Result<Option<Burn>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn subtract_burned_amount(
+        &mut self,
+        _payer: &PublicKeyBinary,
+        _amount: u64,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn add_burned_amount(
+        &mut self,
+        _payer: &PublicKeyBinary,
+        _amount: u64,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn record_org_intent(&mut self, _oui: u64, _enabled: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn org_intent_sent(&mut self, _oui: u64, _enabled: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn claim_packets(&mut self, ids: &[u128]) -> Result<HashSet<u128>, Self::Error> {
+        Ok(ids.iter().copied().collect())
+    }
+}
+
+/// Org intents keyed by oui, with whether they were sent
+#[derive(Default, Clone)]
+struct RecordedIntents(Arc<Mutex<HashMap<u64, (bool, bool)>>>);
+
+#[async_trait::async_trait]
+impl PendingBurns for RecordedIntents {
+    type Error = std::convert::Infallible;
+
+    async fn fetch_all<'a>(
+        &'a mut self,
+    ) -> Pin<Box<dyn Stream<Item = Result<Burn, Self::Error>> + Send + 'a>> {
+        stream::iter(std::iter::empty()).boxed()
+    }
+
+    async fn fetch_next(&mut self) -> Result<Option<Burn>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn subtract_burned_amount(
+        &mut self,
+        _payer: &PublicKeyBinary,
+        _amount: u64,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn add_burned_amount(
+        &mut self,
+        _payer: &PublicKeyBinary,
+        _amount: u64,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn record_org_intent(&mut self, oui: u64, enabled: bool) -> Result<(), Self::Error> {
+        self.0.lock().await.insert(oui, (enabled, false));
+        Ok(())
+    }
+
+    async fn org_intent_sent(&mut self, oui: u64, enabled: bool) -> Result<(), Self::Error> {
+        if let Some(intent) = self.0.lock().await.get_mut(&oui) {
+            intent.1 = intent.0 == enabled;
+        }
+        Ok(())
+    }
+
+    async fn claim_packets(&mut self, ids: &[u128]) -> Result<HashSet<u128>, Self::Error> {
+        Ok(ids.iter().copied().collect())
+    }
 }
 
 fn packet_report(
@@ -155,7 +286,7 @@ fn valid_packet(timestamp: u64, payload_size: u32, payload_hash: Vec<u8>) -> Val
         payload_size,
         payload_hash,
         gateway: vec![],
-        num_dcs: payload_size_to_dc(payload_size as u64) as u32,
+        num_dcs: DcPricing::iot().bytes_to_dc(payload_size as u64) as u32,
         packet_timestamp: timestamp,
     }
 }
@@ -187,6 +318,12 @@ async fn test_config_unlocking() {
     let mut verifier = Verifier {
         debiter: balances.clone(),
         config_server: orgs.clone(),
+        pricing: DcPricing::iot(),
+        verify_reports: false,
+        org_locks: OrgLocks::default(),
+        packets_seen: None::<PacketsSeen>,
+        unknown_ouis: Default::default(),
+        batch_org_intents: false,
     };
     let mut valid_packets = Vec::new();
     let mut invalid_packets = Vec::new();
@@ -226,8 +363,12 @@ async fn test_config_unlocking() {
             .monitor_funds(
                 solana.clone(),
                 balance_cache,
+                Arc::new(Mutex::new(HashMap::<PublicKeyBinary, u64>::new())),
+                false,
+                OrgLocks::default(),
                 1,
                 Duration::from_secs(100),
+                Arc::new(Notify::new()),
                 listener,
             )
             .await
@@ -307,6 +448,12 @@ async fn test_verifier() {
     let mut verifier = Verifier {
         debiter: balances.clone(),
         config_server: orgs,
+        pricing: DcPricing::iot(),
+        verify_reports: false,
+        org_locks: OrgLocks::default(),
+        packets_seen: None::<PacketsSeen>,
+        unknown_ouis: Default::default(),
+        batch_org_intents: false,
     };
 
     // Run the verifier:
@@ -346,6 +493,169 @@ async fn test_verifier() {
     assert!(payers.get(&2).unwrap().enabled);
 }
 
+#[tokio::test]
+async fn test_org_locks_wait_for_consecutive_failures() {
+    let orgs = MockConfigServer::default();
+    orgs.insert(0_u64, PublicKeyBinary::from(vec![0])).await;
+    let mut balances = HashMap::new();
+    balances.insert(PublicKeyBinary::from(vec![0]), 3);
+    let balances = InstantBurnedBalance(Arc::new(Mutex::new(balances)));
+    let mut valid_packets = Vec::new();
+    let mut invalid_packets = Vec::new();
+    let mut verifier = Verifier {
+        debiter: balances.clone(),
+        config_server: orgs,
+        pricing: DcPricing::iot(),
+        verify_reports: false,
+        org_locks: OrgLocks::new(OrgLockSettings {
+            failures: Some(3),
+            ..Default::default()
+        }),
+        packets_seen: None::<PacketsSeen>,
+        unknown_ouis: Default::default(),
+        batch_org_intents: false,
+    };
+
+    // The second packet leaves the payer below the minimum and the third is
+    // not paid for, which is two failures of the three needed
+    let reports = vec![
+        packet_report(0, 0, 24, vec![1]),
+        packet_report(0, 1, 48, vec![2]),
+        packet_report(0, 2, 1, vec![3]),
+    ];
+    verifier
+        .verify(
+            1,
+            balances.clone(),
+            stream::iter(reports),
+            &mut valid_packets,
+            &mut invalid_packets,
+        )
+        .await
+        .unwrap();
+    assert!(verifier.config_server.payers.lock().await[&0].enabled);
+
+    verifier
+        .verify(
+            1,
+            balances.clone(),
+            stream::iter(vec![packet_report(0, 3, 1, vec![4])]),
+            &mut valid_packets,
+            &mut invalid_packets,
+        )
+        .await
+        .unwrap();
+    assert!(!verifier.config_server.payers.lock().await[&0].enabled);
+}
+
+#[tokio::test]
+async fn test_failed_disable_leaves_an_unsent_intent() {
+    let orgs = MockConfigServer::default();
+    orgs.insert(0_u64, PublicKeyBinary::from(vec![0])).await;
+    orgs.insert(1_u64, PublicKeyBinary::from(vec![1])).await;
+    let mut balances = HashMap::new();
+    balances.insert(PublicKeyBinary::from(vec![0]), 0);
+    balances.insert(PublicKeyBinary::from(vec![1]), 0);
+    let intents = RecordedIntents::default();
+    let mut valid_packets = Vec::new();
+    let mut invalid_packets = Vec::new();
+    // The fourth call to the config service, disabling the second org, fails
+    let mut verifier = Verifier {
+        debiter: Arc::new(Mutex::new(balances)),
+        config_server: Faulty::new(orgs, Faults::new().fail_every(4)),
+        pricing: DcPricing::iot(),
+        verify_reports: false,
+        org_locks: OrgLocks::default(),
+        packets_seen: None::<PacketsSeen>,
+        unknown_ouis: Default::default(),
+        batch_org_intents: false,
+    };
+
+    verifier
+        .verify(
+            1,
+            intents.clone(),
+            stream::iter(vec![
+                packet_report(1, 0, 24, vec![1]),
+                packet_report(0, 1, 24, vec![2]),
+            ]),
+            &mut valid_packets,
+            &mut invalid_packets,
+        )
+        .await
+        .expect("a failed disable does not fail the verification");
+
+    let payers = verifier.config_server.inner.payers.lock().await;
+    assert!(payers[&0].enabled);
+    assert!(!payers[&1].enabled);
+    assert_eq!(
+        *intents.0.lock().await,
+        HashMap::from([(0, (false, false)), (1, (false, true))])
+    );
+    assert_eq!(invalid_packets.len(), 2);
+}
+
+#[tokio::test]
+async fn test_batched_intents_are_left_to_the_reconciler() {
+    let orgs = MockConfigServer::default();
+    orgs.insert(0_u64, PublicKeyBinary::from(vec![0])).await;
+    orgs.insert(1_u64, PublicKeyBinary::from(vec![1])).await;
+    let mut balances = HashMap::new();
+    balances.insert(PublicKeyBinary::from(vec![0]), 0);
+    balances.insert(PublicKeyBinary::from(vec![1]), 0);
+    let intents = RecordedIntents::default();
+    let mut valid_packets = Vec::new();
+    let mut invalid_packets = Vec::new();
+    let mut verifier = Verifier {
+        debiter: Arc::new(Mutex::new(balances)),
+        config_server: orgs.clone(),
+        pricing: DcPricing::iot(),
+        verify_reports: false,
+        org_locks: OrgLocks::default(),
+        packets_seen: None::<PacketsSeen>,
+        unknown_ouis: Default::default(),
+        batch_org_intents: true,
+    };
+
+    verifier
+        .verify(
+            1,
+            intents.clone(),
+            stream::iter(vec![
+                packet_report(0, 0, 24, vec![1]),
+                packet_report(1, 1, 24, vec![2]),
+            ]),
+            &mut valid_packets,
+            &mut invalid_packets,
+        )
+        .await
+        .unwrap();
+
+    // Nothing is disabled until the batch is sent
+    assert!(orgs.payers.lock().await.values().all(|org| org.enabled));
+    assert_eq!(
+        *intents.0.lock().await,
+        HashMap::from([(0, (false, false)), (1, (false, false))])
+    );
+
+    // A batch failing on one org applies the others
+    let faulty = Faulty::new(orgs.clone(), Faults::new().fail_every(2));
+    let applied = faulty
+        .set_org_states(&[(0, false), (1, false)])
+        .await
+        .unwrap();
+    assert_eq!(applied, vec![(0, false)]);
+    assert!(orgs.payers.lock().await[&1].enabled);
+
+    // Sending a batch again changes nothing already applied
+    let applied = orgs
+        .set_org_states(&[(0, false), (1, false)])
+        .await
+        .unwrap();
+    assert_eq!(applied, vec![(0, false), (1, false)]);
+    assert!(orgs.payers.lock().await.values().all(|org| !org.enabled));
+}
+
 #[tokio::test]
 async fn test_end_to_end() {
     let payer = PublicKeyBinary::from(vec![0]);
@@ -360,7 +670,7 @@ async fn test_end_to_end() {
     let solana_network = Arc::new(Mutex::new(solana_network));
 
     // Balance cache:
-    let balance_cache = BalanceCache::new(&mut pending_burns, solana_network.clone())
+    let balance_cache = balances::load(&mut pending_burns, solana_network.clone())
         .await
         .unwrap();
 
@@ -384,6 +694,12 @@ async fn test_end_to_end() {
     let mut verifier = Verifier {
         debiter: balance_cache,
         config_server: orgs,
+        pricing: DcPricing::iot(),
+        verify_reports: false,
+        org_locks: OrgLocks::default(),
+        packets_seen: None::<PacketsSeen>,
+        unknown_ouis: Default::default(),
+        batch_org_intents: false,
     };
 
     // Verify four packets, each costing one DC. The last one should be invalid
@@ -392,10 +708,10 @@ async fn test_end_to_end() {
             1,
             pending_burns.clone(),
             stream::iter(vec![
-                packet_report(0, 0, BYTES_PER_DC as u32, vec![1]),
-                packet_report(0, 1, BYTES_PER_DC as u32, vec![2]),
-                packet_report(0, 2, BYTES_PER_DC as u32, vec![3]),
-                packet_report(0, 3, BYTES_PER_DC as u32, vec![4]),
+                packet_report(0, 0, IOT_BYTES_PER_DC as u32, vec![1]),
+                packet_report(0, 1, IOT_BYTES_PER_DC as u32, vec![2]),
+                packet_report(0, 2, IOT_BYTES_PER_DC as u32, vec![3]),
+                packet_report(0, 3, IOT_BYTES_PER_DC as u32, vec![4]),
             ]),
             &mut valid_packets,
             &mut invalid_packets,
@@ -418,24 +734,20 @@ async fn test_end_to_end() {
     assert_eq!(
         valid_packets,
         vec![
-            valid_packet(0, BYTES_PER_DC as u32, vec![1]),
-            valid_packet(1000, BYTES_PER_DC as u32, vec![2]),
-            valid_packet(2000, BYTES_PER_DC as u32, vec![3]),
+            valid_packet(0, IOT_BYTES_PER_DC as u32, vec![1]),
+            valid_packet(1000, IOT_BYTES_PER_DC as u32, vec![2]),
+            valid_packet(2000, IOT_BYTES_PER_DC as u32, vec![3]),
         ]
     );
 
     // Last packet is invalid:
     assert_eq!(
         invalid_packets,
-        vec![invalid_packet(BYTES_PER_DC as u32, vec![4])]
+        vec![invalid_packet(IOT_BYTES_PER_DC as u32, vec![4])]
     );
 
     // Check current balance:
-    let balance = {
-        let balances = verifier.debiter.balances();
-        let balances = balances.lock().await;
-        *balances.get(&payer).unwrap()
-    };
+    let balance = verifier.debiter.balance(&payer).await.unwrap();
     assert_eq!(balance.balance, 3);
     assert_eq!(balance.burned, 3);
 
@@ -447,11 +759,7 @@ async fn test_end_to_end() {
     burner.burn().await.unwrap();
 
     // Now that we've burn, the balances and burn amount should be reset:
-    let balance = {
-        let balances = verifier.debiter.balances();
-        let balances = balances.lock().await;
-        *balances.get(&payer).unwrap()
-    };
+    let balance = verifier.debiter.balance(&payer).await.unwrap();
     assert_eq!(balance.balance, 0);
     assert_eq!(balance.burned, 0);
 
@@ -471,7 +779,7 @@ async fn test_end_to_end() {
         .verify(
             1,
             pending_burns.clone(),
-            stream::iter(vec![packet_report(0, 4, BYTES_PER_DC as u32, vec![5])]),
+            stream::iter(vec![packet_report(0, 4, IOT_BYTES_PER_DC as u32, vec![5])]),
             &mut valid_packets,
             &mut invalid_packets,
         )
@@ -482,7 +790,7 @@ async fn test_end_to_end() {
 
     assert_eq!(
         invalid_packets,
-        vec![invalid_packet(BYTES_PER_DC as u32, vec![5])]
+        vec![invalid_packet(IOT_BYTES_PER_DC as u32, vec![5])]
     );
 
     // Add one DC to the balance:
@@ -498,8 +806,8 @@ async fn test_end_to_end() {
             1,
             pending_burns.clone(),
             stream::iter(vec![
-                packet_report(0, 5, 2 * BYTES_PER_DC as u32, vec![6]),
-                packet_report(0, 6, BYTES_PER_DC as u32, vec![7]),
+                packet_report(0, 5, 2 * IOT_BYTES_PER_DC as u32, vec![6]),
+                packet_report(0, 6, IOT_BYTES_PER_DC as u32, vec![7]),
             ]),
             &mut valid_packets,
             &mut invalid_packets,
@@ -509,18 +817,375 @@ async fn test_end_to_end() {
 
     assert_eq!(
         invalid_packets,
-        vec![invalid_packet(2 * BYTES_PER_DC as u32, vec![6])]
+        vec![invalid_packet(2 * IOT_BYTES_PER_DC as u32, vec![6])]
     );
     assert_eq!(
         valid_packets,
-        vec![valid_packet(6000, BYTES_PER_DC as u32, vec![7])]
+        vec![valid_packet(6000, IOT_BYTES_PER_DC as u32, vec![7])]
     );
 
-    let balance = {
-        let balances = verifier.debiter.balances();
-        let balances = balances.lock().await;
-        *balances.get(&payer).unwrap()
-    };
+    let balance = verifier.debiter.balance(&payer).await.unwrap();
     assert_eq!(balance.balance, 1);
     assert_eq!(balance.burned, 1);
 }
+
+#[tokio::test]
+async fn test_pending_burns_are_settled_on_load() {
+    let payer = PublicKeyBinary::from(vec![0]);
+    let overpaid = PublicKeyBinary::from(vec![1]);
+
+    // Pending burns from a previous run, one of them burned twice:
+    let mut pending_burns = StoredBurns(vec![(payer.clone(), 8), (overpaid.clone(), -2)]);
+
+    let solana_network = MockSolanaNetwork::new()
+        .with_balance(payer.clone(), 10)
+        .await
+        .with_balance(overpaid.clone(), 10)
+        .await;
+    let balance_cache = balances::load(&mut pending_burns, solana_network)
+        .await
+        .unwrap();
+
+    // Only what is left after the pending burns can be spent
+    assert_eq!(
+        balance_cache.debit_if_sufficient(&payer, 3).await.unwrap(),
+        None
+    );
+    assert_eq!(
+        balance_cache.debit_if_sufficient(&payer, 2).await.unwrap(),
+        Some(0)
+    );
+    assert_eq!(
+        balance_cache
+            .debit_if_sufficient(&overpaid, 10)
+            .await
+            .unwrap(),
+        Some(0)
+    );
+}
+
+#[tokio::test]
+async fn test_failed_burn_is_retried() {
+    let payer = PublicKeyBinary::from(vec![0]);
+
+    // Pending burns from a previous run:
+    let mut pending_burns: Arc<Mutex<HashMap<PublicKeyBinary, u64>>> =
+        Arc::new(Mutex::new(HashMap::from([(payer.clone(), 5_u64)])));
+
+    // Solana network:
+    let solana_network = MockSolanaNetwork::new()
+        .with_balance(payer.clone(), 10)
+        .await;
+
+    // Balance cache:
+    let balance_cache = balances::load(&mut pending_burns, solana_network.clone())
+        .await
+        .unwrap();
+
+    // Burner:
+    let mut burner = Burner::new(
+        pending_burns.clone(),
+        &balance_cache,
+        0,
+        solana_network.clone(),
+    );
+
+    // A failed burn transaction leaves everything pending:
+    solana_network.fail_burns(true);
+    assert!(burner.burn().await.is_err());
+    assert_eq!(*pending_burns.lock().await.get(&payer).unwrap(), 5);
+    assert_eq!(balance_cache.balance(&payer).await.unwrap().burned, 5);
+    assert_eq!(solana_network.balance(&payer).await, 10);
+
+    // The next burn goes through:
+    solana_network.fail_burns(false);
+    burner.burn().await.unwrap();
+    assert_eq!(*pending_burns.lock().await.get(&payer).unwrap(), 0);
+    assert_eq!(balance_cache.balance(&payer).await.unwrap().burned, 0);
+    assert_eq!(solana_network.balance(&payer).await, 5);
+    assert_eq!(solana_network.burns().await, vec![(payer, 5)]);
+}
+
+#[tokio::test]
+async fn test_reports_failing_verification_are_invalid() {
+    let gateway = helium_crypto::Keypair::generate(
+        helium_crypto::KeyTag {
+            network: helium_crypto::Network::MainNet,
+            key_type: helium_crypto::KeyType::Ed25519,
+        },
+        &mut rand::rngs::OsRng,
+    )
+    .public_key()
+    .to_vec();
+    let signed_report = PacketRouterPacketReport {
+        gateway: PublicKeyBinary::from(gateway.clone()),
+        ..packet_report(0, 0, 24, vec![1])
+    };
+    let packets = vec![signed_report, packet_report(0, 1, 24, vec![2])];
+    let orgs = MockConfigServer::default();
+    orgs.insert(0_u64, PublicKeyBinary::from(vec![0])).await;
+    let mut balances = HashMap::new();
+    balances.insert(PublicKeyBinary::from(vec![0]), 10);
+    let balances = InstantBurnedBalance(Arc::new(Mutex::new(balances)));
+    let mut valid_packets = Vec::new();
+    let mut invalid_packets = Vec::new();
+    let mut verifier = Verifier {
+        debiter: balances.clone(),
+        config_server: orgs,
+        pricing: DcPricing::iot(),
+        verify_reports: true,
+        org_locks: OrgLocks::default(),
+        packets_seen: None::<PacketsSeen>,
+        unknown_ouis: Default::default(),
+        batch_org_intents: false,
+    };
+
+    let stats = verifier
+        .verify(
+            1,
+            balances.clone(),
+            stream::iter(packets),
+            &mut valid_packets,
+            &mut invalid_packets,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        valid_packets,
+        vec![ValidPacket {
+            gateway,
+            ..valid_packet(0, 24, vec![1])
+        }]
+    );
+    // The second report has no valid gateway key
+    assert_eq!(invalid_packets, vec![invalid_packet(24, vec![2])]);
+    let hour = Utc.timestamp_opt(0, 0).unwrap();
+    assert_eq!(
+        stats.get(0, hour, Outcome::InvalidReport).unwrap().packets,
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_duplicate_reports_are_charged_once() {
+    let orgs = MockConfigServer::default();
+    orgs.insert(0_u64, PublicKeyBinary::from(vec![0])).await;
+    let mut balances = HashMap::new();
+    balances.insert(PublicKeyBinary::from(vec![0]), 10);
+    let balances = InstantBurnedBalance(Arc::new(Mutex::new(balances)));
+    let mut valid_packets = Vec::new();
+    let mut invalid_packets = Vec::new();
+    let mut verifier = Verifier {
+        debiter: balances.clone(),
+        config_server: orgs,
+        pricing: DcPricing::iot(),
+        verify_reports: false,
+        org_locks: OrgLocks::default(),
+        packets_seen: Some(PacketsSeen::new(&PacketsSeenSettings {
+            window: 1,
+            filter_capacity: Some(100),
+            ..Default::default()
+        })),
+        unknown_ouis: Default::default(),
+        batch_org_intents: false,
+    };
+
+    let stats = verifier
+        .verify(
+            1,
+            balances.clone(),
+            stream::iter(vec![
+                packet_report(0, 0, 24, vec![1]),
+                packet_report(0, 0, 24, vec![1]),
+                packet_report(0, 1, 24, vec![2]),
+            ]),
+            &mut valid_packets,
+            &mut invalid_packets,
+        )
+        .await
+        .unwrap();
+    // The first packet left the window and is remembered by the filter
+    verifier
+        .verify(
+            1,
+            balances.clone(),
+            stream::iter(vec![packet_report(0, 0, 24, vec![1])]),
+            &mut valid_packets,
+            &mut invalid_packets,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        valid_packets,
+        vec![valid_packet(0, 24, vec![1]), valid_packet(1, 24, vec![2])]
+    );
+    assert_eq!(
+        invalid_packets,
+        vec![invalid_packet(24, vec![1]), invalid_packet(24, vec![1])]
+    );
+    let hour = Utc.timestamp_opt(0, 0).unwrap();
+    assert_eq!(stats.get(0, hour, Outcome::Duplicate).unwrap().packets, 1);
+    assert_eq!(stats.get(0, hour, Outcome::Valid).unwrap().dcs, 2);
+}
+
+#[tokio::test]
+async fn test_unknown_ouis_are_invalid() {
+    let orgs = MockConfigServer::default();
+    orgs.insert(0_u64, PublicKeyBinary::from(vec![0])).await;
+    let mut balances = HashMap::new();
+    balances.insert(PublicKeyBinary::from(vec![0]), 10);
+    let balances = InstantBurnedBalance(Arc::new(Mutex::new(balances)));
+    let mut valid_packets = Vec::new();
+    let mut invalid_packets = Vec::new();
+    let mut verifier = Verifier {
+        debiter: balances.clone(),
+        config_server: orgs.clone(),
+        pricing: DcPricing::iot(),
+        verify_reports: false,
+        org_locks: OrgLocks::default(),
+        packets_seen: None::<PacketsSeen>,
+        unknown_ouis: Default::default(),
+        batch_org_intents: false,
+    };
+
+    let stats = verifier
+        .verify(
+            1,
+            balances.clone(),
+            stream::iter(vec![
+                packet_report(7, 0, 24, vec![1]),
+                packet_report(0, 1, 24, vec![2]),
+            ]),
+            &mut valid_packets,
+            &mut invalid_packets,
+        )
+        .await
+        .unwrap();
+    // The unknown oui is remembered for a while even once the org exists
+    orgs.insert(7_u64, PublicKeyBinary::from(vec![0])).await;
+    verifier
+        .verify(
+            1,
+            balances.clone(),
+            stream::iter(vec![packet_report(7, 2, 24, vec![3])]),
+            &mut valid_packets,
+            &mut invalid_packets,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(valid_packets, vec![valid_packet(1, 24, vec![2])]);
+    assert_eq!(
+        invalid_packets,
+        vec![invalid_packet(24, vec![1]), invalid_packet(24, vec![3])]
+    );
+    let hour = Utc.timestamp_opt(0, 0).unwrap();
+    assert_eq!(stats.get(7, hour, Outcome::UnknownOui).unwrap().packets, 1);
+}
+
+#[tokio::test]
+async fn test_injected_failures_stop_verification() {
+    let packets = vec![
+        packet_report(0, 0, 24, vec![1]),
+        packet_report(0, 1, 24, vec![2]),
+        packet_report(0, 2, 24, vec![3]),
+    ];
+    let orgs = MockConfigServer::default();
+    orgs.insert(0_u64, PublicKeyBinary::from(vec![0])).await;
+    let mut balances = HashMap::new();
+    balances.insert(PublicKeyBinary::from(vec![0]), 10);
+    let balances = InstantBurnedBalance(Arc::new(Mutex::new(balances)));
+    let mut valid_packets = Vec::new();
+    let mut invalid_packets = Vec::new();
+    let debiter_faults = Faults::new().fail_every(2);
+    let mut verifier = Verifier {
+        debiter: Faulty::new(balances.clone(), debiter_faults.clone()),
+        config_server: orgs,
+        pricing: DcPricing::iot(),
+        verify_reports: false,
+        org_locks: OrgLocks::default(),
+        packets_seen: None::<PacketsSeen>,
+        unknown_ouis: Default::default(),
+        batch_org_intents: false,
+    };
+
+    // The second debit fails:
+    let result = verifier
+        .verify(
+            1,
+            balances.clone(),
+            stream::iter(packets.clone()),
+            &mut valid_packets,
+            &mut invalid_packets,
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(VerificationError::DebitError(FaultyError::Injected(_)))
+    ));
+    assert_eq!(valid_packets, vec![valid_packet(0, 24, vec![1])]);
+    assert_eq!(debiter_faults.calls(), 2);
+
+    // A write whose acknowledgement is lost has still written the packet and
+    // burned its amount, which is why the daemon rolls back the whole file:
+    debiter_faults.clear();
+    let mut written = Vec::new();
+    let result = verifier
+        .verify(
+            1,
+            balances.clone(),
+            stream::iter(packets),
+            Faulty::new(&mut written, Faults::new().partial_every(1)),
+            &mut invalid_packets,
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(VerificationError::ValidPacketWriterError(
+            FaultyError::Injected(_)
+        ))
+    ));
+    assert_eq!(written, vec![valid_packet(0, 24, vec![1])]);
+    assert_eq!(
+        *balances
+            .0
+            .lock()
+            .await
+            .get(&PublicKeyBinary::from(vec![0]))
+            .unwrap(),
+        8
+    );
+    assert!(invalid_packets.is_empty());
+}
+
+#[tokio::test]
+async fn test_burns_failing_every_other_call_are_retried() {
+    let payer = PublicKeyBinary::from(vec![0]);
+    let mut pending_burns: Arc<Mutex<HashMap<PublicKeyBinary, u64>>> =
+        Arc::new(Mutex::new(HashMap::from([(payer.clone(), 5_u64)])));
+    let mock_network = MockSolanaNetwork::new()
+        .with_balance(payer.clone(), 10)
+        .await;
+    let faults = Faults::new().fail_every(2);
+    let solana_network = Faulty::new(mock_network.clone(), faults.clone());
+
+    // Loading the balance is the first call:
+    let balance_cache = balances::load(&mut pending_burns, solana_network.clone())
+        .await
+        .unwrap();
+    let mut burner = Burner::new(pending_burns.clone(), &balance_cache, 0, solana_network);
+
+    // The burn is the second call and fails without reaching the chain:
+    assert!(burner.burn().await.is_err());
+    assert_eq!(*pending_burns.lock().await.get(&payer).unwrap(), 5);
+    assert!(mock_network.burns().await.is_empty());
+
+    burner.burn().await.unwrap();
+    assert_eq!(*pending_burns.lock().await.get(&payer).unwrap(), 0);
+    assert_eq!(balance_cache.balance(&payer).await.unwrap().burned, 0);
+    assert_eq!(mock_network.burns().await, vec![(payer, 5)]);
+    assert_eq!(faults.calls(), 3);
+    assert_eq!(faults.injected(), 1);
+}
@@ -0,0 +1,87 @@
+use db_store::{Error, LeaderElection, LeaderElectionSettings};
+use sqlx::PgPool;
+use std::time::Duration;
+
+const NAME: &str = "iot-packet-verifier";
+
+fn settings(lease_timeout: u64) -> LeaderElectionSettings {
+    LeaderElectionSettings { lease_timeout }
+}
+
+async fn holder(pool: &PgPool) -> Option<String> {
+    sqlx::query_scalar("select holder from leader_leases where name = $1")
+        .bind(NAME)
+        .fetch_optional(pool)
+        .await
+        .unwrap()
+}
+
+#[test]
+fn lease_timeout_must_be_positive() {
+    assert!(settings(1).problems().is_empty());
+    assert_eq!(
+        settings(0).problems(),
+        vec!["leader_election.lease_timeout must be at least 1".to_string()]
+    );
+}
+
+#[sqlx::test]
+async fn only_one_instance_leads(pool: PgPool) {
+    let leader = LeaderElection::new(pool.clone(), NAME, &settings(30));
+    let standby = LeaderElection::new(pool.clone(), NAME, &settings(30));
+    let (trigger, shutdown) = triggered::trigger();
+
+    assert!(leader.wait_for_leadership(&shutdown).await.unwrap());
+    let lease_holder = holder(&pool).await;
+    assert!(lease_holder.is_some());
+
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        trigger.trigger();
+    });
+    assert!(!standby.wait_for_leadership(&shutdown).await.unwrap());
+    assert_eq!(holder(&pool).await, lease_holder);
+}
+
+#[sqlx::test]
+async fn standby_takes_over_an_expired_lease(pool: PgPool) {
+    let leader = LeaderElection::new(pool.clone(), NAME, &settings(1));
+    let standby = LeaderElection::new(pool.clone(), NAME, &settings(1));
+    let (_trigger, shutdown) = triggered::trigger();
+
+    assert!(leader.wait_for_leadership(&shutdown).await.unwrap());
+    let leader_holder = holder(&pool).await;
+
+    // the leader never renews, the standby acquires the lease once expired
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        standby.wait_for_leadership(&shutdown),
+    )
+    .await
+    .expect("standby took over")
+    .unwrap();
+    assert_ne!(holder(&pool).await, leader_holder);
+
+    // and the former leader stops on its next renewal
+    assert!(matches!(
+        leader.maintain(shutdown).await,
+        Err(Error::LeadershipLost(name)) if name == NAME
+    ));
+}
+
+#[sqlx::test]
+async fn lease_is_released_on_shutdown(pool: PgPool) {
+    let leader = LeaderElection::new(pool.clone(), NAME, &settings(30));
+    let standby = LeaderElection::new(pool.clone(), NAME, &settings(30));
+    let (trigger, shutdown) = triggered::trigger();
+
+    assert!(leader.wait_for_leadership(&shutdown).await.unwrap());
+    let maintained = tokio::spawn(leader.maintain(shutdown));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    trigger.trigger();
+    maintained.await.unwrap().unwrap();
+    assert_eq!(holder(&pool).await, None);
+
+    let (_trigger, shutdown) = triggered::trigger();
+    assert!(standby.wait_for_leadership(&shutdown).await.unwrap());
+}
@@ -0,0 +1,121 @@
+use chrono::{Duration, Utc};
+use db_store::maintenance::{Maintenance, MaintenanceSettings, RetentionSettings, Table};
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+const BURN_JOURNAL: Table = Table::new("burn_journal", "burned_at").archived();
+const PENDING_BURNS: Table = Table::new("pending_burns", "last_burn").condition("amount = 0");
+
+fn settings(table: &str, days: u64, archive: bool) -> MaintenanceSettings {
+    MaintenanceSettings {
+        batch_size: 1,
+        retention: HashMap::from([(table.to_string(), RetentionSettings { days, archive })]),
+        ..Default::default()
+    }
+}
+
+async fn journal_burn(pool: &PgPool, signature: &str, days_ago: i64) {
+    sqlx::query(
+        "INSERT INTO burn_journal (signature, payer, amount, burned_at) VALUES ($1, 'payer', 1, $2)",
+    )
+    .bind(signature)
+    .bind(Utc::now() - Duration::days(days_ago))
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+async fn signatures(pool: &PgPool, table: &str) -> Vec<String> {
+    sqlx::query_scalar(&format!("SELECT signature FROM {table} ORDER BY signature"))
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test]
+async fn rows_past_the_retention_are_purged(pool: PgPool) {
+    for (signature, days_ago) in [("a", 10), ("b", 8), ("c", 1)] {
+        journal_burn(&pool, signature, days_ago).await;
+    }
+
+    let maintenance = Maintenance::new(
+        pool.clone(),
+        "test",
+        &settings("burn_journal", 7, false),
+        &[BURN_JOURNAL],
+    )
+    .unwrap();
+    maintenance.purge_all().await;
+
+    assert_eq!(signatures(&pool, "burn_journal").await, vec!["c"]);
+    assert!(signatures(&pool, "burn_journal_archive").await.is_empty());
+}
+
+#[sqlx::test]
+async fn purged_rows_are_moved_to_the_archive(pool: PgPool) {
+    for (signature, days_ago) in [("a", 10), ("b", 8), ("c", 1)] {
+        journal_burn(&pool, signature, days_ago).await;
+    }
+
+    let maintenance = Maintenance::new(
+        pool.clone(),
+        "test",
+        &settings("burn_journal", 7, true),
+        &[BURN_JOURNAL],
+    )
+    .unwrap();
+    maintenance.purge_all().await;
+
+    assert_eq!(signatures(&pool, "burn_journal").await, vec!["c"]);
+    assert_eq!(
+        signatures(&pool, "burn_journal_archive").await,
+        vec!["a", "b"]
+    );
+}
+
+#[sqlx::test]
+async fn only_rows_matching_the_condition_are_purged(pool: PgPool) {
+    let last_burn = Utc::now().naive_utc() - Duration::days(10);
+    for (payer, amount) in [("paid", 0_i64), ("owing", 5)] {
+        sqlx::query("INSERT INTO pending_burns (payer, amount, last_burn) VALUES ($1, $2, $3)")
+            .bind(payer)
+            .bind(amount)
+            .bind(last_burn)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+
+    let maintenance = Maintenance::new(
+        pool.clone(),
+        "test",
+        &settings("pending_burns", 7, false),
+        &[PENDING_BURNS],
+    )
+    .unwrap();
+    maintenance.purge_all().await;
+
+    let payers: Vec<String> = sqlx::query_scalar("SELECT payer FROM pending_burns")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(payers, vec!["owing"]);
+}
+
+#[sqlx::test]
+async fn tables_without_an_archive_can_not_be_archived(pool: PgPool) {
+    assert!(Maintenance::new(
+        pool.clone(),
+        "test",
+        &settings("pending_burns", 7, true),
+        &[PENDING_BURNS],
+    )
+    .is_err());
+    assert!(Maintenance::new(
+        pool,
+        "test",
+        &settings("packets", 7, false),
+        &[PENDING_BURNS]
+    )
+    .is_err());
+}
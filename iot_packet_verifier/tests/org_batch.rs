@@ -0,0 +1,112 @@
+use helium_crypto::{KeyTag, KeyType as CryptoKeyType, Keypair, Network, PublicKeyBinary};
+use iot_config::{
+    admin::{AuthCache, CacheKeys, KeyType},
+    audit::AuditLogger,
+    client::OrgClient,
+    org,
+    org_batch::OrgBatchService,
+    org_review::OrgReviewStateV1,
+};
+use iot_packet_verifier::verifier::ConfigServer;
+use oracle_signer::FileSigner;
+use rand::rngs::OsRng;
+use sqlx::PgPool;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::{broadcast, Mutex};
+use tonic::transport::{Endpoint, Server};
+
+fn keypair() -> Keypair {
+    Keypair::generate(
+        KeyTag {
+            network: Network::MainNet,
+            key_type: CryptoKeyType::Ed25519,
+        },
+        &mut OsRng,
+    )
+}
+
+async fn create_org(pool: &PgPool, review_state: OrgReviewStateV1, locked: bool) -> u64 {
+    let owner = PublicKeyBinary::from(keypair().public_key().to_vec()).to_string();
+    let oui: i64 = sqlx::query_scalar(
+        r#"
+        insert into organizations (owner_pubkey, payer_pubkey, review_state, locked)
+        values ($1, $1, $2, $3) returning oui
+        "#,
+    )
+    .bind(owner)
+    .bind(review_state)
+    .bind(locked)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    oui as u64
+}
+
+/// Serve the org batch service of the config key on a local port, accepting
+/// the requests of the verifier key
+async fn serve(
+    pool: PgPool,
+    config: Keypair,
+    verifier: &Keypair,
+    shutdown: triggered::Listener,
+) -> SocketAddr {
+    let keys = CacheKeys::from([(verifier.public_key().clone(), KeyType::Oracle)]);
+    let service = OrgBatchService::new(
+        AuthCache::from_keys(keys),
+        AuditLogger::new().0,
+        pool,
+        Arc::new(config),
+        broadcast::channel(10).0,
+    );
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(service)
+            .serve_with_shutdown(addr, shutdown),
+    );
+    while tokio::net::TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    addr
+}
+
+#[sqlx::test(migrations = "../iot_config/migrations")]
+async fn org_states_are_set_by_the_batch_service(pool: PgPool) {
+    let out_of_balance = create_org(&pool, OrgReviewStateV1::Approved, false).await;
+    let topped_up = create_org(&pool, OrgReviewStateV1::Approved, true).await;
+    let pending = create_org(&pool, OrgReviewStateV1::Pending, true).await;
+    let unknown = pending + 1;
+
+    let (trigger, shutdown) = triggered::trigger();
+    let config = keypair();
+    let config_pubkey = config.public_key().clone();
+    let verifier = keypair();
+    let addr = serve(pool.clone(), config, &verifier, shutdown).await;
+    let channel = Endpoint::from_shared(format!("http://{addr}"))
+        .unwrap()
+        .connect_lazy();
+    let config_server = Arc::new(Mutex::new(OrgClient::new(
+        channel,
+        Arc::new(FileSigner::new(verifier)),
+        config_pubkey,
+    )));
+
+    let applied = config_server
+        .set_org_states(&[
+            (out_of_balance, false),
+            (topped_up, true),
+            (pending, true),
+            (unknown, true),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(applied, vec![(out_of_balance, false), (topped_up, true)]);
+
+    assert!(org::is_locked(out_of_balance, &pool).await.unwrap());
+    assert!(!org::is_locked(topped_up, &pool).await.unwrap());
+    assert!(org::is_locked(pending, &pool).await.unwrap());
+    trigger.trigger();
+}
@@ -0,0 +1,164 @@
+use async_trait::async_trait;
+use helium_crypto::PublicKeyBinary;
+use iot_packet_verifier::{
+    org_intents::{self, IntentReconciler},
+    org_lock::OrgLocks,
+    verifier::{ConfigServer, Org},
+};
+use solana::mock::MockSolanaNetwork;
+use sqlx::PgPool;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{Mutex, Notify};
+
+const OUI: u64 = 1;
+
+/// Config service of a single org recording every state change sent to it
+#[derive(Clone, Default)]
+struct RecordingConfigServer {
+    locked: Arc<AtomicBool>,
+    fail: Arc<AtomicBool>,
+    sent: Arc<Mutex<Vec<(u64, bool)>>>,
+}
+
+impl RecordingConfigServer {
+    async fn set_enabled(&self, oui: u64, enabled: bool) -> Result<(), ()> {
+        if self.fail.load(Ordering::SeqCst) {
+            return Err(());
+        }
+        self.locked.store(!enabled, Ordering::SeqCst);
+        self.sent.lock().await.push((oui, enabled));
+        Ok(())
+    }
+
+    async fn sent(&self) -> Vec<(u64, bool)> {
+        self.sent.lock().await.clone()
+    }
+}
+
+#[async_trait]
+impl ConfigServer for RecordingConfigServer {
+    type Error = ();
+
+    async fn fetch_org(
+        &self,
+        _oui: u64,
+        _cache: &mut HashMap<u64, PublicKeyBinary>,
+    ) -> Result<Option<PublicKeyBinary>, ()> {
+        Ok(Some(payer()))
+    }
+
+    async fn disable_org(&self, oui: u64) -> Result<(), ()> {
+        self.set_enabled(oui, false).await
+    }
+
+    async fn enable_org(&self, oui: u64) -> Result<(), ()> {
+        self.set_enabled(oui, true).await
+    }
+
+    async fn list_orgs(&self) -> Result<Vec<Org>, ()> {
+        Ok(vec![Org {
+            oui: OUI,
+            payer: payer(),
+            locked: self.locked.load(Ordering::SeqCst),
+        }])
+    }
+}
+
+fn payer() -> PublicKeyBinary {
+    PublicKeyBinary::from(vec![0])
+}
+
+/// Run the funds monitor of the locked org with a funded payer until it
+/// had a chance to enable the org
+async fn monitor_funds(pool: &PgPool, config_server: &RecordingConfigServer, batched: bool) {
+    let solana = MockSolanaNetwork::new().with_balance(payer(), 100).await;
+    let (trigger, listener) = triggered::trigger();
+    let monitor = tokio::spawn(config_server.clone().monitor_funds(
+        solana,
+        Arc::new(Mutex::new(HashMap::<PublicKeyBinary, u64>::new())),
+        pool.clone(),
+        batched,
+        OrgLocks::default(),
+        1,
+        Duration::from_secs(100),
+        Arc::new(Notify::new()),
+        listener,
+    ));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    trigger.trigger();
+    monitor.await.unwrap().unwrap();
+}
+
+#[sqlx::test]
+async fn enable_replaces_an_unsent_disable(pool: PgPool) {
+    // The disable of the verifier was committed but the config service
+    // failed to apply it
+    let config_server = RecordingConfigServer::default();
+    config_server.locked.store(true, Ordering::SeqCst);
+    org_intents::record(&pool, OUI, false).await.unwrap();
+
+    monitor_funds(&pool, &config_server, false).await;
+    assert_eq!(config_server.sent().await, vec![(OUI, true)]);
+
+    // The stale disable is never sent over the enable
+    let reconciler = IntentReconciler::new(pool.clone(), Duration::from_secs(60));
+    assert_eq!(reconciler.reconcile(&config_server).await.unwrap(), 0);
+    assert_eq!(config_server.sent().await, vec![(OUI, true)]);
+    assert!(org_intents::unsent(&pool).await.unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn failed_enable_is_sent_by_the_reconciler(pool: PgPool) {
+    let config_server = RecordingConfigServer::default();
+    config_server.locked.store(true, Ordering::SeqCst);
+    config_server.fail.store(true, Ordering::SeqCst);
+
+    monitor_funds(&pool, &config_server, false).await;
+    assert!(config_server.sent().await.is_empty());
+    let unsent = org_intents::unsent(&pool).await.unwrap();
+    assert_eq!(unsent.len(), 1);
+    assert!(unsent[0].enabled);
+
+    config_server.fail.store(false, Ordering::SeqCst);
+    let reconciler = IntentReconciler::new(pool.clone(), Duration::from_secs(60));
+    assert_eq!(reconciler.reconcile(&config_server).await.unwrap(), 1);
+    assert_eq!(config_server.sent().await, vec![(OUI, true)]);
+    assert!(org_intents::unsent(&pool).await.unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn sending_an_older_state_does_not_mark_a_newer_intent(pool: PgPool) {
+    org_intents::record(&pool, OUI, false).await.unwrap();
+    org_intents::record(&pool, OUI, true).await.unwrap();
+    org_intents::mark_sent(&pool, OUI, false).await.unwrap();
+
+    let unsent = org_intents::unsent(&pool).await.unwrap();
+    assert_eq!(unsent.len(), 1);
+    assert!(unsent[0].enabled);
+}
+
+#[sqlx::test]
+async fn batched_enables_replace_unsent_disables(pool: PgPool) {
+    // A batched disable not yet sent when the payer is topped up
+    let config_server = RecordingConfigServer::default();
+    config_server.locked.store(true, Ordering::SeqCst);
+    org_intents::record(&pool, OUI, false).await.unwrap();
+
+    monitor_funds(&pool, &config_server, true).await;
+    assert!(config_server.sent().await.is_empty());
+    let unsent = org_intents::unsent(&pool).await.unwrap();
+    assert_eq!(unsent.len(), 1);
+    assert!(unsent[0].enabled);
+
+    let reconciler = IntentReconciler::new(pool.clone(), Duration::from_secs(60)).batched(true);
+    assert_eq!(reconciler.reconcile(&config_server).await.unwrap(), 1);
+    assert_eq!(config_server.sent().await, vec![(OUI, true)]);
+    assert!(org_intents::unsent(&pool).await.unwrap().is_empty());
+}
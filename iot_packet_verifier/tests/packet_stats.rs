@@ -0,0 +1,85 @@
+use chrono::{Duration, TimeZone, Utc};
+use helium_proto::services::packet_verifier::InvalidPacketReason;
+use iot_packet_verifier::stats::{self, HourlyStats, Outcome, PacketStats};
+use sqlx::PgPool;
+
+#[sqlx::test]
+async fn saved_counts_are_added_to_the_totals(pool: PgPool) {
+    let hour = Utc.with_ymd_and_hms(2023, 9, 1, 10, 0, 0).unwrap();
+    let insufficient = Outcome::Invalid(InvalidPacketReason::InsufficientBalance);
+
+    let mut first = PacketStats::default();
+    first.record(1, hour, Outcome::Valid, 2);
+    first.record(1, hour + Duration::minutes(30), insufficient, 0);
+    first.record(2, hour, Outcome::Valid, 5);
+    let mut second = PacketStats::default();
+    second.record(1, hour + Duration::minutes(10), Outcome::Valid, 3);
+    second.record(1, hour + Duration::hours(1), Outcome::Valid, 1);
+
+    for stats in [first, second] {
+        let mut transaction = pool.begin().await.unwrap();
+        stats.save(&mut transaction).await.unwrap();
+        transaction.commit().await.unwrap();
+    }
+
+    let fetched = stats::fetch(&pool, 1, hour, hour + Duration::hours(2))
+        .await
+        .unwrap();
+    assert_eq!(
+        fetched,
+        vec![
+            HourlyStats {
+                hour,
+                outcome: insufficient.as_str().to_string(),
+                packets: 1,
+                dcs: 0,
+            },
+            HourlyStats {
+                hour,
+                outcome: Outcome::Valid.as_str().to_string(),
+                packets: 2,
+                dcs: 5,
+            },
+            HourlyStats {
+                hour: hour + Duration::hours(1),
+                outcome: Outcome::Valid.as_str().to_string(),
+                packets: 1,
+                dcs: 1,
+            },
+        ]
+    );
+}
+
+#[sqlx::test]
+async fn fetch_is_limited_to_the_org_and_range(pool: PgPool) {
+    let hour = Utc.with_ymd_and_hms(2023, 9, 1, 10, 0, 0).unwrap();
+    let mut stats = PacketStats::default();
+    stats.record(1, hour - Duration::hours(1), Outcome::Valid, 1);
+    stats.record(1, hour, Outcome::Valid, 1);
+    stats.record(1, hour + Duration::hours(1), Outcome::Valid, 1);
+    stats.record(2, hour, Outcome::Valid, 1);
+    let mut transaction = pool.begin().await.unwrap();
+    stats.save(&mut transaction).await.unwrap();
+    transaction.commit().await.unwrap();
+
+    let fetched = stats::fetch(&pool, 1, hour, hour + Duration::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(fetched.len(), 1);
+    assert_eq!(fetched[0].hour, hour);
+}
+
+#[sqlx::test]
+async fn rolled_back_counts_are_not_saved(pool: PgPool) {
+    let hour = Utc.with_ymd_and_hms(2023, 9, 1, 10, 0, 0).unwrap();
+    let mut stats = PacketStats::default();
+    stats.record(1, hour, Outcome::Valid, 1);
+    let mut transaction = pool.begin().await.unwrap();
+    stats.save(&mut transaction).await.unwrap();
+    transaction.rollback().await.unwrap();
+
+    let fetched = stats::fetch(&pool, 1, hour, hour + Duration::hours(1))
+        .await
+        .unwrap();
+    assert!(fetched.is_empty());
+}
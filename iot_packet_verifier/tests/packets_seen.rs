@@ -0,0 +1,60 @@
+use iot_packet_verifier::packets_seen::{PacketDeduper, PacketsSeenDb};
+use sqlx::PgPool;
+
+async fn insert_batch(pool: &PgPool, ids: &[u128], commit: bool) -> Vec<bool> {
+    let mut transaction = pool.begin().await.unwrap();
+    let seen = PacketsSeenDb
+        .insert_batch(ids, &mut &mut transaction)
+        .await
+        .unwrap();
+    if commit {
+        transaction.commit().await.unwrap();
+    } else {
+        transaction.rollback().await.unwrap();
+    }
+    seen
+}
+
+#[sqlx::test]
+async fn packets_are_seen_once_committed(pool: PgPool) {
+    // Ids use the full range of u128
+    let big = u128::MAX - 1;
+    assert_eq!(
+        insert_batch(&pool, &[1, big, 1], true).await,
+        vec![false, false, true]
+    );
+    assert_eq!(
+        insert_batch(&pool, &[big, 2], true).await,
+        vec![true, false]
+    );
+}
+
+#[sqlx::test]
+async fn packets_of_rolled_back_files_are_forgotten(pool: PgPool) {
+    assert_eq!(insert_batch(&pool, &[1], false).await, vec![false]);
+    assert_eq!(insert_batch(&pool, &[1], true).await, vec![false]);
+    assert_eq!(insert_batch(&pool, &[1], true).await, vec![true]);
+}
+
+#[sqlx::test]
+async fn concurrent_claims_see_the_committed_packet(pool: PgPool) {
+    let mut first = pool.begin().await.unwrap();
+    assert_eq!(
+        PacketsSeenDb
+            .insert_batch(&[1], &mut &mut first)
+            .await
+            .unwrap(),
+        vec![false]
+    );
+
+    // The second claim waits for the first transaction
+    let second = tokio::spawn({
+        let pool = pool.clone();
+        async move { insert_batch(&pool, &[1], true).await }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert!(!second.is_finished());
+    first.commit().await.unwrap();
+
+    assert_eq!(second.await.unwrap(), vec![true]);
+}
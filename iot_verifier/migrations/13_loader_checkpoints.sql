@@ -0,0 +1,10 @@
+CREATE TABLE loader_checkpoints (
+	window_start TIMESTAMPTZ NOT NULL,
+	file_name VARCHAR NOT NULL,
+	file_type VARCHAR NOT NULL,
+	file_timestamp TIMESTAMPTZ NOT NULL,
+	-- xor filter keys of the packet data of the beacons of the file
+	filter_keys BIGINT[] NOT NULL DEFAULT '{}',
+	loaded_at TIMESTAMPTZ NOT NULL,
+	PRIMARY KEY (window_start, file_name)
+);
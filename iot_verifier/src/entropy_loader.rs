@@ -43,9 +43,8 @@ impl EntropyLoader {
         file_info_stream: FileInfoStream<EntropyReport>,
     ) -> anyhow::Result<()> {
         let mut transaction = self.pool.begin().await?;
-        file_info_stream
-            .into_stream(&mut transaction)
-            .await?
+        let (reports, completion) = file_info_stream.into_stream(&mut transaction).await?;
+        let transaction = reports
             .map(anyhow::Ok)
             .try_fold(transaction, |mut transaction, report| async move {
                 let id = hash(&report.data).as_bytes().to_vec();
@@ -60,9 +59,8 @@ impl EntropyLoader {
                 metrics::increment_counter!("oracles_iot_verifier_loader_entropy");
                 Ok(transaction)
             })
-            .await?
-            .commit()
             .await?;
+        completion.commit(transaction).await?;
         Ok(())
     }
 }
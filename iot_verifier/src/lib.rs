@@ -5,6 +5,7 @@ pub mod gateway_updater;
 mod hex_density;
 pub mod last_beacon;
 pub mod loader;
+pub mod loader_checkpoint;
 pub mod meta;
 pub mod packet_loader;
 pub mod poc;
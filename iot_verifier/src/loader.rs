@@ -1,5 +1,6 @@
 use crate::{
     gateway_cache::GatewayCache,
+    loader_checkpoint,
     meta::Meta,
     poc_report::{InsertBindings, IotStatus, Report, ReportType},
     telemetry::LoaderMetricTracker,
@@ -16,6 +17,7 @@ use file_store::{
 };
 use futures::{stream, StreamExt};
 use helium_crypto::PublicKeyBinary;
+use oracle_epoch::Epoch;
 use sqlx::PgPool;
 use std::{hash::Hasher, ops::DerefMut, time::Duration};
 use tokio::{
@@ -148,21 +150,26 @@ impl Loader {
             .max(window_max_lookback);
         let before_max = after + self.window_width;
         let before = (now - (self.window_width * 3)).min(before_max);
-        let cur_window_width = before - after;
+        // the window is empty while the loader is ahead of its lag
+        let window = Epoch::new(after, before.max(after))?;
         tracing::info!(
             "sliding window, after: {after}, before: {before}, cur width: {:?}, required width: {:?}",
-            cur_window_width.num_minutes(), self.window_width.num_minutes()
+            window.duration().num_minutes(), self.window_width.num_minutes()
         );
         // if the current window width is less than our expected width
         // then do nothing
         // this likely means our loader tick interval is set to a value
         // less than our width
-        if cur_window_width < self.window_width {
+        if window.duration() < self.window_width {
             tracing::info!("current window width insufficient. completed handling poc_report tick");
             return Ok(());
         }
         self.process_window(gateway_cache, after, before).await?;
-        Meta::update_last_timestamp(&self.pool, REPORTS_META_NAME, Some(before)).await?;
+        // the files loaded in the window are no longer skipped once the window moves
+        let mut tx = self.pool.begin().await?;
+        Meta::update_last_timestamp(&mut tx, REPORTS_META_NAME, Some(before)).await?;
+        loader_checkpoint::clear(&mut tx, before).await?;
+        tx.commit().await?;
         Report::pending_beacons_to_ready(&self.pool, now).await?;
         tracing::info!("completed handling poc_report tick");
         Ok(())
@@ -189,6 +196,7 @@ impl Loader {
                 &self.ingest_store,
                 gateway_cache,
                 after,
+                after,
                 before,
                 Some(&xor_data),
                 None,
@@ -227,6 +235,7 @@ impl Loader {
                 FileType::IotWitnessIngestReport,
                 &self.ingest_store,
                 gateway_cache,
+                after,
                 after - self.ingestor_rollup_time,
                 before + self.ingestor_rollup_time,
                 None,
@@ -249,6 +258,7 @@ impl Loader {
         file_type: FileType,
         store: &FileStore,
         gateway_cache: &GatewayCache,
+        window_start: chrono::DateTime<Utc>,
         after: chrono::DateTime<Utc>,
         before: chrono::DateTime<Utc>,
         xor_data: Option<&Mutex<Vec<u64>>>,
@@ -257,7 +267,20 @@ impl Loader {
         tracing::info!(
             "checking for new ingest files of type {file_type} after {after} and before {before}"
         );
-        let infos = store.list_all(file_type, after, before).await?;
+        let mut infos = store.list_all(file_type, after, before).await?;
+        // files loaded in the window before a restart are skipped, keeping
+        // the filter keys of their beacons
+        let loaded = loader_checkpoint::loaded(&self.pool, window_start, file_type).await?;
+        if !loaded.is_empty() {
+            infos.retain(|file_info| !loaded.contains_key(&file_info.key));
+            tracing::info!(
+                "skipping {} ingest files of type {file_type} already loaded",
+                loaded.len()
+            );
+            if let Some(xor_data) = xor_data {
+                xor_data.lock().await.extend(loaded.into_values().flatten());
+            }
+        }
         if infos.is_empty() {
             tracing::info!("no available ingest files of type {file_type}");
             return Ok(());
@@ -270,6 +293,7 @@ impl Loader {
                     .process_file(
                         store,
                         file_info.clone(),
+                        window_start,
                         gateway_cache,
                         xor_data,
                         xor_filter,
@@ -297,12 +321,15 @@ impl Loader {
         &self,
         store: &FileStore,
         file_info: FileInfo,
+        window_start: DateTime<Utc>,
         gateway_cache: &GatewayCache,
         xor_data: Option<&Mutex<Vec<u64>>>,
         xor_filter: Option<&Xor16>,
     ) -> anyhow::Result<()> {
         let file_type = file_info.file_type;
         let tx = Mutex::new(self.pool.begin().await?);
+        // the filter keys of the file are kept apart for its checkpoint
+        let file_xor_data = xor_data.map(|_| Mutex::new(Vec::<u64>::new()));
         let metrics = LoaderMetricTracker::new();
         store
             .stream_file(file_info.clone())
@@ -316,7 +343,7 @@ impl Loader {
                             tracing::warn!("skipping report of type {file_type} due to error {err:?}")
                         }
                         Ok(buf) => {
-                            match self.handle_report(file_type, &buf, gateway_cache, xor_data, xor_filter, &metrics).await
+                            match self.handle_report(file_type, &buf, gateway_cache, file_xor_data.as_ref(), xor_filter, &metrics).await
                                 {
                                     Ok(Some(bindings)) =>  inserts.push(bindings),
                                     Ok(None) => (),
@@ -335,7 +362,15 @@ impl Loader {
                 }
             }).await;
 
-        tx.into_inner().commit().await?;
+        let file_keys = file_xor_data
+            .map(|keys| keys.into_inner())
+            .unwrap_or_default();
+        let mut tx = tx.into_inner();
+        loader_checkpoint::record(&mut tx, window_start, &file_info, &file_keys).await?;
+        tx.commit().await?;
+        if let Some(xor_data) = xor_data {
+            xor_data.lock().await.extend(file_keys);
+        }
         metrics.record_metrics();
         Ok(())
     }
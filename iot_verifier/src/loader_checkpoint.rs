@@ -0,0 +1,84 @@
+//! Checkpoints of the files loaded in a loader window.
+//!
+//! The loader only moves its window forward once every file of the window
+//! was loaded, so a restart in the middle of a window loads the whole window
+//! again. Each file records a checkpoint in the transaction inserting its
+//! reports, and a restarted loader skips the files of the window that have
+//! one. The witnesses of a window are filtered by the packet data of its
+//! beacons, so a beacon file checkpoint keeps the filter keys of the file to
+//! rebuild the filter without loading the file again. The checkpoints of a
+//! window are cleared with the move of the window.
+
+use chrono::{DateTime, Utc};
+use file_store::{FileInfo, FileType};
+use sqlx::{Postgres, Transaction};
+use std::collections::HashMap;
+
+#[derive(thiserror::Error, Debug)]
+#[error("loader checkpoint error: {0}")]
+pub struct LoaderCheckpointError(#[from] sqlx::Error);
+
+/// Record the file loaded in the window starting at `window_start`
+pub async fn record(
+    tx: &mut Transaction<'_, Postgres>,
+    window_start: DateTime<Utc>,
+    file_info: &FileInfo,
+    filter_keys: &[u64],
+) -> Result<(), LoaderCheckpointError> {
+    let filter_keys: Vec<i64> = filter_keys.iter().map(|key| *key as i64).collect();
+    sqlx::query(
+        r#"
+        insert into loader_checkpoints (window_start, file_name, file_type, file_timestamp, filter_keys, loaded_at)
+        values ($1, $2, $3, $4, $5, $6)
+        on conflict (window_start, file_name) do update set
+            filter_keys = EXCLUDED.filter_keys,
+            loaded_at = EXCLUDED.loaded_at
+        "#,
+    )
+    .bind(window_start)
+    .bind(&file_info.key)
+    .bind(file_info.file_type.to_str())
+    .bind(file_info.timestamp)
+    .bind(filter_keys)
+    .bind(Utc::now())
+    .execute(tx)
+    .await?;
+    Ok(())
+}
+
+/// The files of a type already loaded in the window, with their filter keys
+pub async fn loaded<'c, E>(
+    executor: E,
+    window_start: DateTime<Utc>,
+    file_type: FileType,
+) -> Result<HashMap<String, Vec<u64>>, LoaderCheckpointError>
+where
+    E: sqlx::Executor<'c, Database = Postgres>,
+{
+    let rows = sqlx::query_as::<_, (String, Vec<i64>)>(
+        r#"
+        select file_name, filter_keys from loader_checkpoints
+        where window_start = $1 and file_type = $2
+        "#,
+    )
+    .bind(window_start)
+    .bind(file_type.to_str())
+    .fetch_all(executor)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(file_name, keys)| (file_name, keys.into_iter().map(|key| key as u64).collect()))
+        .collect())
+}
+
+/// Clear the checkpoints of every window starting before `before`
+pub async fn clear<'c, E>(executor: E, before: DateTime<Utc>) -> Result<(), LoaderCheckpointError>
+where
+    E: sqlx::Executor<'c, Database = Postgres>,
+{
+    sqlx::query("delete from loader_checkpoints where window_start < $1")
+        .bind(before)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
@@ -15,7 +15,6 @@ use iot_verifier::{
 use price::PriceTracker;
 use std::path;
 use tokio::signal;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Debug, clap::Parser)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
@@ -53,20 +52,23 @@ impl Cmd {
 }
 
 #[derive(Debug, clap::Args)]
-pub struct Server {}
+pub struct Server {
+    /// Apply database migrations and exit without starting the service
+    #[clap(long)]
+    migrate_only: bool,
+}
 
 impl Server {
     pub async fn run(&self, settings: &Settings) -> Result<()> {
-        tracing_subscriber::registry()
-            .with(tracing_subscriber::EnvFilter::new(&settings.log))
-            .with(tracing_subscriber::fmt::layer())
-            .init();
+        custom_tracing::init(&settings.log, &settings.tracing)?;
+        poc_metrics::register_error_codes!(poc_metrics, db_store, file_store, price, iot_config);
 
         // Install the prometheus metrics exporter
         poc_metrics::start_metrics(&settings.metrics)?;
 
         // configure shutdown trigger
         let (shutdown_trigger, shutdown) = triggered::trigger();
+        poc_metrics::process::start(shutdown.clone());
         let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
         tokio::spawn(async move {
             tokio::select! {
@@ -80,7 +82,10 @@ impl Server {
             .database
             .connect(env!("CARGO_PKG_NAME"), shutdown.clone())
             .await?;
-        sqlx::migrate!().run(&pool).await?;
+        db_store::migrate::run(&pool, env!("CARGO_PKG_NAME"), &sqlx::migrate!()).await?;
+        if self.migrate_only {
+            return Ok(());
+        }
 
         telemetry::initialize(&pool).await?;
 
@@ -127,6 +132,7 @@ impl Server {
             reward_manifests_sink,
             reward_period_hours: settings.rewards,
             reward_offset: settings.reward_offset_duration(),
+            dc_pricing: settings.dc_pricing(),
         };
 
         // setup the entropy loader continious source
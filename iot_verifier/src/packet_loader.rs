@@ -89,10 +89,9 @@ impl PacketLoader {
         metrics: &LoaderMetricTracker,
     ) -> anyhow::Result<()> {
         let mut transaction = self.pool.begin().await?;
+        let (packets, completion) = file_info_stream.into_stream(&mut transaction).await?;
 
-        file_info_stream
-            .into_stream(&mut transaction)
-            .await?
+        let transaction = packets
             .map(|valid_packet| {
                 (
                     ValidPacket::from(valid_packet.clone()),
@@ -131,9 +130,8 @@ impl PacketLoader {
                     Ok(transaction)
                 },
             )
-            .await?
-            .commit()
             .await?;
+        completion.commit(transaction).await?;
 
         Ok(())
     }
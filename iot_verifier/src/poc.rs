@@ -5,6 +5,7 @@ use crate::{
     hex_density::HexDensityMap,
     last_beacon::{LastBeacon, LastBeaconError},
     region_cache::{RegionCache, RegionCacheError},
+    telemetry,
 };
 use beacon;
 use chrono::{DateTime, Duration, Utc};
@@ -38,6 +39,12 @@ const POC_DISTANCE_LIMIT: u32 = 100;
 const POC_CELL_DISTANCE_MINIMUM: u32 = 8;
 /// the resolution at which parent cell distance is derived
 const POC_CELL_PARENT_RES: Resolution = Resolution::Eleven;
+/// the plausible range of a witness snr in deci-dB. LoRa demodulates down to
+/// about -20 dB at SF12 and an snr above 20 dB is never observed in practice
+const POC_WITNESS_SNR_RANGE: std::ops::RangeInclusive<i32> = -200..=200;
+/// the lowest plausible noise floor (rssi - snr) in deci-dBm. Thermal noise
+/// in a 125 kHz channel is about -123 dBm, no receiver hears below that
+const POC_WITNESS_MIN_NOISE_FLOOR: i32 = -1300;
 
 lazy_static! {
     /// Scaling factor when inactive gateway is not found in the tx scaling map (20%).
@@ -264,7 +271,7 @@ impl Poc {
                 0,
                 0,
                 InvalidParticipantSide::Beaconer,
-            ))
+            ));
         };
         // run the witness verifications
         match do_witness_verifications(
@@ -390,6 +397,7 @@ pub fn do_witness_verifications(
         beaconer_metadata.location,
         witness_metadata.location,
     )?;
+    verify_witness_snr(witness_report.report.signal, witness_report.report.snr)?;
     tracing::debug!(
         "valid witness from gateway: {:?}",
         witness_info.address.clone()
@@ -637,6 +645,21 @@ fn verify_witness_rssi(
     Ok(())
 }
 
+/// verify witness snr is plausible on its own and for the reported rssi.
+/// The poc lora invalid reasons have no snr reason, so implausible values are
+/// written as bad rssi and told apart by the implausible snr witness counter
+fn verify_witness_snr(witness_signal: i32, witness_snr: i32) -> GenericVerifyResult {
+    let noise_floor = witness_signal - witness_snr;
+    if !POC_WITNESS_SNR_RANGE.contains(&witness_snr) || noise_floor < POC_WITNESS_MIN_NOISE_FLOOR {
+        tracing::debug!(
+            "witness verification failed, reason: implausible snr. witness signal: {witness_signal}, witness snr: {witness_snr}"
+        );
+        telemetry::increment_implausible_snr_witnesses();
+        return Err(InvalidReason::BadRssi);
+    }
+    Ok(())
+}
+
 fn verify_witness_data(beacon_data: &Vec<u8>, witness_data: &Vec<u8>) -> GenericVerifyResult {
     if witness_data != beacon_data {
         tracing::debug!(
@@ -1112,6 +1135,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_verify_witness_snr() {
+        assert!(verify_witness_snr(-1080, 35).is_ok());
+        assert!(verify_witness_snr(-1200, -150).is_ok());
+        // snr outside of what lora can demodulate or produce
+        assert_eq!(Err(InvalidReason::BadRssi), verify_witness_snr(-1080, -250));
+        assert_eq!(Err(InvalidReason::BadRssi), verify_witness_snr(-600, 300));
+        // implies a noise floor below thermal noise
+        assert_eq!(Err(InvalidReason::BadRssi), verify_witness_snr(-1250, 100));
+    }
+
     #[test]
     fn test_verify_witness_data() {
         let beacon_data = "data1".as_bytes().to_vec();
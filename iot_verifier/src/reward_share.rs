@@ -1,5 +1,6 @@
 use crate::poc_report::ReportType as PocReportType;
 use chrono::{DateTime, Duration, Utc};
+use dc_pricing::DcPricing;
 use file_store::{iot_packet::IotValidPacket, iot_valid_poc::IotPoc, traits::TimestampEncode};
 use futures::stream::TryStreamExt;
 use helium_crypto::PublicKeyBinary;
@@ -29,7 +30,6 @@ lazy_static! {
     // ie WITNESS_REWARDS_PER_DAY_PERCENT:BEACON_REWARDS_PER_DAY_PERCENT
     static ref WITNESS_DC_REMAINER_PERCENT: Decimal = dec!(0.80);
     static ref BEACON_DC_REMAINER_PERCENT: Decimal = dec!(0.20);
-    static ref DC_USD_PRICE: Decimal =  dec!(0.00001);
 }
 
 fn get_tokens_by_duration(tokens: Decimal, duration: Duration) -> Decimal {
@@ -304,6 +304,7 @@ impl GatewayShares {
         self,
         reward_period: &'_ Range<DateTime<Utc>>,
         iot_price: Decimal,
+        pricing: DcPricing,
     ) -> impl Iterator<Item = proto::IotRewardShare> + '_ {
         // the total number of shares for beacons, witnesses and data transfer
         // dc shares here is the sum of all spent data transfer DC this epoch
@@ -317,7 +318,7 @@ impl GatewayShares {
         // up to a max cap of total_dc_transfer_rewards
         // if the dc transfer rewards is less than total_dc_transfer_rewards
         // then the remainer will be added to the POC rewards allocation
-        let total_dc_transfer_rewards_used = dc_to_iot_bones(total_dc_shares, iot_price);
+        let total_dc_transfer_rewards_used = dc_to_iot_bones(total_dc_shares, iot_price, &pricing);
         let (dc_transfer_rewards_unused, total_dc_transfer_rewards_capped) =
             normalize_dc_transfer_rewards(
                 total_dc_transfer_rewards_used,
@@ -344,7 +345,8 @@ impl GatewayShares {
             %dc_transfer_rewards_per_share,
             "data transfer rewards"
         );
-        self.shares
+        let mut gateway_rewards: Vec<_> = self
+            .shares
             .into_iter()
             .map(move |(hotspot_key, reward_shares)| proto::GatewayReward {
                 hotspot_key: hotspot_key.into(),
@@ -366,6 +368,11 @@ impl GatewayShares {
                     || reward_share.witness_amount > 0
                     || reward_share.dc_transfer_amount > 0
             })
+            .collect();
+        // Written by gateway key so every run writes the same rewards
+        gateway_rewards.sort_by(|a, b| a.hotspot_key.cmp(&b.hotspot_key));
+        gateway_rewards
+            .into_iter()
             .map(|gateway_reward| proto::IotRewardShare {
                 start_period: reward_period.start.encode_timestamp(),
                 end_period: reward_period.end.encode_timestamp(),
@@ -390,25 +397,25 @@ pub mod operational_rewards {
 }
 
 /// returns the equiv iot bones value for a specified dc amount
-pub fn dc_to_iot_bones(dc_amount: Decimal, iot_price: Decimal) -> Decimal {
+pub fn dc_to_iot_bones(dc_amount: Decimal, iot_price: Decimal, pricing: &DcPricing) -> Decimal {
     // iot prices are supplied in 10^6 *per iot token*
     // we need the price at this point per iot bones
     let iot_price = iot_price_to_bones(iot_price);
     // use the price per bones to get the num of bones for our
     // dc USD value
-    let dc_in_usd = dc_amount * (*DC_USD_PRICE);
+    let dc_in_usd = pricing.dc_to_usd(dc_amount);
     (dc_in_usd / iot_price)
         .round_dp_with_strategy(DEFAULT_PREC, RoundingStrategy::ToPositiveInfinity)
 }
 
 /// returns the equiv dc value for a specified iot bones amount
-pub fn iot_bones_to_dc(iot_amount: Decimal, iot_price: Decimal) -> Decimal {
+pub fn iot_bones_to_dc(iot_amount: Decimal, iot_price: Decimal, pricing: &DcPricing) -> Decimal {
     // iot prices are supplied in 10^6 *per iot token*
     // we need the price at this point per iot bones
     let iot_price = iot_price_to_bones(iot_price);
     // use the price per bones to get the value of our bones in DC
     let iot_value = iot_amount * iot_price;
-    (iot_value / (*DC_USD_PRICE)).round_dp_with_strategy(0, RoundingStrategy::ToNegativeInfinity)
+    (iot_value / pricing.usd_per_dc).round_dp_with_strategy(0, RoundingStrategy::ToNegativeInfinity)
 }
 
 pub fn iot_price_to_bones(iot_price: Decimal) -> Decimal {
@@ -449,6 +456,8 @@ fn compute_rewards(rewards_per_share: Decimal, shares: Decimal) -> u64 {
 #[cfg(test)]
 mod test {
     use super::*;
+    use chrono::TimeZone;
+    use prost::Message;
 
     fn reward_shares_in_dec(
         beacon_shares: Decimal,
@@ -520,7 +529,8 @@ mod test {
         let total_dc_spend =
             gw1_dc_spend + gw2_dc_spend + gw3_dc_spend + gw4_dc_spend + gw5_dc_spend + gw6_dc_spend;
         println!("total dc spend: {total_dc_spend}");
-        let total_used_data_transfer_tokens = dc_to_iot_bones(total_dc_spend, iot_price);
+        let total_used_data_transfer_tokens =
+            dc_to_iot_bones(total_dc_spend, iot_price, &DcPricing::iot());
         println!("total data transfer rewards for dc spent: {total_used_data_transfer_tokens}");
         let total_unused_data_transfer_tokens =
             total_data_transfer_tokens_for_period - total_used_data_transfer_tokens;
@@ -555,7 +565,7 @@ mod test {
         let gw_shares = GatewayShares { shares };
         let mut rewards: HashMap<PublicKeyBinary, proto::GatewayReward> = HashMap::new();
         let gw_reward_shares: Vec<proto::IotRewardShare> = gw_shares
-            .into_iot_reward_shares(&reward_period, iot_price)
+            .into_iot_reward_shares(&reward_period, iot_price, DcPricing::iot())
             .collect();
         for reward in gw_reward_shares {
             if let Some(ProtoReward::GatewayReward(gateway_reward)) = reward.reward {
@@ -605,15 +615,25 @@ mod test {
 
         // assert the expected data transfer rewards amounts per gateway
         // using the dc_to_iot_bones helper function
-        let gw1_expected_dc_rewards = dc_to_iot_bones(gw1_dc_spend, iot_price).to_u64().unwrap();
+        let gw1_expected_dc_rewards = dc_to_iot_bones(gw1_dc_spend, iot_price, &DcPricing::iot())
+            .to_u64()
+            .unwrap();
         assert_eq!(gw1_expected_dc_rewards.to_u64().unwrap(), 13_983_286);
-        let gw2_expected_dc_rewards = dc_to_iot_bones(gw2_dc_spend, iot_price).to_u64().unwrap();
+        let gw2_expected_dc_rewards = dc_to_iot_bones(gw2_dc_spend, iot_price, &DcPricing::iot())
+            .to_u64()
+            .unwrap();
         assert_eq!(gw2_expected_dc_rewards.to_u64().unwrap(), 139_275_766);
-        let gw3_expected_dc_rewards = dc_to_iot_bones(gw3_dc_spend, iot_price).to_u64().unwrap();
+        let gw3_expected_dc_rewards = dc_to_iot_bones(gw3_dc_spend, iot_price, &DcPricing::iot())
+            .to_u64()
+            .unwrap();
         assert_eq!(gw3_expected_dc_rewards.to_u64().unwrap(), 139_275_766);
-        let gw5_expected_dc_rewards = dc_to_iot_bones(gw5_dc_spend, iot_price).to_u64().unwrap();
+        let gw5_expected_dc_rewards = dc_to_iot_bones(gw5_dc_spend, iot_price, &DcPricing::iot())
+            .to_u64()
+            .unwrap();
         assert_eq!(gw5_expected_dc_rewards.to_u64().unwrap(), 0);
-        let gw6_expected_dc_rewards = dc_to_iot_bones(gw6_dc_spend, iot_price).to_u64().unwrap();
+        let gw6_expected_dc_rewards = dc_to_iot_bones(gw6_dc_spend, iot_price, &DcPricing::iot())
+            .to_u64()
+            .unwrap();
         assert_eq!(gw6_expected_dc_rewards.to_u64().unwrap(), 1_392_757_660);
         assert_eq!(gw1_rewards.dc_transfer_amount, gw1_expected_dc_rewards);
         assert_eq!(gw2_rewards.dc_transfer_amount, gw2_expected_dc_rewards);
@@ -694,7 +714,11 @@ mod test {
         // distribute this amount of dc across the gateways
         // this results in zero unallocated dc rewards being
         // available to distributed to POC
-        let total_dc_to_spend = iot_bones_to_dc(total_data_transfer_tokens_for_period, iot_price);
+        let total_dc_to_spend = iot_bones_to_dc(
+            total_data_transfer_tokens_for_period,
+            iot_price,
+            &DcPricing::iot(),
+        );
         println!("total dc value of scheduled data transfer tokens: {total_dc_to_spend}");
 
         // generate the rewards map
@@ -741,7 +765,7 @@ mod test {
         let gw_shares = GatewayShares { shares };
         let mut rewards: HashMap<PublicKeyBinary, proto::GatewayReward> = HashMap::new();
         let gw_reward_shares: Vec<proto::IotRewardShare> = gw_shares
-            .into_iot_reward_shares(&reward_period, iot_price)
+            .into_iot_reward_shares(&reward_period, iot_price, DcPricing::iot())
             .collect();
         for reward in gw_reward_shares {
             if let Some(ProtoReward::GatewayReward(gateway_reward)) = reward.reward {
@@ -870,7 +894,11 @@ mod test {
         // get the expected total amount of dc we need to spend
         // spread *some* of this across the gateways and then confirm
         // the unallocated rewards go to poc
-        let total_dc_to_spend = iot_bones_to_dc(total_data_transfer_tokens_for_period, iot_price);
+        let total_dc_to_spend = iot_bones_to_dc(
+            total_data_transfer_tokens_for_period,
+            iot_price,
+            &DcPricing::iot(),
+        );
         println!("total_dc_to_spend: {total_dc_to_spend}");
 
         // generate the rewards map
@@ -911,7 +939,7 @@ mod test {
         let gw_shares = GatewayShares { shares };
         let mut rewards: HashMap<PublicKeyBinary, proto::GatewayReward> = HashMap::new();
         let gw_reward_shares: Vec<proto::IotRewardShare> = gw_shares
-            .into_iot_reward_shares(&reward_period, iot_price)
+            .into_iot_reward_shares(&reward_period, iot_price, DcPricing::iot())
             .collect();
         for reward in gw_reward_shares {
             if let Some(ProtoReward::GatewayReward(gateway_reward)) = reward.reward {
@@ -1006,18 +1034,73 @@ mod test {
         assert_eq!(poc_diff, 4);
     }
 
+    #[test]
+    fn reward_shares_are_written_in_gateway_key_order() {
+        let gateways: Vec<PublicKeyBinary> = [
+            "112NqN2WWMwtK29PMzRby62fDydBJfsCLkCAf392stdok48ovNT6",
+            "11sctWiP9r5wDJVuDe1Th4XSL2vaawaLLSQF8f8iokAoMAJHxqp",
+            "112DJZiXvZ8FduiWrEi8siE3wJX6hpRjjtwbavyXUDkgutEUSLAE",
+            "112p1GbUtRLyfFaJr1XF8fH7yz9cSZ4exbrSpVDeu67DeGb31QUL",
+            "112j1iw1sV2B2Tz2DxPSeum9Cmc5kMKNdDTDg1zDRsdwuvZueq3B",
+            "11fCasUk9XvU15ktsMMH64J9E7XuqQ2L5FJPv8HZMCDG6kdZ3SC",
+        ]
+        .iter()
+        .map(|key| key.parse().expect("failed gateway parse"))
+        .collect();
+        let end = Utc.timestamp_opt(1_686_000_000, 0).unwrap();
+        let reward_period = (end - Duration::hours(24))..end;
+
+        // Every aggregation has its own map, iterated in its own order
+        let encoded_rewards = || -> Vec<Vec<u8>> {
+            let shares = gateways
+                .iter()
+                .enumerate()
+                .map(|(i, gw)| {
+                    let i = Decimal::from(i + 1);
+                    (
+                        gw.clone(),
+                        reward_shares_in_dec(i, i * dec!(10), i * dec!(100)),
+                    )
+                })
+                .collect();
+            GatewayShares { shares }
+                .into_iot_reward_shares(&reward_period, dec!(359), DcPricing::iot())
+                .map(|reward_share| reward_share.encode_to_vec())
+                .collect()
+        };
+        let first = encoded_rewards();
+        assert_eq!(first.len(), gateways.len());
+        assert_eq!(first, encoded_rewards());
+
+        let mut sorted = gateways.clone();
+        sorted.sort();
+        let hotspot_keys: Vec<PublicKeyBinary> = first
+            .iter()
+            .map(|buf| {
+                match proto::IotRewardShare::decode(buf.as_slice())
+                    .unwrap()
+                    .reward
+                {
+                    Some(ProtoReward::GatewayReward(reward)) => reward.hotspot_key.into(),
+                    _ => panic!("not a gateway reward"),
+                }
+            })
+            .collect();
+        assert_eq!(hotspot_keys, sorted);
+    }
+
     #[test]
     fn test_dc_iot_conversion() {
         let iot_price = dec!(359); //iot per token price @ 0.000359 @ 10^6 = 359
         let dc_amount = dec!(1000000);
         // convert the dc amount to iot and assert
-        let dc_iot_amt = dc_to_iot_bones(dc_amount, iot_price);
+        let dc_iot_amt = dc_to_iot_bones(dc_amount, iot_price, &DcPricing::iot());
         println!("dc_iot_amt: {dc_iot_amt}");
         assert_eq!(dc_iot_amt, dec!(27855153203.342618384401115));
 
         // convert the returned iot amount back to dc and assert
         // it matches our original dc amount
-        let iot_dc_amt = iot_bones_to_dc(dc_iot_amt, iot_price);
+        let iot_dc_amt = iot_bones_to_dc(dc_iot_amt, iot_price, &DcPricing::iot());
         println!("iot_dc_amt: {iot_dc_amt}");
         assert_eq!(iot_dc_amt, dc_amount);
     }
@@ -4,6 +4,7 @@ use crate::{
 };
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use db_store::meta;
+use dc_pricing::DcPricing;
 use file_store::{file_sink, traits::TimestampEncode};
 use helium_proto::RewardManifest;
 use price::PriceTracker;
@@ -21,6 +22,7 @@ pub struct Rewarder {
     pub reward_manifests_sink: file_sink::FileSinkClient,
     pub reward_period_hours: i64,
     pub reward_offset: Duration,
+    pub dc_pricing: DcPricing,
 }
 
 impl Rewarder {
@@ -85,9 +87,17 @@ impl Rewarder {
         let gateway_reward_shares =
             GatewayShares::aggregate(&self.pool, &scheduler.reward_period).await?;
 
-        for reward_share in
-            gateway_reward_shares.into_iot_reward_shares(&scheduler.reward_period, iot_price)
-        {
+        // Reward files are named by the end of the epoch, so a rerun of the
+        // epoch writes the same files
+        self.rewards_sink
+            .file_time(scheduler.reward_period.end)
+            .await?;
+
+        for reward_share in gateway_reward_shares.into_iot_reward_shares(
+            &scheduler.reward_period,
+            iot_price,
+            self.dc_pricing,
+        ) {
             self.rewards_sink
                 .write(reward_share, [])
                 .await?
@@ -120,6 +130,9 @@ impl Rewarder {
         transaction.commit().await?;
 
         // now that the db has been purged, safe to write out the manifest
+        self.reward_manifests_sink
+            .file_time(scheduler.reward_period.end)
+            .await?;
         self.reward_manifests_sink
             .write(
                 RewardManifest {
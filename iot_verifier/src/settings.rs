@@ -10,6 +10,9 @@ pub struct Settings {
     /// "iot_verifier=debug,poc_store=info"
     #[serde(default = "default_log")]
     pub log: String,
+    /// Log output format, per module log levels and runtime diagnostics
+    #[serde(default)]
+    pub tracing: custom_tracing::Settings,
     /// Cache location for generated verified reports
     pub cache: String,
     /// the base_stale period in seconds
@@ -35,6 +38,10 @@ pub struct Settings {
     /// of the reward period + reward_offset_minutes
     #[serde(default = "default_reward_offset_minutes")]
     pub reward_offset_minutes: i64,
+    /// Price of a data credit in USD, used to value the data transfer rewards.
+    /// (Default is 0.00001)
+    #[serde(default = "default_usd_per_dc")]
+    pub usd_per_dc: rust_decimal::Decimal,
     #[serde(default = "default_max_witnesses_per_poc")]
     pub max_witnesses_per_poc: u64,
     /// The cadence at which hotspots are permitted to beacon (in seconds)
@@ -163,6 +170,10 @@ fn default_reward_period() -> i64 {
     24
 }
 
+fn default_usd_per_dc() -> rust_decimal::Decimal {
+    dc_pricing::USD_PER_DC
+}
+
 fn default_reward_offset_minutes() -> i64 {
     30
 }
@@ -211,6 +222,25 @@ impl Settings {
             .add_source(Environment::with_prefix("VERIFY").separator("_"))
             .build()
             .and_then(|config| config.try_deserialize())
+            .and_then(|settings: Settings| settings.validate().map(|_| settings))
+    }
+
+    /// Check the loaded settings for values which deserialize but can not be
+    /// used, reporting every problem found rather than just the first.
+    pub fn validate(&self) -> Result<(), config::ConfigError> {
+        let problems = self.dc_pricing().problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(config::ConfigError::Message(format!(
+                "invalid settings: {}",
+                problems.join("; ")
+            )))
+        }
+    }
+
+    pub fn dc_pricing(&self) -> dc_pricing::DcPricing {
+        dc_pricing::DcPricing::iot().usd_per_dc(self.usd_per_dc)
     }
 
     pub fn reward_offset_duration(&self) -> Duration {
@@ -16,6 +16,8 @@ const LOADER_DROPPED_WITNESS_COUNTER: &str =
 const BEACON_GUAGE: &str = concat!(env!("CARGO_PKG_NAME"), "_", "num_beacons");
 const INVALID_WITNESS_COUNTER: &str =
     concat!(env!("CARGO_PKG_NAME"), "_", "invalid_witness_report");
+const IMPLAUSIBLE_SNR_WITNESS_COUNTER: &str =
+    concat!(env!("CARGO_PKG_NAME"), "_", "implausible_snr_witness");
 const LAST_REWARDED_END_TIME: &str = "last_rewarded_end_time";
 
 pub async fn initialize(db: &Pool<Postgres>) -> anyhow::Result<()> {
@@ -65,6 +67,10 @@ pub fn increment_invalid_witnesses(labels: &[(&'static str, &'static str)]) {
     metrics::increment_counter!(INVALID_WITNESS_COUNTER, labels);
 }
 
+pub fn increment_implausible_snr_witnesses() {
+    metrics::increment_counter!(IMPLAUSIBLE_SNR_WITNESS_COUNTER);
+}
+
 pub fn last_rewarded_end_time(datetime: DateTime<Utc>) {
     metrics::gauge!(LAST_REWARDED_END_TIME, datetime.timestamp() as f64);
 }
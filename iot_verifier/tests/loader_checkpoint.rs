@@ -0,0 +1,98 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use file_store::{FileInfo, FileType};
+use iot_verifier::loader_checkpoint;
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+fn window(hours: i64) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 9, 1, 0, 0, 0).unwrap() + Duration::hours(hours)
+}
+
+fn file(file_type: FileType, minutes: i64) -> FileInfo {
+    FileInfo::from((file_type, window(0) + Duration::minutes(minutes)))
+}
+
+async fn record(pool: &PgPool, window_start: DateTime<Utc>, file_info: &FileInfo, keys: &[u64]) {
+    let mut tx = pool.begin().await.unwrap();
+    loader_checkpoint::record(&mut tx, window_start, file_info, keys)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+}
+
+#[sqlx::test]
+async fn loaded_files_are_listed_by_window_and_type(pool: PgPool) {
+    let beacons = file(FileType::IotBeaconIngestReport, 1);
+    let witnesses = file(FileType::IotWitnessIngestReport, 1);
+    let later_beacons = file(FileType::IotBeaconIngestReport, 61);
+    // Filter keys are hashes using the full range of u64
+    let keys = [1, u64::MAX, 1 << 63];
+    record(&pool, window(0), &beacons, &keys).await;
+    record(&pool, window(0), &witnesses, &[]).await;
+    record(&pool, window(1), &later_beacons, &[2]).await;
+
+    let loaded = loader_checkpoint::loaded(&pool, window(0), FileType::IotBeaconIngestReport)
+        .await
+        .unwrap();
+    assert_eq!(
+        loaded,
+        HashMap::from([(beacons.key.clone(), keys.to_vec())])
+    );
+
+    let loaded = loader_checkpoint::loaded(&pool, window(0), FileType::IotWitnessIngestReport)
+        .await
+        .unwrap();
+    assert_eq!(loaded, HashMap::from([(witnesses.key.clone(), vec![])]));
+}
+
+#[sqlx::test]
+async fn loading_a_file_again_replaces_its_keys(pool: PgPool) {
+    let beacons = file(FileType::IotBeaconIngestReport, 1);
+    record(&pool, window(0), &beacons, &[1, 2]).await;
+    record(&pool, window(0), &beacons, &[3]).await;
+
+    let loaded = loader_checkpoint::loaded(&pool, window(0), FileType::IotBeaconIngestReport)
+        .await
+        .unwrap();
+    assert_eq!(loaded, HashMap::from([(beacons.key, vec![3])]));
+}
+
+#[sqlx::test]
+async fn rolled_back_files_are_not_checkpointed(pool: PgPool) {
+    let beacons = file(FileType::IotBeaconIngestReport, 1);
+    let mut tx = pool.begin().await.unwrap();
+    loader_checkpoint::record(&mut tx, window(0), &beacons, &[1])
+        .await
+        .unwrap();
+    tx.rollback().await.unwrap();
+
+    assert!(
+        loader_checkpoint::loaded(&pool, window(0), FileType::IotBeaconIngestReport)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[sqlx::test]
+async fn clear_drops_the_checkpoints_of_earlier_windows(pool: PgPool) {
+    let beacons = file(FileType::IotBeaconIngestReport, 1);
+    let later_beacons = file(FileType::IotBeaconIngestReport, 61);
+    record(&pool, window(0), &beacons, &[1]).await;
+    record(&pool, window(1), &later_beacons, &[2]).await;
+
+    loader_checkpoint::clear(&pool, window(1)).await.unwrap();
+
+    assert!(
+        loader_checkpoint::loaded(&pool, window(0), FileType::IotBeaconIngestReport)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        loader_checkpoint::loaded(&pool, window(1), FileType::IotBeaconIngestReport)
+            .await
+            .unwrap(),
+        HashMap::from([(later_beacons.key, vec![2])])
+    );
+}
@@ -1,3 +1,4 @@
+use crate::error_code::{ErrorCategory, ErrorCode};
 use thiserror::Error;
 
 pub type Result<T = ()> = std::result::Result<T, Error>;
@@ -9,3 +10,16 @@ pub enum Error {
     #[error("metrics build error")]
     Metrics(#[from] metrics_exporter_prometheus::BuildError),
 }
+
+impl ErrorCode for Error {
+    fn category(&self) -> ErrorCategory {
+        ErrorCategory::Config
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DecodeError(_) => "invalid_endpoint",
+            Self::Metrics(_) => "metrics_exporter",
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Taxonomy of the errors of the oracles, shared by every crate.
+//!
+//! Error types implement [`ErrorCode`] to place each of their variants in an
+//! [`ErrorCategory`] with a short code, so alerts can match a class of
+//! failure rather than an error message. Services register the error types
+//! of the crates they use at startup; [`classify`] then labels an error with
+//! the first registered error found walking its source chain, which works for
+//! errors wrapped in `anyhow` contexts as well.
+
+use std::{error::Error, sync::RwLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// Settings, keys and files a service is configured with
+    Config,
+    /// Queries, connections and migrations of the database
+    Database,
+    /// Object storage and local files
+    Storage,
+    /// Remote services reached over grpc or http
+    Network,
+    /// Messages and files that can not be decoded or encoded
+    Decode,
+    /// Signing and verification of signatures
+    Crypto,
+    /// Transactions and accounts on the solana chain
+    Chain,
+    /// Tasks, channels and shutdown of the service itself
+    Internal,
+}
+
+impl ErrorCategory {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Config => "config",
+            Self::Database => "database",
+            Self::Storage => "storage",
+            Self::Network => "network",
+            Self::Decode => "decode",
+            Self::Crypto => "crypto",
+            Self::Chain => "chain",
+            Self::Internal => "internal",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub trait ErrorCode {
+    fn category(&self) -> ErrorCategory;
+    /// Snake case name of the failure, unique within its category
+    fn code(&self) -> &'static str;
+}
+
+/// The category and code an error is labeled with in metrics and logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorLabels {
+    pub category: ErrorCategory,
+    pub code: &'static str,
+}
+
+impl ErrorLabels {
+    /// Labels of errors none of whose sources are registered
+    pub const UNKNOWN: Self = Self::new(ErrorCategory::Internal, "unknown");
+
+    pub const fn new(category: ErrorCategory, code: &'static str) -> Self {
+        Self { category, code }
+    }
+
+    pub fn of<E: ErrorCode + ?Sized>(err: &E) -> Self {
+        Self::new(err.category(), err.code())
+    }
+}
+
+type Classifier = Box<dyn Fn(&(dyn Error + 'static)) -> Option<ErrorLabels> + Send + Sync>;
+
+static CLASSIFIERS: RwLock<Vec<Classifier>> = RwLock::new(Vec::new());
+
+/// Label errors of type `E` by their own [`ErrorCode`]
+pub fn register<E: ErrorCode + Error + 'static>() {
+    register_with(|err: &E| ErrorLabels::of(err))
+}
+
+/// Label errors of type `E` with `labels`, for error types of other crates
+/// which can not implement [`ErrorCode`]
+pub fn register_with<E: Error + 'static>(labels: fn(&E) -> ErrorLabels) {
+    CLASSIFIERS
+        .write()
+        .expect("error classifiers poisoned")
+        .push(Box::new(move |err| err.downcast_ref::<E>().map(labels)));
+}
+
+/// Labels of the outermost registered error of the source chain of `err`
+pub fn classify(err: &(dyn Error + 'static)) -> ErrorLabels {
+    let classifiers = CLASSIFIERS.read().expect("error classifiers poisoned");
+    let mut next = Some(err);
+    while let Some(err) = next {
+        if let Some(labels) = classifiers.iter().find_map(|classify| classify(err)) {
+            return labels;
+        }
+        next = err.source();
+    }
+    ErrorLabels::UNKNOWN
+}
+
+/// Count `err` in the `metric` counter labeled with its category and code,
+/// returning the labels for logging
+pub fn record(
+    metric: &'static str,
+    err: &(dyn Error + 'static),
+    labels: &[(&'static str, &'static str)],
+) -> ErrorLabels {
+    let error_labels = classify(err);
+    let mut metric_labels = vec![
+        metrics::Label::new("category", error_labels.category.as_str()),
+        metrics::Label::new("code", error_labels.code),
+    ];
+    metric_labels.extend(
+        labels
+            .iter()
+            .map(|(key, value)| metrics::Label::new(*key, *value)),
+    );
+    metrics::increment_counter!(metric, metric_labels);
+    error_labels
+}
+
+/// Register the error types of crates with a `register_error_codes` function
+#[macro_export]
+macro_rules! register_error_codes {
+    ( $( $krate:ident ),* $(,)? ) => {
+        $( $krate::register_error_codes(); )*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, thiserror::Error)]
+    enum Inner {
+        #[error("timed out")]
+        Timeout,
+    }
+
+    impl ErrorCode for Inner {
+        fn category(&self) -> ErrorCategory {
+            ErrorCategory::Network
+        }
+
+        fn code(&self) -> &'static str {
+            match self {
+                Self::Timeout => "timeout",
+            }
+        }
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("outer")]
+    struct Outer(#[source] Inner);
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("unregistered")]
+    struct Unregistered;
+
+    #[test]
+    fn errors_are_labeled_by_the_first_registered_source() {
+        register::<Inner>();
+
+        let outer = Outer(Inner::Timeout);
+        assert_eq!(
+            classify(&outer),
+            ErrorLabels::new(ErrorCategory::Network, "timeout")
+        );
+        assert_eq!(classify(&Unregistered), ErrorLabels::UNKNOWN);
+
+        register_with(|_: &Unregistered| ErrorLabels::new(ErrorCategory::Config, "missing"));
+        assert_eq!(
+            classify(&Unregistered),
+            ErrorLabels::new(ErrorCategory::Config, "missing")
+        );
+    }
+}
@@ -1,7 +1,7 @@
 //! Common code shared between the reward and ingest servers.
 
 pub use error::{Error, Result};
-use metrics_exporter_prometheus::PrometheusBuilder;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
 pub use settings::Settings;
 use std::result::Result as StdResult;
 use std::{
@@ -13,16 +13,35 @@ use std::{
 use tower::{Layer, Service};
 
 mod error;
+pub mod error_code;
+pub mod process;
 pub mod settings;
 
 pub fn start_metrics(settings: &Settings) -> Result {
+    start_metrics_with_buckets(settings, &[])
+}
+
+/// Like [`start_metrics`], exporting the histograms with the given names as
+/// buckets with the given upper bounds rather than as summaries
+pub fn start_metrics_with_buckets(settings: &Settings, buckets: &[(&str, &[f64])]) -> Result {
     let socket: SocketAddr = settings.endpoint.parse()?;
-    PrometheusBuilder::new()
-        .with_http_listener(socket)
+    buckets
+        .iter()
+        .try_fold(
+            PrometheusBuilder::new().with_http_listener(socket),
+            |builder, (name, bounds)| {
+                builder.set_buckets_for_metric(Matcher::Full(name.to_string()), bounds)
+            },
+        )?
         .install()?;
     Ok(())
 }
 
+/// Label the errors of this crate in metrics and logs
+pub fn register_error_codes() {
+    error_code::register::<Error>();
+}
+
 /// Install the Prometheus export gateway
 pub fn install_metrics() {
     let endpoint =
@@ -0,0 +1,147 @@
+//! Standard process metrics, collected from procfs where available so every
+//! service reports the same set without instrumenting each binary.
+
+use std::{
+    fs,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const COLLECT_INTERVAL: Duration = Duration::from_secs(15);
+
+const START_TIME_METRIC: &str = "process_start_time_seconds";
+const CPU_SECONDS_METRIC: &str = "process_cpu_seconds_total";
+const RESIDENT_MEMORY_METRIC: &str = "process_resident_memory_bytes";
+const THREADS_METRIC: &str = "process_threads";
+const OPEN_FDS_METRIC: &str = "process_open_fds";
+
+/// Spawns the process metrics collector on the current tokio runtime, which
+/// stops once `shutdown` is triggered. Does nothing when called outside of a
+/// runtime.
+pub fn start(shutdown: triggered::Listener) {
+    let handle = match tokio::runtime::Handle::try_current() {
+        Ok(handle) => handle,
+        Err(_) => {
+            tracing::warn!("no tokio runtime; process metrics disabled");
+            return;
+        }
+    };
+
+    if let Ok(start_time) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        metrics::gauge!(START_TIME_METRIC, start_time.as_secs_f64());
+    }
+
+    let ticks_per_sec = clock_ticks_per_sec();
+    handle.spawn(async move {
+        let mut collect_timer = tokio::time::interval(COLLECT_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                _ = collect_timer.tick() => collect(ticks_per_sec),
+            }
+        }
+        tracing::info!("stopping process metrics collector");
+    });
+}
+
+/// Clock ticks per second procfs reports cpu times in (USER_HZ)
+fn clock_ticks_per_sec() -> Option<f64> {
+    // SAFETY: sysconf has no preconditions and only reads a system setting
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    u64::try_from(ticks)
+        .ok()
+        .filter(|ticks| *ticks > 0)
+        .map(|ticks| ticks as f64)
+}
+
+fn collect(ticks_per_sec: Option<f64>) {
+    // counters only hold whole numbers, so the fractional cpu seconds are
+    // reported as a gauge of the running total
+    if let Some(cpu_seconds) = ticks_per_sec.and_then(|ticks_per_sec| {
+        fs::read_to_string("/proc/self/stat")
+            .ok()
+            .and_then(|stat| parse_cpu_seconds(&stat, ticks_per_sec))
+    }) {
+        metrics::gauge!(CPU_SECONDS_METRIC, cpu_seconds);
+    }
+
+    if let Ok(status) = fs::read_to_string("/proc/self/status") {
+        let status = parse_status(&status);
+        if let Some(resident_bytes) = status.resident_bytes {
+            metrics::gauge!(RESIDENT_MEMORY_METRIC, resident_bytes as f64);
+        }
+        if let Some(threads) = status.threads {
+            metrics::gauge!(THREADS_METRIC, threads as f64);
+        }
+    }
+
+    if let Ok(fds) = fs::read_dir("/proc/self/fd") {
+        metrics::gauge!(OPEN_FDS_METRIC, fds.count() as f64);
+    }
+}
+
+/// Total user and system cpu time in seconds from the contents of
+/// /proc/self/stat
+fn parse_cpu_seconds(stat: &str, ticks_per_sec: f64) -> Option<f64> {
+    // the command name is wrapped in parens and may itself contain spaces, so
+    // fields are counted from after its closing paren where the process
+    // state is field 3
+    let fields: Vec<&str> = stat
+        .get(stat.rfind(')')? + 1..)?
+        .split_whitespace()
+        .collect();
+    let utime: u64 = fields.get(14 - 3)?.parse().ok()?;
+    let stime: u64 = fields.get(15 - 3)?.parse().ok()?;
+    Some((utime + stime) as f64 / ticks_per_sec)
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct ProcessStatus {
+    resident_bytes: Option<u64>,
+    threads: Option<u64>,
+}
+
+fn parse_status(status: &str) -> ProcessStatus {
+    let mut process_status = ProcessStatus::default();
+    for line in status.lines() {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("VmRSS:"), Some(kilobytes)) => {
+                process_status.resident_bytes = kilobytes.parse::<u64>().ok().map(|kb| kb * 1024)
+            }
+            (Some("Threads:"), Some(threads)) => process_status.threads = threads.parse().ok(),
+            _ => (),
+        }
+    }
+    process_status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cpu_seconds_with_spaces_in_command() {
+        let stat = "4242 (iot config) S 1 4242 4242 0 -1 4194560 2116 0 0 0 250 150 0 0 20 0 \
+                    9 0 1234 123456789 3000 18446744073709551615";
+        assert_eq!(parse_cpu_seconds(stat, 100.0), Some(4.0));
+        assert_eq!(parse_cpu_seconds(stat, 1000.0), Some(0.4));
+        assert_eq!(parse_cpu_seconds("garbage", 100.0), None);
+    }
+
+    #[test]
+    fn reads_clock_ticks_from_the_system() {
+        assert!(clock_ticks_per_sec().is_some());
+    }
+
+    #[test]
+    fn parses_resident_memory_and_threads() {
+        let status = "Name:\tiot-config\nVmPeak:\t  20000 kB\nVmRSS:\t   1024 kB\nThreads:\t9\n";
+        assert_eq!(
+            parse_status(status),
+            ProcessStatus {
+                resident_bytes: Some(1024 * 1024),
+                threads: Some(9),
+            }
+        );
+    }
+}
@@ -18,7 +18,7 @@ pub struct EntityClient {
 }
 
 impl EntityClient {
-    pub fn from_settings(settings: &Settings) -> Result<Self, Box<helium_crypto::Error>> {
+    pub fn from_settings(settings: &Settings) -> Result<Self, ClientError> {
         let cache = Arc::new(Cache::new());
         let cloned_cache = cache.clone();
         tokio::spawn(async move {
@@ -28,7 +28,7 @@ impl EntityClient {
         });
 
         Ok(Self {
-            client: settings.connect_entity_client(),
+            client: settings.connect_entity_client()?,
             signing_key: settings.signing_keypair()?,
             config_pubkey: settings.config_pubkey()?,
             cache_ttl: settings.cache_ttl(),
@@ -21,7 +21,7 @@ pub struct GatewayClient {
 }
 
 impl GatewayClient {
-    pub fn from_settings(settings: &Settings) -> Result<Self, Box<helium_crypto::Error>> {
+    pub fn from_settings(settings: &Settings) -> Result<Self, ClientError> {
         let cache = Arc::new(Cache::new());
         let cloned_cache = cache.clone();
         tokio::spawn(async move {
@@ -31,7 +31,7 @@ impl GatewayClient {
         });
 
         Ok(Self {
-            client: settings.connect_gateway_client(),
+            client: settings.connect_gateway_client()?,
             signing_key: settings.signing_keypair()?,
             config_pubkey: settings.config_pubkey()?,
             batch_size: settings.batch_size,
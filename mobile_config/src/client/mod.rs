@@ -3,6 +3,7 @@ pub mod entity_client;
 pub mod gateway_client;
 mod settings;
 
+use poc_metrics::error_code::{self, ErrorCategory, ErrorCode};
 use std::time::Duration;
 
 pub use authorization_client::AuthorizationClient;
@@ -20,4 +21,46 @@ pub enum ClientError {
     GrpcError(#[from] tonic::Status),
     #[error("error verifying response signature {0}")]
     VerificationError(#[from] file_store::Error),
+    #[error("error loading client keys {0}")]
+    Keys(#[from] Box<helium_crypto::Error>),
+    #[error("error configuring client tls {0}")]
+    Tls(#[from] grpc_service::tls::TlsError),
+    #[error("error configuring client channel {0}")]
+    Transport(#[from] tonic::transport::Error),
+    #[error("invalid client settings {0}")]
+    InvalidSettings(String),
+}
+
+impl ErrorCode for ClientError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::SigningError(_) | Self::VerificationError(_) => ErrorCategory::Crypto,
+            Self::GrpcError(_) | Self::Transport(_) => ErrorCategory::Network,
+            Self::Keys(_) | Self::Tls(_) | Self::InvalidSettings(_) => ErrorCategory::Config,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::SigningError(_) => "signing",
+            Self::GrpcError(status) => match status.code() {
+                tonic::Code::NotFound => "config_not_found",
+                tonic::Code::PermissionDenied | tonic::Code::Unauthenticated => {
+                    "config_unauthorized"
+                }
+                tonic::Code::Unavailable | tonic::Code::DeadlineExceeded => "config_unavailable",
+                _ => "config_rpc",
+            },
+            Self::VerificationError(_) => "response_signature",
+            Self::Keys(_) => "client_keys",
+            Self::Tls(_) => "client_tls",
+            Self::Transport(_) => "config_transport",
+            Self::InvalidSettings(_) => "invalid_client_settings",
+        }
+    }
+}
+
+/// Label the errors of the config client in metrics and logs
+pub fn register_error_codes() {
+    error_code::register::<ClientError>();
 }
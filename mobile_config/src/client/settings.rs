@@ -1,3 +1,5 @@
+use super::ClientError;
+use grpc_service::tls::ClientTlsSettings;
 use helium_proto::services::{mobile_config, Channel, Endpoint};
 use serde::Deserialize;
 use std::{str::FromStr, sync::Arc, time::Duration};
@@ -7,8 +9,18 @@ pub struct Settings {
     /// grpc url to the mobile config oracle server
     #[serde(with = "http_serde::uri")]
     pub url: http::Uri,
-    /// File from which to load config server signing keypair
-    pub signing_keypair: String,
+    /// grpc urls of further replicas of the mobile config server. Requests
+    /// are balanced over all healthy urls. Connecting fails on an invalid
+    /// url. Default none
+    #[serde(default)]
+    pub additional_urls: Vec<String>,
+    /// Seconds between connection checks of each url, unhealthy urls are
+    /// taken out of rotation. Default 10
+    #[serde(default = "default_health_check_interval")]
+    pub health_check_interval: u64,
+    /// File from which to load config server signing keypair, or a `file:`
+    /// or `env:` secret reference
+    pub signing_keypair: oracle_settings::Secret,
     /// B58 encoded public key of the mobile config server for verification
     pub config_pubkey: String,
     /// Connect timeout for the mobile config client in seconds. Default 5
@@ -22,6 +34,9 @@ pub struct Settings {
     pub batch_size: u32,
     #[serde(default = "default_cache_ttl_in_secs")]
     pub cache_ttl_in_secs: u64,
+    /// TLS options for connecting to a server using TLS. Plaintext when not
+    /// set
+    pub tls: Option<ClientTlsSettings>,
 }
 
 pub fn default_connect_timeout() -> u64 {
@@ -36,30 +51,42 @@ pub fn default_batch_size() -> u32 {
     100
 }
 
+pub fn default_health_check_interval() -> u64 {
+    10
+}
+
 pub fn default_cache_ttl_in_secs() -> u64 {
     60 * 60
 }
 
 impl Settings {
-    pub fn connect_gateway_client(&self) -> mobile_config::GatewayClient<Channel> {
-        let channel = connect_channel(self);
-        mobile_config::GatewayClient::new(channel)
+    pub fn connect_gateway_client(
+        &self,
+    ) -> Result<mobile_config::GatewayClient<Channel>, ClientError> {
+        let channel = connect_channel(self)?;
+        Ok(mobile_config::GatewayClient::new(channel))
     }
 
-    pub fn connect_authorization_client(&self) -> mobile_config::AuthorizationClient<Channel> {
-        let channel = connect_channel(self);
-        mobile_config::AuthorizationClient::new(channel)
+    pub fn connect_authorization_client(
+        &self,
+    ) -> Result<mobile_config::AuthorizationClient<Channel>, ClientError> {
+        let channel = connect_channel(self)?;
+        Ok(mobile_config::AuthorizationClient::new(channel))
     }
 
-    pub fn connect_entity_client(&self) -> mobile_config::EntityClient<Channel> {
-        let channel = connect_channel(self);
-        mobile_config::EntityClient::new(channel)
+    pub fn connect_entity_client(
+        &self,
+    ) -> Result<mobile_config::EntityClient<Channel>, ClientError> {
+        let channel = connect_channel(self)?;
+        Ok(mobile_config::EntityClient::new(channel))
     }
 
     pub fn signing_keypair(
         &self,
     ) -> Result<Arc<helium_crypto::Keypair>, Box<helium_crypto::Error>> {
-        let data = std::fs::read(&self.signing_keypair).map_err(helium_crypto::Error::from)?;
+        let data = self.signing_keypair.resolve_keypair().map_err(|err| {
+            helium_crypto::Error::from(std::io::Error::new(std::io::ErrorKind::Other, err))
+        })?;
         Ok(Arc::new(helium_crypto::Keypair::try_from(&data[..])?))
     }
 
@@ -70,11 +97,86 @@ impl Settings {
     pub fn cache_ttl(&self) -> std::time::Duration {
         std::time::Duration::from_secs(self.cache_ttl_in_secs)
     }
+
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.health_check_interval == 0 {
+            problems
+                .push("mobile config client health_check_interval must be positive".to_string());
+        }
+        problems
+    }
+
+    /// The health check interval, the default for services that do not
+    /// validate their client settings and were given zero
+    fn health_check_interval(&self) -> Duration {
+        if self.health_check_interval == 0 {
+            tracing::error!("mobile config health_check_interval must be positive, using default");
+            return Duration::from_secs(default_health_check_interval());
+        }
+        Duration::from_secs(self.health_check_interval)
+    }
+}
+
+fn connect_channel(settings: &Settings) -> Result<Channel, ClientError> {
+    let mut uris = vec![settings.url.clone()];
+    for url in &settings.additional_urls {
+        let uri = url
+            .parse()
+            .map_err(|_| ClientError::InvalidSettings(format!("invalid additional url: {url}")))?;
+        uris.push(uri);
+    }
+    let tls = settings
+        .tls
+        .as_ref()
+        .map(|tls| tls.tls_config())
+        .transpose()?;
+    let endpoints = uris
+        .into_iter()
+        .map(|uri| {
+            let endpoint = Endpoint::from(uri)
+                .connect_timeout(Duration::from_secs(settings.connect_timeout))
+                .timeout(Duration::from_secs(settings.rpc_timeout));
+            match &tls {
+                Some(tls) => Ok(endpoint.tls_config(tls.clone())?),
+                None => Ok(endpoint),
+            }
+        })
+        .collect::<Result<_, ClientError>>()?;
+    Ok(client_pool::balanced_channel(
+        "mobile_config",
+        endpoints,
+        settings.health_check_interval(),
+    ))
 }
 
-fn connect_channel(settings: &Settings) -> Channel {
-    Endpoint::from(settings.url.clone())
-        .connect_timeout(Duration::from_secs(settings.connect_timeout))
-        .timeout(Duration::from_secs(settings.rpc_timeout))
-        .connect_lazy()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(additional_urls: &[&str]) -> Settings {
+        Settings {
+            url: http::Uri::from_static("http://127.0.0.1:8080"),
+            additional_urls: additional_urls.iter().map(|url| url.to_string()).collect(),
+            health_check_interval: default_health_check_interval(),
+            signing_keypair: oracle_settings::Secret::new("keypair.bin"),
+            config_pubkey: String::new(),
+            connect_timeout: default_connect_timeout(),
+            rpc_timeout: default_rpc_timeout(),
+            batch_size: default_batch_size(),
+            cache_ttl_in_secs: default_cache_ttl_in_secs(),
+            tls: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_additional_urls_are_rejected() {
+        assert!(settings(&["http://127.0.0.1:8081"])
+            .connect_gateway_client()
+            .is_ok());
+        assert!(matches!(
+            settings(&["http://127.0.0.1:8081", "not a url"]).connect_gateway_client(),
+            Err(ClientError::InvalidSettings(_))
+        ));
+    }
 }
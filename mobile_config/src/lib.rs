@@ -14,7 +14,7 @@ pub mod key_cache;
 pub mod settings;
 pub mod telemetry;
 
-pub use client::{GatewayClient, Settings as ClientSettings};
+pub use client::{register_error_codes, GatewayClient, Settings as ClientSettings};
 
 pub type GrpcResult<T> = Result<Response<T>, Status>;
 pub type GrpcStreamResult<T> = ReceiverStream<Result<T, Status>>;
@@ -1,6 +1,7 @@
 use anyhow::{Error, Result};
 use clap::Parser;
 use futures_util::TryFutureExt;
+use grpc_service::tls;
 use helium_proto::services::mobile_config::{
     AdminServer, AuthorizationServer, EntityServer, GatewayServer,
 };
@@ -12,7 +13,6 @@ use mobile_config::{
 use std::{path::PathBuf, time::Duration};
 use tokio::signal;
 use tonic::transport;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Debug, clap::Parser)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
@@ -49,14 +49,16 @@ impl Cmd {
 }
 
 #[derive(Debug, clap::Args)]
-pub struct Daemon;
+pub struct Daemon {
+    /// Apply database migrations and exit without starting the service
+    #[clap(long)]
+    migrate_only: bool,
+}
 
 impl Daemon {
     pub async fn run(&self, settings: &Settings) -> Result<()> {
-        tracing_subscriber::registry()
-            .with(tracing_subscriber::EnvFilter::new(&settings.log))
-            .with(tracing_subscriber::fmt::layer())
-            .init();
+        custom_tracing::init(&settings.log, &settings.tracing)?;
+        poc_metrics::register_error_codes!(poc_metrics, db_store, file_store, mobile_config);
 
         // Configure shutdown trigger
         let (shutdown_trigger, shutdown_listener) = triggered::trigger();
@@ -70,13 +72,17 @@ impl Daemon {
 
         // Install prometheus metrics exporter
         poc_metrics::start_metrics(&settings.metrics)?;
+        poc_metrics::process::start(shutdown_listener.clone());
 
         // Create database pool
         let (pool, pool_handle) = settings
             .database
             .connect("mobile-config-store", shutdown_listener.clone())
             .await?;
-        sqlx::migrate!().run(&pool).await?;
+        db_store::migrate::run(&pool, env!("CARGO_PKG_NAME"), &sqlx::migrate!()).await?;
+        if self.migrate_only {
+            return Ok(());
+        }
 
         // Create on-chain metadata pool
         let (metadata_pool, md_pool_handle) = settings
@@ -102,15 +108,30 @@ impl Daemon {
             settings.signing_keypair()?,
         );
 
-        let server = transport::Server::builder()
+        let router = transport::Server::builder()
             .http2_keepalive_interval(Some(Duration::from_secs(250)))
             .http2_keepalive_timeout(Some(Duration::from_secs(60)))
             .add_service(AdminServer::new(admin_svc))
             .add_service(GatewayServer::new(gateway_svc))
             .add_service(AuthorizationServer::new(auth_svc))
-            .add_service(EntityServer::new(entity_svc))
-            .serve_with_shutdown(listen_addr, shutdown_listener)
-            .map_err(Error::from);
+            .add_service(EntityServer::new(entity_svc));
+        let server = async {
+            match &settings.tls {
+                Some(tls) => {
+                    let incoming =
+                        tls::incoming(tls, listen_addr, shutdown_listener.clone()).await?;
+                    router
+                        .serve_with_incoming_shutdown(incoming, shutdown_listener)
+                        .await?
+                }
+                None => {
+                    router
+                        .serve_with_shutdown(listen_addr, shutdown_listener)
+                        .await?
+                }
+            }
+            Ok::<(), Error>(())
+        };
 
         tokio::try_join!(
             pool_handle.map_err(Error::from),
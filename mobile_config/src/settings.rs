@@ -1,4 +1,5 @@
 use config::{Config, Environment, File};
+use grpc_service::tls::TlsSettings;
 use serde::Deserialize;
 use std::{
     net::{AddrParseError, SocketAddr},
@@ -12,13 +13,20 @@ pub struct Settings {
     /// "mobile_config=info"
     #[serde(default = "default_log")]
     pub log: String,
+    /// Log output format, per module log levels and runtime diagnostics
+    #[serde(default)]
+    pub tracing: custom_tracing::Settings,
     /// Listen address. Required. Default to 0.0.0.0::8080
     #[serde(default = "default_listen_addr")]
     pub listen: String,
-    /// File from which to load config server signing keypair
-    pub signing_keypair: String,
+    /// Serve grpc over TLS when set. Plaintext by default
+    pub tls: Option<TlsSettings>,
+    /// File from which to load config server signing keypair, or a `file:`
+    /// or `env:` secret reference
+    pub signing_keypair: oracle_settings::Secret,
     /// B58 encoded public key of the default admin keypair
-    pub admin_pubkey: String,
+    #[serde(with = "oracle_settings::pubkey")]
+    pub admin_pubkey: helium_crypto::PublicKey,
     /// Settings passed to the db_store crate for connecting to
     /// the config service's own persistence store
     pub database: db_store::Settings,
@@ -65,11 +73,7 @@ impl Settings {
     }
 
     pub fn signing_keypair(&self) -> anyhow::Result<helium_crypto::Keypair> {
-        let data = std::fs::read(&self.signing_keypair).map_err(helium_crypto::Error::from)?;
+        let data = self.signing_keypair.resolve_keypair()?;
         Ok(helium_crypto::Keypair::try_from(&data[..])?)
     }
-
-    pub fn admin_pubkey(&self) -> anyhow::Result<helium_crypto::PublicKey> {
-        Ok(helium_crypto::PublicKey::from_str(&self.admin_pubkey)?)
-    }
 }
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use dc_pricing::DcPricing;
 use file_store::{file_sink::FileSinkClient, traits::TimestampEncode};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::services::packet_verifier::ValidDataTransferSession;
@@ -22,9 +23,15 @@ pub struct PayerTotals {
     sessions: Vec<DataTransferSession>,
 }
 
+impl DataTransferSession {
+    fn num_dcs(&self, pricing: &DcPricing) -> u64 {
+        pricing.bytes_to_dc(self.uploaded_bytes as u64 + self.downloaded_bytes as u64)
+    }
+}
+
 impl PayerTotals {
-    fn push_sess(&mut self, sess: DataTransferSession) {
-        self.total_dcs += bytes_to_dc(sess.downloaded_bytes as u64 + sess.uploaded_bytes as u64);
+    fn push_sess(&mut self, sess: DataTransferSession, pricing: &DcPricing) {
+        self.total_dcs += sess.num_dcs(pricing);
         self.sessions.push(sess);
     }
 }
@@ -32,6 +39,7 @@ impl PayerTotals {
 pub struct Burner<S> {
     valid_sessions: FileSinkClient,
     solana: S,
+    pricing: DcPricing,
 }
 
 impl<S> Burner<S> {
@@ -39,8 +47,15 @@ impl<S> Burner<S> {
         Self {
             valid_sessions,
             solana,
+            pricing: DcPricing::mobile(),
         }
     }
+
+    /// Charge the sessions with the given pricing instead of the default
+    /// mobile pricing
+    pub fn pricing(self, pricing: DcPricing) -> Self {
+        Self { pricing, ..self }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -70,7 +85,7 @@ where
             payer_totals
                 .entry(session.payer.clone())
                 .or_default()
-                .push_sess(session);
+                .push_sess(session, &self.pricing);
         }
 
         for (
@@ -106,8 +121,7 @@ where
                 .await?;
 
             for session in sessions {
-                let num_dcs =
-                    bytes_to_dc(session.uploaded_bytes as u64 + session.downloaded_bytes as u64);
+                let num_dcs = session.num_dcs(&self.pricing);
                 self.valid_sessions
                     .write(
                         ValidDataTransferSession {
@@ -140,11 +154,3 @@ where
         Ok(())
     }
 }
-
-const BYTES_PER_DC: u64 = 20_000;
-
-fn bytes_to_dc(bytes: u64) -> u64 {
-    let bytes = bytes.max(BYTES_PER_DC);
-    // Integer div/ceil from: https://stackoverflow.com/a/2745086
-    (bytes + BYTES_PER_DC - 1) / BYTES_PER_DC
-}
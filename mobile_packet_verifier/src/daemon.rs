@@ -1,6 +1,7 @@
 use crate::{burner::Burner, settings::Settings};
 use anyhow::{bail, Error, Result};
 use chrono::{TimeZone, Utc};
+use custom_tracing::correlation;
 use file_store::{
     file_info_poller::{FileInfoStream, LookbackBehavior},
     file_sink::FileSinkClient,
@@ -63,13 +64,8 @@ where
                     let Some(file) = file else {
                         anyhow::bail!("FileInfoPoller sender was dropped unexpectedly");
                     };
-                    tracing::info!("Verifying file: {}", file.file_info);
-                    let ts = file.file_info.timestamp;
-                    let mut transaction = self.pool.begin().await?;
-                    let reports = file.into_stream(&mut transaction).await?;
-                    crate::accumulate::accumulate_sessions(&self.gateway_client, &self.auth_client, &mut transaction, &self.invalid_data_session_report_sink, ts, reports).await?;
-                    transaction.commit().await?;
-                    self.invalid_data_session_report_sink.commit().await?;
+                    let correlation_id = file.file_info.key.clone();
+                    correlation::scope(correlation_id, self.handle_file(file)).await?;
                 },
                 _ = sleep_until(burn_time) => {
                     // It's time to burn
@@ -80,16 +76,43 @@ where
             }
         }
     }
+
+    async fn handle_file(
+        &mut self,
+        file: FileInfoStream<DataTransferSessionIngestReport>,
+    ) -> Result<()> {
+        tracing::info!("Verifying file: {}", file.file_info);
+        let ts = file.file_info.timestamp;
+        let mut transaction = self.pool.begin().await?;
+        let (reports, completion) = file.into_stream(&mut transaction).await?;
+        crate::accumulate::accumulate_sessions(
+            &self.gateway_client,
+            &self.auth_client,
+            &mut transaction,
+            &self.invalid_data_session_report_sink,
+            ts,
+            reports,
+        )
+        .await?;
+        completion.commit(transaction).await?;
+        self.invalid_data_session_report_sink.commit().await?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, clap::Args)]
-pub struct Cmd {}
+pub struct Cmd {
+    /// Apply database migrations and exit without starting the service
+    #[clap(long)]
+    migrate_only: bool,
+}
 
 impl Cmd {
     pub async fn run(self, settings: &Settings) -> Result<()> {
         poc_metrics::start_metrics(&settings.metrics)?;
 
         let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        poc_metrics::process::start(shutdown_listener.clone());
         let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
         tokio::spawn(async move {
             tokio::select! {
@@ -103,7 +126,10 @@ impl Cmd {
             .database
             .connect("mobile-packet-verifier", shutdown_listener.clone())
             .await?;
-        sqlx::migrate!().run(&pool).await?;
+        db_store::migrate::run(&pool, env!("CARGO_PKG_NAME"), &sqlx::migrate!()).await?;
+        if self.migrate_only {
+            return Ok(());
+        }
 
         // Set up the solana network:
         let solana = if settings.enable_solana_integration {
@@ -151,7 +177,7 @@ impl Cmd {
         .create()
         .await?;
 
-        let burner = Burner::new(valid_sessions, solana);
+        let burner = Burner::new(valid_sessions, solana).pricing(settings.dc_pricing);
 
         let file_store = FileStore::from_settings(&settings.ingest).await?;
 
@@ -9,11 +9,18 @@ pub struct Settings {
     /// "mobile_verifier=debug,poc_store=info"
     #[serde(default = "default_log")]
     pub log: String,
+    /// Log output format and per module log levels
+    #[serde(default)]
+    pub tracing: custom_tracing::Settings,
     /// Cache location for generated verified reports
     pub cache: String,
     /// Burn period in hours. (Default is 1)
     #[serde(default = "default_burn_period")]
     pub burn_period: i64,
+    /// Data credits charged per data transfer session. Default is a data
+    /// credit per 20_000 bytes, rounded up, at least one per session
+    #[serde(default = "default_dc_pricing")]
+    pub dc_pricing: dc_pricing::DcPricing,
     pub database: db_store::Settings,
     pub ingest: file_store::Settings,
     pub output: file_store::Settings,
@@ -38,6 +45,10 @@ pub fn default_log() -> String {
     "mobile_packet_verifier=debug,poc_store=info".to_string()
 }
 
+pub fn default_dc_pricing() -> dc_pricing::DcPricing {
+    dc_pricing::DcPricing::mobile()
+}
+
 pub fn default_burn_period() -> i64 {
     1
 }
@@ -63,6 +74,21 @@ impl Settings {
             .add_source(Environment::with_prefix("MOBILE_PACKET_VERIFY").separator("_"))
             .build()
             .and_then(|config| config.try_deserialize())
+            .and_then(|settings: Settings| settings.validate().map(|_| settings))
+    }
+
+    /// Check the loaded settings for values which deserialize but can not be
+    /// used, reporting every problem found rather than just the first.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let problems = self.dc_pricing.problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Message(format!(
+                "invalid settings: {}",
+                problems.join("; ")
+            )))
+        }
     }
 
     pub fn start_after(&self) -> DateTime<Utc> {
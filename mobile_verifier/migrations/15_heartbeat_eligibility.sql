@@ -0,0 +1,12 @@
+CREATE TABLE heartbeat_eligibility (
+       cbsd_id TEXT NOT NULL,
+       hotspot_key TEXT NOT NULL,
+       cell_type cell_type NOT NULL,
+       window_start TIMESTAMPTZ NOT NULL,
+       window_end TIMESTAMPTZ NOT NULL,
+       -- Number of hours of the reward window with a valid heartbeat
+       hours BIGINT NOT NULL,
+       PRIMARY KEY(cbsd_id, window_start)
+);
+
+CREATE INDEX heartbeat_eligibility_window_idx ON heartbeat_eligibility (window_start, window_end);
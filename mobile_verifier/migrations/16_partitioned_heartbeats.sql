@@ -0,0 +1,38 @@
+-- Heartbeats are partitioned by utc day of their hour, with partitions named
+-- heartbeats_pYYYYMMDD created and dropped by the partitioner of db_store
+ALTER TABLE heartbeats RENAME TO heartbeats_unpartitioned;
+ALTER TABLE heartbeats_unpartitioned RENAME CONSTRAINT heartbeats_pkey TO heartbeats_unpartitioned_pkey;
+
+CREATE TABLE heartbeats (
+       cbsd_id TEXT NOT NULL,
+       hotspot_key TEXT NOT NULL,
+       cell_type cell_type NOT NULL,
+       latest_timestamp TIMESTAMPTZ NOT NULL,
+       truncated_timestamp TIMESTAMPTZ NOT NULL CHECK (truncated_timestamp = date_trunc('hour', truncated_timestamp)),
+       PRIMARY KEY(cbsd_id, truncated_timestamp)
+) PARTITION BY RANGE (truncated_timestamp);
+
+-- Rows outside of every partition, which the verifier avoids by creating the
+-- partitions of the heartbeat files it is about to save
+CREATE TABLE heartbeats_default PARTITION OF heartbeats DEFAULT;
+
+DO $$
+DECLARE
+       day TIMESTAMP;
+BEGIN
+       FOR day IN
+              SELECT DISTINCT date_trunc('day', truncated_timestamp AT TIME ZONE 'UTC')
+              FROM heartbeats_unpartitioned
+       LOOP
+              EXECUTE format(
+                     'CREATE TABLE %I PARTITION OF heartbeats FOR VALUES FROM (%L) TO (%L)',
+                     'heartbeats_p' || to_char(day, 'YYYYMMDD'),
+                     day AT TIME ZONE 'UTC',
+                     (day + interval '1 day') AT TIME ZONE 'UTC'
+              );
+       END LOOP;
+END $$;
+
+INSERT INTO heartbeats SELECT * FROM heartbeats_unpartitioned;
+
+DROP TABLE heartbeats_unpartitioned;
@@ -0,0 +1,16 @@
+-- Adjustments of the rewards of a hotspot in bones, applied to the next epoch
+-- rewarded after they were recorded. Negative amounts withhold rewards, the
+-- part not covered by the rewards of the epoch is carried as a new correction
+CREATE TABLE reward_corrections (
+       id BIGSERIAL PRIMARY KEY,
+       hotspot_key TEXT NOT NULL,
+       amount BIGINT NOT NULL CHECK (amount <> 0),
+       reason TEXT NOT NULL,
+       -- Key recording the correction, null for carried remainders
+       signer TEXT,
+       created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+       -- End of the epoch the correction was applied to, null while pending
+       applied_to TIMESTAMPTZ
+);
+
+CREATE INDEX reward_corrections_pending_idx ON reward_corrections (id) WHERE applied_to IS NULL;
@@ -0,0 +1,13 @@
+CREATE TABLE location_mismatches (
+	hotspot_key TEXT NOT NULL,
+	cbsd_id TEXT NOT NULL,
+	truncated_timestamp TIMESTAMPTZ NOT NULL,
+	distance_km DOUBLE PRECISION,
+	PRIMARY KEY(cbsd_id, truncated_timestamp)
+);
+
+CREATE TABLE gateway_location_mismatches (
+	hotspot_key TEXT PRIMARY KEY,
+	mismatches BIGINT NOT NULL,
+	denylisted_at TIMESTAMPTZ
+);
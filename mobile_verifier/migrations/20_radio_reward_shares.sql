@@ -0,0 +1,7 @@
+CREATE TABLE radio_reward_shares (
+	hotspot_key TEXT NOT NULL,
+	cbsd_id TEXT NOT NULL,
+	reward_period_end TIMESTAMPTZ NOT NULL,
+	shares DECIMAL NOT NULL,
+	PRIMARY KEY(reward_period_end, hotspot_key, cbsd_id)
+);
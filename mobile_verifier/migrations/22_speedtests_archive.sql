@@ -0,0 +1 @@
+CREATE TABLE speedtests_archive (LIKE speedtests);
@@ -0,0 +1,4 @@
+-- Hash of the signed request recording a correction, without its signature,
+-- so that a replayed request is not recorded twice. Null for carried
+-- remainders
+ALTER TABLE reward_corrections ADD COLUMN request_hash BYTEA UNIQUE;
@@ -1,7 +1,7 @@
 use helium_proto::services::poc_mobile::CellType as CellTypeProto;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 pub const CELLTYPE_NOVA_436H: &str = "2AG32MBS3100196N";
 pub const CELLTYPE_NOVA_430I: &str = "2AG32PBS3101S";
@@ -9,7 +9,7 @@ pub const CELLTYPE_NEUTRINO_430: &str = "2AG32PBS31010";
 pub const CELLTYPE_SERCCOMM_INDOOR: &str = "P27-SCE4255W";
 pub const CELLTYPE_SERCCOMM_OUTDOOR: &str = "P27-SCO4255PA10";
 
-#[derive(Debug, Eq, Hash, PartialEq, Copy, Clone, Serialize, sqlx::Type)]
+#[derive(Debug, Eq, Hash, PartialEq, Copy, Clone, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "cell_type")]
 #[sqlx(rename_all = "lowercase")]
 pub enum CellType {
@@ -32,6 +32,19 @@ impl CellType {
         }
     }
 
+    /// The cell type with the given variant name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::Nova436H,
+            Self::Nova430I,
+            Self::Neutrino430,
+            Self::SercommIndoor,
+            Self::SercommOutdoor,
+        ]
+        .into_iter()
+        .find(|cell_type| format!("{cell_type:?}").eq_ignore_ascii_case(name))
+    }
+
     pub fn reward_weight(&self) -> Decimal {
         match self {
             Self::Nova436H => dec!(4.0),
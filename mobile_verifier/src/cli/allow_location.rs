@@ -0,0 +1,30 @@
+use crate::{heartbeat_location, Settings};
+use anyhow::Result;
+use helium_crypto::PublicKeyBinary;
+
+/// Lift the denylisting of a hotspot for heartbeats away from its asserted
+/// location, once its location was fixed
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    #[clap(long)]
+    hotspot_key: PublicKeyBinary,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        let (pool, _join_handle) = settings
+            .database
+            .connect(env!("CARGO_PKG_NAME"), shutdown_listener)
+            .await?;
+
+        if heartbeat_location::allow(&pool, &self.hotspot_key).await? {
+            tracing::info!(hotspot_key = %self.hotspot_key, "lifted the location denylisting");
+        } else {
+            tracing::info!(hotspot_key = %self.hotspot_key, "hotspot was not denylisted");
+        }
+
+        shutdown_trigger.trigger();
+        Ok(())
+    }
+}
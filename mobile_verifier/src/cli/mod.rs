@@ -1,2 +1,4 @@
+pub mod allow_location;
+pub mod reward_diff;
 pub mod reward_from_db;
 pub mod server;
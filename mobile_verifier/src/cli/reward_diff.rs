@@ -0,0 +1,105 @@
+use crate::{
+    reward_diff::{DbRewards, FileRewards, RewardDiff, RewardSource},
+    Settings,
+};
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use file_store::{file_sink::FileSinkBuilder, FileType};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::path::PathBuf;
+
+/// Compare the poc rewards recomputed from the database against previously
+/// written mobile reward share files for the same period. Nothing is written
+/// to the canonical reward outputs.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    #[clap(long)]
+    start: NaiveDateTime,
+    #[clap(long)]
+    end: NaiveDateTime,
+    /// Mobile reward share files to use as the baseline
+    #[clap(long, required = true)]
+    baseline: Vec<PathBuf>,
+    /// Price of MOBILE in millionths of a USD, as reported by the price
+    /// oracle for the epoch, valuing the data transfer rewards deducted from
+    /// the poc rewards
+    #[clap(long)]
+    mobile_price: u64,
+    /// Optional path to write the diff report to. Defaults to stdout
+    #[clap(long)]
+    output: Option<PathBuf>,
+    /// Optional directory of a file sink receiving a mobile_reward_diff
+    /// record for every gateway whose rewards differ
+    #[clap(long)]
+    sink: Option<PathBuf>,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let start = DateTime::from_utc(self.start, Utc);
+        let end = DateTime::from_utc(self.end, Utc);
+        let epoch = start..end;
+
+        tracing::info!("Comparing rewards for the following time range: {start} to {end}");
+
+        let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        let (pool, _join_handle) = settings
+            .database
+            .connect(env!("CARGO_PKG_NAME"), shutdown_listener.clone())
+            .await?;
+
+        // Mobile prices are supplied in 10^6, so we must convert them to Decimal
+        let mobile_bone_price = Decimal::from(self.mobile_price)
+                / dec!(1_000_000)  // Per Mobile token
+                / dec!(1_000_000); // Per Bone
+
+        let baseline = FileRewards::new(self.baseline);
+        let candidate = DbRewards::new(
+            pool,
+            settings.reward_config.clone(),
+            settings.emissions.clone(),
+            mobile_bone_price,
+        )
+        .min_speedtests(settings.min_speedtests)
+        .pricing(settings.dc_pricing);
+
+        let diff = RewardDiff::new(
+            baseline.name(),
+            &baseline.radio_rewards(&epoch).await?,
+            candidate.name(),
+            &candidate.radio_rewards(&epoch).await?,
+        );
+        tracing::info!(
+            changed_gateways = diff.changed_gateways,
+            total_delta = %diff.total_delta,
+            "reward diff complete"
+        );
+
+        if let Some(sink_path) = &self.sink {
+            let (sink, mut sink_server) = FileSinkBuilder::new(
+                FileType::MobileRewardDiff,
+                sink_path,
+                concat!(env!("CARGO_PKG_NAME"), "_reward_diff"),
+                shutdown_listener.clone(),
+            )
+            .auto_commit(false)
+            .create()
+            .await?;
+            let sink_server = tokio::spawn(async move { sink_server.run().await });
+            diff.write(&sink, &epoch).await?;
+            // The sink stops once its only client is gone
+            drop(sink);
+            sink_server.await??;
+        }
+
+        let report = serde_json::to_string_pretty(&diff)?;
+        match self.output {
+            Some(path) => tokio::fs::write(path, report).await?,
+            None => println!("{report}"),
+        }
+
+        shutdown_trigger.trigger();
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     heartbeats::HeartbeatReward,
-    reward_shares::{get_scheduled_tokens_for_poc_and_dc, PocShares},
+    reward_shares::PocShares,
     speedtests::{Average, SpeedtestAverages},
     Settings,
 };
@@ -30,7 +30,7 @@ impl Cmd {
 
         tracing::info!("Rewarding shares from the following time range: {start} to {end}");
         let epoch = start..end;
-        let expected_rewards = get_scheduled_tokens_for_poc_and_dc(epoch.end - epoch.start);
+        let expected_rewards = settings.emissions.poc_and_dc(&epoch);
 
         let (shutdown_trigger, shutdown_listener) = triggered::trigger();
         let (pool, _join_handle) = settings
@@ -38,13 +38,15 @@ impl Cmd {
             .connect(env!("CARGO_PKG_NAME"), shutdown_listener)
             .await?;
 
-        let heartbeats = HeartbeatReward::validated(&pool, &epoch);
-        let speedtests = SpeedtestAverages::validated(&pool, epoch.end).await?;
+        let heartbeats = HeartbeatReward::validated(&pool, &epoch, &settings.reward_config);
+        let speedtests = SpeedtestAverages::validated(&pool, epoch.end)
+            .await?
+            .min_speedtests(settings.min_speedtests);
         let reward_shares = PocShares::aggregate(heartbeats, speedtests.clone()).await?;
 
         let mut total_rewards = 0_u64;
         let mut owner_rewards = HashMap::<_, u64>::new();
-        for reward in reward_shares.into_rewards(Decimal::ZERO, &epoch) {
+        for reward in reward_shares.into_rewards(&settings.emissions, Decimal::ZERO, &epoch) {
             if let Some(proto::mobile_reward_share::Reward::RadioReward(proto::RadioReward {
                 hotspot_key,
                 poc_reward,
@@ -59,11 +61,12 @@ impl Cmd {
         }
         let rewards: Vec<_> = owner_rewards.into_iter().collect();
         let mut multiplier_count = HashMap::<_, usize>::new();
+        let min_speedtests = speedtests.min_speedtests;
         let speedtest_multipliers: Vec<_> = speedtests
             .speedtests
             .into_iter()
             .map(|(pub_key, avg)| {
-                let reward_multiplier = Average::from(&avg).reward_multiplier();
+                let reward_multiplier = Average::from(&avg).reward_multiplier(min_speedtests);
                 *multiplier_count.entry(reward_multiplier).or_default() += 1;
                 (pub_key, reward_multiplier)
             })
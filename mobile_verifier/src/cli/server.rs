@@ -1,30 +1,50 @@
 use crate::{
-    data_session::DataSessionIngestor, heartbeats::HeartbeatDaemon, rewarder::Rewarder,
-    speedtests::SpeedtestDaemon, subscriber_location::SubscriberLocationIngestor, telemetry,
-    Settings,
+    correction_service::CorrectionService, data_session::DataSessionIngestor,
+    epoch_summary::VerificationCounts, gateway_resolver::GatewayResolver,
+    heartbeat_location::LocationCheck, heartbeats::HeartbeatDaemon, reward_shares,
+    rewarder::Rewarder, speedtest_service::SpeedtestService, speedtests::SpeedtestDaemon,
+    subscriber_location::SubscriberLocationIngestor, telemetry, Settings,
 };
-use anyhow::{Error, Result};
+use anyhow::Result;
 use chrono::Duration;
 use file_store::{
-    file_info_poller::LookbackBehavior, file_sink, file_source, file_upload,
-    heartbeat::CellHeartbeatIngestReport, mobile_subscriber::SubscriberLocationIngestReport,
-    mobile_transfer::ValidDataTransferSession, speedtest::CellSpeedtestIngestReport, FileStore,
-    FileType,
+    canary::Canary,
+    file_info_poller::LookbackBehavior,
+    file_sink,
+    file_source::{self, LegacyDecoder},
+    file_upload,
+    heartbeat::CellHeartbeatIngestReport,
+    mobile_subscriber::SubscriberLocationIngestReport,
+    mobile_transfer::ValidDataTransferSession,
+    speedtest::CellSpeedtestIngestReport,
+    FileStore, FileType,
 };
 
-use futures_util::TryFutureExt;
-use mobile_config::client::{AuthorizationClient, EntityClient, GatewayClient};
+use db_store::{maintenance::Table, LeaderElection, Maintenance, Partitioner};
+use grpc_service::tls;
+use mobile_config::client::{AuthorizationClient, EntityClient};
 use price::PriceTracker;
+use std::sync::Arc;
+use task_manager::{Stage, TaskManager};
 use tokio::signal;
 
+/// Tables purged by the maintenance
+// Heartbeats are dropped by partition, see `heartbeat_partitions`
+const MAINTAINED_TABLES: &[Table] = &[Table::new("speedtests", "latest_timestamp").archived()];
+
 #[derive(Debug, clap::Args)]
-pub struct Cmd {}
+pub struct Cmd {
+    /// Apply database migrations and exit without starting the service
+    #[clap(long)]
+    migrate_only: bool,
+}
 
 impl Cmd {
     pub async fn run(self, settings: &Settings) -> Result<()> {
         poc_metrics::start_metrics(&settings.metrics)?;
 
         let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        poc_metrics::process::start(shutdown_listener.clone());
         let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
         tokio::spawn(async move {
             tokio::select! {
@@ -32,18 +52,59 @@ impl Cmd {
                 _ = signal::ctrl_c() => shutdown_trigger.trigger(),
             }
         });
+        let mut task_manager = TaskManager::new();
 
         let (pool, db_join_handle) = settings
             .database
-            .connect(env!("CARGO_PKG_NAME"), shutdown_listener.clone())
+            .connect(
+                env!("CARGO_PKG_NAME"),
+                task_manager.listener(Stage::Producer),
+            )
             .await?;
-        sqlx::migrate!().run(&pool).await?;
+        db_store::migrate::run(&pool, env!("CARGO_PKG_NAME"), &sqlx::migrate!()).await?;
+        if self.migrate_only {
+            return Ok(());
+        }
+
+        if let Some(leader_election) = &settings.leader_election {
+            let election =
+                LeaderElection::new(pool.clone(), env!("CARGO_PKG_NAME"), leader_election);
+            if !election.wait_for_leadership(&shutdown_listener).await? {
+                return Ok(());
+            }
+            // Hold the lease until every stage has stopped
+            task_manager.add("leader_lease", Stage::Upload, |shutdown| {
+                election.maintain(shutdown)
+            });
+        }
 
         telemetry::initialize(&pool).await?;
 
+        let maintenance = Maintenance::new(
+            pool.clone(),
+            env!("CARGO_PKG_NAME"),
+            &settings.maintenance,
+            MAINTAINED_TABLES,
+        )?;
+
         let (file_upload_tx, file_upload_rx) = file_upload::message_channel();
         let file_upload =
             file_upload::FileUpload::from_settings(&settings.output, file_upload_rx).await?;
+        let reward_canary = settings
+            .reward_canary
+            .as_ref()
+            .map(|canary| {
+                Canary::new(
+                    canary,
+                    FileType::MobileRewardShare,
+                    reward_shares::encoded_reward_amount,
+                )
+            })
+            .transpose()?;
+        let file_upload = match &reward_canary {
+            Some(canary) => file_upload.hook(FileType::MobileRewardShare, Arc::new(canary.clone())),
+            None => file_upload,
+        };
 
         let store_base_path = std::path::Path::new(&settings.cache);
 
@@ -51,13 +112,16 @@ impl Cmd {
         let data_transfer_ingest = FileStore::from_settings(&settings.data_transfer_ingest).await?;
 
         // mobile config clients
-        let gateway_client = GatewayClient::from_settings(&settings.config_client)?;
+        let gateway_client = GatewayResolver::from_settings(settings).await?;
         let auth_client = AuthorizationClient::from_settings(&settings.config_client)?;
         let entity_client = EntityClient::from_settings(&settings.config_client)?;
 
         // price tracker
-        let (price_tracker, tracker_process) =
-            PriceTracker::start(&settings.price_tracker, shutdown_listener.clone()).await?;
+        let (price_tracker, tracker_process) = PriceTracker::start(
+            &settings.price_tracker,
+            task_manager.listener(Stage::Producer),
+        )
+        .await?;
 
         // Heartbeats
         let (heartbeats, heartbeats_join_handle) =
@@ -66,15 +130,22 @@ impl Cmd {
                 .store(report_ingest.clone())
                 .lookback(LookbackBehavior::StartAfter(settings.start_after()))
                 .file_type(FileType::CellHeartbeatIngestReport)
+                .legacy(
+                    settings
+                        .legacy_heartbeats
+                        .clone()
+                        .map(LegacyDecoder::new)
+                        .transpose()?,
+                )
                 .build()?
-                .start(shutdown_listener.clone())
+                .start(task_manager.listener(Stage::Producer))
                 .await?;
 
         let (valid_heartbeats, mut valid_heartbeats_server) = file_sink::FileSinkBuilder::new(
             FileType::ValidatedHeartbeat,
             store_base_path,
             concat!(env!("CARGO_PKG_NAME"), "_heartbeat"),
-            shutdown_listener.clone(),
+            task_manager.listener(Stage::Sink),
         )
         .deposits(Some(file_upload_tx.clone()))
         .auto_commit(false)
@@ -82,12 +153,33 @@ impl Cmd {
         .create()
         .await?;
 
+        let verification_counts = Arc::new(VerificationCounts::default());
+        let heartbeat_partitions = Partitioner::new(
+            pool.clone(),
+            "heartbeats",
+            "truncated_timestamp",
+            &settings.heartbeat_partitions,
+        )?;
         let heartbeat_daemon = HeartbeatDaemon::new(
             pool.clone(),
             gateway_client.clone(),
             heartbeats,
             valid_heartbeats,
-        );
+            settings.max_concurrent_heartbeat_files,
+            settings.epoch_policy(),
+        )
+        .batch_size(settings.heartbeat_batch_size)
+        .partitions(heartbeat_partitions.clone())
+        .verification_counts(verification_counts.clone());
+        let heartbeat_daemon = if settings.incremental_heartbeat_eligibility {
+            heartbeat_daemon.incremental_eligibility(Duration::hours(settings.rewards))
+        } else {
+            heartbeat_daemon
+        };
+        let heartbeat_daemon = match &settings.heartbeat_location {
+            Some(location) => heartbeat_daemon.location_check(LocationCheck::try_from(location)?),
+            None => heartbeat_daemon,
+        };
 
         // Speedtests
         let (speedtests, speedtests_join_handle) =
@@ -96,15 +188,22 @@ impl Cmd {
                 .store(report_ingest.clone())
                 .lookback(LookbackBehavior::StartAfter(settings.start_after()))
                 .file_type(FileType::CellSpeedtestIngestReport)
+                .legacy(
+                    settings
+                        .legacy_speedtests
+                        .clone()
+                        .map(LegacyDecoder::new)
+                        .transpose()?,
+                )
                 .build()?
-                .start(shutdown_listener.clone())
+                .start(task_manager.listener(Stage::Producer))
                 .await?;
 
         let (valid_speedtests, mut valid_speedtests_server) = file_sink::FileSinkBuilder::new(
             FileType::SpeedtestAvg,
             store_base_path,
             concat!(env!("CARGO_PKG_NAME"), "_speedtest_average"),
-            shutdown_listener.clone(),
+            task_manager.listener(Stage::Sink),
         )
         .deposits(Some(file_upload_tx.clone()))
         .auto_commit(false)
@@ -117,7 +216,13 @@ impl Cmd {
             gateway_client.clone(),
             speedtests,
             valid_speedtests,
-        );
+        )
+        .min_speedtests(settings.min_speedtests);
+        let speedtest_service = SpeedtestService::new(pool.clone(), settings.min_speedtests);
+        let listen_addr = settings.listen_addr()?;
+        let correction_keys = settings.correction_keys()?;
+        let correction_service = (!correction_keys.is_empty())
+            .then(|| CorrectionService::new(pool.clone(), correction_keys));
 
         // Mobile rewards
         let reward_period_hours = settings.rewards;
@@ -125,7 +230,18 @@ impl Cmd {
             FileType::MobileRewardShare,
             store_base_path,
             concat!(env!("CARGO_PKG_NAME"), "_radio_reward_shares"),
-            shutdown_listener.clone(),
+            task_manager.listener(Stage::Sink),
+        )
+        .deposits(Some(file_upload_tx.clone()))
+        .auto_commit(false)
+        .create()
+        .await?;
+
+        let (invalid_rewards, mut invalid_rewards_server) = file_sink::FileSinkBuilder::new(
+            FileType::InvalidRadioRewardShare,
+            store_base_path,
+            concat!(env!("CARGO_PKG_NAME"), "_invalid_radio_reward_shares"),
+            task_manager.listener(Stage::Sink),
         )
         .deposits(Some(file_upload_tx.clone()))
         .auto_commit(false)
@@ -136,7 +252,18 @@ impl Cmd {
             FileType::RewardManifest,
             store_base_path,
             concat!(env!("CARGO_PKG_NAME"), "_reward_manifest"),
-            shutdown_listener.clone(),
+            task_manager.listener(Stage::Sink),
+        )
+        .deposits(Some(file_upload_tx.clone()))
+        .auto_commit(false)
+        .create()
+        .await?;
+
+        let (epoch_summaries, mut epoch_summaries_server) = file_sink::FileSinkBuilder::new(
+            FileType::MobileEpochSummary,
+            store_base_path,
+            concat!(env!("CARGO_PKG_NAME"), "_epoch_summary"),
+            task_manager.listener(Stage::Sink),
         )
         .deposits(Some(file_upload_tx.clone()))
         .auto_commit(false)
@@ -148,10 +275,26 @@ impl Cmd {
             Duration::hours(reward_period_hours),
             Duration::minutes(settings.reward_offset_minutes),
             mobile_rewards,
+            invalid_rewards,
             reward_manifests,
             price_tracker,
             settings.disable_discovery_loc_rewards_to_s3,
-        );
+            settings.epoch_policy(),
+            settings.reward_config.clone(),
+            settings.emissions.clone(),
+        )
+        .min_speedtests(settings.min_speedtests)
+        .pricing(settings.dc_pricing)
+        .epoch_summaries(epoch_summaries, verification_counts);
+        let rewarder = if settings.incremental_heartbeat_eligibility {
+            rewarder.incremental_heartbeat_eligibility()
+        } else {
+            rewarder
+        };
+        let rewarder = match reward_canary {
+            Some(canary) => rewarder.canary(canary),
+            None => rewarder,
+        };
 
         // subscriber location
         let (subscriber_location_ingest, subscriber_location_ingest_join_handle) =
@@ -161,7 +304,7 @@ impl Cmd {
                 .lookback(LookbackBehavior::StartAfter(settings.start_after()))
                 .file_type(FileType::SubscriberLocationIngestReport)
                 .build()?
-                .start(shutdown_listener.clone())
+                .start(task_manager.listener(Stage::Producer))
                 .await?;
 
         let (verified_subscriber_location, mut verified_subscriber_location_server) =
@@ -169,7 +312,7 @@ impl Cmd {
                 FileType::VerifiedSubscriberLocationIngestReport,
                 store_base_path,
                 concat!(env!("CARGO_PKG_NAME"), "_verified_subscriber_location"),
-                shutdown_listener.clone(),
+                task_manager.listener(Stage::Sink),
             )
             .deposits(Some(file_upload_tx.clone()))
             .auto_commit(false)
@@ -192,36 +335,139 @@ impl Cmd {
                 .lookback(LookbackBehavior::StartAfter(settings.start_after()))
                 .file_type(FileType::ValidDataTransferSession)
                 .build()?
-                .start(shutdown_listener.clone())
+                .start(task_manager.listener(Stage::Producer))
                 .await?;
 
-        let data_session_ingestor = DataSessionIngestor::new(pool.clone());
-
-        tokio::try_join!(
-            db_join_handle.map_err(Error::from),
-            valid_heartbeats_server.run().map_err(Error::from),
-            valid_speedtests_server.run().map_err(Error::from),
-            mobile_rewards_server.run().map_err(Error::from),
-            file_upload.run(&shutdown_listener).map_err(Error::from),
-            reward_manifests_server.run().map_err(Error::from),
-            verified_subscriber_location_server
-                .run()
-                .map_err(Error::from),
-            subscriber_location_ingestor
-                .run(&shutdown_listener)
-                .map_err(Error::from),
-            data_session_ingestor
-                .run(data_session_ingest, shutdown_listener.clone())
-                .map_err(Error::from),
-            tracker_process.map_err(Error::from),
-            heartbeats_join_handle.map_err(Error::from),
-            speedtests_join_handle.map_err(Error::from),
-            heartbeat_daemon.run(shutdown_listener.clone()),
-            speedtest_daemon.run(shutdown_listener.clone()),
-            rewarder.run(shutdown_listener.clone()),
-            subscriber_location_ingest_join_handle.map_err(anyhow::Error::from),
-            data_session_ingest_join_handle.map_err(anyhow::Error::from),
-        )?;
+        let (valid_data_sessions, mut valid_data_sessions_server) =
+            file_sink::FileSinkBuilder::new(
+                FileType::VerifiedDataTransferSession,
+                store_base_path,
+                concat!(env!("CARGO_PKG_NAME"), "_verified_data_transfer_session"),
+                task_manager.listener(Stage::Sink),
+            )
+            .deposits(Some(file_upload_tx.clone()))
+            .auto_commit(false)
+            .create()
+            .await?;
+
+        let (invalid_data_sessions, mut invalid_data_sessions_server) =
+            file_sink::FileSinkBuilder::new(
+                FileType::InvalidVerifiedDataTransferSession,
+                store_base_path,
+                concat!(
+                    env!("CARGO_PKG_NAME"),
+                    "_invalid_verified_data_transfer_session"
+                ),
+                task_manager.listener(Stage::Sink),
+            )
+            .deposits(Some(file_upload_tx.clone()))
+            .auto_commit(false)
+            .create()
+            .await?;
+
+        let data_session_ingestor = DataSessionIngestor::new(
+            pool.clone(),
+            gateway_client.clone(),
+            valid_data_sessions,
+            invalid_data_sessions,
+        )
+        .pricing(settings.dc_pricing);
+
+        task_manager.add("db_metrics", Stage::Producer, |_| db_join_handle);
+        task_manager.add("db_maintenance", Stage::Producer, |shutdown| {
+            maintenance.run(shutdown)
+        });
+        task_manager.add("heartbeat_partitions", Stage::Producer, |shutdown| {
+            heartbeat_partitions.run(shutdown)
+        });
+        task_manager.add("price_tracker", Stage::Producer, |_| tracker_process);
+        task_manager.add("heartbeat_source", Stage::Producer, |_| {
+            heartbeats_join_handle
+        });
+        task_manager.add("speedtest_source", Stage::Producer, |_| {
+            speedtests_join_handle
+        });
+        task_manager.add("subscriber_location_source", Stage::Producer, |_| {
+            subscriber_location_ingest_join_handle
+        });
+        task_manager.add("data_session_source", Stage::Producer, |_| {
+            data_session_ingest_join_handle
+        });
+        task_manager.add("heartbeats", Stage::Producer, |shutdown| {
+            heartbeat_daemon.run(shutdown)
+        });
+        task_manager.add("speedtests", Stage::Producer, |shutdown| {
+            speedtest_daemon.run(shutdown)
+        });
+        task_manager.add("rewarder", Stage::Producer, |shutdown| {
+            rewarder.run(shutdown)
+        });
+        if let Some(listen_addr) = listen_addr {
+            let tls = settings.tls.clone();
+            task_manager.add(
+                "speedtest_service",
+                Stage::Producer,
+                |shutdown| async move {
+                    tracing::info!("serving speedtest averages on {listen_addr}");
+                    let router = tonic::transport::Server::builder()
+                        .add_service(speedtest_service)
+                        .add_optional_service(correction_service);
+                    match tls {
+                        Some(tls) => {
+                            let incoming =
+                                tls::incoming(&tls, listen_addr, shutdown.clone()).await?;
+                            router
+                                .serve_with_incoming_shutdown(incoming, shutdown)
+                                .await?
+                        }
+                        None => router.serve_with_shutdown(listen_addr, shutdown).await?,
+                    }
+                    Ok::<(), anyhow::Error>(())
+                },
+            );
+        }
+        task_manager.add(
+            "subscriber_location",
+            Stage::Producer,
+            |shutdown| async move { subscriber_location_ingestor.run(&shutdown).await },
+        );
+        task_manager.add("data_sessions", Stage::Producer, |shutdown| {
+            data_session_ingestor.run(data_session_ingest, shutdown)
+        });
+        task_manager.add("valid_heartbeats_sink", Stage::Sink, |_| async move {
+            valid_heartbeats_server.run().await
+        });
+        task_manager.add("valid_speedtests_sink", Stage::Sink, |_| async move {
+            valid_speedtests_server.run().await
+        });
+        task_manager.add("mobile_rewards_sink", Stage::Sink, |_| async move {
+            mobile_rewards_server.run().await
+        });
+        task_manager.add("invalid_rewards_sink", Stage::Sink, |_| async move {
+            invalid_rewards_server.run().await
+        });
+        task_manager.add("reward_manifests_sink", Stage::Sink, |_| async move {
+            reward_manifests_server.run().await
+        });
+        task_manager.add("epoch_summaries_sink", Stage::Sink, |_| async move {
+            epoch_summaries_server.run().await
+        });
+        task_manager.add(
+            "verified_subscriber_location_sink",
+            Stage::Sink,
+            |_| async move { verified_subscriber_location_server.run().await },
+        );
+        task_manager.add("valid_data_sessions_sink", Stage::Sink, |_| async move {
+            valid_data_sessions_server.run().await
+        });
+        task_manager.add("invalid_data_sessions_sink", Stage::Sink, |_| async move {
+            invalid_data_sessions_server.run().await
+        });
+        task_manager.add("file_upload", Stage::Upload, |shutdown| async move {
+            file_upload.run(&shutdown).await
+        });
+
+        task_manager.run(shutdown_listener).await?;
 
         tracing::info!("Shutting down verifier server");
 
@@ -0,0 +1,259 @@
+//! Signed grpc endpoint recording reward corrections, applied to the next
+//! epoch rewarded. Like the speedtest endpoint the service is hand written on
+//! top of the tonic primitives with its messages defined here.
+//!
+//! Requests are signed by one of the correction keys of the settings within
+//! the last five minutes. The hash of the request without its signature is
+//! stored with the correction, so a replayed request is rejected instead of
+//! recorded again, even with an altered signature.
+
+use crate::corrections::{self, Correction};
+use chrono::{TimeZone, Utc};
+use helium_crypto::{PublicKey, PublicKeyBinary, Verify};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use std::{collections::HashSet, convert::Infallible, sync::Arc};
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const SERVICE_NAME: &str = "helium.mobile_verifier.corrections";
+const ADD_PATH: &str = "/helium.mobile_verifier.corrections/add";
+const LIST_PATH: &str = "/helium.mobile_verifier.corrections/list";
+
+/// Requests signed longer ago, or further in the future, are rejected
+const MAX_SIGNATURE_AGE_SECS: i64 = 5 * 60;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AddCorrectionReqV1 {
+    #[prost(bytes = "vec", tag = "1")]
+    pub hotspot_key: Vec<u8>,
+    /// Bones to pay the hotspot, or to withhold from its rewards when
+    /// negative
+    #[prost(sint64, tag = "2")]
+    pub amount: i64,
+    #[prost(string, tag = "3")]
+    pub reason: String,
+    /// Milliseconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "4")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "5")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AddCorrectionResV1 {
+    #[prost(int64, tag = "1")]
+    pub id: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListCorrectionsReqV1 {
+    /// Milliseconds since the epoch of when the request was signed
+    #[prost(uint64, tag = "1")]
+    pub timestamp: u64,
+    #[prost(bytes = "vec", tag = "2")]
+    pub signer: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: Vec<u8>,
+}
+
+/// The corrections to be applied to the next epoch
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListCorrectionsResV1 {
+    #[prost(message, repeated, tag = "1")]
+    pub corrections: Vec<CorrectionV1>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CorrectionV1 {
+    #[prost(int64, tag = "1")]
+    pub id: i64,
+    #[prost(bytes = "vec", tag = "2")]
+    pub hotspot_key: Vec<u8>,
+    #[prost(sint64, tag = "3")]
+    pub amount: i64,
+    #[prost(string, tag = "4")]
+    pub reason: String,
+    /// B58 encoded key recording the correction, empty for remainders carried
+    /// from an earlier epoch
+    #[prost(string, tag = "5")]
+    pub signer: String,
+    /// Seconds since the epoch of when the correction was recorded
+    #[prost(int64, tag = "6")]
+    pub created_at: i64,
+}
+
+impl From<Correction> for CorrectionV1 {
+    fn from(correction: Correction) -> Self {
+        Self {
+            id: correction.id,
+            hotspot_key: correction.hotspot_key.into(),
+            amount: correction.amount,
+            reason: correction.reason,
+            signer: correction.signer.unwrap_or_default(),
+            created_at: correction.created_at.timestamp(),
+        }
+    }
+}
+
+/// The signer of a request if its signature, over the request without the
+/// signature, is valid and recent
+fn verify_signed<M: Message + Clone>(
+    request: &M,
+    signer: &[u8],
+    signature: &[u8],
+    timestamp: u64,
+    unsigned: impl FnOnce(&mut M),
+) -> Result<PublicKey, Status> {
+    let signer =
+        PublicKey::try_from(signer).map_err(|_| Status::invalid_argument("invalid signer"))?;
+    let mut request = request.clone();
+    unsigned(&mut request);
+    signer
+        .verify(&request.encode_to_vec(), signature)
+        .map_err(|_| Status::unauthenticated("invalid signature"))?;
+    let signed_at = Utc
+        .timestamp_millis_opt(timestamp as i64)
+        .single()
+        .ok_or_else(|| Status::invalid_argument("invalid timestamp"))?;
+    if (Utc::now() - signed_at).num_seconds().abs() > MAX_SIGNATURE_AGE_SECS {
+        return Err(Status::unauthenticated("request expired"));
+    }
+    Ok(signer)
+}
+
+#[derive(Clone)]
+pub struct CorrectionService {
+    pool: Pool<Postgres>,
+    correction_keys: Arc<HashSet<PublicKey>>,
+}
+
+impl CorrectionService {
+    pub fn new(pool: Pool<Postgres>, correction_keys: HashSet<PublicKey>) -> Self {
+        Self {
+            pool,
+            correction_keys: Arc::new(correction_keys),
+        }
+    }
+
+    fn authorize(&self, signer: PublicKey) -> Result<PublicKey, Status> {
+        if !self.correction_keys.contains(&signer) {
+            return Err(Status::permission_denied("signer is not a correction key"));
+        }
+        Ok(signer)
+    }
+
+    pub async fn add(&self, request: AddCorrectionReqV1) -> Result<AddCorrectionResV1, Status> {
+        let signer = verify_signed(
+            &request,
+            &request.signer,
+            &request.signature,
+            request.timestamp,
+            |request| request.signature = vec![],
+        )
+        .and_then(|signer| self.authorize(signer))?;
+        PublicKey::try_from(request.hotspot_key.as_slice())
+            .map_err(|_| Status::invalid_argument("invalid hotspot_key"))?;
+        if request.amount == 0 {
+            return Err(Status::invalid_argument("amount must not be 0"));
+        }
+        // Withheld amounts are negated back, which the smallest i64 overflows
+        if request.amount == i64::MIN {
+            return Err(Status::invalid_argument("amount out of range"));
+        }
+        if request.reason.trim().is_empty() {
+            return Err(Status::invalid_argument("reason is required"));
+        }
+
+        let request_hash = Sha256::digest(
+            AddCorrectionReqV1 {
+                signature: vec![],
+                ..request.clone()
+            }
+            .encode_to_vec(),
+        );
+        let hotspot_key = PublicKeyBinary::from(request.hotspot_key);
+        let id = corrections::record(
+            &self.pool,
+            &hotspot_key,
+            request.amount,
+            &request.reason,
+            &signer.to_string(),
+            &request_hash,
+        )
+        .await
+        .map_err(|err| {
+            tracing::error!("failed to record correction of {hotspot_key}: {err}");
+            Status::internal("failed to record correction")
+        })?
+        .ok_or_else(|| Status::already_exists("correction already recorded"))?;
+        tracing::warn!(
+            id,
+            %signer,
+            %hotspot_key,
+            amount = request.amount,
+            reason = request.reason,
+            "reward correction recorded"
+        );
+        Ok(AddCorrectionResV1 { id })
+    }
+
+    pub async fn list(
+        &self,
+        request: ListCorrectionsReqV1,
+    ) -> Result<ListCorrectionsResV1, Status> {
+        verify_signed(
+            &request,
+            &request.signer,
+            &request.signature,
+            request.timestamp,
+            |request| request.signature = vec![],
+        )
+        .and_then(|signer| self.authorize(signer))?;
+        let corrections = corrections::pending(&self.pool).await.map_err(|err| {
+            tracing::error!("failed to fetch corrections: {err}");
+            Status::internal("failed to fetch corrections")
+        })?;
+        Ok(ListCorrectionsResV1 {
+            corrections: corrections.into_iter().map(CorrectionV1::from).collect(),
+        })
+    }
+}
+
+impl NamedService for CorrectionService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for CorrectionService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            ADD_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.add(request).await })
+            }
+            LIST_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.list(request).await })
+            }
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
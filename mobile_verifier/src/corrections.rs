@@ -0,0 +1,423 @@
+//! Corrections of the rewards of hotspots after an epoch was found to be
+//! rewarded wrongly.
+//!
+//! Corrections are recorded through the correction service and applied to
+//! the next epoch rewarded. The corrections of a hotspot are added up: a
+//! positive amount is paid as an extra gateway reward, a negative amount is
+//! withheld from the radio and gateway rewards of the hotspot in the epoch.
+//!
+//! Positive corrections are paid from the poc and dc pool of the epoch, before
+//! it is shared among the radios, and take at most a tenth of the pool so that
+//! a large correction cannot crowd out the rewards of the epoch. What is
+//! withheld by negative corrections is not redistributed.
+//! What the rewards of the epoch do not cover, or the pool does not fund, is
+//! carried to the next epoch as a new correction. The corrections applied are
+//! itemized in the reward manifest of the epoch.
+
+use chrono::{DateTime, Utc};
+use file_store::{reward_manifest::RewardCorrectionV1, traits::TimestampEncode};
+use helium_crypto::PublicKeyBinary;
+use helium_proto::services::poc_mobile::{
+    self as proto, mobile_reward_share::Reward as ProtoReward, MobileRewardShare,
+};
+use reward_scheduler::reward_math::to_bones;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use sqlx::{PgExecutor, Postgres, Transaction};
+use std::{collections::HashMap, ops::Range};
+
+/// Share of the poc and dc pool of an epoch that positive corrections can take
+const MAX_CORRECTION_REWARDS_PERCENT: Decimal = dec!(0.1);
+
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Correction {
+    pub id: i64,
+    pub hotspot_key: PublicKeyBinary,
+    pub amount: i64,
+    pub reason: String,
+    pub signer: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Record a correction to be applied to the next epoch, returning its id.
+/// None if a correction was already recorded by the same request, hashed
+/// without its signature, as the request was replayed
+pub async fn record(
+    exec: impl PgExecutor<'_>,
+    hotspot_key: &PublicKeyBinary,
+    amount: i64,
+    reason: &str,
+    signer: &str,
+    request_hash: &[u8],
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO reward_corrections (hotspot_key, amount, reason, signer, request_hash)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (request_hash) DO NOTHING
+        RETURNING id
+        "#,
+    )
+    .bind(hotspot_key)
+    .bind(amount)
+    .bind(reason)
+    .bind(signer)
+    .bind(request_hash)
+    .fetch_optional(exec)
+    .await
+}
+
+/// The corrections not applied to an epoch yet, oldest first
+pub async fn pending(exec: impl PgExecutor<'_>) -> Result<Vec<Correction>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT id, hotspot_key, amount, reason, signer, created_at
+        FROM reward_corrections
+        WHERE applied_to IS NULL
+        ORDER BY id
+        "#,
+    )
+    .fetch_all(exec)
+    .await
+}
+
+/// Bones of the poc and dc pool of an epoch funding positive corrections: at
+/// most a tenth of the pool, out of what the data transfer rewards leave
+pub fn correction_budget(poc_and_dc: Decimal, transfer_rewards_sum: Decimal) -> u64 {
+    to_bones(poc_and_dc * MAX_CORRECTION_REWARDS_PERCENT)
+        .min(to_bones(poc_and_dc).saturating_sub(to_bones(transfer_rewards_sum)))
+}
+
+#[derive(Debug, Default)]
+struct HotspotCorrection {
+    ids: Vec<i64>,
+    amount: i64,
+    withheld: u64,
+    paid: u64,
+}
+
+impl HotspotCorrection {
+    fn to_withhold(&self) -> u64 {
+        if self.amount < 0 {
+            self.amount.unsigned_abs() - self.withheld
+        } else {
+            0
+        }
+    }
+
+    fn to_pay(&self) -> u64 {
+        u64::try_from(self.amount).unwrap_or(0) - self.paid
+    }
+
+    fn applied(&self) -> i64 {
+        if self.amount > 0 {
+            self.paid as i64
+        } else {
+            -(self.withheld as i64)
+        }
+    }
+}
+
+/// The pending corrections of a reward pass, by hotspot
+#[derive(Debug, Default)]
+pub struct Corrections {
+    hotspots: HashMap<PublicKeyBinary, HotspotCorrection>,
+}
+
+impl Corrections {
+    pub fn new(corrections: impl IntoIterator<Item = Correction>) -> Self {
+        let mut hotspots = HashMap::<_, HotspotCorrection>::new();
+        for correction in corrections {
+            let hotspot = hotspots.entry(correction.hotspot_key).or_default();
+            // A correction overflowing the total of its hotspot is left
+            // pending until the ones before it were applied
+            let Some(amount) = hotspot.amount.checked_add(correction.amount) else {
+                tracing::warn!(id = correction.id, "correction left pending on overflow");
+                continue;
+            };
+            hotspot.ids.push(correction.id);
+            hotspot.amount = amount;
+        }
+        Self { hotspots }
+    }
+
+    pub async fn pending(exec: impl PgExecutor<'_>) -> Result<Self, sqlx::Error> {
+        Ok(Self::new(pending(exec).await?))
+    }
+
+    /// Pay the positive corrections from the budget, in order of hotspot,
+    /// returning the bones taken from it
+    pub fn fund(&mut self, budget: u64) -> u64 {
+        let mut hotspots: Vec<_> = self.hotspots.iter_mut().collect();
+        hotspots.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+        let mut left = budget;
+        for (_, hotspot) in hotspots {
+            let paid = hotspot.to_pay().min(left);
+            hotspot.paid += paid;
+            left -= paid;
+        }
+        budget - left
+    }
+
+    /// Withhold what is left of the negative corrections of the hotspot from
+    /// a radio or gateway reward share
+    pub fn withhold(&mut self, mut share: MobileRewardShare) -> MobileRewardShare {
+        let (hotspot_key, reward) = match &mut share.reward {
+            Some(ProtoReward::RadioReward(proto::RadioReward {
+                hotspot_key,
+                poc_reward,
+                ..
+            })) => (hotspot_key, poc_reward),
+            Some(ProtoReward::GatewayReward(proto::GatewayReward {
+                hotspot_key,
+                dc_transfer_reward,
+            })) => (hotspot_key, dc_transfer_reward),
+            _ => return share,
+        };
+        if let Some(hotspot) = self
+            .hotspots
+            .get_mut(&PublicKeyBinary::from(hotspot_key.clone()))
+        {
+            let withheld = hotspot.to_withhold().min(*reward);
+            *reward -= withheld;
+            hotspot.withheld += withheld;
+        }
+        share
+    }
+
+    /// The gateway reward shares paying the funded positive corrections, and
+    /// the corrections as applied to the epoch
+    pub fn finish(self, epoch: &Range<DateTime<Utc>>) -> (Vec<MobileRewardShare>, Applied) {
+        let mut shares = vec![];
+        let mut applied = Applied::default();
+        let mut hotspots: Vec<_> = self.hotspots.into_iter().collect();
+        hotspots.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+        for (hotspot_key, hotspot) in hotspots {
+            if hotspot.paid > 0 {
+                shares.push(MobileRewardShare {
+                    start_period: epoch.start.encode_timestamp(),
+                    end_period: epoch.end.encode_timestamp(),
+                    reward: Some(ProtoReward::GatewayReward(proto::GatewayReward {
+                        hotspot_key: hotspot_key.clone().into(),
+                        dc_transfer_reward: hotspot.paid,
+                    })),
+                });
+            }
+            let carried = hotspot.amount - hotspot.applied();
+            if carried != 0 {
+                applied
+                    .carried
+                    .push((hotspot_key.clone(), carried, hotspot.ids.clone()));
+            }
+            applied.items.push(RewardCorrectionV1 {
+                hotspot_key: hotspot_key.into(),
+                amount: hotspot.amount,
+                applied: hotspot.applied(),
+                ids: hotspot.ids,
+            });
+        }
+        (shares, applied)
+    }
+}
+
+/// The corrections applied to an epoch
+#[derive(Debug, Default)]
+pub struct Applied {
+    pub items: Vec<RewardCorrectionV1>,
+    // Remainders carried to the next epoch with the corrections they are
+    // left of
+    carried: Vec<(PublicKeyBinary, i64, Vec<i64>)>,
+}
+
+impl Applied {
+    /// Mark the corrections applied to the epoch ending at `epoch_end` and
+    /// record the remainders as corrections of the next epoch
+    pub async fn save(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        epoch_end: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let ids: Vec<i64> = self
+            .items
+            .iter()
+            .flat_map(|item| item.ids.iter().copied())
+            .collect();
+        sqlx::query("UPDATE reward_corrections SET applied_to = $1 WHERE id = ANY($2)")
+            .bind(epoch_end)
+            .bind(&ids)
+            .execute(&mut *transaction)
+            .await?;
+        for (hotspot_key, amount, ids) in &self.carried {
+            sqlx::query(
+                "INSERT INTO reward_corrections (hotspot_key, amount, reason) VALUES ($1, $2, $3)",
+            )
+            .bind(hotspot_key)
+            .bind(amount)
+            .bind(format!(
+                "remainder of corrections {ids:?} carried from the epoch ending {epoch_end}"
+            ))
+            .execute(&mut *transaction)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn correction(id: i64, hotspot: u8, amount: i64) -> Correction {
+        Correction {
+            id,
+            hotspot_key: PublicKeyBinary::from(vec![hotspot]),
+            amount,
+            reason: "test".to_string(),
+            signer: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn radio_reward(hotspot: u8, poc_reward: u64) -> MobileRewardShare {
+        MobileRewardShare {
+            start_period: 0,
+            end_period: 0,
+            reward: Some(ProtoReward::RadioReward(proto::RadioReward {
+                hotspot_key: vec![hotspot],
+                poc_reward,
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn poc_reward(share: &MobileRewardShare) -> u64 {
+        match &share.reward {
+            Some(ProtoReward::RadioReward(reward)) => reward.poc_reward,
+            _ => panic!("not a radio reward"),
+        }
+    }
+
+    #[test]
+    fn negative_corrections_are_withheld_and_carried() {
+        let mut corrections = Corrections::new([
+            correction(1, 1, -100),
+            correction(2, 1, -50),
+            correction(3, 2, -10),
+        ]);
+        assert_eq!(poc_reward(&corrections.withhold(radio_reward(1, 100))), 0);
+        assert_eq!(poc_reward(&corrections.withhold(radio_reward(1, 30))), 0);
+        assert_eq!(poc_reward(&corrections.withhold(radio_reward(2, 30))), 20);
+        assert_eq!(poc_reward(&corrections.withhold(radio_reward(2, 30))), 30);
+        assert_eq!(poc_reward(&corrections.withhold(radio_reward(3, 30))), 30);
+
+        let epoch = Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(86400, 0).unwrap();
+        let (shares, applied) = corrections.finish(&epoch);
+        assert!(shares.is_empty());
+        assert_eq!(
+            applied.items,
+            vec![
+                RewardCorrectionV1 {
+                    hotspot_key: vec![1],
+                    ids: vec![1, 2],
+                    amount: -150,
+                    applied: -130,
+                },
+                RewardCorrectionV1 {
+                    hotspot_key: vec![2],
+                    ids: vec![3],
+                    amount: -10,
+                    applied: -10,
+                },
+            ]
+        );
+        assert_eq!(
+            applied.carried,
+            vec![(PublicKeyBinary::from(vec![1]), -20, vec![1, 2])]
+        );
+    }
+
+    #[test]
+    fn positive_corrections_are_paid_as_gateway_rewards() {
+        let mut corrections = Corrections::new([correction(1, 1, 100), correction(2, 1, -40)]);
+        assert_eq!(corrections.fund(1000), 60);
+        assert_eq!(poc_reward(&corrections.withhold(radio_reward(1, 100))), 100);
+
+        let epoch = Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(86400, 0).unwrap();
+        let (shares, applied) = corrections.finish(&epoch);
+        assert_eq!(
+            shares,
+            vec![MobileRewardShare {
+                start_period: 0,
+                end_period: 86400,
+                reward: Some(ProtoReward::GatewayReward(proto::GatewayReward {
+                    hotspot_key: vec![1],
+                    dc_transfer_reward: 60,
+                })),
+            }]
+        );
+        assert_eq!(applied.items[0].applied, 60);
+        assert!(applied.carried.is_empty());
+    }
+
+    #[test]
+    fn positive_corrections_are_paid_within_the_budget() {
+        let mut corrections = Corrections::new([correction(1, 1, 100), correction(2, 2, 50)]);
+        assert_eq!(corrections.fund(120), 120);
+
+        let epoch = Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(86400, 0).unwrap();
+        let (shares, applied) = corrections.finish(&epoch);
+        let paid: Vec<(Vec<u8>, u64)> = shares
+            .into_iter()
+            .map(|share| match share.reward {
+                Some(ProtoReward::GatewayReward(reward)) => {
+                    (reward.hotspot_key, reward.dc_transfer_reward)
+                }
+                _ => panic!("not a gateway reward"),
+            })
+            .collect();
+        assert_eq!(paid, vec![(vec![1], 100), (vec![2], 20)]);
+        assert_eq!(
+            applied.carried,
+            vec![(PublicKeyBinary::from(vec![2]), 30, vec![2])]
+        );
+    }
+
+    #[test]
+    fn unfunded_positive_corrections_are_carried() {
+        let corrections = Corrections::new([correction(1, 1, 100)]);
+
+        let epoch = Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(86400, 0).unwrap();
+        let (shares, applied) = corrections.finish(&epoch);
+        assert!(shares.is_empty());
+        assert_eq!(applied.items[0].applied, 0);
+        assert_eq!(
+            applied.carried,
+            vec![(PublicKeyBinary::from(vec![1]), 100, vec![1])]
+        );
+    }
+
+    #[test]
+    fn corrections_overflowing_their_hotspot_are_left_pending() {
+        let mut corrections = Corrections::new([
+            correction(1, 1, i64::MAX),
+            correction(2, 1, 1),
+            correction(3, 1, -1),
+        ]);
+        corrections.fund(u64::MAX);
+
+        let epoch = Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(86400, 0).unwrap();
+        let (_, applied) = corrections.finish(&epoch);
+        assert_eq!(applied.items[0].ids, vec![1, 3]);
+        assert_eq!(applied.items[0].amount, i64::MAX - 1);
+        assert_eq!(applied.items[0].applied, i64::MAX - 1);
+    }
+
+    #[test]
+    fn corrections_take_at_most_a_tenth_of_the_pool() {
+        assert_eq!(correction_budget(dec!(1000), dec!(0)), 100);
+        assert_eq!(correction_budget(dec!(1000), dec!(400)), 100);
+        assert_eq!(correction_budget(dec!(1000), dec!(950)), 50);
+        assert_eq!(correction_budget(dec!(1000), dec!(1200)), 0);
+    }
+}
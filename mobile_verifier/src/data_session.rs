@@ -1,23 +1,54 @@
+use crate::gateway_resolver::{GatewayResolver, GatewayResolverError};
 use chrono::{DateTime, Utc};
-use file_store::{file_info_poller::FileInfoStream, mobile_transfer::ValidDataTransferSession};
+use dc_pricing::DcPricing;
+use file_store::{
+    file_info_poller::FileInfoStream,
+    file_sink::FileSinkClient,
+    mobile_transfer::{
+        InvalidDataTransferSessionReason, InvalidDataTransferSessionV1, ValidDataTransferSession,
+    },
+};
 use futures::{
     stream::{Stream, StreamExt, TryStreamExt},
     TryFutureExt,
 };
 use helium_crypto::PublicKeyBinary;
+use helium_proto::services::packet_verifier as proto;
+use mobile_config::gateway_info::GatewayInfoResolver;
 use sqlx::{PgPool, Postgres, Transaction};
 use std::{collections::HashMap, ops::Range};
 use tokio::sync::mpsc::Receiver;
 
 pub struct DataSessionIngestor {
     pub pool: PgPool,
+    gateway_client: GatewayResolver,
+    valid_sessions: FileSinkClient,
+    invalid_sessions: FileSinkClient,
+    pricing: DcPricing,
 }
 
 pub type HotspotMap = HashMap<PublicKeyBinary, u64>;
 
 impl DataSessionIngestor {
-    pub fn new(pool: sqlx::Pool<sqlx::Postgres>) -> Self {
-        Self { pool }
+    pub fn new(
+        pool: sqlx::Pool<sqlx::Postgres>,
+        gateway_client: GatewayResolver,
+        valid_sessions: FileSinkClient,
+        invalid_sessions: FileSinkClient,
+    ) -> Self {
+        Self {
+            pool,
+            gateway_client,
+            valid_sessions,
+            invalid_sessions,
+            pricing: DcPricing::mobile(),
+        }
+    }
+
+    /// Check the data credits charged for the sessions with the given pricing
+    /// instead of the default mobile pricing
+    pub fn pricing(self, pricing: DcPricing) -> Self {
+        Self { pricing, ..self }
     }
 
     pub async fn run(
@@ -54,21 +85,75 @@ impl DataSessionIngestor {
         );
         let mut transaction = self.pool.begin().await?;
         let file_ts = file_info_stream.file_info.timestamp;
-        file_info_stream
-            .into_stream(&mut transaction)
-            .await?
-            .map(anyhow::Ok)
-            .try_fold(transaction, |mut transaction, report| async move {
-                let data_session = HotspotDataSession::from_valid_data_session(report, file_ts);
-                data_session.save(&mut transaction).await?;
-                metrics::increment_counter!("oracles_mobile_verifier_ingest_hotspot_data_session");
-                Ok(transaction)
-            })
-            .await?
-            .commit()
-            .await?;
+        let (mut reports, completion) = file_info_stream.into_stream(&mut transaction).await?;
+
+        while let Some(report) = reports.next().await {
+            match self.validate(&report).await? {
+                None => {
+                    self.valid_sessions
+                        .write(proto::ValidDataTransferSession::from(report.clone()), &[])
+                        .await?;
+                    HotspotDataSession::from_valid_data_session(report, file_ts)
+                        .save(&mut transaction)
+                        .await?;
+                    metrics::increment_counter!(
+                        "oracles_mobile_verifier_ingest_hotspot_data_session"
+                    );
+                }
+                Some(reason) => {
+                    self.invalid_sessions
+                        .write(
+                            InvalidDataTransferSessionV1::new(report, reason, Utc::now()),
+                            &[("reason", reason.as_str())],
+                        )
+                        .await?;
+                    metrics::increment_counter!(
+                        "oracles_mobile_verifier_ingest_invalid_hotspot_data_session",
+                        "reason" => reason.as_str()
+                    );
+                }
+            }
+        }
+
+        self.valid_sessions.commit().await?;
+        self.invalid_sessions.commit().await?;
+        completion.commit(transaction).await?;
         Ok(())
     }
+
+    /// Reason the session is not rewarded, if any. Payers have already been
+    /// debited by the packet verifier, so the session must be for a known
+    /// hotspot and its data credits must cover the transferred bytes.
+    async fn validate(
+        &self,
+        session: &ValidDataTransferSession,
+    ) -> Result<Option<InvalidDataTransferSessionReason>, GatewayResolverError> {
+        if !self.is_known_gateway(&session.pub_key).await? {
+            return Ok(Some(InvalidDataTransferSessionReason::GatewayNotFound));
+        }
+        if !charged_in_full(session, &self.pricing) {
+            return Ok(Some(InvalidDataTransferSessionReason::InsufficientDcs));
+        }
+        Ok(None)
+    }
+
+    async fn is_known_gateway(
+        &self,
+        pub_key: &PublicKeyBinary,
+    ) -> Result<bool, GatewayResolverError> {
+        Ok(self
+            .gateway_client
+            .clone()
+            .resolve_gateway_info(pub_key)
+            .await?
+            .is_some())
+    }
+}
+
+/// Whether the data credits of the session pay for all of its bytes
+fn charged_in_full(session: &ValidDataTransferSession, pricing: &DcPricing) -> bool {
+    let bytes = session.upload_bytes.saturating_add(session.download_bytes);
+    session.num_dcs >= pricing.bytes_to_dc(bytes)
 }
 
 #[derive(sqlx::FromRow)]
@@ -152,3 +237,31 @@ pub async fn clear_hotspot_data_sessions(
         .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(upload_bytes: u64, download_bytes: u64, num_dcs: u64) -> ValidDataTransferSession {
+        let now = Utc::now();
+        ValidDataTransferSession {
+            pub_key: vec![1].into(),
+            payer: vec![2].into(),
+            upload_bytes,
+            download_bytes,
+            num_dcs,
+            first_timestamp: now,
+            last_timestamp: now,
+        }
+    }
+
+    #[test]
+    fn sessions_must_be_charged_for_all_bytes() {
+        let pricing = DcPricing::mobile();
+        assert!(charged_in_full(&session(10_000, 10_000, 1), &pricing));
+        assert!(charged_in_full(&session(10_000, 10_001, 2), &pricing));
+        assert!(!charged_in_full(&session(10_000, 10_001, 1), &pricing));
+        assert!(!charged_in_full(&session(0, 0, 0), &pricing));
+        assert!(charged_in_full(&session(0, 0, 1), &pricing));
+    }
+}
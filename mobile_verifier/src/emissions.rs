@@ -0,0 +1,253 @@
+//! Emission schedule of the mobile subnetwork
+//!
+//! Tokens are emitted at a constant rate from genesis, halving every
+//! `halving_interval_days` when set. The pool of an epoch is the amount
+//! emitted during the epoch, taking the rate before and after a halving
+//! within the epoch into account, and is split between the reward types by
+//! their configured percentages.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Deserialize;
+use std::ops::Range;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmissionSchedule {
+    /// Start of the emissions in seconds since the unix epoch. Nothing is
+    /// emitted before genesis. (Default is 0)
+    #[serde(default)]
+    pub genesis: u64,
+    /// Bones emitted per 365 days until the first halving. (Default is
+    /// 60_000_000_000_000_000)
+    #[serde(default = "default_yearly_emissions")]
+    pub yearly_emissions: Decimal,
+    /// Days between halvings of the emission rate, counted from genesis. The
+    /// rate is never halved when not set
+    pub halving_interval_days: Option<u64>,
+    /// Share of the emissions for poc and data transfer rewards. (Default is
+    /// 0.6)
+    #[serde(default = "default_poc_and_dc_percent")]
+    pub poc_and_dc_percent: Decimal,
+    /// Share of the emissions for mapper rewards. (Default is 0.2)
+    #[serde(default = "default_mappers_percent")]
+    pub mappers_percent: Decimal,
+    /// Share of the emissions for service provider rewards. (Default is 0.1)
+    #[serde(default = "default_service_providers_percent")]
+    pub service_providers_percent: Decimal,
+}
+
+pub fn default_yearly_emissions() -> Decimal {
+    dec!(60_000_000_000_000_000)
+}
+
+pub fn default_poc_and_dc_percent() -> Decimal {
+    dec!(0.6)
+}
+
+pub fn default_mappers_percent() -> Decimal {
+    dec!(0.2)
+}
+
+pub fn default_service_providers_percent() -> Decimal {
+    dec!(0.1)
+}
+
+impl Default for EmissionSchedule {
+    fn default() -> Self {
+        Self {
+            genesis: 0,
+            yearly_emissions: default_yearly_emissions(),
+            halving_interval_days: None,
+            poc_and_dc_percent: default_poc_and_dc_percent(),
+            mappers_percent: default_mappers_percent(),
+            service_providers_percent: default_service_providers_percent(),
+        }
+    }
+}
+
+impl EmissionSchedule {
+    pub fn genesis(&self) -> DateTime<Utc> {
+        Utc.timestamp_opt(self.genesis as i64, 0)
+            .single()
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+
+    fn halving_interval(&self) -> Option<Duration> {
+        self.halving_interval_days
+            .filter(|days| *days > 0)
+            .map(|days| Duration::days(days as i64))
+    }
+
+    /// Problems with the schedule, reported by the settings validation
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if Utc.timestamp_opt(self.genesis as i64, 0).single().is_none() {
+            problems.push(format!(
+                "emissions.genesis is not a valid timestamp, got {}",
+                self.genesis
+            ));
+        }
+        if self.yearly_emissions.is_sign_negative() {
+            problems.push(format!(
+                "emissions.yearly_emissions must not be negative, got {}",
+                self.yearly_emissions
+            ));
+        }
+        if self.halving_interval_days == Some(0) {
+            problems.push("emissions.halving_interval_days must be at least 1".to_string());
+        }
+        let percents = [
+            ("poc_and_dc_percent", self.poc_and_dc_percent),
+            ("mappers_percent", self.mappers_percent),
+            ("service_providers_percent", self.service_providers_percent),
+        ];
+        for (name, percent) in percents {
+            if percent.is_sign_negative() {
+                problems.push(format!(
+                    "emissions.{name} must not be negative, got {percent}"
+                ));
+            }
+        }
+        let total_percent: Decimal = percents.iter().map(|(_, percent)| percent).sum();
+        if total_percent > Decimal::ONE {
+            problems.push(format!(
+                "emissions percentages must not add up to more than 1, got {total_percent}"
+            ));
+        }
+        problems
+    }
+
+    /// Bones emitted during the epoch
+    pub fn total(&self, epoch: &Range<DateTime<Utc>>) -> Decimal {
+        let genesis = self.genesis();
+        let mut start = epoch.start.max(genesis);
+        let Some(interval) = self.halving_interval() else {
+            return self.emitted(0, epoch.end - start);
+        };
+
+        let mut total = Decimal::ZERO;
+        while start < epoch.end {
+            let halvings = (start - genesis).num_seconds() / interval.num_seconds();
+            let next_halving = genesis + interval * (halvings + 1) as i32;
+            let end = next_halving.min(epoch.end);
+            total += self.emitted(halvings, end - start);
+            start = end;
+        }
+        total
+    }
+
+    /// Bones emitted over a duration after the given number of halvings
+    fn emitted(&self, halvings: i64, duration: Duration) -> Decimal {
+        if duration <= Duration::zero() || halvings >= 64 {
+            return Decimal::ZERO;
+        }
+        let emitted =
+            (self.yearly_emissions / dec!(365) / Decimal::from(Duration::hours(24).num_seconds()))
+                * Decimal::from(duration.num_seconds());
+        emitted / Decimal::from(1_u64 << halvings)
+    }
+
+    pub fn poc_and_dc(&self, epoch: &Range<DateTime<Utc>>) -> Decimal {
+        self.total(epoch) * self.poc_and_dc_percent
+    }
+
+    pub fn mappers(&self, epoch: &Range<DateTime<Utc>>) -> Decimal {
+        self.total(epoch) * self.mappers_percent
+    }
+
+    pub fn service_providers(&self, epoch: &Range<DateTime<Utc>>) -> Decimal {
+        self.total(epoch) * self.service_providers_percent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dt(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+    }
+
+    /// Decimal division leaves dust in the last digits
+    fn rounded(amount: Decimal) -> Decimal {
+        amount.round_dp(9)
+    }
+
+    fn halving_schedule() -> EmissionSchedule {
+        EmissionSchedule {
+            genesis: dt(2023, 8, 1, 0).timestamp() as u64,
+            yearly_emissions: dec!(365_000),
+            halving_interval_days: Some(730),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn default_schedule_matches_the_fixed_pools() {
+        let schedule = EmissionSchedule::default();
+        let day = dt(2023, 9, 1, 0)..dt(2023, 9, 2, 0);
+        assert_eq!(schedule.total(&day).trunc(), dec!(164_383_561_643_835));
+        assert_eq!(schedule.mappers(&day).trunc(), dec!(32_876_712_328_767));
+        assert_eq!(
+            schedule.service_providers(&day).trunc(),
+            dec!(16_438_356_164_383)
+        );
+        assert_eq!(
+            schedule
+                .poc_and_dc(&(dt(2023, 9, 1, 0)..dt(2023, 9, 1, 1)))
+                .trunc(),
+            dec!(4_109_589_041_095)
+        );
+    }
+
+    #[test]
+    fn nothing_is_emitted_before_genesis() {
+        let schedule = halving_schedule();
+        assert_eq!(
+            schedule.total(&(dt(2023, 7, 1, 0)..dt(2023, 8, 1, 0))),
+            Decimal::ZERO
+        );
+        // Only the hours after genesis count
+        assert_eq!(
+            rounded(schedule.total(&(dt(2023, 7, 31, 12)..dt(2023, 8, 1, 12)))),
+            dec!(500)
+        );
+    }
+
+    #[test]
+    fn rate_halves_at_each_interval() {
+        let schedule = halving_schedule();
+        let first_halving = dt(2025, 7, 31, 0);
+        let second_halving = dt(2027, 7, 31, 0);
+
+        let day_before = first_halving - Duration::days(1)..first_halving;
+        let day_after = first_halving..first_halving + Duration::days(1);
+        assert_eq!(rounded(schedule.total(&day_before)), dec!(1000));
+        assert_eq!(rounded(schedule.total(&day_after)), dec!(500));
+
+        let day_after_second = second_halving..second_halving + Duration::days(1);
+        assert_eq!(rounded(schedule.total(&day_after_second)), dec!(250));
+    }
+
+    #[test]
+    fn epochs_spanning_a_halving_are_split() {
+        let schedule = halving_schedule();
+        let first_halving = dt(2025, 7, 31, 0);
+        let epoch = first_halving - Duration::hours(6)..first_halving + Duration::hours(18);
+        assert_eq!(rounded(schedule.total(&epoch)), dec!(250) + dec!(375));
+        assert_eq!(rounded(schedule.poc_and_dc(&epoch)), dec!(375));
+    }
+
+    #[test]
+    fn invalid_schedules_are_reported() {
+        assert!(EmissionSchedule::default().problems().is_empty());
+        let schedule = EmissionSchedule {
+            halving_interval_days: Some(0),
+            yearly_emissions: dec!(-1),
+            mappers_percent: dec!(0.5),
+            ..Default::default()
+        };
+        assert_eq!(schedule.problems().len(), 3);
+    }
+}
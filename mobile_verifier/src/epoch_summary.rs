@@ -0,0 +1,208 @@
+//! Summary of every reward pass, written to the epoch summary files so
+//! dashboards are built from files instead of scraped logs.
+//!
+//! The gateway, radio, share and reward totals are those of the rewarded
+//! epoch. The heartbeat counts and verification time are accumulated by the
+//! heartbeat daemon and cover the heartbeat files verified since the previous
+//! summary, or since the start of the service for the first one.
+
+use crate::reward_shares::{self, PocShares};
+use chrono::{DateTime, Utc};
+use file_store::traits::TimestampEncode;
+use helium_proto::services::poc_mobile::{mobile_reward_share::Reward, MobileRewardShare};
+use rust_decimal::Decimal;
+use std::{
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EpochSummaryV1 {
+    /// Seconds since the epoch of the start of the reward period
+    #[prost(uint64, tag = "1")]
+    pub start_period: u64,
+    /// Seconds since the epoch of the end of the reward period
+    #[prost(uint64, tag = "2")]
+    pub end_period: u64,
+    /// Hotspots with radios eligible for poc rewards
+    #[prost(uint64, tag = "3")]
+    pub gateways: u64,
+    #[prost(uint64, tag = "4")]
+    pub radios: u64,
+    /// Radios without poc rewards for too few recent speedtests
+    #[prost(uint64, tag = "5")]
+    pub insufficient_speedtest_radios: u64,
+    #[prost(uint64, tag = "6")]
+    pub valid_heartbeats: u64,
+    #[prost(uint64, tag = "7")]
+    pub invalid_heartbeats: u64,
+    /// Total poc shares of the radios as a decimal
+    #[prost(string, tag = "8")]
+    pub poc_shares: String,
+    /// Bones rewarded by reward type
+    #[prost(uint64, tag = "9")]
+    pub poc_rewards: u64,
+    #[prost(uint64, tag = "10")]
+    pub dc_transfer_rewards: u64,
+    #[prost(uint64, tag = "11")]
+    pub discovery_location_rewards: u64,
+    #[prost(uint64, tag = "12")]
+    pub service_provider_rewards: u64,
+    /// Milliseconds spent verifying heartbeat files
+    #[prost(uint64, tag = "13")]
+    pub heartbeat_verification_ms: u64,
+    /// Milliseconds the reward pass took
+    #[prost(uint64, tag = "14")]
+    pub reward_ms: u64,
+    /// Milliseconds since the epoch of when the summary was written
+    #[prost(uint64, tag = "15")]
+    pub timestamp: u64,
+}
+
+/// Heartbeats verified since the previous summary
+#[derive(Debug, Default)]
+pub struct VerificationCounts {
+    valid_heartbeats: AtomicU64,
+    invalid_heartbeats: AtomicU64,
+    heartbeat_verification_ms: AtomicU64,
+}
+
+impl VerificationCounts {
+    pub fn count_heartbeat(&self, valid: bool) {
+        let count = if valid {
+            &self.valid_heartbeats
+        } else {
+            &self.invalid_heartbeats
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn heartbeat_verification(&self, duration: Duration) {
+        self.heartbeat_verification_ms
+            .fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// The valid and invalid heartbeats and verification milliseconds,
+    /// starting the counts of the next summary
+    fn take(&self) -> (u64, u64, u64) {
+        (
+            self.valid_heartbeats.swap(0, Ordering::Relaxed),
+            self.invalid_heartbeats.swap(0, Ordering::Relaxed),
+            self.heartbeat_verification_ms.swap(0, Ordering::Relaxed),
+        )
+    }
+}
+
+/// Totals of a reward pass, completed into an [`EpochSummaryV1`] once the
+/// epoch is rewarded
+#[derive(Debug, Default)]
+pub struct EpochSummary {
+    gateways: u64,
+    radios: u64,
+    insufficient_speedtest_radios: u64,
+    poc_shares: Decimal,
+    poc_rewards: u64,
+    dc_transfer_rewards: u64,
+    discovery_location_rewards: u64,
+    service_provider_rewards: u64,
+}
+
+impl EpochSummary {
+    pub fn new(poc_shares: &PocShares) -> Self {
+        Self {
+            gateways: poc_shares.hotspot_shares.len() as u64,
+            radios: poc_shares.radios() as u64,
+            insufficient_speedtest_radios: poc_shares.insufficient_speedtests.len() as u64,
+            poc_shares: poc_shares.total_shares(),
+            ..Default::default()
+        }
+    }
+
+    /// Count the reward share into the totals of its reward type
+    pub fn add_reward(&mut self, share: &MobileRewardShare) {
+        let amount = reward_shares::reward_amount(share);
+        match &share.reward {
+            Some(Reward::RadioReward(_)) => self.poc_rewards += amount,
+            Some(Reward::GatewayReward(_)) => self.dc_transfer_rewards += amount,
+            Some(Reward::SubscriberReward(_)) => self.discovery_location_rewards += amount,
+            Some(Reward::ServiceProviderReward(_)) => self.service_provider_rewards += amount,
+            None => (),
+        }
+    }
+
+    pub fn finish(
+        self,
+        epoch: &Range<DateTime<Utc>>,
+        counts: &VerificationCounts,
+        reward_duration: Duration,
+    ) -> EpochSummaryV1 {
+        let (valid_heartbeats, invalid_heartbeats, heartbeat_verification_ms) = counts.take();
+        EpochSummaryV1 {
+            start_period: epoch.start.encode_timestamp(),
+            end_period: epoch.end.encode_timestamp(),
+            gateways: self.gateways,
+            radios: self.radios,
+            insufficient_speedtest_radios: self.insufficient_speedtest_radios,
+            valid_heartbeats,
+            invalid_heartbeats,
+            poc_shares: self.poc_shares.to_string(),
+            poc_rewards: self.poc_rewards,
+            dc_transfer_rewards: self.dc_transfer_rewards,
+            discovery_location_rewards: self.discovery_location_rewards,
+            service_provider_rewards: self.service_provider_rewards,
+            heartbeat_verification_ms,
+            reward_ms: reward_duration.as_millis() as u64,
+            timestamp: Utc::now().encode_timestamp_millis(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use helium_proto::services::poc_mobile::{GatewayReward, RadioReward};
+
+    fn share(reward: Reward) -> MobileRewardShare {
+        MobileRewardShare {
+            start_period: 0,
+            end_period: 0,
+            reward: Some(reward),
+        }
+    }
+
+    #[test]
+    fn summary_totals_rewards_and_takes_verification_counts() {
+        let counts = VerificationCounts::default();
+        counts.count_heartbeat(true);
+        counts.count_heartbeat(true);
+        counts.count_heartbeat(false);
+        counts.heartbeat_verification(Duration::from_millis(1500));
+
+        let mut summary = EpochSummary::default();
+        for poc_reward in [10, 20] {
+            summary.add_reward(&share(Reward::RadioReward(RadioReward {
+                poc_reward,
+                ..Default::default()
+            })));
+        }
+        summary.add_reward(&share(Reward::GatewayReward(GatewayReward {
+            dc_transfer_reward: 5,
+            ..Default::default()
+        })));
+
+        let epoch = Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(86400, 0).unwrap();
+        let summary = summary.finish(&epoch, &counts, Duration::from_secs(2));
+        assert_eq!(summary.end_period, 86400);
+        assert_eq!(summary.poc_rewards, 30);
+        assert_eq!(summary.dc_transfer_rewards, 5);
+        assert_eq!(summary.valid_heartbeats, 2);
+        assert_eq!(summary.invalid_heartbeats, 1);
+        assert_eq!(summary.heartbeat_verification_ms, 1500);
+        assert_eq!(summary.reward_ms, 2000);
+
+        // The next summary starts counting from zero
+        assert_eq!(counts.take(), (0, 0, 0));
+    }
+}
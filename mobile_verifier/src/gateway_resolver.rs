@@ -0,0 +1,97 @@
+//! Resolution of gateway info either through the mobile config service or
+//! directly from the entity accounts on chain, selected by `gateway_source`.
+
+use crate::settings::{GatewaySource, Settings};
+use futures::stream::{self, StreamExt};
+use helium_crypto::PublicKeyBinary;
+use mobile_config::{
+    client::ClientError,
+    gateway_info::{GatewayInfo, GatewayInfoResolver, GatewayInfoStream, GatewayMetadata},
+    GatewayClient,
+};
+use poc_metrics::error_code::{ErrorCategory, ErrorCode};
+use solana::entity::{EntityError, EntityReader, HotspotInfo};
+use std::sync::Arc;
+
+#[derive(thiserror::Error, Debug)]
+pub enum GatewayResolverError {
+    #[error("mobile config error: {0}")]
+    Config(#[from] ClientError),
+    #[error("chain entity error: {0}")]
+    Chain(#[from] EntityError),
+}
+
+impl ErrorCode for GatewayResolverError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Config(err) => err.category(),
+            Self::Chain(err) => err.category(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Config(err) => err.code(),
+            Self::Chain(err) => err.code(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum GatewayResolver {
+    Config(GatewayClient),
+    Chain(Arc<EntityReader>),
+}
+
+impl GatewayResolver {
+    pub async fn from_settings(settings: &Settings) -> anyhow::Result<Self> {
+        Ok(match settings.gateway_source {
+            GatewaySource::Config => {
+                Self::Config(GatewayClient::from_settings(&settings.config_client)?)
+            }
+            GatewaySource::Chain => {
+                let Some(chain_settings) = &settings.chain_gateways else {
+                    anyhow::bail!("Missing chain_gateways section in settings");
+                };
+                Self::Chain(Arc::new(EntityReader::new(chain_settings).await?))
+            }
+        })
+    }
+}
+
+fn gateway_info(address: PublicKeyBinary, info: HotspotInfo) -> GatewayInfo {
+    GatewayInfo {
+        address,
+        metadata: info.location.map(|location| GatewayMetadata { location }),
+    }
+}
+
+#[async_trait::async_trait]
+impl GatewayInfoResolver for GatewayResolver {
+    type Error = GatewayResolverError;
+
+    async fn resolve_gateway_info(
+        &self,
+        address: &PublicKeyBinary,
+    ) -> Result<Option<GatewayInfo>, Self::Error> {
+        match self {
+            Self::Config(client) => Ok(client.resolve_gateway_info(address).await?),
+            Self::Chain(reader) => Ok(reader
+                .hotspot_info(address)
+                .await?
+                .map(|info| gateway_info(address.clone(), info))),
+        }
+    }
+
+    async fn stream_gateways_info(&mut self) -> Result<GatewayInfoStream, Self::Error> {
+        match self {
+            Self::Config(client) => Ok(client.stream_gateways_info().await?),
+            Self::Chain(reader) => {
+                let hotspots = reader.all_hotspots().await?;
+                Ok(stream::iter(hotspots)
+                    .map(|(address, info)| gateway_info(address, info))
+                    .boxed())
+            }
+        }
+    }
+}
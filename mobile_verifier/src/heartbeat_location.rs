@@ -0,0 +1,246 @@
+//! Location checks of cell heartbeats against the asserted location of their
+//! hotspot.
+//!
+//! The reported coordinates of a heartbeat and the asserted location of its
+//! hotspot are both snapped to cells of the configured h3 resolution. A
+//! heartbeat whose cell center is further than `max_distance_km` from that of
+//! the asserted location, or that reports no valid coordinates, mismatches.
+//! Hotspots without an asserted location are not checked. Mismatched
+//! heartbeats are neither written to the verified heartbeats nor rewarded:
+//! they are recorded once per cbsd and hour and every radio with a mismatch
+//! in a reward period gets a `location_mismatch` invalid share. The hours
+//! mismatched are counted per hotspot, once a hotspot reaches
+//! `denylist_after` of them every later heartbeat of it is taken as
+//! mismatched too, until the denylisting is lifted with [`allow`].
+
+use crate::{heartbeats::Heartbeat, reward_shares::InvalidRadioRewardShareV1};
+use chrono::{DateTime, Utc};
+use file_store::traits::TimestampEncode;
+use h3o::{CellIndex, LatLng, Resolution};
+use helium_crypto::PublicKeyBinary;
+use serde::Deserialize;
+use sqlx::{Postgres, Transaction};
+use std::{collections::HashSet, ops::Range};
+
+/// Reason of the invalid shares of mismatched radios
+pub const LOCATION_MISMATCH: &str = "location_mismatch";
+
+const LOCATION_MISMATCH_METRIC: &str = "heartbeat_location_mismatches";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocationSettings {
+    /// Largest distance in km between a heartbeat and the asserted location
+    /// of its hotspot
+    pub max_distance_km: f64,
+    /// H3 resolution both locations are compared at. (Default is 12)
+    #[serde(default = "default_resolution")]
+    pub resolution: u8,
+    /// Denylist hotspots after this many hours with a mismatch. Hotspots are
+    /// never denylisted when not set
+    pub denylist_after: Option<u64>,
+}
+
+fn default_resolution() -> u8 {
+    12
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocationCheck {
+    max_distance_km: f64,
+    resolution: Resolution,
+    denylist_after: Option<u64>,
+}
+
+impl TryFrom<&LocationSettings> for LocationCheck {
+    type Error = h3o::error::InvalidResolution;
+
+    fn try_from(settings: &LocationSettings) -> Result<Self, Self::Error> {
+        Ok(Self {
+            max_distance_km: settings.max_distance_km,
+            resolution: Resolution::try_from(settings.resolution)?,
+            denylist_after: settings.denylist_after,
+        })
+    }
+}
+
+impl LocationCheck {
+    /// Distance in km between the reported coordinates and the asserted
+    /// location when it is more than allowed, infinite for invalid
+    /// coordinates. None when the asserted location is not a valid cell
+    pub fn mismatch(&self, asserted: u64, lat: f64, lon: f64) -> Option<f64> {
+        let asserted = CellIndex::try_from(asserted).ok()?;
+        let asserted = asserted.parent(self.resolution).unwrap_or(asserted);
+        let distance = match LatLng::new(lat, lon) {
+            Ok(reported) => {
+                LatLng::from(asserted).distance_km(LatLng::from(reported.to_cell(self.resolution)))
+            }
+            Err(_) => f64::INFINITY,
+        };
+        (distance > self.max_distance_km).then_some(distance)
+    }
+
+    /// Record the mismatch of the heartbeat in its hour, counting the hour
+    /// towards the denylisting of its hotspot the first time it mismatches
+    pub async fn record(
+        &self,
+        exec: &mut Transaction<'_, Postgres>,
+        heartbeat: &Heartbeat,
+        truncated_timestamp: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO location_mismatches (hotspot_key, cbsd_id, truncated_timestamp, distance_km)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (cbsd_id, truncated_timestamp) DO NOTHING
+            "#,
+        )
+        .bind(&heartbeat.hotspot_key)
+        .bind(&heartbeat.cbsd_id)
+        .bind(truncated_timestamp)
+        .bind(heartbeat.location_mismatch)
+        .execute(&mut *exec)
+        .await?
+        .rows_affected()
+            > 0;
+        if !inserted {
+            return Ok(());
+        }
+        metrics::increment_counter!(LOCATION_MISMATCH_METRIC);
+
+        let (mismatches, denylisted_at): (i64, Option<DateTime<Utc>>) = sqlx::query_as(
+            r#"
+            INSERT INTO gateway_location_mismatches (hotspot_key, mismatches)
+            VALUES ($1, 1)
+            ON CONFLICT (hotspot_key) DO UPDATE SET
+            mismatches = gateway_location_mismatches.mismatches + 1
+            RETURNING mismatches, denylisted_at
+            "#,
+        )
+        .bind(&heartbeat.hotspot_key)
+        .fetch_one(&mut *exec)
+        .await?;
+        match self.denylist_after {
+            Some(limit) if denylisted_at.is_none() && mismatches as u64 >= limit => {
+                tracing::warn!(
+                    hotspot_key = %heartbeat.hotspot_key,
+                    mismatches,
+                    "denylisting hotspot for heartbeats away from its asserted location"
+                );
+                sqlx::query(
+                    "UPDATE gateway_location_mismatches SET denylisted_at = $2 WHERE hotspot_key = $1",
+                )
+                .bind(&heartbeat.hotspot_key)
+                .bind(Utc::now())
+                .execute(&mut *exec)
+                .await?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Hotspots denylisted for too many mismatches
+pub async fn denylisted(
+    exec: impl sqlx::PgExecutor<'_>,
+) -> Result<HashSet<PublicKeyBinary>, sqlx::Error> {
+    let hotspots: Vec<PublicKeyBinary> = sqlx::query_scalar(
+        "SELECT hotspot_key FROM gateway_location_mismatches WHERE denylisted_at IS NOT NULL",
+    )
+    .fetch_all(exec)
+    .await?;
+    Ok(hotspots.into_iter().collect())
+}
+
+/// Lift the denylisting of the hotspot, counting its mismatched hours from
+/// zero again. Whether the hotspot was denylisted
+pub async fn allow(
+    exec: impl sqlx::PgExecutor<'_>,
+    hotspot_key: &PublicKeyBinary,
+) -> Result<bool, sqlx::Error> {
+    let allowed = sqlx::query(
+        r#"
+        UPDATE gateway_location_mismatches SET mismatches = 0, denylisted_at = NULL
+        WHERE hotspot_key = $1 AND denylisted_at IS NOT NULL
+        "#,
+    )
+    .bind(hotspot_key)
+    .execute(exec)
+    .await?
+    .rows_affected()
+        > 0;
+    Ok(allowed)
+}
+
+/// Invalid shares of the radios with a mismatch in the epoch, ordered by
+/// hotspot and cbsd
+pub async fn invalid_shares(
+    exec: impl sqlx::PgExecutor<'_>,
+    epoch: &Range<DateTime<Utc>>,
+) -> Result<Vec<InvalidRadioRewardShareV1>, sqlx::Error> {
+    let radios: Vec<(PublicKeyBinary, String)> = sqlx::query_as(
+        r#"
+        SELECT DISTINCT hotspot_key, cbsd_id FROM location_mismatches
+        WHERE truncated_timestamp >= $1 AND truncated_timestamp < $2
+        "#,
+    )
+    .bind(epoch.start)
+    .bind(epoch.end)
+    .fetch_all(exec)
+    .await?;
+    let start_period = epoch.start.encode_timestamp();
+    let end_period = epoch.end.encode_timestamp();
+    let mut invalid_shares: Vec<_> = radios
+        .into_iter()
+        .map(|(hotspot_key, cbsd_id)| InvalidRadioRewardShareV1 {
+            start_period,
+            end_period,
+            hotspot_key: hotspot_key.into(),
+            cbsd_id,
+            reason: LOCATION_MISMATCH.to_string(),
+            speedtests: 0,
+            required_speedtests: 0,
+        })
+        .collect();
+    invalid_shares.sort_by(|a, b| (&a.hotspot_key, &a.cbsd_id).cmp(&(&b.hotspot_key, &b.cbsd_id)));
+    Ok(invalid_shares)
+}
+
+/// Clear the mismatches recorded before the given time
+pub async fn clear(
+    exec: &mut Transaction<'_, Postgres>,
+    before: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM location_mismatches WHERE truncated_timestamp < $1")
+        .bind(before)
+        .execute(exec)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeats_away_from_the_asserted_location_mismatch() {
+        let check = LocationCheck::try_from(&LocationSettings {
+            max_distance_km: 1.0,
+            resolution: 12,
+            denylist_after: None,
+        })
+        .unwrap();
+        let asserted = LatLng::new(37.7749, -122.4194)
+            .unwrap()
+            .to_cell(Resolution::Twelve);
+
+        assert_eq!(check.mismatch(asserted.into(), 37.7750, -122.4195), None);
+        let distance = check.mismatch(asserted.into(), 37.8044, -122.2712).unwrap();
+        assert!(distance > 10.0 && distance < 20.0);
+        assert_eq!(
+            check.mismatch(asserted.into(), f64::NAN, 0.0),
+            Some(f64::INFINITY)
+        );
+        assert_eq!(check.mismatch(0, 37.8044, -122.2712), None);
+    }
+}
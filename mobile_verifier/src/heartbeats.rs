@@ -1,9 +1,27 @@
 //! Heartbeat storage
-
-use crate::cell_type::CellType;
+//!
+//! Valid heartbeats are saved in batches, each with a single multi-row
+//! upsert, into the heartbeats table which is partitioned by day. The daemon
+//! creates the partitions of the heartbeats of every file before saving them.
+//!
+//! With incremental eligibility enabled the daemon also keeps the number of
+//! hours with a valid heartbeat of every cbsd per reward window up to date as
+//! heartbeat files arrive, so the rewarder can read the eligible cbsds
+//! directly instead of aggregating the whole epoch of heartbeats.
+
+use crate::{
+    cell_type::CellType,
+    epoch_summary::VerificationCounts,
+    gateway_resolver::{GatewayResolver, GatewayResolverError},
+    heartbeat_location::{self, LocationCheck},
+    reward_shares::RewardConfig,
+    rewarder, settings, telemetry,
+};
 use chrono::{DateTime, Duration, DurationRound, RoundingError, Utc};
+use db_store::Partitioner;
 use file_store::{
-    file_info_poller::FileInfoStream, file_sink::FileSinkClient,
+    file_info_poller::{Completion, FileInfoStream},
+    file_sink::FileSinkClient,
     heartbeat::CellHeartbeatIngestReport,
 };
 use futures::{
@@ -12,12 +30,23 @@ use futures::{
 };
 use helium_crypto::PublicKeyBinary;
 use helium_proto::services::poc_mobile as proto;
-use mobile_config::{client::ClientError, gateway_info::GatewayInfoResolver, GatewayClient};
+use mobile_config::gateway_info::GatewayInfoResolver;
+use oracle_epoch::{Epoch, EpochPolicy};
+use poc_metrics::error_code::{ErrorCategory, ErrorCode};
 use retainer::Cache;
 use rust_decimal::{prelude::ToPrimitive, Decimal};
 use sqlx::{Postgres, Transaction};
-use std::{ops::Range, pin::pin, sync::Arc, time};
-use tokio::sync::mpsc::Receiver;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    pin::pin,
+    sync::Arc,
+    time,
+};
+use tokio::{
+    sync::mpsc::{self, Receiver},
+    task::JoinSet,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, sqlx::FromRow)]
 pub struct HeartbeatKey {
@@ -32,39 +61,117 @@ pub struct HeartbeatReward {
     pub reward_weight: Decimal,
 }
 
-impl From<HeartbeatKey> for HeartbeatReward {
-    fn from(value: HeartbeatKey) -> Self {
+impl HeartbeatReward {
+    fn from_key(value: HeartbeatKey, reward_config: &RewardConfig) -> Self {
         Self {
+            reward_weight: reward_config.cell_type_multiplier(&value.cell_type),
             hotspot_key: value.hotspot_key,
             cbsd_id: value.cbsd_id,
-            reward_weight: value.cell_type.reward_weight(),
         }
     }
 }
 
 pub struct HeartbeatDaemon {
     pool: sqlx::Pool<sqlx::Postgres>,
-    gateway_client: GatewayClient,
+    gateway_client: GatewayResolver,
     heartbeats: Receiver<FileInfoStream<CellHeartbeatIngestReport>>,
     file_sink: FileSinkClient,
+    max_concurrent_files: usize,
+    epoch_policy: EpochPolicy,
+    eligibility_period: Option<Duration>,
+    batch_size: usize,
+    partitioner: Option<Partitioner>,
+    verification_counts: Arc<VerificationCounts>,
+    location_check: Option<LocationCheck>,
 }
 
+/// Size of the channel merging validated heartbeats from concurrently
+/// processed files into the single transaction writer.
+const VALIDATED_HEARTBEAT_CHANNEL_SIZE: usize = 1000;
+
 impl HeartbeatDaemon {
     pub fn new(
         pool: sqlx::Pool<sqlx::Postgres>,
-        gateway_client: GatewayClient,
+        gateway_client: GatewayResolver,
         heartbeats: Receiver<FileInfoStream<CellHeartbeatIngestReport>>,
         file_sink: FileSinkClient,
+        max_concurrent_files: usize,
+        epoch_policy: EpochPolicy,
     ) -> Self {
         Self {
             pool,
             gateway_client,
             heartbeats,
             file_sink,
+            max_concurrent_files: max_concurrent_files.max(1),
+            epoch_policy,
+            eligibility_period: None,
+            batch_size: settings::default_heartbeat_batch_size(),
+            partitioner: None,
+            verification_counts: Arc::default(),
+            location_check: None,
+        }
+    }
+
+    /// Save at most this many heartbeats with a single upsert
+    pub fn batch_size(self, batch_size: usize) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            ..self
         }
     }
 
+    /// Create the partitions of the heartbeats of every file before saving
+    /// them
+    pub fn partitions(self, partitioner: Partitioner) -> Self {
+        Self {
+            partitioner: Some(partitioner),
+            ..self
+        }
+    }
+
+    /// Count the verified heartbeats into the next epoch summary
+    pub fn verification_counts(self, verification_counts: Arc<VerificationCounts>) -> Self {
+        Self {
+            verification_counts,
+            ..self
+        }
+    }
+
+    /// Check the heartbeats against the asserted location of their hotspot,
+    /// leaving mismatched ones unrewarded
+    pub fn location_check(self, location_check: LocationCheck) -> Self {
+        Self {
+            location_check: Some(location_check),
+            ..self
+        }
+    }
+
+    /// Maintain the heartbeat eligibility of reward windows of the given
+    /// length as heartbeats are saved
+    pub fn incremental_eligibility(self, reward_period: Duration) -> Self {
+        Self {
+            eligibility_period: Some(reward_period),
+            ..self
+        }
+    }
+
+    /// Start and length of the reward windows, when eligibility is maintained
+    async fn eligibility_windows(&self) -> anyhow::Result<Option<(DateTime<Utc>, Duration)>> {
+        let Some(period) = self.eligibility_period else {
+            return Ok(None);
+        };
+        let origin = rewarder::last_rewarded_end_time(&self.pool).await?;
+        Ok(Some((origin, period)))
+    }
+
     pub async fn run(mut self, shutdown: triggered::Listener) -> anyhow::Result<()> {
+        // Heartbeats saved before eligibility was maintained, or by an older
+        // version, are counted into the current reward window first
+        if let Some((origin, period)) = self.eligibility_windows().await? {
+            HeartbeatEligibility::backfill(&self.pool, &(origin..origin + period)).await?;
+        }
+
         tokio::spawn(async move {
             let cache = Arc::new(Cache::<(String, DateTime<Utc>), ()>::new());
 
@@ -81,7 +188,16 @@ impl HeartbeatDaemon {
                         tracing::info!("HeartbeatDaemon shutting down");
                         break;
                     }
-                    Some(file) = self.heartbeats.recv() => self.process_file(file, &cache).await?,
+                    Some(file) = self.heartbeats.recv() => {
+                        let mut files = vec![file];
+                        while files.len() < self.max_concurrent_files {
+                            match self.heartbeats.try_recv() {
+                                Ok(file) => files.push(file),
+                                Err(_) => break,
+                            }
+                        }
+                        self.process_files(files, &cache).await?
+                    }
                 }
             }
 
@@ -92,40 +208,148 @@ impl HeartbeatDaemon {
         .await
     }
 
-    async fn process_file(
+    /// Validate a batch of heartbeat files concurrently, merging the validated
+    /// heartbeats into a single transaction. Either every file in the batch is
+    /// recorded as processed or none of them are.
+    async fn process_files(
         &self,
-        file: FileInfoStream<CellHeartbeatIngestReport>,
+        files: Vec<FileInfoStream<CellHeartbeatIngestReport>>,
         cache: &Cache<(String, DateTime<Utc>), ()>,
     ) -> anyhow::Result<()> {
-        tracing::info!("Processing heartbeat file {}", file.file_info.key);
-
-        let epoch = (file.file_info.timestamp - Duration::hours(3))
-            ..(file.file_info.timestamp + Duration::minutes(30));
+        let batch_start = time::Instant::now();
+        let file_count = files.len();
+        let eligibility_windows = self.eligibility_windows().await?;
+        if let Some(partitioner) = &self.partitioner {
+            let skew = self.epoch_policy.skew();
+            for file in &files {
+                let epoch = file_epoch(file.file_info.timestamp).widen(skew);
+                partitioner.ensure(&epoch.range()).await?;
+            }
+        }
+        let denylisted = match self.location_check {
+            Some(_) => heartbeat_location::denylisted(&self.pool).await?,
+            None => HashSet::new(),
+        };
         let mut transaction = self.pool.begin().await?;
-        let reports = file.into_stream(&mut transaction).await?;
-
-        let mut validated_heartbeats =
-            pin!(Heartbeat::validate_heartbeats(&self.gateway_client, reports, &epoch).await);
+        let (sender, mut receiver) = mpsc::channel(VALIDATED_HEARTBEAT_CHANNEL_SIZE);
+
+        // Validators still running when this returns early are aborted as the
+        // set is dropped
+        let mut validators = JoinSet::new();
+        let mut completions = Vec::with_capacity(file_count);
+        for file in files {
+            tracing::info!("Processing heartbeat file {}", file.file_info.key);
+            let epoch = file_epoch(file.file_info.timestamp).range();
+            let (reports, completion) = file.into_stream(&mut transaction).await?;
+            completions.push(completion);
+            let gateway_client = self.gateway_client.clone();
+            let sender = sender.clone();
+            let epoch_policy = self.epoch_policy;
+            let location_check = self.location_check;
+            validators.spawn(async move {
+                let mut validated_heartbeats = pin!(
+                    Heartbeat::validate_heartbeats(
+                        &gateway_client,
+                        reports,
+                        &epoch,
+                        epoch_policy,
+                        location_check
+                    )
+                    .await
+                );
+                while let Some(heartbeat) = validated_heartbeats.next().await {
+                    if sender.send(heartbeat).await.is_err() {
+                        // The writer has stopped, most likely due to an error
+                        // which will be reported there.
+                        break;
+                    }
+                }
+            });
+        }
+        // Drop our own sender so the receiver closes once every validator is done
+        drop(sender);
 
-        while let Some(heartbeat) = validated_heartbeats.next().await.transpose()? {
-            heartbeat.write(&self.file_sink).await?;
+        let mut record_count = 0;
+        let mut batch = HeartbeatBatch::default();
+        while let Some(heartbeat) = receiver.recv().await.transpose()? {
+            record_count += 1;
             let key = (heartbeat.cbsd_id.clone(), heartbeat.truncated_timestamp()?);
+            let mismatched = self.location_check.as_ref().filter(|_| {
+                heartbeat.validity == proto::HeartbeatValidity::Valid
+                    && (heartbeat.location_mismatch.is_some()
+                        || denylisted.contains(&heartbeat.hotspot_key))
+            });
+            self.verification_counts.count_heartbeat(
+                heartbeat.validity == proto::HeartbeatValidity::Valid && mismatched.is_none(),
+            );
+
+            // Mismatched heartbeats are recorded for their invalid shares
+            // instead of being written as valid
+            if let Some(location_check) = mismatched {
+                location_check
+                    .record(&mut transaction, &heartbeat, key.1)
+                    .await?;
+                continue;
+            }
+            heartbeat.write(&self.file_sink).await?;
 
             if cache.get(&key).await.is_none() {
-                heartbeat.save(&mut transaction).await?;
+                batch.push(heartbeat)?;
                 cache
                     .insert(key, (), time::Duration::from_secs(60 * 60 * 2))
                     .await;
             }
+            if batch.len() >= self.batch_size {
+                save_batch(&mut transaction, &mut batch, eligibility_windows).await?;
+            }
+        }
+        save_batch(&mut transaction, &mut batch, eligibility_windows).await?;
+
+        while let Some(validator) = validators.join_next().await {
+            validator?;
         }
 
         self.file_sink.commit().await?;
-        transaction.commit().await?;
+        Completion::commit_all(transaction, completions).await?;
+
+        telemetry::heartbeat_batch_processed(file_count, record_count, batch_start);
+        self.verification_counts
+            .heartbeat_verification(batch_start.elapsed());
 
         Ok(())
     }
 }
 
+/// Time range of the heartbeats accepted from a file of the given time
+fn file_epoch(timestamp: DateTime<Utc>) -> Epoch {
+    Epoch::around(timestamp, Duration::hours(3), Duration::minutes(30))
+}
+
+/// Save the batch, recounting the eligibility of the cbsds with new hours
+async fn save_batch(
+    transaction: &mut Transaction<'_, Postgres>,
+    batch: &mut HeartbeatBatch,
+    eligibility_windows: Option<(DateTime<Utc>, Duration)>,
+) -> anyhow::Result<()> {
+    let inserted = batch.save(transaction).await?;
+    if let Some((origin, period)) = eligibility_windows {
+        let mut updated = HashSet::new();
+        for saved in inserted {
+            let window = eligibility_window(origin, period, saved.truncated_timestamp)?;
+            if updated.insert((saved.cbsd_id.clone(), window.start)) {
+                HeartbeatEligibility::update(
+                    transaction,
+                    &saved.cbsd_id,
+                    &saved.hotspot_key,
+                    &window,
+                )
+                .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Minimum number of heartbeats required to give a reward to the hotspot.
 pub const MINIMUM_HEARTBEAT_COUNT: i64 = 12;
 
@@ -133,6 +357,7 @@ impl HeartbeatReward {
     pub fn validated<'a>(
         exec: impl sqlx::PgExecutor<'a> + Copy + 'a,
         epoch: &'a Range<DateTime<Utc>>,
+        reward_config: &'a RewardConfig,
     ) -> impl Stream<Item = Result<HeartbeatReward, sqlx::Error>> + 'a {
         sqlx::query_as::<_, HeartbeatKey>(
             r#"
@@ -148,7 +373,137 @@ impl HeartbeatReward {
         .bind(epoch.end)
         .bind(MINIMUM_HEARTBEAT_COUNT)
         .fetch(exec)
-        .map_ok(HeartbeatReward::from)
+        .map_ok(move |key| HeartbeatReward::from_key(key, reward_config))
+    }
+
+    /// Cbsds eligible for rewards in the epoch according to the incrementally
+    /// maintained eligibility
+    pub fn eligible<'a>(
+        exec: impl sqlx::PgExecutor<'a> + Copy + 'a,
+        epoch: &'a Range<DateTime<Utc>>,
+        reward_config: &'a RewardConfig,
+    ) -> impl Stream<Item = Result<HeartbeatReward, sqlx::Error>> + 'a {
+        sqlx::query_as::<_, HeartbeatKey>(
+            r#"
+            SELECT hotspot_key, cbsd_id, cell_type
+            FROM heartbeat_eligibility
+            WHERE window_start = $1
+                AND window_end = $2
+                AND hours >= $3
+            "#,
+        )
+        .bind(epoch.start)
+        .bind(epoch.end)
+        .bind(MINIMUM_HEARTBEAT_COUNT)
+        .fetch(exec)
+        .map_ok(move |key| HeartbeatReward::from_key(key, reward_config))
+    }
+}
+
+/// Reward window of `period` containing `hour`, with windows aligned to
+/// `origin`
+pub fn eligibility_window(
+    origin: DateTime<Utc>,
+    period: Duration,
+    hour: DateTime<Utc>,
+) -> oracle_epoch::Result<Range<DateTime<Utc>>> {
+    Epoch::containing(origin, period, hour).map(|window| window.range())
+}
+
+pub struct HeartbeatEligibility;
+
+impl HeartbeatEligibility {
+    /// Recount the hours of the cbsd in the window from its saved heartbeats.
+    /// Like the heartbeats, the eligibility of the cbsd under a previous
+    /// hotspot is dropped.
+    pub async fn update(
+        exec: &mut Transaction<'_, Postgres>,
+        cbsd_id: &str,
+        hotspot_key: &PublicKeyBinary,
+        window: &Range<DateTime<Utc>>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM heartbeat_eligibility WHERE cbsd_id = $1 AND hotspot_key != $2")
+            .bind(cbsd_id)
+            .bind(hotspot_key)
+            .execute(&mut *exec)
+            .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO heartbeat_eligibility (cbsd_id, hotspot_key, cell_type, window_start, window_end, hours)
+            SELECT cbsd_id, hotspot_key, cell_type, $2, $3, count(*)
+            FROM heartbeats
+            WHERE cbsd_id = $1
+                AND truncated_timestamp >= $2
+                AND truncated_timestamp < $3
+            GROUP BY cbsd_id, hotspot_key, cell_type
+            ON CONFLICT (cbsd_id, window_start) DO UPDATE SET
+            hotspot_key = EXCLUDED.hotspot_key,
+            cell_type = EXCLUDED.cell_type,
+            window_end = EXCLUDED.window_end,
+            hours = EXCLUDED.hours
+            "#,
+        )
+        .bind(cbsd_id)
+        .bind(window.start)
+        .bind(window.end)
+        .execute(&mut *exec)
+        .await?;
+        Ok(())
+    }
+
+    /// Recount the hours of every cbsd in the window
+    pub async fn backfill(
+        exec: impl sqlx::PgExecutor<'_>,
+        window: &Range<DateTime<Utc>>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO heartbeat_eligibility (cbsd_id, hotspot_key, cell_type, window_start, window_end, hours)
+            SELECT cbsd_id, hotspot_key, cell_type, $1, $2, count(*)
+            FROM heartbeats
+            WHERE truncated_timestamp >= $1
+                AND truncated_timestamp < $2
+            GROUP BY cbsd_id, hotspot_key, cell_type
+            ON CONFLICT (cbsd_id, window_start) DO UPDATE SET
+            hotspot_key = EXCLUDED.hotspot_key,
+            cell_type = EXCLUDED.cell_type,
+            window_end = EXCLUDED.window_end,
+            hours = EXCLUDED.hours
+            "#,
+        )
+        .bind(window.start)
+        .bind(window.end)
+        .execute(exec)
+        .await?;
+        Ok(())
+    }
+
+    /// Whether eligibility was maintained for exactly this epoch. It is not
+    /// when incremental eligibility was disabled, or the reward period changed
+    pub async fn is_maintained(
+        exec: impl sqlx::PgExecutor<'_>,
+        epoch: &Range<DateTime<Utc>>,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM heartbeat_eligibility WHERE window_start = $1 AND window_end = $2)",
+        )
+        .bind(epoch.start)
+        .bind(epoch.end)
+        .fetch_one(exec)
+        .await
+    }
+
+    /// Drop the eligibility of the windows ending before the given time
+    pub async fn clear(
+        exec: &mut Transaction<'_, Postgres>,
+        before: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM heartbeat_eligibility WHERE window_end <= $1")
+            .bind(before)
+            .execute(&mut *exec)
+            .await?;
+        Ok(())
     }
 }
 
@@ -159,11 +514,9 @@ pub struct Heartbeat {
     pub hotspot_key: PublicKeyBinary,
     pub timestamp: DateTime<Utc>,
     pub validity: proto::HeartbeatValidity,
-}
-
-#[derive(sqlx::FromRow)]
-struct HeartbeatSaveResult {
-    inserted: bool,
+    /// Distance in km from the asserted location of the hotspot, when too far
+    /// from it
+    pub location_mismatch: Option<f64>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -174,27 +527,52 @@ pub enum SaveHeartbeatError {
     SqlError(#[from] sqlx::Error),
 }
 
+impl ErrorCode for SaveHeartbeatError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::RoundingError(_) => ErrorCategory::Decode,
+            Self::SqlError(err) => db_store::sqlx_error_labels(err).category,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::RoundingError(_) => "heartbeat_timestamp",
+            Self::SqlError(err) => db_store::sqlx_error_labels(err).code,
+        }
+    }
+}
+
 impl Heartbeat {
     pub fn truncated_timestamp(&self) -> Result<DateTime<Utc>, RoundingError> {
         self.timestamp.duration_trunc(Duration::hours(1))
     }
 
     pub async fn validate_heartbeats<'a>(
-        gateway_client: &'a GatewayClient,
+        gateway_client: &'a GatewayResolver,
         heartbeats: impl Stream<Item = CellHeartbeatIngestReport> + 'a,
         epoch: &'a Range<DateTime<Utc>>,
-    ) -> impl Stream<Item = Result<Self, ClientError>> + 'a {
+        epoch_policy: EpochPolicy,
+        location_check: Option<LocationCheck>,
+    ) -> impl Stream<Item = Result<Self, GatewayResolverError>> + 'a {
         heartbeats.then(move |heartbeat_report| {
             let mut gateway_client = gateway_client.clone();
             async move {
-                let (cell_type, validity) =
-                    validate_heartbeat(&heartbeat_report, &mut gateway_client, epoch).await?;
+                let (cell_type, validity, location_mismatch) = validate_heartbeat(
+                    &heartbeat_report,
+                    &mut gateway_client,
+                    epoch,
+                    &epoch_policy,
+                    location_check.as_ref(),
+                )
+                .await?;
                 Ok(Heartbeat {
                     hotspot_key: heartbeat_report.report.pubkey,
                     cbsd_id: heartbeat_report.report.cbsd_id,
                     timestamp: heartbeat_report.received_timestamp,
                     cell_type,
                     validity,
+                    location_mismatch,
                 })
             }
         })
@@ -219,71 +597,286 @@ impl Heartbeat {
             .await?;
         Ok(())
     }
+}
+
+struct BatchedHeartbeat {
+    cbsd_id: String,
+    hotspot_key: PublicKeyBinary,
+    cell_type: CellType,
+    latest_timestamp: DateTime<Utc>,
+    truncated_timestamp: DateTime<Utc>,
+}
+
+/// Heartbeat of an hour that was not saved before its batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedHeartbeat {
+    pub cbsd_id: String,
+    pub hotspot_key: PublicKeyBinary,
+    pub truncated_timestamp: DateTime<Utc>,
+}
+
+/// Valid heartbeats saved together, at most one per cbsd and hour
+#[derive(Default)]
+pub struct HeartbeatBatch {
+    heartbeats: Vec<BatchedHeartbeat>,
+    /// Index of the heartbeat of every cbsd and hour
+    hours: HashMap<(String, DateTime<Utc>), usize>,
+    /// Hotspot of the latest heartbeat of every cbsd
+    hotspots: HashMap<String, PublicKeyBinary>,
+}
+
+impl HeartbeatBatch {
+    pub fn len(&self) -> usize {
+        self.heartbeats.len()
+    }
+
+    /// Add a heartbeat, ignoring invalid ones. As when saving the heartbeats
+    /// one at a time, a heartbeat replaces the heartbeats of its cbsd under
+    /// another hotspot and updates the latest timestamp of its hour
+    pub fn push(&mut self, heartbeat: Heartbeat) -> Result<(), SaveHeartbeatError> {
+        let (proto::HeartbeatValidity::Valid, Some(cell_type)) =
+            (heartbeat.validity, heartbeat.cell_type)
+        else {
+            return Ok(());
+        };
+        let truncated_timestamp = heartbeat.truncated_timestamp()?;
+        let Heartbeat {
+            cbsd_id,
+            hotspot_key,
+            timestamp,
+            ..
+        } = heartbeat;
+
+        if self
+            .hotspots
+            .get(&cbsd_id)
+            .map_or(false, |batched| batched != &hotspot_key)
+        {
+            self.heartbeats.retain(|batched| batched.cbsd_id != cbsd_id);
+            self.hours = self
+                .heartbeats
+                .iter()
+                .enumerate()
+                .map(|(i, batched)| ((batched.cbsd_id.clone(), batched.truncated_timestamp), i))
+                .collect();
+        }
+        self.hotspots.insert(cbsd_id.clone(), hotspot_key.clone());
+
+        match self.hours.get(&(cbsd_id.clone(), truncated_timestamp)) {
+            Some(&i) => self.heartbeats[i].latest_timestamp = timestamp,
+            None => {
+                self.hours.insert(
+                    (cbsd_id.clone(), truncated_timestamp),
+                    self.heartbeats.len(),
+                );
+                self.heartbeats.push(BatchedHeartbeat {
+                    cbsd_id,
+                    hotspot_key,
+                    cell_type,
+                    latest_timestamp: timestamp,
+                    truncated_timestamp,
+                });
+            }
+        }
+        Ok(())
+    }
 
+    /// Save and clear the batch, returning the heartbeats of the hours that
+    /// were not saved before
     pub async fn save(
-        self,
+        &mut self,
         exec: &mut Transaction<'_, Postgres>,
-    ) -> Result<bool, SaveHeartbeatError> {
-        // If the heartbeat is not valid, do not save it
-        if self.validity != proto::HeartbeatValidity::Valid {
-            return Ok(false);
+    ) -> Result<Vec<SavedHeartbeat>, SaveHeartbeatError> {
+        let heartbeats = std::mem::take(&mut self.heartbeats);
+        let hotspots = std::mem::take(&mut self.hotspots);
+        self.hours.clear();
+        if heartbeats.is_empty() {
+            return Ok(vec![]);
         }
 
-        sqlx::query("DELETE FROM heartbeats WHERE cbsd_id = $1 AND hotspot_key != $2")
-            .bind(&self.cbsd_id)
-            .bind(&self.hotspot_key)
-            .execute(&mut *exec)
-            .await?;
-
-        let truncated_timestamp = self.truncated_timestamp()?;
-        Ok(
-            sqlx::query_as::<_, HeartbeatSaveResult>(
-                r#"
-                INSERT INTO heartbeats (cbsd_id, hotspot_key, cell_type, latest_timestamp, truncated_timestamp)
-                VALUES ($1, $2, $3, $4, $5)
-                ON CONFLICT (cbsd_id, truncated_timestamp) DO UPDATE SET
-                latest_timestamp = EXCLUDED.latest_timestamp
-                RETURNING (xmax = 0) as inserted
-                "#
-            )
-            .bind(self.cbsd_id)
-            .bind(self.hotspot_key)
-            .bind(self.cell_type.unwrap())
-            .bind(self.timestamp)
-            .bind(truncated_timestamp)
-            .fetch_one(&mut *exec)
-            .await?
-            .inserted
+        let (cbsd_ids, hotspot_keys): (Vec<String>, Vec<String>) = hotspots
+            .into_iter()
+            .map(|(cbsd_id, hotspot_key)| (cbsd_id, hotspot_key.to_string()))
+            .unzip();
+        sqlx::query(
+            r#"
+            DELETE FROM heartbeats
+            USING UNNEST($1::text[], $2::text[]) AS batch(cbsd_id, hotspot_key)
+            WHERE heartbeats.cbsd_id = batch.cbsd_id
+            AND heartbeats.hotspot_key != batch.hotspot_key
+            "#,
         )
+        .bind(&cbsd_ids)
+        .bind(&hotspot_keys)
+        .execute(&mut *exec)
+        .await?;
+
+        let (hour_cbsd_ids, hours): (Vec<String>, Vec<DateTime<Utc>>) = heartbeats
+            .iter()
+            .map(|heartbeat| (heartbeat.cbsd_id.clone(), heartbeat.truncated_timestamp))
+            .unzip();
+        let existing: HashSet<(String, DateTime<Utc>)> = sqlx::query_as(
+            r#"
+            SELECT heartbeats.cbsd_id, heartbeats.truncated_timestamp FROM heartbeats
+            JOIN UNNEST($1::text[], $2::timestamptz[]) AS batch(cbsd_id, truncated_timestamp)
+            ON heartbeats.cbsd_id = batch.cbsd_id
+            AND heartbeats.truncated_timestamp = batch.truncated_timestamp
+            "#,
+        )
+        .bind(&hour_cbsd_ids)
+        .bind(&hours)
+        .fetch_all(&mut *exec)
+        .await?
+        .into_iter()
+        .collect();
+
+        let mut query = sqlx::QueryBuilder::<Postgres>::new(
+            "INSERT INTO heartbeats (cbsd_id, hotspot_key, cell_type, latest_timestamp, truncated_timestamp) ",
+        );
+        query.push_values(&heartbeats, |mut row, heartbeat| {
+            row.push_bind(&heartbeat.cbsd_id)
+                .push_bind(&heartbeat.hotspot_key)
+                .push_bind(heartbeat.cell_type)
+                .push_bind(heartbeat.latest_timestamp)
+                .push_bind(heartbeat.truncated_timestamp);
+        });
+        query.push(
+            " ON CONFLICT (cbsd_id, truncated_timestamp) DO UPDATE SET latest_timestamp = EXCLUDED.latest_timestamp",
+        );
+        query.build().execute(&mut *exec).await?;
+
+        Ok(heartbeats
+            .into_iter()
+            .filter(|heartbeat| {
+                !existing.contains(&(heartbeat.cbsd_id.clone(), heartbeat.truncated_timestamp))
+            })
+            .map(|heartbeat| SavedHeartbeat {
+                cbsd_id: heartbeat.cbsd_id,
+                hotspot_key: heartbeat.hotspot_key,
+                truncated_timestamp: heartbeat.truncated_timestamp,
+            })
+            .collect())
     }
 }
 
-/// Validate a heartbeat in the given epoch.
+/// Validate a heartbeat in the given epoch, with the distance from the
+/// asserted location of its hotspot when checked and too far from it.
 async fn validate_heartbeat(
     heartbeat: &CellHeartbeatIngestReport,
-    gateway_client: &mut GatewayClient,
+    gateway_client: &mut GatewayResolver,
     epoch: &Range<DateTime<Utc>>,
-) -> Result<(Option<CellType>, proto::HeartbeatValidity), ClientError> {
+    epoch_policy: &EpochPolicy,
+    location_check: Option<&LocationCheck>,
+) -> Result<(Option<CellType>, proto::HeartbeatValidity, Option<f64>), GatewayResolverError> {
     let cell_type = match CellType::from_cbsd_id(&heartbeat.report.cbsd_id) {
         Some(ty) => Some(ty),
-        _ => return Ok((None, proto::HeartbeatValidity::BadCbsdId)),
+        _ => return Ok((None, proto::HeartbeatValidity::BadCbsdId, None)),
     };
 
     if !heartbeat.report.operation_mode {
-        return Ok((cell_type, proto::HeartbeatValidity::NotOperational));
+        return Ok((cell_type, proto::HeartbeatValidity::NotOperational, None));
     }
 
-    if !epoch.contains(&heartbeat.received_timestamp) {
-        return Ok((cell_type, proto::HeartbeatValidity::HeartbeatOutsideRange));
+    if !epoch_policy.accepts(epoch, &heartbeat.received_timestamp) {
+        return Ok((
+            cell_type,
+            proto::HeartbeatValidity::HeartbeatOutsideRange,
+            None,
+        ));
     }
 
-    if gateway_client
+    let Some(gateway_info) = gateway_client
         .resolve_gateway_info(&heartbeat.report.pubkey)
         .await?
-        .is_none()
-    {
-        return Ok((cell_type, proto::HeartbeatValidity::GatewayOwnerNotFound));
+    else {
+        return Ok((
+            cell_type,
+            proto::HeartbeatValidity::GatewayOwnerNotFound,
+            None,
+        ));
+    };
+
+    let location_mismatch =
+        location_check
+            .zip(gateway_info.metadata)
+            .and_then(|(check, metadata)| {
+                check.mismatch(
+                    metadata.location,
+                    heartbeat.report.lat,
+                    heartbeat.report.lon,
+                )
+            });
+
+    Ok((
+        cell_type,
+        proto::HeartbeatValidity::Valid,
+        location_mismatch,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn hours_are_assigned_to_the_window_containing_them() {
+        let origin = Utc.with_ymd_and_hms(2023, 8, 1, 1, 0, 0).unwrap();
+        let period = Duration::hours(24);
+        let at = |d, h| Utc.with_ymd_and_hms(2023, 8, d, h, 0, 0).unwrap();
+
+        assert_eq!(
+            eligibility_window(origin, period, at(1, 1)).unwrap(),
+            origin..at(2, 1)
+        );
+        assert_eq!(
+            eligibility_window(origin, period, at(2, 0)).unwrap(),
+            origin..at(2, 1)
+        );
+        assert_eq!(
+            eligibility_window(origin, period, at(2, 1)).unwrap(),
+            at(2, 1)..at(3, 1)
+        );
+        // Late heartbeats of already rewarded windows
+        assert_eq!(
+            eligibility_window(origin, period, at(1, 0)).unwrap(),
+            origin - period..origin
+        );
     }
 
-    Ok((cell_type, proto::HeartbeatValidity::Valid))
+    #[test]
+    fn batches_keep_one_heartbeat_per_cbsd_and_hour() {
+        let heartbeat = |cbsd_id: &str, hotspot: u8, minute| Heartbeat {
+            cbsd_id: cbsd_id.to_string(),
+            cell_type: Some(CellType::Nova436H),
+            hotspot_key: PublicKeyBinary::from(vec![hotspot]),
+            timestamp: Utc.with_ymd_and_hms(2023, 8, 1, 1, minute, 0).unwrap(),
+            validity: proto::HeartbeatValidity::Valid,
+            location_mismatch: None,
+        };
+        let mut batch = HeartbeatBatch::default();
+        batch.push(heartbeat("a", 1, 0)).unwrap();
+        batch.push(heartbeat("a", 1, 30)).unwrap();
+        batch.push(heartbeat("b", 1, 0)).unwrap();
+        batch
+            .push(Heartbeat {
+                validity: proto::HeartbeatValidity::GatewayOwnerNotFound,
+                ..heartbeat("c", 1, 0)
+            })
+            .unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(
+            batch.heartbeats[0].latest_timestamp,
+            Utc.with_ymd_and_hms(2023, 8, 1, 1, 30, 0).unwrap()
+        );
+
+        // A cbsd moving to another hotspot replaces its heartbeats
+        batch.push(heartbeat("a", 2, 45)).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.heartbeats[0].cbsd_id, "b");
+        assert_eq!(
+            batch.hours[&("a".to_string(), batch.heartbeats[1].truncated_timestamp)],
+            1
+        );
+    }
 }
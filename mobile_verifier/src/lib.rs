@@ -1,13 +1,29 @@
-mod cell_type;
+mod correction_service;
 mod data_session;
-mod heartbeats;
+mod emissions;
+mod epoch_summary;
+mod gateway_resolver;
+mod reward_diff;
 mod reward_shares;
+mod reward_smoothing;
 mod settings;
+mod speedtest_service;
 mod speedtests;
 mod subscriber_location;
 mod telemetry;
 
+pub mod cell_type;
 pub mod cli;
+pub mod corrections;
+pub mod heartbeat_location;
+pub mod heartbeats;
 pub mod rewarder;
 
+pub use reward_shares::RewardConfig;
 pub use settings::Settings;
+
+/// Label the errors of the verifier in metrics and logs
+pub fn register_error_codes() {
+    poc_metrics::error_code::register::<gateway_resolver::GatewayResolverError>();
+    poc_metrics::error_code::register::<heartbeats::SaveHeartbeatError>();
+}
@@ -1,11 +1,10 @@
 use anyhow::Result;
 use clap::Parser;
 use mobile_verifier::{
-    cli::{reward_from_db, server},
+    cli::{allow_location, reward_diff, reward_from_db, server},
     Settings,
 };
 use std::path;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(clap::Parser)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
@@ -24,10 +23,16 @@ pub struct Cli {
 impl Cli {
     pub async fn run(self) -> Result<()> {
         let settings = Settings::new(self.config)?;
-        tracing_subscriber::registry()
-            .with(tracing_subscriber::EnvFilter::new(&settings.log))
-            .with(tracing_subscriber::fmt::layer())
-            .init();
+        custom_tracing::init(&settings.log, &settings.tracing)?;
+        poc_metrics::register_error_codes!(
+            poc_metrics,
+            db_store,
+            file_store,
+            solana,
+            price,
+            mobile_config,
+            mobile_verifier,
+        );
         self.cmd.run(settings).await
     }
 }
@@ -36,6 +41,8 @@ impl Cli {
 pub enum Cmd {
     Server(server::Cmd),
     RewardFromDb(reward_from_db::Cmd),
+    RewardDiff(reward_diff::Cmd),
+    AllowLocation(allow_location::Cmd),
 }
 
 impl Cmd {
@@ -43,6 +50,8 @@ impl Cmd {
         match self {
             Self::Server(cmd) => cmd.run(&settings).await,
             Self::RewardFromDb(cmd) => cmd.run(&settings).await,
+            Self::RewardDiff(cmd) => cmd.run(&settings).await,
+            Self::AllowLocation(cmd) => cmd.run(&settings).await,
         }
     }
 }
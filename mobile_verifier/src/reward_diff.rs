@@ -0,0 +1,316 @@
+//! Compare radio rewards produced by two reward sources for the same epoch
+//! without writing any canonical rewards.
+
+use crate::{
+    data_session,
+    emissions::EmissionSchedule,
+    heartbeats::HeartbeatReward,
+    reward_shares::{PocShares, RewardConfig, TransferRewards},
+    speedtests::{SpeedtestAverages, MIN_REQUIRED_SAMPLES},
+};
+use chrono::{DateTime, Utc};
+use dc_pricing::DcPricing;
+use file_store::{file_sink::FileSinkClient, file_source, traits::TimestampEncode};
+use futures::stream::TryStreamExt;
+use helium_crypto::PublicKeyBinary;
+use helium_proto::services::poc_mobile as proto;
+use prost::Message;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+    path::PathBuf,
+};
+
+pub type RadioRewards = HashMap<PublicKeyBinary, u64>;
+
+#[async_trait::async_trait]
+pub trait RewardSource {
+    fn name(&self) -> &'static str;
+
+    async fn radio_rewards(&self, epoch: &Range<DateTime<Utc>>) -> anyhow::Result<RadioRewards>;
+}
+
+/// Recomputes the poc rewards for the epoch from the heartbeats, speedtests
+/// and data transfer sessions currently in the database.
+pub struct DbRewards {
+    pool: Pool<Postgres>,
+    reward_config: RewardConfig,
+    emissions: EmissionSchedule,
+    mobile_bone_price: Decimal,
+    min_speedtests: usize,
+    pricing: DcPricing,
+}
+
+impl DbRewards {
+    /// The data transfer rewards deducted from the poc rewards are valued at
+    /// the given price of a bone in USD
+    pub fn new(
+        pool: Pool<Postgres>,
+        reward_config: RewardConfig,
+        emissions: EmissionSchedule,
+        mobile_bone_price: Decimal,
+    ) -> Self {
+        Self {
+            pool,
+            reward_config,
+            emissions,
+            mobile_bone_price,
+            min_speedtests: MIN_REQUIRED_SAMPLES,
+            pricing: DcPricing::mobile(),
+        }
+    }
+
+    pub fn min_speedtests(self, min_speedtests: usize) -> Self {
+        Self {
+            min_speedtests,
+            ..self
+        }
+    }
+
+    pub fn pricing(self, pricing: DcPricing) -> Self {
+        Self { pricing, ..self }
+    }
+}
+
+#[async_trait::async_trait]
+impl RewardSource for DbRewards {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn radio_rewards(&self, epoch: &Range<DateTime<Utc>>) -> anyhow::Result<RadioRewards> {
+        let heartbeats = HeartbeatReward::validated(&self.pool, epoch, &self.reward_config);
+        let speedtests = SpeedtestAverages::validated(&self.pool, epoch.end)
+            .await?
+            .min_speedtests(self.min_speedtests);
+        let poc_shares = PocShares::aggregate(heartbeats, speedtests).await?;
+        // The poc rewards are what is left of the pool after the data transfer
+        // rewards, as in the rewarder
+        let transfer_rewards = TransferRewards::from_transfer_sessions(
+            self.mobile_bone_price,
+            &self.pricing,
+            data_session::aggregate_hotspot_data_sessions_to_dc(&self.pool, epoch).await?,
+            &poc_shares,
+            &self.emissions,
+            epoch,
+        )
+        .await;
+
+        let mut rewards = RadioRewards::new();
+        for reward in poc_shares.into_rewards(&self.emissions, transfer_rewards.reward_sum(), epoch)
+        {
+            add_radio_reward(&mut rewards, reward);
+        }
+        Ok(rewards)
+    }
+}
+
+/// Reads previously written mobile reward share files, only counting the
+/// shares whose reward period matches the epoch.
+pub struct FileRewards {
+    paths: Vec<PathBuf>,
+}
+
+impl FileRewards {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self { paths }
+    }
+}
+
+#[async_trait::async_trait]
+impl RewardSource for FileRewards {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn radio_rewards(&self, epoch: &Range<DateTime<Utc>>) -> anyhow::Result<RadioRewards> {
+        let start_period = epoch.start.encode_timestamp();
+        let end_period = epoch.end.encode_timestamp();
+
+        let mut rewards = RadioRewards::new();
+        let mut shares = file_source::source(&self.paths);
+        while let Some(buf) = shares.try_next().await? {
+            let share = proto::MobileRewardShare::decode(buf)?;
+            if share.start_period == start_period && share.end_period == end_period {
+                add_radio_reward(&mut rewards, share);
+            }
+        }
+        Ok(rewards)
+    }
+}
+
+fn add_radio_reward(rewards: &mut RadioRewards, share: proto::MobileRewardShare) {
+    if let Some(proto::mobile_reward_share::Reward::RadioReward(proto::RadioReward {
+        hotspot_key,
+        poc_reward,
+        ..
+    })) = share.reward
+    {
+        *rewards.entry(hotspot_key.into()).or_default() += poc_reward;
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct GatewayDelta {
+    pub hotspot_key: String,
+    pub baseline: u64,
+    pub candidate: u64,
+    pub delta: i128,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RewardDiff {
+    pub baseline: &'static str,
+    pub candidate: &'static str,
+    pub baseline_total: u64,
+    pub candidate_total: u64,
+    pub total_delta: i128,
+    pub changed_gateways: usize,
+    pub deltas: Vec<GatewayDelta>,
+}
+
+/// A gateway whose rewards differ between the sources, as written to the
+/// reward diff file sink
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GatewayRewardDeltaV1 {
+    #[prost(bytes = "vec", tag = "1")]
+    pub hotspot_key: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub baseline: u64,
+    #[prost(uint64, tag = "3")]
+    pub candidate: u64,
+    /// Seconds since the epoch of the start of the compared epoch
+    #[prost(uint64, tag = "4")]
+    pub start_period: u64,
+    /// Seconds since the epoch of the end of the compared epoch
+    #[prost(uint64, tag = "5")]
+    pub end_period: u64,
+}
+
+impl RewardDiff {
+    /// Builds the diff of two sets of rewards. Only gateways whose rewards
+    /// differ are included in the deltas, ordered by hotspot key.
+    pub fn new(
+        baseline_name: &'static str,
+        baseline: &RadioRewards,
+        candidate_name: &'static str,
+        candidate: &RadioRewards,
+    ) -> Self {
+        let hotspots: BTreeSet<String> = baseline
+            .keys()
+            .chain(candidate.keys())
+            .map(|hotspot_key| hotspot_key.to_string())
+            .collect();
+        let baseline_by_key: HashMap<String, u64> = baseline
+            .iter()
+            .map(|(key, reward)| (key.to_string(), *reward))
+            .collect();
+        let candidate_by_key: HashMap<String, u64> = candidate
+            .iter()
+            .map(|(key, reward)| (key.to_string(), *reward))
+            .collect();
+
+        let deltas: Vec<_> = hotspots
+            .into_iter()
+            .filter_map(|hotspot_key| {
+                let baseline = baseline_by_key.get(&hotspot_key).copied().unwrap_or(0);
+                let candidate = candidate_by_key.get(&hotspot_key).copied().unwrap_or(0);
+                (baseline != candidate).then(|| GatewayDelta {
+                    delta: candidate as i128 - baseline as i128,
+                    hotspot_key,
+                    baseline,
+                    candidate,
+                })
+            })
+            .collect();
+
+        let baseline_total = baseline.values().sum();
+        let candidate_total = candidate.values().sum();
+        Self {
+            baseline: baseline_name,
+            candidate: candidate_name,
+            baseline_total,
+            candidate_total,
+            total_delta: candidate_total as i128 - baseline_total as i128,
+            changed_gateways: deltas.len(),
+            deltas,
+        }
+    }
+
+    /// Write a record for every gateway whose rewards differ to the sink and
+    /// commit it
+    pub async fn write(
+        &self,
+        sink: &FileSinkClient,
+        epoch: &Range<DateTime<Utc>>,
+    ) -> anyhow::Result<()> {
+        let start_period = epoch.start.encode_timestamp();
+        let end_period = epoch.end.encode_timestamp();
+        for delta in &self.deltas {
+            let hotspot_key: PublicKeyBinary = delta.hotspot_key.parse()?;
+            sink.write(
+                GatewayRewardDeltaV1 {
+                    hotspot_key: hotspot_key.into(),
+                    baseline: delta.baseline,
+                    candidate: delta.candidate,
+                    start_period,
+                    end_period,
+                },
+                [],
+            )
+            .await?
+            .await??;
+        }
+        sink.commit().await?.await??;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    const HOTSPOT_1: &str = "112NqN2WWMwtK29PMzRby62fDydBJfsCLkCAf392stdok48ovNT6";
+    const HOTSPOT_2: &str = "11eX55faMbqZB7jzN4p67m6w7ScPMH6ubnvCjCPLh72J49PaJEL";
+    const HOTSPOT_3: &str = "11sctWiP9r5wDJVuDe1Th4XSL2vaawaLLSQF8f8iokAoMAJHxqp";
+
+    fn hotspot(key: &str) -> PublicKeyBinary {
+        PublicKeyBinary::from_str(key).unwrap()
+    }
+
+    #[test]
+    fn identical_rewards_have_no_diff() {
+        let rewards = RadioRewards::from([(hotspot(HOTSPOT_1), 10), (hotspot(HOTSPOT_2), 20)]);
+        let diff = RewardDiff::new("baseline", &rewards, "candidate", &rewards);
+
+        assert!(diff.deltas.is_empty());
+        assert_eq!(diff.baseline_total, 30);
+        assert_eq!(diff.total_delta, 0);
+    }
+
+    #[test]
+    fn diff_includes_added_removed_and_changed_gateways() {
+        let baseline = RadioRewards::from([(hotspot(HOTSPOT_1), 10), (hotspot(HOTSPOT_2), 20)]);
+        let candidate = RadioRewards::from([(hotspot(HOTSPOT_2), 25), (hotspot(HOTSPOT_3), 5)]);
+        let diff = RewardDiff::new("baseline", &baseline, "candidate", &candidate);
+
+        assert_eq!(diff.baseline_total, 30);
+        assert_eq!(diff.candidate_total, 30);
+        assert_eq!(diff.total_delta, 0);
+        assert_eq!(diff.changed_gateways, 3);
+
+        let delta = |key: &str| {
+            diff.deltas
+                .iter()
+                .find(|delta| delta.hotspot_key == key)
+                .map(|delta| delta.delta)
+        };
+        assert_eq!(delta(HOTSPOT_1), Some(-10));
+        assert_eq!(delta(HOTSPOT_2), Some(5));
+        assert_eq!(delta(HOTSPOT_3), Some(5));
+    }
+}
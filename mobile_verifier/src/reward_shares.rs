@@ -1,40 +1,76 @@
 use crate::{
+    cell_type::CellType,
     data_session::HotspotMap,
+    emissions::EmissionSchedule,
     heartbeats::HeartbeatReward,
-    speedtests::{Average, SpeedtestAverages},
+    reward_smoothing::SmoothingConfig,
+    speedtests::{InsufficientSpeedtests, SpeedtestAverages},
     subscriber_location::SubscriberValidatedLocations,
 };
 
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Utc};
+use dc_pricing::DcPricing;
 use file_store::traits::TimestampEncode;
 use futures::{Stream, StreamExt};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::services::poc_mobile as proto;
 use helium_proto::services::poc_mobile::mobile_reward_share::Reward as ProtoReward;
+use reward_scheduler::reward_math::{self, to_bones};
 use rust_decimal::prelude::*;
 use rust_decimal_macros::dec;
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::ops::Range;
 
-/// Total tokens emissions pool per 365 days
-const TOTAL_EMISSIONS_POOL: Decimal = dec!(60_000_000_000_000_000);
-
 /// Maximum amount of the total emissions pool allocated for data transfer
 /// rewards
 const MAX_DATA_TRANSFER_REWARDS_PERCENT: Decimal = dec!(0.4);
 
-/// The fixed price of a mobile data credit
-const DC_USD_PRICE: Decimal = dec!(0.00001);
-
 /// Default precision used for rounding
 const DEFAULT_PREC: u32 = 15;
 
-// Percent of total emissions allocated for mapper rewards
-const MAPPERS_REWARDS_PERCENT: Decimal = dec!(0.2);
-
 /// shares of the mappers pool allocated per eligble subscriber for discovery mapping
 const DISCOVERY_MAPPING_SHARES: Decimal = dec!(30);
 
+/// Reward parameters which can be tuned through settings
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RewardConfig {
+    /// Overrides for the default reward multiplier of a cell type, keyed by
+    /// the cell type name in any case, e.g. `SercommOutdoor = 3.0`
+    #[serde(default, deserialize_with = "deserialize_cell_type_multipliers")]
+    pub cell_type_multipliers: HashMap<CellType, Decimal>,
+    /// Smooth the poc shares of the radios over trailing epochs. Disabled
+    /// when not set
+    pub smoothing: Option<SmoothingConfig>,
+}
+
+impl RewardConfig {
+    pub fn cell_type_multiplier(&self, cell_type: &CellType) -> Decimal {
+        self.cell_type_multipliers
+            .get(cell_type)
+            .copied()
+            .unwrap_or_else(|| cell_type.reward_weight())
+    }
+}
+
+/// The config crate lowercases the keys of tables, so the cell types are
+/// matched ignoring case
+fn deserialize_cell_type_multipliers<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<CellType, Decimal>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    HashMap::<String, Decimal>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, multiplier)| {
+            CellType::from_name(&name)
+                .map(|cell_type| (cell_type, multiplier))
+                .ok_or_else(|| serde::de::Error::custom(format!("unknown cell type: {name}")))
+        })
+        .collect()
+}
+
 pub struct TransferRewards {
     reward_scale: Decimal,
     rewards: HashMap<PublicKeyBinary, Decimal>,
@@ -57,25 +93,36 @@ impl TransferRewards {
 
     pub async fn from_transfer_sessions(
         mobile_bone_price: Decimal,
+        pricing: &DcPricing,
         transfer_sessions: HotspotMap,
         hotspots: &PocShares,
+        emissions: &EmissionSchedule,
         epoch: &Range<DateTime<Utc>>,
     ) -> Self {
+        let total_emissions_pool = emissions.total(epoch);
+        if total_emissions_pool.is_zero() {
+            // Nothing is emitted before genesis or by a schedule without
+            // emissions, so there is nothing to reward data transfer with
+            return Self {
+                reward_scale: Decimal::ONE,
+                rewards: HashMap::new(),
+                reward_sum: Decimal::ZERO,
+            };
+        }
+
         let mut reward_sum = Decimal::ZERO;
         let rewards = transfer_sessions
             .into_iter()
             .filter(|(pub_key, _)| hotspots.is_valid(pub_key))
             // Calculate rewards per hotspot
             .map(|(pub_key, dc_amount)| {
-                let bones = dc_to_mobile_bones(Decimal::from(dc_amount), mobile_bone_price);
+                let bones =
+                    dc_to_mobile_bones(Decimal::from(dc_amount), mobile_bone_price, pricing);
                 reward_sum += bones;
                 (pub_key, bones)
             })
             .collect();
 
-        let duration = epoch.end - epoch.start;
-        let total_emissions_pool = get_total_scheduled_tokens(duration);
-
         // Determine if we need to scale the rewards given for data transfer rewards.
         // Ideally this should never happen, but if the total number of data transfer rewards
         // is greater than (at the time of writing) 40% of the total pool, we need to scale
@@ -111,25 +158,27 @@ impl TransferRewards {
         let Self {
             reward_scale,
             rewards,
-            ..
+            reward_sum,
         } = self;
         let start_period = epoch.start.encode_timestamp();
         let end_period = epoch.end.encode_timestamp();
-        rewards
+        let rewards = rewards
             .into_iter()
-            .map(move |(hotspot_key, reward)| proto::MobileRewardShare {
-                start_period,
-                end_period,
-                reward: Some(proto::mobile_reward_share::Reward::GatewayReward(
-                    proto::GatewayReward {
-                        hotspot_key: hotspot_key.into(),
-                        dc_transfer_reward: (reward * reward_scale)
-                            .round_dp_with_strategy(0, RoundingStrategy::ToZero)
-                            .to_u64()
-                            .unwrap_or(0),
-                    },
-                )),
-            })
+            .map(|(hotspot_key, reward)| (Vec::<u8>::from(hotspot_key), reward * reward_scale));
+        sorted_by_key(reward_math::allocate(to_bones(reward_sum), rewards))
+            .into_iter()
+            .map(
+                move |(hotspot_key, dc_transfer_reward)| proto::MobileRewardShare {
+                    start_period,
+                    end_period,
+                    reward: Some(proto::mobile_reward_share::Reward::GatewayReward(
+                        proto::GatewayReward {
+                            hotspot_key,
+                            dc_transfer_reward,
+                        },
+                    )),
+                },
+            )
     }
 }
 
@@ -147,14 +196,14 @@ impl MapperShares {
 
     pub fn rewards_per_share(
         &self,
+        emissions: &EmissionSchedule,
         reward_period: &'_ Range<DateTime<Utc>>,
     ) -> anyhow::Result<Decimal> {
         // note: currently rewards_per_share calculation only takes into
         // consideration discovery mapping shares
         // in the future it will also need to take into account
         // verification mapping shares
-        let duration: Duration = reward_period.end - reward_period.start;
-        let total_mappers_pool = get_scheduled_tokens_for_mappers(duration);
+        let total_mappers_pool = emissions.mappers(reward_period);
 
         // the number of subscribers eligible for discovery location rewards hihofe
         let discovery_mappers_count = Decimal::from(self.discovery_mapping_shares.len());
@@ -175,34 +224,93 @@ impl MapperShares {
         reward_period: &'_ Range<DateTime<Utc>>,
         reward_per_share: Decimal,
     ) -> impl Iterator<Item = proto::MobileRewardShare> + '_ {
-        self.discovery_mapping_shares
+        let total_mapper_shares =
+            Decimal::from(self.discovery_mapping_shares.len()) * DISCOVERY_MAPPING_SHARES;
+        let shares = self
+            .discovery_mapping_shares
             .into_iter()
-            .map(move |subscriber_id| proto::SubscriberReward {
+            .map(|subscriber_id| (subscriber_id, DISCOVERY_MAPPING_SHARES));
+        sorted_by_key(reward_math::allocate(
+            to_bones(reward_per_share * total_mapper_shares),
+            shares,
+        ))
+        .into_iter()
+        .map(
+            |(subscriber_id, discovery_location_amount)| proto::SubscriberReward {
                 subscriber_id,
-                discovery_location_amount: (DISCOVERY_MAPPING_SHARES * reward_per_share)
-                    .round_dp_with_strategy(0, RoundingStrategy::ToZero)
-                    .to_u64()
-                    .unwrap_or(0),
-            })
-            .filter(|subscriber_reward| subscriber_reward.discovery_location_amount > 0)
-            .map(|subscriber_reward| proto::MobileRewardShare {
-                start_period: reward_period.start.encode_timestamp(),
-                end_period: reward_period.end.encode_timestamp(),
-                reward: Some(ProtoReward::SubscriberReward(subscriber_reward)),
-            })
+                discovery_location_amount,
+            },
+        )
+        .filter(|subscriber_reward| subscriber_reward.discovery_location_amount > 0)
+        .map(|subscriber_reward| proto::MobileRewardShare {
+            start_period: reward_period.start.encode_timestamp(),
+            end_period: reward_period.end.encode_timestamp(),
+            reward: Some(ProtoReward::SubscriberReward(subscriber_reward)),
+        })
     }
 }
 
+/// Rewards of the service providers for the epoch. Helium Mobile is currently
+/// the only service provider and receives the full service provider pool
+pub fn into_service_provider_rewards<'a>(
+    emissions: &EmissionSchedule,
+    reward_period: &'a Range<DateTime<Utc>>,
+) -> impl Iterator<Item = proto::MobileRewardShare> + 'a {
+    let amount = to_bones(emissions.service_providers(reward_period));
+    [(proto::ServiceProvider::HeliumMobile, amount)]
+        .into_iter()
+        .filter(|(_, amount)| *amount > 0)
+        .map(move |(service_provider, amount)| proto::MobileRewardShare {
+            start_period: reward_period.start.encode_timestamp(),
+            end_period: reward_period.end.encode_timestamp(),
+            reward: Some(ProtoReward::ServiceProviderReward(
+                proto::ServiceProviderReward {
+                    service_provider_id: service_provider as i32,
+                    amount,
+                },
+            )),
+        })
+}
+
+/// Order allocations by their key, so rewards are written in the same order
+/// on every run instead of the iteration order of the shares
+fn sorted_by_key<K: Ord>(mut allocations: Vec<(K, u64)>) -> Vec<(K, u64)> {
+    allocations.sort_by(|(a, _), (b, _)| a.cmp(b));
+    allocations
+}
+
 /// Returns the equivalent amount of Mobile bones for a specified amount of Data Credits
-pub fn dc_to_mobile_bones(dc_amount: Decimal, mobile_bone_price: Decimal) -> Decimal {
-    let dc_in_usd = dc_amount * DC_USD_PRICE;
+pub fn dc_to_mobile_bones(
+    dc_amount: Decimal,
+    mobile_bone_price: Decimal,
+    pricing: &DcPricing,
+) -> Decimal {
+    let dc_in_usd = pricing.dc_to_usd(dc_amount);
     (dc_in_usd / mobile_bone_price)
         .round_dp_with_strategy(DEFAULT_PREC, RoundingStrategy::ToPositiveInfinity)
 }
 
+/// Bones rewarded by a mobile reward share, whatever its reward type
+pub fn reward_amount(share: &proto::MobileRewardShare) -> u64 {
+    match &share.reward {
+        Some(ProtoReward::RadioReward(reward)) => reward.poc_reward,
+        Some(ProtoReward::GatewayReward(reward)) => reward.dc_transfer_reward,
+        Some(ProtoReward::SubscriberReward(reward)) => reward.discovery_location_amount,
+        Some(ProtoReward::ServiceProviderReward(reward)) => reward.amount,
+        None => 0,
+    }
+}
+
+/// Bones rewarded by an encoded mobile reward share, as read back by the
+/// canary of the reward files
+pub fn encoded_reward_amount(buf: &[u8]) -> Result<u64, prost::DecodeError> {
+    use prost::Message;
+    proto::MobileRewardShare::decode(buf).map(|share| reward_amount(&share))
+}
+
 #[derive(Default)]
 pub struct RadioShares {
-    radio_shares: HashMap<String, Decimal>,
+    pub radio_shares: HashMap<String, Decimal>,
 }
 
 impl RadioShares {
@@ -213,9 +321,40 @@ impl RadioShares {
     }
 }
 
+/// Reason of the radios without poc rewards for too few recent speedtests of
+/// their hotspot
+pub const INSUFFICIENT_SPEEDTESTS: &str = "insufficient_speedtests";
+
+/// Radio left without poc rewards in a reward period, written to the invalid
+/// radio reward share files. Defined here until helium-proto carries it, with
+/// the same field numbers
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InvalidRadioRewardShareV1 {
+    /// Seconds since the epoch of the start of the reward period
+    #[prost(uint64, tag = "1")]
+    pub start_period: u64,
+    /// Seconds since the epoch of the end of the reward period
+    #[prost(uint64, tag = "2")]
+    pub end_period: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    pub hotspot_key: Vec<u8>,
+    #[prost(string, tag = "4")]
+    pub cbsd_id: String,
+    #[prost(string, tag = "5")]
+    pub reason: String,
+    /// Recent speedtests of the hotspot
+    #[prost(uint32, tag = "6")]
+    pub speedtests: u32,
+    /// Recent speedtests required for a non zero speedtest multiplier
+    #[prost(uint32, tag = "7")]
+    pub required_speedtests: u32,
+}
+
 #[derive(Default)]
 pub struct PocShares {
     pub hotspot_shares: HashMap<PublicKeyBinary, RadioShares>,
+    /// Radios of hotspots with too few recent speedtests, which have no shares
+    pub insufficient_speedtests: HashMap<(PublicKeyBinary, String), InsufficientSpeedtests>,
 }
 
 impl PocShares {
@@ -226,10 +365,16 @@ impl PocShares {
         let mut poc_shares = Self::default();
         let mut heartbeats = std::pin::pin!(heartbeats);
         while let Some(heartbeat) = heartbeats.next().await.transpose()? {
-            let speedmultiplier = speedtests
-                .get_average(&heartbeat.hotspot_key)
-                .as_ref()
-                .map_or(Decimal::ZERO, Average::reward_multiplier);
+            let speedmultiplier = match speedtests.reward_multiplier(&heartbeat.hotspot_key) {
+                Ok(multiplier) => multiplier,
+                Err(insufficient) => {
+                    poc_shares.insufficient_speedtests.insert(
+                        (heartbeat.hotspot_key.clone(), heartbeat.cbsd_id.clone()),
+                        insufficient,
+                    );
+                    Decimal::ZERO
+                }
+            };
             *poc_shares
                 .hotspot_shares
                 .entry(heartbeat.hotspot_key)
@@ -249,6 +394,14 @@ impl PocShares {
         }
     }
 
+    /// Number of radios with shares
+    pub fn radios(&self) -> usize {
+        self.hotspot_shares
+            .values()
+            .map(|shares| shares.radio_shares.len())
+            .sum()
+    }
+
     pub fn total_shares(&self) -> Decimal {
         self.hotspot_shares
             .values()
@@ -257,74 +410,82 @@ impl PocShares {
             })
     }
 
-    pub fn into_rewards(
+    /// Invalid shares of the radios without rewards for too few recent
+    /// speedtests, ordered by hotspot and cbsd
+    pub fn invalid_shares(&self, epoch: &Range<DateTime<Utc>>) -> Vec<InvalidRadioRewardShareV1> {
+        let start_period = epoch.start.encode_timestamp();
+        let end_period = epoch.end.encode_timestamp();
+        let mut invalid_shares: Vec<_> = self
+            .insufficient_speedtests
+            .iter()
+            .map(
+                |((hotspot_key, cbsd_id), insufficient)| InvalidRadioRewardShareV1 {
+                    start_period,
+                    end_period,
+                    hotspot_key: hotspot_key.clone().into(),
+                    cbsd_id: cbsd_id.clone(),
+                    reason: INSUFFICIENT_SPEEDTESTS.to_string(),
+                    speedtests: insufficient.speedtests as u32,
+                    required_speedtests: insufficient.required as u32,
+                },
+            )
+            .collect();
+        invalid_shares
+            .sort_by(|a, b| (&a.hotspot_key, &a.cbsd_id).cmp(&(&b.hotspot_key, &b.cbsd_id)));
+        invalid_shares
+    }
+
+    pub fn into_rewards<'a>(
         self,
+        emissions: &EmissionSchedule,
         transfer_rewards_sum: Decimal,
-        epoch: &'_ Range<DateTime<Utc>>,
-    ) -> impl Iterator<Item = proto::MobileRewardShare> + '_ {
-        let total_shares = self.total_shares();
+        epoch: &'a Range<DateTime<Utc>>,
+    ) -> impl Iterator<Item = proto::MobileRewardShare> + 'a {
+        // The data transfer rewards are paid out in whole bones as well, so
+        // together both use up the whole pool
         let available_poc_rewards =
-            get_scheduled_tokens_for_poc_and_dc(epoch.end - epoch.start) - transfer_rewards_sum;
-        let poc_rewards_per_share = available_poc_rewards / total_shares;
+            to_bones(emissions.poc_and_dc(epoch)).saturating_sub(to_bones(transfer_rewards_sum));
         let start_period = epoch.start.encode_timestamp();
         let end_period = epoch.end.encode_timestamp();
-        self.hotspot_shares
+        let radio_shares = self.hotspot_shares.into_iter().flat_map(
+            |(hotspot_key, RadioShares { radio_shares })| {
+                let hotspot_key: Vec<u8> = hotspot_key.into();
+                radio_shares
+                    .into_iter()
+                    .map(move |(cbsd_id, amount)| ((hotspot_key.clone(), cbsd_id), amount))
+            },
+        );
+        sorted_by_key(reward_math::allocate(available_poc_rewards, radio_shares))
             .into_iter()
-            .flat_map(move |(hotspot_key, RadioShares { radio_shares })| {
-                radio_shares.into_iter().map(move |(cbsd_id, amount)| {
-                    let poc_reward = poc_rewards_per_share * amount;
-                    let hotspot_key: Vec<u8> = hotspot_key.clone().into();
-                    proto::MobileRewardShare {
-                        start_period,
-                        end_period,
-                        reward: Some(proto::mobile_reward_share::Reward::RadioReward(
-                            proto::RadioReward {
-                                hotspot_key,
-                                cbsd_id,
-                                poc_reward: poc_reward
-                                    .round_dp_with_strategy(0, RoundingStrategy::ToZero)
-                                    .to_u64()
-                                    .unwrap_or(0),
-                                ..Default::default()
-                            },
-                        )),
-                    }
-                })
-            })
-            .filter(|mobile_reward| match mobile_reward.reward {
-                Some(proto::mobile_reward_share::Reward::RadioReward(ref radio_reward)) => {
-                    radio_reward.poc_reward > 0
-                }
-                _ => false,
-            })
+            .filter(|(_, poc_reward)| *poc_reward > 0)
+            .map(
+                move |((hotspot_key, cbsd_id), poc_reward)| proto::MobileRewardShare {
+                    start_period,
+                    end_period,
+                    reward: Some(proto::mobile_reward_share::Reward::RadioReward(
+                        proto::RadioReward {
+                            hotspot_key,
+                            cbsd_id,
+                            poc_reward,
+                            ..Default::default()
+                        },
+                    )),
+                },
+            )
     }
 }
 
-pub fn get_total_scheduled_tokens(duration: Duration) -> Decimal {
-    (TOTAL_EMISSIONS_POOL / dec!(365) / Decimal::from(Duration::hours(24).num_seconds()))
-        * Decimal::from(duration.num_seconds())
-}
-
-pub fn get_scheduled_tokens_for_poc_and_dc(duration: Duration) -> Decimal {
-    get_total_scheduled_tokens(duration) * dec!(0.6)
-}
-
-pub fn get_scheduled_tokens_for_mappers(duration: Duration) -> Decimal {
-    get_total_scheduled_tokens(duration) * MAPPERS_REWARDS_PERCENT
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::{
-        cell_type::CellType,
         data_session,
         data_session::HotspotDataSession,
         heartbeats::HeartbeatReward,
         speedtests::{Speedtest, SpeedtestAverages},
         subscriber_location::SubscriberValidatedLocations,
     };
-    use chrono::{Duration, Utc};
+    use chrono::{Duration, TimeZone, Utc};
     use futures::stream;
     use helium_proto::services::poc_mobile::mobile_reward_share::Reward as MobileReward;
     use prost::Message;
@@ -336,14 +497,71 @@ mod test {
         RadioShares { radio_shares }
     }
 
+    #[test]
+    fn reward_config_overrides_cell_type_multiplier() {
+        let reward_config = RewardConfig {
+            cell_type_multipliers: HashMap::from([(CellType::SercommOutdoor, dec!(3.0))]),
+            ..Default::default()
+        };
+        assert_eq!(
+            reward_config.cell_type_multiplier(&CellType::SercommOutdoor),
+            dec!(3.0)
+        );
+        assert_eq!(
+            reward_config.cell_type_multiplier(&CellType::Nova436H),
+            CellType::Nova436H.reward_weight()
+        );
+        assert_eq!(
+            RewardConfig::default().cell_type_multiplier(&CellType::SercommOutdoor),
+            dec!(2.5)
+        );
+    }
+
+    #[test]
+    fn cell_type_multipliers_are_read_through_config() {
+        let reward_config: RewardConfig = config::Config::builder()
+            .add_source(config::File::from_str(
+                r#"
+                [cell_type_multipliers]
+                SercommOutdoor = 3.0
+                nova436h = 5.0
+                "#,
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .unwrap();
+        assert_eq!(
+            reward_config.cell_type_multiplier(&CellType::SercommOutdoor),
+            dec!(3.0)
+        );
+        assert_eq!(
+            reward_config.cell_type_multiplier(&CellType::Nova436H),
+            dec!(5.0)
+        );
+        assert_eq!(
+            reward_config.cell_type_multiplier(&CellType::SercommIndoor),
+            CellType::SercommIndoor.reward_weight()
+        );
+
+        let unknown = config::Config::builder()
+            .add_source(config::File::from_str(
+                "[cell_type_multipliers]\nNova = 1.0",
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .and_then(|config| config.try_deserialize::<RewardConfig>());
+        assert!(unknown.is_err());
+    }
+
     #[test]
     fn bytes_to_bones() {
         assert_eq!(
-            dc_to_mobile_bones(Decimal::from(1), dec!(1.0)),
+            dc_to_mobile_bones(Decimal::from(1), dec!(1.0), &DcPricing::mobile()),
             dec!(0.00001)
         );
         assert_eq!(
-            dc_to_mobile_bones(Decimal::from(2), dec!(1.0)),
+            dc_to_mobile_bones(Decimal::from(2), dec!(1.0), &DcPricing::mobile()),
             dec!(0.00002)
         );
     }
@@ -368,17 +586,22 @@ mod test {
 
         // translate location shares into discovery mapping shares
         let mapping_shares = MapperShares::new(location_shares);
-        let rewards_per_share = mapping_shares.rewards_per_share(&epoch).unwrap();
+        let emissions = EmissionSchedule::default();
+        let rewards_per_share = mapping_shares
+            .rewards_per_share(&emissions, &epoch)
+            .unwrap();
 
         // verify total rewards for the epoch
-        let total_epoch_rewards = get_total_scheduled_tokens(epoch.end - epoch.start)
+        let total_epoch_rewards = emissions
+            .total(&epoch)
             .round_dp_with_strategy(0, RoundingStrategy::ToZero)
             .to_u64()
             .unwrap_or(0);
         assert_eq!(164_383_561_643_835, total_epoch_rewards);
 
         // verify total rewards allocated to mappers the epoch
-        let total_mapper_rewards = get_scheduled_tokens_for_mappers(epoch.end - epoch.start)
+        let total_mapper_rewards = emissions
+            .mappers(&epoch)
             .round_dp_with_strategy(0, RoundingStrategy::ToZero)
             .to_u64()
             .unwrap_or(0);
@@ -388,22 +611,139 @@ mod test {
 
         // get the summed rewards allocated to subscribers for discovery location
         let mut total_discovery_mapping_rewards = 0_u64;
+        let mut rounded_up = 0_u64;
         for subscriber_share in mapping_shares.into_subscriber_rewards(&epoch, rewards_per_share) {
             if let Some(MobileReward::SubscriberReward(r)) = subscriber_share.reward {
                 total_discovery_mapping_rewards += r.discovery_location_amount;
-                assert_eq!(expected_reward_per_subscriber, r.discovery_location_amount);
+                if r.discovery_location_amount == expected_reward_per_subscriber + 1 {
+                    rounded_up += 1;
+                } else {
+                    assert_eq!(expected_reward_per_subscriber, r.discovery_location_amount);
+                }
             }
         }
 
-        // verify the total rewards awared for discovery mapping
-        assert_eq!(32_876_712_320_000, total_discovery_mapping_rewards);
+        // the bones lost truncating each subscriber's reward are handed out
+        // one each, so the full epoch amount is distributed
+        assert_eq!(total_mapper_rewards, total_discovery_mapping_rewards);
+        assert_eq!(rounded_up, total_mapper_rewards % NUM_SUBSCRIBERS);
+    }
+
+    #[test]
+    fn service_provider_reward_amount() {
+        let now = Utc::now();
+        let epoch = (now - Duration::hours(24))..now;
+
+        let rewards: Vec<_> =
+            into_service_provider_rewards(&EmissionSchedule::default(), &epoch).collect();
+        assert_eq!(rewards.len(), 1);
+        let Some(MobileReward::ServiceProviderReward(reward)) = &rewards[0].reward else {
+            panic!("expected a service provider reward");
+        };
+        assert_eq!(
+            reward.service_provider_id,
+            proto::ServiceProvider::HeliumMobile as i32
+        );
+        // 10% of the 164_383_561_643_835 bones emitted per day
+        assert_eq!(reward.amount, 16_438_356_164_383);
+    }
+
+    #[tokio::test]
+    async fn reward_output_is_reproducible() {
+        let gateways: Vec<PublicKeyBinary> = [
+            "112NqN2WWMwtK29PMzRby62fDydBJfsCLkCAf392stdok48ovNT6",
+            "11sctWiP9r5wDJVuDe1Th4XSL2vaawaLLSQF8f8iokAoMAJHxqp",
+            "112DJZiXvZ8FduiWrEi8siE3wJX6hpRjjtwbavyXUDkgutEUSLAE",
+            "112p1GbUtRLyfFaJr1XF8fH7yz9cSZ4exbrSpVDeu67DeGb31QUL",
+            "112j1iw1sV2B2Tz2DxPSeum9Cmc5kMKNdDTDg1zDRsdwuvZueq3B",
+        ]
+        .iter()
+        .map(|key| key.parse().expect("failed gateway parse"))
+        .collect();
+        let end = Utc.timestamp_opt(1_686_000_000, 0).unwrap();
+        let epoch = (end - Duration::hours(24))..end;
+
+        // Every run aggregates into maps of its own, iterated in their own
+        // order
+        async fn encoded_rewards(
+            gateways: &[PublicKeyBinary],
+            epoch: &Range<DateTime<Utc>>,
+        ) -> Vec<Vec<u8>> {
+            let emissions = EmissionSchedule::default();
+            let mut hotspot_shares = HashMap::new();
+            let mut data_transfers = HotspotMap::new();
+            for (i, gateway) in gateways.iter().enumerate() {
+                let weight = Decimal::from(i + 1);
+                let radio_shares = [("radio-a", weight), ("radio-b", weight * dec!(2))]
+                    .into_iter()
+                    .map(|(cbsd_id, shares)| (cbsd_id.to_string(), shares))
+                    .collect();
+                hotspot_shares.insert(gateway.clone(), RadioShares { radio_shares });
+                data_transfers.insert(gateway.clone(), 1_000 * (i as u64 + 1));
+            }
+            let poc_shares = PocShares {
+                hotspot_shares,
+                ..Default::default()
+            };
+            let transfer_rewards = TransferRewards::from_transfer_sessions(
+                dec!(1.0),
+                &DcPricing::mobile(),
+                data_transfers,
+                &poc_shares,
+                &emissions,
+                epoch,
+            )
+            .await;
+            let mut location_shares = SubscriberValidatedLocations::new();
+            for subscriber in (0..7_u64).rev() {
+                location_shares.push(subscriber.encode_to_vec());
+            }
+            let mapper_shares = MapperShares::new(location_shares);
+            let rewards_per_share = mapper_shares.rewards_per_share(&emissions, epoch).unwrap();
+
+            poc_shares
+                .into_rewards(&emissions, transfer_rewards.reward_sum(), epoch)
+                .chain(transfer_rewards.into_rewards(epoch))
+                .chain(into_service_provider_rewards(&emissions, epoch))
+                .chain(mapper_shares.into_subscriber_rewards(epoch, rewards_per_share))
+                .map(|reward| reward.encode_to_vec())
+                .collect()
+        }
+        let first = encoded_rewards(&gateways, &epoch).await;
+        assert_eq!(first, encoded_rewards(&gateways, &epoch).await);
 
-        // the sum of rewards distributed should not exceed the epoch amount
-        // but due to rounding whilst going to u64 for each subscriber,
-        // we will be some bones short of the full epoch amount
-        // the difference in bones cannot be more than the total number of subscribers ( 10 k)
-        let diff = total_mapper_rewards - total_discovery_mapping_rewards;
-        assert!(diff < NUM_SUBSCRIBERS);
+        // and the rewards of each kind are written in key order
+        let rewards: Vec<_> = first
+            .iter()
+            .map(|buf| proto::MobileRewardShare::decode(buf.as_slice()).unwrap())
+            .collect();
+        let radios: Vec<_> = rewards
+            .iter()
+            .filter_map(|reward| match &reward.reward {
+                Some(MobileReward::RadioReward(r)) => Some((r.hotspot_key.clone(), &r.cbsd_id)),
+                _ => None,
+            })
+            .collect();
+        let gateway_keys: Vec<_> = rewards
+            .iter()
+            .filter_map(|reward| match &reward.reward {
+                Some(MobileReward::GatewayReward(r)) => Some(r.hotspot_key.clone()),
+                _ => None,
+            })
+            .collect();
+        let subscribers: Vec<_> = rewards
+            .iter()
+            .filter_map(|reward| match &reward.reward {
+                Some(MobileReward::SubscriberReward(r)) => Some(r.subscriber_id.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(radios.len(), 2 * gateways.len());
+        assert_eq!(gateway_keys.len(), gateways.len());
+        assert_eq!(subscribers.len(), 7);
+        assert!(radios.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(gateway_keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(subscribers.windows(2).all(|pair| pair[0] < pair[1]));
     }
 
     #[tokio::test]
@@ -432,11 +772,14 @@ mod test {
 
         let mut hotspot_shares = HashMap::default();
         hotspot_shares.insert(owner.clone(), valid_shares());
-        let poc_shares = PocShares { hotspot_shares };
+        let poc_shares = PocShares {
+            hotspot_shares,
+            ..Default::default()
+        };
 
         let now = Utc::now();
         let epoch = (now - Duration::hours(1))..now;
-        let total_rewards = get_scheduled_tokens_for_poc_and_dc(epoch.end - epoch.start);
+        let total_rewards = EmissionSchedule::default().poc_and_dc(&epoch);
 
         // confirm our hourly rewards add up to expected 24hr amount
         // total_rewards will be in bones
@@ -447,16 +790,18 @@ mod test {
 
         let data_transfer_rewards = TransferRewards::from_transfer_sessions(
             dec!(1.0),
+            &DcPricing::mobile(),
             data_transfer_map,
             &poc_shares,
+            &EmissionSchedule::default(),
             &epoch,
         )
         .await;
 
         assert_eq!(data_transfer_rewards.reward(&owner), dec!(0.00002));
         assert_eq!(data_transfer_rewards.reward_scale(), dec!(1.0));
-        let available_poc_rewards = get_scheduled_tokens_for_poc_and_dc(epoch.end - epoch.start)
-            - data_transfer_rewards.reward_sum;
+        let available_poc_rewards =
+            EmissionSchedule::default().poc_and_dc(&epoch) - data_transfer_rewards.reward_sum;
         assert_eq!(
             available_poc_rewards,
             total_rewards
@@ -496,12 +841,17 @@ mod test {
 
         let mut hotspot_shares = HashMap::default();
         hotspot_shares.insert(owner.clone(), valid_shares());
-        let poc_shares = PocShares { hotspot_shares };
+        let poc_shares = PocShares {
+            hotspot_shares,
+            ..Default::default()
+        };
 
         let data_transfer_rewards = TransferRewards::from_transfer_sessions(
             dec!(1.0),
+            &DcPricing::mobile(),
             aggregated_data_transfer_sessions,
             &poc_shares,
+            &EmissionSchedule::default(),
             &epoch,
         )
         .await;
@@ -510,8 +860,8 @@ mod test {
         // allotted reward amount for data transfer, which is 40% of the daily tokens. We check to
         // ensure that amount of tokens remaining for POC is no less than 20% of the rewards allocated
         // for POC and data transfer (which is 60% of the daily total emissions).
-        let available_poc_rewards = get_scheduled_tokens_for_poc_and_dc(epoch.end - epoch.start)
-            - data_transfer_rewards.reward_sum;
+        let available_poc_rewards =
+            EmissionSchedule::default().poc_and_dc(&epoch) - data_transfer_rewards.reward_sum;
         assert_eq!(available_poc_rewards.trunc(), dec!(32_876_712_328_767));
         assert_eq!(
             // Rewards are automatically scaled
@@ -521,6 +871,46 @@ mod test {
         assert_eq!(data_transfer_rewards.reward_scale().round_dp(1), dec!(0.5));
     }
 
+    #[tokio::test]
+    async fn transfer_rewards_of_zero_emission_epochs() {
+        let owner: PublicKeyBinary = "112NqN2WWMwtK29PMzRby62fDydBJfsCLkCAf392stdok48ovNT6"
+            .parse()
+            .expect("failed owner parse");
+        let mut hotspot_shares = HashMap::default();
+        hotspot_shares.insert(owner.clone(), valid_shares());
+        let poc_shares = PocShares {
+            hotspot_shares,
+            ..Default::default()
+        };
+
+        let now = Utc::now();
+        let epoch = (now - Duration::hours(24))..now;
+        let before_genesis = EmissionSchedule {
+            genesis: (now + Duration::hours(1)).timestamp() as u64,
+            ..Default::default()
+        };
+        let no_emissions = EmissionSchedule {
+            yearly_emissions: Decimal::ZERO,
+            ..Default::default()
+        };
+
+        for emissions in [before_genesis, no_emissions] {
+            let data_transfer_rewards = TransferRewards::from_transfer_sessions(
+                dec!(1.0),
+                &DcPricing::mobile(),
+                HotspotMap::from([(owner.clone(), 1_000)]),
+                &poc_shares,
+                &emissions,
+                &epoch,
+            )
+            .await;
+
+            assert_eq!(data_transfer_rewards.reward(&owner), Decimal::ZERO);
+            assert_eq!(data_transfer_rewards.reward_sum, Decimal::ZERO);
+            assert_eq!(data_transfer_rewards.into_rewards(&epoch).count(), 0);
+        }
+    }
+
     fn bytes_per_s(mbps: i64) -> i64 {
         mbps * 125000
     }
@@ -567,6 +957,71 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn radios_with_too_few_speedtests_get_invalid_shares() {
+        let g1: PublicKeyBinary = "11eX55faMbqZB7jzN4p67m6w7ScPMH6ubnvCjCPLh72J49PaJEL"
+            .parse()
+            .expect("unable to construct pubkey");
+        let g2: PublicKeyBinary = "118SPA16MX8WrUKcuXxsg6SH8u5dWszAySiUAJX6tTVoQVy7nWc"
+            .parse()
+            .expect("unable to construct pubkey");
+        let c1 = "P27-SCE4255W2107CW5000014".to_string();
+        let c2 = "2AG32PBS3101S1202000464223GY0153".to_string();
+        let heartbeats = || {
+            vec![
+                HeartbeatReward {
+                    cbsd_id: c1.clone(),
+                    hotspot_key: g1.clone(),
+                    reward_weight: cell_type_weight(&c1),
+                },
+                HeartbeatReward {
+                    cbsd_id: c2.clone(),
+                    hotspot_key: g2.clone(),
+                    reward_weight: cell_type_weight(&c2),
+                },
+            ]
+        };
+
+        let timestamp = Utc::now();
+        let mut speedtests = HashMap::new();
+        speedtests.insert(
+            g1.clone(),
+            VecDeque::from(vec![
+                acceptable_speedtest(timestamp - Duration::hours(12)),
+                acceptable_speedtest(timestamp),
+            ]),
+        );
+        let speedtest_avgs = SpeedtestAverages {
+            speedtests,
+            ..Default::default()
+        };
+
+        let shares =
+            PocShares::aggregate(stream::iter(heartbeats()).map(Ok), speedtest_avgs.clone())
+                .await
+                .unwrap();
+        assert!(shares.is_valid(&g1));
+        assert!(!shares.is_valid(&g2));
+        let epoch = (timestamp - Duration::hours(24))..timestamp;
+        let invalid_shares = shares.invalid_shares(&epoch);
+        assert_eq!(invalid_shares.len(), 1);
+        assert_eq!(invalid_shares[0].hotspot_key, Vec::<u8>::from(g2.clone()));
+        assert_eq!(invalid_shares[0].cbsd_id, c2);
+        assert_eq!(invalid_shares[0].reason, INSUFFICIENT_SPEEDTESTS);
+        assert_eq!(invalid_shares[0].speedtests, 0);
+        assert_eq!(invalid_shares[0].required_speedtests, 2);
+
+        // Two acceptable speedtests are not enough when three are required
+        let shares = PocShares::aggregate(
+            stream::iter(heartbeats()).map(Ok),
+            speedtest_avgs.min_speedtests(3),
+        )
+        .await
+        .unwrap();
+        assert!(!shares.is_valid(&g1));
+        assert_eq!(shares.invalid_shares(&epoch).len(), 2);
+    }
+
     #[tokio::test]
     async fn test_radio_weights() {
         let g1: PublicKeyBinary = "11eX55faMbqZB7jzN4p67m6w7ScPMH6ubnvCjCPLh72J49PaJEL"
@@ -616,7 +1071,10 @@ mod test {
         let mut speedtests = HashMap::new();
         speedtests.insert(g1.clone(), VecDeque::from(g1_speedtests));
         speedtests.insert(g2.clone(), VecDeque::from(g2_speedtests));
-        let speedtest_avgs = SpeedtestAverages { speedtests };
+        let speedtest_avgs = SpeedtestAverages {
+            speedtests,
+            ..Default::default()
+        };
 
         let rewards = PocShares::aggregate(stream::iter(heartbeats).map(Ok), speedtest_avgs)
             .await
@@ -806,7 +1264,10 @@ mod test {
         speedtests.insert(gw5, VecDeque::from(gw5_speedtests));
         speedtests.insert(gw6, VecDeque::from(gw6_speedtests));
         speedtests.insert(gw7, VecDeque::from(gw7_speedtests));
-        let speedtest_avgs = SpeedtestAverages { speedtests };
+        let speedtest_avgs = SpeedtestAverages {
+            speedtests,
+            ..Default::default()
+        };
 
         // calculate the rewards for the sample group
         let mut owner_rewards = HashMap::<PublicKeyBinary, u64>::new();
@@ -814,7 +1275,7 @@ mod test {
         for mobile_reward in PocShares::aggregate(stream::iter(heartbeats).map(Ok), speedtest_avgs)
             .await
             .unwrap()
-            .into_rewards(Decimal::ZERO, &epoch)
+            .into_rewards(&EmissionSchedule::default(), Decimal::ZERO, &epoch)
         {
             let radio_reward = match mobile_reward.reward {
                 Some(proto::mobile_reward_share::Reward::RadioReward(radio_reward)) => radio_reward,
@@ -832,20 +1293,20 @@ mod test {
             *owner_rewards
                 .get(&owner1)
                 .expect("Could not fetch owner1 rewards"),
-            983_491_394_449
+            983_491_394_451
         );
         assert_eq!(
             *owner_rewards
                 .get(&owner2)
                 .expect("Could not fetch owner2 rewards"),
-            2_950_474_183_346
+            2_950_474_183_349
         );
 
         assert_eq!(
             *owner_rewards
                 .get(&owner3)
                 .expect("Could not fetch owner3 rewards"),
-            175_623_463_294
+            175_623_463_295
         );
         assert_eq!(owner_rewards.get(&owner4), None);
 
@@ -854,7 +1315,7 @@ mod test {
             total += *val
         }
 
-        assert_eq!(total, 4_109_589_041_089); // total emissions for 1 hour
+        assert_eq!(total, 4_109_589_041_095); // total emissions for 1 hour
     }
 
     #[tokio::test]
@@ -892,10 +1353,15 @@ mod test {
         let now = Utc::now();
         // We should never see any radio shares from owner2, since all of them are
         // less than or equal to zero.
-        let owner_shares = PocShares { hotspot_shares };
+        let owner_shares = PocShares {
+            hotspot_shares,
+            ..Default::default()
+        };
         let epoch = now - Duration::hours(1)..now;
         let expected_hotspot = gw1;
-        for mobile_reward in owner_shares.into_rewards(Decimal::ZERO, &epoch) {
+        for mobile_reward in
+            owner_shares.into_rewards(&EmissionSchedule::default(), Decimal::ZERO, &epoch)
+        {
             let radio_reward = match mobile_reward.reward {
                 Some(proto::mobile_reward_share::Reward::RadioReward(radio_reward)) => radio_reward,
                 _ => unreachable!(),
@@ -0,0 +1,296 @@
+//! Smoothing of the poc shares of radios over trailing epochs
+//!
+//! With smoothing configured, the poc shares of every radio with shares in
+//! the rewarded epoch are replaced by the weighted average of its shares in
+//! the rewarded epoch and the epochs before it, the first weight being that
+//! of the rewarded epoch. Epochs a radio had no shares in count as zero.
+//! Radios without shares in the rewarded epoch are not rewarded, whatever
+//! their shares before. The raw shares of every rewarded epoch are saved for
+//! the epochs after it, so smoothing only takes the epochs rewarded since it
+//! was configured into account.
+
+use crate::reward_shares::PocShares;
+use chrono::{DateTime, Utc};
+use file_store::reward_manifest::RewardSmoothingV1;
+use helium_crypto::PublicKeyBinary;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use sqlx::{Postgres, Transaction};
+use std::{collections::HashMap, ops::Range};
+
+/// Radios saved with a single insert, every radio takes four of the at most
+/// 65535 parameters of a statement
+const SAVE_BATCH_SIZE: usize = 10_000;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SmoothingConfig {
+    /// Weights of the rewarded epoch and the epochs before it, most recent
+    /// first, e.g. `[3, 2, 1]` for a trailing window of three epochs
+    pub weights: Vec<Decimal>,
+}
+
+/// Raw poc shares of a radio in an epoch
+#[derive(Clone, Debug, PartialEq)]
+pub struct RadioShare {
+    pub hotspot_key: PublicKeyBinary,
+    pub cbsd_id: String,
+    pub shares: Decimal,
+}
+
+/// Raw shares of the radios in the epochs before the rewarded one, the
+/// shares of every radio indexed by the number of epochs before it minus one
+#[derive(Default)]
+pub struct ShareHistory(HashMap<PublicKeyBinary, HashMap<String, Vec<Decimal>>>);
+
+impl ShareHistory {
+    fn insert(&mut self, share: RadioShare, age: usize, epochs: usize) {
+        let shares = self
+            .0
+            .entry(share.hotspot_key)
+            .or_default()
+            .entry(share.cbsd_id)
+            .or_insert_with(|| vec![Decimal::ZERO; epochs]);
+        if let Some(slot) = shares.get_mut(age - 1) {
+            *slot = share.shares;
+        }
+    }
+
+    fn shares(&self, hotspot_key: &PublicKeyBinary, cbsd_id: &str, age: usize) -> Decimal {
+        self.0
+            .get(hotspot_key)
+            .and_then(|radios| radios.get(cbsd_id))
+            .and_then(|shares| shares.get(age - 1))
+            .copied()
+            .unwrap_or(Decimal::ZERO)
+    }
+}
+
+impl SmoothingConfig {
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.weights.is_empty() {
+            problems.push("reward_config.smoothing.weights must not be empty".to_string());
+        }
+        if self.weights.iter().any(|weight| weight.is_sign_negative()) {
+            problems.push(format!(
+                "reward_config.smoothing.weights must not be negative, got {:?}",
+                self.weights
+            ));
+        } else if self.total_weight().is_zero() {
+            problems.push("reward_config.smoothing.weights must not all be zero".to_string());
+        }
+        problems
+    }
+
+    fn total_weight(&self) -> Decimal {
+        self.weights.iter().sum()
+    }
+
+    /// The poc shares with the shares of every radio replaced by their
+    /// weighted average over the trailing epochs
+    pub fn smooth(&self, mut poc_shares: PocShares, history: &ShareHistory) -> PocShares {
+        let total_weight = self.total_weight();
+        if total_weight.is_zero() {
+            return poc_shares;
+        }
+        for (hotspot_key, radios) in poc_shares.hotspot_shares.iter_mut() {
+            for (cbsd_id, shares) in radios.radio_shares.iter_mut() {
+                if shares.is_zero() {
+                    continue;
+                }
+                let weighted: Decimal = self
+                    .weights
+                    .iter()
+                    .enumerate()
+                    .map(|(age, weight)| match age {
+                        0 => *weight * *shares,
+                        _ => *weight * history.shares(hotspot_key, cbsd_id, age),
+                    })
+                    .sum();
+                *shares = weighted / total_weight;
+            }
+        }
+        poc_shares
+    }
+
+    /// Raw shares of the radios in the epochs of the window before the
+    /// rewarded epoch
+    pub async fn history(
+        &self,
+        exec: impl sqlx::PgExecutor<'_>,
+        epoch: &Range<DateTime<Utc>>,
+    ) -> Result<ShareHistory, sqlx::Error> {
+        let mut history = ShareHistory::default();
+        let previous_epochs = self.weights.len().saturating_sub(1);
+        let period = epoch.end - epoch.start;
+        if previous_epochs == 0 || period.num_seconds() <= 0 {
+            return Ok(history);
+        }
+        let since = epoch.end - period * previous_epochs as i32;
+        let saved: Vec<(PublicKeyBinary, String, DateTime<Utc>, Decimal)> = sqlx::query_as(
+            r#"
+            SELECT hotspot_key, cbsd_id, reward_period_end, shares FROM radio_reward_shares
+            WHERE reward_period_end >= $1 AND reward_period_end < $2
+            "#,
+        )
+        .bind(since)
+        .bind(epoch.end)
+        .fetch_all(exec)
+        .await?;
+        for (hotspot_key, cbsd_id, reward_period_end, shares) in saved {
+            let age = (epoch.end - reward_period_end).num_seconds() / period.num_seconds();
+            if age >= 1 {
+                history.insert(
+                    RadioShare {
+                        hotspot_key,
+                        cbsd_id,
+                        shares,
+                    },
+                    age as usize,
+                    previous_epochs,
+                );
+            }
+        }
+        Ok(history)
+    }
+
+    /// Save the raw shares of the rewarded epoch, dropping those of epochs
+    /// which fall out of the window of the next epoch
+    pub async fn save(
+        &self,
+        exec: &mut Transaction<'_, Postgres>,
+        epoch: &Range<DateTime<Utc>>,
+        shares: &[RadioShare],
+    ) -> Result<(), sqlx::Error> {
+        let period = epoch.end - epoch.start;
+        let keep_since = epoch.end - period * self.weights.len().saturating_sub(2) as i32;
+        sqlx::query(
+            r#"
+            DELETE FROM radio_reward_shares
+            WHERE reward_period_end < $1 OR reward_period_end = $2
+            "#,
+        )
+        .bind(keep_since)
+        .bind(epoch.end)
+        .execute(&mut *exec)
+        .await?;
+        for chunk in shares.chunks(SAVE_BATCH_SIZE) {
+            let mut query = sqlx::QueryBuilder::<Postgres>::new(
+                "INSERT INTO radio_reward_shares (hotspot_key, cbsd_id, reward_period_end, shares) ",
+            );
+            query.push_values(chunk, |mut row, share| {
+                row.push_bind(&share.hotspot_key)
+                    .push_bind(&share.cbsd_id)
+                    .push_bind(epoch.end)
+                    .push_bind(share.shares);
+            });
+            query.build().execute(&mut *exec).await?;
+        }
+        Ok(())
+    }
+
+    /// The smoothing flagged in the reward manifest
+    pub fn manifest(&self) -> RewardSmoothingV1 {
+        RewardSmoothingV1 {
+            epochs: self.weights.len() as u32,
+            weights: self.weights.iter().map(Decimal::to_string).collect(),
+        }
+    }
+}
+
+/// The raw shares of every radio with shares
+pub fn radio_shares(poc_shares: &PocShares) -> Vec<RadioShare> {
+    poc_shares
+        .hotspot_shares
+        .iter()
+        .flat_map(|(hotspot_key, radios)| {
+            radios
+                .radio_shares
+                .iter()
+                .filter(|(_, shares)| !shares.is_zero())
+                .map(|(cbsd_id, shares)| RadioShare {
+                    hotspot_key: hotspot_key.clone(),
+                    cbsd_id: cbsd_id.clone(),
+                    shares: *shares,
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn poc_shares(radios: &[(u8, &str, Decimal)]) -> PocShares {
+        let mut poc_shares = PocShares::default();
+        for (hotspot, cbsd_id, shares) in radios {
+            poc_shares
+                .hotspot_shares
+                .entry(PublicKeyBinary::from(vec![*hotspot]))
+                .or_default()
+                .radio_shares
+                .insert(cbsd_id.to_string(), *shares);
+        }
+        poc_shares
+    }
+
+    fn history(epochs: &[&[(u8, &str, Decimal)]]) -> ShareHistory {
+        let mut history = ShareHistory::default();
+        for (age, radios) in epochs.iter().enumerate() {
+            for share in radio_shares(&poc_shares(radios)) {
+                history.insert(share, age + 1, epochs.len());
+            }
+        }
+        history
+    }
+
+    fn shares(poc_shares: &PocShares, hotspot: u8, cbsd_id: &str) -> Decimal {
+        poc_shares.hotspot_shares[&PublicKeyBinary::from(vec![hotspot])].radio_shares[cbsd_id]
+    }
+
+    #[test]
+    fn smoothed_shares_are_weighted_over_trailing_epochs() {
+        let raw = poc_shares(&[
+            (1, "a", dec!(10)),
+            (1, "b", dec!(4)),
+            (2, "c", dec!(6)),
+            (2, "d", dec!(0)),
+        ]);
+        let history = history(&[
+            &[(1, "a", dec!(4)), (2, "d", dec!(8))],
+            &[(1, "a", dec!(1)), (1, "b", dec!(4))],
+        ]);
+        let smoothing = SmoothingConfig {
+            weights: vec![dec!(3), dec!(2), dec!(1)],
+        };
+        let smoothed = smoothing.smooth(poc_shares(&[]), &history);
+        assert_eq!(smoothed.total_shares(), Decimal::ZERO);
+
+        let smoothed = smoothing.smooth(raw, &history);
+        // (3 * 10 + 2 * 4 + 1 * 1) / 6
+        assert_eq!(shares(&smoothed, 1, "a"), dec!(6.5));
+        // A missed epoch counts as zero
+        assert_eq!(shares(&smoothed, 1, "b"), dec!(16) / dec!(6));
+        // New radios are ramped up
+        assert_eq!(shares(&smoothed, 2, "c"), dec!(3));
+        // Radios without shares in the epoch stay without
+        assert_eq!(shares(&smoothed, 2, "d"), Decimal::ZERO);
+
+        // Radios with steady shares keep their raw shares
+        let steady = history(&[&[(1, "a", dec!(10))], &[(1, "a", dec!(10))]]);
+        let raw = poc_shares(&[(1, "a", dec!(10))]);
+        let smoothed = smoothing.smooth(poc_shares(&[(1, "a", dec!(10))]), &steady);
+        assert_eq!(shares(&smoothed, 1, "a"), shares(&raw, 1, "a"));
+        assert_eq!(radio_shares(&smoothed), radio_shares(&raw));
+    }
+
+    #[test]
+    fn weights_are_checked() {
+        let problems = |weights: Vec<Decimal>| SmoothingConfig { weights }.problems().len();
+        assert_eq!(problems(vec![dec!(2), dec!(1)]), 0);
+        assert_eq!(problems(vec![]), 2);
+        assert_eq!(problems(vec![dec!(1), dec!(-1)]), 1);
+        assert_eq!(problems(vec![dec!(0), dec!(0)]), 1);
+    }
+}
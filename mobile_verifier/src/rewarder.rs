@@ -1,22 +1,38 @@
 use crate::{
+    corrections::{correction_budget, Corrections},
     data_session,
-    heartbeats::HeartbeatReward,
-    reward_shares::{MapperShares, PocShares, TransferRewards},
-    speedtests::SpeedtestAverages,
+    emissions::EmissionSchedule,
+    epoch_summary::{EpochSummary, VerificationCounts},
+    heartbeat_location::{self, LOCATION_MISMATCH},
+    heartbeats::{HeartbeatEligibility, HeartbeatReward},
+    reward_shares::{
+        self, MapperShares, PocShares, RewardConfig, TransferRewards, INSUFFICIENT_SPEEDTESTS,
+    },
+    reward_smoothing::{self, SmoothingConfig},
+    speedtests::{SpeedtestAverages, MIN_REQUIRED_SAMPLES},
     subscriber_location, telemetry,
 };
 use anyhow::bail;
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use db_store::meta;
-use file_store::{file_sink::FileSinkClient, traits::TimestampEncode};
-use helium_proto::services::poc_mobile::mobile_reward_share::Reward as ProtoReward;
-use helium_proto::RewardManifest;
+use dc_pricing::DcPricing;
+use file_store::{
+    canary::{Canary, Totals},
+    file_sink::{FileManifest, FileSinkClient},
+    reward_manifest::CorrectedRewardManifest,
+    traits::TimestampEncode,
+};
+use futures::StreamExt;
+use helium_proto::services::poc_mobile::{
+    mobile_reward_share::Reward as ProtoReward, MobileRewardShare,
+};
+use oracle_epoch::EpochPolicy;
 use price::PriceTracker;
 use reward_scheduler::Scheduler;
 use rust_decimal::{prelude::ToPrimitive, Decimal};
 use rust_decimal_macros::dec;
 use sqlx::{PgExecutor, Pool, Postgres};
-use std::ops::Range;
+use std::{ops::Range, sync::Arc, time::Instant};
 use tokio::time::sleep;
 
 const REWARDS_NOT_CURRENT_DELAY_PERIOD: i64 = 5;
@@ -26,29 +42,102 @@ pub struct Rewarder {
     reward_period_duration: Duration,
     reward_offset: Duration,
     mobile_rewards: FileSinkClient,
+    invalid_rewards: FileSinkClient,
     reward_manifests: FileSinkClient,
     price_tracker: PriceTracker,
     disable_discovery_loc_rewards_to_s3: bool,
+    epoch_policy: EpochPolicy,
+    reward_config: RewardConfig,
+    emissions: EmissionSchedule,
+    incremental_heartbeat_eligibility: bool,
+    min_speedtests: usize,
+    epoch_summaries: Option<FileSinkClient>,
+    verification_counts: Arc<VerificationCounts>,
+    canary: Option<Canary>,
+    pricing: DcPricing,
 }
 
 impl Rewarder {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         pool: Pool<Postgres>,
         reward_period_duration: Duration,
         reward_offset: Duration,
         mobile_rewards: FileSinkClient,
+        invalid_rewards: FileSinkClient,
         reward_manifests: FileSinkClient,
         price_tracker: PriceTracker,
         disable_discovery_loc_rewards_to_s3: bool,
+        epoch_policy: EpochPolicy,
+        reward_config: RewardConfig,
+        emissions: EmissionSchedule,
     ) -> Self {
         Self {
             pool,
             reward_period_duration,
             reward_offset,
             mobile_rewards,
+            invalid_rewards,
             reward_manifests,
             price_tracker,
             disable_discovery_loc_rewards_to_s3,
+            epoch_policy,
+            reward_config,
+            emissions,
+            incremental_heartbeat_eligibility: false,
+            min_speedtests: MIN_REQUIRED_SAMPLES,
+            epoch_summaries: None,
+            verification_counts: Arc::default(),
+            canary: None,
+            pricing: DcPricing::mobile(),
+        }
+    }
+
+    /// Value the data transfer rewards with the given pricing instead of the
+    /// default mobile pricing
+    pub fn pricing(self, pricing: DcPricing) -> Self {
+        Self { pricing, ..self }
+    }
+
+    /// Minimum number of recent speedtests of a hotspot for its radios to be
+    /// rewarded
+    pub fn min_speedtests(self, min_speedtests: usize) -> Self {
+        Self {
+            min_speedtests,
+            ..self
+        }
+    }
+
+    /// Write a summary of every rewarded epoch, including the heartbeats
+    /// counted by the heartbeat daemon sharing the verification counts
+    pub fn epoch_summaries(
+        self,
+        epoch_summaries: FileSinkClient,
+        verification_counts: Arc<VerificationCounts>,
+    ) -> Self {
+        Self {
+            epoch_summaries: Some(epoch_summaries),
+            verification_counts,
+            ..self
+        }
+    }
+
+    /// Compare the reward shares read back from the uploaded reward files
+    /// with those written by every reward pass
+    pub fn canary(self, canary: Canary) -> Self {
+        Self {
+            canary: Some(canary),
+            ..self
+        }
+    }
+
+    /// Read the eligible cbsds from the incrementally maintained heartbeat
+    /// eligibility, falling back to aggregating the heartbeats for epochs it
+    /// was not maintained for
+    pub fn incremental_heartbeat_eligibility(self) -> Self {
+        Self {
+            incremental_heartbeat_eligibility: true,
+            ..self
         }
     }
 
@@ -101,12 +190,14 @@ impl Rewarder {
         &self,
         reward_period: &Range<DateTime<Utc>>,
     ) -> anyhow::Result<bool> {
-        // Check if we have heartbeats and speedtests past the end of the reward period
+        // Check if we have heartbeats and speedtests past the end of the reward
+        // period, including any which may have been delayed by clock skew
+        let settled_at = self.epoch_policy.settled_at(reward_period);
         if reward_period.end >= self.disable_complete_data_checks_until().await? {
             if sqlx::query_scalar::<_, i64>(
                 "SELECT COUNT(*) FROM heartbeats WHERE latest_timestamp >= $1",
             )
-            .bind(reward_period.end)
+            .bind(settled_at)
             .fetch_one(&self.pool)
             .await?
                 == 0
@@ -118,7 +209,7 @@ impl Rewarder {
             if sqlx::query_scalar::<_, i64>(
                 "SELECT COUNT(*) FROM speedtests WHERE latest_timestamp >= $1",
             )
-            .bind(reward_period.end)
+            .bind(settled_at)
             .fetch_one(&self.pool)
             .await?
                 == 0
@@ -134,6 +225,7 @@ impl Rewarder {
     }
 
     pub async fn reward(&self, scheduler: &Scheduler) -> anyhow::Result<()> {
+        let reward_start = Instant::now();
         let reward_period = &scheduler.reward_period;
 
         tracing::info!(
@@ -142,10 +234,31 @@ impl Rewarder {
             reward_period.end
         );
 
-        let heartbeats = HeartbeatReward::validated(&self.pool, reward_period);
-        let speedtests = SpeedtestAverages::validated(&self.pool, reward_period.end).await?;
+        let heartbeats = if self.incremental_heartbeat_eligibility
+            && HeartbeatEligibility::is_maintained(&self.pool, reward_period).await?
+        {
+            HeartbeatReward::eligible(&self.pool, reward_period, &self.reward_config).left_stream()
+        } else {
+            HeartbeatReward::validated(&self.pool, reward_period, &self.reward_config)
+                .right_stream()
+        };
+        let speedtests = SpeedtestAverages::validated(&self.pool, reward_period.end)
+            .await?
+            .min_speedtests(self.min_speedtests);
 
         let poc_rewards = PocShares::aggregate(heartbeats, speedtests).await?;
+        let (poc_rewards, raw_shares) = match &self.reward_config.smoothing {
+            Some(smoothing) => {
+                let raw_shares = reward_smoothing::radio_shares(&poc_rewards);
+                let history = smoothing.history(&self.pool, reward_period).await?;
+                (smoothing.smooth(poc_rewards, &history), raw_shares)
+            }
+            None => (poc_rewards, vec![]),
+        };
+        let mut corrections = Corrections::pending(&self.pool).await?;
+        let invalid_shares = poc_rewards.invalid_shares(reward_period);
+        let mut summary = EpochSummary::new(&poc_rewards);
+        let mut written = Totals::default();
         let mobile_price = self
             .price_tracker
             .price(&helium_proto::BlockchainTokenTypeV1::Mobile)
@@ -157,8 +270,10 @@ impl Rewarder {
                 / dec!(1_000_000); // Per Bone
         let transfer_rewards = TransferRewards::from_transfer_sessions(
             mobile_bone_price,
+            &self.pricing,
             data_session::aggregate_hotspot_data_sessions_to_dc(&self.pool, reward_period).await?,
             &poc_rewards,
+            &self.emissions,
             reward_period,
         )
         .await;
@@ -170,9 +285,48 @@ impl Rewarder {
         };
         telemetry::data_transfer_rewards_scale(scale);
 
+        // Reward files are named by the end of the epoch, so a rerun of the
+        // epoch writes the same files
+        self.mobile_rewards.file_time(reward_period.end).await?;
+        self.invalid_rewards.file_time(reward_period.end).await?;
+
+        // Positive corrections are funded from the poc and dc pool before it
+        // is shared among the radios
+        let corrections_paid = corrections.fund(correction_budget(
+            self.emissions.poc_and_dc(reward_period),
+            transfer_rewards.reward_sum(),
+        ));
+        for mobile_reward_share in poc_rewards.into_rewards(
+            &self.emissions,
+            transfer_rewards.reward_sum() + Decimal::from(corrections_paid),
+            reward_period,
+        ) {
+            let mobile_reward_share = corrections.withhold(mobile_reward_share);
+            summary.add_reward(&mobile_reward_share);
+            written += reward_totals(&mobile_reward_share);
+            self.mobile_rewards
+                .write(mobile_reward_share, [])
+                .await?
+                // Await the returned one shot to ensure that we wrote the file
+                .await??;
+        }
+
+        for mobile_reward_share in transfer_rewards.into_rewards(reward_period) {
+            let mobile_reward_share = corrections.withhold(mobile_reward_share);
+            summary.add_reward(&mobile_reward_share);
+            written += reward_totals(&mobile_reward_share);
+            self.mobile_rewards
+                .write(mobile_reward_share, [])
+                .await?
+                // Await the returned one shot to ensure that we wrote the file
+                .await??;
+        }
+
         for mobile_reward_share in
-            poc_rewards.into_rewards(transfer_rewards.reward_sum(), reward_period)
+            reward_shares::into_service_provider_rewards(&self.emissions, reward_period)
         {
+            summary.add_reward(&mobile_reward_share);
+            written += reward_totals(&mobile_reward_share);
             self.mobile_rewards
                 .write(mobile_reward_share, [])
                 .await?
@@ -180,7 +334,12 @@ impl Rewarder {
                 .await??;
         }
 
-        for mobile_reward_share in transfer_rewards.into_rewards(reward_period) {
+        // The funded positive corrections are paid once the negative ones were
+        // withheld from the rewards of the epoch
+        let (correction_shares, applied_corrections) = corrections.finish(reward_period);
+        for mobile_reward_share in correction_shares {
+            summary.add_reward(&mobile_reward_share);
+            written += reward_totals(&mobile_reward_share);
             self.mobile_rewards
                 .write(mobile_reward_share, [])
                 .await?
@@ -199,7 +358,7 @@ impl Rewarder {
 
         // determine mapping shares based on location shares and data transferred
         let mapping_shares = MapperShares::new(location_shares);
-        let rewards_per_share = mapping_shares.rewards_per_share(reward_period)?;
+        let rewards_per_share = mapping_shares.rewards_per_share(&self.emissions, reward_period)?;
 
         // translate discovery mapping shares into subscriber rewards
         for mapping_share in
@@ -217,6 +376,8 @@ impl Rewarder {
                     )
                 }
             } else {
+                summary.add_reward(&mapping_share);
+                written += reward_totals(&mapping_share);
                 self.mobile_rewards
                     .write(mapping_share.clone(), [])
                     .await?
@@ -225,7 +386,25 @@ impl Rewarder {
             }
         }
 
-        let written_files = self.mobile_rewards.commit().await?.await??;
+        telemetry::insufficient_speedtest_radios(invalid_shares.len());
+        for invalid_share in invalid_shares {
+            self.invalid_rewards
+                .write(invalid_share, &[("reason", INSUFFICIENT_SPEEDTESTS)])
+                .await?
+                .await??;
+        }
+        for invalid_share in heartbeat_location::invalid_shares(&self.pool, reward_period).await? {
+            self.invalid_rewards
+                .write(invalid_share, &[("reason", LOCATION_MISMATCH)])
+                .await?
+                .await??;
+        }
+
+        let written_files = self.commit_rewards().await?;
+        if let Some(canary) = &self.canary {
+            let label = format!("{} to {}", reward_period.start, reward_period.end);
+            canary.expect(label, &written_files, written).await;
+        }
 
         let mut transaction = self.pool.begin().await?;
 
@@ -234,10 +413,20 @@ impl Rewarder {
             .bind(reward_period.start)
             .execute(&mut transaction)
             .await?;
+        HeartbeatEligibility::clear(&mut transaction, reward_period.end).await?;
+        heartbeat_location::clear(&mut transaction, reward_period.end).await?;
+        if let Some(smoothing) = &self.reward_config.smoothing {
+            smoothing
+                .save(&mut transaction, reward_period, &raw_shares)
+                .await?;
+        }
 
         // clear the db of data sessions data & subscriber location data for the epoch
         data_session::clear_hotspot_data_sessions(&mut transaction, reward_period).await?;
         subscriber_location::clear_location_shares(&mut transaction, reward_period).await?;
+        applied_corrections
+            .save(&mut transaction, reward_period.end)
+            .await?;
 
         let next_reward_period = scheduler.next_reward_period();
         save_last_rewarded_end_time(&mut transaction, &next_reward_period.start).await?;
@@ -245,22 +434,98 @@ impl Rewarder {
         transaction.commit().await?;
 
         // now that the db has been purged, safe to write out the manifest
+        self.reward_manifests.file_time(reward_period.end).await?;
         self.reward_manifests
             .write(
-                RewardManifest {
+                CorrectedRewardManifest {
                     start_timestamp: reward_period.start.encode_timestamp(),
                     end_timestamp: reward_period.end.encode_timestamp(),
                     written_files,
+                    corrections: applied_corrections.items,
+                    smoothing: self
+                        .reward_config
+                        .smoothing
+                        .as_ref()
+                        .map(SmoothingConfig::manifest),
                 },
                 [],
             )
             .await?
             .await??;
 
-        self.reward_manifests.commit().await?;
+        if let Some(epoch_summaries) = &self.epoch_summaries {
+            let summary = summary.finish(
+                reward_period,
+                &self.verification_counts,
+                reward_start.elapsed(),
+            );
+            epoch_summaries.file_time(reward_period.end).await?;
+            epoch_summaries.write(summary, []).await?.await??;
+        }
+        // the summary is never committed without the manifest of its epoch
+        let epoch_summaries = self
+            .epoch_summaries
+            .as_ref()
+            .map(std::slice::from_ref)
+            .unwrap_or_default();
+        commit_together(&self.reward_manifests, epoch_summaries).await?;
         telemetry::last_rewarded_end_time(next_reward_period.start);
         Ok(())
     }
+
+    /// Commit the rewards and the invalid rewards of an epoch together,
+    /// returning the files of the rewards
+    async fn commit_rewards(&self) -> anyhow::Result<FileManifest> {
+        commit_together(
+            &self.mobile_rewards,
+            std::slice::from_ref(&self.invalid_rewards),
+        )
+        .await
+    }
+}
+
+/// Commit the files of a sink together with those of its companion sinks.
+/// Every file is closed before any is deposited, and the companions are
+/// rolled back when the files of the sink fail to deposit, so they are never
+/// committed without them. Returns the files of the sink
+async fn commit_together(
+    sink: &FileSinkClient,
+    companions: &[FileSinkClient],
+) -> anyhow::Result<FileManifest> {
+    let closed = async {
+        sink.close().await?.await??;
+        for companion in companions {
+            companion.close().await?.await??;
+        }
+        anyhow::Ok(())
+    };
+    if let Err(err) = closed.await {
+        sink.rollback().await?.await??;
+        for companion in companions {
+            companion.rollback().await?.await??;
+        }
+        return Err(err);
+    }
+    let written_files = match sink.commit().await?.await? {
+        Ok(written_files) => written_files,
+        Err(err) => {
+            for companion in companions {
+                companion.rollback().await?.await??;
+            }
+            return Err(err.into());
+        }
+    };
+    for companion in companions {
+        companion.commit().await?.await??;
+    }
+    Ok(written_files)
+}
+
+fn reward_totals(share: &MobileRewardShare) -> Totals {
+    Totals {
+        messages: 1,
+        amount: reward_shares::reward_amount(share),
+    }
 }
 
 pub async fn last_rewarded_end_time(db: &Pool<Postgres>) -> db_store::Result<DateTime<Utc>> {
@@ -1,7 +1,21 @@
-use chrono::{DateTime, TimeZone, Utc};
+use crate::{
+    emissions::EmissionSchedule,
+    heartbeat_location::LocationSettings,
+    reward_shares::RewardConfig,
+    speedtests::{MIN_REQUIRED_SAMPLES, SPEEDTEST_AVG_MAX_DATA_POINTS},
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use config::{Config, ConfigError, Environment, File};
+use grpc_service::tls::TlsSettings;
+use helium_crypto::PublicKey;
+use oracle_epoch::EpochPolicy;
 use serde::Deserialize;
-use std::path::Path;
+use std::{
+    collections::HashSet,
+    net::{AddrParseError, SocketAddr},
+    path::Path,
+    str::FromStr,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
@@ -9,6 +23,9 @@ pub struct Settings {
     /// "mobile_verifier=debug,poc_store=info"
     #[serde(default = "default_log")]
     pub log: String,
+    /// Log output format, per module log levels and runtime diagnostics
+    #[serde(default)]
+    pub tracing: custom_tracing::Settings,
     /// Cache location for generated verified reports
     pub cache: String,
     /// Reward period in hours. (Default is 24)
@@ -21,12 +38,108 @@ pub struct Settings {
     pub data_transfer_ingest: file_store::Settings,
     pub output: file_store::Settings,
     pub metrics: poc_metrics::Settings,
+    /// Leader election between replicas. Every instance is active when not set
+    pub leader_election: Option<db_store::LeaderElectionSettings>,
+    /// Retention of the heartbeats and speedtests. Nothing is purged by
+    /// default
+    #[serde(default)]
+    pub maintenance: db_store::MaintenanceSettings,
     pub price_tracker: price::price_tracker::Settings,
     pub config_client: mobile_config::ClientSettings,
+    /// Where gateway info is resolved from. (Default is config)
+    #[serde(default)]
+    pub gateway_source: GatewaySource,
+    /// Entity accounts gateways are read from when `gateway_source` is chain
+    pub chain_gateways: Option<solana::entity::Settings>,
     #[serde(default = "default_start_after")]
     pub start_after: u64,
     #[serde(default = "default_disable_discovery_loc_rewards_to_s3")]
     pub disable_discovery_loc_rewards_to_s3: bool,
+    /// Data credits the payer of a data transfer session has to be charged
+    /// for the session to be rewarded. Default is a data credit per 20_000
+    /// bytes, rounded up, at least one per session
+    #[serde(default = "default_dc_pricing")]
+    pub dc_pricing: dc_pricing::DcPricing,
+    /// Maximum number of heartbeat files validated concurrently. (Default is 4)
+    #[serde(default = "default_max_concurrent_heartbeat_files")]
+    pub max_concurrent_heartbeat_files: usize,
+    /// Maximum number of heartbeats saved by a single upsert. (Default is
+    /// 1000)
+    #[serde(default = "default_heartbeat_batch_size")]
+    pub heartbeat_batch_size: usize,
+    /// Creation and retention of the daily partitions of the heartbeats
+    #[serde(default)]
+    pub heartbeat_partitions: db_store::PartitionSettings,
+    /// Count the rewardable hours of every cbsd per reward period as heartbeat
+    /// files arrive, instead of aggregating all heartbeats at reward time.
+    /// (Default is false)
+    #[serde(default)]
+    pub incremental_heartbeat_eligibility: bool,
+    /// Check heartbeats against the asserted location of their hotspot.
+    /// Disabled when not set
+    pub heartbeat_location: Option<LocationSettings>,
+    /// Layout of the legacy gzip csv exports among the heartbeat files, read
+    /// to replay old heartbeats. Only framed files are read when not set
+    pub legacy_heartbeats: Option<file_store::file_source::CsvSchema>,
+    /// Layout of the legacy gzip csv exports among the speedtest files, read
+    /// to replay old speedtests. Only framed files are read when not set
+    pub legacy_speedtests: Option<file_store::file_source::CsvSchema>,
+    /// Clock skew tolerance in seconds applied when accepting reports near an
+    /// epoch boundary. (Default is 30)
+    #[serde(default = "default_clock_skew_tolerance_secs")]
+    pub clock_skew_tolerance_secs: i64,
+    /// Tunable reward parameters such as per cell type multipliers
+    #[serde(default)]
+    pub reward_config: RewardConfig,
+    /// Emission schedule the reward pools of each epoch are computed from
+    #[serde(default)]
+    pub emissions: EmissionSchedule,
+    /// Minimum number of recent speedtests of a hotspot for its radios to be
+    /// rewarded, at most 6. (Default is 2)
+    #[serde(default = "default_min_speedtests")]
+    pub min_speedtests: usize,
+    /// Listen address of the grpc endpoint serving the speedtest averages.
+    /// Disabled when not set
+    pub listen: Option<String>,
+    /// Serve the grpc endpoint over TLS when set. Plaintext by default
+    pub tls: Option<TlsSettings>,
+    /// B58 encoded keys allowed to record reward corrections through the
+    /// listen address. Disabled when empty
+    #[serde(default)]
+    pub correction_keys: Vec<String>,
+    /// Read back and compare every uploaded reward file with the rewards
+    /// written for it. Disabled when not set
+    pub reward_canary: Option<file_store::canary::Settings>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GatewaySource {
+    /// The gateway service of mobile config
+    #[default]
+    Config,
+    /// The helium entity manager accounts on chain
+    Chain,
+}
+
+pub fn default_clock_skew_tolerance_secs() -> i64 {
+    30
+}
+
+pub fn default_max_concurrent_heartbeat_files() -> usize {
+    4
+}
+
+pub fn default_heartbeat_batch_size() -> usize {
+    1000
+}
+
+/// Every heartbeat of an upsert takes five of the at most 65535 parameters of
+/// a statement
+pub const MAX_HEARTBEAT_BATCH_SIZE: usize = 10_000;
+
+pub fn default_min_speedtests() -> usize {
+    MIN_REQUIRED_SAMPLES
 }
 
 pub fn default_disable_discovery_loc_rewards_to_s3() -> bool {
@@ -37,6 +150,10 @@ pub fn default_log() -> String {
     "mobile_verifier=debug,poc_store=info".to_string()
 }
 
+pub fn default_dc_pricing() -> dc_pricing::DcPricing {
+    dc_pricing::DcPricing::mobile()
+}
+
 pub fn default_start_after() -> u64 {
     0
 }
@@ -70,6 +187,117 @@ impl Settings {
             .add_source(Environment::with_prefix("VERIFY").separator("_"))
             .build()
             .and_then(|config| config.try_deserialize())
+            .and_then(|settings: Settings| settings.validate().map(|_| settings))
+    }
+
+    /// Check the loaded settings for values which deserialize but can not be
+    /// used, reporting every problem found rather than just the first.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = vec![];
+
+        if self.cache.trim().is_empty() {
+            problems.push("cache must not be empty".to_string());
+        }
+        if self.rewards <= 0 {
+            problems.push(format!("rewards must be positive, got {}", self.rewards));
+        }
+        if self.reward_offset_minutes < 0 {
+            problems.push(format!(
+                "reward_offset_minutes must not be negative, got {}",
+                self.reward_offset_minutes
+            ));
+        }
+        if self.gateway_source == GatewaySource::Chain && self.chain_gateways.is_none() {
+            problems.push("chain_gateways is required when gateway_source is chain".to_string());
+        }
+        if self.max_concurrent_heartbeat_files == 0 {
+            problems.push("max_concurrent_heartbeat_files must be at least 1".to_string());
+        }
+        if !(1..=MAX_HEARTBEAT_BATCH_SIZE).contains(&self.heartbeat_batch_size) {
+            problems.push(format!(
+                "heartbeat_batch_size must be between 1 and {MAX_HEARTBEAT_BATCH_SIZE}, got {}",
+                self.heartbeat_batch_size
+            ));
+        }
+        if !(1..=SPEEDTEST_AVG_MAX_DATA_POINTS).contains(&self.min_speedtests) {
+            problems.push(format!(
+                "min_speedtests must be between 1 and {SPEEDTEST_AVG_MAX_DATA_POINTS}, got {}",
+                self.min_speedtests
+            ));
+        }
+        if let Err(err) = self.listen_addr() {
+            problems.push(format!("listen is not a valid address: {err}"));
+        }
+        if let Err(err) = self.correction_keys() {
+            problems.push(format!("correction_keys are not valid keys: {err}"));
+        }
+        if !self.correction_keys.is_empty() && self.listen.is_none() {
+            problems.push("listen is required when correction_keys are set".to_string());
+        }
+        if self.heartbeat_partitions.retention_days == Some(0) {
+            problems.push("heartbeat_partitions.retention_days must be at least 1".to_string());
+        }
+        if self.clock_skew_tolerance_secs < 0 {
+            problems.push(format!(
+                "clock_skew_tolerance_secs must not be negative, got {}",
+                self.clock_skew_tolerance_secs
+            ));
+        } else if self.rewards > 0 && self.clock_skew_tolerance_secs >= self.rewards * 60 * 60 {
+            problems.push(format!(
+                "clock_skew_tolerance_secs must be shorter than the reward period, got {}",
+                self.clock_skew_tolerance_secs
+            ));
+        }
+        for (cell_type, multiplier) in &self.reward_config.cell_type_multipliers {
+            if multiplier.is_sign_negative() {
+                problems.push(format!(
+                    "reward_config.cell_type_multipliers.{cell_type:?} must not be negative, got {multiplier}"
+                ));
+            }
+        }
+        if let Some(smoothing) = &self.reward_config.smoothing {
+            problems.extend(smoothing.problems());
+        }
+        problems.extend(self.emissions.problems());
+        if let Some(leader_election) = &self.leader_election {
+            problems.extend(leader_election.problems());
+        }
+        problems.extend(self.config_client.problems());
+        problems.extend(self.dc_pricing.problems());
+        if Utc
+            .timestamp_opt(self.start_after as i64, 0)
+            .single()
+            .is_none()
+        {
+            problems.push(format!(
+                "start_after is not a valid timestamp, got {}",
+                self.start_after
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Message(format!(
+                "invalid settings: {}",
+                problems.join("; ")
+            )))
+        }
+    }
+
+    pub fn listen_addr(&self) -> Result<Option<SocketAddr>, AddrParseError> {
+        self.listen.as_deref().map(SocketAddr::from_str).transpose()
+    }
+
+    pub fn correction_keys(&self) -> Result<HashSet<PublicKey>, helium_crypto::Error> {
+        self.correction_keys
+            .iter()
+            .map(|key| PublicKey::from_str(key))
+            .collect()
+    }
+
+    pub fn epoch_policy(&self) -> EpochPolicy {
+        EpochPolicy::new(Duration::seconds(self.clock_skew_tolerance_secs))
     }
 
     pub fn start_after(&self) -> DateTime<Utc> {
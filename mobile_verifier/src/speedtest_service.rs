@@ -0,0 +1,87 @@
+//! Grpc query of the speedtest rolling average of a gateway. Like the price
+//! oracle endpoint the service is hand written on top of the tonic
+//! primitives, with the request defined here and the response being the same
+//! `SpeedtestAvg` written to the speedtest average files, valid only with the
+//! minimum number of recent speedtests the rewarder requires.
+
+use crate::speedtests::SpeedtestRollingAverage;
+use helium_crypto::PublicKeyBinary;
+use helium_proto::services::poc_mobile::SpeedtestAvg;
+use sqlx::{Pool, Postgres};
+use std::convert::Infallible;
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const SERVICE_NAME: &str = "helium.mobile_verifier.speedtests";
+const GET_AVERAGE_PATH: &str = "/helium.mobile_verifier.speedtests/get_average";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SpeedtestAvgReqV1 {
+    #[prost(bytes = "vec", tag = "1")]
+    pub pub_key: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct SpeedtestService {
+    pool: Pool<Postgres>,
+    min_speedtests: usize,
+}
+
+impl SpeedtestService {
+    pub fn new(pool: Pool<Postgres>, min_speedtests: usize) -> Self {
+        Self {
+            pool,
+            min_speedtests,
+        }
+    }
+
+    pub async fn get_average(&self, request: SpeedtestAvgReqV1) -> Result<SpeedtestAvg, Status> {
+        if request.pub_key.is_empty() {
+            return Err(Status::invalid_argument("pub_key is required"));
+        }
+        let pub_key = PublicKeyBinary::from(request.pub_key);
+        let average = SpeedtestRollingAverage::fetch(&self.pool, &pub_key)
+            .await
+            .map_err(|err| {
+                tracing::error!("failed to fetch speedtests of {pub_key}: {err}");
+                Status::internal("failed to fetch speedtests")
+            })?
+            .ok_or_else(|| Status::not_found(format!("no speedtests for {pub_key}")))?;
+        Ok(average.to_proto(self.min_speedtests))
+    }
+}
+
+impl NamedService for SpeedtestService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for SpeedtestService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            GET_AVERAGE_PATH => {
+                grpc_service::unary(
+                    req,
+                    move |request| async move { svc.get_average(request).await },
+                )
+            }
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::gateway_resolver::{GatewayResolver, GatewayResolverError};
 use chrono::{DateTime, Duration, Utc};
 use file_store::{
     file_info_poller::FileInfoStream,
@@ -11,7 +12,7 @@ use futures::{
 };
 use helium_crypto::PublicKeyBinary;
 use helium_proto::services::poc_mobile as proto;
-use mobile_config::{client::ClientError, gateway_info::GatewayInfoResolver, GatewayClient};
+use mobile_config::gateway_info::GatewayInfoResolver;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use sqlx::{
@@ -24,7 +25,7 @@ use std::{
 };
 use tokio::sync::mpsc::Receiver;
 
-const SPEEDTEST_AVG_MAX_DATA_POINTS: usize = 6;
+pub const SPEEDTEST_AVG_MAX_DATA_POINTS: usize = 6;
 const SPEEDTEST_LAPSE: i64 = 48;
 
 #[derive(Debug, Clone, Type)]
@@ -55,15 +56,16 @@ impl Speedtest {
 
 pub struct SpeedtestDaemon {
     pool: sqlx::Pool<sqlx::Postgres>,
-    gateway_client: GatewayClient,
+    gateway_client: GatewayResolver,
     speedtests: Receiver<FileInfoStream<CellSpeedtestIngestReport>>,
     file_sink: FileSinkClient,
+    min_speedtests: usize,
 }
 
 impl SpeedtestDaemon {
     pub fn new(
         pool: sqlx::Pool<sqlx::Postgres>,
-        gateway_client: GatewayClient,
+        gateway_client: GatewayResolver,
         speedtests: Receiver<FileInfoStream<CellSpeedtestIngestReport>>,
         file_sink: FileSinkClient,
     ) -> Self {
@@ -72,6 +74,16 @@ impl SpeedtestDaemon {
             gateway_client,
             speedtests,
             file_sink,
+            min_speedtests: MIN_REQUIRED_SAMPLES,
+        }
+    }
+
+    /// Minimum number of recent speedtests for the written averages to be
+    /// valid
+    pub fn min_speedtests(self, min_speedtests: usize) -> Self {
+        Self {
+            min_speedtests,
+            ..self
         }
     }
 
@@ -101,7 +113,7 @@ impl SpeedtestDaemon {
         tracing::info!("Processing speedtest file {}", file.file_info.key);
 
         let mut transaction = self.pool.begin().await?;
-        let reports = file.into_stream(&mut transaction).await?;
+        let (reports, completion) = file.into_stream(&mut transaction).await?;
 
         let mut validated_speedtests = pin!(
             SpeedtestRollingAverage::validate_speedtests(
@@ -112,12 +124,14 @@ impl SpeedtestDaemon {
             .await?
         );
         while let Some(speedtest) = validated_speedtests.next().await.transpose()? {
-            speedtest.write(&self.file_sink).await?;
+            speedtest
+                .write(&self.file_sink, self.min_speedtests)
+                .await?;
             speedtest.save(&mut transaction).await?;
         }
 
         self.file_sink.commit().await?;
-        transaction.commit().await?;
+        completion.commit(transaction).await?;
 
         Ok(())
     }
@@ -156,11 +170,21 @@ impl SpeedtestRollingAverage {
         }
     }
 
+    pub async fn fetch(
+        exec: impl sqlx::PgExecutor<'_>,
+        id: &PublicKeyBinary,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, SpeedtestRollingAverage>("SELECT * FROM speedtests WHERE id = $1")
+            .bind(id)
+            .fetch_optional(exec)
+            .await
+    }
+
     pub async fn validate_speedtests<'a>(
-        gateway_client: &'a GatewayClient,
+        gateway_client: &'a GatewayResolver,
         speedtests: impl Stream<Item = CellSpeedtest> + 'a,
         exec: &mut Transaction<'_, Postgres>,
-    ) -> Result<impl Stream<Item = Result<Self, ClientError>> + 'a, sqlx::Error> {
+    ) -> Result<impl Stream<Item = Result<Self, GatewayResolverError>> + 'a, sqlx::Error> {
         let tests_by_publickey = speedtests
             .fold(
                 HashMap::<PublicKeyBinary, Vec<CellSpeedtest>>::new(),
@@ -175,12 +199,7 @@ impl SpeedtestRollingAverage {
 
         let mut speedtests = Vec::new();
         for (pubkey, cell_speedtests) in tests_by_publickey.into_iter() {
-            let rolling_average: SpeedtestRollingAverage =
-                sqlx::query_as::<_, SpeedtestRollingAverage>(
-                    "SELECT * FROM speedtests WHERE id = $1",
-                )
-                .bind(&pubkey)
-                .fetch_optional(&mut *exec)
+            let rolling_average = Self::fetch(&mut *exec, &pubkey)
                 .await?
                 .unwrap_or_else(|| SpeedtestRollingAverage::new(pubkey.clone()));
             speedtests.push((rolling_average, cell_speedtests));
@@ -240,59 +259,96 @@ impl SpeedtestRollingAverage {
         .map(|result| result.inserted)
     }
 
-    pub async fn write(&self, averages: &file_sink::FileSinkClient) -> file_store::Result {
-        // Write out the speedtests to S3
+    /// The rolling average as of now, valid with at least the given number
+    /// of recent speedtests
+    pub fn to_proto(&self, min_speedtests: usize) -> proto::SpeedtestAvg {
         let average = Average::from(&self.speedtests);
-        let validity = average.validity();
+        let validity = average.validity(min_speedtests);
         // this is guaratneed to safely convert and not panic as it can only be one of
         // four possible decimal values based on the speedtest average tier
-        let reward_multiplier = average.reward_multiplier().try_into().unwrap();
+        let reward_multiplier = average
+            .reward_multiplier(min_speedtests)
+            .try_into()
+            .unwrap();
         let Average {
             upload_speed_avg_bps,
             download_speed_avg_bps,
             latency_avg_ms,
             ..
         } = average;
-        averages
-            .write(
-                proto::SpeedtestAvg {
-                    pub_key: self.id.clone().into(),
-                    upload_speed_avg_bps,
-                    download_speed_avg_bps,
-                    latency_avg_ms,
-                    timestamp: Utc::now().encode_timestamp(),
-                    speedtests: speedtests_without_lapsed(
-                        self.speedtests.iter(),
-                        Duration::hours(SPEEDTEST_LAPSE),
-                    )
-                    .map(|st| proto::Speedtest {
-                        timestamp: st.timestamp.timestamp() as u64,
-                        upload_speed_bps: st.upload_speed as u64,
-                        download_speed_bps: st.download_speed as u64,
-                        latency_ms: st.latency as u32,
-                    })
-                    .collect(),
-                    validity: validity as i32,
-                    reward_multiplier,
-                },
-                &[("validity", validity.as_str_name())],
+        proto::SpeedtestAvg {
+            pub_key: self.id.clone().into(),
+            upload_speed_avg_bps,
+            download_speed_avg_bps,
+            latency_avg_ms,
+            timestamp: Utc::now().encode_timestamp(),
+            speedtests: speedtests_without_lapsed(
+                self.speedtests.iter(),
+                Duration::hours(SPEEDTEST_LAPSE),
             )
+            .map(|st| proto::Speedtest {
+                timestamp: st.timestamp.timestamp() as u64,
+                upload_speed_bps: st.upload_speed as u64,
+                download_speed_bps: st.download_speed as u64,
+                latency_ms: st.latency as u32,
+            })
+            .collect(),
+            validity: validity as i32,
+            reward_multiplier,
+        }
+    }
+
+    pub async fn write(
+        &self,
+        averages: &file_sink::FileSinkClient,
+        min_speedtests: usize,
+    ) -> file_store::Result {
+        // Write out the speedtests to S3
+        let average = self.to_proto(min_speedtests);
+        let validity = average.validity();
+        averages
+            .write(average, &[("validity", validity.as_str_name())])
             .await?;
 
         Ok(())
     }
 }
 
-#[derive(Clone, Default)]
+/// Number of recent speedtests of a gateway with fewer than the minimum
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InsufficientSpeedtests {
+    pub speedtests: usize,
+    pub required: usize,
+}
+
+#[derive(Clone)]
 pub struct SpeedtestAverages {
     // I'm not sure that VecDeque is actually all that useful here, considering
     // we have to constantly convert between the two.
     // It does make me more confident in the implementation of validate_speedtests
     // though.
     pub speedtests: HashMap<PublicKeyBinary, VecDeque<Speedtest>>,
+    /// Minimum number of recent speedtests for a non zero multiplier
+    pub min_speedtests: usize,
+}
+
+impl Default for SpeedtestAverages {
+    fn default() -> Self {
+        Self {
+            speedtests: HashMap::new(),
+            min_speedtests: MIN_REQUIRED_SAMPLES,
+        }
+    }
 }
 
 impl SpeedtestAverages {
+    pub fn min_speedtests(self, min_speedtests: usize) -> Self {
+        Self {
+            min_speedtests,
+            ..self
+        }
+    }
+
     #[allow(dead_code)]
     pub fn into_iter(self) -> impl IntoIterator<Item = SpeedtestRollingAverage> {
         self.speedtests
@@ -312,6 +368,22 @@ impl SpeedtestAverages {
         self.speedtests.get(pub_key).map(Average::from)
     }
 
+    /// Reward multiplier of the gateway, or the number of its recent
+    /// speedtests when it has fewer than the minimum
+    pub fn reward_multiplier(
+        &self,
+        pub_key: &PublicKeyBinary,
+    ) -> Result<Decimal, InsufficientSpeedtests> {
+        let average = self.get_average(pub_key).unwrap_or_default();
+        if average.window_size < self.min_speedtests {
+            return Err(InsufficientSpeedtests {
+                speedtests: average.window_size,
+                required: self.min_speedtests,
+            });
+        }
+        Ok(average.reward_multiplier(self.min_speedtests))
+    }
+
     pub async fn validated(
         exec: impl sqlx::PgExecutor<'_> + Copy,
         period_end: DateTime<Utc>,
@@ -330,13 +402,29 @@ impl SpeedtestAverages {
             ..
         }) = rows.try_next().await?
         {
-            speedtests.insert(id, VecDeque::from(window));
+            let window = speedtests_before(window, period_end);
+            if !window.is_empty() {
+                speedtests.insert(id, window);
+            }
         }
 
-        Ok(Self { speedtests })
+        Ok(Self {
+            speedtests,
+            ..Default::default()
+        })
     }
 }
 
+/// Drop any speedtests taken at or after the end of the period. Rolling
+/// averages keep being updated after a period closes, so without this the
+/// rewards for a period would depend on when they were calculated.
+fn speedtests_before(window: Vec<Speedtest>, period_end: DateTime<Utc>) -> VecDeque<Speedtest> {
+    window
+        .into_iter()
+        .filter(|speedtest| speedtest.timestamp < period_end)
+        .collect()
+}
+
 impl Extend<SpeedtestRollingAverage> for SpeedtestAverages {
     fn extend<T>(&mut self, iter: T)
     where
@@ -409,12 +497,13 @@ where
 const MIN_DOWNLOAD: u64 = mbps(30);
 const MIN_UPLOAD: u64 = mbps(2);
 const MAX_LATENCY: u32 = 100;
+/// Default minimum number of recent speedtests for a valid average
 pub const MIN_REQUIRED_SAMPLES: usize = 2;
 
 impl Average {
     // TODO: Change this to a multiplier
-    pub fn validity(&self) -> proto::SpeedtestAvgValidity {
-        if self.window_size < MIN_REQUIRED_SAMPLES {
+    pub fn validity(&self, min_samples: usize) -> proto::SpeedtestAvgValidity {
+        if self.window_size < min_samples {
             return proto::SpeedtestAvgValidity::TooFewSamples;
         }
         if self.download_speed_avg_bps < MIN_DOWNLOAD {
@@ -429,8 +518,8 @@ impl Average {
         proto::SpeedtestAvgValidity::Valid
     }
 
-    pub fn tier(&self) -> SpeedtestTier {
-        if self.window_size < MIN_REQUIRED_SAMPLES {
+    pub fn tier(&self, min_samples: usize) -> SpeedtestTier {
+        if self.window_size < min_samples {
             SpeedtestTier::Failed
         } else {
             SpeedtestTier::from_download_speed(self.download_speed_avg_bps)
@@ -439,8 +528,8 @@ impl Average {
         }
     }
 
-    pub fn reward_multiplier(&self) -> Decimal {
-        self.tier().into_multiplier()
+    pub fn reward_multiplier(&self, min_samples: usize) -> Decimal {
+        self.tier(min_samples).into_multiplier()
     }
 }
 
@@ -587,11 +676,11 @@ mod test {
     fn check_known_valid() {
         let speedtests = known_speedtests();
         assert_ne!(
-            Average::from(&speedtests[0..5]).tier(),
+            Average::from(&speedtests[0..5]).tier(MIN_REQUIRED_SAMPLES),
             SpeedtestTier::Acceptable,
         );
         assert_eq!(
-            Average::from(&speedtests[0..6]).tier(),
+            Average::from(&speedtests[0..6]).tier(MIN_REQUIRED_SAMPLES),
             SpeedtestTier::Acceptable
         );
     }
@@ -600,15 +689,15 @@ mod test {
     fn check_minimum_known_valid() {
         let speedtests = known_speedtests();
         assert_ne!(
-            Average::from(&speedtests[4..4]).tier(),
+            Average::from(&speedtests[4..4]).tier(MIN_REQUIRED_SAMPLES),
             SpeedtestTier::Acceptable
         );
         assert_eq!(
-            Average::from(&speedtests[4..=5]).tier(),
+            Average::from(&speedtests[4..=5]).tier(MIN_REQUIRED_SAMPLES),
             SpeedtestTier::Acceptable
         );
         assert_eq!(
-            Average::from(&speedtests[4..=6]).tier(),
+            Average::from(&speedtests[4..=6]).tier(MIN_REQUIRED_SAMPLES),
             SpeedtestTier::Acceptable
         );
     }
@@ -617,7 +706,7 @@ mod test {
     fn check_minimum_known_invalid() {
         let speedtests = known_speedtests();
         assert_ne!(
-            Average::from(&speedtests[5..6]).tier(),
+            Average::from(&speedtests[5..6]).tier(MIN_REQUIRED_SAMPLES),
             SpeedtestTier::Acceptable
         );
     }
@@ -630,6 +719,7 @@ mod test {
         let speedtests = VecDeque::from(known_speedtests());
         let avgs = SpeedtestAverages {
             speedtests: HashMap::from([(owner, speedtests)]),
+            ..Default::default()
         }
         .into_iter();
         for avg in avgs {
@@ -672,4 +762,34 @@ mod test {
         assert_eq!(contiguous_speedtests.count(), 8);
         assert_eq!(disjoint_speedtests.count(), 1);
     }
+
+    #[test]
+    fn check_speedtests_before_period_end() {
+        let period_end = parse_dt("2022-08-02 12:00:00 +0000");
+        let window = speedtests_before(known_speedtests(), period_end);
+
+        // The speedtest taken exactly at the end of the period belongs to the
+        // next period
+        assert_eq!(window.len(), 6);
+        assert!(window.iter().all(|st| st.timestamp < period_end));
+        assert_eq!(
+            window.front().map(|st| st.timestamp),
+            Some(parse_dt("2022-08-02 6:00:00 +0000"))
+        );
+
+        let at = |timestamp| Speedtest::new(timestamp, 0, 0, 0);
+        let millisecond = Duration::milliseconds(1);
+        let window = speedtests_before(
+            vec![
+                at(period_end - millisecond),
+                at(period_end),
+                at(period_end + millisecond),
+            ],
+            period_end,
+        );
+        assert_eq!(
+            window.iter().map(|st| st.timestamp).collect::<Vec<_>>(),
+            vec![parse_dt("2022-08-02 11:59:59 +0000") + Duration::milliseconds(999)]
+        );
+    }
 }
@@ -71,9 +71,8 @@ impl SubscriberLocationIngestor {
         file_info_stream: FileInfoStream<SubscriberLocationIngestReport>,
     ) -> anyhow::Result<()> {
         let mut transaction = self.pool.begin().await?;
-        file_info_stream
-            .into_stream(&mut transaction)
-            .await?
+        let (reports, completion) = file_info_stream.into_stream(&mut transaction).await?;
+        let transaction = reports
             .map(anyhow::Ok)
             .try_fold(
                 transaction,
@@ -106,9 +105,8 @@ impl SubscriberLocationIngestor {
                     Ok(transaction)
                 },
             )
-            .await?
-            .commit()
             .await?;
+        completion.commit(transaction).await?;
         Ok(())
     }
 
@@ -5,6 +5,10 @@ use crate::rewarder;
 
 const LAST_REWARDED_END_TIME: &str = "last_rewarded_end_time";
 const DATA_TRANSFER_REWARDS_SCALE: &str = "data_transfer_rewards_scale";
+const HEARTBEAT_FILES_RATE: &str = "heartbeat_files_per_second";
+const HEARTBEAT_RECORDS_RATE: &str = "heartbeat_records_per_second";
+const HEARTBEAT_BATCH_DURATION: &str = "heartbeat_batch_duration";
+const INSUFFICIENT_SPEEDTEST_RADIOS: &str = "insufficient_speedtest_radios";
 
 pub async fn initialize(db: &Pool<Postgres>) -> anyhow::Result<()> {
     last_rewarded_end_time(rewarder::last_rewarded_end_time(db).await?);
@@ -19,3 +23,18 @@ pub fn last_rewarded_end_time(timestamp: DateTime<Utc>) {
 pub fn data_transfer_rewards_scale(scale: f64) {
     metrics::gauge!(DATA_TRANSFER_REWARDS_SCALE, scale);
 }
+
+/// Record the seconds a batch of heartbeat files took and the files and
+/// records per second it was processed at
+pub fn heartbeat_batch_processed(files: usize, records: u64, start: std::time::Instant) {
+    let elapsed = start.elapsed().as_secs_f64();
+    metrics::histogram!(HEARTBEAT_BATCH_DURATION, elapsed);
+    if elapsed > 0.0 {
+        metrics::gauge!(HEARTBEAT_FILES_RATE, files as f64 / elapsed);
+        metrics::gauge!(HEARTBEAT_RECORDS_RATE, records as f64 / elapsed);
+    }
+}
+
+pub fn insufficient_speedtest_radios(count: usize) {
+    metrics::gauge!(INSUFFICIENT_SPEEDTEST_RADIOS, count as f64);
+}
@@ -0,0 +1,101 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use file_store::reward_manifest::RewardCorrectionV1;
+use helium_crypto::PublicKeyBinary;
+use helium_proto::services::poc_mobile::{
+    self as proto, mobile_reward_share::Reward as ProtoReward, MobileRewardShare,
+};
+use mobile_verifier::corrections::{self, Corrections};
+use sqlx::PgPool;
+
+fn hotspot(byte: u8) -> PublicKeyBinary {
+    PublicKeyBinary::from(vec![byte])
+}
+
+fn epoch_end() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 9, 2, 0, 0, 0).unwrap()
+}
+
+async fn record(pool: &PgPool, hotspot_key: &PublicKeyBinary, amount: i64, request: u8) -> i64 {
+    corrections::record(pool, hotspot_key, amount, "test", "signer", &[request])
+        .await
+        .unwrap()
+        .expect("correction recorded")
+}
+
+#[sqlx::test]
+async fn replayed_requests_are_not_recorded(pool: PgPool) {
+    let first = record(&pool, &hotspot(1), 100, 1).await;
+    let replayed = corrections::record(&pool, &hotspot(1), 100, "test", "signer", &[1])
+        .await
+        .unwrap();
+    assert_eq!(replayed, None);
+    let second = record(&pool, &hotspot(1), 100, 2).await;
+
+    let pending: Vec<(i64, i64)> = corrections::pending(&pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|correction| (correction.id, correction.amount))
+        .collect();
+    assert_eq!(pending, vec![(first, 100), (second, 100)]);
+}
+
+#[sqlx::test]
+async fn applied_corrections_are_saved_with_their_remainders(pool: PgPool) {
+    let withheld = record(&pool, &hotspot(1), -100, 1).await;
+    let paid = record(&pool, &hotspot(2), 50, 2).await;
+
+    let mut corrections = Corrections::pending(&pool).await.unwrap();
+    assert_eq!(corrections.fund(1000), 50);
+    corrections.withhold(MobileRewardShare {
+        start_period: 0,
+        end_period: 0,
+        reward: Some(ProtoReward::RadioReward(proto::RadioReward {
+            hotspot_key: hotspot(1).into(),
+            poc_reward: 30,
+            ..Default::default()
+        })),
+    });
+    let epoch = epoch_end() - Duration::days(1)..epoch_end();
+    let (_, applied) = corrections.finish(&epoch);
+    assert_eq!(
+        applied.items,
+        vec![
+            RewardCorrectionV1 {
+                hotspot_key: hotspot(1).into(),
+                ids: vec![withheld],
+                amount: -100,
+                applied: -30,
+            },
+            RewardCorrectionV1 {
+                hotspot_key: hotspot(2).into(),
+                ids: vec![paid],
+                amount: 50,
+                applied: 50,
+            },
+        ]
+    );
+
+    let mut transaction = pool.begin().await.unwrap();
+    applied.save(&mut transaction, epoch_end()).await.unwrap();
+    transaction.commit().await.unwrap();
+
+    // Only the remainder of the withheld correction is left for the next
+    // epoch, without a signer
+    let pending = corrections::pending(&pool).await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].hotspot_key, hotspot(1));
+    assert_eq!(pending[0].amount, -70);
+    assert_eq!(pending[0].signer, None);
+
+    let applied_to: Vec<(i64, DateTime<Utc>)> = sqlx::query_as(
+        "SELECT id, applied_to FROM reward_corrections WHERE applied_to IS NOT NULL ORDER BY id",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        applied_to,
+        vec![(withheld, epoch_end()), (paid, epoch_end())]
+    );
+}
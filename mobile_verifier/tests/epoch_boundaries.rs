@@ -0,0 +1,123 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use futures::TryStreamExt;
+use helium_crypto::PublicKeyBinary;
+use helium_proto::services::poc_mobile as proto;
+use mobile_verifier::{
+    cell_type::CellType,
+    heartbeats::{Heartbeat, HeartbeatBatch, HeartbeatReward},
+    RewardConfig,
+};
+use oracle_epoch::EpochPolicy;
+use sqlx::PgPool;
+use std::ops::Range;
+
+fn at(day: u32, hour: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 9, day, hour, 0, 0).unwrap()
+}
+
+fn epoch() -> Range<DateTime<Utc>> {
+    at(1, 0)..at(2, 0)
+}
+
+fn heartbeat(cbsd_id: &str, timestamp: DateTime<Utc>) -> Heartbeat {
+    Heartbeat {
+        cbsd_id: cbsd_id.to_string(),
+        cell_type: Some(CellType::Nova436H),
+        hotspot_key: PublicKeyBinary::from(vec![1]),
+        timestamp,
+        validity: proto::HeartbeatValidity::Valid,
+        location_mismatch: None,
+    }
+}
+
+async fn rewarded(pool: &PgPool, epoch: &Range<DateTime<Utc>>) -> Vec<String> {
+    let reward_config = RewardConfig::default();
+    let mut rewarded: Vec<_> = HeartbeatReward::validated(pool, epoch, &reward_config)
+        .map_ok(|reward| reward.cbsd_id)
+        .try_collect()
+        .await
+        .unwrap();
+    rewarded.sort();
+    rewarded
+}
+
+#[test]
+fn heartbeats_at_the_epoch_end_are_accepted_only_within_the_skew() {
+    let epoch = epoch();
+    let millisecond = Duration::milliseconds(1);
+    let before_end =
+        at(1, 23) + Duration::minutes(59) + Duration::seconds(59) + Duration::milliseconds(999);
+    assert_eq!(epoch.end - millisecond, before_end);
+
+    let policy = EpochPolicy::default();
+    assert!(policy.accepts(&epoch, &before_end));
+    assert!(!policy.accepts(&epoch, &at(2, 0)));
+    assert!(!policy.accepts(&epoch, &(at(2, 0) + millisecond)));
+
+    let policy = EpochPolicy::new(Duration::seconds(30));
+    assert!(policy.accepts(&epoch, &before_end));
+    assert!(policy.accepts(&epoch, &at(2, 0)));
+    assert!(policy.accepts(&epoch, &(at(2, 0) + millisecond)));
+    assert!(!policy.accepts(&epoch, &(at(2, 0) + Duration::seconds(30))));
+    assert_eq!(policy.settled_at(&epoch), at(2, 0) + Duration::seconds(30));
+
+    // The hour a heartbeat is saved in, and so rewarded in, ignores the skew
+    let hour = |timestamp| heartbeat("cbsd", timestamp).truncated_timestamp().unwrap();
+    assert_eq!(hour(before_end), at(1, 23));
+    assert_eq!(hour(at(2, 0)), at(2, 0));
+    assert_eq!(hour(at(2, 0) + millisecond), at(2, 0));
+}
+
+#[sqlx::test]
+async fn heartbeats_at_the_epoch_end_are_rewarded_in_the_next_epoch(pool: PgPool) {
+    let epoch = epoch();
+    let next_epoch = epoch.end..epoch.end + Duration::hours(24);
+    let millisecond = Duration::milliseconds(1);
+
+    // Every cbsd has heartbeats in the 11 hours before the last hour of the
+    // epoch, one short of the minimum, and a last one around its end
+    let mut batch = HeartbeatBatch::default();
+    for (cbsd_id, last) in [
+        ("before-end", epoch.end - millisecond),
+        ("at-end", epoch.end),
+        ("after-end", epoch.end + millisecond),
+    ] {
+        for hour in 12..23 {
+            batch.push(heartbeat(cbsd_id, at(1, hour))).unwrap();
+        }
+        batch.push(heartbeat(cbsd_id, last)).unwrap();
+    }
+    let mut transaction = pool.begin().await.unwrap();
+    batch.save(&mut transaction).await.unwrap();
+    transaction.commit().await.unwrap();
+
+    let last_hours: Vec<(String, DateTime<Utc>)> = sqlx::query_as(
+        r#"
+        SELECT cbsd_id, MAX(truncated_timestamp) FROM heartbeats
+        GROUP BY cbsd_id
+        ORDER BY cbsd_id
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        last_hours,
+        vec![
+            ("after-end".to_string(), at(2, 0)),
+            ("at-end".to_string(), at(2, 0)),
+            ("before-end".to_string(), at(1, 23)),
+        ]
+    );
+
+    assert_eq!(rewarded(&pool, &epoch).await, vec!["before-end"]);
+    // The hour at the end counts towards the next epoch instead
+    let next_hours: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM heartbeats WHERE truncated_timestamp >= $1")
+            .bind(next_epoch.start)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(next_hours, 2);
+    assert!(rewarded(&pool, &next_epoch).await.is_empty());
+}
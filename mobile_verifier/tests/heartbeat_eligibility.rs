@@ -0,0 +1,235 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use futures::TryStreamExt;
+use helium_crypto::PublicKeyBinary;
+use helium_proto::services::poc_mobile as proto;
+use mobile_verifier::{
+    cell_type::CellType,
+    heartbeats::{Heartbeat, HeartbeatBatch, HeartbeatEligibility, HeartbeatReward},
+    RewardConfig,
+};
+use rust_decimal::Decimal;
+use sqlx::PgPool;
+use std::ops::Range;
+
+fn hotspot(id: u8) -> PublicKeyBinary {
+    PublicKeyBinary::from(vec![id])
+}
+
+fn hour(hours: i64) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 9, 1, 0, 0, 0).unwrap() + Duration::hours(hours)
+}
+
+fn window(day: i64) -> Range<DateTime<Utc>> {
+    hour(24 * day)..hour(24 * (day + 1))
+}
+
+/// Save a valid heartbeat of the cbsd in every given hour
+async fn save(
+    pool: &PgPool,
+    cbsd_id: &str,
+    hotspot_key: &PublicKeyBinary,
+    cell_type: CellType,
+    hours: Range<i64>,
+) {
+    let mut batch = HeartbeatBatch::default();
+    for i in hours {
+        batch
+            .push(Heartbeat {
+                cbsd_id: cbsd_id.to_string(),
+                cell_type: Some(cell_type),
+                hotspot_key: hotspot_key.clone(),
+                timestamp: hour(i) + Duration::minutes(10),
+                validity: proto::HeartbeatValidity::Valid,
+                location_mismatch: None,
+            })
+            .unwrap();
+    }
+    let mut transaction = pool.begin().await.unwrap();
+    batch.save(&mut transaction).await.unwrap();
+    transaction.commit().await.unwrap();
+}
+
+async fn update(
+    pool: &PgPool,
+    cbsd_id: &str,
+    hotspot_key: &PublicKeyBinary,
+    window: &Range<DateTime<Utc>>,
+) {
+    let mut transaction = pool.begin().await.unwrap();
+    HeartbeatEligibility::update(&mut transaction, cbsd_id, hotspot_key, window)
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+}
+
+/// Hotspot and hours of the eligibility rows of the cbsd, by window start
+async fn eligibility(pool: &PgPool, cbsd_id: &str) -> Vec<(String, DateTime<Utc>, i64)> {
+    sqlx::query_as(
+        r#"
+        SELECT hotspot_key, window_start, hours FROM heartbeat_eligibility
+        WHERE cbsd_id = $1
+        ORDER BY window_start
+        "#,
+    )
+    .bind(cbsd_id)
+    .fetch_all(pool)
+    .await
+    .unwrap()
+}
+
+async fn collect(
+    rewards: impl futures::Stream<Item = Result<HeartbeatReward, sqlx::Error>>,
+) -> Vec<(String, PublicKeyBinary, Decimal)> {
+    let mut rewards: Vec<_> = rewards
+        .map_ok(|reward| (reward.cbsd_id, reward.hotspot_key, reward.reward_weight))
+        .try_collect()
+        .await
+        .unwrap();
+    rewards.sort_by(|a, b| a.0.cmp(&b.0));
+    rewards
+}
+
+async fn validated(
+    pool: &PgPool,
+    epoch: &Range<DateTime<Utc>>,
+) -> Vec<(String, PublicKeyBinary, Decimal)> {
+    collect(HeartbeatReward::validated(
+        pool,
+        epoch,
+        &RewardConfig::default(),
+    ))
+    .await
+}
+
+async fn eligible(
+    pool: &PgPool,
+    epoch: &Range<DateTime<Utc>>,
+) -> Vec<(String, PublicKeyBinary, Decimal)> {
+    collect(HeartbeatReward::eligible(
+        pool,
+        epoch,
+        &RewardConfig::default(),
+    ))
+    .await
+}
+
+#[sqlx::test]
+async fn update_counts_the_hours_of_the_cbsd_in_the_window(pool: PgPool) {
+    save(&pool, "cbsd-1", &hotspot(1), CellType::Nova436H, 0..12).await;
+    // Hours of the next window are not counted
+    save(&pool, "cbsd-1", &hotspot(1), CellType::Nova436H, 24..26).await;
+    save(&pool, "cbsd-2", &hotspot(2), CellType::SercommIndoor, 0..11).await;
+
+    update(&pool, "cbsd-1", &hotspot(1), &window(0)).await;
+    update(&pool, "cbsd-2", &hotspot(2), &window(0)).await;
+    assert_eq!(
+        eligibility(&pool, "cbsd-1").await,
+        vec![(hotspot(1).to_string(), hour(0), 12)]
+    );
+    assert_eq!(
+        eligibility(&pool, "cbsd-2").await,
+        vec![(hotspot(2).to_string(), hour(0), 11)]
+    );
+
+    // Recounting after another hour replaces the count
+    save(
+        &pool,
+        "cbsd-2",
+        &hotspot(2),
+        CellType::SercommIndoor,
+        11..12,
+    )
+    .await;
+    update(&pool, "cbsd-2", &hotspot(2), &window(0)).await;
+    assert_eq!(
+        eligibility(&pool, "cbsd-2").await,
+        vec![(hotspot(2).to_string(), hour(0), 12)]
+    );
+
+    let eligible = eligible(&pool, &window(0)).await;
+    assert_eq!(eligible.len(), 2);
+    assert_eq!(eligible, validated(&pool, &window(0)).await);
+}
+
+#[sqlx::test]
+async fn update_drops_the_eligibility_under_a_previous_hotspot(pool: PgPool) {
+    save(&pool, "cbsd-1", &hotspot(1), CellType::Nova436H, 0..36).await;
+    update(&pool, "cbsd-1", &hotspot(1), &window(0)).await;
+    update(&pool, "cbsd-1", &hotspot(1), &window(1)).await;
+    assert_eq!(eligible(&pool, &window(0)).await.len(), 1);
+
+    // The cbsd moved to another hotspot, which drops its heartbeats under
+    // the first one
+    save(&pool, "cbsd-1", &hotspot(2), CellType::Nova436H, 37..38).await;
+    update(&pool, "cbsd-1", &hotspot(2), &window(1)).await;
+    assert_eq!(
+        eligibility(&pool, "cbsd-1").await,
+        vec![(hotspot(2).to_string(), hour(24), 1)]
+    );
+    assert!(eligible(&pool, &window(0)).await.is_empty());
+    assert_eq!(
+        eligible(&pool, &window(1)).await,
+        validated(&pool, &window(1)).await
+    );
+}
+
+#[sqlx::test]
+async fn backfill_matches_the_validated_heartbeats(pool: PgPool) {
+    save(&pool, "cbsd-1", &hotspot(1), CellType::Nova436H, 0..24).await;
+    save(&pool, "cbsd-2", &hotspot(1), CellType::SercommIndoor, 6..18).await;
+    save(&pool, "cbsd-3", &hotspot(2), CellType::Nova436H, 12..23).await;
+    save(
+        &pool,
+        "cbsd-4",
+        &hotspot(2),
+        CellType::SercommIndoor,
+        20..40,
+    )
+    .await;
+
+    HeartbeatEligibility::backfill(&pool, &window(0))
+        .await
+        .unwrap();
+    // Backfilling again recounts the same hours
+    HeartbeatEligibility::backfill(&pool, &window(0))
+        .await
+        .unwrap();
+    assert_eq!(
+        eligibility(&pool, "cbsd-4").await,
+        vec![(hotspot(2).to_string(), hour(0), 4)]
+    );
+
+    let eligible = eligible(&pool, &window(0)).await;
+    assert_eq!(
+        eligible
+            .iter()
+            .map(|(cbsd_id, ..)| cbsd_id.as_str())
+            .collect::<Vec<_>>(),
+        vec!["cbsd-1", "cbsd-2"]
+    );
+    assert_eq!(eligible, validated(&pool, &window(0)).await);
+}
+
+#[sqlx::test]
+async fn eligibility_is_maintained_only_for_the_exact_window(pool: PgPool) {
+    assert!(!HeartbeatEligibility::is_maintained(&pool, &window(0))
+        .await
+        .unwrap());
+
+    save(&pool, "cbsd-1", &hotspot(1), CellType::Nova436H, 0..12).await;
+    HeartbeatEligibility::backfill(&pool, &window(0))
+        .await
+        .unwrap();
+    assert!(HeartbeatEligibility::is_maintained(&pool, &window(0))
+        .await
+        .unwrap());
+    // Not after the reward period changed
+    assert!(
+        !HeartbeatEligibility::is_maintained(&pool, &(hour(0)..hour(12)))
+            .await
+            .unwrap()
+    );
+    assert!(!HeartbeatEligibility::is_maintained(&pool, &window(1))
+        .await
+        .unwrap());
+}
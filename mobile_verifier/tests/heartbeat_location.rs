@@ -0,0 +1,99 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use helium_crypto::PublicKeyBinary;
+use helium_proto::services::poc_mobile as proto;
+use mobile_verifier::{
+    heartbeat_location::{self, LocationCheck, LocationSettings},
+    heartbeats::Heartbeat,
+};
+use sqlx::PgPool;
+use std::collections::HashSet;
+
+fn hotspot() -> PublicKeyBinary {
+    PublicKeyBinary::from(vec![1])
+}
+
+fn hour(hours: i64) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 9, 1, 0, 0, 0).unwrap() + Duration::hours(hours)
+}
+
+fn check(denylist_after: u64) -> LocationCheck {
+    LocationCheck::try_from(&LocationSettings {
+        max_distance_km: 1.0,
+        resolution: 12,
+        denylist_after: Some(denylist_after),
+    })
+    .unwrap()
+}
+
+/// Record a mismatched heartbeat of the cbsd of the hotspot in the hour
+async fn record(pool: &PgPool, check: &LocationCheck, cbsd_id: &str, hour: DateTime<Utc>) {
+    let heartbeat = Heartbeat {
+        cbsd_id: cbsd_id.to_string(),
+        cell_type: None,
+        hotspot_key: hotspot(),
+        timestamp: hour,
+        validity: proto::HeartbeatValidity::Valid,
+        location_mismatch: Some(10.0),
+    };
+    let mut transaction = pool.begin().await.unwrap();
+    check
+        .record(&mut transaction, &heartbeat, hour)
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+}
+
+#[sqlx::test]
+async fn hotspots_are_denylisted_after_hours_with_a_mismatch(pool: PgPool) {
+    let check = check(2);
+    // Heartbeats of the same cbsd and hour count once
+    record(&pool, &check, "cbsd-1", hour(0)).await;
+    record(&pool, &check, "cbsd-1", hour(0)).await;
+    assert!(heartbeat_location::denylisted(&pool)
+        .await
+        .unwrap()
+        .is_empty());
+
+    record(&pool, &check, "cbsd-2", hour(0)).await;
+    assert_eq!(
+        heartbeat_location::denylisted(&pool).await.unwrap(),
+        HashSet::from([hotspot()])
+    );
+
+    let radios: Vec<String> = heartbeat_location::invalid_shares(&pool, &(hour(0)..hour(1)))
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|share| share.cbsd_id)
+        .collect();
+    assert_eq!(radios, vec!["cbsd-1".to_string(), "cbsd-2".to_string()]);
+}
+
+#[sqlx::test]
+async fn allowed_hotspots_count_their_mismatches_again(pool: PgPool) {
+    let check = check(2);
+    record(&pool, &check, "cbsd-1", hour(0)).await;
+    record(&pool, &check, "cbsd-1", hour(1)).await;
+    assert!(!heartbeat_location::denylisted(&pool)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert!(heartbeat_location::allow(&pool, &hotspot()).await.unwrap());
+    assert!(heartbeat_location::denylisted(&pool)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(!heartbeat_location::allow(&pool, &hotspot()).await.unwrap());
+
+    record(&pool, &check, "cbsd-1", hour(2)).await;
+    assert!(heartbeat_location::denylisted(&pool)
+        .await
+        .unwrap()
+        .is_empty());
+    record(&pool, &check, "cbsd-1", hour(3)).await;
+    assert_eq!(
+        heartbeat_location::denylisted(&pool).await.unwrap(),
+        HashSet::from([hotspot()])
+    );
+}
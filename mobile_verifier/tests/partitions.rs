@@ -0,0 +1,190 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use db_store::partitions::{PartitionSettings, Partitioner};
+use helium_crypto::PublicKeyBinary;
+use helium_proto::services::poc_mobile as proto;
+use mobile_verifier::{
+    cell_type::CellType,
+    heartbeats::{Heartbeat, HeartbeatBatch},
+};
+use sqlx::{Executor, PgPool};
+
+fn day(day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 9, day, 0, 0, 0).unwrap()
+}
+
+fn partitioner(pool: &PgPool) -> Partitioner {
+    Partitioner::new(
+        pool.clone(),
+        "heartbeats",
+        "truncated_timestamp",
+        &PartitionSettings {
+            retention_days: Some(2),
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+async fn partitions(pool: &PgPool) -> Vec<String> {
+    sqlx::query_scalar(
+        r#"
+        SELECT child.relname::text
+        FROM pg_inherits
+        JOIN pg_class parent ON pg_inherits.inhparent = parent.oid
+        JOIN pg_class child ON pg_inherits.inhrelid = child.oid
+        WHERE parent.relname = 'heartbeats'
+        ORDER BY child.relname
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap()
+}
+
+async fn count(pool: &PgPool, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+async fn insert(pool: &PgPool, cbsd_id: &str, hour: DateTime<Utc>) {
+    sqlx::query(
+        r#"
+        INSERT INTO heartbeats (cbsd_id, hotspot_key, cell_type, latest_timestamp, truncated_timestamp)
+        VALUES ($1, 'hotspot', 'nova436h', $2, $2)
+        "#,
+    )
+    .bind(cbsd_id)
+    .bind(hour)
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+#[sqlx::test]
+async fn partitions_of_every_day_of_the_range_are_created(pool: PgPool) {
+    let partitioner = partitioner(&pool);
+    partitioner
+        .ensure(&(day(1) + Duration::hours(12)..day(3)))
+        .await
+        .unwrap();
+    // Ensuring again is a no-op
+    partitioner.ensure(&(day(1)..day(2))).await.unwrap();
+    assert_eq!(
+        partitions(&pool).await,
+        vec![
+            "heartbeats_default",
+            "heartbeats_p20230901",
+            "heartbeats_p20230902"
+        ]
+    );
+}
+
+#[sqlx::test]
+async fn default_rows_are_moved_into_new_partitions(pool: PgPool) {
+    insert(&pool, "cbsd-1", day(1) + Duration::hours(5)).await;
+    insert(&pool, "cbsd-1", day(2) + Duration::hours(5)).await;
+    assert_eq!(count(&pool, "heartbeats_default").await, 2);
+
+    partitioner(&pool).ensure(&(day(1)..day(2))).await.unwrap();
+    assert_eq!(count(&pool, "heartbeats_p20230901").await, 1);
+    assert_eq!(count(&pool, "heartbeats_default").await, 1);
+    assert_eq!(count(&pool, "heartbeats").await, 2);
+}
+
+#[sqlx::test]
+async fn partitions_ending_before_the_retention_are_dropped(pool: PgPool) {
+    let partitioner = partitioner(&pool);
+    partitioner.ensure(&(day(1)..day(5))).await.unwrap();
+    insert(&pool, "cbsd-1", day(1)).await;
+    insert(&pool, "cbsd-1", day(4)).await;
+
+    let dropped = partitioner
+        .drop_expired(day(5) + Duration::hours(12))
+        .await
+        .unwrap();
+    assert_eq!(dropped.len(), 2);
+    assert!(dropped.contains(&"heartbeats_p20230901".to_string()));
+    assert!(dropped.contains(&"heartbeats_p20230902".to_string()));
+    assert_eq!(
+        partitions(&pool).await,
+        vec![
+            "heartbeats_default",
+            "heartbeats_p20230903",
+            "heartbeats_p20230904"
+        ]
+    );
+    assert_eq!(count(&pool, "heartbeats").await, 1);
+
+    // A dropped day is created again when written to
+    partitioner.ensure(&(day(1)..day(2))).await.unwrap();
+    assert!(partitions(&pool)
+        .await
+        .contains(&"heartbeats_p20230901".to_string()));
+}
+
+#[sqlx::test]
+async fn batched_heartbeats_are_upserted_into_partitions(pool: PgPool) {
+    partitioner(&pool).ensure(&(day(1)..day(3))).await.unwrap();
+    let heartbeat = |hour: i64, minute: i64| Heartbeat {
+        cbsd_id: "cbsd-1".to_string(),
+        cell_type: Some(CellType::Nova436H),
+        hotspot_key: PublicKeyBinary::from(vec![1]),
+        timestamp: day(1) + Duration::hours(hour) + Duration::minutes(minute),
+        validity: proto::HeartbeatValidity::Valid,
+        location_mismatch: None,
+    };
+
+    let mut batch = HeartbeatBatch::default();
+    batch.push(heartbeat(23, 10)).unwrap();
+    batch.push(heartbeat(24, 10)).unwrap();
+    let mut transaction = pool.begin().await.unwrap();
+    assert_eq!(batch.save(&mut transaction).await.unwrap().len(), 2);
+    transaction.commit().await.unwrap();
+
+    batch.push(heartbeat(24, 20)).unwrap();
+    let mut transaction = pool.begin().await.unwrap();
+    assert!(batch.save(&mut transaction).await.unwrap().is_empty());
+    transaction.commit().await.unwrap();
+
+    assert_eq!(count(&pool, "heartbeats_p20230901").await, 1);
+    assert_eq!(count(&pool, "heartbeats_p20230902").await, 1);
+    assert_eq!(count(&pool, "heartbeats_default").await, 0);
+    let latest: DateTime<Utc> =
+        sqlx::query_scalar("SELECT latest_timestamp FROM heartbeats_p20230902")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(latest, day(2) + Duration::minutes(20));
+}
+
+#[sqlx::test(migrations = false)]
+async fn migration_moves_heartbeats_into_partitions_of_their_day(pool: PgPool) {
+    let migrator = sqlx::migrate!("./migrations");
+    for migration in migrator.migrations.iter().filter(|m| m.version < 16) {
+        pool.execute(&*migration.sql).await.unwrap();
+    }
+    insert(&pool, "cbsd-1", day(1) + Duration::hours(23)).await;
+    insert(&pool, "cbsd-1", day(2)).await;
+    insert(&pool, "cbsd-2", day(2)).await;
+
+    let partitioned = migrator
+        .migrations
+        .iter()
+        .find(|m| m.version == 16)
+        .unwrap();
+    pool.execute(&*partitioned.sql).await.unwrap();
+
+    assert_eq!(
+        partitions(&pool).await,
+        vec![
+            "heartbeats_default",
+            "heartbeats_p20230901",
+            "heartbeats_p20230902"
+        ]
+    );
+    assert_eq!(count(&pool, "heartbeats_p20230901").await, 1);
+    assert_eq!(count(&pool, "heartbeats_p20230902").await, 2);
+    assert_eq!(count(&pool, "heartbeats_default").await, 0);
+}
@@ -0,0 +1,314 @@
+//! Half-open epochs of time shared by the verifiers.
+//!
+//! An [`Epoch`] runs from its start up to, but excluding, its end: a
+//! timestamp exactly at the end of an epoch belongs to the following one, so
+//! consecutive epochs never overlap and never leave a gap. Aligned epochs are
+//! whole periods counted from an origin, times before the origin fall in the
+//! epochs before it. All arithmetic is in whole seconds from the origin
+//! rounded down, which keeps sub-second timestamps at a boundary in the right
+//! epoch.
+//!
+//! An [`EpochPolicy`] tolerates clock skew: it widens the window in which a
+//! report is accepted for an epoch, never the epoch a report is attributed to.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error("epoch ends at {end} before it starts at {start}")]
+    Inverted {
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    },
+    #[error("epoch period must be a positive number of whole seconds, got {0}")]
+    InvalidPeriod(Duration),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "RawEpoch")]
+pub struct Epoch {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct RawEpoch {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+impl TryFrom<RawEpoch> for Epoch {
+    type Error = Error;
+
+    fn try_from(raw: RawEpoch) -> Result<Self> {
+        Self::new(raw.start, raw.end)
+    }
+}
+
+impl TryFrom<Range<DateTime<Utc>>> for Epoch {
+    type Error = Error;
+
+    fn try_from(range: Range<DateTime<Utc>>) -> Result<Self> {
+        Self::new(range.start, range.end)
+    }
+}
+
+impl From<Epoch> for Range<DateTime<Utc>> {
+    fn from(epoch: Epoch) -> Self {
+        epoch.range()
+    }
+}
+
+impl Epoch {
+    /// The epoch from `start` up to `end`, empty when they are equal
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Self> {
+        if end < start {
+            return Err(Error::Inverted { start, end });
+        }
+        Ok(Self { start, end })
+    }
+
+    /// The epoch of `period` containing `time`, with epochs aligned to
+    /// `origin`
+    pub fn containing(
+        origin: DateTime<Utc>,
+        period: Duration,
+        time: DateTime<Utc>,
+    ) -> Result<Self> {
+        let period_secs = period_secs(period)?;
+        let periods = floor_secs(time - origin).div_euclid(period_secs);
+        let start = origin + Duration::seconds(periods * period_secs);
+        Ok(Self {
+            start,
+            end: start + period,
+        })
+    }
+
+    /// The hour containing `time`
+    pub fn hour(time: DateTime<Utc>) -> Self {
+        Self::containing(Utc.timestamp_opt(0, 0).unwrap(), Duration::hours(1), time)
+            .expect("an hour is a valid period")
+    }
+
+    /// The epoch from `before` ahead of `time` up to `after` past it, with
+    /// negative margins taken as zero
+    pub fn around(time: DateTime<Utc>, before: Duration, after: Duration) -> Self {
+        Self {
+            start: time - before.max(Duration::zero()),
+            end: time + after.max(Duration::zero()),
+        }
+    }
+
+    pub fn start(&self) -> DateTime<Utc> {
+        self.start
+    }
+
+    pub fn end(&self) -> DateTime<Utc> {
+        self.end
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn range(&self) -> Range<DateTime<Utc>> {
+        self.start..self.end
+    }
+
+    pub fn contains(&self, time: &DateTime<Utc>) -> bool {
+        self.range().contains(time)
+    }
+
+    /// The epoch of the same length starting at the end of this one
+    pub fn following(&self) -> Self {
+        Self {
+            start: self.end,
+            end: self.end + self.duration(),
+        }
+    }
+
+    /// The epoch extended by `margin` on either side, e.g. to allow for clock
+    /// skew. Negative margins are taken as zero
+    pub fn widen(&self, margin: Duration) -> Self {
+        let margin = margin.max(Duration::zero());
+        Self {
+            start: self.start - margin,
+            end: self.end + margin,
+        }
+    }
+
+    /// Consecutive epochs of `length` covering this one, the last ending at
+    /// the end of this one and so possibly shorter
+    pub fn split(&self, length: Duration) -> Result<impl Iterator<Item = Epoch>> {
+        period_secs(length)?;
+        let end = self.end;
+        Ok(
+            std::iter::successors(Some(self.start), move |start| Some(*start + length))
+                .take_while(move |start| *start < end)
+                .map(move |start| Epoch {
+                    start,
+                    end: (start + length).min(end),
+                }),
+        )
+    }
+}
+
+/// Clock skew tolerated on the reports of an epoch
+#[derive(Clone, Copy, Debug)]
+pub struct EpochPolicy {
+    skew: Duration,
+}
+
+impl Default for EpochPolicy {
+    fn default() -> Self {
+        Self::new(Duration::zero())
+    }
+}
+
+impl EpochPolicy {
+    /// Policy tolerating `skew` on either side of an epoch, a negative skew
+    /// is taken as zero
+    pub fn new(skew: Duration) -> Self {
+        Self {
+            skew: skew.max(Duration::zero()),
+        }
+    }
+
+    pub fn skew(&self) -> Duration {
+        self.skew
+    }
+
+    /// Whether a report received at `timestamp` is accepted for the epoch,
+    /// allowing for the skew on either side
+    pub fn accepts(&self, epoch: &Range<DateTime<Utc>>, timestamp: &DateTime<Utc>) -> bool {
+        (epoch.start - self.skew..epoch.end + self.skew).contains(timestamp)
+    }
+
+    /// The earliest time all reports of the epoch, including those delayed
+    /// by clock skew, are expected to have arrived
+    pub fn settled_at(&self, epoch: &Range<DateTime<Utc>>) -> DateTime<Utc> {
+        epoch.end + self.skew
+    }
+}
+
+/// Truncate `time` to the start of its hour
+pub fn truncate_to_hour(time: DateTime<Utc>) -> DateTime<Utc> {
+    Epoch::hour(time).start
+}
+
+fn period_secs(period: Duration) -> Result<i64> {
+    let secs = period.num_seconds();
+    if secs < 1 || Duration::seconds(secs) != period {
+        return Err(Error::InvalidPeriod(period));
+    }
+    Ok(secs)
+}
+
+/// Whole seconds of the duration rounded down, rather than towards zero
+fn floor_secs(duration: Duration) -> i64 {
+    let secs = duration.num_seconds();
+    if Duration::seconds(secs) > duration {
+        secs - 1
+    } else {
+        secs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 8, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn aligned_epochs_are_half_open() {
+        let origin = at(1, 1);
+        let period = Duration::hours(24);
+        let epoch = |time| Epoch::containing(origin, period, time).unwrap().range();
+
+        assert_eq!(epoch(at(1, 1)), origin..at(2, 1));
+        assert_eq!(epoch(at(2, 1) - Duration::nanoseconds(1)), origin..at(2, 1));
+        assert_eq!(epoch(at(2, 1)), at(2, 1)..at(3, 1));
+        assert_eq!(
+            epoch(origin - Duration::nanoseconds(1)),
+            origin - period..origin
+        );
+
+        assert_eq!(truncate_to_hour(at(1, 1) + Duration::minutes(59)), at(1, 1));
+        assert_eq!(
+            Epoch::hour(at(1, 1)).following().range(),
+            at(1, 2)..at(1, 3)
+        );
+
+        assert!(Epoch::containing(origin, Duration::zero(), origin).is_err());
+        assert!(Epoch::containing(origin, Duration::milliseconds(1500), origin).is_err());
+        assert!(Epoch::new(at(2, 0), at(1, 0)).is_err());
+    }
+
+    #[test]
+    fn epochs_split_into_consecutive_parts() {
+        let epoch = Epoch::new(at(1, 0), at(1, 5)).unwrap();
+        let parts: Vec<_> = epoch
+            .split(Duration::hours(2))
+            .unwrap()
+            .map(|part| part.range())
+            .collect();
+        assert_eq!(
+            parts,
+            vec![at(1, 0)..at(1, 2), at(1, 2)..at(1, 4), at(1, 4)..at(1, 5)]
+        );
+        assert_eq!(
+            Epoch::new(at(1, 0), at(1, 0))
+                .unwrap()
+                .split(Duration::hours(1))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn skew_widens_acceptance_only() {
+        let epoch = at(1, 0)..at(2, 0);
+        let policy = EpochPolicy::new(Duration::seconds(30));
+
+        let late = epoch.end + Duration::seconds(29);
+        assert!(policy.accepts(&epoch, &late));
+        assert!(!epoch.contains(&late));
+        let early = epoch.start - Duration::seconds(30);
+        assert!(policy.accepts(&epoch, &early));
+        assert!(!policy.accepts(&epoch, &(epoch.end + Duration::seconds(30))));
+        assert!(!policy.accepts(&epoch, &(early - Duration::seconds(1))));
+        assert_eq!(policy.settled_at(&epoch), epoch.end + Duration::seconds(30));
+
+        // Without skew acceptance is the half-open epoch
+        let policy = EpochPolicy::new(Duration::seconds(-10));
+        assert_eq!(policy.skew(), Duration::zero());
+        assert!(policy.accepts(&epoch, &epoch.start));
+        assert!(!policy.accepts(&epoch, &epoch.end));
+        assert_eq!(policy.settled_at(&epoch), epoch.end);
+    }
+
+    #[test]
+    fn epochs_are_checked_when_deserialized() {
+        let epoch = Epoch::new(at(1, 0), at(2, 0)).unwrap();
+        let json = serde_json::to_string(&epoch).unwrap();
+        assert_eq!(serde_json::from_str::<Epoch>(&json).unwrap(), epoch);
+
+        let inverted = json
+            .replace("start", "tmp")
+            .replace("end", "start")
+            .replace("tmp", "end");
+        assert!(serde_json::from_str::<Epoch>(&inverted).is_err());
+    }
+}
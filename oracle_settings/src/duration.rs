@@ -0,0 +1,63 @@
+//! Serde support for durations given as a number of seconds or a string with
+//! a unit suffix, such as "500ms", "30s", "5m", "2h" or "1d".
+//!
+//! ```ignore
+//! #[serde(with = "oracle_settings::duration")]
+//! pub poll_interval: Duration,
+//! ```
+
+use serde::{de, Deserialize, Deserializer};
+use std::time::Duration;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawDuration {
+    Secs(u64),
+    Text(String),
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match RawDuration::deserialize(deserializer)? {
+        RawDuration::Secs(secs) => Ok(Duration::from_secs(secs)),
+        RawDuration::Text(text) => parse(&text).map_err(de::Error::custom),
+    }
+}
+
+pub fn parse(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration {value}"))?;
+    let millis_per_unit = match unit.trim() {
+        "ms" => 1,
+        "" | "s" => 1_000,
+        "m" => 60 * 1_000,
+        "h" => 60 * 60 * 1_000,
+        "d" => 24 * 60 * 60 * 1_000,
+        other => return Err(format!("invalid duration unit {other} in {value}")),
+    };
+    Ok(Duration::from_millis(amount * millis_per_unit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(parse("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse("5 weeks").is_err());
+        assert!(parse("m").is_err());
+    }
+}
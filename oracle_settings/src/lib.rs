@@ -0,0 +1,190 @@
+//! Layered settings loading shared by the oracle services.
+//!
+//! Settings are read from an optional toml file, then overridden by
+//! environment variables with a service specific prefix and finally by
+//! `--set key=value` command line flags. The loaded settings are validated
+//! before the service starts.
+
+use config::{Config, Environment, File};
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+
+pub mod duration;
+pub mod pubkey;
+pub mod reload;
+pub mod secret;
+
+pub use reload::{Reload, SettingsWatcher};
+pub use secret::{Secret, SecretResolver};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("config error: {0}")]
+    Config(#[from] config::ConfigError),
+    #[error("invalid settings: {0}")]
+    Invalid(String),
+    #[error("failed to resolve secret {0}: {1}")]
+    Secret(String, String),
+    #[error("failed to listen for reload signal: {0}")]
+    Signal(std::io::Error),
+}
+
+/// Startup validation of loaded settings beyond what deserialization checks
+pub trait Validate {
+    fn validate(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Command line settings overrides, flattened into a service `Cli`
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct Overrides {
+    /// Override a setting, for example `--set database.max_connections=5`.
+    /// Takes precedence over the settings file and environment
+    #[clap(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub values: Vec<(String, String)>,
+}
+
+/// Parse a `KEY=VALUE` command line argument. The value may hold `=` itself
+pub fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got {value}")),
+    }
+}
+
+#[derive(Clone)]
+pub struct SettingsLoader {
+    path: Option<PathBuf>,
+    env_prefix: String,
+    separator: String,
+    overrides: Vec<(String, String)>,
+}
+
+impl SettingsLoader {
+    pub fn new(env_prefix: &str) -> Self {
+        Self {
+            path: None,
+            env_prefix: env_prefix.to_string(),
+            separator: "_".to_string(),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Optional settings file, ignored if it doesn't exist
+    pub fn file(self, path: Option<impl AsRef<Path>>) -> Self {
+        Self {
+            path: path.map(|path| path.as_ref().to_path_buf()),
+            ..self
+        }
+    }
+
+    /// Separator between nested keys in environment variables. Default "_"
+    pub fn separator(self, separator: &str) -> Self {
+        Self {
+            separator: separator.to_string(),
+            ..self
+        }
+    }
+
+    pub fn overrides(self, overrides: &Overrides) -> Self {
+        Self {
+            overrides: overrides.values.clone(),
+            ..self
+        }
+    }
+
+    pub fn load<T>(self) -> Result<T, Error>
+    where
+        T: DeserializeOwned + Validate,
+    {
+        let settings: T = self.build()?.try_deserialize()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Load the settings together with the untyped values they were
+    /// deserialized from, used to find what changed on reload
+    fn load_raw<T>(&self) -> Result<(serde_json::Value, T), Error>
+    where
+        T: DeserializeOwned + Validate,
+    {
+        let config = self.build()?;
+        let raw = config.clone().try_deserialize()?;
+        let settings: T = config.try_deserialize()?;
+        settings.validate()?;
+        Ok((raw, settings))
+    }
+
+    fn build(&self) -> Result<Config, Error> {
+        let mut builder = Config::builder();
+        if let Some(path) = &self.path {
+            builder = builder.add_source(File::from(path.as_path()).required(false));
+        }
+        builder = builder
+            .add_source(Environment::with_prefix(&self.env_prefix).separator(&self.separator));
+        for (key, value) in &self.overrides {
+            builder = builder.set_override(key, value.as_str())?;
+        }
+        Ok(builder.build()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct TestSettings {
+        name: String,
+        #[serde(default)]
+        workers: u32,
+    }
+
+    impl Validate for TestSettings {
+        fn validate(&self) -> Result<(), Error> {
+            if self.workers == 0 {
+                return Err(Error::Invalid("workers must be positive".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn overrides_take_precedence_and_are_validated() {
+        let overrides = Overrides {
+            values: vec![
+                parse_key_value("name=verifier").unwrap(),
+                parse_key_value("workers=4").unwrap(),
+            ],
+        };
+        let settings: TestSettings = SettingsLoader::new("ORACLE_SETTINGS_TEST")
+            .overrides(&overrides)
+            .load()
+            .unwrap();
+        assert_eq!(settings.name, "verifier");
+        assert_eq!(settings.workers, 4);
+
+        let overrides = Overrides {
+            values: vec![parse_key_value("name=verifier").unwrap()],
+        };
+        assert!(matches!(
+            SettingsLoader::new("ORACLE_SETTINGS_TEST")
+                .overrides(&overrides)
+                .load::<TestSettings>(),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn override_requires_key() {
+        assert_eq!(
+            parse_key_value("a.b=c=d"),
+            Ok(("a.b".to_string(), "c=d".to_string()))
+        );
+        assert!(parse_key_value("=value").is_err());
+        assert!(parse_key_value("novalue").is_err());
+    }
+}
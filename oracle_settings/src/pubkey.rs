@@ -0,0 +1,34 @@
+//! Serde support for helium public keys given in their b58 string form.
+//!
+//! ```ignore
+//! #[serde(with = "oracle_settings::pubkey")]
+//! pub admin: PublicKey,
+//! #[serde(default, deserialize_with = "oracle_settings::pubkey::deserialize_vec")]
+//! pub admin_keys: Vec<PublicKey>,
+//! ```
+
+use helium_crypto::PublicKey;
+use serde::{de, Deserialize, Deserializer};
+use std::str::FromStr;
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<PublicKey, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let key = String::deserialize(deserializer)?;
+    parse(&key).map_err(de::Error::custom)
+}
+
+pub fn deserialize_vec<'de, D>(deserializer: D) -> Result<Vec<PublicKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|key| parse(key).map_err(de::Error::custom))
+        .collect()
+}
+
+fn parse(key: &str) -> Result<PublicKey, String> {
+    PublicKey::from_str(key).map_err(|err| format!("invalid pubkey {key}: {err}"))
+}
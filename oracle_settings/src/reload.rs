@@ -0,0 +1,272 @@
+//! Reloading of settings while a service is running.
+//!
+//! The settings are loaded again when the process receives SIGHUP or the
+//! settings file changes. Only the values a service declares as live are
+//! handed to its running components, through a watch channel. Changes to any
+//! other setting are logged and ignored until the service is restarted.
+
+use crate::{Error, SettingsLoader, Validate};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::time::{Duration, SystemTime};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+
+/// How often the settings file is checked for changes
+const FILE_POLL_PERIOD: Duration = Duration::from_secs(10);
+
+pub trait Reload: DeserializeOwned + Validate {
+    /// The settings applied to the running service
+    type Live: Clone + PartialEq + Send + Sync + 'static;
+
+    /// Settings keys, or prefixes of nested keys like "tracing", of the
+    /// values in `Live`. Any other key is only read at startup.
+    const LIVE_KEYS: &'static [&'static str];
+
+    fn live(&self) -> Self::Live;
+}
+
+pub struct SettingsWatcher<T: Reload> {
+    loader: SettingsLoader,
+    raw: Value,
+    live: watch::Sender<T::Live>,
+}
+
+impl<T: Reload> SettingsWatcher<T> {
+    /// Load the settings, returning them with a watcher that keeps their
+    /// live values up to date
+    pub fn load(loader: SettingsLoader) -> Result<(T, Self), Error> {
+        let (raw, settings) = loader.load_raw::<T>()?;
+        let (live, _) = watch::channel(settings.live());
+        Ok((settings, Self { loader, raw, live }))
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<T::Live> {
+        self.live.subscribe()
+    }
+
+    /// Load the settings again and publish any change to the live values.
+    /// Returns the keys whose changes were rejected because they require a
+    /// restart.
+    pub fn reload(&mut self) -> Result<Vec<String>, Error> {
+        let (raw, settings) = self.loader.load_raw::<T>()?;
+        let mut rejected = Vec::new();
+        changed_keys(&self.raw, &raw, "", &mut rejected);
+        rejected.retain(|key| !is_live::<T>(key));
+
+        let live = settings.live();
+        self.live.send_if_modified(|current| {
+            if *current != live {
+                *current = live;
+                true
+            } else {
+                false
+            }
+        });
+        // Keep comparing against the settings the service was started with
+        // for the keys that were not applied
+        self.raw = merge_live::<T>(&self.raw, raw);
+        Ok(rejected)
+    }
+
+    fn file_modified(&self) -> Option<SystemTime> {
+        self.loader
+            .path
+            .as_ref()
+            .and_then(|path| std::fs::metadata(path).ok())
+            .and_then(|metadata| metadata.modified().ok())
+    }
+
+    /// Reload the settings on SIGHUP and whenever the settings file is
+    /// modified, until shutdown. Settings that fail to load or validate are
+    /// logged and the running values are kept.
+    pub async fn run(mut self, shutdown: triggered::Listener) -> Result<(), Error> {
+        let mut sighup = signal(SignalKind::hangup()).map_err(Error::Signal)?;
+        let mut file_poll = tokio::time::interval(FILE_POLL_PERIOD);
+        let mut modified = self.file_modified();
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => return Ok(()),
+                _ = sighup.recv() => (),
+                _ = file_poll.tick() => {
+                    let current = self.file_modified();
+                    if current == modified {
+                        continue;
+                    }
+                    modified = current;
+                }
+            }
+            match self.reload() {
+                Ok(rejected) => {
+                    for key in rejected {
+                        tracing::warn!(%key, "setting change requires a restart, ignored");
+                    }
+                    tracing::info!("reloaded settings");
+                }
+                Err(err) => tracing::error!("failed to reload settings: {err}"),
+            }
+        }
+    }
+}
+
+fn is_live<T: Reload>(key: &str) -> bool {
+    T::LIVE_KEYS.iter().any(|live| {
+        key == *live
+            || key
+                .strip_prefix(live)
+                .is_some_and(|rest| rest.starts_with('.'))
+    })
+}
+
+/// The dotted keys of the leaf values that differ between `old` and `new`
+fn changed_keys(old: &Value, new: &Value, prefix: &str, changed: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let keys = old
+                .keys()
+                .chain(new.keys().filter(|key| !old.contains_key(*key)));
+            for key in keys {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                changed_keys(
+                    old.get(key).unwrap_or(&Value::Null),
+                    new.get(key).unwrap_or(&Value::Null),
+                    &path,
+                    changed,
+                );
+            }
+        }
+        (old, new) if old != new => changed.push(prefix.to_string()),
+        _ => (),
+    }
+}
+
+/// The raw settings with the live values taken from `new`
+fn merge_live<T: Reload>(old: &Value, new: Value) -> Value {
+    let mut merged = old.clone();
+    for key in T::LIVE_KEYS {
+        let path: Vec<&str> = key.split('.').collect();
+        merge_key(&mut merged, &new, &path);
+    }
+    merged
+}
+
+/// Replace the value at `path` in `merged` with the one in `new`, leaving
+/// the other keys of the enclosing sections as they are
+fn merge_key(merged: &mut Value, new: &Value, path: &[&str]) {
+    let Some((key, rest)) = path.split_first() else {
+        return;
+    };
+    let Value::Object(merged) = merged else {
+        return;
+    };
+    let new = new.get(*key);
+    if rest.is_empty() {
+        match new {
+            Some(value) => merged.insert(key.to_string(), value.clone()),
+            None => merged.remove(*key),
+        };
+        return;
+    }
+    match (merged.get_mut(*key), new) {
+        (Some(section), Some(new)) => merge_key(section, new, rest),
+        (Some(section), None) => merge_key(section, &Value::Null, rest),
+        (None, Some(new)) => {
+            let mut section = Value::Object(Default::default());
+            merge_key(&mut section, new, rest);
+            merged.insert(key.to_string(), section);
+        }
+        (None, None) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct TestSettings {
+        period: u64,
+    }
+
+    impl Validate for TestSettings {}
+
+    impl Reload for TestSettings {
+        type Live = u64;
+        const LIVE_KEYS: &'static [&'static str] = &["period", "tracing"];
+
+        fn live(&self) -> u64 {
+            self.period
+        }
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    struct NestedSettings {}
+
+    impl Validate for NestedSettings {}
+
+    impl Reload for NestedSettings {
+        type Live = ();
+        const LIVE_KEYS: &'static [&'static str] = &["tracing.modules"];
+
+        fn live(&self) {}
+    }
+
+    #[test]
+    fn nested_changes_are_reported_by_key() {
+        let old = json!({"database": {"url": "a", "max_connections": 5}, "period": 1});
+        let new =
+            json!({"database": {"url": "b", "max_connections": 5}, "period": 2, "cache": "c"});
+        let mut changed = Vec::new();
+        changed_keys(&old, &new, "", &mut changed);
+        changed.sort();
+        assert_eq!(changed, vec!["cache", "database.url", "period"]);
+    }
+
+    #[test]
+    fn live_keys_match_nested_settings() {
+        assert!(is_live::<TestSettings>("period"));
+        assert!(is_live::<TestSettings>("tracing.modules.file_store"));
+        assert!(!is_live::<TestSettings>("periods"));
+        assert!(!is_live::<TestSettings>("database.url"));
+    }
+
+    #[test]
+    fn nested_live_keys_are_merged_per_key() {
+        let old = json!({"tracing": {"format": "pretty", "modules": {"file_store": "warn"}}});
+        let new = json!({"tracing": {"format": "json", "modules": {"file_store": "debug"}}});
+        let merged = merge_live::<NestedSettings>(&old, new);
+        assert_eq!(
+            merged,
+            json!({"tracing": {"format": "pretty", "modules": {"file_store": "debug"}}})
+        );
+
+        // a removed live section is removed, its siblings are kept
+        let merged = merge_live::<NestedSettings>(&merged, json!({"tracing": {"format": "json"}}));
+        assert_eq!(merged, json!({"tracing": {"format": "pretty"}}));
+
+        // and added again when it comes back
+        let merged = merge_live::<NestedSettings>(
+            &json!({"period": 1}),
+            json!({"tracing": {"modules": {"file_store": "warn"}}}),
+        );
+        assert_eq!(
+            merged,
+            json!({"period": 1, "tracing": {"modules": {"file_store": "warn"}}})
+        );
+    }
+
+    #[test]
+    fn only_live_values_are_merged() {
+        let old = json!({"database": {"url": "a"}, "period": 1});
+        let merged =
+            merge_live::<TestSettings>(&old, json!({"database": {"url": "b"}, "period": 2}));
+        assert_eq!(merged, json!({"database": {"url": "a"}, "period": 2}));
+    }
+}
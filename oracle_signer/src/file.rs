@@ -0,0 +1,32 @@
+use crate::{Result, Signer};
+use async_trait::async_trait;
+use helium_crypto::{Keypair, PublicKey, Sign};
+use std::path::Path;
+
+/// Signer with the keypair in memory
+pub struct FileSigner {
+    keypair: Keypair,
+}
+
+impl FileSigner {
+    pub fn new(keypair: Keypair) -> Self {
+        Self { keypair }
+    }
+
+    /// Read a binary helium keypair file
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        Ok(Self::new(Keypair::try_from(&data[..])?))
+    }
+}
+
+#[async_trait]
+impl Signer for FileSigner {
+    fn public_key(&self) -> &PublicKey {
+        self.keypair.public_key()
+    }
+
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.keypair.sign(message)?)
+    }
+}
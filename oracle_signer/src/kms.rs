@@ -0,0 +1,77 @@
+use crate::{Error, Result, Signer};
+use async_trait::async_trait;
+use aws_sdk_kms::{
+    model::{MessageType, SigningAlgorithmSpec},
+    types::Blob,
+    Client, Region,
+};
+use helium_crypto::{KeyType, PublicKey};
+use tokio::sync::OnceCell;
+
+/// Signer with the key in AWS KMS. The client is set up from the aws config
+/// on the first signature
+pub struct KmsSigner {
+    key_id: String,
+    public_key: PublicKey,
+    region: Option<String>,
+    algorithm: SigningAlgorithmSpec,
+    client: OnceCell<Client>,
+}
+
+impl KmsSigner {
+    pub fn new(key_id: String, public_key: PublicKey, region: Option<String>) -> Result<Self> {
+        let algorithm = match public_key.key_type() {
+            KeyType::Ed25519 => SigningAlgorithmSpec::from("ED25519_SHA_512"),
+            KeyType::EccCompact => SigningAlgorithmSpec::EcdsaSha256,
+            other => {
+                return Err(Error::InvalidSettings(format!(
+                    "kms can not sign with {other:?} keys"
+                )))
+            }
+        };
+        Ok(Self {
+            key_id,
+            public_key,
+            region,
+            algorithm,
+            client: OnceCell::new(),
+        })
+    }
+
+    async fn client(&self) -> &Client {
+        self.client
+            .get_or_init(|| async {
+                let mut config = aws_config::from_env();
+                if let Some(region) = &self.region {
+                    config = config.region(Region::new(region.clone()));
+                }
+                Client::new(&config.load().await)
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Signer for KmsSigner {
+    fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let output = self
+            .client()
+            .await
+            .sign()
+            .key_id(&self.key_id)
+            .message(Blob::new(message))
+            .message_type(MessageType::Raw)
+            .signing_algorithm(self.algorithm.clone())
+            .send()
+            .await
+            .map_err(|err| Error::Kms(err.to_string()))?;
+        output
+            .signature()
+            .map(|signature| signature.as_ref().to_vec())
+            .ok_or_else(|| Error::Kms(format!("no signature returned for {}", self.key_id)))
+    }
+}
@@ -0,0 +1,227 @@
+//! Signing with the key of an oracle.
+//!
+//! Oracles sign their requests to the config services and their solana
+//! transactions. The key either lives in a keypair file read at startup, or
+//! never leaves AWS KMS or a remote signing service, selected by the `type`
+//! of the [`Settings`]. Keys outside a file need their public key configured
+//! next to them, it is what the signatures are verified against.
+
+use async_trait::async_trait;
+use helium_crypto::{PublicKey, Verify};
+use poc_metrics::error_code::{self, ErrorCategory, ErrorCode};
+use serde::Deserialize;
+use std::{path::PathBuf, str::FromStr, sync::Arc};
+
+pub mod file;
+pub mod kms;
+pub mod remote;
+
+pub use file::FileSigner;
+pub use kms::KmsSigner;
+pub use remote::RemoteSigner;
+
+pub type Result<T = ()> = std::result::Result<T, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("crypto error: {0}")]
+    Crypto(#[from] helium_crypto::Error),
+    #[error("error reading keypair: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("kms error: {0}")]
+    Kms(String),
+    #[error("signing service error: {0}")]
+    Remote(#[from] reqwest::Error),
+    #[error("invalid signature: {0}")]
+    InvalidSignature(String),
+    #[error("invalid signer settings: {0}")]
+    InvalidSettings(String),
+}
+
+impl ErrorCode for Error {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Crypto(_) | Self::InvalidSignature(_) => ErrorCategory::Crypto,
+            Self::Io(_) | Self::InvalidSettings(_) => ErrorCategory::Config,
+            Self::Kms(_) | Self::Remote(_) => ErrorCategory::Network,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Crypto(_) => "signing",
+            Self::Io(_) => "keypair_file",
+            Self::Kms(_) => "kms",
+            Self::Remote(_) => "signing_service",
+            Self::InvalidSignature(_) => "invalid_signature",
+            Self::InvalidSettings(_) => "invalid_signer_settings",
+        }
+    }
+}
+
+/// Label the errors of this crate in metrics and logs
+pub fn register_error_codes() {
+    error_code::register::<Error>();
+}
+
+#[async_trait]
+pub trait Signer: Send + Sync + 'static {
+    /// Public key the signatures can be verified with
+    fn public_key(&self) -> &PublicKey;
+
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl std::fmt::Debug for dyn Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Signer")
+            .field("public_key", &self.public_key().to_string())
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Settings {
+    /// Keypair read from a file
+    File { path: PathBuf },
+    /// Asymmetric signing key in AWS KMS. Ed25519 keys sign with
+    /// ED25519_SHA_512 and ecc compact keys with ECDSA_SHA_256
+    Kms {
+        /// Id, arn or alias of the key
+        key_id: String,
+        /// B58 encoded public key of the key
+        public_key: String,
+        /// Region of the key. Inferred from the aws config when not set
+        region: Option<String>,
+    },
+    /// Signing service holding the key, see [`remote`]
+    Remote {
+        url: String,
+        /// B58 encoded public key of the key
+        public_key: String,
+        /// Bearer token sent with every request. Default none
+        token: Option<String>,
+        /// Timeout of a signing request in seconds. Default 5
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+    },
+}
+
+pub fn default_timeout() -> u64 {
+    5
+}
+
+fn parse_public_key(public_key: &str) -> Result<PublicKey> {
+    Ok(PublicKey::from_str(public_key)?)
+}
+
+/// Signer for the settings. Nothing is signed until the first request so
+/// this does not check that the key is reachable, see [`check`]
+pub fn from_settings(settings: &Settings) -> Result<Arc<dyn Signer>> {
+    Ok(match settings {
+        Settings::File { path } => Arc::new(FileSigner::load(path)?),
+        Settings::Kms {
+            key_id,
+            public_key,
+            region,
+        } => Arc::new(KmsSigner::new(
+            key_id.clone(),
+            parse_public_key(public_key)?,
+            region.clone(),
+        )?),
+        Settings::Remote {
+            url,
+            public_key,
+            token,
+            timeout,
+        } => Arc::new(RemoteSigner::new(
+            url.clone(),
+            parse_public_key(public_key)?,
+            token.clone(),
+            std::time::Duration::from_secs(*timeout),
+        )?),
+    })
+}
+
+/// Sign a probe and verify it against the public key, failing when the key
+/// can not be reached or does not match the public key
+pub async fn check(signer: &dyn Signer) -> Result {
+    const PROBE: &[u8] = b"oracle signer probe";
+    let signature = signer.sign(PROBE).await?;
+    signer
+        .public_key()
+        .verify(PROBE, &signature)
+        .map_err(|_| Error::InvalidSignature(format!("not signed by {}", signer.public_key())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_crypto::{KeyTag, KeyType, Keypair, Network};
+
+    fn keypair() -> Keypair {
+        Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::Ed25519,
+            },
+            &mut rand::rngs::OsRng,
+        )
+    }
+
+    fn settings(toml: &str) -> Settings {
+        config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn file_signatures_verify() {
+        let signer = FileSigner::new(keypair());
+        check(&signer).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn signatures_of_another_key_are_rejected() {
+        struct Impostor(FileSigner, PublicKey);
+
+        #[async_trait]
+        impl Signer for Impostor {
+            fn public_key(&self) -> &PublicKey {
+                &self.1
+            }
+
+            async fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+                self.0.sign(message).await
+            }
+        }
+
+        let impostor = Impostor(FileSigner::new(keypair()), keypair().public_key().clone());
+        assert!(matches!(
+            check(&impostor).await,
+            Err(Error::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn settings_select_the_signer() {
+        let public_key = keypair().public_key().to_string();
+        let Settings::Kms { key_id, region, .. } = settings(&format!(
+            "type = \"kms\"\nkey_id = \"alias/burn\"\npublic_key = \"{public_key}\""
+        )) else {
+            panic!("expected kms settings");
+        };
+        assert_eq!(key_id, "alias/burn");
+        assert_eq!(region, None);
+
+        let remote = settings(&format!(
+            "type = \"remote\"\nurl = \"http://signer\"\npublic_key = \"{public_key}\""
+        ));
+        assert!(matches!(remote, Settings::Remote { timeout: 5, .. }));
+        let signer = from_settings(&remote).unwrap();
+        assert_eq!(signer.public_key().to_string(), public_key);
+    }
+}
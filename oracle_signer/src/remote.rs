@@ -0,0 +1,73 @@
+//! Signer with the key held by a signing service.
+//!
+//! Every message is posted as json to the url of the service, with the bearer
+//! token when one is configured:
+//!
+//! ```json
+//! { "public_key": "<b58 public key>", "message": "<base64 message>" }
+//! ```
+//!
+//! and the service responds with `{ "signature": "<base64 signature>" }`.
+
+use crate::{Error, Result, Signer};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use helium_crypto::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub struct RemoteSigner {
+    client: reqwest::Client,
+    url: String,
+    public_key: PublicKey,
+    token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SignRequest {
+    public_key: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct SignResponse {
+    signature: String,
+}
+
+impl RemoteSigner {
+    pub fn new(
+        url: String,
+        public_key: PublicKey,
+        token: Option<String>,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(Self {
+            client,
+            url,
+            public_key,
+            token,
+        })
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let mut request = self.client.post(&self.url).json(&SignRequest {
+            public_key: self.public_key.to_string(),
+            message: STANDARD.encode(message),
+        });
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response: SignResponse = request.send().await?.error_for_status()?.json().await?;
+        STANDARD
+            .decode(response.signature)
+            .map_err(|err| Error::InvalidSignature(err.to_string()))
+    }
+}
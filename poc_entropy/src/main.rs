@@ -6,7 +6,6 @@ use futures_util::TryFutureExt;
 use poc_entropy::{entropy_generator::EntropyGenerator, server::ApiServer, Settings};
 use std::{net::SocketAddr, path};
 use tokio::{self, signal};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 const ENTROPY_SINK_ROLL_MINS: i64 = 2;
 
@@ -49,16 +48,15 @@ pub struct Server {}
 
 impl Server {
     pub async fn run(&self, settings: &Settings) -> Result<()> {
-        tracing_subscriber::registry()
-            .with(tracing_subscriber::EnvFilter::new(&settings.log))
-            .with(tracing_subscriber::fmt::layer())
-            .init();
+        custom_tracing::init(&settings.log, &settings.tracing)?;
+        poc_metrics::register_error_codes!(poc_metrics, file_store);
 
         // Install the prometheus metrics exporter
         poc_metrics::start_metrics(&settings.metrics)?;
 
         // configure shutdown trigger
         let (shutdown_trigger, shutdown) = triggered::trigger();
+        poc_metrics::process::start(shutdown.clone());
         let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
         tokio::spawn(async move {
             tokio::select! {
@@ -91,7 +89,7 @@ impl Server {
 
         // server
         let socket_addr: SocketAddr = settings.listen.parse()?;
-        let api_server = ApiServer::new(socket_addr, entropy_watch).await?;
+        let api_server = ApiServer::new(socket_addr, settings.tls.clone(), entropy_watch).await?;
 
         tracing::info!("api listening on {}", api_server.socket_addr);
 
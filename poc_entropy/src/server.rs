@@ -1,4 +1,5 @@
 use crate::entropy_generator::MessageReceiver;
+use grpc_service::tls::{self, TlsSettings};
 use helium_proto::{
     services::poc_entropy::{EntropyReqV1, PocEntropy, Server as GrpcServer},
     EntropyReportV1,
@@ -25,30 +26,44 @@ impl PocEntropy for EntropyServer {
 
 pub struct ApiServer {
     pub socket_addr: SocketAddr,
+    tls: Option<TlsSettings>,
     service: GrpcServer<EntropyServer>,
 }
 
 impl ApiServer {
     pub async fn new(
         socket_addr: SocketAddr,
+        tls: Option<TlsSettings>,
         entropy_watch: MessageReceiver,
     ) -> anyhow::Result<Self> {
         let service = GrpcServer::new(EntropyServer { entropy_watch });
 
         Ok(Self {
             socket_addr,
+            tls,
             service,
         })
     }
 
     pub async fn run(self, shutdown: &triggered::Listener) -> anyhow::Result<()> {
         tracing::info!(listen = self.socket_addr.to_string(), "starting");
-        transport::Server::builder()
+        let router = transport::Server::builder()
             .http2_keepalive_interval(Some(Duration::from_secs(250)))
             .http2_keepalive_timeout(Some(Duration::from_secs(60)))
-            .add_service(self.service)
-            .serve_with_shutdown(self.socket_addr, shutdown.clone())
-            .await?;
+            .add_service(self.service);
+        match &self.tls {
+            Some(tls) => {
+                let incoming = tls::incoming(tls, self.socket_addr, shutdown.clone()).await?;
+                router
+                    .serve_with_incoming_shutdown(incoming, shutdown.clone())
+                    .await?
+            }
+            None => {
+                router
+                    .serve_with_shutdown(self.socket_addr, shutdown.clone())
+                    .await?
+            }
+        }
         tracing::info!("stopping api server");
         Ok(())
     }
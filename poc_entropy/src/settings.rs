@@ -1,4 +1,5 @@
 use config::{Config, Environment, File};
+use grpc_service::tls::TlsSettings;
 use serde::Deserialize;
 use std::path::Path;
 
@@ -8,9 +9,14 @@ pub struct Settings {
     /// "poc_entropy=debug,poc_store=info"
     #[serde(default = "default_log")]
     pub log: String,
+    /// Log output format, per module log levels and runtime diagnostics
+    #[serde(default)]
+    pub tracing: custom_tracing::Settings,
     /// Listen address for http requests for entropy. Default "0.0.0.0:8080"
     #[serde(default = "default_listen_addr")]
     pub listen: String,
+    /// Serve grpc over TLS when set. Plaintext by default
+    pub tls: Option<TlsSettings>,
     /// Source URL for entropy data. Required
     pub source: String,
     /// Target output bucket details
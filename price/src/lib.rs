@@ -1,9 +1,11 @@
 pub mod cli;
 pub mod metrics;
 pub mod price_generator;
+pub mod price_service;
 pub mod price_tracker;
 pub mod settings;
 
 pub use price_generator::PriceGenerator;
-pub use price_tracker::PriceTracker;
+pub use price_service::PriceService;
+pub use price_tracker::{register_error_codes, PriceTracker};
 pub use settings::Settings;
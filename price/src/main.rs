@@ -3,11 +3,11 @@ use chrono::Duration;
 use clap::Parser;
 use file_store::{file_sink, file_upload, FileType};
 use futures_util::TryFutureExt;
+use grpc_service::tls;
 use helium_proto::BlockchainTokenTypeV1;
-use price::{cli::check, PriceGenerator, Settings};
+use price::{cli::check, PriceGenerator, PriceService, Settings};
 use std::path::{self, PathBuf};
 use tokio::{self, signal};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 const PRICE_SINK_ROLL_MINS: i64 = 3;
 
@@ -72,16 +72,15 @@ pub struct Server {}
 
 impl Server {
     pub async fn run(&self, settings: &Settings) -> Result<()> {
-        tracing_subscriber::registry()
-            .with(tracing_subscriber::EnvFilter::new(&settings.log))
-            .with(tracing_subscriber::fmt::layer())
-            .init();
+        custom_tracing::init(&settings.log, &settings.tracing)?;
+        poc_metrics::register_error_codes!(poc_metrics, file_store, price);
 
         // Install the prometheus metrics exporter
         poc_metrics::start_metrics(&settings.metrics)?;
 
         // configure shutdown trigger
         let (shutdown_trigger, shutdown) = triggered::trigger();
+        poc_metrics::process::start(shutdown.clone());
 
         let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
         tokio::spawn(async move {
@@ -108,6 +107,37 @@ impl Server {
         let mut hst_price_generator =
             PriceGenerator::new(settings, BlockchainTokenTypeV1::Hst).await?;
 
+        let price_service = PriceService::new(settings.stale_price_duration())
+            .add(BlockchainTokenTypeV1::Hnt, hnt_price_generator.latest())
+            .add(
+                BlockchainTokenTypeV1::Mobile,
+                mobile_price_generator.latest(),
+            )
+            .add(BlockchainTokenTypeV1::Iot, iot_price_generator.latest())
+            .add(BlockchainTokenTypeV1::Hst, hst_price_generator.latest());
+        let price_server = {
+            let listen_addr = settings.listen_addr()?;
+            let tls = settings.tls.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                let Some(listen_addr) = listen_addr else {
+                    return Ok(());
+                };
+                tracing::info!("serving latest prices on {listen_addr}");
+                let router = tonic::transport::Server::builder().add_service(price_service);
+                match tls {
+                    Some(tls) => {
+                        let incoming = tls::incoming(&tls, listen_addr, shutdown.clone()).await?;
+                        router
+                            .serve_with_incoming_shutdown(incoming, shutdown)
+                            .await?
+                    }
+                    None => router.serve_with_shutdown(listen_addr, shutdown).await?,
+                }
+                Ok::<(), Error>(())
+            }
+        };
+
         let (price_sink, mut price_sink_server) = file_sink::FileSinkBuilder::new(
             FileType::PriceReport,
             store_base_path,
@@ -133,6 +163,7 @@ impl Server {
                 .run(price_sink, &shutdown)
                 .map_err(Error::from),
             price_sink_server.run().map_err(Error::from),
+            price_server,
             file_upload.run(&shutdown).map_err(Error::from),
         )
         .map(|_| ())
@@ -5,12 +5,12 @@ use chrono::{DateTime, Duration, TimeZone, Utc};
 use file_store::file_sink;
 use futures::TryFutureExt;
 use helium_proto::{BlockchainTokenTypeV1, PriceReportV1};
-use price_oracle::{calculate_current_price, PriceOracleV0};
+use price_oracle::{calculate_current_price, OracleV0, PriceOracleV0};
 use serde::{Deserialize, Serialize};
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::pubkey::Pubkey as SolPubkey;
 use std::{path::PathBuf, str::FromStr};
-use tokio::{fs, time};
+use tokio::{fs, sync::watch, time};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Price {
@@ -38,6 +38,66 @@ pub struct PriceGenerator {
     default_price: Option<u64>,
     stale_price_duration: Duration,
     latest_price_file: PathBuf,
+    validation: PriceValidation,
+    latest_sender: watch::Sender<Option<Price>>,
+}
+
+/// Checks applied to the oracle submissions a newly fetched price is
+/// calculated from
+#[derive(Debug, Clone, Copy)]
+pub struct PriceValidation {
+    /// Submissions older than this are not considered fresh
+    pub max_submission_age: Duration,
+    /// Fresh submissions required for a price to be accepted
+    pub min_submissions: usize,
+    /// Maximum deviation of any fresh submission from the price in basis
+    /// points. Not checked when not set
+    pub max_deviation_bps: Option<u64>,
+}
+
+impl PriceValidation {
+    pub fn validate(&self, oracles: &[OracleV0], price: u64, now: DateTime<Utc>) -> Result<()> {
+        let oldest = (now - self.max_submission_age).timestamp();
+        let submissions: Vec<u64> = oracles
+            .iter()
+            .filter_map(|oracle| {
+                oracle
+                    .last_submitted_timestamp
+                    .zip(oracle.last_submitted_price)
+            })
+            .filter(|(timestamp, _)| *timestamp >= oldest)
+            .map(|(_, price)| price)
+            .collect();
+        self.validate_submissions(&submissions, price)
+    }
+
+    fn validate_submissions(&self, submissions: &[u64], price: u64) -> Result<()> {
+        if price == 0 {
+            anyhow::bail!("price is zero");
+        }
+        if submissions.len() < self.min_submissions {
+            anyhow::bail!(
+                "stale price, {} fresh submissions, {} required",
+                submissions.len(),
+                self.min_submissions
+            );
+        }
+        if let Some(max_deviation_bps) = self.max_deviation_bps {
+            let deviation_bps = submissions
+                .iter()
+                .map(|submission| {
+                    (submission.abs_diff(price) as u128 * 10_000 / price as u128) as u64
+                })
+                .max()
+                .unwrap_or_default();
+            if deviation_bps > max_deviation_bps {
+                anyhow::bail!(
+                    "low confidence price, submissions deviate {deviation_bps} bps, max {max_deviation_bps}"
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 impl From<Price> for PriceReportV1 {
@@ -70,6 +130,7 @@ impl TryFrom<PriceReportV1> for Price {
 impl PriceGenerator {
     pub async fn new(settings: &Settings, token_type: BlockchainTokenTypeV1) -> Result<Self> {
         let client = RpcClient::new(settings.source.clone());
+        let (latest_sender, _) = watch::channel(None);
         Ok(Self {
             last_price_opt: None,
             token_type,
@@ -80,9 +141,21 @@ impl PriceGenerator {
             stale_price_duration: settings.stale_price_duration(),
             latest_price_file: PathBuf::from_str(&settings.cache)?
                 .join(format!("{token_type:?}.latest")),
+            validation: settings.price_validation(),
+            latest_sender,
         })
     }
 
+    /// Receiver of the latest price reported by this generator
+    pub fn latest(&self) -> watch::Receiver<Option<Price>> {
+        self.latest_sender.subscribe()
+    }
+
+    fn report(&self, price: &Price) -> PriceReportV1 {
+        self.latest_sender.send_replace(Some(price.clone()));
+        PriceReportV1::from(price.clone())
+    }
+
     pub async fn run(
         &mut self,
         file_sink: file_sink::FileSinkClient,
@@ -121,7 +194,7 @@ impl PriceGenerator {
                 _ = shutdown.clone() => break,
                 _ = trigger.tick() => {
                     let price = Price::new(Utc::now(), default_price, self.token_type);
-                    let price_report = PriceReportV1::from(price);
+                    let price_report = self.report(&price);
                     tracing::info!("updating {:?} with default price: {}", self.token_type, default_price);
                     file_sink.write(price_report, []).await?;
                 }
@@ -158,7 +231,8 @@ impl PriceGenerator {
         key: &SolPubkey,
         file_sink: &file_sink::FileSinkClient,
     ) -> Result<()> {
-        let price_opt = match get_price(&self.client, key, self.token_type).await {
+        let price_opt = match get_price(&self.client, key, self.token_type, &self.validation).await
+        {
             Ok(new_price) => {
                 tracing::info!(
                     "updating price for {:?} to {}",
@@ -210,7 +284,7 @@ impl PriceGenerator {
         };
 
         if let Some(price) = price_opt {
-            let price_report = PriceReportV1::from(price);
+            let price_report = self.report(&price);
             tracing::debug!("price_report: {:?}", price_report);
             file_sink.write(price_report, []).await?;
         }
@@ -257,6 +331,7 @@ pub async fn get_price(
     client: &RpcClient,
     price_key: &SolPubkey,
     token_type: BlockchainTokenTypeV1,
+    validation: &PriceValidation,
 ) -> Result<Price> {
     let price_oracle_v0_data = client.get_account_data(price_key).await?;
     let mut price_oracle_v0_data = price_oracle_v0_data.as_ref();
@@ -265,10 +340,45 @@ pub async fn get_price(
     let current_time = Utc::now();
     let current_timestamp = current_time.timestamp();
 
-    calculate_current_price(&price_oracle_v0.oracles, current_timestamp)
-        .map(|price| {
-            tracing::debug!("got price: {:?} for token_type: {:?}", price, token_type);
-            Price::new(current_time, price, token_type)
-        })
-        .ok_or_else(|| anyhow!("unable to fetch price!"))
+    let price = calculate_current_price(&price_oracle_v0.oracles, current_timestamp)
+        .ok_or_else(|| anyhow!("unable to fetch price!"))?;
+    tracing::debug!("got price: {:?} for token_type: {:?}", price, token_type);
+    validation.validate(&price_oracle_v0.oracles, price, current_time)?;
+    Ok(Price::new(current_time, price, token_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validation(max_deviation_bps: Option<u64>) -> PriceValidation {
+        PriceValidation {
+            max_submission_age: Duration::hours(1),
+            min_submissions: 2,
+            max_deviation_bps,
+        }
+    }
+
+    #[test]
+    fn rejects_stale_prices() {
+        assert!(validation(None).validate_submissions(&[100], 100).is_err());
+        assert!(validation(None)
+            .validate_submissions(&[100, 100], 100)
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_low_confidence_prices() {
+        let submissions = [95, 100, 110];
+        assert!(validation(None)
+            .validate_submissions(&submissions, 100)
+            .is_ok());
+        assert!(validation(Some(1_000))
+            .validate_submissions(&submissions, 100)
+            .is_ok());
+        assert!(validation(Some(999))
+            .validate_submissions(&submissions, 100)
+            .is_err());
+        assert!(validation(None).validate_submissions(&[0, 0], 0).is_err());
+    }
 }
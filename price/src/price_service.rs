@@ -0,0 +1,97 @@
+//! Minimal grpc service serving the latest price of each token to other
+//! oracles. The service is hand written on top of the tonic server
+//! primitives, with the request defined here and the response being
+//! the same `PriceReportV1` written to the price report files.
+
+use crate::price_generator::Price;
+use chrono::{Duration, Utc};
+use helium_proto::{BlockchainTokenTypeV1, PriceReportV1};
+use std::{collections::HashMap, convert::Infallible};
+use tokio::sync::watch;
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const SERVICE_NAME: &str = "helium.price_oracle.price";
+const GET_PRICE_PATH: &str = "/helium.price_oracle.price/get_price";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PriceReqV1 {
+    #[prost(enumeration = "BlockchainTokenTypeV1", tag = "1")]
+    pub token_type: i32,
+}
+
+#[derive(Clone)]
+pub struct PriceService {
+    prices: HashMap<BlockchainTokenTypeV1, watch::Receiver<Option<Price>>>,
+    stale_price_duration: Duration,
+}
+
+impl PriceService {
+    pub fn new(stale_price_duration: Duration) -> Self {
+        Self {
+            prices: HashMap::new(),
+            stale_price_duration,
+        }
+    }
+
+    pub fn add(
+        mut self,
+        token_type: BlockchainTokenTypeV1,
+        latest: watch::Receiver<Option<Price>>,
+    ) -> Self {
+        self.prices.insert(token_type, latest);
+        self
+    }
+
+    pub fn get_price(&self, request: PriceReqV1) -> Result<PriceReportV1, Status> {
+        let token_type = BlockchainTokenTypeV1::from_i32(request.token_type).ok_or_else(|| {
+            Status::invalid_argument(format!("unsupported token type: {}", request.token_type))
+        })?;
+        let report = self
+            .prices
+            .get(&token_type)
+            .and_then(|latest| latest.borrow().clone())
+            .map(PriceReportV1::from)
+            .ok_or_else(|| Status::unavailable(format!("no price for {token_type:?}")))?;
+
+        let oldest = Utc::now() - self.stale_price_duration;
+        if (report.timestamp as i64) < oldest.timestamp() {
+            return Err(Status::unavailable(format!(
+                "price for {token_type:?} is stale"
+            )));
+        }
+        Ok(report)
+    }
+}
+
+impl NamedService for PriceService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for PriceService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            GET_PRICE_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.get_price(request) })
+            }
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
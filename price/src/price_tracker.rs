@@ -2,6 +2,7 @@ use chrono::{DateTime, Duration, TimeZone, Utc};
 use file_store::{FileInfo, FileStore, FileType};
 use futures::stream::{StreamExt, TryStreamExt};
 use helium_proto::{BlockchainTokenTypeV1, Message, PriceReportV1};
+use poc_metrics::error_code::{self, ErrorCategory, ErrorCode};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use tokio::sync::{mpsc, watch};
@@ -26,6 +27,37 @@ pub enum PriceTrackerError {
     SendError(#[from] mpsc::error::SendError<String>),
 }
 
+impl ErrorCode for PriceTrackerError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::InvalidTimestamp(_) | Self::DecodeError(_) => ErrorCategory::Decode,
+            Self::PriceNotAvailable | Self::PriceTooOld(_) => ErrorCategory::Storage,
+            Self::FileStoreError(err) => err.category(),
+            Self::JoinError(_) | Self::KilledError(_) | Self::SendError(_) => {
+                ErrorCategory::Internal
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidTimestamp(_) => "invalid_price_timestamp",
+            Self::PriceNotAvailable => "price_not_available",
+            Self::PriceTooOld(_) => "price_too_old",
+            Self::JoinError(_) => "join",
+            Self::FileStoreError(err) => err.code(),
+            Self::DecodeError(_) => "prost_decode",
+            Self::KilledError(_) => "price_tracker_killed",
+            Self::SendError(_) => "channel_closed",
+        }
+    }
+}
+
+/// Label the errors of the price tracker in metrics and logs
+pub fn register_error_codes() {
+    error_code::register::<PriceTrackerError>();
+}
+
 #[derive(Clone)]
 struct Price {
     price: u64,
@@ -1,10 +1,16 @@
+use crate::price_generator::PriceValidation;
 use anyhow::{anyhow, Result};
 use chrono::Duration;
 use config::{Config, Environment, File};
+use grpc_service::tls::TlsSettings;
 use helium_proto::BlockchainTokenTypeV1;
 use serde::Deserialize;
 use solana_sdk::pubkey::Pubkey as SolPubkey;
-use std::{path::Path, str::FromStr};
+use std::{
+    net::{AddrParseError, SocketAddr},
+    path::Path,
+    str::FromStr,
+};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ClusterConfig {
@@ -41,6 +47,9 @@ pub struct Settings {
     /// "price=debug"
     #[serde(default = "default_log")]
     pub log: String,
+    /// Log output format, per module log levels and runtime diagnostics
+    #[serde(default)]
+    pub tracing: custom_tracing::Settings,
     /// Source URL for price data. Required
     #[serde(default = "default_source")]
     pub source: String,
@@ -60,6 +69,21 @@ pub struct Settings {
     /// How long to use a stale price in minutes
     #[serde(default = "default_stale_price_minutes")]
     pub stale_price_minutes: u64,
+    /// Oracle submissions older than this many seconds are not considered
+    /// when validating a new price. Default 1 hour
+    #[serde(default = "default_max_submission_age_secs")]
+    pub max_submission_age_secs: i64,
+    /// Fresh oracle submissions required to accept a new price. Default 1
+    #[serde(default = "default_min_submissions")]
+    pub min_submissions: usize,
+    /// Maximum deviation of any fresh oracle submission from a new price in
+    /// basis points before the price is rejected. Not checked by default
+    pub max_deviation_bps: Option<u64>,
+    /// Listen address of the grpc endpoint serving the latest prices.
+    /// Disabled when not set
+    pub listen: Option<String>,
+    /// Serve the grpc endpoint over TLS when set. Plaintext by default
+    pub tls: Option<TlsSettings>,
 }
 
 pub fn default_source() -> String {
@@ -78,6 +102,14 @@ pub fn default_stale_price_minutes() -> u64 {
     12 * 60
 }
 
+pub fn default_max_submission_age_secs() -> i64 {
+    60 * 60
+}
+
+pub fn default_min_submissions() -> usize {
+    1
+}
+
 pub fn default_cluster() -> ClusterConfig {
     ClusterConfig::default()
 }
@@ -117,6 +149,18 @@ impl Settings {
         Duration::minutes(self.stale_price_minutes as i64)
     }
 
+    pub fn price_validation(&self) -> PriceValidation {
+        PriceValidation {
+            max_submission_age: Duration::seconds(self.max_submission_age_secs),
+            min_submissions: self.min_submissions,
+            max_deviation_bps: self.max_deviation_bps,
+        }
+    }
+
+    pub fn listen_addr(&self) -> Result<Option<SocketAddr>, AddrParseError> {
+        self.listen.as_deref().map(SocketAddr::from_str).transpose()
+    }
+
     pub fn price_key(&self, token_type: BlockchainTokenTypeV1) -> Result<Option<SolPubkey>> {
         self.key(token_type)
             .as_ref()
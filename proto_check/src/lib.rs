@@ -0,0 +1,272 @@
+//! Compatibility check of stored messages against the current protobuf
+//! schema.
+//!
+//! The fixtures are samples of store files as written by the producers. A
+//! message of a fixture is decoded with the prost type of its file type and
+//! encoded again. Prost drops the fields a type does not know about, so a
+//! field that was removed from the schema or moved to another number makes
+//! the encoded message differ from the stored one, while a field that
+//! changed its type makes the message fail to decode. Both are reported as
+//! incompatibilities of the fixture.
+//!
+//! Prost encodes the fields of a message in the order of their numbers and
+//! leaves out default values, so fixtures have to be taken from files
+//! written by prost producers for a compatible message to encode to the
+//! same bytes.
+
+use file_store::{
+    file_source, mobile_transfer::InvalidDataTransferSessionV1,
+    reward_manifest::CorrectedRewardManifest, FileInfo, FileType,
+};
+use futures::TryStreamExt;
+use helium_proto::{
+    services::{
+        packet_verifier::{InvalidPacket, ValidDataTransferSession, ValidPacket},
+        poc_lora::{
+            IotRewardShare, LoraBeaconIngestReportV1, LoraInvalidBeaconReportV1,
+            LoraInvalidWitnessReportV1, LoraPocV1, LoraWitnessIngestReportV1, NonRewardablePacket,
+        },
+        poc_mobile::{
+            CellHeartbeatIngestReportV1, CellHeartbeatReqV1, CoverageObjectIngestReportV1,
+            DataTransferSessionIngestReportV1, Heartbeat, InvalidDataTransferIngestReportV1,
+            MobileRewardShare, RadioRewardShare, SpeedtestAvg, SpeedtestIngestReportV1,
+            SpeedtestReqV1, SubscriberLocationIngestReportV1, SubscriberLocationReqV1,
+            VerifiedSubscriberLocationIngestReportV1,
+        },
+        router::PacketRouterPacketReportV1,
+    },
+    BlockchainTxn, EntropyReportV1, Message, PriceReportV1, SubnetworkRewards,
+};
+use prost::DecodeError;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("file error: {0}")]
+    File(#[from] file_store::Error),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T = ()> = std::result::Result<T, Error>;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum Incompatibility {
+    #[error("message {index} does not decode: {reason}")]
+    Decode { index: usize, reason: String },
+    #[error(
+        "message {index} of {stored} bytes encodes to {encoded}, fields were removed or renumbered"
+    )]
+    Changed {
+        index: usize,
+        stored: usize,
+        encoded: usize,
+    },
+}
+
+fn reencode<M: Message + Default>(buf: &[u8]) -> std::result::Result<Vec<u8>, DecodeError> {
+    M::decode(buf).map(|msg| msg.encode_to_vec())
+}
+
+/// Decode and encode a message with the prost type of the file type, or
+/// `None` when no type of the file type is known
+pub fn roundtrip(
+    file_type: FileType,
+    buf: &[u8],
+) -> Option<std::result::Result<Vec<u8>, DecodeError>> {
+    let encoded = match file_type {
+        FileType::CellHeartbeat => reencode::<CellHeartbeatReqV1>(buf),
+        FileType::CellSpeedtest => reencode::<SpeedtestReqV1>(buf),
+        FileType::CellHeartbeatIngestReport => reencode::<CellHeartbeatIngestReportV1>(buf),
+        FileType::CellSpeedtestIngestReport => reencode::<SpeedtestIngestReportV1>(buf),
+        FileType::EntropyReport => reencode::<EntropyReportV1>(buf),
+        FileType::SubnetworkRewards => reencode::<SubnetworkRewards>(buf),
+        FileType::IotBeaconIngestReport => reencode::<LoraBeaconIngestReportV1>(buf),
+        FileType::IotWitnessIngestReport => reencode::<LoraWitnessIngestReportV1>(buf),
+        FileType::IotPoc => reencode::<LoraPocV1>(buf),
+        FileType::IotInvalidBeaconReport => reencode::<LoraInvalidBeaconReportV1>(buf),
+        FileType::IotInvalidWitnessReport => reencode::<LoraInvalidWitnessReportV1>(buf),
+        FileType::SpeedtestAvg => reencode::<SpeedtestAvg>(buf),
+        FileType::ValidatedHeartbeat => reencode::<Heartbeat>(buf),
+        FileType::SignedPocReceiptTxn => reencode::<BlockchainTxn>(buf),
+        FileType::RadioRewardShare => reencode::<RadioRewardShare>(buf),
+        // Manifests may itemize the reward corrections of the epoch
+        FileType::RewardManifest => reencode::<CorrectedRewardManifest>(buf),
+        FileType::IotPacketReport => reencode::<PacketRouterPacketReportV1>(buf),
+        FileType::IotValidPacket => reencode::<ValidPacket>(buf),
+        FileType::InvalidPacket => reencode::<InvalidPacket>(buf),
+        FileType::NonRewardablePacket => reencode::<NonRewardablePacket>(buf),
+        FileType::IotRewardShare => reencode::<IotRewardShare>(buf),
+        FileType::DataTransferSessionIngestReport => {
+            reencode::<DataTransferSessionIngestReportV1>(buf)
+        }
+        FileType::InvalidDataTransferSessionIngestReport => {
+            reencode::<InvalidDataTransferIngestReportV1>(buf)
+        }
+        FileType::ValidDataTransferSession | FileType::VerifiedDataTransferSession => {
+            reencode::<ValidDataTransferSession>(buf)
+        }
+        FileType::InvalidVerifiedDataTransferSession => {
+            reencode::<InvalidDataTransferSessionV1>(buf)
+        }
+        FileType::PriceReport => reencode::<PriceReportV1>(buf),
+        FileType::MobileRewardShare => reencode::<MobileRewardShare>(buf),
+        FileType::SubscriberLocationReq => reencode::<SubscriberLocationReqV1>(buf),
+        FileType::SubscriberLocationIngestReport => {
+            reencode::<SubscriberLocationIngestReportV1>(buf)
+        }
+        FileType::VerifiedSubscriberLocationIngestReport => {
+            reencode::<VerifiedSubscriberLocationIngestReportV1>(buf)
+        }
+        FileType::CoverageObjectIngestReport => reencode::<CoverageObjectIngestReportV1>(buf),
+        // Raw entropy and mapper messages are not protobuf, the iot config
+        // audit, denylist and route snapshot messages are defined by
+        // iot_config, balance top ups and org state changes by
+        // iot_packet_verifier, receipts by ingest and invalid radio reward
+        // shares, epoch summaries and reward diffs by mobile_verifier
+        FileType::Entropy
+        | FileType::MapperMsg
+        | FileType::IotConfigAudit
+        | FileType::IotConfigDenylist
+        | FileType::IotConfigRouteSnapshot
+        | FileType::IotBalanceTopUp
+        | FileType::IotOrgStateChange
+        | FileType::IngestReceipt
+        | FileType::InvalidRadioRewardShare
+        | FileType::MobileEpochSummary
+        | FileType::MobileRewardDiff => return None,
+    };
+    Some(encoded)
+}
+
+/// Check a single stored message, `Ok(false)` when its file type has no
+/// known type
+pub fn check_message(
+    file_type: FileType,
+    index: usize,
+    buf: &[u8],
+) -> std::result::Result<bool, Incompatibility> {
+    match roundtrip(file_type, buf) {
+        None => Ok(false),
+        Some(Err(err)) => Err(Incompatibility::Decode {
+            index,
+            reason: err.to_string(),
+        }),
+        Some(Ok(encoded)) if encoded != buf => Err(Incompatibility::Changed {
+            index,
+            stored: buf.len(),
+            encoded: encoded.len(),
+        }),
+        Some(Ok(_)) => Ok(true),
+    }
+}
+
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub file_type: FileType,
+    pub messages: usize,
+    /// Whether there is no known type to check the messages with
+    pub skipped: bool,
+    pub incompatibilities: Vec<Incompatibility>,
+}
+
+impl FileReport {
+    pub fn is_compatible(&self) -> bool {
+        self.incompatibilities.is_empty()
+    }
+}
+
+/// Check every message of a store file, named like the files of a bucket
+pub async fn check_file(path: &Path) -> Result<FileReport> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let file_type = FileInfo::from_str(&name)?.file_type;
+    let mut report = FileReport {
+        path: path.to_path_buf(),
+        file_type,
+        messages: 0,
+        skipped: false,
+        incompatibilities: vec![],
+    };
+    let mut messages = file_source::source([path]);
+    while let Some(msg) = messages.try_next().await? {
+        match check_message(file_type, report.messages, &msg) {
+            Ok(checked) => report.skipped = !checked,
+            Err(incompatibility) => report.incompatibilities.push(incompatibility),
+        }
+        report.messages += 1;
+    }
+    Ok(report)
+}
+
+/// Check the store files, ending in `.gz`, of a directory in the order of
+/// their names
+pub async fn check_dir(dir: &Path) -> Result<Vec<FileReport>> {
+    let mut paths = vec![];
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().map_or(false, |ext| ext == "gz") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut reports = Vec::with_capacity(paths.len());
+    for path in paths {
+        reports.push(check_file(&path).await?);
+    }
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entropy_report() -> Vec<u8> {
+        EntropyReportV1 {
+            data: vec![1, 2, 3],
+            timestamp: 1_686_000_000,
+            version: 1,
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn current_messages_are_compatible() {
+        assert_eq!(
+            check_message(FileType::EntropyReport, 0, &entropy_report()),
+            Ok(true)
+        );
+        assert_eq!(check_message(FileType::Entropy, 0, &[1, 2, 3]), Ok(false));
+    }
+
+    #[test]
+    fn unknown_fields_are_incompatible() {
+        // A varint of field 15, which EntropyReportV1 does not have, as left
+        // by a producer that still has a field the consumer removed
+        let mut stored = entropy_report();
+        stored.extend([15 << 3, 42]);
+        assert_eq!(
+            check_message(FileType::EntropyReport, 3, &stored),
+            Err(Incompatibility::Changed {
+                index: 3,
+                stored: stored.len(),
+                encoded: stored.len() - 2,
+            })
+        );
+    }
+
+    #[test]
+    fn undecodable_messages_are_incompatible() {
+        // Field 1, the data bytes, stored as a varint
+        let result = check_message(FileType::EntropyReport, 0, &[1 << 3, 1]);
+        assert!(matches!(result, Err(Incompatibility::Decode { .. })));
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{bail, Result};
+use async_compression::tokio::write::GzipEncoder;
+use clap::Parser;
+use file_store::{file_sink::MAX_FRAME_LENGTH, file_source, FileType};
+use futures::{SinkExt, StreamExt, TryStreamExt};
+use proto_check::{check_dir, FileReport};
+use std::{collections::BTreeSet, path::PathBuf};
+use strum::EnumCount;
+use tokio::{fs::File, io::BufWriter};
+use tokio_util::codec::LengthDelimitedCodec;
+
+#[derive(Debug, clap::Parser)]
+#[clap(version = env!("CARGO_PKG_VERSION"))]
+#[clap(about = "Check stored messages against the current protobuf schema")]
+pub struct Cli {
+    #[clap(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Cmd {
+    Check(Check),
+    Sample(Sample),
+}
+
+/// Check the fixtures of a directory, failing on any incompatible message
+#[derive(Debug, clap::Args)]
+pub struct Check {
+    /// Directory of the fixtures
+    #[clap(default_value = "fixtures")]
+    dir: PathBuf,
+}
+
+impl Check {
+    async fn run(&self) -> Result<()> {
+        let reports = check_dir(&self.dir).await?;
+        let mut checked = BTreeSet::new();
+        for FileReport {
+            path,
+            file_type,
+            messages,
+            skipped,
+            incompatibilities,
+        } in &reports
+        {
+            if *skipped {
+                println!("{}: skipped, no type for {file_type}", path.display());
+                continue;
+            }
+            checked.insert(file_type.to_str());
+            println!(
+                "{}: {messages} messages, {} incompatible",
+                path.display(),
+                incompatibilities.len()
+            );
+            for incompatibility in incompatibilities {
+                println!("  {incompatibility}");
+            }
+        }
+        println!(
+            "{} of {} file types have fixtures",
+            checked.len(),
+            FileType::COUNT
+        );
+
+        let incompatible = reports.iter().filter(|r| !r.is_compatible()).count();
+        if incompatible > 0 {
+            bail!("{incompatible} fixtures are incompatible with the current schema");
+        }
+        Ok(())
+    }
+}
+
+/// Copy the first messages of a store file into a fixture of the same name
+#[derive(Debug, clap::Args)]
+pub struct Sample {
+    /// Store file to take the messages from
+    in_path: PathBuf,
+    /// Directory to write the fixture to
+    #[clap(default_value = "fixtures")]
+    out_dir: PathBuf,
+    /// Number of messages to copy
+    #[clap(long, default_value_t = 10)]
+    count: usize,
+}
+
+impl Sample {
+    async fn run(&self) -> Result<()> {
+        let Some(name) = self.in_path.file_name() else {
+            bail!("{} is not a file", self.in_path.display());
+        };
+        let out_path = self.out_dir.join(name);
+        let file = BufWriter::new(File::create(&out_path).await?);
+        let mut transport = LengthDelimitedCodec::builder()
+            .max_frame_length(MAX_FRAME_LENGTH)
+            .new_write(GzipEncoder::new(file));
+
+        let mut messages = file_source::source([&self.in_path]).take(self.count);
+        let mut count = 0;
+        while let Some(msg) = messages.try_next().await? {
+            transport.send(msg.freeze()).await?;
+            count += 1;
+        }
+        transport.close().await?;
+        println!("wrote {count} messages to {}", out_path.display());
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    match cli.cmd {
+        Cmd::Check(cmd) => cmd.run().await,
+        Cmd::Sample(cmd) => cmd.run().await,
+    }
+}
@@ -0,0 +1,29 @@
+use proto_check::check_dir;
+use std::path::Path;
+
+#[tokio::test]
+async fn fixtures_are_compatible_with_the_current_schema() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+    let reports = check_dir(&dir).await.expect("unable to check fixtures");
+    let checked = reports
+        .iter()
+        .filter(|report| !report.skipped && report.messages > 0)
+        .count();
+    assert!(checked > 0, "no fixtures checked in {}", dir.display());
+
+    let incompatible: Vec<String> = reports
+        .iter()
+        .filter(|report| !report.is_compatible())
+        .flat_map(|report| {
+            report
+                .incompatibilities
+                .iter()
+                .map(move |incompatibility| format!("{}: {incompatibility}", report.path.display()))
+        })
+        .collect();
+    assert!(
+        incompatible.is_empty(),
+        "fixtures incompatible with the current schema:\n{}",
+        incompatible.join("\n")
+    );
+}
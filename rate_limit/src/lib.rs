@@ -0,0 +1,277 @@
+//! Token bucket rate limits per caller.
+//!
+//! Limits are configured by name, e.g. a grpc endpoint or a report type, with
+//! an optional default for names without a limit of their own. Every caller
+//! gets its own bucket per name, which starts full and refills at the
+//! sustained rate of the limit up to its burst. Callers over their limit are
+//! told how long to wait until a token is available again.
+
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tonic::{metadata::MetadataValue, Status};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid rate limit for {name}: {reason}")]
+    InvalidLimit { name: String, reason: &'static str },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RateLimit {
+    /// Sustained number of requests allowed per second
+    pub per_second: f64,
+    /// Number of requests allowed in a burst above the sustained rate
+    pub burst: u32,
+}
+
+impl RateLimit {
+    fn validate(&self, name: &str) -> Result<(), Error> {
+        let reason = if !(self.per_second.is_finite() && self.per_second > 0.0) {
+            "per_second must be a positive number"
+        } else if self.burst == 0 {
+            "burst must be at least 1"
+        } else {
+            return Ok(());
+        };
+        Err(Error::InvalidLimit {
+            name: name.to_string(),
+            reason,
+        })
+    }
+
+    fn time_to_full(&self) -> Duration {
+        Duration::from_secs_f64(self.burst as f64 / self.per_second)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated_at: now,
+        }
+    }
+
+    /// Take a token from the bucket, returning how long until the next token
+    /// is available if the bucket is empty
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / limit.per_second,
+            ))
+        }
+    }
+}
+
+/// Token bucket rate limits per name and caller key
+pub struct RateLimiter<K> {
+    default: Option<RateLimit>,
+    limits: HashMap<String, RateLimit>,
+    prune_threshold: usize,
+    buckets: Mutex<HashMap<(String, K), TokenBucket>>,
+}
+
+impl<K> RateLimiter<K>
+where
+    K: Hash + Eq,
+{
+    /// Create a limiter applying `limits` by name and `default` to any other
+    /// name. Idle buckets are pruned once more than `prune_threshold` are
+    /// tracked. Fails on limits that never allow a request.
+    pub fn new(
+        default: Option<RateLimit>,
+        limits: HashMap<String, RateLimit>,
+        prune_threshold: usize,
+    ) -> Result<Self, Error> {
+        if let Some(default) = &default {
+            default.validate("default")?;
+        }
+        for (name, limit) in &limits {
+            limit.validate(name)?;
+        }
+        Ok(Self {
+            default,
+            limits,
+            prune_threshold,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn limit_for(&self, name: &str) -> Option<RateLimit> {
+        self.limits.get(name).copied().or(self.default)
+    }
+
+    /// Count a request of `caller` against the limit of `name`, returning
+    /// how long until the next request is allowed when over the limit
+    pub fn check(&self, name: &str, caller: K) -> Result<(), Duration> {
+        self.check_at(name, caller, Instant::now())
+    }
+
+    pub fn check_at(&self, name: &str, caller: K, now: Instant) -> Result<(), Duration> {
+        let limit = match self.limit_for(name) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let mut buckets = self.buckets.lock().expect("rate limit buckets poisoned");
+        if buckets.len() > self.prune_threshold {
+            self.prune(&mut buckets, now);
+        }
+        buckets
+            .entry((name.to_string(), caller))
+            .or_insert_with(|| TokenBucket::new(&limit, now))
+            .take(&limit, now)
+    }
+
+    // Idle buckets that would have refilled completely carry no state worth
+    // keeping, a new bucket starts full
+    fn prune(&self, buckets: &mut HashMap<(String, K), TokenBucket>, now: Instant) {
+        buckets.retain(|(name, _), bucket| {
+            self.limit_for(name).is_some_and(|limit| {
+                now.saturating_duration_since(bucket.updated_at) < limit.time_to_full()
+            })
+        });
+    }
+}
+
+/// RESOURCE_EXHAUSTED status with a retry-after (seconds) metadata entry
+pub fn throttled_status(retry_after: Duration) -> Status {
+    // Round up so clients never retry before a token is available
+    let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let mut status = Status::resource_exhausted("rate limit exceeded");
+    status
+        .metadata_mut()
+        .insert("retry-after", MetadataValue::from(retry_after_secs));
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GET: &str = "get";
+
+    fn limit(per_second: f64, burst: u32) -> RateLimit {
+        RateLimit { per_second, burst }
+    }
+
+    fn limiter(default: Option<RateLimit>, limits: &[(&str, RateLimit)]) -> RateLimiter<u8> {
+        RateLimiter::new(
+            default,
+            limits
+                .iter()
+                .map(|(name, limit)| (name.to_string(), *limit))
+                .collect(),
+            100,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let limit = limit(2.0, 2);
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(&limit, now);
+
+        assert!(bucket.take(&limit, now).is_ok());
+        assert!(bucket.take(&limit, now).is_ok());
+        assert_eq!(bucket.take(&limit, now), Err(Duration::from_millis(500)));
+
+        let later = now + Duration::from_millis(500);
+        assert!(bucket.take(&limit, later).is_ok());
+        assert!(bucket.take(&limit, later).is_err());
+    }
+
+    #[test]
+    fn limits_apply_per_name_and_caller() {
+        let limiter = limiter(None, &[(GET, limit(1.0, 1))]);
+        let now = Instant::now();
+
+        assert!(limiter.check_at(GET, 1, now).is_ok());
+        assert_eq!(limiter.check_at(GET, 1, now), Err(Duration::from_secs(1)));
+        assert!(limiter.check_at(GET, 2, now).is_ok());
+        assert!(limiter
+            .check_at(GET, 1, now + Duration::from_secs(1))
+            .is_ok());
+        // names without a limit and no default are never throttled
+        for _ in 0..10 {
+            assert!(limiter.check_at("list", 1, now).is_ok());
+        }
+    }
+
+    #[test]
+    fn named_limit_overrides_default() {
+        let limiter = limiter(Some(limit(1.0, 1)), &[(GET, limit(10.0, 3))]);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at(GET, 1, now).is_ok());
+        }
+        assert!(limiter.check_at(GET, 1, now).is_err());
+        assert!(limiter.check_at("list", 1, now).is_ok());
+        assert!(limiter.check_at("list", 1, now).is_err());
+    }
+
+    #[test]
+    fn limits_that_never_allow_a_request_are_rejected() {
+        for invalid in [
+            limit(0.0, 1),
+            limit(-1.0, 1),
+            limit(f64::NAN, 1),
+            limit(1.0, 0),
+        ] {
+            assert!(RateLimiter::<u8>::new(Some(invalid), HashMap::new(), 100).is_err());
+            assert!(
+                RateLimiter::<u8>::new(None, HashMap::from([(GET.to_string(), invalid)]), 100)
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn idle_buckets_are_pruned() {
+        let limiter = RateLimiter::new(Some(limit(1.0, 1)), HashMap::new(), 1).unwrap();
+        let now = Instant::now();
+        assert!(limiter.check_at(GET, 1, now).is_ok());
+        assert!(limiter.check_at(GET, 2, now).is_ok());
+
+        // both buckets have refilled by now and are dropped on the next check
+        assert!(limiter
+            .check_at(GET, 3, now + Duration::from_secs(2))
+            .is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        let status = throttled_status(Duration::from_millis(1500));
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(
+            status
+                .metadata()
+                .get("retry-after")
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "2"
+        );
+    }
+}
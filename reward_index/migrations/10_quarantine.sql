@@ -0,0 +1,7 @@
+CREATE TABLE quarantine (
+	file_name VARCHAR PRIMARY KEY,
+	file_type VARCHAR NOT NULL,
+	file_timestamp TIMESTAMPTZ NOT NULL,
+	reason TEXT NOT NULL,
+	quarantined_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,10 @@
+create table reward_manifests (
+    start_timestamp timestamptz not null,
+    end_timestamp timestamptz not null,
+    written_files integer not null,
+    rewards bigint not null,
+    indexed_at timestamptz not null default now(),
+    primary key (start_timestamp, end_timestamp)
+);
+
+create index reward_manifests_end_timestamp_idx on reward_manifests (end_timestamp);
@@ -0,0 +1 @@
+ALTER TYPE reward_type ADD VALUE 'mobile_service_provider';
@@ -0,0 +1,10 @@
+create table reward_history (
+    address text not null,
+    reward_type reward_type not null,
+    start_period timestamptz not null,
+    end_period timestamptz not null,
+    rewards bigint not null,
+    primary key (address, reward_type, end_period)
+);
+
+create index reward_history_end_period_idx on reward_history (end_period);
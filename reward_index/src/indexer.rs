@@ -3,12 +3,15 @@ use anyhow::{anyhow, bail, Result};
 use chrono::Utc;
 use file_store::{
     file_info_poller::FileInfoStream, reward_manifest::RewardManifest, FileInfo, FileStore,
+    FileType,
 };
 use futures::{stream, StreamExt, TryStreamExt};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::{
     services::poc_lora::{iot_reward_share::Reward as IotReward, IotRewardShare},
-    services::poc_mobile::{mobile_reward_share::Reward as MobileReward, MobileRewardShare},
+    services::poc_mobile::{
+        mobile_reward_share::Reward as MobileReward, MobileRewardShare, ServiceProvider,
+    },
     Message,
 };
 use poc_metrics::record_duration;
@@ -30,6 +33,19 @@ pub enum RewardType {
     IotGateway,
     IotOperational,
     MobileSubscriber,
+    MobileServiceProvider,
+}
+
+impl RewardType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MobileGateway => "mobile_gateway",
+            Self::IotGateway => "iot_gateway",
+            Self::IotOperational => "iot_operational",
+            Self::MobileSubscriber => "mobile_subscriber",
+            Self::MobileServiceProvider => "mobile_service_provider",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -70,7 +86,7 @@ impl Indexer {
                     let key = &file_info_stream.file_info.key.clone();
                     tracing::info!(file = %key, "Processing reward file");
                     let mut txn = self.pool.begin().await?;
-                    let mut stream = file_info_stream.into_stream(&mut txn).await?;
+                    let (mut stream, completion) = file_info_stream.into_stream(&mut txn).await?;
 
                     while let Some(reward_manifest) = stream.next().await {
                         record_duration!(
@@ -78,7 +94,7 @@ impl Indexer {
                             self.handle_rewards(&mut txn, reward_manifest).await?
                         )
                     }
-                    txn.commit().await?;
+                    completion.commit(txn).await?;
                     tracing::info!(file = %key, "Completed processing reward file");
                     telemetry::last_reward_processed_time(&self.pool, Utc::now()).await?;
                 }
@@ -91,15 +107,19 @@ impl Indexer {
         txn: &mut Transaction<'_, Postgres>,
         manifest: RewardManifest,
     ) -> Result<()> {
+        let reward_files = verify_manifest(&manifest, self.mode.reward_file_type())?;
+        if reward_index::manifest_indexed(&mut *txn, &manifest).await? {
+            tracing::warn!(
+                start = %manifest.start_timestamp,
+                end = %manifest.end_timestamp,
+                "skipping reward manifest overlapping an indexed period"
+            );
+            metrics::increment_counter!("reward_index_manifest_skipped");
+            return Ok(());
+        }
         let manifest_time = manifest.end_timestamp;
 
-        let reward_files = stream::iter(
-            manifest
-                .written_files
-                .into_iter()
-                .map(|file_name| FileInfo::from_str(&file_name)),
-        )
-        .boxed();
+        let reward_files = stream::iter(reward_files.into_iter().map(Ok)).boxed();
 
         let mut reward_shares = self.verifier_store.source_unordered(5, reward_files);
         let mut hotspot_rewards: HashMap<RewardKey, u64> = HashMap::new();
@@ -109,7 +129,16 @@ impl Indexer {
             *hotspot_rewards.entry(key).or_default() += amount;
         }
 
+        let total_rewards = hotspot_rewards.values().sum();
         for (reward_key, amount) in hotspot_rewards {
+            reward_index::insert_history(
+                &mut *txn,
+                &reward_key.key,
+                amount,
+                &reward_key.reward_type,
+                &manifest,
+            )
+            .await?;
             reward_index::insert(
                 &mut *txn,
                 reward_key.key,
@@ -119,6 +148,7 @@ impl Indexer {
             )
             .await?;
         }
+        reward_index::insert_manifest(&mut *txn, &manifest, total_rewards).await?;
 
         Ok(())
     }
@@ -149,6 +179,18 @@ impl Indexer {
                         },
                         r.discovery_location_amount,
                     )),
+                    Some(MobileReward::ServiceProviderReward(r)) => Ok((
+                        RewardKey {
+                            key: ServiceProvider::from_i32(r.service_provider_id)
+                                .ok_or_else(|| {
+                                    anyhow!("unknown service provider {}", r.service_provider_id)
+                                })?
+                                .as_str_name()
+                                .to_string(),
+                            reward_type: RewardType::MobileServiceProvider,
+                        },
+                        r.amount,
+                    )),
                     _ => bail!("got an invalid reward share"),
                 }
             }
@@ -175,3 +217,69 @@ impl Indexer {
         }
     }
 }
+
+/// Check a manifest is well formed and only lists reward files of the
+/// expected type before any of its rewards are indexed
+fn verify_manifest(manifest: &RewardManifest, file_type: FileType) -> Result<Vec<FileInfo>> {
+    if manifest.end_timestamp <= manifest.start_timestamp {
+        bail!(
+            "invalid reward manifest period {} to {}",
+            manifest.start_timestamp,
+            manifest.end_timestamp
+        );
+    }
+    manifest
+        .written_files
+        .iter()
+        .map(|file_name| {
+            let file_info = FileInfo::from_str(file_name)?;
+            if file_info.file_type != file_type {
+                bail!("unexpected file {file_name} in reward manifest, expected {file_type}");
+            }
+            Ok(file_info)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn manifest(written_files: &[&str], period: Duration) -> RewardManifest {
+        let start_timestamp = Utc.timestamp_opt(1_686_000_000, 0).unwrap();
+        RewardManifest {
+            written_files: written_files.iter().map(|file| file.to_string()).collect(),
+            start_timestamp,
+            end_timestamp: start_timestamp + period,
+        }
+    }
+
+    #[test]
+    fn verifies_manifest() {
+        let reward_file = "iot_reward_share.1686000000000.gz";
+        let verified = verify_manifest(
+            &manifest(&[reward_file], Duration::hours(24)),
+            FileType::IotRewardShare,
+        )
+        .unwrap();
+        assert_eq!(verified.len(), 1);
+        assert_eq!(verified[0].key, reward_file);
+
+        assert!(verify_manifest(
+            &manifest(&[reward_file], Duration::zero()),
+            FileType::IotRewardShare
+        )
+        .is_err());
+        assert!(verify_manifest(
+            &manifest(&[reward_file], Duration::hours(24)),
+            FileType::MobileRewardShare
+        )
+        .is_err());
+        assert!(verify_manifest(
+            &manifest(&["not_a_file"], Duration::hours(24)),
+            FileType::IotRewardShare
+        )
+        .is_err());
+    }
+}
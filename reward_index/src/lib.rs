@@ -1,7 +1,9 @@
 pub mod indexer;
+pub mod query_service;
 mod reward_index;
 pub mod settings;
 pub mod telemetry;
 
 pub use indexer::Indexer;
+pub use query_service::QueryService;
 pub use settings::Settings;
@@ -6,10 +6,10 @@ use file_store::{
     FileType,
 };
 use futures_util::TryFutureExt;
-use reward_index::{settings::Settings, telemetry, Indexer};
+use grpc_service::tls;
+use reward_index::{settings::Settings, telemetry, Indexer, QueryService};
 use std::path::PathBuf;
 use tokio::signal;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Debug, clap::Parser)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
@@ -46,20 +46,23 @@ impl Cmd {
 }
 
 #[derive(Debug, clap::Args)]
-pub struct Server {}
+pub struct Server {
+    /// Apply database migrations and exit without starting the service
+    #[clap(long)]
+    migrate_only: bool,
+}
 
 impl Server {
     pub async fn run(&self, settings: &Settings) -> Result<()> {
-        tracing_subscriber::registry()
-            .with(tracing_subscriber::EnvFilter::new(&settings.log))
-            .with(tracing_subscriber::fmt::layer())
-            .init();
+        custom_tracing::init(&settings.log, &settings.tracing)?;
+        poc_metrics::register_error_codes!(poc_metrics, db_store, file_store, iot_config);
 
         // Install the prometheus metrics exporter
         poc_metrics::start_metrics(&settings.metrics)?;
         //
         // Configure shutdown trigger
         let (shutdown_trigger, shutdown_listener) = triggered::trigger();
+        poc_metrics::process::start(shutdown_listener.clone());
         let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
         tokio::spawn(async move {
             tokio::select! {
@@ -74,7 +77,10 @@ impl Server {
             .database
             .connect(&app_name, shutdown_listener.clone())
             .await?;
-        sqlx::migrate!().run(&pool).await?;
+        db_store::migrate::run(&pool, &app_name, &sqlx::migrate!()).await?;
+        if self.migrate_only {
+            return Ok(());
+        }
 
         telemetry::initialize(&pool).await?;
 
@@ -95,12 +101,48 @@ impl Server {
             .start(shutdown_listener.clone())
             .await?;
 
+        // Reward query server
+        let query_server = {
+            let query = match settings.listen_addr()? {
+                Some(listen_addr) => {
+                    let query_service =
+                        QueryService::new(pool.clone(), settings.signing_keypair()?);
+                    let query_service = match settings.token_verifier()? {
+                        Some(tokens) => query_service.api_tokens(tokens),
+                        None => query_service,
+                    };
+                    Some((listen_addr, query_service))
+                }
+                None => None,
+            };
+            let tls = settings.tls.clone();
+            let shutdown = shutdown_listener.clone();
+            async move {
+                let Some((listen_addr, query_service)) = query else {
+                    return Ok(());
+                };
+                tracing::info!("serving reward queries on {listen_addr}");
+                let router = tonic::transport::Server::builder().add_service(query_service);
+                match tls {
+                    Some(tls) => {
+                        let incoming = tls::incoming(&tls, listen_addr, shutdown.clone()).await?;
+                        router
+                            .serve_with_incoming_shutdown(incoming, shutdown)
+                            .await?
+                    }
+                    None => router.serve_with_shutdown(listen_addr, shutdown).await?,
+                }
+                Ok::<(), anyhow::Error>(())
+            }
+        };
+
         // Reward server
         let mut indexer = Indexer::new(settings, pool).await?;
 
         tokio::try_join!(
             db_join_handle.map_err(anyhow::Error::from),
             source_join_handle.map_err(anyhow::Error::from),
+            query_server,
             indexer.run(shutdown_listener, receiver),
         )?;
 
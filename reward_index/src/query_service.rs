@@ -0,0 +1,559 @@
+//! Grpc queries of the indexed rewards: the paginated reward totals, the
+//! rewards of a gateway, of the gateways of an owner and the totals of the
+//! rewarded periods over a range, and a paginated leaderboard. Like the price
+//! oracle endpoint the service is hand written on top of the tonic primitives
+//! with its messages defined here.
+//!
+//! Reward shares carry no owner and the owner of a hotspot is the holder of
+//! its asset, which the index does not track. An owner query therefore lists
+//! the gateways of the owner, e.g. as returned by a wallet or the hotspot
+//! asset api, and is answered with the rewards of each of them.
+//!
+//! With api tokens required every query carries a token with the rewards
+//! scope minted by the iot config service, checked like the packet
+//! statistics tokens of the iot packet verifier. Otherwise the queries are
+//! public.
+
+use crate::reward_index;
+use chrono::{DateTime, TimeZone, Utc};
+use helium_crypto::{Keypair, Sign};
+use iot_config::{api_token::REWARDS_SCOPE, client::TokenVerifier};
+use prost::Message;
+use sqlx::{Pool, Postgres};
+use std::{convert::Infallible, ops::Range, sync::Arc};
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError},
+    transport::NamedService,
+    Status,
+};
+
+const SERVICE_NAME: &str = "helium.reward_index.rewards";
+const LIST_PATH: &str = "/helium.reward_index.rewards/list";
+const GATEWAY_PATH: &str = "/helium.reward_index.rewards/gateway";
+const OWNER_PATH: &str = "/helium.reward_index.rewards/owner";
+const EPOCHS_PATH: &str = "/helium.reward_index.rewards/epochs";
+const LEADERBOARD_PATH: &str = "/helium.reward_index.rewards/leaderboard";
+
+pub const DEFAULT_PAGE_SIZE: u32 = 100;
+pub const MAX_PAGE_SIZE: u32 = 1000;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RewardsListReqV1 {
+    /// Address to start the page after, empty for the first page
+    #[prost(string, tag = "1")]
+    pub cursor: String,
+    /// Maximum number of rewards in the page. Default 100, at most 1000
+    #[prost(uint32, tag = "2")]
+    pub limit: u32,
+    /// Encoded api token with the rewards scope, when tokens are required
+    #[prost(bytes, tag = "3")]
+    pub token: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RewardV1 {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    #[prost(string, tag = "2")]
+    pub reward_type: String,
+    /// Total rewards of the address in bones
+    #[prost(uint64, tag = "3")]
+    pub rewards: u64,
+    /// Seconds since the epoch of the end of the last rewarded period
+    #[prost(uint64, tag = "4")]
+    pub last_reward: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RewardsListResV1 {
+    #[prost(message, repeated, tag = "1")]
+    pub rewards: Vec<RewardV1>,
+    /// Cursor of the next page, empty when this is the last page
+    #[prost(string, tag = "2")]
+    pub next_cursor: String,
+    #[prost(bytes, tag = "3")]
+    pub signer: Vec<u8>,
+    #[prost(bytes, tag = "4")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GatewayRewardsReqV1 {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    /// Seconds since the epoch after which the rewarded periods end
+    #[prost(uint64, tag = "2")]
+    pub start: u64,
+    /// Seconds since the epoch at or before which the rewarded periods end,
+    /// now when 0
+    #[prost(uint64, tag = "3")]
+    pub end: u64,
+    /// Maximum number of rewarded periods, the most recent first. Default
+    /// 100, at most 1000
+    #[prost(uint32, tag = "4")]
+    pub limit: u32,
+    /// Encoded api token with the rewards scope, when tokens are required
+    #[prost(bytes, tag = "5")]
+    pub token: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PeriodRewardV1 {
+    #[prost(string, tag = "1")]
+    pub reward_type: String,
+    /// Seconds since the epoch of the start of the rewarded period
+    #[prost(uint64, tag = "2")]
+    pub start_period: u64,
+    /// Seconds since the epoch of the end of the rewarded period
+    #[prost(uint64, tag = "3")]
+    pub end_period: u64,
+    /// Rewards of the period in bones
+    #[prost(uint64, tag = "4")]
+    pub rewards: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GatewayRewardsResV1 {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    #[prost(message, repeated, tag = "2")]
+    pub rewards: Vec<PeriodRewardV1>,
+    /// Sum of the listed rewards in bones
+    #[prost(uint64, tag = "3")]
+    pub total: u64,
+    #[prost(bytes, tag = "4")]
+    pub signer: Vec<u8>,
+    #[prost(bytes, tag = "5")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OwnerRewardsReqV1 {
+    /// Owner of the gateways, returned as is in the response
+    #[prost(string, tag = "1")]
+    pub owner: String,
+    /// Addresses of the gateways of the owner, at most 1000
+    #[prost(string, repeated, tag = "2")]
+    pub gateways: Vec<String>,
+    /// Seconds since the epoch after which the rewarded periods end
+    #[prost(uint64, tag = "3")]
+    pub start: u64,
+    /// Seconds since the epoch at or before which the rewarded periods end,
+    /// now when 0
+    #[prost(uint64, tag = "4")]
+    pub end: u64,
+    /// Encoded api token with the rewards scope, when tokens are required
+    #[prost(bytes, tag = "5")]
+    pub token: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GatewayTotalV1 {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    /// Rewards of the gateway over the range in bones
+    #[prost(uint64, tag = "2")]
+    pub rewards: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OwnerRewardsResV1 {
+    #[prost(string, tag = "1")]
+    pub owner: String,
+    /// Rewards of each rewarded gateway, the most rewarded first
+    #[prost(message, repeated, tag = "2")]
+    pub gateways: Vec<GatewayTotalV1>,
+    /// Sum of the rewards of all gateways in bones
+    #[prost(uint64, tag = "3")]
+    pub total: u64,
+    #[prost(bytes, tag = "4")]
+    pub signer: Vec<u8>,
+    #[prost(bytes, tag = "5")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EpochTotalsReqV1 {
+    /// Seconds since the epoch after which the rewarded periods end
+    #[prost(uint64, tag = "1")]
+    pub start: u64,
+    /// Seconds since the epoch at or before which the rewarded periods end,
+    /// now when 0
+    #[prost(uint64, tag = "2")]
+    pub end: u64,
+    /// Maximum number of rewarded periods, the most recent first. Default
+    /// 100, at most 1000
+    #[prost(uint32, tag = "3")]
+    pub limit: u32,
+    /// Encoded api token with the rewards scope, when tokens are required
+    #[prost(bytes, tag = "4")]
+    pub token: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EpochTotalsResV1 {
+    /// Rewards of all addresses by period and reward type
+    #[prost(message, repeated, tag = "1")]
+    pub totals: Vec<PeriodRewardV1>,
+    #[prost(bytes, tag = "2")]
+    pub signer: Vec<u8>,
+    #[prost(bytes, tag = "3")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LeaderboardReqV1 {
+    /// Only rank rewards of this type, all types when empty
+    #[prost(string, tag = "1")]
+    pub reward_type: String,
+    /// Seconds since the epoch after which the rewarded periods end
+    #[prost(uint64, tag = "2")]
+    pub start: u64,
+    /// Seconds since the epoch at or before which the rewarded periods end,
+    /// now when 0
+    #[prost(uint64, tag = "3")]
+    pub end: u64,
+    /// Number of ranked addresses to skip, 0 for the first page
+    #[prost(uint32, tag = "4")]
+    pub offset: u32,
+    /// Maximum number of addresses in the page. Default 100, at most 1000
+    #[prost(uint32, tag = "5")]
+    pub limit: u32,
+    /// Encoded api token with the rewards scope, when tokens are required
+    #[prost(bytes, tag = "6")]
+    pub token: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LeaderboardEntryV1 {
+    /// Position of the address, starting at 1
+    #[prost(uint32, tag = "1")]
+    pub rank: u32,
+    #[prost(string, tag = "2")]
+    pub address: String,
+    /// Rewards of the address over the range in bones
+    #[prost(uint64, tag = "3")]
+    pub rewards: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LeaderboardResV1 {
+    #[prost(message, repeated, tag = "1")]
+    pub entries: Vec<LeaderboardEntryV1>,
+    /// Offset of the next page, 0 when this is the last page
+    #[prost(uint32, tag = "2")]
+    pub next_offset: u32,
+    #[prost(bytes, tag = "3")]
+    pub signer: Vec<u8>,
+    #[prost(bytes, tag = "4")]
+    pub signature: Vec<u8>,
+}
+
+/// Queries of the indexed rewards. Every response is signed by the key of the
+/// oracle over its encoding with an empty signature
+#[derive(Clone)]
+pub struct QueryService {
+    pool: Pool<Postgres>,
+    signing_key: Arc<Keypair>,
+    tokens: Option<TokenVerifier>,
+}
+
+fn page_size(limit: u32) -> u32 {
+    match limit {
+        0 => DEFAULT_PAGE_SIZE,
+        limit => limit.min(MAX_PAGE_SIZE),
+    }
+}
+
+fn timestamp(seconds: u64) -> Result<DateTime<Utc>, Status> {
+    i64::try_from(seconds)
+        .ok()
+        .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+        .ok_or_else(|| Status::invalid_argument(format!("invalid timestamp {seconds}")))
+}
+
+/// Range of the ends of the rewarded periods of a query
+fn period_range(start: u64, end: u64) -> Result<Range<DateTime<Utc>>, Status> {
+    let start = timestamp(start)?;
+    let end = match end {
+        0 => Utc::now(),
+        end => timestamp(end)?,
+    };
+    if end <= start {
+        return Err(Status::invalid_argument("end must be after start"));
+    }
+    Ok(start..end)
+}
+
+fn internal(query: &str, err: sqlx::Error) -> Status {
+    tracing::error!("failed to query {query}: {err:?}");
+    Status::internal(format!("failed to query {query}"))
+}
+
+fn period_reward(reward: reward_index::PeriodReward) -> PeriodRewardV1 {
+    PeriodRewardV1 {
+        reward_type: reward.reward_type.as_str().to_string(),
+        start_period: reward.start_period.timestamp() as u64,
+        end_period: reward.end_period.timestamp() as u64,
+        rewards: reward.rewards as u64,
+    }
+}
+
+impl QueryService {
+    pub fn new(pool: Pool<Postgres>, signing_key: Keypair) -> Self {
+        Self {
+            pool,
+            signing_key: Arc::new(signing_key),
+            tokens: None,
+        }
+    }
+
+    /// Only serve queries carrying an api token with the rewards scope
+    pub fn api_tokens(self, tokens: TokenVerifier) -> Self {
+        Self {
+            tokens: Some(tokens),
+            ..self
+        }
+    }
+
+    async fn authorize(&self, token: &[u8]) -> Result<(), Status> {
+        match &self.tokens {
+            None => Ok(()),
+            Some(_) if token.is_empty() => Err(Status::unauthenticated("api token required")),
+            Some(tokens) => Ok(tokens.verify_scope(token, REWARDS_SCOPE).await?),
+        }
+    }
+
+    fn signer(&self) -> Vec<u8> {
+        self.signing_key.public_key().to_vec()
+    }
+
+    fn sign_response(&self, response: &[u8]) -> Result<Vec<u8>, Status> {
+        self.signing_key
+            .sign(response)
+            .map_err(|_| Status::internal("response signing error"))
+    }
+
+    pub async fn list(&self, request: RewardsListReqV1) -> Result<RewardsListResV1, Status> {
+        self.authorize(&request.token).await?;
+        let limit = page_size(request.limit);
+        let rewards = reward_index::list(&self.pool, &request.cursor, limit as i64)
+            .await
+            .map_err(|err| internal("rewards", err))?;
+
+        let next_cursor = if rewards.len() == limit as usize {
+            rewards
+                .last()
+                .map(|reward| reward.address.clone())
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let rewards = rewards
+            .into_iter()
+            .map(|reward| RewardV1 {
+                address: reward.address,
+                reward_type: reward
+                    .reward_type
+                    .map(|reward_type| reward_type.as_str().to_string())
+                    .unwrap_or_default(),
+                rewards: reward.rewards as u64,
+                last_reward: reward
+                    .last_reward
+                    .map(|last_reward| last_reward.timestamp() as u64)
+                    .unwrap_or_default(),
+            })
+            .collect();
+        let mut response = RewardsListResV1 {
+            rewards,
+            next_cursor,
+            signer: self.signer(),
+            signature: vec![],
+        };
+        response.signature = self.sign_response(&response.encode_to_vec())?;
+        Ok(response)
+    }
+
+    pub async fn gateway(
+        &self,
+        request: GatewayRewardsReqV1,
+    ) -> Result<GatewayRewardsResV1, Status> {
+        self.authorize(&request.token).await?;
+        let range = period_range(request.start, request.end)?;
+        let rewards = reward_index::address_rewards(
+            &self.pool,
+            &request.address,
+            &range,
+            page_size(request.limit) as i64,
+        )
+        .await
+        .map_err(|err| internal("gateway rewards", err))?;
+
+        let rewards: Vec<PeriodRewardV1> = rewards.into_iter().map(period_reward).collect();
+        let mut response = GatewayRewardsResV1 {
+            address: request.address,
+            total: rewards.iter().map(|reward| reward.rewards).sum(),
+            rewards,
+            signer: self.signer(),
+            signature: vec![],
+        };
+        response.signature = self.sign_response(&response.encode_to_vec())?;
+        Ok(response)
+    }
+
+    pub async fn owner(&self, request: OwnerRewardsReqV1) -> Result<OwnerRewardsResV1, Status> {
+        self.authorize(&request.token).await?;
+        if request.gateways.len() > MAX_PAGE_SIZE as usize {
+            return Err(Status::invalid_argument(format!(
+                "at most {MAX_PAGE_SIZE} gateways per query"
+            )));
+        }
+        let range = period_range(request.start, request.end)?;
+        let totals = reward_index::addresses_totals(&self.pool, &request.gateways, &range)
+            .await
+            .map_err(|err| internal("owner rewards", err))?;
+
+        let gateways: Vec<GatewayTotalV1> = totals
+            .into_iter()
+            .map(|reward| GatewayTotalV1 {
+                address: reward.address,
+                rewards: reward.rewards as u64,
+            })
+            .collect();
+        let mut response = OwnerRewardsResV1 {
+            owner: request.owner,
+            total: gateways.iter().map(|gateway| gateway.rewards).sum(),
+            gateways,
+            signer: self.signer(),
+            signature: vec![],
+        };
+        response.signature = self.sign_response(&response.encode_to_vec())?;
+        Ok(response)
+    }
+
+    pub async fn epochs(&self, request: EpochTotalsReqV1) -> Result<EpochTotalsResV1, Status> {
+        self.authorize(&request.token).await?;
+        let range = period_range(request.start, request.end)?;
+        let totals =
+            reward_index::period_totals(&self.pool, &range, page_size(request.limit) as i64)
+                .await
+                .map_err(|err| internal("epoch totals", err))?;
+
+        let mut response = EpochTotalsResV1 {
+            totals: totals.into_iter().map(period_reward).collect(),
+            signer: self.signer(),
+            signature: vec![],
+        };
+        response.signature = self.sign_response(&response.encode_to_vec())?;
+        Ok(response)
+    }
+
+    pub async fn leaderboard(&self, request: LeaderboardReqV1) -> Result<LeaderboardResV1, Status> {
+        self.authorize(&request.token).await?;
+        let range = period_range(request.start, request.end)?;
+        let limit = page_size(request.limit);
+        let reward_type = Some(request.reward_type.as_str()).filter(|t| !t.is_empty());
+        let ranked = reward_index::leaderboard(
+            &self.pool,
+            reward_type,
+            &range,
+            request.offset as i64,
+            limit as i64,
+        )
+        .await
+        .map_err(|err| internal("leaderboard", err))?;
+
+        let next_offset = if ranked.len() == limit as usize {
+            request.offset.saturating_add(limit)
+        } else {
+            0
+        };
+        let entries = ranked
+            .into_iter()
+            .zip(request.offset.saturating_add(1)..)
+            .map(|(reward, rank)| LeaderboardEntryV1 {
+                rank,
+                address: reward.address,
+                rewards: reward.rewards as u64,
+            })
+            .collect();
+        let mut response = LeaderboardResV1 {
+            entries,
+            next_offset,
+            signer: self.signer(),
+            signature: vec![],
+        };
+        response.signature = self.sign_response(&response.encode_to_vec())?;
+        Ok(response)
+    }
+}
+
+impl NamedService for QueryService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<B> Service<http::Request<B>> for QueryService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            LIST_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.list(request).await })
+            }
+            GATEWAY_PATH => {
+                grpc_service::unary(
+                    req,
+                    move |request| async move { svc.gateway(request).await },
+                )
+            }
+            OWNER_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.owner(request).await })
+            }
+            EPOCHS_PATH => {
+                grpc_service::unary(req, move |request| async move { svc.epochs(request).await })
+            }
+            LEADERBOARD_PATH => {
+                grpc_service::unary(
+                    req,
+                    move |request| async move { svc.leaderboard(request).await },
+                )
+            }
+            _ => grpc_service::unimplemented(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn period_ranges_are_validated() {
+        let range = period_range(1_686_000_000, 1_686_086_400).unwrap();
+        assert_eq!(range.start.timestamp(), 1_686_000_000);
+        assert_eq!(range.end.timestamp(), 1_686_086_400);
+        assert!(period_range(1_686_000_000, 0).unwrap().end > range.end);
+
+        assert!(period_range(1_686_086_400, 1_686_000_000).is_err());
+        assert!(period_range(u64::MAX, 0).is_err());
+    }
+
+    #[test]
+    fn page_sizes_are_capped() {
+        assert_eq!(page_size(0), DEFAULT_PAGE_SIZE);
+        assert_eq!(page_size(10), 10);
+        assert_eq!(page_size(MAX_PAGE_SIZE + 1), MAX_PAGE_SIZE);
+    }
+}
@@ -1,5 +1,7 @@
 use crate::indexer::RewardType;
 use chrono::{DateTime, Utc};
+use file_store::reward_manifest::RewardManifest;
+use std::ops::Range;
 
 pub async fn insert<'c, E>(
     executor: E,
@@ -38,3 +40,248 @@ where
 
     Ok(())
 }
+
+/// Record the rewards of an address for the period of a manifest
+pub async fn insert_history<'c, E>(
+    executor: E,
+    address: &str,
+    amount: u64,
+    reward_type: &RewardType,
+    manifest: &RewardManifest,
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    if amount == 0 {
+        return Ok(());
+    }
+
+    sqlx::query(
+        r#"
+        insert into reward_history (address, reward_type, start_period, end_period, rewards)
+        values ($1, $2, $3, $4, $5)
+        on conflict do nothing
+        "#,
+    )
+    .bind(address)
+    .bind(reward_type)
+    .bind(manifest.start_timestamp)
+    .bind(manifest.end_timestamp)
+    .bind(amount as i64)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct IndexedReward {
+    pub address: String,
+    pub rewards: i64,
+    pub last_reward: Option<DateTime<Utc>>,
+    pub reward_type: Option<RewardType>,
+}
+
+/// Page of indexed rewards ordered by address, starting after the given
+/// address
+pub async fn list<'c, E>(
+    executor: E,
+    after: &str,
+    limit: i64,
+) -> Result<Vec<IndexedReward>, sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    sqlx::query_as(
+        r#"
+        select address, rewards, last_reward, reward_type
+        from reward_index
+        where address > $1
+        order by address
+        limit $2
+        "#,
+    )
+    .bind(after)
+    .bind(limit)
+    .fetch_all(executor)
+    .await
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct PeriodReward {
+    pub reward_type: RewardType,
+    pub start_period: DateTime<Utc>,
+    pub end_period: DateTime<Utc>,
+    pub rewards: i64,
+}
+
+/// Rewards of an address for the most recent periods ending within the
+/// range, newest first
+pub async fn address_rewards<'c, E>(
+    executor: E,
+    address: &str,
+    range: &Range<DateTime<Utc>>,
+    limit: i64,
+) -> Result<Vec<PeriodReward>, sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    sqlx::query_as(
+        r#"
+        select reward_type, start_period, end_period, rewards
+        from reward_history
+        where address = $1 and end_period > $2 and end_period <= $3
+        order by end_period desc, reward_type
+        limit $4
+        "#,
+    )
+    .bind(address)
+    .bind(range.start)
+    .bind(range.end)
+    .bind(limit)
+    .fetch_all(executor)
+    .await
+}
+
+/// Rewards of each of the addresses over the periods ending within the range,
+/// addresses without rewards are left out
+pub async fn addresses_totals<'c, E>(
+    executor: E,
+    addresses: &[String],
+    range: &Range<DateTime<Utc>>,
+) -> Result<Vec<RankedReward>, sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    sqlx::query_as(
+        r#"
+        select address, sum(rewards)::bigint as rewards
+        from reward_history
+        where address = any($1) and end_period > $2 and end_period <= $3
+        group by address
+        order by rewards desc, address
+        "#,
+    )
+    .bind(addresses)
+    .bind(range.start)
+    .bind(range.end)
+    .fetch_all(executor)
+    .await
+}
+
+/// Rewards of all addresses by period and reward type for the most recent
+/// periods ending within the range, newest first
+pub async fn period_totals<'c, E>(
+    executor: E,
+    range: &Range<DateTime<Utc>>,
+    limit: i64,
+) -> Result<Vec<PeriodReward>, sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    sqlx::query_as(
+        r#"
+        select reward_type, start_period, end_period, sum(rewards)::bigint as rewards
+        from reward_history
+        where end_period in (
+            select distinct end_period from reward_history
+            where end_period > $1 and end_period <= $2
+            order by end_period desc
+            limit $3
+        )
+        group by reward_type, start_period, end_period
+        order by end_period desc, reward_type
+        "#,
+    )
+    .bind(range.start)
+    .bind(range.end)
+    .bind(limit)
+    .fetch_all(executor)
+    .await
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct RankedReward {
+    pub address: String,
+    pub rewards: i64,
+}
+
+/// Page of the addresses with the most rewards for the periods ending within
+/// the range, optionally of a single reward type
+pub async fn leaderboard<'c, E>(
+    executor: E,
+    reward_type: Option<&str>,
+    range: &Range<DateTime<Utc>>,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<RankedReward>, sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    sqlx::query_as(
+        r#"
+        select address, sum(rewards)::bigint as rewards
+        from reward_history
+        where end_period > $1 and end_period <= $2
+            and ($3::text is null or reward_type::text = $3)
+        group by address
+        order by rewards desc, address
+        offset $4
+        limit $5
+        "#,
+    )
+    .bind(range.start)
+    .bind(range.end)
+    .bind(reward_type)
+    .bind(offset)
+    .bind(limit)
+    .fetch_all(executor)
+    .await
+}
+
+/// Whether a manifest covering any part of the given period was indexed
+/// before
+pub async fn manifest_indexed<'c, E>(
+    executor: E,
+    manifest: &RewardManifest,
+) -> Result<bool, sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    sqlx::query_scalar(
+        r#"
+        select exists(
+            select 1 from reward_manifests
+            where start_timestamp < $2 and end_timestamp > $1
+        )
+        "#,
+    )
+    .bind(manifest.start_timestamp)
+    .bind(manifest.end_timestamp)
+    .fetch_one(executor)
+    .await
+}
+
+pub async fn insert_manifest<'c, E>(
+    executor: E,
+    manifest: &RewardManifest,
+    rewards: u64,
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    sqlx::query(
+        r#"
+        insert into reward_manifests (start_timestamp, end_timestamp, written_files, rewards)
+        values ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(manifest.start_timestamp)
+    .bind(manifest.end_timestamp)
+    .bind(manifest.written_files.len() as i32)
+    .bind(rewards as i64)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
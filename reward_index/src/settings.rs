@@ -1,7 +1,14 @@
 use chrono::Duration;
 use config::{Config, Environment, File};
+use grpc_service::tls::TlsSettings;
+use iot_config::client::{ApiTokenClient, TokenVerifier};
 use serde::Deserialize;
-use std::{fmt, path::Path};
+use std::{
+    fmt,
+    net::{AddrParseError, SocketAddr},
+    path::Path,
+    str::FromStr,
+};
 
 /// Mode to start the indexer in. Each mode uses different files from
 /// the verifier
@@ -12,6 +19,17 @@ pub enum Mode {
     Mobile,
 }
 
+impl Mode {
+    /// Type of the reward share files listed in the reward manifests of this
+    /// mode
+    pub fn reward_file_type(&self) -> file_store::FileType {
+        match self {
+            Self::Iot => file_store::FileType::IotRewardShare,
+            Self::Mobile => file_store::FileType::MobileRewardShare,
+        }
+    }
+}
+
 impl fmt::Display for Mode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -27,6 +45,9 @@ pub struct Settings {
     /// "poc_entropy=debug,poc_store=info"
     #[serde(default = "default_log")]
     pub log: String,
+    /// Log output format, per module log levels and runtime diagnostics
+    #[serde(default)]
+    pub tracing: custom_tracing::Settings,
     /// Check interval in seconds. (Default is 900; 15 minutes)
     #[serde(default = "default_interval")]
     pub interval: i64,
@@ -38,6 +59,32 @@ pub struct Settings {
     pub operation_fund_key: Option<String>,
     #[serde(default = "default_start_after")]
     pub start_after: u64,
+    /// Listen address of the reward query grpc endpoint. Disabled when not
+    /// set
+    pub listen: Option<String>,
+    /// Serve the grpc endpoint over TLS when set. Plaintext by default
+    pub tls: Option<TlsSettings>,
+    /// File name of the keypair signing the query responses, or a `file:` or
+    /// `env:` secret reference. Required when listen is set
+    pub signing_keypair: Option<oracle_settings::Secret>,
+    /// Only serve reward queries carrying an api token with the rewards
+    /// scope, minted by the iot config service. Default is false
+    #[serde(default)]
+    pub api_tokens: bool,
+    /// How long the revocation status of an api token is cached. Default is
+    /// 60 seconds
+    #[serde(
+        with = "oracle_settings::duration",
+        default = "default_api_token_status_ttl"
+    )]
+    pub api_token_status_ttl: std::time::Duration,
+    /// Client of the iot config service asking for the revocation status of
+    /// api tokens. Required when api_tokens is set
+    pub iot_config_client: Option<iot_config::client::Settings>,
+}
+
+pub fn default_api_token_status_ttl() -> std::time::Duration {
+    std::time::Duration::from_secs(iot_config::client::api_token_client::DEFAULT_STATUS_TTL_SECS)
 }
 
 pub fn default_start_after() -> u64 {
@@ -75,6 +122,33 @@ impl Settings {
         Duration::seconds(self.interval)
     }
 
+    pub fn listen_addr(&self) -> Result<Option<SocketAddr>, AddrParseError> {
+        self.listen.as_deref().map(SocketAddr::from_str).transpose()
+    }
+
+    pub fn signing_keypair(&self) -> anyhow::Result<helium_crypto::Keypair> {
+        let Some(keypair) = &self.signing_keypair else {
+            anyhow::bail!("signing_keypair is required to serve reward queries");
+        };
+        let data = keypair.resolve_keypair()?;
+        Ok(helium_crypto::Keypair::try_from(&data[..])?)
+    }
+
+    /// Verifier of the api tokens of the reward queries, when they require
+    /// one
+    pub fn token_verifier(&self) -> anyhow::Result<Option<TokenVerifier>> {
+        if !self.api_tokens {
+            return Ok(None);
+        }
+        let Some(client) = &self.iot_config_client else {
+            anyhow::bail!("iot_config_client is required to verify api tokens");
+        };
+        Ok(Some(TokenVerifier::new(
+            ApiTokenClient::from_settings(client)?,
+            self.api_token_status_ttl,
+        )))
+    }
+
     pub fn operation_fund_key(&self) -> Option<String> {
         self.operation_fund_key.clone()
     }
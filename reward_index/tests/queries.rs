@@ -0,0 +1,278 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use helium_crypto::{KeyTag, KeyType, Keypair, Network, PublicKey, Sign, Verify};
+use iot_config::{
+    api_token::{OrgTokenV1, PACKET_STATS_SCOPE, REWARDS_SCOPE},
+    client::{ApiTokenClient, TokenVerifier},
+};
+use oracle_signer::FileSigner;
+use prost::Message;
+use rand::rngs::OsRng;
+use reward_index::query_service::{
+    EpochTotalsReqV1, GatewayRewardsReqV1, LeaderboardReqV1, OwnerRewardsReqV1, QueryService,
+};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tonic::transport::Endpoint;
+
+fn keypair() -> Keypair {
+    Keypair::generate(
+        KeyTag {
+            network: Network::MainNet,
+            key_type: KeyType::Ed25519,
+        },
+        &mut OsRng,
+    )
+}
+
+fn epoch(day: i64) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 9, 1, 0, 0, 0).unwrap() + Duration::days(day)
+}
+
+fn seconds(day: i64) -> u64 {
+    epoch(day).timestamp() as u64
+}
+
+/// Daily rewards of the periods ending on day 1 to 3
+async fn seed(pool: &PgPool) {
+    for (address, reward_type, day, rewards) in [
+        ("a", "mobile_gateway", 1, 10_i64),
+        ("a", "mobile_gateway", 2, 20),
+        ("a", "mobile_gateway", 3, 30),
+        ("b", "mobile_gateway", 2, 100),
+        ("b", "mobile_subscriber", 2, 5),
+        ("c", "mobile_subscriber", 3, 7),
+    ] {
+        sqlx::query(
+            r#"
+            insert into reward_history (address, reward_type, start_period, end_period, rewards)
+            values ($1, $2::reward_type, $3, $4, $5)
+            "#,
+        )
+        .bind(address)
+        .bind(reward_type)
+        .bind(epoch(day - 1))
+        .bind(epoch(day))
+        .bind(rewards)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+}
+
+/// Verify a response signature over the encoding of the response with an
+/// empty signature
+fn assert_signed(signer: &[u8], unsigned: impl Message, signature: &[u8]) {
+    PublicKey::try_from(signer)
+        .unwrap()
+        .verify(&unsigned.encode_to_vec(), signature)
+        .expect("response signed by the oracle");
+}
+
+#[sqlx::test]
+async fn gateway_rewards_are_limited_to_the_range(pool: PgPool) {
+    seed(&pool).await;
+    let keypair = keypair();
+    let signer = keypair.public_key().to_vec();
+    let service = QueryService::new(pool, keypair);
+
+    let response = service
+        .gateway(GatewayRewardsReqV1 {
+            address: "a".to_string(),
+            start: seconds(1),
+            end: seconds(3),
+            limit: 0,
+            token: vec![],
+        })
+        .await
+        .unwrap();
+    let ends: Vec<u64> = response
+        .rewards
+        .iter()
+        .map(|reward| reward.end_period)
+        .collect();
+    assert_eq!(ends, vec![seconds(3), seconds(2)]);
+    assert_eq!(response.total, 50);
+
+    assert_eq!(response.signer, signer);
+    let mut unsigned = response.clone();
+    unsigned.signature.clear();
+    assert_signed(&signer, unsigned, &response.signature);
+}
+
+#[sqlx::test]
+async fn owner_rewards_sum_the_listed_gateways(pool: PgPool) {
+    seed(&pool).await;
+    let keypair = keypair();
+    let signer = keypair.public_key().to_vec();
+    let service = QueryService::new(pool, keypair);
+
+    let response = service
+        .owner(OwnerRewardsReqV1 {
+            owner: "owner".to_string(),
+            gateways: vec!["a".to_string(), "b".to_string(), "unknown".to_string()],
+            start: seconds(0),
+            end: seconds(3),
+            token: vec![],
+        })
+        .await
+        .unwrap();
+    assert_eq!(response.owner, "owner");
+    let gateways: Vec<(&str, u64)> = response
+        .gateways
+        .iter()
+        .map(|gateway| (gateway.address.as_str(), gateway.rewards))
+        .collect();
+    assert_eq!(gateways, vec![("b", 105), ("a", 60)]);
+    assert_eq!(response.total, 165);
+
+    let mut unsigned = response.clone();
+    unsigned.signature.clear();
+    assert_signed(&signer, unsigned, &response.signature);
+}
+
+#[sqlx::test]
+async fn owner_queries_are_capped(pool: PgPool) {
+    let service = QueryService::new(pool, keypair());
+    let status = service
+        .owner(OwnerRewardsReqV1 {
+            owner: "owner".to_string(),
+            gateways: vec!["a".to_string(); 1001],
+            start: seconds(0),
+            end: seconds(3),
+            token: vec![],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[sqlx::test]
+async fn epoch_totals_are_summed_by_reward_type(pool: PgPool) {
+    seed(&pool).await;
+    let service = QueryService::new(pool, keypair());
+
+    let response = service
+        .epochs(EpochTotalsReqV1 {
+            start: seconds(0),
+            end: seconds(3),
+            limit: 2,
+            token: vec![],
+        })
+        .await
+        .unwrap();
+    let totals: Vec<(&str, u64, u64)> = response
+        .totals
+        .iter()
+        .map(|total| (total.reward_type.as_str(), total.end_period, total.rewards))
+        .collect();
+    assert_eq!(
+        totals,
+        vec![
+            ("mobile_gateway", seconds(3), 30),
+            ("mobile_subscriber", seconds(3), 7),
+            ("mobile_gateway", seconds(2), 120),
+            ("mobile_subscriber", seconds(2), 5),
+        ]
+    );
+}
+
+#[sqlx::test]
+async fn leaderboard_pages_through_the_ranking(pool: PgPool) {
+    seed(&pool).await;
+    let service = QueryService::new(pool, keypair());
+    let request = LeaderboardReqV1 {
+        reward_type: String::new(),
+        start: seconds(0),
+        end: seconds(3),
+        offset: 0,
+        limit: 2,
+        token: vec![],
+    };
+
+    let first = service.leaderboard(request.clone()).await.unwrap();
+    let ranked: Vec<(u32, &str, u64)> = first
+        .entries
+        .iter()
+        .map(|entry| (entry.rank, entry.address.as_str(), entry.rewards))
+        .collect();
+    assert_eq!(ranked, vec![(1, "b", 105), (2, "a", 60)]);
+    assert_eq!(first.next_offset, 2);
+
+    let last = service
+        .leaderboard(LeaderboardReqV1 {
+            offset: first.next_offset,
+            ..request.clone()
+        })
+        .await
+        .unwrap();
+    assert_eq!(last.entries.len(), 1);
+    assert_eq!((last.entries[0].rank, last.entries[0].rewards), (3, 7));
+    assert_eq!(last.next_offset, 0);
+
+    let subscribers = service
+        .leaderboard(LeaderboardReqV1 {
+            reward_type: "mobile_subscriber".to_string(),
+            ..request
+        })
+        .await
+        .unwrap();
+    let addresses: Vec<&str> = subscribers
+        .entries
+        .iter()
+        .map(|entry| entry.address.as_str())
+        .collect();
+    assert_eq!(addresses, vec!["c", "b"]);
+}
+
+/// Token verifier of tokens signed by the config key, with a config service
+/// that is never reached
+fn token_verifier(config: &Keypair) -> TokenVerifier {
+    let client = ApiTokenClient::new(
+        Endpoint::from_static("http://127.0.0.1:1").connect_lazy(),
+        Arc::new(FileSigner::new(keypair())),
+        config.public_key().clone(),
+    );
+    TokenVerifier::new(client, std::time::Duration::from_secs(60))
+}
+
+fn encoded_token(config: &Keypair, scope: &str) -> Vec<u8> {
+    let mut token = OrgTokenV1 {
+        id: vec![1; 16],
+        oui: 1,
+        scopes: vec![scope.to_string()],
+        expires_at: (Utc::now() + Duration::hours(1)).timestamp() as u64,
+        signer: config.public_key().into(),
+        signature: vec![],
+    };
+    token.signature = config.sign(&token.encode_to_vec()).unwrap();
+    token.encode_to_vec()
+}
+
+#[sqlx::test]
+async fn required_tokens_must_grant_the_rewards_scope(pool: PgPool) {
+    let config = keypair();
+    let service = QueryService::new(pool, keypair()).api_tokens(token_verifier(&config));
+    let request = EpochTotalsReqV1 {
+        start: seconds(0),
+        end: seconds(3),
+        limit: 2,
+        token: vec![],
+    };
+
+    let status = service.epochs(request.clone()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+    for token in [
+        encoded_token(&config, PACKET_STATS_SCOPE),
+        encoded_token(&keypair(), REWARDS_SCOPE),
+    ] {
+        let status = service
+            .epochs(EpochTotalsReqV1 {
+                token,
+                ..request.clone()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+}
@@ -1,4 +1,7 @@
+pub mod reward_math;
+
 use chrono::{DateTime, Duration, Utc};
+use oracle_epoch::{Epoch, EpochPolicy};
 use std::ops::Range;
 
 #[derive(Debug)]
@@ -26,12 +29,22 @@ impl Scheduler {
         }
     }
 
+    // The offset delays rewarding a period until its late reports arrived
+    fn settled_at(&self, reward_period: &Range<DateTime<Utc>>) -> DateTime<Utc> {
+        EpochPolicy::new(self.reward_offset).settled_at(reward_period)
+    }
+
     pub fn should_reward(&self, now: DateTime<Utc>) -> bool {
-        now >= self.reward_period.end + self.reward_offset
+        now >= self.settled_at(&self.reward_period)
     }
 
     pub fn next_reward_period(&self) -> Range<DateTime<Utc>> {
-        self.reward_period.end..(self.reward_period.end + self.reward_period_length)
+        Epoch::around(
+            self.reward_period.end,
+            Duration::zero(),
+            self.reward_period_length,
+        )
+        .range()
     }
 
     pub fn sleep_duration(
@@ -40,12 +53,15 @@ impl Scheduler {
     ) -> Result<std::time::Duration, OutOfRangeError> {
         let next_reward_period = self.next_reward_period();
 
-        let duration = if self.reward_period.end + self.reward_offset > now {
-            self.reward_period.end + self.reward_offset - now
-        } else if next_reward_period.end + self.reward_offset <= now {
+        let settled_at = self.settled_at(&self.reward_period);
+        let next_settled_at = self.settled_at(&next_reward_period);
+
+        let duration = if settled_at > now {
+            settled_at - now
+        } else if next_settled_at <= now {
             Duration::zero()
         } else {
-            (next_reward_period.end + self.reward_offset) - now
+            next_settled_at - now
         };
 
         duration.to_std().map_err(|_| OutOfRangeError)
@@ -139,4 +155,29 @@ mod tests {
                 .expect("failed sleep duration check")
         );
     }
+
+    #[test]
+    fn reward_once_the_offset_past_the_period_elapsed() {
+        let scheduler = Scheduler::new(
+            reward_period_length(),
+            dt(2022, 12, 1, 0, 0, 0),
+            dt(2022, 12, 2, 0, 0, 0),
+            Duration::minutes(30),
+        );
+
+        let settled_at = dt(2022, 12, 2, 0, 30, 0);
+        assert!(!scheduler.should_reward(settled_at - Duration::milliseconds(1)));
+        assert!(scheduler.should_reward(settled_at));
+        assert_eq!(
+            dt(2022, 12, 2, 0, 0, 0)..dt(2022, 12, 3, 0, 0, 0),
+            scheduler.next_reward_period()
+        );
+        // Past the offset of the next period the reward is overdue
+        assert_eq!(
+            std::time::Duration::ZERO,
+            scheduler
+                .sleep_duration(dt(2022, 12, 3, 0, 30, 0))
+                .expect("failed sleep duration check")
+        );
+    }
 }
@@ -0,0 +1,200 @@
+//! Exact allocation of reward pools in whole bones.
+//!
+//! Rewards are computed as decimal shares of a pool but paid out in bones.
+//! Truncating every share on its own loses up to a bone per recipient, so
+//! pools are split with the largest remainder method instead: every
+//! recipient gets the truncated amount of its exact share, and the bones
+//! left over go one each to the recipients with the largest truncated
+//! fractions. The amounts always add up to the pool.
+
+use rust_decimal::prelude::*;
+
+/// Whole bones of an amount, zero for negative amounts
+pub fn to_bones(amount: Decimal) -> u64 {
+    amount
+        .max(Decimal::ZERO)
+        .trunc()
+        .to_u64()
+        .unwrap_or(u64::MAX)
+}
+
+struct Allocation<K> {
+    key: K,
+    eligible: bool,
+    amount: u64,
+    remainder: Decimal,
+}
+
+/// Split `total` bones between the keys proportionally to their shares,
+/// returning the amounts in the order of the shares.
+///
+/// Keys with a share of zero or less get nothing. Equal remainders are
+/// broken in favour of the smallest key, so the result does not depend on
+/// the order of the shares. Unless no key has a positive share the amounts
+/// add up to exactly `total`.
+pub fn allocate<K: Ord>(
+    total: u64,
+    shares: impl IntoIterator<Item = (K, Decimal)>,
+) -> Vec<(K, u64)> {
+    let shares: Vec<(K, Decimal)> = shares
+        .into_iter()
+        .map(|(key, share)| (key, share.max(Decimal::ZERO)))
+        .collect();
+    let total_shares = shares
+        .iter()
+        .fold(Decimal::ZERO, |sum, (_, share)| sum + share);
+    if total_shares.is_zero() {
+        return shares.into_iter().map(|(key, _)| (key, 0)).collect();
+    }
+
+    let pool = Decimal::from(total);
+    let mut allocations: Vec<Allocation<K>> = shares
+        .into_iter()
+        .map(|(key, share)| {
+            let exact = pool * (share / total_shares);
+            let amount = to_bones(exact).min(total);
+            Allocation {
+                key,
+                eligible: share > Decimal::ZERO,
+                amount,
+                remainder: exact - Decimal::from(amount),
+            }
+        })
+        .collect();
+
+    // Indices of the keys that can receive the left over bones, largest
+    // remainder first
+    let mut order: Vec<usize> = (0..allocations.len())
+        .filter(|i| allocations[*i].eligible)
+        .collect();
+    order.sort_by(|a, b| {
+        let (a, b) = (&allocations[*a], &allocations[*b]);
+        b.remainder
+            .cmp(&a.remainder)
+            .then_with(|| a.key.cmp(&b.key))
+    });
+
+    let allocated: u128 = allocations
+        .iter()
+        .map(|allocation| allocation.amount as u128)
+        .sum();
+    let total = total as u128;
+    if allocated <= total {
+        // Fewer bones are left over than there are eligible keys, except
+        // for the rounding of extreme shares which is what the cycle is for
+        for i in order.iter().cycle().take((total - allocated) as usize) {
+            allocations[*i].amount += 1;
+        }
+    } else {
+        // Rounding the exact shares up can only ever overshoot by a bone or
+        // so, which is taken back from the smallest remainders
+        let mut excess = allocated - total;
+        for i in order.iter().rev().cycle() {
+            if excess == 0 {
+                break;
+            }
+            if allocations[*i].amount > 0 {
+                allocations[*i].amount -= 1;
+                excess -= 1;
+            }
+        }
+    }
+
+    allocations
+        .into_iter()
+        .map(|allocation| (allocation.key, allocation.amount))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn left_over_bones_go_to_the_largest_remainders() {
+        assert_eq!(
+            allocate(100, [("a", dec!(1)), ("b", dec!(1)), ("c", dec!(1))]),
+            vec![("a", 34), ("b", 33), ("c", 33)]
+        );
+        assert_eq!(
+            allocate(10, [("a", dec!(1.5)), ("b", dec!(2.5)), ("c", dec!(3))]),
+            vec![("a", 2), ("b", 4), ("c", 4)]
+        );
+    }
+
+    #[test]
+    fn ties_do_not_depend_on_the_order_of_the_shares() {
+        assert_eq!(
+            allocate(2, [("c", dec!(1)), ("b", dec!(1)), ("a", dec!(1))]),
+            vec![("c", 0), ("b", 1), ("a", 1)]
+        );
+    }
+
+    #[test]
+    fn non_positive_shares_get_nothing() {
+        assert_eq!(
+            allocate(7, [("a", dec!(-1)), ("b", dec!(0)), ("c", dec!(2))]),
+            vec![("a", 0), ("b", 0), ("c", 7)]
+        );
+        assert_eq!(
+            allocate(7, [("a", dec!(-1)), ("b", dec!(0))]),
+            vec![("a", 0), ("b", 0)]
+        );
+        assert!(allocate::<u8>(7, []).is_empty());
+    }
+
+    #[test]
+    fn to_bones_truncates() {
+        assert_eq!(to_bones(dec!(1.999)), 1);
+        assert_eq!(to_bones(dec!(-1.5)), 0);
+        assert_eq!(to_bones(dec!(100_000_000_000_000_000_000)), u64::MAX);
+    }
+
+    fn shares() -> impl Strategy<Value = Vec<Decimal>> {
+        prop::collection::vec(
+            (any::<u32>(), 0_u32..16)
+                .prop_map(|(mantissa, scale)| Decimal::new(mantissa as i64, scale)),
+            1..200,
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn allocations_add_up_to_the_total(total in any::<u64>(), shares in shares()) {
+            prop_assume!(shares.iter().any(|share| *share > Decimal::ZERO));
+            let allocations = allocate(total, shares.iter().copied().enumerate());
+            let allocated: u128 = allocations.iter().map(|(_, amount)| *amount as u128).sum();
+            prop_assert_eq!(allocated, total as u128);
+        }
+
+        #[test]
+        fn allocations_are_within_a_bone_of_the_exact_share(
+            total in 0_u64..1_000_000_000_000_000,
+            shares in shares(),
+        ) {
+            let total_shares: Decimal = shares.iter().sum();
+            prop_assume!(total_shares > Decimal::ZERO);
+            for (i, amount) in allocate(total, shares.iter().copied().enumerate()) {
+                let exact = Decimal::from(total) * (shares[i] / total_shares);
+                prop_assert!((Decimal::from(amount) - exact).abs() < Decimal::ONE);
+                if shares[i].is_zero() {
+                    prop_assert_eq!(amount, 0);
+                }
+            }
+        }
+
+        #[test]
+        fn allocations_do_not_depend_on_the_order_of_the_shares(
+            total in any::<u64>(),
+            shares in shares(),
+        ) {
+            let mut forward = allocate(total, shares.iter().copied().enumerate());
+            let mut reversed = allocate(total, shares.iter().copied().enumerate().rev());
+            forward.sort();
+            reversed.sort();
+            prop_assert_eq!(forward, reversed);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{SolanaRpc, SolanaRpcError};
-use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use solana_sdk::pubkey::Pubkey;
 use std::{sync::Arc, time::Duration};
 
 // Check balance every 12 hours
@@ -14,13 +14,10 @@ pub async fn start(
     Ok(match solana {
         None => Box::pin(async move { Ok(()) }),
         Some(rpc_client) => {
-            let Ok(keypair) = Keypair::from_bytes(&rpc_client.keypair) else {
-                tracing::error!("sol monitor: keypair failed to deserialize");
-                return Err(SolanaRpcError::InvalidKeypair)
-            };
+            let service_pubkey = rpc_client.signer.pubkey();
             let app_metric_name = format!("{app_account}-sol-balance");
             let handle = tokio::spawn(async move {
-                run(app_metric_name, rpc_client, keypair.pubkey(), shutdown).await
+                run(app_metric_name, rpc_client, service_pubkey, shutdown).await
             });
             Box::pin(handle)
         }
@@ -0,0 +1,75 @@
+//! Signer of the burn transactions, either the burn keypair file or an
+//! [`oracle_signer::Signer`] with an ed25519 key, like one in AWS KMS.
+
+use crate::{Settings, SolanaRpcError};
+use helium_crypto::KeyType;
+use solana_sdk::{
+    message::Message,
+    pubkey::Pubkey,
+    signature::{read_keypair, Keypair, Signature},
+    signer::Signer,
+    transaction::Transaction,
+};
+use std::sync::Arc;
+
+pub enum BurnSigner {
+    Keypair([u8; 64]),
+    Signer {
+        signer: Arc<dyn oracle_signer::Signer>,
+        pubkey: Pubkey,
+    },
+}
+
+impl BurnSigner {
+    /// The configured signer after checking that it signs for its public
+    /// key, the burn keypair file otherwise
+    pub async fn from_settings(settings: &Settings) -> Result<Self, SolanaRpcError> {
+        let Some(signer_settings) = &settings.signer else {
+            let keypair = settings
+                .burn_keypair
+                .resolve_keypair()
+                .ok()
+                .and_then(|data| read_keypair(&mut &data[..]).ok())
+                .ok_or(SolanaRpcError::FailedToReadKeypairError)?;
+            return Ok(Self::Keypair(keypair.to_bytes()));
+        };
+        let signer = oracle_signer::from_settings(signer_settings)?;
+        let public_key = signer.public_key();
+        if !matches!(public_key.key_type(), KeyType::Ed25519) {
+            return Err(SolanaRpcError::InvalidKeypair);
+        }
+        // Helium keys are prefixed with their key type
+        let pubkey = Pubkey::try_from(&public_key.to_vec()[1..])
+            .map_err(|_| SolanaRpcError::InvalidKeypair)?;
+        oracle_signer::check(signer.as_ref()).await?;
+        Ok(Self::Signer { signer, pubkey })
+    }
+
+    pub fn pubkey(&self) -> Pubkey {
+        match self {
+            Self::Keypair(keypair) => Keypair::from_bytes(keypair).unwrap().pubkey(),
+            Self::Signer { pubkey, .. } => *pubkey,
+        }
+    }
+
+    /// Transaction of the message, which must have this signer as its only
+    /// required signer
+    pub async fn sign(&self, message: Message) -> Result<Transaction, SolanaRpcError> {
+        match self {
+            Self::Keypair(keypair) => {
+                let keypair = Keypair::from_bytes(keypair).unwrap();
+                let blockhash = message.recent_blockhash;
+                Ok(Transaction::new(&[&keypair], message, blockhash))
+            }
+            Self::Signer { signer, .. } => {
+                let signature = signer.sign(&message.serialize()).await?;
+                let signature = Signature::try_from(signature.as_slice())
+                    .map_err(|_| SolanaRpcError::InvalidSignature)?;
+                Ok(Transaction {
+                    signatures: vec![signature],
+                    message,
+                })
+            }
+        }
+    }
+}
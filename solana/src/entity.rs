@@ -0,0 +1,424 @@
+//! Hotspot metadata read directly from the helium entity manager accounts on
+//! chain, for services that should not depend on a metadata indexer.
+//!
+//! A hotspot is known when the key to asset account of its entity key exists
+//! and the asset has an info account for the configured sub dao. Lookups are
+//! cached for `cache_ttl` seconds, so accounts are only fetched the first time
+//! a hotspot is seen and again once the cached entry expired.
+
+use anchor_lang::{AccountDeserialize, AnchorDeserialize};
+use helium_crypto::PublicKeyBinary;
+use helium_sub_daos::SubDaoV0;
+use poc_metrics::error_code::{ErrorCategory, ErrorCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    client_error::ClientError,
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    pubkey,
+    pubkey::{ParsePubkeyError, Pubkey},
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+pub const ENTITY_MANAGER_ID: Pubkey = pubkey!("hemjuPXBpNvggtaUnN1MwT3wrdhttKEfosTcc2P9Pg8");
+
+/// Most cached lookups, expired and then the oldest entries are dropped
+/// beyond it
+const MAX_CACHED: usize = 100_000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum EntityError {
+    #[error("Solana rpc error: {0}")]
+    RpcClientError(#[from] ClientError),
+    #[error("Anchor error: {0}")]
+    AnchorError(#[from] anchor_lang::error::Error),
+    #[error("Parse pubkey error: {0}")]
+    ParsePubkeyError(#[from] ParsePubkeyError),
+    #[error("Invalid {0} account")]
+    InvalidAccount(&'static str),
+}
+
+impl ErrorCode for EntityError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::RpcClientError(_) => ErrorCategory::Network,
+            Self::AnchorError(_) | Self::InvalidAccount(_) => ErrorCategory::Chain,
+            Self::ParsePubkeyError(_) => ErrorCategory::Decode,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::RpcClientError(_) => "solana_rpc",
+            Self::AnchorError(_) => "anchor",
+            Self::ParsePubkeyError(_) => "pubkey",
+            Self::InvalidAccount(_) => "invalid_account",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Settings {
+    /// Solana RPC. This may contain a secret
+    pub rpc_url: String,
+    /// Mint of the sub dao token the hotspots are rewarded in
+    pub dnt_mint: String,
+    /// Symbol of the rewardable entity config of the sub dao, "MOBILE" or
+    /// "IOT". Default "MOBILE"
+    #[serde(default = "default_symbol")]
+    pub symbol: String,
+    /// Seconds hotspot lookups are cached for. Default 600
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: u64,
+}
+
+fn default_symbol() -> String {
+    "MOBILE".to_string()
+}
+
+fn default_cache_ttl() -> u64 {
+    600
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotspotInfo {
+    pub asset: Pubkey,
+    /// Asserted h3 location
+    pub location: Option<u64>,
+}
+
+pub struct EntityReader {
+    provider: RpcClient,
+    dao: Pubkey,
+    rewardable_entity_config: Pubkey,
+    info_account: &'static str,
+    cache_ttl: Duration,
+    cache: Mutex<HashMap<PublicKeyBinary, (Instant, Option<HotspotInfo>)>>,
+}
+
+impl EntityReader {
+    pub async fn new(settings: &Settings) -> Result<Self, EntityError> {
+        let dnt_mint: Pubkey = settings.dnt_mint.parse()?;
+        let provider =
+            RpcClient::new_with_commitment(settings.rpc_url.clone(), CommitmentConfig::finalized());
+        let (sub_dao, _) = Pubkey::find_program_address(
+            &["sub_dao".as_bytes(), dnt_mint.as_ref()],
+            &helium_sub_daos::ID,
+        );
+        let dao = {
+            let account_data = provider.get_account_data(&sub_dao).await?;
+            let mut account_data = account_data.as_ref();
+            SubDaoV0::try_deserialize(&mut account_data)?.dao
+        };
+        let (rewardable_entity_config, _) = Pubkey::find_program_address(
+            &[
+                "rewardable_entity_config".as_bytes(),
+                sub_dao.as_ref(),
+                settings.symbol.as_bytes(),
+            ],
+            &ENTITY_MANAGER_ID,
+        );
+        Ok(Self {
+            provider,
+            dao,
+            rewardable_entity_config,
+            info_account: info_account(&settings.symbol),
+            cache_ttl: Duration::from_secs(settings.cache_ttl),
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The hotspot with the given entity key, none when it is not onboarded
+    /// to the sub dao
+    pub async fn hotspot_info(
+        &self,
+        entity_key: &PublicKeyBinary,
+    ) -> Result<Option<HotspotInfo>, EntityError> {
+        if let Some((fetched, info)) = self.cache.lock().await.get(entity_key) {
+            if fetched.elapsed() < self.cache_ttl {
+                return Ok(*info);
+            }
+        }
+        let info = self.fetch_hotspot_info(entity_key).await?;
+
+        cache_insert(
+            &mut *self.cache.lock().await,
+            entity_key.clone(),
+            info,
+            Instant::now(),
+            self.cache_ttl,
+            MAX_CACHED,
+        );
+        Ok(info)
+    }
+
+    async fn fetch_hotspot_info(
+        &self,
+        entity_key: &PublicKeyBinary,
+    ) -> Result<Option<HotspotInfo>, EntityError> {
+        let key_to_asset = key_to_asset(&self.dao, entity_key);
+        let Some(account) = self.account_data(&key_to_asset).await? else {
+            return Ok(None);
+        };
+        let asset = decode_key_to_asset(&account)?.1;
+
+        let info = info_key(self.info_account, &self.rewardable_entity_config, &asset);
+        let Some(account) = self.account_data(&info).await? else {
+            return Ok(None);
+        };
+        decode_hotspot_info(self.info_account, &account).map(Some)
+    }
+
+    async fn account_data(&self, key: &Pubkey) -> Result<Option<Vec<u8>>, EntityError> {
+        Ok(self
+            .provider
+            .get_account_with_commitment(key, CommitmentConfig::finalized())
+            .await?
+            .value
+            .map(|account| account.data))
+    }
+
+    /// Every hotspot onboarded to the sub dao with its entity key. This
+    /// fetches all key to asset and info accounts, so it is expensive and
+    /// not cached.
+    pub async fn all_hotspots(&self) -> Result<Vec<(PublicKeyBinary, HotspotInfo)>, EntityError> {
+        let mut infos = HashMap::new();
+        for (_, account) in self
+            .program_accounts(discriminator(&account_name(self.info_account)))
+            .await?
+        {
+            let info = decode_hotspot_info(self.info_account, &account.data)?;
+            infos.insert(info.asset, info);
+        }
+
+        let mut hotspots = Vec::with_capacity(infos.len());
+        for (_, account) in self.program_accounts(discriminator("KeyToAssetV0")).await? {
+            let (dao, asset, entity_key) = decode_key_to_asset(&account.data)?;
+            if dao != self.dao {
+                continue;
+            }
+            if let Some(info) = infos.get(&asset) {
+                hotspots.push((PublicKeyBinary::from(entity_key), *info));
+            }
+        }
+        Ok(hotspots)
+    }
+
+    async fn program_accounts(
+        &self,
+        discriminator: [u8; 8],
+    ) -> Result<Vec<(Pubkey, solana_sdk::account::Account)>, EntityError> {
+        Ok(self
+            .provider
+            .get_program_accounts_with_config(
+                &ENTITY_MANAGER_ID,
+                RpcProgramAccountsConfig {
+                    filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                        0,
+                        &discriminator,
+                    ))]),
+                    account_config: RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        commitment: Some(CommitmentConfig::finalized()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .await?)
+    }
+}
+
+/// Name of the info account seed and type prefix of the sub dao, "mobile" or
+/// "iot"
+fn info_account(symbol: &str) -> &'static str {
+    if symbol.eq_ignore_ascii_case("iot") {
+        "iot"
+    } else {
+        "mobile"
+    }
+}
+
+fn account_name(info_account: &str) -> String {
+    match info_account {
+        "iot" => "IotHotspotInfoV0".to_string(),
+        _ => "MobileHotspotInfoV0".to_string(),
+    }
+}
+
+/// The anchor discriminator of an account type
+fn discriminator(account_name: &str) -> [u8; 8] {
+    let digest = Sha256::digest(format!("account:{account_name}"));
+    let mut discriminator = [0; 8];
+    discriminator.copy_from_slice(&digest[..8]);
+    discriminator
+}
+
+pub fn key_to_asset(dao: &Pubkey, entity_key: &PublicKeyBinary) -> Pubkey {
+    let entity_key: &[u8] = entity_key.as_ref();
+    let (key, _) = Pubkey::find_program_address(
+        &[
+            "key_to_asset".as_bytes(),
+            dao.as_ref(),
+            &Sha256::digest(entity_key),
+        ],
+        &ENTITY_MANAGER_ID,
+    );
+    key
+}
+
+fn info_key(info_account: &str, rewardable_entity_config: &Pubkey, asset: &Pubkey) -> Pubkey {
+    let seed = format!("{info_account}_info");
+    let (key, _) = Pubkey::find_program_address(
+        &[
+            seed.as_bytes(),
+            rewardable_entity_config.as_ref(),
+            asset.as_ref(),
+        ],
+        &ENTITY_MANAGER_ID,
+    );
+    key
+}
+
+/// The account data after its discriminator, if it is of the given type
+fn account_body<'a>(account_name: &str, data: &'a [u8]) -> Option<&'a [u8]> {
+    (data.len() >= 8 && data[..8] == discriminator(account_name)).then(|| &data[8..])
+}
+
+/// The dao, asset and entity key of a key to asset account
+fn decode_key_to_asset(data: &[u8]) -> Result<(Pubkey, Pubkey, Vec<u8>), EntityError> {
+    let invalid = || EntityError::InvalidAccount("key to asset");
+    let mut body = account_body("KeyToAssetV0", data).ok_or_else(invalid)?;
+    let dao = Pubkey::deserialize(&mut body).map_err(|_| invalid())?;
+    let asset = Pubkey::deserialize(&mut body).map_err(|_| invalid())?;
+    let entity_key = Vec::<u8>::deserialize(&mut body).map_err(|_| invalid())?;
+    Ok((dao, asset, entity_key))
+}
+
+fn decode_hotspot_info(info_account: &str, data: &[u8]) -> Result<HotspotInfo, EntityError> {
+    let invalid = || EntityError::InvalidAccount("hotspot info");
+    let mut body = account_body(&account_name(info_account), data).ok_or_else(invalid)?;
+    let asset = Pubkey::deserialize(&mut body).map_err(|_| invalid())?;
+    let _bump_seed = u8::deserialize(&mut body).map_err(|_| invalid())?;
+    let location = Option::<u64>::deserialize(&mut body).map_err(|_| invalid())?;
+    Ok(HotspotInfo { asset, location })
+}
+
+/// Cache a lookup, keeping at most `max_cached` entries by dropping the
+/// expired ones first and the oldest ones when that is not enough
+fn cache_insert<K, V>(
+    cache: &mut HashMap<K, (Instant, V)>,
+    key: K,
+    value: V,
+    now: Instant,
+    ttl: Duration,
+    max_cached: usize,
+) where
+    K: Clone + Eq + std::hash::Hash,
+{
+    if !cache.contains_key(&key) && cache.len() >= max_cached {
+        cache.retain(|_, (fetched, _)| now.saturating_duration_since(*fetched) < ttl);
+        if cache.len() >= max_cached {
+            let mut by_age: Vec<_> = cache
+                .iter()
+                .map(|(key, (fetched, _))| (*fetched, key.clone()))
+                .collect();
+            by_age.sort_unstable_by_key(|(fetched, _)| *fetched);
+            let excess = cache.len() + 1 - max_cached;
+            for (_, key) in by_age.into_iter().take(excess) {
+                cache.remove(&key);
+            }
+        }
+    }
+    cache.insert(key, (now, value));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::AnchorSerialize;
+
+    #[test]
+    fn cache_is_bounded() {
+        let ttl = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut cache = HashMap::new();
+        for key in 0..3 {
+            let at = start + Duration::from_secs(key);
+            cache_insert(&mut cache, key, (), at, ttl, 3);
+        }
+
+        // fresh entries are dropped oldest first
+        cache_insert(&mut cache, 3, (), start + Duration::from_secs(3), ttl, 3);
+        assert_eq!(cache.len(), 3);
+        assert!(!cache.contains_key(&0));
+
+        // expired entries are dropped before any fresh one
+        cache_insert(&mut cache, 4, (), start + Duration::from_secs(12), ttl, 3);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains_key(&3) && cache.contains_key(&4));
+
+        // refreshing a cached entry does not drop another
+        cache_insert(&mut cache, 5, (), start + Duration::from_secs(12), ttl, 3);
+        cache_insert(&mut cache, 5, (), start + Duration::from_secs(13), ttl, 3);
+        assert_eq!(cache.len(), 3);
+    }
+
+    fn account(account_name: &str, fields: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut data = discriminator(account_name).to_vec();
+        fields(&mut data);
+        data
+    }
+
+    #[test]
+    fn decodes_entity_manager_accounts() {
+        let dao = Pubkey::new_unique();
+        let asset = Pubkey::new_unique();
+        let key_to_asset = account("KeyToAssetV0", |data| {
+            dao.serialize(data).unwrap();
+            asset.serialize(data).unwrap();
+            vec![1u8, 2, 3].serialize(data).unwrap();
+            255u8.serialize(data).unwrap();
+        });
+        assert_eq!(
+            decode_key_to_asset(&key_to_asset).unwrap(),
+            (dao, asset, vec![1, 2, 3])
+        );
+
+        let info = account("MobileHotspotInfoV0", |data| {
+            asset.serialize(data).unwrap();
+            254u8.serialize(data).unwrap();
+            Some(631_711_281_856_284_671u64).serialize(data).unwrap();
+            true.serialize(data).unwrap();
+        });
+        assert_eq!(
+            decode_hotspot_info("mobile", &info).unwrap(),
+            HotspotInfo {
+                asset,
+                location: Some(631_711_281_856_284_671),
+            }
+        );
+    }
+
+    #[test]
+    fn accounts_of_another_type_are_rejected() {
+        let info = account("IotHotspotInfoV0", |data| {
+            Pubkey::new_unique().serialize(data).unwrap();
+            0u8.serialize(data).unwrap();
+            None::<u64>.serialize(data).unwrap();
+        });
+        assert!(decode_hotspot_info("mobile", &info).is_err());
+        assert!(decode_key_to_asset(&info).is_err());
+        assert!(decode_hotspot_info("iot", &info).is_ok());
+    }
+}
@@ -0,0 +1,30 @@
+//! Solana network disturbed by programmable faults. A partial burn goes
+//! through on chain but fails anyway, like a burn whose confirmation is lost.
+
+use crate::{ChainBurn, SolanaNetwork};
+use async_trait::async_trait;
+use fault_injection::{Faulty, FaultyError};
+use helium_crypto::PublicKeyBinary;
+
+#[async_trait]
+impl<S: SolanaNetwork> SolanaNetwork for Faulty<S> {
+    type Error = FaultyError<S::Error>;
+
+    async fn payer_balance(&self, payer: &PublicKeyBinary) -> Result<u64, Self::Error> {
+        self.faults.apply(self.inner.payer_balance(payer)).await
+    }
+
+    async fn burn_data_credits(
+        &self,
+        payer: &PublicKeyBinary,
+        amount: u64,
+    ) -> Result<Option<String>, Self::Error> {
+        self.faults
+            .apply(self.inner.burn_data_credits(payer, amount))
+            .await
+    }
+
+    async fn burn_history(&self, since: i64) -> Result<Vec<ChainBurn>, Self::Error> {
+        self.faults.apply(self.inner.burn_history(since)).await
+    }
+}
@@ -1,24 +1,35 @@
 pub mod balance_monitor;
+pub mod burn_signer;
+pub mod entity;
+#[cfg(feature = "test-utils")]
+pub mod faults;
+pub mod mock;
 
-use anchor_client::{RequestBuilder, RequestNamespace};
-use anchor_lang::AccountDeserialize;
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
 use async_trait::async_trait;
+use burn_signer::BurnSigner;
 use data_credits::{accounts, instruction};
 use helium_crypto::PublicKeyBinary;
 use helium_sub_daos::{DaoV0, SubDaoV0};
+use poc_metrics::error_code::{self, ErrorCategory, ErrorCode};
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
-use solana_client::{client_error::ClientError, nonblocking::rpc_client::RpcClient};
+use solana_client::{
+    client_error::ClientError, nonblocking::rpc_client::RpcClient,
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+};
 use solana_sdk::{
     commitment_config::CommitmentConfig,
+    compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
+    message::Message,
     program_pack::Pack,
     pubkey::{ParsePubkeyError, Pubkey},
-    signature::{read_keypair_file, Keypair},
-    signer::Signer,
-    transaction::Transaction,
+    signature::{ParseSignatureError, Signature},
 };
 use std::collections::HashMap;
 use std::convert::Infallible;
+use std::str::FromStr;
 use std::{
     sync::Arc,
     time::{SystemTime, SystemTimeError},
@@ -31,13 +42,34 @@ pub trait SolanaNetwork: Send + Sync + 'static {
 
     async fn payer_balance(&self, payer: &PublicKeyBinary) -> Result<u64, Self::Error>;
 
+    /// Burn the data credits of the payer, returning the signature of the
+    /// burn transaction if one was submitted
     async fn burn_data_credits(
         &self,
         payer: &PublicKeyBinary,
         amount: u64,
-    ) -> Result<(), Self::Error>;
+    ) -> Result<Option<String>, Self::Error>;
+
+    /// The transactions signed by the burn authority at or after the given
+    /// unix time, newest first. Networks without a history have none.
+    async fn burn_history(&self, _since: i64) -> Result<Vec<ChainBurn>, Self::Error> {
+        Ok(Vec::new())
+    }
 }
 
+/// A transaction signed by the burn authority
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainBurn {
+    pub signature: String,
+    /// Unix time of the block, if known
+    pub block_time: Option<i64>,
+    /// Whether the transaction failed on chain
+    pub failed: bool,
+}
+
+/// Signatures fetched per history request, the maximum the rpc allows
+const HISTORY_PAGE_SIZE: usize = 1000;
+
 #[derive(thiserror::Error, Debug)]
 pub enum SolanaRpcError {
     #[error("Solana rpc error: {0}")]
@@ -54,42 +86,156 @@ pub enum SolanaRpcError {
     SystemTimeError(#[from] SystemTimeError),
     #[error("Failed to read keypair file")]
     FailedToReadKeypairError,
+    #[error("Parse signature error: {0}")]
+    ParseSignatureError(#[from] ParseSignatureError),
+    #[error("Signer error: {0}")]
+    SignerError(#[from] oracle_signer::Error),
+    #[error("Signer returned an invalid transaction signature")]
+    InvalidSignature,
+}
+
+impl ErrorCode for SolanaRpcError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::RpcClientError(_) => ErrorCategory::Network,
+            Self::AnchorError(_) | Self::ProgramError(_) => ErrorCategory::Chain,
+            Self::ParsePubkeyError(_) | Self::ParseSignatureError(_) => ErrorCategory::Decode,
+            Self::InvalidKeypair | Self::FailedToReadKeypairError => ErrorCategory::Config,
+            Self::SystemTimeError(_) => ErrorCategory::Internal,
+            Self::SignerError(err) => err.category(),
+            Self::InvalidSignature => ErrorCategory::Crypto,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::RpcClientError(_) => "solana_rpc",
+            Self::AnchorError(_) => "anchor",
+            Self::ProgramError(_) => "program",
+            Self::ParsePubkeyError(_) => "pubkey",
+            Self::InvalidKeypair => "burn_authority_mismatch",
+            Self::SystemTimeError(_) => "system_time",
+            Self::FailedToReadKeypairError => "keypair_file",
+            Self::ParseSignatureError(_) => "signature",
+            Self::SignerError(err) => err.code(),
+            Self::InvalidSignature => "invalid_transaction_signature",
+        }
+    }
+}
+
+/// Label the errors of this crate and of the signers in metrics and logs
+pub fn register_error_codes() {
+    oracle_signer::register_error_codes();
+    error_code::register::<SolanaRpcError>();
+    error_code::register::<SolanaBackendError>();
+    error_code::register::<mock::MockSolanaError>();
+    error_code::register::<entity::EntityError>();
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     rpc_url: String,
-    cluster: String,
-    burn_keypair: String,
+    /// Path of the burn keypair file or a `file:` or `env:` secret
+    /// reference. Not needed when a signer is set
+    #[serde(default)]
+    burn_keypair: oracle_settings::Secret,
+    /// Signer of the burn transactions instead of the burn keypair, must hold
+    /// an ed25519 key
+    signer: Option<oracle_signer::Settings>,
     dc_mint: String,
     dnt_mint: String,
+    /// Commitment burn transactions are confirmed at and balances are read
+    /// at, confirmed or finalized. Default finalized
+    #[serde(default)]
+    commitment: Commitment,
+    /// Priority fee of burn transactions in micro lamports per compute unit.
+    /// No priority fee when not set
+    compute_unit_price: Option<u64>,
+    /// Compute units requested by burn transactions. The runtime default of
+    /// 200,000 when not set
+    compute_unit_limit: Option<u32>,
+}
+
+/// Commitment of burn transactions. Processed is left out on purpose, a
+/// processed transaction can still be dropped with its fork and the burn
+/// would then be recorded without being paid
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Commitment {
+    Confirmed,
+    #[default]
+    Finalized,
+}
+
+impl From<Commitment> for CommitmentConfig {
+    fn from(commitment: Commitment) -> Self {
+        match commitment {
+            Commitment::Confirmed => Self::confirmed(),
+            Commitment::Finalized => Self::finalized(),
+        }
+    }
+}
+
+/// Compute units of a transaction without a compute unit limit instruction
+const DEFAULT_COMPUTE_UNIT_LIMIT: u32 = 200_000;
+
+/// The compute budget of burn transactions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ComputeBudget {
+    pub unit_price: Option<u64>,
+    pub unit_limit: Option<u32>,
+}
+
+impl ComputeBudget {
+    /// The compute budget instructions to put before the instructions of a
+    /// transaction
+    pub fn instructions(&self) -> Vec<Instruction> {
+        let limit = self
+            .unit_limit
+            .map(ComputeBudgetInstruction::set_compute_unit_limit);
+        let price = self
+            .unit_price
+            .map(ComputeBudgetInstruction::set_compute_unit_price);
+        limit.into_iter().chain(price).collect()
+    }
+
+    /// Lamports paid in priority fees by a transaction of this budget
+    pub fn priority_fee(&self) -> u64 {
+        let Some(unit_price) = self.unit_price else {
+            return 0;
+        };
+        let units = self.unit_limit.unwrap_or(DEFAULT_COMPUTE_UNIT_LIMIT) as u128;
+        // The price is in micro lamports, rounded up to whole lamports
+        ((unit_price as u128 * units + 999_999) / 1_000_000) as u64
+    }
 }
 
 pub struct SolanaRpc {
     provider: RpcClient,
     program_cache: BurnProgramCache,
-    cluster: String,
-    keypair: [u8; 64],
+    signer: BurnSigner,
+    compute_budget: ComputeBudget,
 }
 
 impl SolanaRpc {
     pub async fn new(settings: &Settings) -> Result<Arc<Self>, SolanaRpcError> {
         let dc_mint = settings.dc_mint.parse()?;
         let dnt_mint = settings.dnt_mint.parse()?;
-        let Ok(keypair) = read_keypair_file(&settings.burn_keypair) else {
-            return Err(SolanaRpcError::FailedToReadKeypairError);
-        };
+        let signer = BurnSigner::from_settings(settings).await?;
         let provider =
-            RpcClient::new_with_commitment(settings.rpc_url.clone(), CommitmentConfig::finalized());
+            RpcClient::new_with_commitment(settings.rpc_url.clone(), settings.commitment.into());
         let program_cache = BurnProgramCache::new(&provider, dc_mint, dnt_mint).await?;
-        if program_cache.dc_burn_authority != keypair.pubkey() {
+        if program_cache.dc_burn_authority != signer.pubkey() {
             return Err(SolanaRpcError::InvalidKeypair);
         }
         Ok(Arc::new(Self {
-            cluster: settings.cluster.clone(),
             provider,
             program_cache,
-            keypair: keypair.to_bytes(),
+            signer,
+            compute_budget: ComputeBudget {
+                unit_price: settings.compute_unit_price,
+                unit_limit: settings.compute_unit_limit,
+            },
         }))
     }
 }
@@ -117,7 +263,7 @@ impl SolanaNetwork for SolanaRpc {
         &self,
         payer: &PublicKeyBinary,
         amount: u64,
-    ) -> Result<(), Self::Error> {
+    ) -> Result<Option<String>, Self::Error> {
         // Fetch the sub dao epoch info:
         const EPOCH_LENGTH: u64 = 60 * 60 * 24;
         let epoch = SystemTime::now()
@@ -140,61 +286,93 @@ impl SolanaNetwork for SolanaRpc {
             &data_credits::ID,
         );
 
-        let instructions = {
-            let request = RequestBuilder::from(
-                data_credits::id(),
-                &self.cluster,
-                std::rc::Rc::new(Keypair::from_bytes(&self.keypair).unwrap()),
-                Some(CommitmentConfig::confirmed()),
-                RequestNamespace::Global,
-            );
-
-            let accounts = accounts::BurnDelegatedDataCreditsV0 {
-                sub_dao_epoch_info,
-                dao: self.program_cache.dao,
-                sub_dao: self.program_cache.sub_dao,
-                account_payer: self.program_cache.account_payer,
-                data_credits: self.program_cache.data_credits,
-                delegated_data_credits: delegated_data_credits(&self.program_cache.sub_dao, payer),
-                token_program: spl_token::id(),
-                helium_sub_daos_program: helium_sub_daos::id(),
-                system_program: solana_program::system_program::id(),
-                dc_burn_authority: self.program_cache.dc_burn_authority,
-                dc_mint: self.program_cache.dc_mint,
-                escrow_account,
-                registrar: self.program_cache.registrar,
-            };
-            let args = instruction::BurnDelegatedDataCreditsV0 {
-                args: data_credits::BurnDelegatedDataCreditsArgsV0 { amount },
-            };
-
-            // As far as I can tell, the instructions function does not actually have any
-            // error paths.
-            request
-                .accounts(accounts)
-                .args(args)
-                .instructions()
-                .unwrap()
+        let accounts = accounts::BurnDelegatedDataCreditsV0 {
+            sub_dao_epoch_info,
+            dao: self.program_cache.dao,
+            sub_dao: self.program_cache.sub_dao,
+            account_payer: self.program_cache.account_payer,
+            data_credits: self.program_cache.data_credits,
+            delegated_data_credits: delegated_data_credits(&self.program_cache.sub_dao, payer),
+            token_program: spl_token::id(),
+            helium_sub_daos_program: helium_sub_daos::id(),
+            system_program: solana_program::system_program::id(),
+            dc_burn_authority: self.program_cache.dc_burn_authority,
+            dc_mint: self.program_cache.dc_mint,
+            escrow_account,
+            registrar: self.program_cache.registrar,
+        };
+        let args = instruction::BurnDelegatedDataCreditsV0 {
+            args: data_credits::BurnDelegatedDataCreditsArgsV0 { amount },
+        };
+        let instruction = Instruction {
+            program_id: data_credits::id(),
+            accounts: accounts.to_account_metas(None),
+            data: args.data(),
         };
 
-        let blockhash = self.provider.get_latest_blockhash().await?;
-        let signer = Keypair::from_bytes(&self.keypair).unwrap();
+        let mut instructions = self.compute_budget.instructions();
+        instructions.push(instruction);
 
-        let tx = Transaction::new_signed_with_payer(
-            &instructions,
-            Some(&signer.pubkey()),
-            &[&signer],
-            blockhash,
-        );
+        let blockhash = self.provider.get_latest_blockhash().await?;
+        let message =
+            Message::new_with_blockhash(&instructions, Some(&self.signer.pubkey()), &blockhash);
+        let fee = self.provider.get_fee_for_message(&message).await;
+        let tx = self.signer.sign(message).await?;
 
         let signature = self.provider.send_and_confirm_transaction(&tx).await?;
 
+        let priority_fee = self.compute_budget.priority_fee();
+        metrics::increment_counter!("solana_burn_transactions");
+        metrics::counter!("solana_burn_priority_fee_lamports", priority_fee);
+        match fee {
+            Ok(fee) => metrics::counter!("solana_burn_fee_lamports", fee),
+            Err(err) => tracing::warn!(transaction = %signature, "failed to fetch burn fee: {err}"),
+        }
         tracing::info!(
             transaction = %signature,
+            priority_fee,
             "Successfully burned data credits",
         );
 
-        Ok(())
+        Ok(Some(signature.to_string()))
+    }
+
+    async fn burn_history(&self, since: i64) -> Result<Vec<ChainBurn>, Self::Error> {
+        let mut history = Vec::new();
+        let mut before = None;
+        loop {
+            let page = self
+                .provider
+                .get_signatures_for_address_with_config(
+                    &self.program_cache.dc_burn_authority,
+                    GetConfirmedSignaturesForAddress2Config {
+                        before,
+                        until: None,
+                        limit: Some(HISTORY_PAGE_SIZE),
+                        commitment: Some(CommitmentConfig::finalized()),
+                    },
+                )
+                .await?;
+            let page_len = page.len();
+            for status in page {
+                // Signatures are returned newest first
+                if status
+                    .block_time
+                    .is_some_and(|block_time| block_time < since)
+                {
+                    return Ok(history);
+                }
+                before = Some(Signature::from_str(&status.signature)?);
+                history.push(ChainBurn {
+                    signature: status.signature,
+                    block_time: status.block_time,
+                    failed: status.err.is_some(),
+                });
+            }
+            if page_len < HISTORY_PAGE_SIZE {
+                return Ok(history);
+            }
+        }
     }
 }
 
@@ -264,15 +442,119 @@ impl SolanaNetwork for Option<Arc<SolanaRpc>> {
         &self,
         payer: &PublicKeyBinary,
         amount: u64,
-    ) -> Result<(), Self::Error> {
+    ) -> Result<Option<String>, Self::Error> {
         if let Some(ref rpc) = self {
             rpc.burn_data_credits(payer, amount).await
         } else {
-            Ok(())
+            Ok(None)
+        }
+    }
+
+    async fn burn_history(&self, since: i64) -> Result<Vec<ChainBurn>, Self::Error> {
+        if let Some(ref rpc) = self {
+            rpc.burn_history(since).await
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// The solana network a service burns on, selected by its settings
+#[derive(Clone)]
+pub enum SolanaBackend {
+    /// Burns nothing, every payer has a fixed balance
+    Disabled,
+    Rpc(Arc<SolanaRpc>),
+    /// Simulated chain of staging environments
+    Mock(mock::MockSolanaNetwork),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SolanaBackendError {
+    #[error(transparent)]
+    Rpc(#[from] SolanaRpcError),
+    #[error(transparent)]
+    Mock(#[from] mock::MockSolanaError),
+}
+
+impl ErrorCode for SolanaBackendError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Rpc(err) => err.category(),
+            Self::Mock(err) => err.category(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Rpc(err) => err.code(),
+            Self::Mock(err) => err.code(),
+        }
+    }
+}
+
+impl SolanaBackend {
+    /// The mock chain when set, otherwise the rpc of the solana settings if
+    /// the integration is enabled
+    pub async fn new(
+        enable_solana_integration: bool,
+        settings: Option<&Settings>,
+        mock: Option<&mock::Settings>,
+    ) -> Result<Self, SolanaRpcError> {
+        if let Some(mock) = mock {
+            tracing::warn!("burning data credits on a mock solana chain");
+            return Ok(Self::Mock(mock::MockSolanaNetwork::from_settings(mock)));
+        }
+        match settings {
+            Some(settings) if enable_solana_integration => {
+                Ok(Self::Rpc(SolanaRpc::new(settings).await?))
+            }
+            _ => Ok(Self::Disabled),
+        }
+    }
+
+    /// The rpc client, if burning on chain
+    pub fn rpc(&self) -> Option<Arc<SolanaRpc>> {
+        match self {
+            Self::Rpc(rpc) => Some(rpc.clone()),
+            _ => None,
         }
     }
 }
 
+#[async_trait]
+impl SolanaNetwork for SolanaBackend {
+    type Error = SolanaBackendError;
+
+    async fn payer_balance(&self, payer: &PublicKeyBinary) -> Result<u64, Self::Error> {
+        Ok(match self {
+            Self::Disabled => FIXED_BALANCE,
+            Self::Rpc(rpc) => rpc.payer_balance(payer).await?,
+            Self::Mock(mock) => mock.payer_balance(payer).await?,
+        })
+    }
+
+    async fn burn_data_credits(
+        &self,
+        payer: &PublicKeyBinary,
+        amount: u64,
+    ) -> Result<Option<String>, Self::Error> {
+        Ok(match self {
+            Self::Disabled => None,
+            Self::Rpc(rpc) => rpc.burn_data_credits(payer, amount).await?,
+            Self::Mock(mock) => mock.burn_data_credits(payer, amount).await?,
+        })
+    }
+
+    async fn burn_history(&self, since: i64) -> Result<Vec<ChainBurn>, Self::Error> {
+        Ok(match self {
+            Self::Disabled => Vec::new(),
+            Self::Rpc(rpc) => rpc.burn_history(since).await?,
+            Self::Mock(mock) => mock.burn_history(since).await?,
+        })
+    }
+}
+
 #[async_trait]
 impl SolanaNetwork for Arc<Mutex<HashMap<PublicKeyBinary, u64>>> {
     type Error = Infallible;
@@ -285,9 +567,9 @@ impl SolanaNetwork for Arc<Mutex<HashMap<PublicKeyBinary, u64>>> {
         &self,
         payer: &PublicKeyBinary,
         amount: u64,
-    ) -> Result<(), Self::Error> {
+    ) -> Result<Option<String>, Self::Error> {
         *self.lock().await.get_mut(payer).unwrap() -= amount;
-        Ok(())
+        Ok(None)
     }
 }
 
@@ -306,3 +588,54 @@ pub fn delegated_data_credits(sub_dao: &Pubkey, payer: &PublicKeyBinary) -> Pubk
     );
     ddc_key
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority_fees_follow_the_compute_budget() {
+        assert!(ComputeBudget::default().instructions().is_empty());
+        assert_eq!(ComputeBudget::default().priority_fee(), 0);
+
+        let budget = ComputeBudget {
+            unit_price: Some(1_500),
+            unit_limit: None,
+        };
+        assert_eq!(budget.instructions().len(), 1);
+        // 200,000 units at 1,500 micro lamports
+        assert_eq!(budget.priority_fee(), 300);
+
+        let budget = ComputeBudget {
+            unit_price: Some(1),
+            unit_limit: Some(50_000),
+        };
+        assert_eq!(
+            budget.instructions(),
+            vec![
+                ComputeBudgetInstruction::set_compute_unit_limit(50_000),
+                ComputeBudgetInstruction::set_compute_unit_price(1),
+            ]
+        );
+        // 0.05 lamports are rounded up
+        assert_eq!(budget.priority_fee(), 1);
+    }
+
+    #[test]
+    fn commitment_defaults_to_finalized() {
+        assert_eq!(
+            CommitmentConfig::from(Commitment::default()),
+            CommitmentConfig::finalized()
+        );
+    }
+
+    #[test]
+    fn commitment_must_be_confirmed_or_finalized() {
+        use serde::de::{value::Error, IntoDeserializer};
+        let commitment =
+            |value: &str| Commitment::deserialize(value.into_deserializer()).map_err(|_: Error| ());
+        assert_eq!(commitment("confirmed"), Ok(Commitment::Confirmed));
+        assert_eq!(commitment("finalized"), Ok(Commitment::Finalized));
+        assert!(commitment("processed").is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! In memory solana network for tests of the burners and balance caches, and
+//! the simulated chain of staging environments.
+//!
+//! Burns succeed instantly with a synthetic signature and show up in the
+//! burn history, so a staging pipeline runs the burner, the burn journal and
+//! the reconciliation against the chain without touching devnet or mainnet.
+//! Created from [`Settings`] every payer starts with the same balance,
+//! reduced by its burns, and a configurable share of the burns fails to
+//! exercise the retry paths.
+
+use crate::{ChainBurn, SolanaNetwork};
+use async_trait::async_trait;
+use helium_crypto::PublicKeyBinary;
+use poc_metrics::error_code::{ErrorCategory, ErrorCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use solana_sdk::signature::Signature;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Settings {
+    /// Share of the burns that fail, between 0 and 1. Default 0
+    #[serde(default)]
+    pub failure_rate: f64,
+    /// Data credit balance every payer starts with. Default 1_000_000_000
+    #[serde(default = "default_balance")]
+    pub balance: u64,
+}
+
+fn default_balance() -> u64 {
+    1_000_000_000
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            failure_rate: 0.0,
+            balance: default_balance(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if !(0.0..=1.0).contains(&self.failure_rate) {
+            problems.push(format!(
+                "mock solana failure_rate must be between 0 and 1, got {}",
+                self.failure_rate
+            ));
+        }
+        problems
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum MockSolanaError {
+    #[error("insufficient balance for {payer}: {balance} < {amount}")]
+    InsufficientBalance {
+        payer: PublicKeyBinary,
+        balance: u64,
+        amount: u64,
+    },
+    #[error("burn transaction failed")]
+    BurnFailed,
+}
+
+impl ErrorCode for MockSolanaError {
+    fn category(&self) -> ErrorCategory {
+        ErrorCategory::Chain
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::InsufficientBalance { .. } => "insufficient_balance",
+            Self::BurnFailed => "burn_failed",
+        }
+    }
+}
+
+/// Solana network keeping the payer balances in memory and recording every
+/// successful burn. Clones share the same state, so a test can hand a clone
+/// to the code under test and inspect the original afterwards.
+#[derive(Clone, Default)]
+pub struct MockSolanaNetwork {
+    balances: Arc<Mutex<HashMap<PublicKeyBinary, u64>>>,
+    burns: Arc<Mutex<Vec<(PublicKeyBinary, u64)>>>,
+    history: Arc<Mutex<Vec<ChainBurn>>>,
+    fail_burns: Arc<AtomicBool>,
+    /// Balance of payers without one of their own
+    initial_balance: u64,
+    failure_rate: f64,
+    /// Seed of the signatures and failures, so restarts don't repeat the
+    /// signatures of earlier burns
+    seed: u64,
+    attempts: Arc<AtomicU64>,
+}
+
+impl MockSolanaNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Simulated chain of a staging environment
+    pub fn from_settings(settings: &Settings) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |now| now.as_nanos() as u64);
+        Self::with_seed(settings, seed)
+    }
+
+    fn with_seed(settings: &Settings, seed: u64) -> Self {
+        Self {
+            initial_balance: settings.balance,
+            failure_rate: settings.failure_rate,
+            seed,
+            ..Default::default()
+        }
+    }
+
+    pub async fn with_balance(self, payer: PublicKeyBinary, balance: u64) -> Self {
+        self.balances.lock().await.insert(payer, balance);
+        self
+    }
+
+    /// Make every following burn fail until reset, the balances are left
+    /// untouched by failed burns
+    pub fn fail_burns(&self, fail: bool) {
+        self.fail_burns.store(fail, Ordering::SeqCst);
+    }
+
+    pub async fn balance(&self, payer: &PublicKeyBinary) -> u64 {
+        self.balances
+            .lock()
+            .await
+            .get(payer)
+            .copied()
+            .unwrap_or(self.initial_balance)
+    }
+
+    /// Successful burns in the order they were made
+    pub async fn burns(&self) -> Vec<(PublicKeyBinary, u64)> {
+        self.burns.lock().await.clone()
+    }
+
+    /// Add a transaction of the burn authority that was not made through
+    /// this network, like a burn by another service
+    pub async fn add_chain_burn(&self, burn: ChainBurn) {
+        self.history.lock().await.push(burn);
+    }
+
+    fn digest(&self, attempt: u64, domain: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(domain);
+        hasher.update(self.seed.to_le_bytes());
+        hasher.update(attempt.to_le_bytes());
+        hasher.finalize().into()
+    }
+
+    /// Whether the burn attempt fails, failing the configured share of all
+    /// attempts
+    fn fails(&self, attempt: u64) -> bool {
+        let digest = self.digest(attempt, b"failure");
+        let draw = u64::from_le_bytes(digest[..8].try_into().expect("8 bytes")) as f64;
+        draw / (u64::MAX as f64) < self.failure_rate
+    }
+
+    fn signature(&self, attempt: u64) -> String {
+        let mut bytes = [0; 64];
+        bytes[..32].copy_from_slice(&self.digest(attempt, b"signature-0"));
+        bytes[32..].copy_from_slice(&self.digest(attempt, b"signature-1"));
+        Signature::new(&bytes).to_string()
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[async_trait]
+impl SolanaNetwork for MockSolanaNetwork {
+    type Error = MockSolanaError;
+
+    /// Like the delegated data credits account of a payer that was never
+    /// funded, unknown payers have a zero balance unless the network was
+    /// created with an initial balance
+    async fn payer_balance(&self, payer: &PublicKeyBinary) -> Result<u64, Self::Error> {
+        Ok(self.balance(payer).await)
+    }
+
+    async fn burn_data_credits(
+        &self,
+        payer: &PublicKeyBinary,
+        amount: u64,
+    ) -> Result<Option<String>, Self::Error> {
+        let attempt = self.attempts.fetch_add(1, Ordering::SeqCst);
+        if self.fail_burns.load(Ordering::SeqCst) || self.fails(attempt) {
+            tracing::info!(%payer, amount, "simulating failed burn");
+            return Err(MockSolanaError::BurnFailed);
+        }
+        let mut balances = self.balances.lock().await;
+        let balance = balances
+            .entry(payer.clone())
+            .or_insert(self.initial_balance);
+        if *balance < amount {
+            return Err(MockSolanaError::InsufficientBalance {
+                payer: payer.clone(),
+                balance: *balance,
+                amount,
+            });
+        }
+        *balance -= amount;
+        self.burns.lock().await.push((payer.clone(), amount));
+        let signature = self.signature(attempt);
+        self.history.lock().await.push(ChainBurn {
+            signature: signature.clone(),
+            block_time: Some(unix_now()),
+            failed: false,
+        });
+        tracing::info!(%payer, amount, %signature, "simulated burn");
+        Ok(Some(signature))
+    }
+
+    async fn burn_history(&self, since: i64) -> Result<Vec<ChainBurn>, Self::Error> {
+        Ok(self
+            .history
+            .lock()
+            .await
+            .iter()
+            .rev()
+            .filter(|burn| {
+                burn.block_time
+                    .map_or(true, |block_time| block_time >= since)
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn payer(byte: u8) -> PublicKeyBinary {
+        PublicKeyBinary::from(vec![byte])
+    }
+
+    #[tokio::test]
+    async fn burns_reduce_balance() {
+        let solana = MockSolanaNetwork::new().with_balance(payer(1), 100).await;
+
+        let signature = solana.burn_data_credits(&payer(1), 40).await.unwrap();
+
+        assert_eq!(solana.payer_balance(&payer(1)).await, Ok(60));
+        assert_eq!(solana.burns().await, vec![(payer(1), 40)]);
+        let history = solana.burn_history(0).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(Some(history[0].signature.clone()), signature);
+        assert!(Signature::from_str(signature.as_deref().unwrap()).is_ok());
+        assert_eq!(solana.payer_balance(&payer(2)).await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn failed_burns_are_not_recorded() {
+        let solana = MockSolanaNetwork::new().with_balance(payer(1), 100).await;
+
+        assert_eq!(
+            solana.burn_data_credits(&payer(1), 101).await,
+            Err(MockSolanaError::InsufficientBalance {
+                payer: payer(1),
+                balance: 100,
+                amount: 101,
+            })
+        );
+        solana.fail_burns(true);
+        assert_eq!(
+            solana.burn_data_credits(&payer(1), 10).await,
+            Err(MockSolanaError::BurnFailed)
+        );
+        solana.fail_burns(false);
+        solana.burn_data_credits(&payer(1), 10).await.unwrap();
+
+        assert_eq!(solana.balance(&payer(1)).await, 90);
+        assert_eq!(solana.burns().await, vec![(payer(1), 10)]);
+    }
+
+    #[tokio::test]
+    async fn staging_payers_start_with_the_configured_balance() {
+        let solana = MockSolanaNetwork::with_seed(
+            &Settings {
+                balance: 100,
+                ..Default::default()
+            },
+            1,
+        );
+
+        let first = solana.burn_data_credits(&payer(1), 40).await.unwrap();
+        let second = solana.burn_data_credits(&payer(1), 60).await.unwrap();
+        assert_ne!(first, second);
+
+        assert_eq!(solana.payer_balance(&payer(1)).await, Ok(0));
+        assert_eq!(solana.payer_balance(&payer(2)).await, Ok(100));
+        let history: Vec<_> = solana
+            .burn_history(0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|burn| Some(burn.signature))
+            .collect();
+        assert_eq!(history, vec![second, first]);
+    }
+
+    #[tokio::test]
+    async fn failure_rate_fails_share_of_burns() {
+        let failing = MockSolanaNetwork::with_seed(
+            &Settings {
+                failure_rate: 1.0,
+                ..Default::default()
+            },
+            1,
+        );
+        assert_eq!(
+            failing.burn_data_credits(&payer(1), 1).await,
+            Err(MockSolanaError::BurnFailed)
+        );
+        assert_eq!(
+            failing.payer_balance(&payer(1)).await,
+            Ok(default_balance())
+        );
+        assert!(failing.burn_history(0).await.unwrap().is_empty());
+
+        let flaky = MockSolanaNetwork::with_seed(
+            &Settings {
+                failure_rate: 0.5,
+                ..Default::default()
+            },
+            1,
+        );
+        let mut failed = 0;
+        for _ in 0..1000 {
+            if flaky.burn_data_credits(&payer(1), 1).await.is_err() {
+                failed += 1;
+            }
+        }
+        assert!((400..600).contains(&failed), "{failed} of 1000 failed");
+    }
+}
@@ -0,0 +1,299 @@
+//! Supervision of the long running tasks of a service binary.
+//!
+//! Tasks are registered by name in a shutdown [`Stage`]. When the service is
+//! asked to shut down, or any task fails, the stages are stopped in order:
+//! producers first, then the sinks they write to once every producer has
+//! finished, and finally the uploads of the files the sinks wrote. Each stage
+//! has its own shutdown listener, which must be used by every component of
+//! the stage so the ordering holds.
+//!
+//! Task failures and restarts are logged and counted with the category and
+//! code of their error, see [`poc_metrics::error_code`].
+
+use futures::{future::LocalBoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use poc_metrics::error_code;
+use std::{future::Future, time::Duration};
+
+const TASK_FAILURE_METRIC: &str = "task_manager_task_failure";
+const TASK_RESTART_METRIC: &str = "task_manager_task_restart";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    /// File sources, daemons and monitors that produce work
+    Producer,
+    /// File sinks writing the output of producers
+    Sink,
+    /// Uploads of the files written by sinks
+    Upload,
+}
+
+const STAGES: [Stage; 3] = [Stage::Producer, Stage::Sink, Stage::Upload];
+
+/// Restart a failed task up to `max_restarts` times, waiting `backoff`
+/// between attempts
+#[derive(Clone, Copy, Debug)]
+pub struct RestartPolicy {
+    pub max_restarts: u32,
+    pub backoff: Duration,
+}
+
+struct Task {
+    name: &'static str,
+    stage: Stage,
+    future: LocalBoxFuture<'static, anyhow::Result<()>>,
+}
+
+pub struct TaskManager {
+    stages: [(triggered::Trigger, triggered::Listener); 3],
+    tasks: Vec<Task>,
+}
+
+impl Default for TaskManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TaskManager {
+    pub fn new() -> Self {
+        Self {
+            stages: STAGES.map(|_| triggered::trigger()),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Shutdown listener of a stage, for components that are handed their
+    /// listener when they are constructed such as file sinks and sources
+    pub fn listener(&self, stage: Stage) -> triggered::Listener {
+        self.stages[stage as usize].1.clone()
+    }
+
+    /// Register a task, started with the shutdown listener of its stage
+    pub fn add<F, Fut, T, E>(&mut self, name: &'static str, stage: Stage, task: F)
+    where
+        F: FnOnce(triggered::Listener) -> Fut,
+        Fut: Future<Output = Result<T, E>> + 'static,
+        E: Into<anyhow::Error>,
+    {
+        let future =
+            task(self.listener(stage)).map(|result| result.map(|_| ()).map_err(Into::into));
+        self.tasks.push(Task {
+            name,
+            stage,
+            future: future.boxed_local(),
+        });
+    }
+
+    /// Register a task that is started again according to `policy` when it
+    /// fails. The task is only considered failed once its restarts are
+    /// exhausted.
+    pub fn add_restartable<F, Fut, T, E>(
+        &mut self,
+        name: &'static str,
+        stage: Stage,
+        policy: RestartPolicy,
+        mut task: F,
+    ) where
+        F: FnMut(triggered::Listener) -> Fut + 'static,
+        Fut: Future<Output = Result<T, E>> + 'static,
+        E: Into<anyhow::Error>,
+    {
+        let shutdown = self.listener(stage);
+        let future = async move {
+            let mut restarts = 0;
+            loop {
+                let err = match task(shutdown.clone()).await {
+                    Ok(_) => return Ok(()),
+                    Err(err) => err.into(),
+                };
+                if restarts >= policy.max_restarts || shutdown.is_triggered() {
+                    return Err(err);
+                }
+                restarts += 1;
+                let labels =
+                    error_code::record(TASK_RESTART_METRIC, err.as_ref(), &[("task", name)]);
+                tracing::warn!(
+                    task = name,
+                    restarts,
+                    category = %labels.category,
+                    code = labels.code,
+                    "task failed, restarting: {err:?}"
+                );
+                tokio::select! {
+                    _ = shutdown.clone() => return Err(err),
+                    _ = tokio::time::sleep(policy.backoff) => (),
+                }
+            }
+        };
+        self.tasks.push(Task {
+            name,
+            stage,
+            future: future.boxed_local(),
+        });
+    }
+
+    /// Run all registered tasks until they have finished. Shutdown starts
+    /// when `shutdown` is triggered or the first task fails, in which case
+    /// the error of that task is returned once every stage has stopped.
+    pub async fn run(self, shutdown: triggered::Listener) -> anyhow::Result<()> {
+        let Self { stages, tasks } = self;
+
+        let mut remaining = [0usize; STAGES.len()];
+        let mut running: FuturesUnordered<_> = tasks
+            .into_iter()
+            .map(
+                |Task {
+                     name,
+                     stage,
+                     future,
+                 }| {
+                    remaining[stage as usize] += 1;
+                    future.map(move |result| (name, stage, result))
+                },
+            )
+            .collect();
+
+        // Index of the last stage asked to stop
+        let mut stopping: Option<usize> = None;
+        let mut first_error = None;
+
+        loop {
+            // A stage is stopped once every task of the stage before it has
+            // finished
+            while let Some(stage) =
+                stopping.filter(|stage| remaining[*stage] == 0 && stage + 1 < STAGES.len())
+            {
+                tracing::info!(stage = ?STAGES[stage + 1], "stopping stage");
+                stages[stage + 1].0.trigger();
+                stopping = Some(stage + 1);
+            }
+
+            tokio::select! {
+                _ = shutdown.clone(), if stopping.is_none() => {
+                    tracing::info!("shutting down tasks");
+                    stages[0].0.trigger();
+                    stopping = Some(0);
+                }
+                finished = running.next() => match finished {
+                    None => break,
+                    Some((name, stage, result)) => {
+                        remaining[stage as usize] -= 1;
+                        match result {
+                            Ok(()) => tracing::info!(task = name, "task finished"),
+                            Err(err) => {
+                                let labels = error_code::record(
+                                    TASK_FAILURE_METRIC,
+                                    err.as_ref(),
+                                    &[("task", name)],
+                                );
+                                tracing::error!(
+                                    task = name,
+                                    category = %labels.category,
+                                    code = labels.code,
+                                    "task failed: {err:?}"
+                                );
+                                first_error.get_or_insert(err);
+                                if stopping.is_none() {
+                                    stages[0].0.trigger();
+                                    stopping = Some(0);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        sync::atomic::{AtomicU32, Ordering},
+        sync::Arc,
+    };
+
+    fn record_on_shutdown(
+        manager: &mut TaskManager,
+        stage: Stage,
+        stopped: &Rc<RefCell<Vec<Stage>>>,
+    ) {
+        let stopped = stopped.clone();
+        manager.add("recorder", stage, |shutdown| async move {
+            shutdown.await;
+            // give later stages the chance to stop too early
+            tokio::task::yield_now().await;
+            stopped.borrow_mut().push(stage);
+            anyhow::Ok(())
+        });
+    }
+
+    #[tokio::test]
+    async fn stages_stop_in_order() {
+        let stopped = Rc::new(RefCell::new(Vec::new()));
+        let mut manager = TaskManager::new();
+        record_on_shutdown(&mut manager, Stage::Upload, &stopped);
+        record_on_shutdown(&mut manager, Stage::Sink, &stopped);
+        record_on_shutdown(&mut manager, Stage::Producer, &stopped);
+        record_on_shutdown(&mut manager, Stage::Producer, &stopped);
+
+        let (trigger, shutdown) = triggered::trigger();
+        trigger.trigger();
+        manager.run(shutdown).await.unwrap();
+
+        assert_eq!(
+            *stopped.borrow(),
+            vec![Stage::Producer, Stage::Producer, Stage::Sink, Stage::Upload]
+        );
+    }
+
+    #[tokio::test]
+    async fn failure_shuts_down_every_stage() {
+        let stopped = Rc::new(RefCell::new(Vec::new()));
+        let mut manager = TaskManager::new();
+        record_on_shutdown(&mut manager, Stage::Sink, &stopped);
+        manager.add("failing", Stage::Producer, |_| async {
+            Err::<(), _>(anyhow::anyhow!("producer failed"))
+        });
+
+        let (_trigger, shutdown) = triggered::trigger();
+        let err = manager.run(shutdown).await.unwrap_err();
+
+        assert_eq!(err.to_string(), "producer failed");
+        assert_eq!(*stopped.borrow(), vec![Stage::Sink]);
+    }
+
+    #[tokio::test]
+    async fn restartable_task_is_restarted_until_exhausted() {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let policy = RestartPolicy {
+            max_restarts: 2,
+            backoff: Duration::ZERO,
+        };
+
+        let mut manager = TaskManager::new();
+        let task_attempts = attempts.clone();
+        manager.add_restartable("recovering", Stage::Producer, policy, move |_| {
+            let attempt = task_attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                anyhow::ensure!(attempt == 2, "attempt {attempt} failed");
+                Ok(())
+            }
+        });
+        let (_trigger, shutdown) = triggered::trigger();
+        manager.run(shutdown).await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let mut manager = TaskManager::new();
+        manager.add_restartable("failing", Stage::Producer, policy, |_| async {
+            Err::<(), _>(anyhow::anyhow!("always fails"))
+        });
+        let (_trigger, shutdown) = triggered::trigger();
+        assert!(manager.run(shutdown).await.is_err());
+    }
+}